@@ -0,0 +1,215 @@
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::str::FromStr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{VeloError, VeloResult};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cidr {
+    network: IpAddr,
+    prefix: u8,
+}
+
+impl Cidr {
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        match (self.network, ip.to_canonical()) {
+            (IpAddr::V4(net), IpAddr::V4(ip)) => {
+                let mask = u32::MAX.checked_shl(32 - self.prefix as u32).unwrap_or(0);
+                u32::from(net) & mask == u32::from(ip) & mask
+            }
+            (IpAddr::V6(net), IpAddr::V6(ip)) => {
+                let mask = u128::MAX.checked_shl(128 - self.prefix as u32).unwrap_or(0);
+                u128::from(net) & mask == u128::from(ip) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+impl FromStr for Cidr {
+    type Err = VeloError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || VeloError::InvalidOperation(format!("Invalid CIDR: {}", s));
+        let (addr, prefix) = match s.trim().split_once('/') {
+            Some((addr, prefix)) => (addr, Some(prefix.parse::<u8>().map_err(|_| invalid())?)),
+            None => (s.trim(), None),
+        };
+        let network = IpAddr::from_str(addr).map_err(|_| invalid())?.to_canonical();
+        let max_prefix = if network.is_ipv4() { 32 } else { 128 };
+        let prefix = prefix.unwrap_or(max_prefix);
+        if prefix > max_prefix {
+            return Err(invalid());
+        }
+        Ok(Self { network, prefix })
+    }
+}
+
+impl std::fmt::Display for Cidr {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}/{}", self.network, self.prefix)
+    }
+}
+
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AccessConfig {
+    #[serde(default)]
+    pub allow: Vec<String>,
+    #[serde(default)]
+    pub deny: Vec<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct BlockInfo {
+    pub cidr: String,
+    pub expires_in_secs: u64,
+    pub reason: String,
+}
+
+struct TemporaryBlock {
+    cidr: Cidr,
+    until: Instant,
+    reason: String,
+}
+
+/// Parsed allow and deny lists. An empty allowlist admits everyone not
+/// denied.
+#[derive(Debug, Clone, Default)]
+pub struct AccessList {
+    allow: Vec<Cidr>,
+    deny: Vec<Cidr>,
+}
+
+impl AccessList {
+    pub fn new(config: &AccessConfig) -> VeloResult<Self> {
+        let parse = |list: &[String]| list.iter().map(|s| s.parse()).collect::<VeloResult<Vec<Cidr>>>();
+        Ok(Self {
+            allow: parse(&config.allow)?,
+            deny: parse(&config.deny)?,
+        })
+    }
+
+    pub fn check(&self, ip: IpAddr) -> Result<(), String> {
+        if let Some(cidr) = self.deny.iter().find(|c| c.contains(ip)) {
+            return Err(format!("denied by {}", cidr));
+        }
+        if !self.allow.is_empty() && !self.allow.iter().any(|c| c.contains(ip)) {
+            return Err("not in allowlist".to_string());
+        }
+        Ok(())
+    }
+}
+
+pub struct AccessControl {
+    lists: AccessList,
+    blocks: Mutex<Vec<TemporaryBlock>>,
+    auth_failures: Mutex<HashMap<IpAddr, u32>>,
+    max_auth_failures: u32,
+    ban_duration: Duration,
+}
+
+impl AccessControl {
+    pub fn new(
+        config: &AccessConfig,
+        max_auth_failures: u32,
+        ban_duration: Duration,
+    ) -> VeloResult<Self> {
+        Ok(Self {
+            lists: AccessList::new(config)?,
+            blocks: Mutex::new(Vec::new()),
+            auth_failures: Mutex::new(HashMap::new()),
+            max_auth_failures,
+            ban_duration,
+        })
+    }
+
+
+    pub fn check(&self, ip: IpAddr) -> Result<(), String> {
+        if let Some(cidr) = self.lists.deny.iter().find(|c| c.contains(ip)) {
+            return Err(format!("denied by {}", cidr));
+        }
+
+        {
+            let mut blocks = self.blocks.lock().unwrap();
+            let now = Instant::now();
+            blocks.retain(|b| b.until > now);
+            if let Some(block) = blocks.iter().find(|b| b.cidr.contains(ip)) {
+                return Err(format!("temporarily blocked ({})", block.reason));
+            }
+        }
+
+        if !self.lists.allow.is_empty() && !self.lists.allow.iter().any(|c| c.contains(ip)) {
+            return Err("not in allowlist".to_string());
+        }
+
+        Ok(())
+    }
+
+    pub fn block(&self, cidr: Cidr, duration: Duration, reason: &str) {
+        let mut blocks = self.blocks.lock().unwrap();
+        blocks.retain(|b| b.cidr != cidr);
+        blocks.push(TemporaryBlock {
+            cidr,
+            until: Instant::now() + duration,
+            reason: reason.to_string(),
+        });
+    }
+
+    pub fn unblock(&self, cidr: Cidr) -> bool {
+        let mut blocks = self.blocks.lock().unwrap();
+        let before = blocks.len();
+        blocks.retain(|b| b.cidr != cidr);
+        blocks.len() != before
+    }
+
+    pub fn blocks(&self) -> Vec<BlockInfo> {
+        let now = Instant::now();
+        self.blocks
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|b| b.until > now)
+            .map(|b| BlockInfo {
+                cidr: b.cidr.to_string(),
+                expires_in_secs: (b.until - now).as_secs(),
+                reason: b.reason.clone(),
+            })
+            .collect()
+    }
+
+
+    pub fn record_auth_failure(&self, ip: IpAddr) -> bool {
+        if self.max_auth_failures == 0 {
+            return false;
+        }
+
+        let banned = {
+            let mut failures = self.auth_failures.lock().unwrap();
+            let count = failures.entry(ip).or_insert(0);
+            *count += 1;
+            let reached = *count >= self.max_auth_failures;
+            if reached {
+                failures.remove(&ip);
+            }
+            reached
+        };
+
+        if banned {
+            let cidr = Cidr {
+                network: ip.to_canonical(),
+                prefix: if ip.to_canonical().is_ipv4() { 32 } else { 128 },
+            };
+            self.block(cidr, self.ban_duration, "too many failed logins");
+        }
+        banned
+    }
+
+    pub fn record_auth_success(&self, ip: IpAddr) {
+        self.auth_failures.lock().unwrap().remove(&ip);
+    }
+}
@@ -1,9 +1,19 @@
-use crate::{VeloError, VeloResult, Velocity, VelocityConfig};
+use crate::backup_schedule::{BackupSchedule, BackupScheduleInfo};
+use crate::compaction::{
+    CompactionControl, CompactionProgress, CompactionState, MaintenanceProgress,
+};
+use crate::dry_run::{self, DryRunReport};
+use crate::fsutil::atomic_write;
+use crate::layout::{DataLayout, META_DIR, SST_DIR, WAL_DIR};
+use crate::sql::SqlEngine;
+use crate::{CachePolicy, RawValue, VeloError, VeloResult, Velocity, VelocityConfig};
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{HashMap, VecDeque};
 use std::fs;
 use std::path::{Path, PathBuf};
-use std::sync::{Arc, RwLock};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Instant;
 
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
@@ -16,6 +26,71 @@ pub struct DatabaseAddonConfig {
     pub databases: HashMap<String, PathBuf>,
     #[serde(default)]
     pub database_max_disk_size_bytes: HashMap<String, u64>,
+    #[serde(default)]
+    pub read_only_databases: Vec<String>,
+    #[serde(default)]
+    pub record_metadata_databases: Vec<String>,
+    #[serde(default)]
+    pub soft_delete_databases: Vec<String>,
+    /// Databases that keep a case-insensitive key index for
+    /// `WHERE key = '...' COLLATE NOCASE`.
+    #[serde(default)]
+    pub nocase_key_databases: Vec<String>,
+    #[serde(default = "default_trash_retention_hours")]
+    pub trash_retention_hours: u64,
+    #[serde(default)]
+    pub key_policies: HashMap<String, KeyPolicy>,
+    /// Flush a database's memtable once its oldest write is this many
+    /// seconds old. Databases without an entry use the default; with
+    /// neither set, memtables are only flushed when full or on request.
+    #[serde(default)]
+    pub default_memtable_max_age_seconds: Option<u64>,
+    #[serde(default)]
+    pub memtable_max_age_seconds: HashMap<String, u64>,
+    /// Databases whose writes treat the read cache differently from the
+    /// default write-through policy.
+    #[serde(default)]
+    pub cache_policies: HashMap<String, CachePolicy>,
+    /// Caps the bytes scans may read from a database per interval.
+    /// Databases without an entry use the default; with neither set,
+    /// scans are not limited.
+    #[serde(default)]
+    pub default_scan_budget: Option<ScanBudget>,
+    #[serde(default)]
+    pub scan_budgets: HashMap<String, ScanBudget>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ScanBudgetAction {
+    /// Hold scans back until the next interval starts.
+    #[default]
+    Throttle,
+    /// Fail scans until the next interval starts.
+    Reject,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct ScanBudget {
+    pub bytes_per_interval: u64,
+    #[serde(default = "default_scan_interval_seconds")]
+    pub interval_seconds: u64,
+    #[serde(default)]
+    pub action: ScanBudgetAction,
+}
+
+fn default_scan_interval_seconds() -> u64 {
+    60
+}
+
+// Scan bytes a database had read when its current budget interval began.
+struct ScanWindow {
+    started: Instant,
+    start_bytes: u64,
+}
+
+fn default_trash_retention_hours() -> u64 {
+    168
 }
 
 impl Default for DatabaseAddonConfig {
@@ -26,7 +101,99 @@ impl Default for DatabaseAddonConfig {
             default_max_disk_size_bytes: None,
             databases: HashMap::new(),
             database_max_disk_size_bytes: HashMap::new(),
+            read_only_databases: Vec::new(),
+            record_metadata_databases: Vec::new(),
+            soft_delete_databases: Vec::new(),
+            nocase_key_databases: Vec::new(),
+            trash_retention_hours: default_trash_retention_hours(),
+            key_policies: HashMap::new(),
+            default_memtable_max_age_seconds: None,
+            memtable_max_age_seconds: HashMap::new(),
+            cache_policies: HashMap::new(),
+            default_scan_budget: None,
+            scan_budgets: HashMap::new(),
+        }
+    }
+}
+
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct KeyPolicy {
+    #[serde(default)]
+    pub allowed_pattern: Option<String>,
+    #[serde(default)]
+    pub max_namespace_depth: Option<usize>,
+    #[serde(default = "default_reserved_prefixes")]
+    pub reserved_prefixes: Vec<String>,
+    #[serde(skip)]
+    compiled_pattern: Option<regex::Regex>,
+}
+
+fn default_reserved_prefixes() -> Vec<String> {
+    ["auth:", "_system:", "_studio:", "_trash:", "_nocase:"]
+        .iter()
+        .map(|p| p.to_string())
+        .collect()
+}
+
+impl Default for KeyPolicy {
+    fn default() -> Self {
+        Self {
+            allowed_pattern: None,
+            max_namespace_depth: None,
+            reserved_prefixes: default_reserved_prefixes(),
+            compiled_pattern: None,
+        }
+    }
+}
+
+impl KeyPolicy {
+    fn compile(&mut self) -> VeloResult<()> {
+        self.compiled_pattern = match &self.allowed_pattern {
+            Some(pattern) => Some(regex::Regex::new(&format!("^(?:{})$", pattern)).map_err(
+                |e| {
+                    VeloError::InvalidOperation(format!("Invalid key pattern '{}': {}", pattern, e))
+                },
+            )?),
+            None => None,
+        };
+        Ok(())
+    }
+
+    /// Checks a key about to be written. Privileged sessions may write
+    /// reserved prefixes but are still held to the pattern and depth rules.
+    pub fn check(&self, key: &str, privileged: bool) -> VeloResult<()> {
+        if !privileged {
+            if let Some(prefix) = self
+                .reserved_prefixes
+                .iter()
+                .find(|p| key.starts_with(p.as_str()))
+            {
+                return Err(VeloError::InvalidOperation(format!(
+                    "Key '{}' uses reserved prefix '{}'",
+                    key, prefix
+                )));
+            }
+        }
+        if let Some(max_depth) = self.max_namespace_depth {
+            let depth = key.split(':').count();
+            if depth > max_depth {
+                return Err(VeloError::InvalidOperation(format!(
+                    "Key '{}' has {} namespace levels (max {})",
+                    key, depth, max_depth
+                )));
+            }
+        }
+        if let Some(pattern) = &self.compiled_pattern {
+            if !pattern.is_match(key) {
+                return Err(VeloError::InvalidOperation(format!(
+                    "Key '{}' does not match the allowed pattern '{}'",
+                    key,
+                    self.allowed_pattern.as_deref().unwrap_or_default()
+                )));
+            }
         }
+        Ok(())
     }
 }
 
@@ -39,6 +206,14 @@ pub struct BackupAddonConfig {
     pub backup_all: bool,
     #[serde(default)]
     pub target_databases: Vec<String>,
+    #[serde(default)]
+    pub consistent: bool,
+    #[serde(default)]
+    pub cron: Option<String>,
+    #[serde(default)]
+    pub allowed_windows: Vec<String>,
+    #[serde(default)]
+    pub blackout_periods: Vec<String>,
 }
 
 impl Default for BackupAddonConfig {
@@ -49,6 +224,10 @@ impl Default for BackupAddonConfig {
             interval_minutes: 60,
             backup_all: true,
             target_databases: Vec::new(),
+            consistent: false,
+            cron: None,
+            allowed_windows: Vec::new(),
+            blackout_periods: Vec::new(),
         }
     }
 }
@@ -70,32 +249,209 @@ impl Default for BackgroundServiceAddonConfig {
     }
 }
 
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum ManagerEvent {
+    DatabaseCreated { name: String, path: PathBuf },
+    DatabaseDropped { name: String },
+    DatabaseAltered { name: String, option: String, enabled: bool },
+    BackupStarted { consistent: bool },
+    BackupFinished { databases: Vec<String>, duration_ms: u64 },
+    BackupFailed { error: String },
+    ConfigReloaded,
+    AddonToggled { addon: String, enabled: bool },
+    Maintenance(MaintenanceEvent),
+}
+
+pub type EventCallback = Arc<dyn Fn(&ManagerEvent) + Send + Sync>;
+
+// Maintenance events kept for `maintenance_events`.
+const MAINTENANCE_FEED_CAPACITY: usize = 256;
+
+/// Progress of a flush or merge in one database. Ids increase by one per
+/// event, so a follower can ask for what came after the last one it saw.
+#[derive(Debug, Clone, Serialize)]
+pub struct MaintenanceEvent {
+    pub id: u64,
+    pub database: String,
+    pub timestamp: String,
+    #[serde(flatten)]
+    pub progress: MaintenanceProgress,
+}
+
+#[derive(Default)]
+struct MaintenanceFeed {
+    next_id: u64,
+    events: VecDeque<MaintenanceEvent>,
+}
+
+pub const SYSTEM_DATABASE: &str = "_system";
+
+const SYSTEM_KEY_PREFIXES: [&str; 2] = ["auth:", crate::studio::STUDIO_KEY_PREFIX];
+
 pub struct DatabaseManager {
     default_db: Arc<Velocity>,
+    system_db: Option<Arc<Velocity>>,
     databases: RwLock<HashMap<String, Arc<Velocity>>>,
     pub db_config: RwLock<DatabaseAddonConfig>,
     backup_config: RwLock<BackupAddonConfig>,
     background_service_config: RwLock<BackgroundServiceAddonConfig>,
     config_path: PathBuf,
+    last_backup_attempt: RwLock<chrono::DateTime<chrono::Local>>,
+    listeners: Arc<RwLock<Vec<(u64, EventCallback)>>>,
+    next_listener_id: AtomicU64,
+    maintenance: Arc<Mutex<MaintenanceFeed>>,
+    compactions: Mutex<HashMap<String, Arc<CompactionControl>>>,
+    scan_windows: Mutex<HashMap<String, ScanWindow>>,
 }
 
 impl DatabaseManager {
     pub fn new(default_db: Velocity, config_path: PathBuf) -> Self {
+        let system_path = default_db.data_dir().join(SYSTEM_DATABASE);
+        let system_db = match open_alongside(&default_db, &system_path) {
+            Ok(db) => Some(Arc::new(db)),
+            Err(e) => {
+                log::error!("Failed to open system database at {:?}: {}", system_path, e);
+                None
+            }
+        };
         let manager = Self {
             default_db: Arc::new(default_db),
+            system_db,
             databases: RwLock::new(HashMap::new()),
             db_config: RwLock::new(DatabaseAddonConfig::default()),
             backup_config: RwLock::new(BackupAddonConfig::default()),
             background_service_config: RwLock::new(BackgroundServiceAddonConfig::default()),
             config_path,
+            last_backup_attempt: RwLock::new(chrono::Local::now()),
+            listeners: Arc::new(RwLock::new(Vec::new())),
+            next_listener_id: AtomicU64::new(1),
+            maintenance: Arc::new(Mutex::new(MaintenanceFeed::default())),
+            compactions: Mutex::new(HashMap::new()),
+            scan_windows: Mutex::new(HashMap::new()),
         };
+        manager.watch_maintenance("default", &manager.default_db);
+        if let Some(system_db) = &manager.system_db {
+            manager.watch_maintenance(SYSTEM_DATABASE, system_db);
+        }
 
 
         let _ = manager.reload_config();
+        if manager.default_db.is_read_only() {
+            return manager;
+        }
+        if let Err(e) = manager.migrate_system_keys() {
+            log::error!("Failed to migrate internal keys to the system database: {}", e);
+        }
 
         manager
     }
 
+    /// Internal state (user metadata, API keys, Studio data) lives here.
+    /// Falls back to the default database if the system database could not
+    /// be opened.
+    pub fn system_database(&self) -> Arc<Velocity> {
+        self.system_db.clone().unwrap_or_else(|| self.default_db.clone())
+    }
+
+    fn migrate_system_keys(&self) -> VeloResult<usize> {
+        let Some(system_db) = &self.system_db else {
+            return Ok(0);
+        };
+
+        let mut migrated = 0;
+        for prefix in SYSTEM_KEY_PREFIXES {
+            for (key, value) in self.default_db.scan_prefix_raw(prefix) {
+                if system_db.get(&key)?.is_none() {
+                    system_db.put_raw(key.clone(), value)?;
+                }
+                self.default_db.put_raw(key, RawValue::tombstone())?;
+                migrated += 1;
+            }
+        }
+        if migrated > 0 {
+            log::info!(
+                "Moved {} internal keys from the default database to '{}'",
+                migrated,
+                SYSTEM_DATABASE
+            );
+        }
+        Ok(migrated)
+    }
+
+    pub fn on_event<F>(&self, callback: F) -> u64
+    where
+        F: Fn(&ManagerEvent) + Send + Sync + 'static,
+    {
+        let id = self.next_listener_id.fetch_add(1, Ordering::Relaxed);
+        self.listeners.write().unwrap().push((id, Arc::new(callback)));
+        id
+    }
+
+    pub fn remove_listener(&self, id: u64) -> bool {
+        let mut listeners = self.listeners.write().unwrap();
+        let before = listeners.len();
+        listeners.retain(|(listener_id, _)| *listener_id != id);
+        listeners.len() != before
+    }
+
+
+    fn emit(&self, event: ManagerEvent) {
+        emit_to(&self.listeners, event);
+    }
+
+    // Progress arrives on the database's maintenance event thread, so it is
+    // recorded and sent to the listeners from there.
+    fn watch_maintenance(&self, name: &str, db: &Velocity) {
+        let listeners = self.listeners.clone();
+        let feed = self.maintenance.clone();
+        let database = name.to_string();
+        db.on_maintenance(move |progress| {
+            let event = {
+                let mut feed = feed.lock().unwrap();
+                feed.next_id += 1;
+                let event = MaintenanceEvent {
+                    id: feed.next_id,
+                    database: database.clone(),
+                    timestamp: chrono::Local::now().to_rfc3339(),
+                    progress: progress.clone(),
+                };
+                if feed.events.len() == MAINTENANCE_FEED_CAPACITY {
+                    feed.events.pop_front();
+                }
+                feed.events.push_back(event.clone());
+                event
+            };
+            emit_to(&listeners, ManagerEvent::Maintenance(event));
+        });
+    }
+
+    /// Recent maintenance events with an id above `after`, oldest first.
+    pub fn maintenance_events(&self, after: u64) -> Vec<MaintenanceEvent> {
+        let feed = self.maintenance.lock().unwrap();
+        feed.events
+            .iter()
+            .filter(|event| event.id > after)
+            .cloned()
+            .collect()
+    }
+
+    /// The latest event of every flush or merge that has not ended yet.
+    pub fn running_maintenance(&self) -> Vec<MaintenanceEvent> {
+        let feed = self.maintenance.lock().unwrap();
+        let mut latest: HashMap<u64, &MaintenanceEvent> = HashMap::new();
+        for event in &feed.events {
+            latest.insert(event.progress.operation_id, event);
+        }
+        let mut running: Vec<MaintenanceEvent> = latest
+            .into_values()
+            .filter(|event| event.progress.state == CompactionState::Running)
+            .cloned()
+            .collect();
+        running.sort_by_key(|event| event.id);
+        running
+    }
+
     pub fn reload_config(&self) -> VeloResult<()> {
         if !self.config_path.exists() {
             return Ok(());
@@ -135,17 +491,35 @@ impl DatabaseManager {
         }
 
 
+        for policy in db_config.key_policies.values_mut() {
+            policy.compile()?;
+        }
+
         if db_config.enabled {
             let mut dbs = self.databases.write().unwrap();
 
-            if !db_config.default_path.exists() {
+            if !db_config.default_path.exists() && !self.default_db.is_read_only() {
                 fs::create_dir_all(&db_config.default_path)?;
             }
 
             for (name, path) in &db_config.databases {
                 if !dbs.contains_key(name) {
-                    match Velocity::open(path) {
+                    match open_alongside(&self.default_db, path) {
                         Ok(db) => {
+                            if db_config.record_metadata_databases.contains(name) {
+                                db.set_record_metadata(true);
+                            }
+                            if db_config.soft_delete_databases.contains(name) {
+                                db.set_soft_delete(true);
+                            }
+                            if db_config.nocase_key_databases.contains(name) {
+                                db.set_nocase_index(true);
+                            }
+                            if let Some(policy) = db_config.cache_policies.get(name) {
+                                db.set_cache_policy(*policy);
+                            }
+                            db.set_hot_key_tracking(&self.default_db.hot_key_config());
+                            self.watch_maintenance(name, &db);
                             dbs.insert(name.clone(), Arc::new(db));
                             log::info!("Loaded database '{}' from {:?}", name, path);
                         }
@@ -155,10 +529,23 @@ impl DatabaseManager {
             }
         }
 
+        if db_config.record_metadata_databases.iter().any(|db| db == "default") {
+            self.default_db.set_record_metadata(true);
+        }
+        if db_config.soft_delete_databases.iter().any(|db| db == "default") {
+            self.default_db.set_soft_delete(true);
+        }
+        if db_config.nocase_key_databases.iter().any(|db| db == "default") {
+            self.default_db.set_nocase_index(true);
+        }
+        if let Some(policy) = db_config.cache_policies.get("default") {
+            self.default_db.set_cache_policy(*policy);
+        }
         *self.db_config.write().unwrap() = db_config;
         *self.backup_config.write().unwrap() = backup_config;
         *self.background_service_config.write().unwrap() = background_service_config;
 
+        self.emit(ManagerEvent::ConfigReloaded);
         Ok(())
     }
 
@@ -205,7 +592,28 @@ impl DatabaseManager {
         let new_content = toml::to_string_pretty(&toml_value)
             .map_err(|e| VeloError::InvalidOperation(format!("TOML serialization error: {}", e)))?;
 
-        fs::write(&self.config_path, new_content)?;
+        atomic_write(&self.config_path, new_content)?;
+
+        Ok(())
+    }
+
+    pub fn set_user_password_hash(&self, username: &str, hash: &str) -> VeloResult<()> {
+        let content = fs::read_to_string(&self.config_path).unwrap_or_default();
+        let mut toml_value: toml::Value =
+            toml::from_str(&content).unwrap_or_else(|_| toml::Value::Table(toml::map::Map::new()));
+
+        let root = toml_value.as_table_mut().unwrap();
+        let users = root
+            .entry("users".to_string())
+            .or_insert_with(|| toml::Value::Table(toml::map::Map::new()));
+        if let Some(users_table) = users.as_table_mut() {
+            users_table.insert(username.to_string(), toml::Value::String(hash.to_string()));
+        }
+
+        let new_content = toml::to_string_pretty(&toml_value)
+            .map_err(|e| VeloError::InvalidOperation(format!("TOML serialization error: {}", e)))?;
+
+        atomic_write(&self.config_path, new_content)?;
 
         Ok(())
     }
@@ -214,7 +622,7 @@ impl DatabaseManager {
 
         {
             let dbs = self.databases.read().unwrap();
-            if dbs.contains_key(name) || name == "default" {
+            if dbs.contains_key(name) || name == "default" || name == SYSTEM_DATABASE {
                 return Err(VeloError::InvalidOperation(format!(
                     "Database '{}' already exists",
                     name
@@ -237,7 +645,9 @@ impl DatabaseManager {
         }
 
 
-        let db = Velocity::open(&db_path)?;
+        let db = open_alongside(&self.default_db, &db_path)?;
+        db.set_hot_key_tracking(&self.default_db.hot_key_config());
+        self.watch_maintenance(name, &db);
 
 
         let mut dbs = self.databases.write().unwrap();
@@ -259,14 +669,19 @@ impl DatabaseManager {
         self.save_config()?;
 
         log::info!("Created new database '{}' at {:?}", name, db_path);
+        self.emit(ManagerEvent::DatabaseCreated {
+            name: name.to_string(),
+            path: db_path,
+        });
         Ok(())
     }
 
     pub fn drop_database(&self, name: &str) -> VeloResult<()> {
-        if name == "default" {
-            return Err(VeloError::InvalidOperation(
-                "Cannot drop the default database".to_string(),
-            ));
+        if name == "default" || name == SYSTEM_DATABASE {
+            return Err(VeloError::InvalidOperation(format!(
+                "Cannot drop the {} database",
+                name
+            )));
         }
 
         let mut config = self.db_config.write().unwrap();
@@ -289,6 +704,15 @@ impl DatabaseManager {
 
         config.databases.remove(name);
         config.database_max_disk_size_bytes.remove(name);
+        config.read_only_databases.retain(|db| db != name);
+        config.record_metadata_databases.retain(|db| db != name);
+        config.soft_delete_databases.retain(|db| db != name);
+        config.nocase_key_databases.retain(|db| db != name);
+        config.key_policies.remove(name);
+        config.memtable_max_age_seconds.remove(name);
+        config.cache_policies.remove(name);
+        config.scan_budgets.remove(name);
+        self.scan_windows.lock().unwrap().remove(name);
 
 
         drop(config);
@@ -303,13 +727,55 @@ impl DatabaseManager {
         }
 
         log::info!("Dropped database '{}'", name);
+        self.emit(ManagerEvent::DatabaseDropped {
+            name: name.to_string(),
+        });
         Ok(())
     }
 
+    /// What `drop_database` would remove: every stored entry of `name`,
+    /// trashed ones included, and the files in its directory.
+    pub fn drop_database_dry_run(&self, name: &str) -> VeloResult<DryRunReport> {
+        if name == "default" || name == SYSTEM_DATABASE {
+            return Err(VeloError::InvalidOperation(format!(
+                "Cannot drop the {} database",
+                name
+            )));
+        }
+        let db = self
+            .databases
+            .read()
+            .unwrap()
+            .get(name)
+            .cloned()
+            .ok_or_else(|| VeloError::KeyNotFound(format!("Database '{}' not found", name)))?;
+        let path = self.db_config.read().unwrap().databases.get(name).cloned();
+
+        // Counted a batch at a time rather than by loading the database.
+        const BATCH: usize = 10_000;
+        let mut keys = 0u64;
+        let mut start = String::new();
+        loop {
+            let rows = db.range(&start, None, BATCH)?;
+            keys += rows.len() as u64;
+            match rows.last() {
+                Some((last, _)) if rows.len() == BATCH => start = format!("{}\0", last),
+                _ => break,
+            }
+        }
+
+        let mut report = DryRunReport::default();
+        report.add(name, keys, path.map_or(0, |path| dry_run::tree_bytes(&path)));
+        Ok(report)
+    }
+
     pub fn get_database(&self, name: &str) -> Option<Arc<Velocity>> {
         if name == "default" {
             return Some(self.default_db.clone());
         }
+        if name == SYSTEM_DATABASE {
+            return self.system_db.clone();
+        }
 
         let db_configs = self.db_config.read().unwrap();
         if !db_configs.enabled {
@@ -337,7 +803,299 @@ impl DatabaseManager {
         config.database_max_disk_size_bytes.get(name).copied()
     }
 
+    pub fn is_read_only(&self, name: &str) -> bool {
+        let config = self.db_config.read().unwrap();
+        config.read_only_databases.iter().any(|db| db == name)
+    }
+
+    pub fn set_read_only(&self, name: &str, read_only: bool) -> VeloResult<()> {
+        if self.get_database(name).is_none() {
+            return Err(VeloError::KeyNotFound(format!(
+                "Database '{}' not found",
+                name
+            )));
+        }
+
+        let mut config = self.db_config.write().unwrap();
+        config.read_only_databases.retain(|db| db != name);
+        if read_only {
+            config.read_only_databases.push(name.to_string());
+            config.read_only_databases.sort();
+        }
+        drop(config);
+        self.save_config()?;
+
+        log::info!(
+            "Database '{}' is now {}",
+            name,
+            if read_only { "read-only" } else { "writable" }
+        );
+        self.emit(ManagerEvent::DatabaseAltered {
+            name: name.to_string(),
+            option: "read_only".to_string(),
+            enabled: read_only,
+        });
+        Ok(())
+    }
+
+    pub fn set_record_metadata(&self, name: &str, enabled: bool) -> VeloResult<()> {
+        let Some(db) = self.get_database(name) else {
+            return Err(VeloError::KeyNotFound(format!(
+                "Database '{}' not found",
+                name
+            )));
+        };
+
+        let mut config = self.db_config.write().unwrap();
+        config.record_metadata_databases.retain(|db| db != name);
+        if enabled {
+            config.record_metadata_databases.push(name.to_string());
+            config.record_metadata_databases.sort();
+        }
+        drop(config);
+        db.set_record_metadata(enabled);
+        self.save_config()?;
+
+        log::info!(
+            "Record metadata {} for database '{}'",
+            if enabled { "enabled" } else { "disabled" },
+            name
+        );
+        self.emit(ManagerEvent::DatabaseAltered {
+            name: name.to_string(),
+            option: "record_metadata".to_string(),
+            enabled,
+        });
+        Ok(())
+    }
+
+    pub fn set_soft_delete(&self, name: &str, enabled: bool) -> VeloResult<()> {
+        let Some(db) = self.get_database(name) else {
+            return Err(VeloError::KeyNotFound(format!(
+                "Database '{}' not found",
+                name
+            )));
+        };
+
+        let mut config = self.db_config.write().unwrap();
+        config.soft_delete_databases.retain(|db| db != name);
+        if enabled {
+            config.soft_delete_databases.push(name.to_string());
+            config.soft_delete_databases.sort();
+        }
+        drop(config);
+        db.set_soft_delete(enabled);
+        self.save_config()?;
+
+        log::info!(
+            "Soft delete {} for database '{}'",
+            if enabled { "enabled" } else { "disabled" },
+            name
+        );
+        self.emit(ManagerEvent::DatabaseAltered {
+            name: name.to_string(),
+            option: "soft_delete".to_string(),
+            enabled,
+        });
+        Ok(())
+    }
+
+    /// Turns the case-insensitive key index on or off, indexing existing
+    /// keys or dropping the index as needed.
+    pub fn set_nocase_keys(&self, name: &str, enabled: bool) -> VeloResult<()> {
+        let Some(db) = self.get_database(name) else {
+            return Err(VeloError::KeyNotFound(format!(
+                "Database '{}' not found",
+                name
+            )));
+        };
+
+        let changed = db.nocase_index_enabled() != enabled;
+        db.set_nocase_index(enabled);
+        if changed {
+            let entries = db.rebuild_nocase_index()?;
+            log::info!("Rebuilt case-insensitive key index of '{}' ({} entries)", name, entries);
+        }
+
+        let mut config = self.db_config.write().unwrap();
+        config.nocase_key_databases.retain(|db| db != name);
+        if enabled {
+            config.nocase_key_databases.push(name.to_string());
+            config.nocase_key_databases.sort();
+        }
+        drop(config);
+        self.save_config()?;
+
+        log::info!(
+            "Case-insensitive keys {} for database '{}'",
+            if enabled { "enabled" } else { "disabled" },
+            name
+        );
+        self.emit(ManagerEvent::DatabaseAltered {
+            name: name.to_string(),
+            option: "nocase_keys".to_string(),
+            enabled,
+        });
+        Ok(())
+    }
+
+    /// Changes how writes to a database update its read cache. The
+    /// write-through default is not stored.
+    pub fn set_cache_policy(&self, name: &str, policy: CachePolicy) -> VeloResult<()> {
+        let Some(db) = self.get_database(name) else {
+            return Err(VeloError::KeyNotFound(format!(
+                "Database '{}' not found",
+                name
+            )));
+        };
+        db.set_cache_policy(policy);
+
+        let mut config = self.db_config.write().unwrap();
+        if policy == CachePolicy::default() {
+            config.cache_policies.remove(name);
+        } else {
+            config.cache_policies.insert(name.to_string(), policy);
+        }
+        drop(config);
+        self.save_config()?;
+
+        log::info!("Cache policy of database '{}' set to {}", name, policy.name());
+        Ok(())
+    }
+
+    pub fn trash_retention(&self) -> std::time::Duration {
+        let hours = self.db_config.read().unwrap().trash_retention_hours;
+        std::time::Duration::from_secs(hours * 3600)
+    }
+
+    pub fn purge_expired_trash(&self) -> usize {
+        let retention = self.trash_retention();
+        let mut purged = 0;
+        for name in self.list_databases() {
+            let Some(db) = self.get_database(&name) else {
+                continue;
+            };
+            match db.purge_trash(Some(retention)) {
+                Ok(count) => purged += count,
+                Err(e) => log::error!("Failed to purge trash in '{}': {}", name, e),
+            }
+        }
+        purged
+    }
+
+    pub fn memtable_max_age(&self, name: &str) -> Option<std::time::Duration> {
+        let config = self.db_config.read().unwrap();
+        config
+            .memtable_max_age_seconds
+            .get(name)
+            .copied()
+            .or(config.default_memtable_max_age_seconds)
+            .filter(|seconds| *seconds > 0)
+            .map(std::time::Duration::from_secs)
+    }
+
+    pub fn scan_budget(&self, name: &str) -> Option<ScanBudget> {
+        let config = self.db_config.read().unwrap();
+        config
+            .scan_budgets
+            .get(name)
+            .or(config.default_scan_budget.as_ref())
+            .filter(|budget| budget.bytes_per_interval > 0)
+            .cloned()
+    }
+
+    /// Whether a scan of `name` may start now. Returns how long to wait
+    /// before asking again when the database's scan budget for the current
+    /// interval is used up and the budget throttles, or an error when it
+    /// rejects. A scan that starts within budget is never cut short, so one
+    /// large scan can overrun the budget; later scans wait for it.
+    pub fn admit_scan(&self, name: &str) -> VeloResult<Option<std::time::Duration>> {
+        let Some(budget) = self.scan_budget(name) else {
+            return Ok(None);
+        };
+        let Some(db) = self.get_database(name) else {
+            return Ok(None);
+        };
+        let interval = std::time::Duration::from_secs(budget.interval_seconds.max(1));
+        let scanned = db.scan_bytes();
+        let mut windows = self.scan_windows.lock().unwrap();
+        let window = windows.entry(name.to_string()).or_insert_with(|| ScanWindow {
+            started: Instant::now(),
+            start_bytes: scanned,
+        });
+        if window.started.elapsed() >= interval {
+            window.started = Instant::now();
+            window.start_bytes = scanned;
+        }
+        let used = scanned.saturating_sub(window.start_bytes);
+        if used < budget.bytes_per_interval {
+            return Ok(None);
+        }
+        let remaining = interval.saturating_sub(window.started.elapsed());
+        match budget.action {
+            ScanBudgetAction::Throttle => Ok(Some(remaining)),
+            ScanBudgetAction::Reject => Err(VeloError::InvalidOperation(format!(
+                "Database '{}' scan budget exceeded: scans read {} bytes of the {} allowed \
+                 per {}s; retry in {}s or narrow the query to a key lookup",
+                name,
+                used,
+                budget.bytes_per_interval,
+                budget.interval_seconds.max(1),
+                remaining.as_secs().max(1)
+            ))),
+        }
+    }
+
+    /// A SQL engine over `db`, the database named `name`, that checks keys
+    /// against its key policy (reserved prefixes are writable when
+    /// `privileged`) and scans against its scan budget.
+    pub fn sql_engine(self: &Arc<Self>, db: Arc<Velocity>, name: &str, privileged: bool) -> SqlEngine {
+        let policy = self.key_policy(name);
+        let (scans, writes) = (self.clone(), self.clone());
+        let (scanned, written) = (name.to_string(), name.to_string());
+        SqlEngine::new(db)
+            .with_key_validator(Arc::new(move |key| policy.check(key, privileged)))
+            .with_scan_admission(Arc::new(move || scans.admit_scan(&scanned)))
+            .with_write_admission(Arc::new(move || writes.can_accept_write(&written)))
+    }
+
+    /// Flushes every database whose memtable has outlived its max age.
+    pub fn flush_aged_memtables(&self) -> usize {
+        let mut flushed = 0;
+        for name in self.list_databases() {
+            let Some(max_age) = self.memtable_max_age(&name) else {
+                continue;
+            };
+            let Some(db) = self.get_database(&name) else {
+                continue;
+            };
+            match db.flush_if_older_than(max_age) {
+                Ok(true) => flushed += 1,
+                Ok(false) => {}
+                Err(e) => log::error!("Failed to auto-flush '{}': {}", name, e),
+            }
+        }
+        flushed
+    }
+
+    pub fn key_policy(&self, name: &str) -> KeyPolicy {
+        self.db_config
+            .read()
+            .unwrap()
+            .key_policies
+            .get(name)
+            .cloned()
+            .unwrap_or_default()
+    }
+
     pub fn can_accept_write(&self, name: &str) -> VeloResult<()> {
+        if self.is_read_only(name) {
+            return Err(VeloError::InvalidOperation(format!(
+                "Database '{}' is read-only",
+                name
+            )));
+        }
+
         let Some(limit_bytes) = self.get_database_max_disk_size_bytes(name) else {
             return Ok(());
         };
@@ -360,6 +1118,60 @@ impl DatabaseManager {
         Ok(())
     }
 
+    /// Starts a full compaction of `name` on its own thread, limited by the
+    /// default database's `compaction_io_budget`. Only one may run per
+    /// database at a time.
+    pub fn start_full_compaction(&self, name: &str) -> VeloResult<Arc<CompactionControl>> {
+        let Some(db) = self.get_database(name) else {
+            return Err(VeloError::KeyNotFound(format!(
+                "Database '{}' not found",
+                name
+            )));
+        };
+
+        let mut compactions = self.compactions.lock().unwrap();
+        if let Some(running) = compactions.get(name) {
+            if running.state() == CompactionState::Running {
+                return Err(VeloError::InvalidOperation(format!(
+                    "A full compaction of '{}' is already running",
+                    name
+                )));
+            }
+        }
+
+        let control = Arc::new(CompactionControl::new(
+            self.default_db.config().compaction_io_budget,
+        ));
+        let task = control.clone();
+        let db_name = name.to_string();
+        db.clone().run_in_background(move || match db.compact_full(&task) {
+            Ok(state) => log::info!("Full compaction of '{}' {:?}", db_name, state),
+            Err(e) => log::error!("Full compaction of '{}' failed: {}", db_name, e),
+        })?;
+        compactions.insert(name.to_string(), control.clone());
+        Ok(control)
+    }
+
+    /// Progress of the running or most recent full compaction of `name`.
+    pub fn compaction_progress(&self, name: &str) -> Option<CompactionProgress> {
+        let compactions = self.compactions.lock().unwrap();
+        compactions.get(name).map(|control| control.progress())
+    }
+
+    pub fn cancel_compaction(&self, name: &str) -> VeloResult<()> {
+        let compactions = self.compactions.lock().unwrap();
+        match compactions.get(name) {
+            Some(control) if control.state() == CompactionState::Running => {
+                control.cancel();
+                Ok(())
+            }
+            _ => Err(VeloError::InvalidOperation(format!(
+                "No full compaction of '{}' is running",
+                name
+            ))),
+        }
+    }
+
     pub fn get_default_database_max_disk_size_bytes(&self) -> Option<u64> {
         self.db_config.read().unwrap().default_max_disk_size_bytes
     }
@@ -405,7 +1217,12 @@ impl DatabaseManager {
                 config.enabled = enabled;
             }
         }
-        self.save_config()
+        self.save_config()?;
+        self.emit(ManagerEvent::AddonToggled {
+            addon: kind.as_str().to_string(),
+            enabled,
+        });
+        Ok(())
     }
 
     pub fn list_addons(&self) -> Vec<(String, bool)> {
@@ -428,12 +1245,43 @@ impl DatabaseManager {
     }
 
     pub fn backup_all_databases(&self) -> VeloResult<Vec<String>> {
-        let config = self.backup_config.read().unwrap();
+        let config = self.backup_config.read().unwrap().clone();
         if !config.enabled {
             return Err(VeloError::InvalidOperation(
                 "Backup addon is disabled".to_string(),
             ));
         }
+        self.run_backup(config.consistent)
+    }
+
+    pub fn backup_snapshot(&self) -> VeloResult<Vec<String>> {
+        self.run_backup(true)
+    }
+
+    fn run_backup(&self, consistent: bool) -> VeloResult<Vec<String>> {
+        *self.last_backup_attempt.write().unwrap() = chrono::Local::now();
+        self.emit(ManagerEvent::BackupStarted { consistent });
+
+        let started = std::time::Instant::now();
+        let result = if consistent {
+            self.snapshot_databases()
+        } else {
+            self.copy_databases()
+        };
+        match &result {
+            Ok(databases) => self.emit(ManagerEvent::BackupFinished {
+                databases: databases.clone(),
+                duration_ms: started.elapsed().as_millis() as u64,
+            }),
+            Err(e) => self.emit(ManagerEvent::BackupFailed {
+                error: e.to_string(),
+            }),
+        }
+        result
+    }
+
+    fn copy_databases(&self) -> VeloResult<Vec<String>> {
+        let config = self.backup_config.read().unwrap().clone();
 
         if !config.backup_path.exists() {
             fs::create_dir_all(&config.backup_path)?;
@@ -465,24 +1313,153 @@ impl DatabaseManager {
 
             if db_path.exists() {
                 self.copy_dir(&db_path, &backup_dir)?;
+                if let Some(db) = self.get_database(&db_name) {
+                    db.record_backup()?;
+                }
                 successful_backups.push(db_name);
             }
         }
 
+        if !successful_backups.is_empty() {
+            self.default_db.record_backup()?;
+        }
+
         Ok(successful_backups)
     }
 
+    pub fn backup_schedule(&self) -> VeloResult<BackupSchedule> {
+        BackupSchedule::from_config(&self.backup_config.read().unwrap())
+    }
+
+    pub fn next_backup_run(&self) -> VeloResult<Option<chrono::DateTime<chrono::Local>>> {
+        let last_attempt = *self.last_backup_attempt.read().unwrap();
+        let last_run = self
+            .lifetime_stats()
+            .last_backup_at
+            .and_then(|secs| chrono::DateTime::from_timestamp(secs as i64, 0))
+            .map(|t| t.with_timezone(&chrono::Local).max(last_attempt))
+            .unwrap_or(last_attempt);
+        Ok(self
+            .backup_schedule()?
+            .next_run_after(chrono::Local::now(), Some(last_run)))
+    }
+
+    pub fn backup_schedule_info(&self) -> VeloResult<BackupScheduleInfo> {
+        let config = self.backup_config.read().unwrap().clone();
+        let next_run = if config.enabled {
+            self.next_backup_run()?.map(|t| t.to_rfc3339())
+        } else {
+            None
+        };
+        Ok(BackupScheduleInfo {
+            enabled: config.enabled,
+            cron: config.cron,
+            interval_minutes: config.interval_minutes,
+            allowed_windows: config.allowed_windows,
+            blackout_periods: config.blackout_periods,
+            next_run,
+        })
+    }
+
+    fn snapshot_databases(&self) -> VeloResult<Vec<String>> {
+        let config = self.backup_config.read().unwrap().clone();
+        let timestamp = chrono::Local::now().format("%Y%m%d_%H%M%S").to_string();
+
+        let names = if config.backup_all {
+            let mut names = self.list_databases();
+            names.push(SYSTEM_DATABASE.to_string());
+            names
+        } else {
+            config.target_databases.clone()
+        };
+        let mut databases: Vec<(String, Arc<Velocity>)> = names
+            .into_iter()
+            .filter_map(|name| self.get_database(&name).map(|db| (name, db)))
+            .collect();
+        // A database listed twice would be paused twice below, and the
+        // second pause waits forever on the first.
+        databases.sort_by(|a, b| a.0.cmp(&b.0));
+        databases.dedup_by(|a, b| a.0 == b.0);
+
+        let started = std::time::Instant::now();
+        let checkpoints = {
+            let _paused: Vec<_> = databases.iter().map(|(_, db)| db.pause_writes()).collect();
+            databases
+                .iter()
+                .map(|(name, db)| db.create_checkpoint().map(|cp| (name.clone(), cp)))
+                .collect::<VeloResult<Vec<_>>>()?
+        };
+        log::info!(
+            "Snapshot of {} databases taken with writes paused for {} ms",
+            databases.len(),
+            started.elapsed().as_millis()
+        );
+
+        let mut manifest = serde_json::Map::new();
+        for ((name, db), (_, checkpoint)) in databases.iter().zip(&checkpoints) {
+            let backup_layout = DataLayout::new(
+                config.backup_path.join(name).join(&timestamp),
+                db.layout().mode(),
+            );
+            fs::create_dir_all(backup_layout.sst_dir())?;
+            for file in &checkpoint.files {
+                fs::copy(
+                    db.layout().sst_dir().join(&file.name),
+                    backup_layout.sst_dir().join(&file.name),
+                )?;
+            }
+            db.record_backup()?;
+            manifest.insert(name.clone(), serde_json::to_value(checkpoint).unwrap());
+        }
+
+        let manifest = serde_json::json!({
+            "timestamp": timestamp,
+            "consistent": true,
+            "databases": manifest,
+        });
+        atomic_write(
+            config.backup_path.join(format!("{}.manifest.json", timestamp)),
+            serde_json::to_vec_pretty(&manifest).unwrap(),
+        )?;
+
+        Ok(databases.into_iter().map(|(name, _)| name).collect())
+    }
+
     fn copy_dir(&self, src: &Path, dst: &Path) -> VeloResult<()> {
         for entry in fs::read_dir(src)? {
             let entry = entry?;
             let path = entry.path();
             if path.is_file() {
                 fs::copy(&path, dst.join(entry.file_name()))?;
+            } else if path.is_dir()
+                && [WAL_DIR, SST_DIR, META_DIR].iter().any(|dir| entry.file_name() == *dir)
+            {
+                let target = dst.join(entry.file_name());
+                fs::create_dir_all(&target)?;
+                self.copy_dir(&path, &target)?;
             }
         }
         Ok(())
     }
 
+    /// Flushes and closes every open database, for use on shutdown.
+    pub fn close_all(&self) -> VeloResult<()> {
+        let databases: Vec<Arc<Velocity>> =
+            self.databases.read().unwrap().values().cloned().collect();
+        for db in databases
+            .iter()
+            .chain(self.system_db.iter())
+            .chain(std::iter::once(&self.default_db))
+        {
+            db.close()?;
+        }
+        Ok(())
+    }
+
+    pub fn lifetime_stats(&self) -> crate::LifetimeStats {
+        self.default_db.lifetime_stats()
+    }
+
     pub fn stats(&self) -> crate::VelocityStats {
         let default_stats = self.default_db.stats();
         let mut agg_stats = default_stats;
@@ -495,10 +1472,115 @@ impl DatabaseManager {
                 agg_stats.memtable_entries += s.memtable_entries;
                 agg_stats.sstable_count += s.sstable_count;
                 agg_stats.cache_entries += s.cache_entries;
+                agg_stats.cache_bytes += s.cache_bytes;
+                agg_stats.memtable_bytes += s.memtable_bytes;
                 agg_stats.total_sstable_size += s.total_sstable_size;
+                agg_stats.cache_hits += s.cache_hits;
+                agg_stats.cache_misses += s.cache_misses;
+                agg_stats.memtable_arena += s.memtable_arena;
+                agg_stats.read_sources += s.read_sources;
             }
         }
 
         agg_stats
     }
 }
+
+
+pub async fn run_memtable_auto_flush(manager: Arc<DatabaseManager>) {
+    let mut interval = tokio::time::interval(std::time::Duration::from_secs(1));
+    loop {
+        interval.tick().await;
+        let manager = manager.clone();
+        let flushed = tokio::task::spawn_blocking(move || manager.flush_aged_memtables())
+            .await
+            .unwrap_or(0);
+        if flushed > 0 {
+            log::debug!("Auto-flushed {} memtables", flushed);
+        }
+    }
+}
+
+// Keys are loaded in small chunks so warming never holds up requests for
+// long. Every database open at startup is warmed, one after another.
+pub async fn run_cache_warmup(manager: Arc<DatabaseManager>) {
+    let mut databases: Vec<(String, Arc<Velocity>)> = manager
+        .databases
+        .read()
+        .unwrap()
+        .iter()
+        .map(|(name, db)| (name.clone(), db.clone()))
+        .collect();
+    databases.insert(0, ("default".to_string(), manager.default_db.clone()));
+    for (name, db) in databases {
+        let mut warmed = 0;
+        loop {
+            let db = db.clone();
+            match tokio::task::spawn_blocking(move || db.warm_cache(256)).await {
+                Ok(Ok(0)) => break,
+                Ok(Ok(loaded)) => warmed += loaded,
+                Ok(Err(e)) => {
+                    log::warn!("Cache warmup of '{}' stopped: {}", name, e);
+                    break;
+                }
+                Err(_) => break,
+            }
+            tokio::task::yield_now().await;
+        }
+        if warmed > 0 {
+            log::info!("Warmed the cache of '{}' with {} keys", name, warmed);
+        }
+    }
+}
+
+pub async fn run_capacity_sampling(manager: Arc<DatabaseManager>) {
+    let mut interval = tokio::time::interval(std::time::Duration::from_secs(3600));
+    loop {
+        interval.tick().await;
+        for name in manager.list_databases() {
+            let Some(db) = manager.get_database(&name) else {
+                continue;
+            };
+            let sampled = tokio::task::spawn_blocking(move || db.record_capacity_sample()).await;
+            if let Ok(Err(e)) = sampled {
+                log::warn!("Failed to record capacity sample for '{}': {}", name, e);
+            }
+        }
+    }
+}
+
+pub async fn run_trash_retention(manager: Arc<DatabaseManager>) {
+    let mut interval = tokio::time::interval(std::time::Duration::from_secs(3600));
+    loop {
+        interval.tick().await;
+        let purged = manager.purge_expired_trash();
+        if purged > 0 {
+            log::info!("Purged {} expired trash entries", purged);
+        }
+    }
+}
+
+// Databases opened next to a read-only default database, as `velocity
+// doctor` does, are read-only too, and all of them use its data layout and
+// cache warmup.
+fn open_alongside(default_db: &Velocity, path: &Path) -> VeloResult<Velocity> {
+    let config = VelocityConfig {
+        read_only: default_db.is_read_only(),
+        data_layout: default_db.config().data_layout,
+        cache_warmup: default_db.config().cache_warmup,
+        ..VelocityConfig::default()
+    };
+    Velocity::open_with_config(path, config)
+}
+
+fn emit_to(listeners: &RwLock<Vec<(u64, EventCallback)>>, event: ManagerEvent) {
+    let listeners: Vec<EventCallback> = listeners
+        .read()
+        .unwrap()
+        .iter()
+        .map(|(_, callback)| callback.clone())
+        .collect();
+    for callback in listeners {
+        callback(&event);
+    }
+}
@@ -0,0 +1,620 @@
+use std::collections::HashMap;
+use std::future::Future;
+use std::path::PathBuf;
+use std::pin::Pin;
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use argon2::{Argon2, PasswordHash, PasswordVerifier};
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use ring::signature::{self, RsaPublicKeyComponents};
+use serde::{Deserialize, Serialize};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::time::timeout;
+use tokio_rustls::TlsConnector;
+
+use crate::server::load_root_store;
+use crate::{VeloError, VeloResult};
+
+pub type AuthFuture<'a> = Pin<Box<dyn Future<Output = VeloResult<Option<String>>> + Send + 'a>>;
+
+
+pub trait AuthProvider: Send + Sync {
+    fn name(&self) -> &'static str;
+
+
+    fn authenticate<'a>(&'a self, username: &'a str, secret: &'a str) -> AuthFuture<'a>;
+
+    fn supports_password_change(&self) -> bool {
+        false
+    }
+}
+
+fn default_realm() -> String {
+    "local".to_string()
+}
+
+fn default_ldap_timeout() -> u64 {
+    5
+}
+
+fn default_username_claim() -> String {
+    "preferred_username".to_string()
+}
+
+fn default_leeway() -> u64 {
+    60
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AuthConfig {
+    #[serde(default = "default_realm")]
+    pub default_realm: String,
+    #[serde(default)]
+    pub realms: HashMap<String, RealmConfig>,
+}
+
+impl Default for AuthConfig {
+    fn default() -> Self {
+        Self {
+            default_realm: default_realm(),
+            realms: HashMap::new(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "provider", rename_all = "lowercase")]
+pub enum RealmConfig {
+    Static,
+    Ldap(LdapConfig),
+    Oidc(OidcConfig),
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LdapConfig {
+    pub url: String,
+    pub bind_dn_template: String,
+    #[serde(default)]
+    pub ca_cert_path: Option<String>,
+    #[serde(default = "default_ldap_timeout")]
+    pub timeout_seconds: u64,
+    /// Upgrade an `ldap://` connection with StartTLS before binding.
+    #[serde(default)]
+    pub start_tls: bool,
+    /// Allow binding over plain `ldap://`, which sends passwords in
+    /// cleartext.
+    #[serde(default)]
+    pub allow_insecure: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OidcConfig {
+    pub issuer: String,
+    pub audience: String,
+    pub jwks_path: PathBuf,
+    #[serde(default = "default_username_claim")]
+    pub username_claim: String,
+    #[serde(default = "default_leeway")]
+    pub leeway_seconds: u64,
+}
+
+#[derive(Debug, Clone)]
+pub struct AuthenticatedUser {
+    pub principal: String,
+    pub username: String,
+    pub realm: String,
+    pub can_change_password: bool,
+}
+
+
+pub struct AuthRegistry {
+    default_realm: String,
+    providers: HashMap<String, Arc<dyn AuthProvider>>,
+}
+
+impl AuthRegistry {
+    pub fn from_config(
+        config: &AuthConfig,
+        users: Arc<RwLock<HashMap<String, String>>>,
+    ) -> VeloResult<Self> {
+        let mut providers: HashMap<String, Arc<dyn AuthProvider>> = HashMap::new();
+        for (realm, realm_config) in &config.realms {
+            let provider: Arc<dyn AuthProvider> = match realm_config {
+                RealmConfig::Static => Arc::new(StaticAuthProvider::new(users.clone())),
+                RealmConfig::Ldap(ldap) => Arc::new(LdapAuthProvider::new(ldap.clone())?),
+                RealmConfig::Oidc(oidc) => Arc::new(OidcAuthProvider::new(oidc.clone())?),
+            };
+            providers.insert(realm.clone(), provider);
+        }
+
+        providers
+            .entry(config.default_realm.clone())
+            .or_insert_with(|| Arc::new(StaticAuthProvider::new(users)));
+
+        Ok(Self {
+            default_realm: config.default_realm.clone(),
+            providers,
+        })
+    }
+
+    pub fn realms(&self) -> Vec<(String, &'static str)> {
+        let mut realms: Vec<_> = self
+            .providers
+            .iter()
+            .map(|(realm, provider)| (realm.clone(), provider.name()))
+            .collect();
+        realms.sort();
+        realms
+    }
+
+
+    pub async fn authenticate(
+        &self,
+        login: &str,
+        secret: &str,
+    ) -> VeloResult<Option<AuthenticatedUser>> {
+        let (username, realm) = match login.rsplit_once('@') {
+            Some((user, realm)) if self.providers.contains_key(realm) => (user, realm),
+            _ => (login, self.default_realm.as_str()),
+        };
+        let provider = &self.providers[realm];
+
+        let Some(username) = provider.authenticate(username, secret).await? else {
+            return Ok(None);
+        };
+
+        let principal = if realm == self.default_realm {
+            username.clone()
+        } else {
+            format!("{}@{}", username, realm)
+        };
+
+        Ok(Some(AuthenticatedUser {
+            principal,
+            username,
+            realm: realm.to_string(),
+            can_change_password: provider.supports_password_change(),
+        }))
+    }
+}
+
+
+pub struct StaticAuthProvider {
+    users: Arc<RwLock<HashMap<String, String>>>,
+}
+
+impl StaticAuthProvider {
+    pub fn new(users: Arc<RwLock<HashMap<String, String>>>) -> Self {
+        Self { users }
+    }
+}
+
+impl AuthProvider for StaticAuthProvider {
+    fn name(&self) -> &'static str {
+        "static"
+    }
+
+    fn authenticate<'a>(&'a self, username: &'a str, secret: &'a str) -> AuthFuture<'a> {
+        Box::pin(async move {
+            let stored_hash = self.users.read().unwrap().get(username).cloned();
+            let Some(stored_hash) = stored_hash else {
+                return Ok(None);
+            };
+
+            let verified = PasswordHash::new(&stored_hash)
+                .map(|parsed| {
+                    Argon2::default()
+                        .verify_password(secret.as_bytes(), &parsed)
+                        .is_ok()
+                })
+                .unwrap_or(false);
+
+            Ok(verified.then(|| username.to_string()))
+        })
+    }
+
+    fn supports_password_change(&self) -> bool {
+        true
+    }
+}
+
+
+pub struct LdapAuthProvider {
+    config: LdapConfig,
+    host: String,
+    port: u16,
+    tls: Option<TlsConnector>,
+}
+
+impl LdapAuthProvider {
+    pub fn new(config: LdapConfig) -> VeloResult<Self> {
+        let (secure, rest) = if let Some(rest) = config.url.strip_prefix("ldaps://") {
+            (true, rest)
+        } else if let Some(rest) = config.url.strip_prefix("ldap://") {
+            (false, rest)
+        } else {
+            return Err(VeloError::InvalidOperation(format!(
+                "Unsupported LDAP url: {}",
+                config.url
+            )));
+        };
+
+        let authority = rest.trim_end_matches('/');
+        let (host, port) = match authority.rsplit_once(':') {
+            Some((host, port)) => (
+                host.to_string(),
+                port.parse::<u16>().map_err(|_| {
+                    VeloError::InvalidOperation(format!("Invalid LDAP port in {}", config.url))
+                })?,
+            ),
+            None => (authority.to_string(), if secure { 636 } else { 389 }),
+        };
+
+        if secure && config.start_tls {
+            return Err(VeloError::InvalidOperation(
+                "start_tls applies to ldap:// urls; ldaps:// is already encrypted".to_string(),
+            ));
+        }
+        let tls = if secure || config.start_tls {
+            Some(ldap_tls_connector(config.ca_cert_path.as_deref())?)
+        } else if config.allow_insecure {
+            log::warn!(
+                "LDAP realm at {} binds without TLS; passwords are sent in cleartext",
+                config.url
+            );
+            None
+        } else {
+            return Err(VeloError::InvalidOperation(format!(
+                "{} would send passwords in cleartext; use ldaps://, set start_tls = true, or set allow_insecure = true",
+                config.url
+            )));
+        };
+
+        Ok(Self {
+            config,
+            host,
+            port,
+            tls,
+        })
+    }
+
+    async fn bind(&self, dn: &str, password: &str) -> VeloResult<bool> {
+        let mut stream = TcpStream::connect((self.host.as_str(), self.port)).await?;
+        let Some(connector) = &self.tls else {
+            return ldap_simple_bind(stream, 1, dn, password).await;
+        };
+        let server_name = rustls::ServerName::try_from(self.host.as_str()).map_err(|_| {
+            VeloError::InvalidOperation(format!("Invalid LDAP host: {}", self.host))
+        })?;
+        let message_id = if self.config.start_tls {
+            ldap_start_tls(&mut stream).await?;
+            2
+        } else {
+            1
+        };
+        let stream = connector.connect(server_name, stream).await?;
+        ldap_simple_bind(stream, message_id, dn, password).await
+    }
+}
+
+impl AuthProvider for LdapAuthProvider {
+    fn name(&self) -> &'static str {
+        "ldap"
+    }
+
+    fn authenticate<'a>(&'a self, username: &'a str, secret: &'a str) -> AuthFuture<'a> {
+        Box::pin(async move {
+
+            if username.is_empty() || secret.is_empty() {
+                return Ok(None);
+            }
+
+            let dn = self
+                .config
+                .bind_dn_template
+                .replace("{username}", &escape_dn_value(username));
+            let bound = timeout(
+                Duration::from_secs(self.config.timeout_seconds),
+                self.bind(&dn, secret),
+            )
+            .await
+            .map_err(|_| VeloError::InvalidOperation("LDAP bind timed out".to_string()))??;
+
+            Ok(bound.then(|| username.to_string()))
+        })
+    }
+}
+
+fn ldap_tls_connector(ca_cert_path: Option<&str>) -> VeloResult<TlsConnector> {
+    let ca_cert_path = ca_cert_path.ok_or_else(|| {
+        VeloError::InvalidOperation("LDAP over TLS requires ca_cert_path".to_string())
+    })?;
+
+    let config = rustls::ClientConfig::builder()
+        .with_safe_defaults()
+        .with_root_certificates(load_root_store(ca_cert_path)?)
+        .with_no_client_auth();
+    Ok(TlsConnector::from(Arc::new(config)))
+}
+
+fn escape_dn_value(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    let mut chars = value.chars().enumerate().peekable();
+    while let Some((i, c)) = chars.next() {
+        let leading = i == 0 && (c == ' ' || c == '#');
+        let trailing = chars.peek().is_none() && c == ' ';
+        if leading || trailing || matches!(c, ',' | '+' | '"' | '\\' | '<' | '>' | ';' | '=') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+fn ber_tlv(tag: u8, content: &[u8]) -> Vec<u8> {
+    let mut out = vec![tag];
+    if content.len() < 0x80 {
+        out.push(content.len() as u8);
+    } else {
+        let len_bytes = (content.len() as u32).to_be_bytes();
+        let skip = len_bytes.iter().take_while(|b| **b == 0).count();
+        out.push(0x80 | (4 - skip) as u8);
+        out.extend_from_slice(&len_bytes[skip..]);
+    }
+    out.extend_from_slice(content);
+    out
+}
+
+fn ber_read(buf: &[u8]) -> Option<(u8, &[u8], &[u8])> {
+    let tag = *buf.first()?;
+    let first = *buf.get(1)? as usize;
+    let (len, header) = if first < 0x80 {
+        (first, 2)
+    } else {
+        let n = first & 0x7f;
+        let bytes = buf.get(2..2 + n)?;
+        (bytes.iter().fold(0usize, |acc, b| (acc << 8) | *b as usize), 2 + n)
+    };
+    let content = buf.get(header..header + len)?;
+    Some((tag, content, &buf[header + len..]))
+}
+
+const START_TLS_OID: &str = "1.3.6.1.4.1.1466.20037";
+
+async fn ldap_simple_bind<S>(
+    mut stream: S,
+    message_id: u8,
+    dn: &str,
+    password: &str,
+) -> VeloResult<bool>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let mut bind = ber_tlv(0x02, &[3]);
+    bind.extend(ber_tlv(0x04, dn.as_bytes()));
+    bind.extend(ber_tlv(0x80, password.as_bytes()));
+    match ldap_exchange(&mut stream, message_id, ber_tlv(0x60, &bind), 0x61).await? {
+        0 => Ok(true),
+        49 => Ok(false),
+        code => Err(VeloError::InvalidOperation(format!(
+            "LDAP bind failed with result code {}",
+            code
+        ))),
+    }
+}
+
+// Asks the server to switch the connection to TLS; the handshake follows.
+async fn ldap_start_tls<S>(stream: &mut S) -> VeloResult<()>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let request = ber_tlv(0x77, &ber_tlv(0x80, START_TLS_OID.as_bytes()));
+    match ldap_exchange(stream, 1, request, 0x78).await? {
+        0 => Ok(()),
+        code => Err(VeloError::InvalidOperation(format!(
+            "LDAP server refused StartTLS with result code {}",
+            code
+        ))),
+    }
+}
+
+// Sends one request and returns the result code of the response, which
+// must be a `response_tag` operation.
+async fn ldap_exchange<S>(
+    stream: &mut S,
+    message_id: u8,
+    request: Vec<u8>,
+    response_tag: u8,
+) -> VeloResult<u8>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let mut message = ber_tlv(0x02, &[message_id]);
+    message.extend(request);
+    stream.write_all(&ber_tlv(0x30, &message)).await?;
+    stream.flush().await?;
+
+    let mut header = [0u8; 2];
+    stream.read_exact(&mut header).await?;
+    let mut response = header.to_vec();
+    let len = if header[1] < 0x80 {
+        header[1] as usize
+    } else {
+        let mut len_bytes = vec![0u8; (header[1] & 0x7f) as usize];
+        stream.read_exact(&mut len_bytes).await?;
+        response.extend_from_slice(&len_bytes);
+        len_bytes.iter().fold(0usize, |acc, b| (acc << 8) | *b as usize)
+    };
+    if len > 64 * 1024 {
+        return Err(VeloError::CorruptedData("LDAP response too large".to_string()));
+    }
+    let mut content = vec![0u8; len];
+    stream.read_exact(&mut content).await?;
+    response.extend_from_slice(&content);
+
+    ber_read(&response)
+        .filter(|(tag, _, _)| *tag == 0x30)
+        .and_then(|(_, message, _)| ber_read(message))
+        .and_then(|(_, _, rest)| ber_read(rest))
+        .filter(|(tag, _, _)| *tag == response_tag)
+        .and_then(|(_, result, _)| ber_read(result))
+        .filter(|(tag, _, _)| *tag == 0x0a)
+        .and_then(|(_, code, _)| code.last().copied())
+        .ok_or_else(|| VeloError::CorruptedData("Malformed LDAP response".to_string()))
+}
+
+
+#[derive(Deserialize)]
+struct Jwks {
+    keys: Vec<Jwk>,
+}
+
+#[derive(Deserialize)]
+struct Jwk {
+    kty: String,
+    #[serde(default)]
+    kid: Option<String>,
+    #[serde(default)]
+    n: Option<String>,
+    #[serde(default)]
+    e: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct JwtHeader {
+    alg: String,
+    #[serde(default)]
+    kid: Option<String>,
+}
+
+struct RsaKey {
+    kid: Option<String>,
+    n: Vec<u8>,
+    e: Vec<u8>,
+}
+
+pub struct OidcAuthProvider {
+    config: OidcConfig,
+    keys: Vec<RsaKey>,
+}
+
+impl OidcAuthProvider {
+    pub fn new(config: OidcConfig) -> VeloResult<Self> {
+        let content = std::fs::read_to_string(&config.jwks_path)?;
+        let jwks: Jwks = serde_json::from_str(&content).map_err(|e| {
+            VeloError::InvalidOperation(format!("Invalid JWKS {:?}: {}", config.jwks_path, e))
+        })?;
+
+        let keys: Vec<RsaKey> = jwks
+            .keys
+            .into_iter()
+            .filter(|k| k.kty == "RSA")
+            .filter_map(|k| {
+                Some(RsaKey {
+                    kid: k.kid,
+                    n: URL_SAFE_NO_PAD.decode(k.n?).ok()?,
+                    e: URL_SAFE_NO_PAD.decode(k.e?).ok()?,
+                })
+            })
+            .collect();
+        if keys.is_empty() {
+            return Err(VeloError::InvalidOperation(format!(
+                "No usable RSA keys in {:?}",
+                config.jwks_path
+            )));
+        }
+
+        Ok(Self { config, keys })
+    }
+
+    fn validate(&self, token: &str) -> Option<serde_json::Value> {
+        let mut segments = token.split('.');
+        let (header, payload, signature) = (segments.next()?, segments.next()?, segments.next()?);
+        if segments.next().is_some() {
+            return None;
+        }
+
+        let header: JwtHeader = serde_json::from_slice(&URL_SAFE_NO_PAD.decode(header).ok()?).ok()?;
+        let algorithm: &signature::RsaParameters = match header.alg.as_str() {
+            "RS256" => &signature::RSA_PKCS1_2048_8192_SHA256,
+            "RS384" => &signature::RSA_PKCS1_2048_8192_SHA384,
+            "RS512" => &signature::RSA_PKCS1_2048_8192_SHA512,
+            _ => return None,
+        };
+        let signature = URL_SAFE_NO_PAD.decode(signature).ok()?;
+        let signed = &token[..token.rfind('.')?];
+
+        let verified = self
+            .keys
+            .iter()
+            .filter(|key| header.kid.is_none() || key.kid == header.kid)
+            .any(|key| {
+                RsaPublicKeyComponents { n: &key.n, e: &key.e }
+                    .verify(algorithm, signed.as_bytes(), &signature)
+                    .is_ok()
+            });
+        if !verified {
+            return None;
+        }
+
+        let claims: serde_json::Value =
+            serde_json::from_slice(&URL_SAFE_NO_PAD.decode(payload).ok()?).ok()?;
+
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        let leeway = self.config.leeway_seconds;
+        let exp = claims.get("exp")?.as_u64()?;
+        if now > exp + leeway {
+            return None;
+        }
+        if let Some(nbf) = claims.get("nbf").and_then(|v| v.as_u64()) {
+            if now + leeway < nbf {
+                return None;
+            }
+        }
+        if claims.get("iss")?.as_str()? != self.config.issuer {
+            return None;
+        }
+        let audience_ok = match claims.get("aud")? {
+            serde_json::Value::String(aud) => *aud == self.config.audience,
+            serde_json::Value::Array(auds) => auds
+                .iter()
+                .any(|aud| aud.as_str() == Some(self.config.audience.as_str())),
+            _ => false,
+        };
+
+        audience_ok.then_some(claims)
+    }
+}
+
+impl AuthProvider for OidcAuthProvider {
+    fn name(&self) -> &'static str {
+        "oidc"
+    }
+
+    fn authenticate<'a>(&'a self, username: &'a str, secret: &'a str) -> AuthFuture<'a> {
+        Box::pin(async move {
+            let Some(claims) = self.validate(secret) else {
+                return Ok(None);
+            };
+            let Some(subject) = claims
+                .get(&self.config.username_claim)
+                .and_then(|v| v.as_str())
+            else {
+                return Ok(None);
+            };
+
+
+            if !username.is_empty() && username != subject {
+                return Ok(None);
+            }
+
+            Ok(Some(subject.to_string()))
+        })
+    }
+}
@@ -0,0 +1,179 @@
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{VeloError, VeloResult};
+
+type Job = Box<dyn FnOnce() + Send + 'static>;
+
+fn default_threads() -> usize {
+    2
+}
+
+fn default_thread_name() -> String {
+    "velocity-bg".to_string()
+}
+
+fn default_max_queued() -> usize {
+    1024
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BackgroundPoolConfig {
+    #[serde(default = "default_threads")]
+    pub threads: usize,
+    /// Threads are named `<thread_name>-<n>`.
+    #[serde(default = "default_thread_name")]
+    pub thread_name: String,
+    /// CPUs to pin the threads to, assigned round robin. Empty leaves
+    /// placement to the OS; pinning is only supported on Linux.
+    #[serde(default)]
+    pub cpus: Vec<usize>,
+    /// Jobs waiting for a thread beyond this many are refused.
+    #[serde(default = "default_max_queued")]
+    pub max_queued: usize,
+}
+
+impl Default for BackgroundPoolConfig {
+    fn default() -> Self {
+        Self {
+            threads: default_threads(),
+            thread_name: default_thread_name(),
+            cpus: Vec::new(),
+            max_queued: default_max_queued(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct BackgroundPoolStats {
+    pub threads: usize,
+    pub queued: usize,
+    pub running: usize,
+    pub completed: u64,
+}
+
+#[derive(Default)]
+struct Counters {
+    queued: AtomicUsize,
+    running: AtomicUsize,
+    completed: AtomicU64,
+}
+
+/// A fixed set of named threads that run the engine's background work,
+/// such as full compactions. The threads exit once the pool is dropped and
+/// the queue is empty.
+pub struct BackgroundPool {
+    sender: Mutex<mpsc::Sender<Job>>,
+    threads: usize,
+    max_queued: usize,
+    counters: Arc<Counters>,
+}
+
+impl BackgroundPool {
+    pub fn new(config: &BackgroundPoolConfig) -> VeloResult<Self> {
+        let threads = config.threads.max(1);
+        let (sender, receiver) = mpsc::channel::<Job>();
+        let receiver = Arc::new(Mutex::new(receiver));
+        let counters = Arc::new(Counters::default());
+
+        for index in 0..threads {
+            let receiver = receiver.clone();
+            let counters = counters.clone();
+            let cpu = (!config.cpus.is_empty()).then(|| config.cpus[index % config.cpus.len()]);
+            thread::Builder::new()
+                .name(format!("{}-{}", config.thread_name, index))
+                .spawn(move || {
+                    if let Some(cpu) = cpu {
+                        pin_to_cpu(cpu);
+                    }
+                    loop {
+                        let job = receiver.lock().unwrap().recv();
+                        let Ok(job) = job else {
+                            break;
+                        };
+                        counters.queued.fetch_sub(1, Ordering::Relaxed);
+                        counters.running.fetch_add(1, Ordering::Relaxed);
+                        job();
+                        counters.running.fetch_sub(1, Ordering::Relaxed);
+                        counters.completed.fetch_add(1, Ordering::Relaxed);
+                    }
+                })
+                .map_err(|e| {
+                    VeloError::InvalidOperation(format!(
+                        "Failed to start background thread: {}",
+                        e
+                    ))
+                })?;
+        }
+
+        Ok(Self {
+            sender: Mutex::new(sender),
+            threads,
+            max_queued: config.max_queued.max(1),
+            counters,
+        })
+    }
+
+    /// Queues `job`, or refuses it when `max_queued` jobs are already
+    /// waiting.
+    pub fn spawn<F>(&self, job: F) -> VeloResult<()>
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        let queued = self.counters.queued.fetch_add(1, Ordering::Relaxed);
+        if queued >= self.max_queued {
+            self.counters.queued.fetch_sub(1, Ordering::Relaxed);
+            return Err(VeloError::InvalidOperation(format!(
+                "Background queue is full ({} jobs waiting)",
+                queued
+            )));
+        }
+        if self.sender.lock().unwrap().send(Box::new(job)).is_err() {
+            self.counters.queued.fetch_sub(1, Ordering::Relaxed);
+            return Err(VeloError::InvalidOperation(
+                "Background pool has stopped".to_string(),
+            ));
+        }
+        Ok(())
+    }
+
+    pub fn stats(&self) -> BackgroundPoolStats {
+        BackgroundPoolStats {
+            threads: self.threads,
+            queued: self.counters.queued.load(Ordering::Relaxed),
+            running: self.counters.running.load(Ordering::Relaxed),
+            completed: self.counters.completed.load(Ordering::Relaxed),
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn pin_to_cpu(cpu: usize) {
+    if cpu >= libc::CPU_SETSIZE as usize {
+        log::warn!("Ignoring CPU {}: beyond the highest CPU that can be pinned", cpu);
+        return;
+    }
+    // SAFETY: the set is zeroed and filled before use, and only the calling
+    // thread's affinity is changed.
+    let result = unsafe {
+        let mut set: libc::cpu_set_t = std::mem::zeroed();
+        libc::CPU_SET(cpu, &mut set);
+        libc::sched_setaffinity(0, std::mem::size_of::<libc::cpu_set_t>(), &set)
+    };
+    if result != 0 {
+        log::warn!(
+            "Failed to pin {} to CPU {}: {}",
+            thread::current().name().unwrap_or("background thread"),
+            cpu,
+            std::io::Error::last_os_error()
+        );
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn pin_to_cpu(cpu: usize) {
+    log::warn!("CPU pinning is not supported on this platform; ignoring CPU {}", cpu);
+}
@@ -0,0 +1,292 @@
+use std::str::FromStr;
+use std::sync::Arc;
+
+use chrono::{DateTime, Datelike, Duration, Local, NaiveTime, Timelike};
+use serde::Serialize;
+
+use crate::addon::{BackupAddonConfig, DatabaseManager};
+use crate::{VeloError, VeloResult};
+
+const SEARCH_LIMIT_MINUTES: i64 = 366 * 24 * 60;
+
+const WEEKDAYS: [&str; 7] = ["sun", "mon", "tue", "wed", "thu", "fri", "sat"];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CronExpression {
+    minutes: u64,
+    hours: u64,
+    days_of_month: u64,
+    months: u64,
+    days_of_week: u64,
+    dom_restricted: bool,
+    dow_restricted: bool,
+}
+
+impl CronExpression {
+    pub fn matches(&self, time: &DateTime<Local>) -> bool {
+        let bit = |mask: u64, value: u32| mask & (1 << value) != 0;
+
+        let dom = bit(self.days_of_month, time.day());
+        let dow = bit(self.days_of_week, time.weekday().num_days_from_sunday());
+        let day_matches = match (self.dom_restricted, self.dow_restricted) {
+            (true, true) => dom || dow,
+            _ => dom && dow,
+        };
+
+        bit(self.minutes, time.minute())
+            && bit(self.hours, time.hour())
+            && bit(self.months, time.month())
+            && day_matches
+    }
+}
+
+impl FromStr for CronExpression {
+    type Err = VeloError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let fields: Vec<&str> = s.split_whitespace().collect();
+        if fields.len() != 5 {
+            return Err(VeloError::InvalidOperation(format!(
+                "Cron expression '{}' must have 5 fields (minute hour day month weekday)",
+                s
+            )));
+        }
+
+        let mut days_of_week = parse_cron_field(fields[4], 0, 7)?;
+        if days_of_week & (1 << 7) != 0 {
+            days_of_week |= 1;
+        }
+
+        Ok(Self {
+            minutes: parse_cron_field(fields[0], 0, 59)?,
+            hours: parse_cron_field(fields[1], 0, 23)?,
+            days_of_month: parse_cron_field(fields[2], 1, 31)?,
+            months: parse_cron_field(fields[3], 1, 12)?,
+            days_of_week,
+            dom_restricted: fields[2] != "*",
+            dow_restricted: fields[4] != "*",
+        })
+    }
+}
+
+fn parse_cron_field(field: &str, min: u32, max: u32) -> VeloResult<u64> {
+    let invalid = || VeloError::InvalidOperation(format!("Invalid cron field '{}'", field));
+    let mut mask = 0u64;
+
+    for part in field.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => (range, step.parse::<u32>().map_err(|_| invalid())?),
+            None => (part, 1),
+        };
+        let (start, end) = match range {
+            "*" => (min, max),
+            _ => match range.split_once('-') {
+                Some((a, b)) => (
+                    a.parse().map_err(|_| invalid())?,
+                    b.parse().map_err(|_| invalid())?,
+                ),
+                None => {
+                    let value = range.parse().map_err(|_| invalid())?;
+                    (value, if part.contains('/') { max } else { value })
+                }
+            },
+        };
+        if step == 0 || start < min || end > max || start > end {
+            return Err(invalid());
+        }
+        for value in (start..=end).step_by(step as usize) {
+            mask |= 1 << value;
+        }
+    }
+
+    Ok(mask)
+}
+
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TimeWindow {
+    days: u8,
+    start: NaiveTime,
+    end: NaiveTime,
+}
+
+impl TimeWindow {
+    pub fn contains(&self, time: &DateTime<Local>) -> bool {
+        if self.days & (1 << time.weekday().num_days_from_sunday()) == 0 {
+            return false;
+        }
+        let t = time.time();
+        if self.start <= self.end {
+            t >= self.start && t < self.end
+        } else {
+            t >= self.start || t < self.end
+        }
+    }
+}
+
+impl FromStr for TimeWindow {
+    type Err = VeloError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || {
+            VeloError::InvalidOperation(format!(
+                "Invalid time window '{}' (expected e.g. \"Mon-Fri 09:00-17:00\")",
+                s
+            ))
+        };
+        let (days_spec, times) = match s.trim().rsplit_once(' ') {
+            Some((days, times)) => (Some(days.trim()), times),
+            None => (None, s.trim()),
+        };
+        let (start, end) = times.split_once('-').ok_or_else(invalid)?;
+        let parse_time = |t: &str| NaiveTime::parse_from_str(t, "%H:%M").map_err(|_| invalid());
+
+        let days = match days_spec {
+            None => 0x7f,
+            Some(spec) => {
+                let day_index = |name: &str| {
+                    WEEKDAYS
+                        .iter()
+                        .position(|d| name.to_lowercase().starts_with(d))
+                        .ok_or_else(invalid)
+                };
+                let mut days = 0u8;
+                for part in spec.split(',') {
+                    match part.split_once('-') {
+                        Some((a, b)) => {
+                            let (a, b) = (day_index(a)?, day_index(b)?);
+                            let mut day = a;
+                            loop {
+                                days |= 1 << day;
+                                if day == b {
+                                    break;
+                                }
+                                day = (day + 1) % 7;
+                            }
+                        }
+                        None => days |= 1 << day_index(part)?,
+                    }
+                }
+                days
+            }
+        };
+
+        Ok(Self {
+            days,
+            start: parse_time(start)?,
+            end: parse_time(end)?,
+        })
+    }
+}
+
+
+#[derive(Debug, Clone)]
+pub struct BackupSchedule {
+    interval: Duration,
+    cron: Option<CronExpression>,
+    allowed_windows: Vec<TimeWindow>,
+    blackout_periods: Vec<TimeWindow>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct BackupScheduleInfo {
+    pub enabled: bool,
+    pub cron: Option<String>,
+    pub interval_minutes: u64,
+    pub allowed_windows: Vec<String>,
+    pub blackout_periods: Vec<String>,
+    pub next_run: Option<String>,
+}
+
+impl BackupSchedule {
+    pub fn from_config(config: &BackupAddonConfig) -> VeloResult<Self> {
+        let parse_windows = |list: &[String]| {
+            list.iter()
+                .map(|w| w.parse())
+                .collect::<VeloResult<Vec<TimeWindow>>>()
+        };
+
+        Ok(Self {
+            interval: Duration::minutes(config.interval_minutes.max(1) as i64),
+            cron: config.cron.as_deref().map(str::parse).transpose()?,
+            allowed_windows: parse_windows(&config.allowed_windows)?,
+            blackout_periods: parse_windows(&config.blackout_periods)?,
+        })
+    }
+
+    pub fn is_permitted(&self, time: &DateTime<Local>) -> bool {
+        (self.allowed_windows.is_empty() || self.allowed_windows.iter().any(|w| w.contains(time)))
+            && !self.blackout_periods.iter().any(|w| w.contains(time))
+    }
+
+
+    pub fn next_run_after(
+        &self,
+        now: DateTime<Local>,
+        last_run: Option<DateTime<Local>>,
+    ) -> Option<DateTime<Local>> {
+        let now = now.with_second(0)?.with_nanosecond(0)?;
+        let step = if self.cron.is_some() {
+            Duration::minutes(1)
+        } else {
+            self.interval
+        };
+        let mut candidate = match last_run {
+            Some(last) => (last + step).max(now),
+            None => now + step,
+        };
+        candidate = candidate.with_second(0)?.with_nanosecond(0)?;
+
+        for _ in 0..SEARCH_LIMIT_MINUTES {
+            let due = self.cron.as_ref().is_none_or(|cron| cron.matches(&candidate));
+            if due && self.is_permitted(&candidate) {
+                return Some(candidate);
+            }
+            candidate += Duration::minutes(1);
+        }
+        None
+    }
+}
+
+
+pub async fn run_backup_schedule(manager: Arc<DatabaseManager>) {
+    let recheck = std::time::Duration::from_secs(60);
+
+    loop {
+        let next = match manager.next_backup_run() {
+            Ok(Some(next)) => next,
+            Ok(None) => {
+                log::warn!("Backup schedule has no permitted run within the next year");
+                tokio::time::sleep(recheck).await;
+                continue;
+            }
+            Err(e) => {
+                log::error!("Invalid backup schedule: {}", e);
+                tokio::time::sleep(recheck).await;
+                continue;
+            }
+        };
+
+
+        let wait = (next - Local::now()).to_std().unwrap_or_default();
+        if !wait.is_zero() {
+            log::debug!("Next automatic backup at {}", next);
+            tokio::time::sleep(wait.min(recheck)).await;
+            continue;
+        }
+
+        let enabled = manager
+            .list_addons()
+            .iter()
+            .any(|(name, enabled)| name == "backup" && *enabled);
+        if !enabled {
+            tokio::time::sleep(recheck).await;
+            continue;
+        }
+
+        match manager.backup_all_databases() {
+            Ok(succ) => log::info!("Automatic backup successful for {} databases", succ.len()),
+            Err(e) => log::error!("Automatic backup failed: {}", e),
+        }
+    }
+}
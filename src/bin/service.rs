@@ -1,7 +1,8 @@
 use clap::{Parser, Subcommand};
 use colored::*;
 use std::fs;
-use std::path::{PathBuf};
+use std::path::{Path, PathBuf};
+use velocity::fsutil::atomic_write;
 
 #[path = "../config.rs"]
 mod config;
@@ -39,6 +40,12 @@ enum ServiceCommand {
     Run {
         #[arg(long)]
         pid_file: Option<PathBuf>,
+        #[arg(long)]
+        user: Option<String>,
+        #[arg(long)]
+        group: Option<String>,
+        #[arg(long)]
+        read_only: bool,
     },
     Install {},
     Uninstall {},
@@ -46,8 +53,8 @@ enum ServiceCommand {
 
 fn generate_systemd_unit(
     exe_path: &str,
-    config: &PathBuf,
-    data_dir: &PathBuf,
+    config: &Path,
+    data_dir: &Path,
     bind: Option<&String>,
 ) -> String {
     let bind_arg = bind
@@ -61,7 +68,8 @@ After=network.target\n\
 \n\
 [Service]\n\
 Type=simple\n\
-ExecStart={} service run --config {} --data-dir {}{} --verbose\n\
+ExecStart={} service run --config {} --data-dir {}{}\n\
+ExecReload=/bin/kill -HUP $MAINPID\n\
 Restart=on-failure\n\
 User=velocity\n\
 \n\
@@ -74,9 +82,9 @@ WantedBy=multi-user.target\n",
     )
 }
 
-fn generate_windows_script(exe_path: &str, config: &PathBuf, data_dir: &PathBuf) -> String {
+fn generate_windows_script(exe_path: &str, config: &Path, data_dir: &Path) -> String {
     format!(
-        "param(\n    [string]$ServiceName = \"VelocityDB\",\n    [string]$DisplayName = \"VelocityDB Service\"\n)\n\n$binPath = \"{} service run --config {} --data-dir {} --verbose\"\n\nsc.exe create $ServiceName binPath= \"$binPath\" DisplayName= \"$DisplayName\" start= auto\nsc.exe description $ServiceName \"VelocityDB background service\"\n",
+        "param(\n    [string]$ServiceName = \"VelocityDB\",\n    [string]$DisplayName = \"VelocityDB Service\"\n)\n\n$binPath = \"{} service run --config {} --data-dir {}\"\n\nsc.exe create $ServiceName binPath= \"$binPath\" DisplayName= \"$DisplayName\" start= auto\nsc.exe description $ServiceName \"VelocityDB background service\"\n",
         exe_path,
         config.display(),
         data_dir.display(),
@@ -84,9 +92,9 @@ fn generate_windows_script(exe_path: &str, config: &PathBuf, data_dir: &PathBuf)
 }
 
 fn install_templates(
-    dir: &PathBuf,
-    config: &PathBuf,
-    data_dir: &PathBuf,
+    dir: &Path,
+    config: &Path,
+    data_dir: &Path,
     bind: Option<&String>,
 ) -> Result<(), Box<dyn std::error::Error>> {
     fs::create_dir_all(dir)?;
@@ -94,8 +102,8 @@ fn install_templates(
     let exe_str = exe.display().to_string();
     let unit = generate_systemd_unit(&exe_str, config, data_dir, bind);
     let script = generate_windows_script(&exe_str, config, data_dir);
-    fs::write(dir.join("velocity.service"), unit)?;
-    fs::write(dir.join("install-velocity.ps1"), script)?;
+    atomic_write(dir.join("velocity.service"), unit)?;
+    atomic_write(dir.join("install-velocity.ps1"), script)?;
     println!(
         "{} Service templates written to {:?}",
         "[SUCCESS]".green(),
@@ -104,7 +112,7 @@ fn install_templates(
     Ok(())
 }
 
-fn uninstall_templates(dir: &PathBuf) -> Result<(), Box<dyn std::error::Error>> {
+fn uninstall_templates(dir: &Path) -> Result<(), Box<dyn std::error::Error>> {
     if dir.exists() {
         fs::remove_dir_all(dir)?;
         println!("{} Removed templates under {:?}", "[WARN]".yellow(), dir);
@@ -119,7 +127,12 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     let cli = ServiceCli::parse();
 
     match cli.command {
-        ServiceCommand::Run { pid_file } => {
+        ServiceCommand::Run {
+            pid_file,
+            user,
+            group,
+            read_only,
+        } => {
             run_velocity_service(ServiceSpec {
                 config_path: cli.config,
                 data_dir: cli.data_dir,
@@ -127,6 +140,9 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                 verbose: cli.verbose,
                 pid_file,
                 watch_config: true,
+                run_as_user: user,
+                run_as_group: group,
+                read_only,
             })
             .await?;
         }
@@ -27,6 +27,10 @@ enum Commands {
 
         #[arg(long)]
         no_bloom: bool,
+
+        /// Seed the key choices and hashing so runs can be compared.
+        #[arg(long)]
+        seed: Option<u64>,
     },
 }
 
@@ -40,8 +44,9 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
             sstable_count,
             operations,
             no_bloom,
+            seed,
         } => {
-            run_stress_test(&data_dir, sstable_count, operations, no_bloom).await?;
+            run_stress_test(&data_dir, sstable_count, operations, no_bloom, seed).await?;
         }
     }
 
@@ -53,6 +58,7 @@ async fn run_stress_test(
     target_sstables: usize,
     ops_per_phase: usize,
     disable_bloom: bool,
+    seed: Option<u64>,
 ) -> Result<(), Box<dyn std::error::Error>> {
     use std::time::Instant;
     use velocity::{Velocity, VelocityConfig};
@@ -83,10 +89,28 @@ async fn run_stress_test(
         memory_only_mode: false,
         batch_wal_writes: true,
         adaptive_cache: false,
+        cache_policy: Default::default(),
+        hot_keys: Default::default(),
         enable_metrics: false,
         metrics_interval: std::time::Duration::from_secs(60),
         target_cache_hit_rate: 0.85,
         wal_sync_mode: velocity::WalSyncMode::Batch,
+        wal_shards: 1,
+        memtable_huge_pages: false,
+        io_backend: Default::default(),
+        record_metadata: false,
+        data_layout: Default::default(),
+        recovery_progress: None,
+        fault_injection: None,
+        background_threads: true,
+        background_pool: Default::default(),
+        compaction_io_budget: 0,
+        compaction_picker: None,
+        key_policies: Vec::new(),
+        cache_warmup: Default::default(),
+        hash_seed: seed,
+        wal_recovery: Default::default(),
+        read_only: false,
     };
 
     println!("{} Test Configuration:", "[CONFIG]".blue());
@@ -167,7 +191,10 @@ async fn run_stress_test(
 
     let mut read_latencies = Vec::with_capacity(ops_per_phase);
     use rand::Rng;
-    let mut rng = rand::thread_rng();
+    let mut rng: rand::rngs::StdRng = match seed {
+        Some(seed) => rand::SeedableRng::seed_from_u64(seed),
+        None => rand::SeedableRng::from_entropy(),
+    };
 
     let read_start = Instant::now();
     for _ in 0..ops_per_phase {
@@ -0,0 +1,226 @@
+//! A fluent way to open a tuned database without filling in every field of
+//! `VelocityConfig`, for example
+//! `Velocity::builder(path).preset(Preset::Throughput).cache_mb(256).open()`.
+//!
+//! Settings apply in the order they are called, so a preset is best set
+//! first. Everything the builder has no method for can be changed with
+//! `configure`.
+
+use std::path::{Path, PathBuf};
+
+use crate::compaction::CompactionStrategy;
+use crate::policy::{self, PrefixPolicy};
+use crate::{VeloError, VeloResult, Velocity, VelocityConfig, WalSyncMode};
+
+// The cache holds entries rather than bytes; `cache_mb` sizes it for keys
+// and values of about this much together.
+const ASSUMED_ENTRY_BYTES: usize = 1024;
+
+/// Starting points for common workloads.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Preset {
+    /// Every write is synced to the WAL before it returns.
+    Durable,
+    /// Large memtable and cache, WAL synced every 100 ms, and compaction
+    /// that keeps the table count low.
+    Throughput,
+    /// Small memtable and cache, one background thread and compressed
+    /// values, for small machines and many databases in one process.
+    LowMemory,
+}
+
+/// How values are compressed when they are written to SSTables.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ValueCompression {
+    None,
+    /// zstd at a level from 1 to 22.
+    Zstd(i32),
+}
+
+pub struct VelocityBuilder {
+    path: PathBuf,
+    config: VelocityConfig,
+    verify_on_open: bool,
+}
+
+impl Velocity {
+    pub fn builder<P: AsRef<Path>>(path: P) -> VelocityBuilder {
+        VelocityBuilder::new(path)
+    }
+}
+
+impl VelocityBuilder {
+    pub fn new<P: AsRef<Path>>(path: P) -> Self {
+        Self {
+            path: path.as_ref().to_path_buf(),
+            config: VelocityConfig::default(),
+            verify_on_open: false,
+        }
+    }
+
+    pub fn preset(mut self, preset: Preset) -> Self {
+        let config = &mut self.config;
+        match preset {
+            Preset::Durable => {
+                config.wal_sync_mode = WalSyncMode::EveryWrite;
+                config.batch_wal_writes = false;
+            }
+            Preset::Throughput => {
+                config.max_memtable_size = 100_000;
+                config.cache_size = 100_000;
+                config.wal_sync_mode = WalSyncMode::Interval(100);
+                config.batch_wal_writes = true;
+                config.compaction_picker = CompactionStrategy::SizeTiered.picker();
+            }
+            Preset::LowMemory => {
+                config.max_memtable_size = 2_000;
+                config.cache_size = 2_000;
+                config.adaptive_cache = false;
+                config.enable_compression = true;
+                config.background_pool.threads = 1;
+            }
+        }
+        self
+    }
+
+    /// Sizes the cache for about `mb` megabytes of entries; use
+    /// `cache_entries` when the average entry size is known.
+    pub fn cache_mb(mut self, mb: usize) -> Self {
+        self.config.cache_size = mb.saturating_mul(1024 * 1024) / ASSUMED_ENTRY_BYTES;
+        self
+    }
+
+    pub fn cache_entries(mut self, entries: usize) -> Self {
+        self.config.cache_size = entries;
+        self
+    }
+
+    /// Writes held in memory before they are flushed to an SSTable.
+    pub fn memtable_entries(mut self, entries: usize) -> Self {
+        self.config.max_memtable_size = entries;
+        self
+    }
+
+    pub fn wal_sync(mut self, mode: WalSyncMode) -> Self {
+        self.config.wal_sync_mode = mode;
+        self
+    }
+
+    pub fn wal_shards(mut self, shards: usize) -> Self {
+        self.config.wal_shards = shards;
+        self
+    }
+
+    /// Compression for keys without a key policy of their own.
+    pub fn compression(mut self, compression: ValueCompression) -> Self {
+        self.config
+            .key_policies
+            .retain(|policy| !policy.prefix.is_empty());
+        match compression {
+            ValueCompression::None => self.config.enable_compression = false,
+            ValueCompression::Zstd(level) => {
+                self.config.enable_compression = true;
+                // Longer prefixes win, so an empty one only sets the level
+                // for keys no other policy covers.
+                self.config.key_policies.push(PrefixPolicy {
+                    prefix: String::new(),
+                    ttl_secs: None,
+                    compression: Some(policy::Compression::Zstd),
+                    compression_level: Some(level),
+                });
+            }
+        }
+        self
+    }
+
+    pub fn bloom_false_positive_rate(mut self, rate: f64) -> Self {
+        self.config.bloom_false_positive_rate = rate;
+        self
+    }
+
+    pub fn compaction(mut self, strategy: CompactionStrategy) -> Self {
+        self.config.compaction_picker = strategy.picker();
+        self
+    }
+
+    pub fn prefix_policy(mut self, policy: PrefixPolicy) -> Self {
+        self.config.key_policies.push(policy);
+        self
+    }
+
+    pub fn record_metadata(mut self, enabled: bool) -> Self {
+        self.config.record_metadata = enabled;
+        self
+    }
+
+    pub fn background_threads(mut self, enabled: bool) -> Self {
+        self.config.background_threads = enabled;
+        self
+    }
+
+    /// Opens an existing database without accepting writes.
+    pub fn read_only(mut self) -> Self {
+        self.config.read_only = true;
+        self
+    }
+
+    /// Spot-checks every SSTable on open; see
+    /// `Velocity::open_with_verification`.
+    pub fn verify_on_open(mut self) -> Self {
+        self.verify_on_open = true;
+        self
+    }
+
+    /// Changes any other setting.
+    pub fn configure(mut self, f: impl FnOnce(&mut VelocityConfig)) -> Self {
+        f(&mut self.config);
+        self
+    }
+
+    fn validate(&self) -> VeloResult<()> {
+        let config = &self.config;
+        let invalid = |message: &str| Err(VeloError::InvalidOperation(message.to_string()));
+        if config.cache_size == 0 {
+            return invalid("Cache size must be at least one entry");
+        }
+        if config.max_memtable_size == 0 {
+            return invalid("Memtable size must be at least one entry");
+        }
+        if config.wal_shards == 0 {
+            return invalid("wal_shards must be at least 1");
+        }
+        if !(config.bloom_false_positive_rate > 0.0 && config.bloom_false_positive_rate <= 1.0) {
+            return invalid("Bloom filter false positive rate must be above 0 and at most 1");
+        }
+        if config.wal_sync_mode == WalSyncMode::Interval(0) {
+            return invalid("WAL sync interval must be greater than 0 ms");
+        }
+        if config.background_pool.threads == 0 {
+            return invalid("The background pool needs at least one thread");
+        }
+        let default_level = config
+            .key_policies
+            .iter()
+            .find(|policy| policy.prefix.is_empty())
+            .and_then(|policy| policy.compression_level);
+        if default_level.is_some_and(|level| !(1..=22).contains(&level)) {
+            return invalid("zstd level must be between 1 and 22");
+        }
+        policy::validate_policies(&config.key_policies)
+    }
+
+    /// The validated config, for callers that open the database themselves.
+    pub fn build(self) -> VeloResult<VelocityConfig> {
+        self.validate()?;
+        Ok(self.config)
+    }
+
+    pub fn open(self) -> VeloResult<Velocity> {
+        self.validate()?;
+        if self.verify_on_open {
+            Velocity::open_with_verification(&self.path, self.config)
+        } else {
+            Velocity::open_with_config(&self.path, self.config)
+        }
+    }
+}
@@ -0,0 +1,286 @@
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::addon::DatabaseAddonConfig;
+use crate::format::database_dirs;
+use crate::layout::{META_DIR, SST_DIR, TMP_DIR, WAL_DIR};
+use crate::{fsutil, VeloError, VeloResult, Velocity, VelocityConfig};
+
+/// Daily size and cache samples of a database, kept in its meta directory.
+pub const CAPACITY_HISTORY_FILE: &str = "capacity_history.json";
+
+const HISTORY_DAYS: usize = 90;
+const TREND_DAYS: usize = 7;
+// Hit rates closer together than this count as steady.
+const TREND_TOLERANCE: f64 = 0.02;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CapacitySample {
+    /// UTC day, YYYY-MM-DD.
+    pub day: String,
+    pub disk_bytes: u64,
+    pub bytes_written: u64,
+    /// Cache hit rate since the database was opened, when it served reads.
+    pub cache_hit_rate: Option<f64>,
+}
+
+pub fn read_history<P: AsRef<Path>>(root: P) -> VeloResult<Vec<CapacitySample>> {
+    let root = root.as_ref();
+    for path in [
+        root.join(META_DIR).join(CAPACITY_HISTORY_FILE),
+        root.join(CAPACITY_HISTORY_FILE),
+    ] {
+        let Ok(content) = std::fs::read_to_string(&path) else {
+            continue;
+        };
+        return serde_json::from_str(&content).map_err(|e| {
+            VeloError::CorruptedData(format!("Unreadable capacity history {:?}: {}", path, e))
+        });
+    }
+    Ok(Vec::new())
+}
+
+/// Bytes used by the database at `root`, not counting databases nested in it.
+pub fn database_bytes<P: AsRef<Path>>(root: P) -> u64 {
+    let root = root.as_ref();
+    let files_in = |dir: &Path| -> u64 {
+        std::fs::read_dir(dir)
+            .map(|entries| {
+                entries
+                    .flatten()
+                    .filter_map(|entry| entry.metadata().ok())
+                    .filter(|meta| meta.is_file())
+                    .map(|meta| meta.len())
+                    .sum()
+            })
+            .unwrap_or(0)
+    };
+    files_in(root)
+        + [WAL_DIR, SST_DIR, META_DIR, TMP_DIR]
+            .iter()
+            .map(|dir| files_in(&root.join(dir)))
+            .sum::<u64>()
+}
+
+fn sstable_count(root: &Path) -> usize {
+    [root.to_path_buf(), root.join(SST_DIR)]
+        .iter()
+        .filter_map(|dir| std::fs::read_dir(dir).ok())
+        .flat_map(|entries| entries.flatten())
+        .filter(|entry| {
+            entry
+                .file_name()
+                .to_str()
+                .is_some_and(|name| name.starts_with("sstable_") && name.ends_with(".vdb"))
+        })
+        .count()
+}
+
+impl Velocity {
+    /// Adds today's sample to the capacity history, replacing an earlier
+    /// sample of the same day.
+    pub fn record_capacity_sample(&self) -> VeloResult<()> {
+        if self.is_read_only() {
+            return Ok(());
+        }
+        let stats = self.stats();
+        let reads = stats.cache_hits + stats.cache_misses;
+        let sample = CapacitySample {
+            day: chrono::Utc::now().format("%Y-%m-%d").to_string(),
+            disk_bytes: database_bytes(self.layout.root()),
+            bytes_written: self.lifetime_stats().bytes_written,
+            cache_hit_rate: (reads > 0).then(|| stats.cache_hits as f64 / reads as f64),
+        };
+
+        let mut history = read_history(self.layout.root()).unwrap_or_default();
+        history.retain(|existing| existing.day != sample.day);
+        history.push(sample);
+        history.sort_by(|a, b| a.day.cmp(&b.day));
+        let excess = history.len().saturating_sub(HISTORY_DAYS);
+        history.drain(..excess);
+
+        let content = serde_json::to_string_pretty(&history).map_err(|e| {
+            VeloError::InvalidOperation(format!("Failed to encode capacity history: {}", e))
+        })?;
+        fsutil::atomic_write(self.layout.meta_dir().join(CAPACITY_HISTORY_FILE), content)?;
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Trend {
+    Rising,
+    Falling,
+    Steady,
+    Unknown,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct DatabaseCapacity {
+    pub path: PathBuf,
+    pub disk_bytes: u64,
+    /// Average growth since the oldest sample; `None` until that sample is a
+    /// day old.
+    pub growth_bytes_per_day: Option<f64>,
+    /// Average bytes written per day since the oldest sample, including
+    /// data later overwritten or compacted away.
+    pub write_bytes_per_day: Option<f64>,
+    /// Days since the oldest sample.
+    pub history_days: u64,
+    pub sstables: usize,
+    /// SSTables beyond the compaction threshold, waiting to be merged.
+    pub compaction_debt: usize,
+    pub cache_hit_rate: Option<f64>,
+    pub cache_hit_trend: Trend,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct CapacityReport {
+    pub data_dir: PathBuf,
+    pub disk_bytes: u64,
+    pub disk_available_bytes: Option<u64>,
+    pub disk_total_bytes: Option<u64>,
+    pub growth_bytes_per_day: Option<f64>,
+    /// Days until the filesystem is full at the current growth rate.
+    pub days_until_full: Option<f64>,
+    pub cache_size: usize,
+    pub recommended_cache_size: usize,
+    pub cache_advice: String,
+    pub databases: Vec<DatabaseCapacity>,
+}
+
+impl CapacityReport {
+    /// Builds the report from the files under `data_dir` without opening any
+    /// database, so it can run next to a live server.
+    pub fn build<P: AsRef<Path>>(
+        data_dir: P,
+        config: &VelocityConfig,
+        addon: Option<&DatabaseAddonConfig>,
+    ) -> VeloResult<Self> {
+        let data_dir = data_dir.as_ref();
+        let databases = database_dirs(data_dir, addon)
+            .into_iter()
+            .map(|dir| database_capacity(dir, config))
+            .collect::<VeloResult<Vec<_>>>()?;
+
+        let disk_bytes = databases.iter().map(|db| db.disk_bytes).sum();
+        let growth: Vec<f64> = databases
+            .iter()
+            .filter_map(|db| db.growth_bytes_per_day)
+            .collect();
+        let growth_bytes_per_day = (!growth.is_empty()).then(|| growth.iter().sum::<f64>());
+        let disk_available_bytes = fs2::available_space(data_dir).ok();
+        let days_until_full = match (disk_available_bytes, growth_bytes_per_day) {
+            (Some(available), Some(growth)) if growth > 0.0 => Some(available as f64 / growth),
+            _ => None,
+        };
+        let (recommended_cache_size, cache_advice) = recommend_cache(&databases, config);
+
+        Ok(Self {
+            data_dir: data_dir.to_path_buf(),
+            disk_bytes,
+            disk_available_bytes,
+            disk_total_bytes: fs2::total_space(data_dir).ok(),
+            growth_bytes_per_day,
+            days_until_full,
+            cache_size: config.cache_size,
+            recommended_cache_size,
+            cache_advice,
+            databases,
+        })
+    }
+}
+
+fn database_capacity(dir: PathBuf, config: &VelocityConfig) -> VeloResult<DatabaseCapacity> {
+    let history = read_history(&dir)?;
+    let disk_bytes = database_bytes(&dir);
+    let sstables = sstable_count(&dir);
+
+    let (history_days, first_bytes) = history
+        .first()
+        .and_then(|first| {
+            let first_day = chrono::NaiveDate::parse_from_str(&first.day, "%Y-%m-%d").ok()?;
+            let days = (chrono::Utc::now().date_naive() - first_day).num_days();
+            Some((days.max(0) as u64, first.disk_bytes))
+        })
+        .unwrap_or((0, disk_bytes));
+    let growth_bytes_per_day = (history_days > 0)
+        .then(|| (disk_bytes as f64 - first_bytes as f64) / history_days as f64);
+    let write_bytes_per_day = match (history.first(), history.last()) {
+        (Some(first), Some(last)) if history_days > 0 => {
+            Some(last.bytes_written.saturating_sub(first.bytes_written) as f64 / history_days as f64)
+        }
+        _ => None,
+    };
+
+    let rates: Vec<f64> = history
+        .iter()
+        .rev()
+        .take(TREND_DAYS)
+        .filter_map(|sample| sample.cache_hit_rate)
+        .collect();
+    let cache_hit_trend = match (rates.first(), rates.last()) {
+        (Some(latest), Some(oldest)) if rates.len() > 1 => {
+            if latest - oldest > TREND_TOLERANCE {
+                Trend::Rising
+            } else if oldest - latest > TREND_TOLERANCE {
+                Trend::Falling
+            } else {
+                Trend::Steady
+            }
+        }
+        _ => Trend::Unknown,
+    };
+
+    Ok(DatabaseCapacity {
+        path: dir,
+        disk_bytes,
+        growth_bytes_per_day,
+        write_bytes_per_day,
+        history_days,
+        sstables,
+        compaction_debt: sstables.saturating_sub(config.compaction_threshold),
+        cache_hit_rate: rates.first().copied(),
+        cache_hit_trend,
+    })
+}
+
+fn recommend_cache(databases: &[DatabaseCapacity], config: &VelocityConfig) -> (usize, String) {
+    let target = config.target_cache_hit_rate;
+    let rates: Vec<f64> = databases.iter().filter_map(|db| db.cache_hit_rate).collect();
+    let Some(lowest) = rates.iter().copied().reduce(f64::min) else {
+        return (
+            config.cache_size,
+            "No cache samples yet; keep the current size".to_string(),
+        );
+    };
+    let falling = databases
+        .iter()
+        .any(|db| db.cache_hit_trend == Trend::Falling);
+
+    if lowest < target {
+        (
+            config.cache_size.saturating_mul(2),
+            format!(
+                "Lowest hit rate {:.1}% is below the {:.1}% target; double the cache",
+                lowest * 100.0,
+                target * 100.0
+            ),
+        )
+    } else if falling {
+        (
+            config.cache_size + config.cache_size / 2,
+            format!(
+                "Hit rates meet the {:.1}% target but are falling; grow the cache by half",
+                target * 100.0
+            ),
+        )
+    } else {
+        (
+            config.cache_size,
+            format!("Hit rates meet the {:.1}% target", target * 100.0),
+        )
+    }
+}
@@ -1,26 +1,87 @@
 use std::collections::HashMap;
 use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
 use std::time::Duration;
 
-use bytes::{Buf, BufMut, BytesMut};
+use bytes::{Buf, BytesMut};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
 use serde_json;
-use sha2::{Digest, Sha256};
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
 use tokio::net::TcpStream;
-use tokio::time::timeout;
+use tokio::sync::mpsc;
+use tokio_rustls::TlsConnector;
+
+use crate::dry_run::DryRunReport;
+use crate::identity::{to_hex, IdentityProof};
+use crate::idempotency::with_idempotency_key;
+use crate::request_id::with_request_id;
+use crate::scheduler::CommandPriority;
+use crate::serializer::{self, JsonSerializer, ValueSerializer};
+use crate::server::{
+    encode_batch_ops, load_certs, load_private_key, load_root_store, BatchWriteResult,
+    MessageType, ServerClock, VelocityMessage, VelocityServer,
+};
+use crate::sql::{is_write_statement, QueryResult, SqlValue};
+use crate::usage::DailyUsage;
+use crate::{BatchOp, VeloError, VeloResult};
+
+
+pub trait ClientTransport: AsyncRead + AsyncWrite + Unpin + Send {}
+
+impl<T: AsyncRead + AsyncWrite + Unpin + Send> ClientTransport for T {}
+
+
+#[derive(Debug, Clone)]
+pub struct TlsOptions {
+    pub server_name: String,
+    pub ca_cert_path: String,
+    pub client_cert_path: Option<String>,
+    pub client_key_path: Option<String>,
+}
+
+/// Hooks run around every command a client sends: queries, batch writes,
+/// stats and admin commands. The handshake, authentication and pings are
+/// not intercepted.
+pub trait ClientInterceptor: Send + Sync {
+    /// Called before each attempt is written; may rewrite the message, for
+    /// example to tag it with `request_id::with_request_id`.
+    fn before_send(&self, _message: &mut VelocityMessage) {}
+
+    /// Called with the outcome of each attempt and how long it took.
+    fn after_receive(
+        &self,
+        _request: &VelocityMessage,
+        _response: &VeloResult<VelocityMessage>,
+        _elapsed: Duration,
+    ) {
+    }
 
-use crate::server::{MessageType, VelocityMessage};
-use crate::sql::{QueryResult, SqlValue};
-use crate::{VeloError, VeloResult};
+    /// Called when the server answers `ServerBusy`, which it does before
+    /// running the command. Returning a delay sends the command again after
+    /// it; `attempt` counts from 1.
+    fn retry_after(
+        &self,
+        _request: &VelocityMessage,
+        _error: &VeloError,
+        _attempt: u32,
+    ) -> Option<Duration> {
+        None
+    }
+}
 
 
 pub struct VelocityClient {
-    stream: TcpStream,
+    stream: Box<dyn ClientTransport>,
     server_fingerprint: Option<String>,
+    verified_fingerprints: Vec<String>,
+    database: Option<String>,
     authenticated: bool,
     #[allow(dead_code)]
     cached_fingerprints: HashMap<SocketAddr, String>,
+    serializer: Arc<dyn ValueSerializer>,
+    interceptors: Vec<Arc<dyn ClientInterceptor>>,
 }
 
 impl VelocityClient {
@@ -29,15 +90,71 @@ impl VelocityClient {
         let stream = TcpStream::connect(address).await?;
         let _server_addr = stream.peer_addr()?;
 
+        Self::with_transport(Box::new(stream), None).await
+    }
+
+
+    pub async fn connect_tenant(address: &str, tenant: &str) -> VeloResult<Self> {
+        let stream = TcpStream::connect(address).await?;
+        Self::with_transport(Box::new(stream), Some(tenant)).await
+    }
+
+
+    pub async fn connect_tls(address: &str, options: &TlsOptions) -> VeloResult<Self> {
+        let builder = rustls::ClientConfig::builder()
+            .with_safe_defaults()
+            .with_root_certificates(load_root_store(&options.ca_cert_path)?);
+        let tls_config = match (&options.client_cert_path, &options.client_key_path) {
+            (Some(cert_path), Some(key_path)) => builder
+                .with_client_auth_cert(load_certs(cert_path)?, load_private_key(key_path)?)
+                .map_err(|e| {
+                    VeloError::InvalidOperation(format!("Invalid client certificate: {}", e))
+                })?,
+            _ => builder.with_no_client_auth(),
+        };
+
+        let server_name = rustls::ServerName::try_from(options.server_name.as_str()).map_err(|_| {
+            VeloError::InvalidOperation(format!("Invalid server name: {}", options.server_name))
+        })?;
+        let stream = TcpStream::connect(address).await?;
+        let stream = TlsConnector::from(Arc::new(tls_config))
+            .connect(server_name, stream)
+            .await?;
+
+        Self::with_transport(Box::new(stream), None).await
+    }
+
+
+    /// Connects over the server's `unix_socket_path`. Access is controlled by
+    /// the socket file's permissions rather than `[server.access]` rules.
+    #[cfg(unix)]
+    pub async fn connect_unix<P: AsRef<std::path::Path>>(path: P) -> VeloResult<Self> {
+        let stream = tokio::net::UnixStream::connect(path).await?;
+        Self::with_transport(Box::new(stream), None).await
+    }
+
+
+    pub async fn connect_in_memory(server: &VelocityServer) -> VeloResult<Self> {
+        Self::with_transport(Box::new(server.connect_in_memory()), None).await
+    }
+
+    async fn with_transport(
+        stream: Box<dyn ClientTransport>,
+        tenant: Option<&str>,
+    ) -> VeloResult<Self> {
         let mut client = Self {
             stream,
             server_fingerprint: None,
+            verified_fingerprints: Vec::new(),
+            database: None,
             authenticated: false,
             cached_fingerprints: HashMap::new(),
+            serializer: Arc::new(JsonSerializer),
+            interceptors: Vec::new(),
         };
 
 
-        client.handshake().await?;
+        client.handshake(tenant).await?;
 
         Ok(client)
     }
@@ -49,12 +166,14 @@ impl VelocityClient {
     ) -> VeloResult<Self> {
         let client = Self::connect(address).await?;
 
-        if let Some(ref fingerprint) = client.server_fingerprint {
-            if fingerprint != expected_fingerprint {
-                return Err(VeloError::InvalidOperation(
-                    "Server fingerprint mismatch - possible MITM attack".to_string(),
-                ));
-            }
+        if !client
+            .verified_fingerprints
+            .iter()
+            .any(|fingerprint| fingerprint == expected_fingerprint)
+        {
+            return Err(VeloError::InvalidOperation(
+                "Server fingerprint mismatch - possible MITM attack".to_string(),
+            ));
         }
 
         Ok(client)
@@ -62,7 +181,21 @@ impl VelocityClient {
 
 
     pub async fn authenticate(&mut self, username: &str, password: &str) -> VeloResult<()> {
-        let auth_payload = format!("{}\0{}", username, password);
+        self.send_auth(format!("{}\0{}", username, password)).await
+    }
+
+
+    pub async fn change_password(
+        &mut self,
+        username: &str,
+        old_password: &str,
+        new_password: &str,
+    ) -> VeloResult<()> {
+        self.send_auth(format!("{}\0{}\0{}", username, old_password, new_password))
+            .await
+    }
+
+    async fn send_auth(&mut self, auth_payload: String) -> VeloResult<()> {
         let message = VelocityMessage::new(MessageType::AuthRequest, auth_payload.into_bytes());
 
         self.send_message(&message).await?;
@@ -96,14 +229,62 @@ impl VelocityClient {
 
 
     pub async fn query(&mut self, sql: &str) -> VeloResult<QueryResult> {
+        self.query_with_priority(sql, CommandPriority::Interactive)
+            .await
+    }
+
+
+    pub async fn query_with_priority(
+        &mut self,
+        sql: &str,
+        priority: CommandPriority,
+    ) -> VeloResult<QueryResult> {
         if !self.authenticated {
             return Err(VeloError::InvalidOperation("Not authenticated".to_string()));
         }
 
-        let message = VelocityMessage::new(MessageType::Command, sql.as_bytes().to_vec());
-        self.send_message(&message).await?;
+        let msg_type = match priority {
+            CommandPriority::Interactive => MessageType::Command,
+            CommandPriority::Batch => MessageType::BatchCommand,
+        };
+        self.send_query(msg_type, sql.as_bytes().to_vec()).await
+    }
 
-        let response = self.receive_message().await?;
+    /// Runs a write with an idempotency token: if the server already applied
+    /// a command with the same token (within its dedup window), the original
+    /// result is returned instead of applying the write again.
+    pub async fn query_idempotent(&mut self, sql: &str, token: &str) -> VeloResult<QueryResult> {
+        if !self.authenticated {
+            return Err(VeloError::InvalidOperation("Not authenticated".to_string()));
+        }
+
+        self.send_query(MessageType::Command, with_idempotency_key(token, sql.as_bytes()))
+            .await
+    }
+
+    /// Runs `sql` under a request ID chosen by the caller, so the command
+    /// can be found in the server's logs and audit entries. Errors from the
+    /// server end with `(request <id>)`.
+    pub async fn query_with_request_id(
+        &mut self,
+        sql: &str,
+        request_id: &str,
+    ) -> VeloResult<QueryResult> {
+        if !self.authenticated {
+            return Err(VeloError::InvalidOperation("Not authenticated".to_string()));
+        }
+
+        self.send_query(MessageType::Command, with_request_id(request_id, sql.as_bytes()))
+            .await
+    }
+
+    async fn send_query(
+        &mut self,
+        msg_type: MessageType,
+        payload: Vec<u8>,
+    ) -> VeloResult<QueryResult> {
+        let message = VelocityMessage::new(msg_type, payload);
+        let response = self.exchange(message).await?;
         match response.msg_type {
             MessageType::Response => {
                 let result: QueryResult =
@@ -116,6 +297,9 @@ impl VelocityClient {
                 let error_text = String::from_utf8_lossy(&response.payload);
                 Err(VeloError::InvalidOperation(error_text.to_string()))
             }
+            MessageType::ServerBusy => Err(VeloError::ServerBusy(
+                String::from_utf8_lossy(&response.payload).to_string(),
+            )),
             _ => Err(VeloError::InvalidOperation(
                 "Unexpected response to query".to_string(),
             )),
@@ -124,16 +308,18 @@ impl VelocityClient {
 
 
     pub async fn select(&mut self, key: &str) -> VeloResult<Option<String>> {
-        let sql = format!("SELECT value FROM kv WHERE key = '{}'", key);
+        let sql = format!(
+            "SELECT key, value FROM kv WHERE key = '{}'",
+            key.replace('\'', "''")
+        );
         let result = self.query(&sql).await?;
 
         if result.data.is_empty() {
             Ok(None)
         } else {
             match &result.data[0].values[1] {
-                SqlValue::String(s) => Ok(Some(s.clone())),
-                SqlValue::Binary(b) => Ok(Some(String::from_utf8_lossy(b).to_string())),
-                _ => Ok(None),
+                SqlValue::Null => Ok(None),
+                value => Ok(Some(String::from_utf8_lossy(&value.to_bytes()).to_string())),
             }
         }
     }
@@ -142,7 +328,8 @@ impl VelocityClient {
     pub async fn insert(&mut self, key: &str, value: &str) -> VeloResult<()> {
         let sql = format!(
             "INSERT INTO kv (key, value) VALUES ('{}', '{}')",
-            key, value
+            key.replace('\'', "''"),
+            value.replace('\'', "''")
         );
         let result = self.query(&sql).await?;
 
@@ -154,8 +341,110 @@ impl VelocityClient {
     }
 
 
+    /// Writes a key that the server deletes when this connection closes,
+    /// for locks, presence markers and scratch data. Once another write
+    /// changes its value, the key is kept.
+    pub async fn insert_temporary(&mut self, key: &str, value: &str) -> VeloResult<()> {
+        let sql = format!(
+            "PUT TEMPORARY '{}' '{}'",
+            key.replace('\'', "''"),
+            value.replace('\'', "''")
+        );
+        self.query(&sql).await.map(|_| ())
+    }
+
+
+    /// Writes `value` encoded with the client's serializer (JSON unless
+    /// changed with `set_serializer`). The bytes go through a batch write,
+    /// so binary encodings are stored as they are.
+    pub async fn put_typed<T: Serialize + ?Sized>(
+        &mut self,
+        key: &str,
+        value: &T,
+    ) -> VeloResult<()> {
+        let value = serializer::to_bytes(self.serializer.as_ref(), value)?;
+        let result = self
+            .write_batch(&[BatchOp::Put {
+                key: key.to_string(),
+                value,
+            }])
+            .await?;
+        match result.results.into_iter().next() {
+            Some(op) if result.applied => match op.error {
+                Some(error) => Err(VeloError::InvalidOperation(error)),
+                None => Ok(()),
+            },
+            op => Err(VeloError::InvalidOperation(
+                op.and_then(|op| op.error)
+                    .unwrap_or_else(|| "Write was not applied".to_string()),
+            )),
+        }
+    }
+
+    /// Reads a value written by `put_typed` with the same serializer. A
+    /// missing or deleted key is `None`.
+    pub async fn get_typed<T: DeserializeOwned>(&mut self, key: &str) -> VeloResult<Option<T>> {
+        if !self.authenticated {
+            return Err(VeloError::InvalidOperation("Not authenticated".to_string()));
+        }
+
+        let message = VelocityMessage::new(MessageType::Get, key.as_bytes().to_vec());
+        let response = self.exchange(message).await?;
+        let bytes = match response.msg_type {
+            MessageType::Response => match response.payload.split_first() {
+                Some((0x01, bytes)) => bytes.to_vec(),
+                Some((0x00, _)) => return Ok(None),
+                _ => {
+                    return Err(VeloError::CorruptedData(
+                        "Malformed get response".to_string(),
+                    ))
+                }
+            },
+            MessageType::Error => {
+                return Err(VeloError::InvalidOperation(
+                    String::from_utf8_lossy(&response.payload).to_string(),
+                ))
+            }
+            MessageType::ServerBusy => {
+                return Err(VeloError::ServerBusy(
+                    String::from_utf8_lossy(&response.payload).to_string(),
+                ))
+            }
+            _ => {
+                return Err(VeloError::InvalidOperation(
+                    "Unexpected response to get".to_string(),
+                ))
+            }
+        };
+        serializer::from_bytes(self.serializer.as_ref(), &bytes).map(Some)
+    }
+
+    /// The serializer used by `put_typed` and `get_typed`.
+    pub fn set_serializer(&mut self, serializer: Arc<dyn ValueSerializer>) {
+        self.serializer = serializer;
+    }
+
+    pub fn serializer(&self) -> &dyn ValueSerializer {
+        self.serializer.as_ref()
+    }
+
+    /// Adds an interceptor after the ones already added. `before_send` runs
+    /// in the order interceptors were added, `after_receive` in reverse.
+    pub fn add_interceptor(&mut self, interceptor: Arc<dyn ClientInterceptor>) {
+        self.interceptors.push(interceptor);
+    }
+
+    pub fn clear_interceptors(&mut self) {
+        self.interceptors.clear();
+    }
+
+
     pub async fn update(&mut self, key: &str, value: &str) -> VeloResult<bool> {
-        let sql = format!("UPDATE kv SET value = '{}' WHERE key = '{}'", value, key);
+        let sql = format!(
+            "UPDATE kv SET value = '{}' WHERE key = '{}'",
+            value.replace('\'', "''"),
+            key.replace('\'', "''")
+        );
         let result = self.query(&sql).await?;
 
         Ok(result.rows_affected > 0)
@@ -163,18 +452,59 @@ impl VelocityClient {
 
 
     pub async fn delete(&mut self, key: &str) -> VeloResult<bool> {
-        let sql = format!("DELETE FROM kv WHERE key = '{}'", key);
+        let sql = format!("DELETE FROM kv WHERE key = '{}'", key.replace('\'', "''"));
         let result = self.query(&sql).await?;
 
         Ok(result.rows_affected > 0)
     }
 
 
+    pub async fn write_batch(&mut self, ops: &[BatchOp]) -> VeloResult<BatchWriteResult> {
+        if !self.authenticated {
+            return Err(VeloError::InvalidOperation("Not authenticated".to_string()));
+        }
+
+        self.send_batch(encode_batch_ops(ops)).await
+    }
+
+    /// Applies a batch with an idempotency token: a retry with the same token
+    /// returns the original result instead of applying the batch again.
+    pub async fn write_batch_idempotent(
+        &mut self,
+        ops: &[BatchOp],
+        token: &str,
+    ) -> VeloResult<BatchWriteResult> {
+        if !self.authenticated {
+            return Err(VeloError::InvalidOperation("Not authenticated".to_string()));
+        }
+
+        self.send_batch(with_idempotency_key(token, &encode_batch_ops(ops)))
+            .await
+    }
+
+    async fn send_batch(&mut self, payload: Vec<u8>) -> VeloResult<BatchWriteResult> {
+        let message = VelocityMessage::new(MessageType::BatchWrite, payload);
+        let response = self.exchange(message).await?;
+        match response.msg_type {
+            MessageType::Response => serde_json::from_slice(&response.payload).map_err(|e| {
+                VeloError::CorruptedData(format!("Failed to parse batch result: {}", e))
+            }),
+            MessageType::Error => Err(VeloError::InvalidOperation(
+                String::from_utf8_lossy(&response.payload).to_string(),
+            )),
+            MessageType::ServerBusy => Err(VeloError::ServerBusy(
+                String::from_utf8_lossy(&response.payload).to_string(),
+            )),
+            _ => Err(VeloError::InvalidOperation(
+                "Unexpected response to batch write".to_string(),
+            )),
+        }
+    }
+
+
     pub async fn stats(&mut self) -> VeloResult<serde_json::Value> {
         let message = VelocityMessage::new(MessageType::Stats, Vec::new());
-        self.send_message(&message).await?;
-
-        let response = self.receive_message().await?;
+        let response = self.exchange(message).await?;
         match response.msg_type {
             MessageType::Response => {
                 let stats: serde_json::Value =
@@ -194,37 +524,204 @@ impl VelocityClient {
     }
 
 
+    /// Asks the server to close its listeners, finish the commands in
+    /// flight, flush every database and exit, then waits up to `timeout`
+    /// for it to close this connection. Needs an administrator account.
+    pub async fn drain_server(&mut self, timeout: Duration) -> VeloResult<()> {
+        self.replica_command("DRAIN SERVER").await?;
+        let closed = async { while self.receive_message().await.is_ok() {} };
+        tokio::time::timeout(timeout, closed).await.map_err(|_| {
+            VeloError::InvalidOperation(format!(
+                "Server was still running {}s after the drain request",
+                timeout.as_secs()
+            ))
+        })
+    }
+
+    /// The cached statements and cache statistics of each database, as
+    /// returned by `SHOW PLAN CACHE`. Needs an administrator account.
+    pub async fn plan_cache(&mut self) -> VeloResult<serde_json::Value> {
+        let payload = self.replica_command("SHOW PLAN CACHE").await?;
+        serde_json::from_slice(&payload).map_err(|e| {
+            VeloError::InvalidOperation(format!("Invalid plan cache response: {}", e))
+        })
+    }
+
+    /// Empties the plan cache of `database`, or of every database.
+    pub async fn clear_plan_cache(&mut self, database: Option<&str>) -> VeloResult<()> {
+        let command = match database {
+            Some(database) => format!("CLEAR PLAN CACHE {}", database),
+            None => "CLEAR PLAN CACHE".to_string(),
+        };
+        self.replica_command(&command).await.map(|_| ())
+    }
+
+    /// Per-user usage totals of every day, or of `day` (YYYY-MM-DD), as
+    /// returned by `SHOW USAGE`. Needs an administrator account.
+    pub async fn usage(&mut self, day: Option<&str>) -> VeloResult<Vec<DailyUsage>> {
+        let command = match day {
+            Some(day) => format!("SHOW USAGE {}", day),
+            None => "SHOW USAGE".to_string(),
+        };
+        let payload = self.replica_command(&command).await?;
+        serde_json::from_slice(&payload)
+            .map_err(|e| VeloError::InvalidOperation(format!("Invalid usage response: {}", e)))
+    }
+
+
+    /// Flushes and compactions still running, and the progress events with
+    /// an id above `after`, as returned by `SHOW MAINTENANCE`. Needs a user
+    /// account.
+    pub async fn maintenance(&mut self, after: u64) -> VeloResult<serde_json::Value> {
+        let payload = self
+            .replica_command(&format!("SHOW MAINTENANCE AFTER {}", after))
+            .await?;
+        serde_json::from_slice(&payload).map_err(|e| {
+            VeloError::InvalidOperation(format!("Invalid maintenance response: {}", e))
+        })
+    }
+
+
+    /// Reports what `command` would remove without running it. Supports
+    /// DROP DATABASE, PURGE TRASH and DELETE; the last two run against the
+    /// current database.
+    pub async fn dry_run(&mut self, command: &str) -> VeloResult<DryRunReport> {
+        let payload = self
+            .replica_command(&format!("DRY RUN {}", command))
+            .await?;
+        serde_json::from_slice(&payload).map_err(|e| {
+            VeloError::InvalidOperation(format!("Invalid dry run response: {}", e))
+        })
+    }
+
+    pub async fn drop_database(&mut self, name: &str) -> VeloResult<()> {
+        self.replica_command(&format!("DROP DATABASE {}", name))
+            .await
+            .map(|_| ())
+    }
+
+    /// Removes every trashed key of the current database for good and
+    /// returns the server's summary.
+    pub async fn purge_trash(&mut self) -> VeloResult<String> {
+        let payload = self.replica_command("PURGE TRASH").await?;
+        Ok(String::from_utf8_lossy(&payload).to_string())
+    }
+
+    /// Sets the connection's isolation level. Only `read_committed` is
+    /// accepted; see the README for what it guarantees.
+    pub async fn set_isolation_level(&mut self, level: &str) -> VeloResult<()> {
+        self.replica_command(&format!("SET TRANSACTION ISOLATION LEVEL {}", level))
+            .await
+            .map(|_| ())
+    }
+
+    pub async fn isolation_level(&mut self) -> VeloResult<String> {
+        let payload = self.replica_command("SHOW TRANSACTION ISOLATION LEVEL").await?;
+        Ok(String::from_utf8_lossy(&payload).to_string())
+    }
+
+    /// Switches the connection to `database`.
+    pub async fn use_database(&mut self, database: &str) -> VeloResult<()> {
+        self.replica_command(&format!("USE {}", database)).await?;
+        self.database = Some(database.to_string());
+        Ok(())
+    }
+
+
+    pub(crate) async fn replica_command(&mut self, command: &str) -> VeloResult<Vec<u8>> {
+        if !self.authenticated {
+            return Err(VeloError::InvalidOperation("Not authenticated".to_string()));
+        }
+
+        let message = VelocityMessage::new(MessageType::Command, command.as_bytes().to_vec());
+        let response = self.exchange(message).await?;
+        match response.msg_type {
+            MessageType::Response => Ok(response.payload),
+            MessageType::Error => Err(VeloError::InvalidOperation(
+                String::from_utf8_lossy(&response.payload).to_string(),
+            )),
+            MessageType::ServerBusy => Err(VeloError::ServerBusy(
+                String::from_utf8_lossy(&response.payload).to_string(),
+            )),
+            _ => Err(VeloError::InvalidOperation(
+                "Unexpected response to replica command".to_string(),
+            )),
+        }
+    }
+
+
     pub async fn ping(&mut self) -> VeloResult<Duration> {
+        self.ping_sample().await.map(|sample| sample.rtt)
+    }
+
+    /// Pings the server once and returns the round trip together with the
+    /// server's clocks, when the server reports them.
+    pub async fn ping_sample(&mut self) -> VeloResult<PingSample> {
+        let sent_at_us = chrono::Utc::now().timestamp_micros();
         let start = std::time::Instant::now();
 
         let message = VelocityMessage::new(MessageType::Ping, Vec::new());
         self.send_message(&message).await?;
 
         let response = self.receive_message().await?;
-        let duration = start.elapsed();
+        let rtt = start.elapsed();
 
         match response.msg_type {
-            MessageType::Pong => Ok(duration),
+            MessageType::Pong => Ok(PingSample {
+                sent_at_us,
+                rtt,
+                server: serde_json::from_slice(&response.payload).ok(),
+            }),
             _ => Err(VeloError::InvalidOperation(
                 "Unexpected response to ping".to_string(),
             )),
         }
     }
 
+    /// Pings the server `samples` times, one after another, and summarizes
+    /// the round trips, the server's share of them and the clock skew.
+    pub async fn latency_report(&mut self, samples: usize) -> VeloResult<LatencyReport> {
+        if samples == 0 {
+            return Err(VeloError::InvalidOperation(
+                "A latency report needs at least one sample".to_string(),
+            ));
+        }
+        let mut pings = Vec::with_capacity(samples);
+        for _ in 0..samples {
+            pings.push(self.ping_sample().await?);
+        }
+        Ok(LatencyReport::from_samples(&pings))
+    }
+
 
     pub fn server_fingerprint(&self) -> Option<&String> {
         self.server_fingerprint.as_ref()
     }
 
 
+    pub fn verified_fingerprints(&self) -> &[String] {
+        &self.verified_fingerprints
+    }
+
+
+    pub fn database(&self) -> Option<&str> {
+        self.database.as_deref()
+    }
+
+
     pub fn is_authenticated(&self) -> bool {
         self.authenticated
     }
 
 
-    async fn handshake(&mut self) -> VeloResult<()> {
+    async fn handshake(&mut self, tenant: Option<&str>) -> VeloResult<()> {
 
-        let hello = VelocityMessage::new(MessageType::Hello, Vec::new());
+        let nonce: [u8; 32] = rand::random();
+        let mut hello_payload = format!("Nonce: {}", to_hex(&nonce));
+        if let Some(tenant) = tenant {
+            hello_payload.push_str(&format!("\nTenant: {}", tenant));
+        }
+        let hello = VelocityMessage::new(MessageType::Hello, hello_payload.into_bytes());
         self.send_message(&hello).await?;
 
 
@@ -232,26 +729,90 @@ impl VelocityClient {
         match response.msg_type {
             MessageType::ServerInfo => {
                 let server_info = String::from_utf8_lossy(&response.payload);
-
-
-                for line in server_info.lines() {
-                    if line.starts_with("Fingerprint: ") {
-                        self.server_fingerprint = Some(line[13..].to_string());
-                        break;
+                let fields: HashMap<&str, &str> = server_info
+                    .lines()
+                    .filter_map(|line| line.split_once(": "))
+                    .collect();
+
+                self.server_fingerprint = fields.get("Fingerprint").map(|f| f.to_string());
+                self.authenticated = fields.contains_key("Authenticated");
+                self.database = fields.get("Database").map(|db| db.to_string());
+
+
+                for prefix in ["", "Previous"] {
+                    let field = |name: &str| fields.get(format!("{}{}", prefix, name).as_str());
+                    if let (Some(fingerprint), Some(public_key), Some(signature)) =
+                        (field("Fingerprint"), field("PublicKey"), field("Signature"))
+                    {
+                        let proof = IdentityProof {
+                            fingerprint: fingerprint.to_string(),
+                            public_key: public_key.to_string(),
+                            signature: signature.to_string(),
+                        };
+                        if proof.verify(&nonce) {
+                            self.verified_fingerprints.push(proof.fingerprint);
+                        }
                     }
                 }
 
                 Ok(())
             }
+            MessageType::Error => Err(VeloError::InvalidOperation(format!(
+                "Handshake rejected: {}",
+                String::from_utf8_lossy(&response.payload)
+            ))),
             _ => Err(VeloError::InvalidOperation(
                 "Unexpected response to hello".to_string(),
             )),
         }
     }
 
+    // Sends a command and reads its response through the interceptors,
+    // repeating it while one of them asks for a retry of a busy answer.
+    async fn exchange(&mut self, request: VelocityMessage) -> VeloResult<VelocityMessage> {
+        if self.interceptors.is_empty() {
+            self.send_message(&request).await?;
+            return self.receive_message().await;
+        }
+
+        let interceptors = self.interceptors.clone();
+        let mut attempt = 0;
+        loop {
+            let mut message = VelocityMessage::new(request.msg_type, request.payload.clone());
+            for interceptor in &interceptors {
+                interceptor.before_send(&mut message);
+            }
+            let start = std::time::Instant::now();
+            let response = match self.send_message(&message).await {
+                Ok(()) => self.receive_message().await,
+                Err(e) => Err(e),
+            };
+            let elapsed = start.elapsed();
+            for interceptor in interceptors.iter().rev() {
+                interceptor.after_receive(&message, &response, elapsed);
+            }
+
+            let response = response?;
+            if response.msg_type != MessageType::ServerBusy {
+                return Ok(response);
+            }
+            attempt += 1;
+            let error =
+                VeloError::ServerBusy(String::from_utf8_lossy(&response.payload).to_string());
+            let delay = interceptors
+                .iter()
+                .find_map(|interceptor| interceptor.retry_after(&message, &error, attempt));
+            match delay {
+                Some(delay) => tokio::time::sleep(delay).await,
+                None => return Ok(response),
+            }
+        }
+    }
+
     async fn send_message(&mut self, message: &VelocityMessage) -> VeloResult<()> {
         let data = message.encode();
         self.stream.write_all(&data).await?;
+        self.stream.flush().await?;
         Ok(())
     }
 
@@ -267,13 +828,7 @@ impl VelocityClient {
         }
 
 
-        let payload_len = {
-            let mut temp = &buffer[8..12];
-            temp.get_u32_le() as usize
-        };
-
-
-        let total_len = 14 + payload_len;
+        let total_len = VelocityMessage::frame_len(&buffer).unwrap_or(14);
         while buffer.len() < total_len {
             let n = self.stream.read_buf(&mut buffer).await?;
             if n == 0 {
@@ -290,6 +845,91 @@ impl VelocityClient {
 }
 
 
+#[derive(Debug, Clone, Copy)]
+pub struct PingSample {
+    /// Client wall clock when the ping was sent, in microseconds since the
+    /// Unix epoch.
+    pub sent_at_us: i64,
+    pub rtt: Duration,
+    pub server: Option<ServerClock>,
+}
+
+impl PingSample {
+    /// Round trip minus the server's processing time.
+    pub fn network_us(&self) -> Option<u64> {
+        let server = self.server?;
+        Some((self.rtt.as_micros() as u64).saturating_sub(server.processing_us))
+    }
+
+    /// Server wall clock minus client wall clock, assuming the ping and the
+    /// pong spent equally long on the network.
+    pub fn clock_offset_us(&self) -> Option<i64> {
+        let server = self.server?;
+        let answered_at =
+            self.sent_at_us + (self.network_us()? / 2) as i64 + server.processing_us as i64;
+        Some(server.wall_clock_us - answered_at)
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct LatencyReport {
+    pub samples: usize,
+    pub rtt_min_us: u64,
+    pub rtt_mean_us: u64,
+    pub rtt_p50_us: u64,
+    pub rtt_p90_us: u64,
+    pub rtt_p99_us: u64,
+    pub rtt_max_us: u64,
+    /// Median time the server spent answering; `None` when the server does
+    /// not report its clocks.
+    pub server_p50_us: Option<u64>,
+    /// Median time spent on the network and in the client.
+    pub network_p50_us: Option<u64>,
+    /// Server wall clock minus client wall clock, taken from the fastest
+    /// round trip; positive when the server is ahead.
+    pub clock_skew_us: Option<i64>,
+    /// The skew is off by at most this much: half of that round trip.
+    pub clock_skew_error_us: Option<u64>,
+}
+
+impl LatencyReport {
+    pub fn from_samples(samples: &[PingSample]) -> Self {
+        let mut rtts: Vec<u64> = samples.iter().map(|s| s.rtt.as_micros() as u64).collect();
+        rtts.sort_unstable();
+        let mut server: Vec<u64> = samples
+            .iter()
+            .filter_map(|s| s.server.map(|clock| clock.processing_us))
+            .collect();
+        server.sort_unstable();
+        let mut network: Vec<u64> = samples.iter().filter_map(PingSample::network_us).collect();
+        network.sort_unstable();
+        let fastest = samples
+            .iter()
+            .filter(|s| s.server.is_some())
+            .min_by_key(|s| s.rtt);
+
+        Self {
+            samples: samples.len(),
+            rtt_min_us: rtts.first().copied().unwrap_or(0),
+            rtt_mean_us: rtts.iter().sum::<u64>() / rtts.len().max(1) as u64,
+            rtt_p50_us: percentile(&rtts, 0.5).unwrap_or(0),
+            rtt_p90_us: percentile(&rtts, 0.9).unwrap_or(0),
+            rtt_p99_us: percentile(&rtts, 0.99).unwrap_or(0),
+            rtt_max_us: rtts.last().copied().unwrap_or(0),
+            server_p50_us: percentile(&server, 0.5),
+            network_p50_us: percentile(&network, 0.5),
+            clock_skew_us: fastest.and_then(PingSample::clock_offset_us),
+            clock_skew_error_us: fastest.and_then(|s| s.network_us()).map(|us| us / 2),
+        }
+    }
+}
+
+fn percentile(sorted: &[u64], q: f64) -> Option<u64> {
+    let last = sorted.len().checked_sub(1)?;
+    Some(sorted[(last as f64 * q).round() as usize])
+}
+
+
 pub struct VelocityPool {
     address: String,
     username: String,
@@ -298,6 +938,8 @@ pub struct VelocityPool {
     max_connections: usize,
     available: Arc<tokio::sync::Mutex<Vec<VelocityClient>>>,
     semaphore: tokio::sync::Semaphore,
+    serializer: Arc<dyn ValueSerializer>,
+    interceptors: Vec<Arc<dyn ClientInterceptor>>,
 }
 
 impl VelocityPool {
@@ -314,9 +956,23 @@ impl VelocityPool {
             max_connections,
             available: tokio::sync::Mutex::new(Vec::new()).into(),
             semaphore: tokio::sync::Semaphore::new(max_connections),
+            serializer: Arc::new(JsonSerializer),
+            interceptors: Vec::new(),
         }
     }
 
+    /// Sets the serializer of every connection handed out by the pool.
+    pub fn with_serializer(mut self, serializer: Arc<dyn ValueSerializer>) -> Self {
+        self.serializer = serializer;
+        self
+    }
+
+    /// Adds an interceptor to every connection handed out by the pool.
+    pub fn with_interceptor(mut self, interceptor: Arc<dyn ClientInterceptor>) -> Self {
+        self.interceptors.push(interceptor);
+        self
+    }
+
     pub async fn get_connection(&self) -> VeloResult<PooledConnection<'_>> {
         let _permit = self.semaphore.acquire().await.unwrap();
 
@@ -340,6 +996,8 @@ impl VelocityPool {
             client.authenticate(&self.username, &self.password).await?;
         }
 
+        client.set_serializer(Arc::clone(&self.serializer));
+        client.interceptors = self.interceptors.clone();
         Ok(PooledConnection {
             client: Some(client),
             pool: self,
@@ -359,6 +1017,18 @@ impl<'a> PooledConnection<'a> {
         self.client.as_mut().unwrap().query(sql).await
     }
 
+    pub async fn query_with_priority(
+        &mut self,
+        sql: &str,
+        priority: CommandPriority,
+    ) -> VeloResult<QueryResult> {
+        self.client
+            .as_mut()
+            .unwrap()
+            .query_with_priority(sql, priority)
+            .await
+    }
+
     pub async fn select(&mut self, key: &str) -> VeloResult<Option<String>> {
         self.client.as_mut().unwrap().select(key).await
     }
@@ -371,9 +1041,49 @@ impl<'a> PooledConnection<'a> {
         self.client.as_mut().unwrap().update(key, value).await
     }
 
+    pub async fn put_typed<T: Serialize + ?Sized>(
+        &mut self,
+        key: &str,
+        value: &T,
+    ) -> VeloResult<()> {
+        self.client.as_mut().unwrap().put_typed(key, value).await
+    }
+
+    pub async fn get_typed<T: DeserializeOwned>(&mut self, key: &str) -> VeloResult<Option<T>> {
+        self.client.as_mut().unwrap().get_typed(key).await
+    }
+
     pub async fn delete(&mut self, key: &str) -> VeloResult<bool> {
         self.client.as_mut().unwrap().delete(key).await
     }
+
+    pub async fn query_idempotent(&mut self, sql: &str, token: &str) -> VeloResult<QueryResult> {
+        self.client.as_mut().unwrap().query_idempotent(sql, token).await
+    }
+
+    pub async fn query_with_request_id(
+        &mut self,
+        sql: &str,
+        request_id: &str,
+    ) -> VeloResult<QueryResult> {
+        self.client
+            .as_mut()
+            .unwrap()
+            .query_with_request_id(sql, request_id)
+            .await
+    }
+
+    pub async fn write_batch(&mut self, ops: &[BatchOp]) -> VeloResult<BatchWriteResult> {
+        self.client.as_mut().unwrap().write_batch(ops).await
+    }
+
+    pub async fn write_batch_idempotent(
+        &mut self,
+        ops: &[BatchOp],
+        token: &str,
+    ) -> VeloResult<BatchWriteResult> {
+        self.client.as_mut().unwrap().write_batch_idempotent(ops, token).await
+    }
 }
 
 impl<'a> Drop for PooledConnection<'a> {
@@ -387,3 +1097,210 @@ impl<'a> Drop for PooledConnection<'a> {
         }
     }
 }
+
+
+#[derive(Debug, Clone)]
+pub struct ShadowConfig {
+    pub compare_reads: bool,
+    pub queue_capacity: usize,
+}
+
+impl Default for ShadowConfig {
+    fn default() -> Self {
+        Self {
+            compare_reads: false,
+            queue_capacity: 1024,
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct ShadowMetrics {
+    pub mirrored_writes: AtomicU64,
+    pub failed_writes: AtomicU64,
+    pub dropped_ops: AtomicU64,
+    pub compared_reads: AtomicU64,
+    pub divergent_reads: AtomicU64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ShadowMetricsSnapshot {
+    pub mirrored_writes: u64,
+    pub failed_writes: u64,
+    pub dropped_ops: u64,
+    pub compared_reads: u64,
+    pub divergent_reads: u64,
+}
+
+impl ShadowMetrics {
+    pub fn snapshot(&self) -> ShadowMetricsSnapshot {
+        ShadowMetricsSnapshot {
+            mirrored_writes: self.mirrored_writes.load(Ordering::Relaxed),
+            failed_writes: self.failed_writes.load(Ordering::Relaxed),
+            dropped_ops: self.dropped_ops.load(Ordering::Relaxed),
+            compared_reads: self.compared_reads.load(Ordering::Relaxed),
+            divergent_reads: self.divergent_reads.load(Ordering::Relaxed),
+        }
+    }
+}
+
+enum ShadowOp {
+    Write(String),
+    UseDatabase(String),
+    CompareRead {
+        key: String,
+        expected: Option<String>,
+    },
+}
+
+
+pub struct ShadowClient {
+    primary: VelocityClient,
+    shadow_tx: mpsc::Sender<ShadowOp>,
+    metrics: Arc<ShadowMetrics>,
+    compare_reads: bool,
+}
+
+impl ShadowClient {
+    pub fn new(primary: VelocityClient, shadow: VelocityClient, config: ShadowConfig) -> Self {
+        let (shadow_tx, shadow_rx) = mpsc::channel(config.queue_capacity.max(1));
+        let metrics = Arc::new(ShadowMetrics::default());
+
+        tokio::spawn(Self::run_shadow(shadow, shadow_rx, metrics.clone()));
+
+        Self {
+            primary,
+            shadow_tx,
+            metrics,
+            compare_reads: config.compare_reads,
+        }
+    }
+
+    pub fn metrics(&self) -> ShadowMetricsSnapshot {
+        self.metrics.snapshot()
+    }
+
+    pub fn primary(&mut self) -> &mut VelocityClient {
+        &mut self.primary
+    }
+
+    pub async fn query(&mut self, sql: &str) -> VeloResult<QueryResult> {
+        let mut words = sql.split_whitespace();
+        if let (Some(word), Some(database), None) = (words.next(), words.next(), words.next()) {
+            if word.eq_ignore_ascii_case("USE") {
+                self.use_database(database.trim_end_matches(';')).await?;
+                return Ok(QueryResult {
+                    success: true,
+                    rows_affected: 0,
+                    data: Vec::new(),
+                    columns: Vec::new(),
+                    execution_time_ms: 0,
+                    next_cursor: None,
+                });
+            }
+        }
+        let result = self.primary.query(sql).await?;
+        if is_write_statement(sql) {
+            self.enqueue(ShadowOp::Write(sql.to_string()));
+        }
+        Ok(result)
+    }
+
+    /// Switches both connections to `database`. The switch is queued behind
+    /// earlier mirrored writes and is never dropped, so later writes land in
+    /// the same database on the shadow.
+    pub async fn use_database(&mut self, database: &str) -> VeloResult<()> {
+        self.primary.use_database(database).await?;
+        if self
+            .shadow_tx
+            .send(ShadowOp::UseDatabase(database.to_string()))
+            .await
+            .is_err()
+        {
+            self.metrics.dropped_ops.fetch_add(1, Ordering::Relaxed);
+        }
+        Ok(())
+    }
+
+    pub async fn select(&mut self, key: &str) -> VeloResult<Option<String>> {
+        let value = self.primary.select(key).await?;
+        if self.compare_reads {
+            self.enqueue(ShadowOp::CompareRead {
+                key: key.to_string(),
+                expected: value.clone(),
+            });
+        }
+        Ok(value)
+    }
+
+    pub async fn insert(&mut self, key: &str, value: &str) -> VeloResult<()> {
+        self.primary.insert(key, value).await?;
+        self.enqueue(ShadowOp::Write(format!(
+            "INSERT INTO kv (key, value) VALUES ('{}', '{}')",
+            key.replace('\'', "''"),
+            value.replace('\'', "''")
+        )));
+        Ok(())
+    }
+
+    pub async fn update(&mut self, key: &str, value: &str) -> VeloResult<bool> {
+        let updated = self.primary.update(key, value).await?;
+        self.enqueue(ShadowOp::Write(format!(
+            "UPDATE kv SET value = '{}' WHERE key = '{}'",
+            value.replace('\'', "''"),
+            key.replace('\'', "''")
+        )));
+        Ok(updated)
+    }
+
+    pub async fn delete(&mut self, key: &str) -> VeloResult<bool> {
+        let deleted = self.primary.delete(key).await?;
+        self.enqueue(ShadowOp::Write(format!(
+            "DELETE FROM kv WHERE key = '{}'",
+            key.replace('\'', "''")
+        )));
+        Ok(deleted)
+    }
+
+    fn enqueue(&self, op: ShadowOp) {
+        if self.shadow_tx.try_send(op).is_err() {
+            self.metrics.dropped_ops.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    async fn run_shadow(
+        mut shadow: VelocityClient,
+        mut rx: mpsc::Receiver<ShadowOp>,
+        metrics: Arc<ShadowMetrics>,
+    ) {
+        while let Some(op) = rx.recv().await {
+            match op {
+                ShadowOp::Write(sql) => match shadow.query(&sql).await {
+                    Ok(_) => {
+                        metrics.mirrored_writes.fetch_add(1, Ordering::Relaxed);
+                    }
+                    Err(e) => {
+                        metrics.failed_writes.fetch_add(1, Ordering::Relaxed);
+                        log::warn!("Shadow write failed: {}", e);
+                    }
+                },
+                ShadowOp::UseDatabase(database) => {
+                    if let Err(e) = shadow.use_database(&database).await {
+                        log::warn!("Shadow failed to switch to database '{}': {}", database, e);
+                    }
+                }
+                ShadowOp::CompareRead { key, expected } => {
+                    metrics.compared_reads.fetch_add(1, Ordering::Relaxed);
+                    let diverged = match shadow.select(&key).await {
+                        Ok(actual) => actual != expected,
+                        Err(_) => true,
+                    };
+                    if diverged {
+                        metrics.divergent_reads.fetch_add(1, Ordering::Relaxed);
+                        log::warn!("Shadow read diverged for key '{}'", key);
+                    }
+                }
+            }
+        }
+    }
+}
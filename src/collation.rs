@@ -0,0 +1,69 @@
+use std::sync::atomic::Ordering;
+
+use crate::{RawValue, VeloKey, VeloResult, Velocity, TRASH_PREFIX};
+
+/// Entries `_nocase:<lowercased key>\0<key>` map a case-folded key to every
+/// key that folds to it.
+pub const NOCASE_PREFIX: &str = "_nocase:";
+
+impl Velocity {
+    /// Keeps the case-insensitive key index up to date on every write. Call
+    /// `rebuild_nocase_index` after turning it on for a database that
+    /// already holds keys, or after turning it off to drop the index.
+    pub fn set_nocase_index(&self, enabled: bool) {
+        self.nocase_index.store(enabled, Ordering::Relaxed);
+    }
+
+    pub fn nocase_index_enabled(&self) -> bool {
+        self.nocase_index.load(Ordering::Relaxed)
+    }
+
+    /// Indexes every existing key, or removes all index entries when the
+    /// index is off. Returns the number of entries written or removed.
+    pub fn rebuild_nocase_index(&self) -> VeloResult<usize> {
+        let mut changed = 0;
+        if self.nocase_index_enabled() {
+            for (key, _) in self.range("", None, usize::MAX)? {
+                if let Some(index_key) = self.nocase_index_key(&key) {
+                    self.put_raw(index_key, RawValue::plain(vec![1]))?;
+                    changed += 1;
+                }
+            }
+        } else {
+            for (index_key, _) in self.scan_prefix_raw(NOCASE_PREFIX) {
+                self.put_raw(index_key, RawValue::tombstone())?;
+                changed += 1;
+            }
+        }
+        Ok(changed)
+    }
+
+    /// Existing keys equal to `key` ignoring case, found through the index.
+    /// Only meaningful while the index is enabled.
+    pub fn keys_nocase(&self, key: &str) -> VeloResult<Vec<VeloKey>> {
+        let folded = key.to_lowercase();
+        let start = format!("{}{}\0", NOCASE_PREFIX, folded);
+        let end = format!("{}{}\u{1}", NOCASE_PREFIX, folded);
+        let mut keys = Vec::new();
+        for (index_key, _) in self.range(&start, Some(&end), usize::MAX)? {
+            let original = &index_key[start.len()..];
+            if self.get_raw(original)?.is_some_and(|raw| !raw.is_tombstone()) {
+                keys.push(original.to_string());
+            }
+        }
+        Ok(keys)
+    }
+
+    // Index entries are left out of every scan except one that starts inside
+    // the index.
+    pub(crate) fn hidden_from_scan(start: &str, key: &str) -> bool {
+        key.starts_with(NOCASE_PREFIX) && !start.starts_with(NOCASE_PREFIX)
+    }
+
+    pub(crate) fn nocase_index_key(&self, key: &str) -> Option<VeloKey> {
+        let indexed = self.nocase_index_enabled()
+            && !key.starts_with(NOCASE_PREFIX)
+            && !key.starts_with(TRASH_PREFIX);
+        indexed.then(|| format!("{}{}\0{}", NOCASE_PREFIX, key.to_lowercase(), key))
+    }
+}
@@ -0,0 +1,1017 @@
+use std::fs::File;
+use std::io::{self, BufReader, Read, Seek, SeekFrom};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicU8, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+
+use crate::compression::{self, Dictionary};
+use crate::policy::CompressionPlan;
+use crate::{
+    decode_len, now_millis, size_tier, temp_sstable_path, RawValue, SSTable, SSTableWriter,
+    VeloError, VeloKey, VeloResult, Velocity,
+};
+
+// Throttling is checked after this many bytes rather than per record.
+const THROTTLE_CHUNK: u64 = 64 * 1024;
+// Merges a picker may start after one flush.
+const MAX_PICKS_PER_COMPACTION: usize = 8;
+// Progress of a running flush or merge is reported at most this often.
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(500);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CompactionState {
+    Running,
+    Completed,
+    Cancelled,
+    Failed,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct CompactionProgress {
+    pub state: CompactionState,
+    pub bytes_done: u64,
+    pub bytes_total: u64,
+    pub percent: f64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Shared between a running full compaction and whoever watches or cancels
+/// it.
+pub struct CompactionControl {
+    cancelled: AtomicBool,
+    state: AtomicU8,
+    bytes_done: AtomicU64,
+    bytes_total: AtomicU64,
+    max_bytes_per_second: u64,
+    error: Mutex<Option<String>>,
+}
+
+impl CompactionControl {
+    /// `max_bytes_per_second` caps how fast input tables are read; 0 means
+    /// no limit.
+    pub fn new(max_bytes_per_second: u64) -> Self {
+        Self {
+            cancelled: AtomicBool::new(false),
+            state: AtomicU8::new(CompactionState::Running as u8),
+            bytes_done: AtomicU64::new(0),
+            bytes_total: AtomicU64::new(0),
+            max_bytes_per_second,
+            error: Mutex::new(None),
+        }
+    }
+
+    /// Asks the compaction to stop at the next record. Nothing is replaced
+    /// until the merged table is complete, so stopping early leaves the
+    /// existing tables as they were.
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Release);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Acquire)
+    }
+
+    pub fn state(&self) -> CompactionState {
+        match self.state.load(Ordering::Acquire) {
+            0 => CompactionState::Running,
+            1 => CompactionState::Completed,
+            2 => CompactionState::Cancelled,
+            _ => CompactionState::Failed,
+        }
+    }
+
+    pub fn progress(&self) -> CompactionProgress {
+        let bytes_done = self.bytes_done.load(Ordering::Relaxed);
+        let bytes_total = self.bytes_total.load(Ordering::Relaxed);
+        let percent = match (self.state(), bytes_total) {
+            (CompactionState::Completed, _) | (_, 0) => 100.0,
+            _ => (bytes_done as f64 / bytes_total as f64 * 100.0).min(100.0),
+        };
+        CompactionProgress {
+            state: self.state(),
+            bytes_done,
+            bytes_total,
+            percent,
+            error: self.error.lock().unwrap().clone(),
+        }
+    }
+
+    fn finish(&self, state: CompactionState) {
+        self.state.store(state as u8, Ordering::Release);
+    }
+
+    fn throttle(&self, started: Instant) {
+        if self.max_bytes_per_second == 0 {
+            return;
+        }
+        let done = self.bytes_done.load(Ordering::Relaxed);
+        let due = Duration::from_secs_f64(done as f64 / self.max_bytes_per_second as f64);
+        if let Some(wait) = due.checked_sub(started.elapsed()) {
+            std::thread::sleep(wait);
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MaintenanceOperation {
+    Flush,
+    /// A merge started after a flush, by the compaction picker.
+    Compaction,
+    FullCompaction,
+}
+
+/// Reported to `Velocity::on_maintenance` listeners when a flush or merge
+/// starts, every half second while it runs, and when it ends.
+#[derive(Debug, Clone, Serialize)]
+pub struct MaintenanceProgress {
+    /// The same for every report of one operation, and unique in the process.
+    pub operation_id: u64,
+    pub operation: MaintenanceOperation,
+    pub state: CompactionState,
+    pub bytes_done: u64,
+    pub bytes_total: u64,
+    /// Input tables not yet read to the end; 0 for a flush.
+    pub tables_remaining: usize,
+    pub elapsed_ms: u64,
+    /// From the rate so far, while running and once some bytes are done.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub eta_ms: Option<u64>,
+}
+
+static NEXT_OPERATION_ID: AtomicU64 = AtomicU64::new(1);
+
+// Reports an operation that is dropped without `finish` as failed, so an
+// early return through `?` still ends it for listeners.
+pub(crate) struct ProgressReporter<'a> {
+    db: &'a Velocity,
+    id: u64,
+    operation: MaintenanceOperation,
+    started: Instant,
+    last_report: Instant,
+    bytes_done: u64,
+    bytes_total: u64,
+    tables_remaining: usize,
+    finished: bool,
+}
+
+impl<'a> ProgressReporter<'a> {
+    pub(crate) fn start(
+        db: &'a Velocity,
+        operation: MaintenanceOperation,
+        bytes_total: u64,
+        tables_remaining: usize,
+    ) -> Self {
+        let now = Instant::now();
+        let reporter = Self {
+            db,
+            id: NEXT_OPERATION_ID.fetch_add(1, Ordering::Relaxed),
+            operation,
+            started: now,
+            last_report: now,
+            bytes_done: 0,
+            bytes_total,
+            tables_remaining,
+            finished: false,
+        };
+        reporter.report(CompactionState::Running);
+        reporter
+    }
+
+    pub(crate) fn update(&mut self, bytes_done: u64, tables_remaining: usize) {
+        self.bytes_done = bytes_done;
+        self.tables_remaining = tables_remaining;
+        if self.last_report.elapsed() >= PROGRESS_INTERVAL {
+            self.last_report = Instant::now();
+            self.report(CompactionState::Running);
+        }
+    }
+
+    pub(crate) fn finish(mut self, state: CompactionState) {
+        if state == CompactionState::Completed {
+            self.bytes_done = self.bytes_total;
+            self.tables_remaining = 0;
+        }
+        self.finished = true;
+        self.report(state);
+    }
+
+    fn report(&self, state: CompactionState) {
+        let elapsed = self.started.elapsed();
+        let eta_ms = (state == CompactionState::Running && self.bytes_done > 0).then(|| {
+            let remaining = self.bytes_total.saturating_sub(self.bytes_done) as f64;
+            (elapsed.as_millis() as f64 * remaining / self.bytes_done as f64) as u64
+        });
+        self.db.notify_maintenance(MaintenanceProgress {
+            operation_id: self.id,
+            operation: self.operation,
+            state,
+            bytes_done: self.bytes_done,
+            bytes_total: self.bytes_total,
+            tables_remaining: self.tables_remaining,
+            elapsed_ms: elapsed.as_millis() as u64,
+            eta_ms,
+        });
+    }
+}
+
+impl Drop for ProgressReporter<'_> {
+    fn drop(&mut self) {
+        if !self.finished {
+            self.report(CompactionState::Failed);
+        }
+    }
+}
+
+/// A table as a `CompactionPicker` sees it.
+#[derive(Debug, Clone)]
+pub struct PickerTable {
+    pub id: u64,
+    /// 0 for tables written by a flush.
+    pub level: u32,
+    /// Unix milliseconds of the oldest and newest write in the table: when
+    /// the first write reached the memtable that was flushed into it, and
+    /// when it was flushed.
+    pub write_times: (u64, u64),
+    pub size_bytes: u64,
+    pub entries: usize,
+    pub tombstones: usize,
+    pub min_key: Option<VeloKey>,
+    pub max_key: Option<VeloKey>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CompactionPick {
+    /// Ids of neighbouring tables, oldest first.
+    pub tables: Vec<u64>,
+    /// Level of the merged table.
+    pub output_level: u32,
+    /// Why the tables were picked, for the compaction log.
+    pub reason: String,
+    /// Deletes the tables instead of merging them, for data that is no
+    /// longer wanted. Only the oldest tables can be dropped.
+    pub expired: bool,
+}
+
+/// Decides which tables to merge or drop next. The engine asks after every
+/// flush and keeps going while picks come back.
+///
+/// `tables` is in age order, oldest first, and a pick has to be a run of
+/// neighbouring tables from it: newer versions of a key shadow older ones
+/// by position, so merging around a table in between would change what
+/// reads return. The merged table takes the place of the run.
+pub trait CompactionPicker: Send + Sync {
+    fn name(&self) -> &str;
+
+    fn pick(&self, tables: &[PickerTable]) -> Option<CompactionPick>;
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CompactionStrategy {
+    /// Tables are only rewritten to reclaim space, or merged by a full
+    /// compaction.
+    Off,
+    #[default]
+    SizeTiered,
+    Leveled,
+    /// `TimeWindowPicker` with its defaults.
+    TimeWindowed,
+}
+
+impl CompactionStrategy {
+    pub fn picker(self) -> Option<Arc<dyn CompactionPicker>> {
+        match self {
+            CompactionStrategy::Off => None,
+            CompactionStrategy::SizeTiered => Some(Arc::new(SizeTieredPicker::default())),
+            CompactionStrategy::Leveled => Some(Arc::new(LeveledPicker::default())),
+            CompactionStrategy::TimeWindowed => Some(Arc::new(TimeWindowPicker::default())),
+        }
+    }
+}
+
+/// Merges runs of similarly sized tables, so each table is rewritten about
+/// once per size tier it passes through. Cheap on writes; a key may be in
+/// as many tables as there are tiers.
+#[derive(Debug, Clone)]
+pub struct SizeTieredPicker {
+    /// Neighbouring tables in one size tier needed for a merge.
+    pub min_tables: usize,
+    pub max_tables: usize,
+}
+
+impl Default for SizeTieredPicker {
+    fn default() -> Self {
+        Self {
+            min_tables: 4,
+            max_tables: 32,
+        }
+    }
+}
+
+impl CompactionPicker for SizeTieredPicker {
+    fn name(&self) -> &str {
+        "size_tiered"
+    }
+
+    // The newest qualifying run is picked, since the tiers fill from the
+    // newest tables.
+    fn pick(&self, tables: &[PickerTable]) -> Option<CompactionPick> {
+        let min_tables = self.min_tables.max(2);
+        let mut end = tables.len();
+        while end > 0 {
+            let tier = size_tier(tables[end - 1].size_bytes);
+            let start = tables[..end]
+                .iter()
+                .rposition(|t| size_tier(t.size_bytes) != tier)
+                .map_or(0, |i| i + 1);
+            if end - start >= min_tables {
+                let start = start.max(end.saturating_sub(self.max_tables.max(min_tables)));
+                return Some(CompactionPick {
+                    tables: tables[start..end].iter().map(|t| t.id).collect(),
+                    output_level: 0,
+                    reason: format!("{} tables in size tier {}", end - start, tier),
+                    expired: false,
+                });
+            }
+            end = start;
+        }
+        None
+    }
+}
+
+/// Keeps flushed tables in level 0 and merges them down into level 1 once
+/// there are `level0_tables` of them. Each deeper level may hold
+/// `level_multiplier` times the bytes of the one above before it is merged
+/// into the next. Reads search few tables, at the cost of rewriting data
+/// more often than size-tiered merging.
+#[derive(Debug, Clone)]
+pub struct LeveledPicker {
+    pub level0_tables: usize,
+    pub level1_bytes: u64,
+    pub level_multiplier: u64,
+    pub max_level: u32,
+}
+
+impl Default for LeveledPicker {
+    fn default() -> Self {
+        Self {
+            level0_tables: 4,
+            level1_bytes: 64 * 1024 * 1024,
+            level_multiplier: 10,
+            max_level: 6,
+        }
+    }
+}
+
+impl LeveledPicker {
+    fn level_bytes(&self, level: u32) -> u64 {
+        self.level1_bytes.saturating_mul(
+            self.level_multiplier
+                .saturating_pow(level.saturating_sub(1)),
+        )
+    }
+
+    // The newest run of tables at `level`, extended with the run just older
+    // than it when that one is at the next level down.
+    fn run_into_next(&self, tables: &[PickerTable], level: u32) -> (usize, usize) {
+        let end = tables
+            .iter()
+            .rposition(|t| t.level == level)
+            .map_or(0, |i| i + 1);
+        let run_start = |end: usize, level: u32| {
+            tables[..end]
+                .iter()
+                .rposition(|t| t.level != level)
+                .map_or(0, |i| i + 1)
+        };
+        let start = run_start(end, level);
+        match start.checked_sub(1) {
+            Some(before) if tables[before].level == level + 1 => (run_start(start, level + 1), end),
+            _ => (start, end),
+        }
+    }
+}
+
+impl CompactionPicker for LeveledPicker {
+    fn name(&self) -> &str {
+        "leveled"
+    }
+
+    fn pick(&self, tables: &[PickerTable]) -> Option<CompactionPick> {
+        let level0 = tables.iter().rev().take_while(|t| t.level == 0).count();
+        if level0 >= self.level0_tables.max(1) {
+            let (start, end) = self.run_into_next(tables, 0);
+            return Some(CompactionPick {
+                tables: tables[start..end].iter().map(|t| t.id).collect(),
+                output_level: 1,
+                reason: format!("{} tables in level 0", level0),
+                expired: false,
+            });
+        }
+        for level in 1..self.max_level.max(1) {
+            let bytes: u64 = tables
+                .iter()
+                .filter(|t| t.level == level)
+                .map(|t| t.size_bytes)
+                .sum();
+            if bytes > self.level_bytes(level) {
+                let (start, end) = self.run_into_next(tables, level);
+                return Some(CompactionPick {
+                    tables: tables[start..end].iter().map(|t| t.id).collect(),
+                    output_level: level + 1,
+                    reason: format!(
+                        "level {} holds {} bytes, over its {} byte target",
+                        level,
+                        bytes,
+                        self.level_bytes(level)
+                    ),
+                    expired: false,
+                });
+            }
+        }
+        None
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WindowSource {
+    /// When the table's records were written.
+    #[default]
+    WriteTime,
+    /// A Unix timestamp in the keys, right after `key_prefix`: seconds, or
+    /// milliseconds when it has more than 11 digits.
+    KeyPrefix,
+}
+
+fn default_window_seconds() -> u64 {
+    24 * 60 * 60
+}
+
+fn default_window_min_tables() -> usize {
+    4
+}
+
+/// Groups tables into fixed time windows and only merges tables of the
+/// same window, for time-series data that is written once and expires as
+/// a whole. The current window is merged like size-tiered compaction once
+/// it has `min_tables` tables; an older window is merged into a single
+/// table. With `retention_seconds` set, windows that ended longer ago
+/// than that are deleted a table at a time, without reading them.
+///
+/// A table whose times span more than one window is left alone, so late
+/// writes into a closed window do not drag it into a merge with a newer
+/// one.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TimeWindowPicker {
+    #[serde(default = "default_window_seconds")]
+    pub window_seconds: u64,
+    #[serde(default)]
+    pub source: WindowSource,
+    /// Prefix before the timestamp when `source` is `key_prefix`.
+    #[serde(default)]
+    pub key_prefix: String,
+    #[serde(default = "default_window_min_tables")]
+    pub min_tables: usize,
+    #[serde(default)]
+    pub retention_seconds: Option<u64>,
+}
+
+impl Default for TimeWindowPicker {
+    fn default() -> Self {
+        Self {
+            window_seconds: default_window_seconds(),
+            source: WindowSource::default(),
+            key_prefix: String::new(),
+            min_tables: default_window_min_tables(),
+            retention_seconds: None,
+        }
+    }
+}
+
+impl TimeWindowPicker {
+    fn key_time(&self, key: &str) -> Option<u64> {
+        let digits: &str = key.strip_prefix(self.key_prefix.as_str())?;
+        let end = digits
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(digits.len());
+        let value: u64 = digits[..end].parse().ok()?;
+        Some(if end > 11 {
+            value
+        } else {
+            value.saturating_mul(1000)
+        })
+    }
+
+    // Unix milliseconds of the oldest and newest data in `table`.
+    fn times(&self, table: &PickerTable) -> Option<(u64, u64)> {
+        match self.source {
+            WindowSource::WriteTime => Some(table.write_times),
+            WindowSource::KeyPrefix => Some((
+                self.key_time(table.min_key.as_deref()?)?,
+                self.key_time(table.max_key.as_deref()?)?,
+            )),
+        }
+    }
+
+    fn window_ms(&self) -> u64 {
+        self.window_seconds.max(1).saturating_mul(1000)
+    }
+
+    fn window(&self, table: &PickerTable) -> Option<u64> {
+        let (oldest, newest) = self.times(table)?;
+        let window = oldest / self.window_ms();
+        (newest / self.window_ms() == window).then_some(window)
+    }
+}
+
+impl CompactionPicker for TimeWindowPicker {
+    fn name(&self) -> &str {
+        "time_windowed"
+    }
+
+    fn pick(&self, tables: &[PickerTable]) -> Option<CompactionPick> {
+        let now = now_millis();
+        if let Some(retention) = self.retention_seconds {
+            let cutoff = now.saturating_sub(retention.saturating_mul(1000));
+            let expired = tables
+                .iter()
+                .take_while(|t| {
+                    self.times(t).is_some_and(|(_, newest)| {
+                        (newest / self.window_ms() + 1) * self.window_ms() <= cutoff
+                    })
+                })
+                .count();
+            if expired > 0 {
+                return Some(CompactionPick {
+                    tables: tables[..expired].iter().map(|t| t.id).collect(),
+                    output_level: 0,
+                    reason: format!(
+                        "{} tables in windows that ended more than {}s ago",
+                        expired, retention
+                    ),
+                    expired: true,
+                });
+            }
+        }
+
+        let current = now / self.window_ms();
+        let mut start = 0;
+        while start < tables.len() {
+            let window = self.window(&tables[start]);
+            let end = start
+                + tables[start..]
+                    .iter()
+                    .take_while(|t| window.is_some() && self.window(t) == window)
+                    .count()
+                    .max(1);
+            let needed = if window == Some(current) {
+                self.min_tables.max(2)
+            } else {
+                2
+            };
+            if let Some(window) = window.filter(|_| end - start >= needed) {
+                return Some(CompactionPick {
+                    tables: tables[start..end].iter().map(|t| t.id).collect(),
+                    output_level: 0,
+                    reason: format!(
+                        "{} tables in the {}s window starting at {}",
+                        end - start,
+                        self.window_seconds.max(1),
+                        window * self.window_ms() / 1000
+                    ),
+                    expired: false,
+                });
+            }
+            start = end;
+        }
+        None
+    }
+}
+
+// Reads a table's entries in key order with their values decompressed.
+struct RecordReader {
+    reader: BufReader<File>,
+    table: Arc<SSTable>,
+    head: Option<(VeloKey, RawValue)>,
+    head_size: u64,
+}
+
+impl RecordReader {
+    fn open(table: &Arc<SSTable>) -> VeloResult<Self> {
+        let mut file = BufReader::with_capacity(256 * 1024, File::open(&table.path)?);
+        file.seek(SeekFrom::Start(table.data_start()))?;
+        let mut reader = Self {
+            reader: file,
+            table: table.clone(),
+            head: None,
+            head_size: 0,
+        };
+        reader.advance()?;
+        Ok(reader)
+    }
+
+    // Returns the size on disk of the record that was consumed.
+    fn advance(&mut self) -> VeloResult<u64> {
+        let consumed = self.head_size;
+        let mut k_size_buf = [0u8; 2];
+        match self.reader.read_exact(&mut k_size_buf) {
+            Ok(()) => {}
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => {
+                self.head = None;
+                return Ok(consumed);
+            }
+            Err(e) => return Err(e.into()),
+        }
+        let mut key = vec![0u8; u16::from_le_bytes(k_size_buf) as usize];
+        self.reader.read_exact(&mut key)?;
+        let key = String::from_utf8_lossy(&key).into_owned();
+        let mut v_size_buf = [0u8; 4];
+        self.reader.read_exact(&mut v_size_buf)?;
+        let (value_len, has_meta) = decode_len(v_size_buf);
+        let mut value = vec![0u8; value_len];
+        self.reader.read_exact(&mut value)?;
+        self.head_size = (6 + key.len() + value.len()) as u64;
+        self.head = Some((key, self.table.decode(value, has_meta)?));
+        Ok(consumed)
+    }
+}
+
+// Trains a dictionary for the merged table on an even share of the values
+// from each input that `plan` compresses.
+fn train_dictionary(tables: &[Arc<SSTable>], plan: &CompressionPlan) -> VeloResult<Dictionary> {
+    let share = compression::SAMPLE_BYTES / tables.len().max(1);
+    let mut samples = Vec::new();
+    for table in tables {
+        let mut reader = RecordReader::open(table)?;
+        let mut taken = 0;
+        while let Some((key, value)) = &reader.head {
+            if taken >= share {
+                break;
+            }
+            if compression::is_sample(&value.bytes) && plan.level(key).is_some() {
+                taken += value.bytes.len();
+                samples.push(value.bytes.clone());
+            }
+            reader.advance()?;
+        }
+    }
+    Ok(Dictionary::train(&samples))
+}
+
+impl Velocity {
+    /// Flushes the memtable and merges every SSTable into one, keeping only
+    /// the newest version of each key and dropping tombstones. Progress,
+    /// cancellation and the I/O limit go through `control`. The merged table
+    /// is written under a new id, and the old tables are only deleted once
+    /// the manifest lists it instead, so a crash at any point leaves either
+    /// the old tables or the merged one.
+    pub fn compact_full(&self, control: &CompactionControl) -> VeloResult<CompactionState> {
+        let result = self.run_full_compaction(control);
+        match &result {
+            Ok(state) => control.finish(*state),
+            Err(e) => {
+                *control.error.lock().unwrap() = Some(e.to_string());
+                control.finish(CompactionState::Failed);
+            }
+        }
+        result
+    }
+
+    fn run_full_compaction(&self, control: &CompactionControl) -> VeloResult<CompactionState> {
+        self.flush()?;
+        let _compacting = self.compaction_lock.lock().unwrap();
+        let _flag = self.mark_compacting();
+
+        let (tables, level) = {
+            let sstables = self.sstables.read().unwrap();
+            if sstables.len() < 2 && sstables.iter().all(|t| t.tombstones == 0) {
+                return Ok(CompactionState::Completed);
+            }
+            let levels = self.table_levels.lock().unwrap();
+            (
+                sstables.iter().map(|t| t.id).collect::<Vec<_>>(),
+                sstables
+                    .iter()
+                    .filter_map(|t| levels.get(&t.id).copied())
+                    .max()
+                    .unwrap_or(0),
+            )
+        };
+        self.merge_tables(&tables, level, control, "full", None)
+    }
+
+    // Merges a run of neighbouring tables into a new table at `output_level`,
+    // in the place of the run. Tombstones are only dropped when no older table
+    // is left for them to shadow. The caller holds the compaction lock.
+    pub(crate) fn merge_tables(
+        &self,
+        tables: &[u64],
+        output_level: u32,
+        control: &CompactionControl,
+        action: &str,
+        reason: Option<&str>,
+    ) -> VeloResult<CompactionState> {
+        let (inputs, drop_tombstones) = {
+            let sstables = self.sstables.read().unwrap();
+            let start = tables
+                .first()
+                .and_then(|first| sstables.iter().position(|t| t.id == *first));
+            let run = start.and_then(|start| sstables.get(start..start + tables.len()));
+            match run {
+                Some(run) if run.iter().map(|t| t.id).eq(tables.iter().copied()) => {
+                    (run.to_vec(), start == Some(0))
+                }
+                _ => {
+                    return Err(VeloError::InvalidOperation(format!(
+                        "Cannot merge tables {:?}: they must be neighbouring tables, oldest first",
+                        tables
+                    )))
+                }
+            }
+        };
+        let paths: Vec<_> = inputs.iter().map(|t| t.path.clone()).collect();
+        let expected_entries: usize = inputs.iter().map(|t| t.entry_count).sum();
+        let bytes_total: u64 = inputs.iter().map(|t| t.size).sum();
+        let oldest = inputs[0].id;
+        let (merged_id, merged_path) = self.new_table_path();
+        control.bytes_total.store(bytes_total, Ordering::Relaxed);
+        let operation = if action == "full" {
+            MaintenanceOperation::FullCompaction
+        } else {
+            MaintenanceOperation::Compaction
+        };
+        let mut progress = ProgressReporter::start(self, operation, bytes_total, inputs.len());
+
+        let plan = self.compression_plan();
+        let compression = if plan.any() {
+            Some((train_dictionary(&inputs, &plan)?, plan))
+        } else {
+            None
+        };
+        let tmp_path = temp_sstable_path(&merged_path);
+        let mut writer = SSTableWriter::new(
+            tmp_path.clone(),
+            merged_id,
+            expected_entries,
+            self.config.bloom_false_positive_rate,
+        )?
+        .compress_with(compression)?;
+        let merged = (|| {
+            let mut readers = inputs
+                .iter()
+                .map(RecordReader::open)
+                .collect::<VeloResult<Vec<_>>>()?;
+            let started = Instant::now();
+            let now = now_millis();
+            let mut since_throttle = 0u64;
+
+            loop {
+                if control.is_cancelled() {
+                    return Ok(false);
+                }
+
+                // Readers are in age order, so on equal keys the last one
+                // seen is the newest.
+                let mut newest: Option<usize> = None;
+                for (i, reader) in readers.iter().enumerate() {
+                    let Some((key, _)) = &reader.head else { continue };
+                    let smaller_or_equal = match newest {
+                        None => true,
+                        Some(n) => key <= &readers[n].head.as_ref().unwrap().0,
+                    };
+                    if smaller_or_equal {
+                        newest = Some(i);
+                    }
+                }
+                let Some(newest) = newest else {
+                    return Ok(true);
+                };
+
+                let (key, value) = readers[newest].head.clone().unwrap();
+                let mut consumed = 0;
+                for reader in readers.iter_mut() {
+                    if reader.head.as_ref().is_some_and(|(k, _)| *k == key) {
+                        consumed += reader.advance()?;
+                    }
+                }
+                if !value.is_tombstone() && !self.is_expired(&key, &value.bytes, now) {
+                    writer.add(&key, &value.bytes, value.has_meta)?;
+                } else if !drop_tombstones {
+                    writer.add(&key, &[], false)?;
+                }
+
+                let bytes_done =
+                    control.bytes_done.fetch_add(consumed, Ordering::Relaxed) + consumed;
+                since_throttle += consumed;
+                if since_throttle >= THROTTLE_CHUNK {
+                    since_throttle = 0;
+                    control.throttle(started);
+                    let remaining = readers.iter().filter(|r| r.head.is_some()).count();
+                    progress.update(bytes_done, remaining);
+                }
+            }
+        })();
+
+        let completed = match merged {
+            Ok(completed) => completed,
+            Err(e) => {
+                drop(writer);
+                let _ = std::fs::remove_file(&tmp_path);
+                return Err(e);
+            }
+        };
+        if !completed {
+            drop(writer);
+            let _ = std::fs::remove_file(&tmp_path);
+            self.log_compaction(None, action, "cancelled; tables left unchanged".to_string());
+            progress.finish(CompactionState::Cancelled);
+            return Ok(CompactionState::Cancelled);
+        }
+
+        let mut merged = writer.finish()?;
+        merged.path = merged_path.clone();
+        if !drop_tombstones {
+            merged.tombstones_retained = merged.tombstones;
+        }
+        let (entries_after, bytes_after) = (merged.entry_count, merged.size);
+
+        {
+            let mut sstables = self.sstables.write().unwrap();
+            if merged.entry_count == 0 {
+                std::fs::remove_file(&tmp_path)?;
+            } else {
+                self.install_replacement(&sstables, &tmp_path, &merged_path)?;
+            }
+
+            let position = sstables.iter().position(|t| t.id == oldest).unwrap_or(0);
+            sstables.retain(|t| !tables.contains(&t.id));
+            let mut levels = self.table_levels.lock().unwrap();
+            let mut write_times = self.table_write_times.lock().unwrap();
+            let merged_times = tables
+                .iter()
+                .filter_map(|id| write_times.get(id).copied())
+                .reduce(|a, b| (a.0.min(b.0), a.1.max(b.1)));
+            levels.retain(|id, _| !tables.contains(id));
+            write_times.retain(|id, _| !tables.contains(id));
+            if merged.entry_count > 0 {
+                merged.level.store(output_level, Ordering::Relaxed);
+                sstables.insert(position, Arc::new(merged));
+                if output_level > 0 {
+                    levels.insert(merged_id, output_level);
+                }
+                if let Some(times) = merged_times {
+                    write_times.insert(merged_id, times);
+                }
+            }
+            drop((levels, write_times));
+            self.persist_manifest(&sstables)?;
+            for path in &paths {
+                std::fs::remove_file(path)?;
+            }
+        }
+
+        let merged = format!(
+            "merged {} tables ({} bytes) into {} entries ({} bytes)",
+            tables.len(),
+            bytes_total,
+            entries_after,
+            bytes_after
+        );
+        self.log_compaction(
+            (action != "full").then_some(merged_id),
+            action,
+            match reason {
+                Some(reason) => format!("{}; {} at level {}", reason, merged, output_level),
+                None => merged,
+            },
+        );
+        self.lifetime.lock().unwrap().total_compactions += 1;
+        self.persist_lifetime_stats()?;
+        progress.finish(CompactionState::Completed);
+        Ok(CompactionState::Completed)
+    }
+
+    // Deletes tables whose data a picker found expired. Only the oldest
+    // tables can go: no older version of their keys is left to reappear.
+    fn drop_expired_tables(&self, tables: &[u64], reason: &str) -> VeloResult<()> {
+        let dropped = {
+            let mut sstables = self.sstables.write().unwrap();
+            if !sstables
+                .iter()
+                .map(|t| t.id)
+                .take(tables.len())
+                .eq(tables.iter().copied())
+            {
+                return Err(VeloError::InvalidOperation(format!(
+                    "Cannot drop tables {:?}: only the oldest tables can be dropped",
+                    tables
+                )));
+            }
+            let dropped: Vec<Arc<SSTable>> = sstables.drain(..tables.len()).collect();
+            self.table_levels
+                .lock()
+                .unwrap()
+                .retain(|id, _| !tables.contains(id));
+            self.table_write_times
+                .lock()
+                .unwrap()
+                .retain(|id, _| !tables.contains(id));
+            self.persist_manifest(&sstables)?;
+            dropped
+        };
+
+        // Keys are read from disk before the cache is locked, so lookups
+        // are not held up by the reads.
+        let mut keys = Vec::new();
+        for table in &dropped {
+            keys.extend(table.keys()?);
+        }
+        let mut cache = self.cache.lock().unwrap();
+        for key in &keys {
+            cache.remove(key);
+        }
+        drop(cache);
+        // To write listeners, such as result caches, dropping a key is a
+        // write of it.
+        let sequence = crate::hlc::clock().now().as_u64();
+        for key in &keys {
+            self.notify_write(sequence, key);
+        }
+        for table in &dropped {
+            std::fs::remove_file(&table.path)?;
+            self.log_compaction(
+                Some(table.id),
+                "drop",
+                format!(
+                    "{}; deleted {} entries ({} bytes)",
+                    reason, table.entry_count, table.size
+                ),
+            );
+        }
+        Ok(())
+    }
+
+    pub fn set_compaction_picker(&self, picker: Option<Arc<dyn CompactionPicker>>) {
+        *self.compaction_picker.write().unwrap() = picker;
+    }
+
+    pub fn compaction_picker(&self) -> Option<Arc<dyn CompactionPicker>> {
+        self.compaction_picker.read().unwrap().clone()
+    }
+
+    // What the picker would merge next; `None` without a picker.
+    pub(crate) fn next_compaction_pick(&self, sstables: &[Arc<SSTable>]) -> Option<CompactionPick> {
+        let picker = self.compaction_picker()?;
+        let levels = self.table_levels.lock().unwrap();
+        let write_times = self.table_write_times.lock().unwrap();
+        let tables: Vec<PickerTable> = sstables
+            .iter()
+            .map(|t| PickerTable {
+                id: t.id,
+                level: levels.get(&t.id).copied().unwrap_or(0),
+                write_times: write_times.get(&t.id).copied().unwrap_or_default(),
+                size_bytes: t.size,
+                entries: t.entry_count,
+                tombstones: t.tombstones,
+                min_key: t.min_key.clone(),
+                max_key: t.max_key.clone(),
+            })
+            .collect();
+        drop((levels, write_times));
+        picker.pick(&tables).filter(|pick| !pick.tables.is_empty())
+    }
+
+    // Runs the picker's merges until it has nothing left, up to a limit so
+    // a picker that keeps asking cannot stall the flush that triggered it.
+    pub(crate) fn run_picked_compactions(&self) -> VeloResult<()> {
+        for _ in 0..MAX_PICKS_PER_COMPACTION {
+            let pick = {
+                let sstables = self.sstables.read().unwrap();
+                self.next_compaction_pick(&sstables)
+            };
+            let Some(pick) = pick else {
+                return Ok(());
+            };
+            if pick.expired {
+                self.drop_expired_tables(&pick.tables, &pick.reason)?;
+                continue;
+            }
+            let control = CompactionControl::new(self.config.compaction_io_budget);
+            self.merge_tables(
+                &pick.tables,
+                pick.output_level,
+                &control,
+                "merge",
+                Some(&pick.reason),
+            )?;
+        }
+        Ok(())
+    }
+}
+
@@ -0,0 +1,197 @@
+use std::io::{Read, Seek, SeekFrom};
+
+use zstd::bulk::{Compressor, Decompressor};
+use zstd::dict::DecoderDictionary;
+
+use crate::{VeloError, VeloResult, VeloValue};
+
+/// Compressed tables start with this header, then the length of the zstd
+/// dictionary their values were compressed with as a u32 and the dictionary
+/// itself, which is empty when there was too little to train on. Read as a
+/// record, the header would have a key that is not valid UTF-8, so a table
+/// without it can never start with these bytes.
+pub const TABLE_HEADER: [u8; 8] = [0x06, 0x00, 0xFF, b'z', b's', b't', b'd', 0x01];
+
+const DICTIONARY_SIZE: usize = 16 * 1024;
+pub(crate) const DEFAULT_LEVEL: i32 = 3;
+
+// Values are sampled up to this many bytes in total; larger values compress
+// well enough on their own and are left out.
+pub(crate) const SAMPLE_BYTES: usize = 1024 * 1024;
+const MAX_SAMPLE_LEN: usize = 16 * 1024;
+// Below this many samples training either fails or yields a dictionary that
+// does not pay for itself.
+const MIN_SAMPLES: usize = 256;
+
+// Every non-empty value in a compressed table starts with one of these.
+const STORED: u8 = 0;
+const ZSTD: u8 = 1;
+
+pub struct Dictionary {
+    bytes: Vec<u8>,
+    decoder: Option<DecoderDictionary<'static>>,
+}
+
+impl Dictionary {
+    /// Trains a dictionary on `samples`. With too few of them values are
+    /// compressed one by one instead.
+    pub(crate) fn train(samples: &[VeloValue]) -> Dictionary {
+        if samples.len() < MIN_SAMPLES {
+            return Self::load(Vec::new());
+        }
+        match zstd::dict::from_samples(samples, DICTIONARY_SIZE) {
+            Ok(bytes) => Self::load(bytes),
+            Err(e) => {
+                log::debug!("Compressing without a dictionary; training failed: {}", e);
+                Self::load(Vec::new())
+            }
+        }
+    }
+
+    pub(crate) fn load(bytes: Vec<u8>) -> Dictionary {
+        Dictionary {
+            decoder: (!bytes.is_empty()).then(|| DecoderDictionary::copy(&bytes)),
+            bytes,
+        }
+    }
+
+    /// The zstd dictionary id, or `None` for a table compressed without one.
+    pub fn id(&self) -> Option<u32> {
+        zstd::zstd_safe::get_dict_id_from_dict(&self.bytes).map(|id| id.get())
+    }
+
+    pub fn bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    pub(crate) fn encoder(&self) -> VeloResult<ValueEncoder> {
+        let compressor = if self.bytes.is_empty() {
+            Compressor::new(DEFAULT_LEVEL)?
+        } else {
+            Compressor::with_dictionary(DEFAULT_LEVEL, &self.bytes)?
+        };
+        Ok(ValueEncoder {
+            compressor,
+            level: DEFAULT_LEVEL,
+        })
+    }
+
+    pub(crate) fn decode(&self, stored: VeloValue) -> VeloResult<VeloValue> {
+        match stored.split_first() {
+            None => Ok(stored),
+            Some((&STORED, value)) => Ok(value.to_vec()),
+            Some((&ZSTD, rest)) if rest.len() >= 4 => {
+                let raw_len = u32::from_le_bytes(rest[..4].try_into().unwrap()) as usize;
+                let mut decompressor = match &self.decoder {
+                    Some(decoder) => Decompressor::with_prepared_dictionary(decoder)?,
+                    None => Decompressor::new()?,
+                };
+                decompressor
+                    .decompress(&rest[4..], raw_len)
+                    .map_err(|e| VeloError::CorruptedData(format!("Bad compressed value: {}", e)))
+            }
+            Some((codec, _)) => Err(VeloError::CorruptedData(format!(
+                "Unknown value encoding {}",
+                codec
+            ))),
+        }
+    }
+}
+
+pub(crate) fn encode_header(dictionary: &Dictionary) -> Vec<u8> {
+    let mut header = Vec::with_capacity(TABLE_HEADER.len() + 4 + dictionary.bytes.len());
+    header.extend_from_slice(&TABLE_HEADER);
+    header.extend_from_slice(&(dictionary.bytes.len() as u32).to_le_bytes());
+    header.extend_from_slice(&dictionary.bytes);
+    header
+}
+
+// Where the records of a table compressed with `dictionary` start.
+pub(crate) fn header_len(dictionary: &Dictionary) -> u64 {
+    (TABLE_HEADER.len() + 4 + dictionary.bytes.len()) as u64
+}
+
+// The dictionary of a table with a header, leaving `reader` at the first
+// record either way.
+pub(crate) fn read_header<R: Read + Seek>(reader: &mut R) -> VeloResult<Option<Dictionary>> {
+    reader.seek(SeekFrom::Start(0))?;
+    let mut header = [0u8; TABLE_HEADER.len()];
+    if reader.read_exact(&mut header).is_err() || header != TABLE_HEADER {
+        reader.seek(SeekFrom::Start(0))?;
+        return Ok(None);
+    }
+    let mut len = [0u8; 4];
+    reader.read_exact(&mut len)?;
+    let mut bytes = vec![0u8; u32::from_le_bytes(len) as usize];
+    reader.read_exact(&mut bytes)?;
+    Ok(Some(Dictionary::load(bytes)))
+}
+
+// The length a stored value decodes to, without decompressing it.
+pub(crate) fn raw_len(stored: &[u8]) -> usize {
+    match stored.split_first() {
+        Some((&ZSTD, rest)) if rest.len() >= 4 => {
+            u32::from_le_bytes(rest[..4].try_into().unwrap()) as usize
+        }
+        Some((_, value)) => value.len(),
+        None => 0,
+    }
+}
+
+pub(crate) fn is_sample(value: &[u8]) -> bool {
+    !value.is_empty() && value.len() <= MAX_SAMPLE_LEN
+}
+
+// Training samples from `values`, up to `budget` bytes of them.
+pub(crate) fn sample<'a>(
+    values: impl IntoIterator<Item = &'a VeloValue>,
+    budget: usize,
+) -> Vec<VeloValue> {
+    let mut taken = 0;
+    values
+        .into_iter()
+        .filter(|value| is_sample(value))
+        .take_while(|value| {
+            taken += value.len();
+            taken <= budget
+        })
+        .cloned()
+        .collect()
+}
+
+pub(crate) struct ValueEncoder {
+    compressor: Compressor<'static>,
+    level: i32,
+}
+
+impl ValueEncoder {
+    // Tombstones stay empty. Values are stored as they are without a
+    // `level`, or when compressing them does not make them smaller.
+    pub(crate) fn encode(&mut self, value: &[u8], level: Option<i32>) -> VeloResult<VeloValue> {
+        if value.is_empty() {
+            return Ok(Vec::new());
+        }
+        let compressed = match level {
+            Some(level) => {
+                if level != self.level {
+                    self.compressor.set_compression_level(level)?;
+                    self.level = level;
+                }
+                Some(self.compressor.compress(value)?)
+            }
+            None => None,
+        };
+        let mut stored;
+        if let Some(compressed) = compressed.filter(|c| c.len() + 4 < value.len()) {
+            stored = Vec::with_capacity(5 + compressed.len());
+            stored.push(ZSTD);
+            stored.extend_from_slice(&(value.len() as u32).to_le_bytes());
+            stored.extend_from_slice(&compressed);
+        } else {
+            stored = Vec::with_capacity(1 + value.len());
+            stored.push(STORED);
+            stored.extend_from_slice(value);
+        }
+        Ok(stored)
+    }
+}
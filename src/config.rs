@@ -1,8 +1,28 @@
+use colored::*;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
 use velocity::addon::{
     BackgroundServiceAddonConfig, BackupAddonConfig, DatabaseAddonConfig,
 };
+use velocity::access::AccessConfig;
+use velocity::auth::AuthConfig;
+use velocity::background::BackgroundPoolConfig;
+use velocity::compaction::{CompactionPicker, CompactionStrategy, TimeWindowPicker};
+use velocity::faults::FaultInjectionConfig;
+use velocity::fsutil::atomic_write;
+use velocity::hotkeys::HotKeyConfig;
+use velocity::identity::ServerIdentity;
+use velocity::io_backend::IoBackend;
+use velocity::layout::LayoutMode;
+use velocity::policy::PrefixPolicy;
+use velocity::server::{ListenerConfig, ServerConfig};
+use velocity::studio::AnalysisConfig;
+use velocity::users::PasswordPolicy;
+use velocity::warmup::CacheWarmup;
+use velocity::{CachePolicy, Velocity, VelocityConfig, WalSyncMode};
 
 pub fn default_bind_address() -> String {
     "127.0.0.1:2005".to_string()
@@ -20,6 +40,70 @@ pub fn default_rate_limit() -> u32 {
     1000
 }
 
+pub fn default_interactive_latency_target_ms() -> u64 {
+    50
+}
+
+pub fn default_max_concurrent_batch_commands() -> usize {
+    4
+}
+
+pub fn default_idempotency_window_seconds() -> u64 {
+    600
+}
+
+pub fn default_idempotency_max_entries() -> usize {
+    100_000
+}
+
+pub fn default_max_in_flight_commands() -> usize {
+    1024
+}
+
+pub fn default_max_concurrent_reads() -> usize {
+    64
+}
+
+pub fn default_max_concurrent_writes() -> usize {
+    16
+}
+
+pub fn default_max_pipelined_commands() -> usize {
+    64
+}
+
+pub fn default_max_frame_bytes() -> usize {
+    64 * 1024 * 1024
+}
+
+pub fn default_slow_command_threshold_ms() -> u64 {
+    1000
+}
+
+pub fn default_statement_cache_size() -> usize {
+    256
+}
+
+pub fn default_statement_cache_bytes() -> usize {
+    1024 * 1024
+}
+
+pub fn default_result_cache_max_age_seconds() -> u64 {
+    60
+}
+
+pub fn default_json_values() -> bool {
+    false
+}
+
+pub fn default_drain_timeout_seconds() -> u64 {
+    30
+}
+
+pub fn default_unix_socket_mode() -> u32 {
+    0o660
+}
+
 pub fn default_log_level() -> String {
     "info".to_string()
 }
@@ -36,6 +120,10 @@ pub fn default_cache_hit_rate() -> f64 {
     0.85
 }
 
+pub fn default_wal_shards() -> usize {
+    1
+}
+
 pub fn default_audit_log_path() -> String {
     "./velocitydb_audit.log".to_string()
 }
@@ -76,6 +164,68 @@ pub struct ServerConfigSection {
     pub rate_limit_per_second: u32,
     #[serde(default)]
     pub enable_tls: bool,
+    #[serde(default)]
+    pub cert_path: Option<String>,
+    #[serde(default)]
+    pub key_path: Option<String>,
+    #[serde(default)]
+    pub client_ca_path: Option<String>,
+    #[serde(default)]
+    pub require_client_cert: bool,
+    #[serde(default)]
+    pub client_cert_users: HashMap<String, String>,
+    #[serde(default)]
+    pub tenants: HashMap<String, String>,
+    #[serde(default)]
+    pub access: AccessConfig,
+    #[serde(default = "default_interactive_latency_target_ms")]
+    pub interactive_latency_target_ms: u64,
+    #[serde(default = "default_max_concurrent_batch_commands")]
+    pub max_concurrent_batch_commands: usize,
+    #[serde(default = "default_idempotency_window_seconds")]
+    pub idempotency_window_seconds: u64,
+    #[serde(default = "default_idempotency_max_entries")]
+    pub idempotency_max_entries: usize,
+    #[serde(default = "default_max_in_flight_commands")]
+    pub max_in_flight_commands: usize,
+    #[serde(default = "default_max_concurrent_reads")]
+    pub max_concurrent_reads: usize,
+    #[serde(default = "default_max_concurrent_writes")]
+    pub max_concurrent_writes: usize,
+    #[serde(default = "default_max_pipelined_commands")]
+    pub max_pipelined_commands: usize,
+    #[serde(default = "default_max_frame_bytes")]
+    pub max_frame_bytes: usize,
+    #[serde(default = "default_slow_command_threshold_ms")]
+    pub slow_command_threshold_ms: u64,
+    #[serde(default = "default_statement_cache_size")]
+    pub statement_cache_size: usize,
+    #[serde(default = "default_statement_cache_bytes")]
+    pub statement_cache_bytes: usize,
+    #[serde(default)]
+    pub result_cache_size: usize,
+    #[serde(default = "default_result_cache_max_age_seconds")]
+    pub result_cache_max_age_seconds: u64,
+    #[serde(default = "default_json_values")]
+    pub json_values: bool,
+    #[serde(default)]
+    pub read_only: bool,
+    #[serde(default = "default_drain_timeout_seconds")]
+    pub drain_timeout_seconds: u64,
+    #[serde(default)]
+    pub run_as_user: Option<String>,
+    #[serde(default)]
+    pub run_as_group: Option<String>,
+    #[serde(default)]
+    pub health_bind_address: Option<String>,
+    #[serde(default)]
+    pub shutdown_delay_seconds: u64,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub listeners: Vec<ListenerConfig>,
+    #[serde(default)]
+    pub unix_socket_path: Option<String>,
+    #[serde(default = "default_unix_socket_mode")]
+    pub unix_socket_mode: u32,
 }
 
 impl Default for ServerConfigSection {
@@ -86,10 +236,58 @@ impl Default for ServerConfigSection {
             connection_timeout_seconds: default_timeout(),
             rate_limit_per_second: default_rate_limit(),
             enable_tls: false,
+            cert_path: None,
+            key_path: None,
+            client_ca_path: None,
+            require_client_cert: false,
+            client_cert_users: HashMap::new(),
+            tenants: HashMap::new(),
+            access: AccessConfig::default(),
+            interactive_latency_target_ms: default_interactive_latency_target_ms(),
+            max_concurrent_batch_commands: default_max_concurrent_batch_commands(),
+            idempotency_window_seconds: default_idempotency_window_seconds(),
+            idempotency_max_entries: default_idempotency_max_entries(),
+            max_in_flight_commands: default_max_in_flight_commands(),
+            max_concurrent_reads: default_max_concurrent_reads(),
+            max_concurrent_writes: default_max_concurrent_writes(),
+            max_pipelined_commands: default_max_pipelined_commands(),
+            max_frame_bytes: default_max_frame_bytes(),
+            slow_command_threshold_ms: default_slow_command_threshold_ms(),
+            statement_cache_size: default_statement_cache_size(),
+            statement_cache_bytes: default_statement_cache_bytes(),
+            result_cache_size: 0,
+            result_cache_max_age_seconds: default_result_cache_max_age_seconds(),
+            json_values: default_json_values(),
+            read_only: false,
+            drain_timeout_seconds: default_drain_timeout_seconds(),
+            run_as_user: None,
+            run_as_group: None,
+            health_bind_address: None,
+            shutdown_delay_seconds: 0,
+            listeners: Vec::new(),
+            unix_socket_path: None,
+            unix_socket_mode: default_unix_socket_mode(),
         }
     }
 }
 
+/// Settings meant for testing only.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct DebugSection {
+    #[serde(default)]
+    pub fault_injection: Option<FaultInjectionConfig>,
+    /// Seed for reproducible bloom filters, cache hashing and fault
+    /// injection.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub deterministic_seed: Option<u64>,
+}
+
+impl DebugSection {
+    pub fn is_empty(&self) -> bool {
+        self.fault_injection.is_none() && self.deterministic_seed.is_none()
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct LoggingSection {
     #[serde(default = "default_log_level")]
@@ -114,6 +312,19 @@ pub struct PerformanceSection {
     pub metrics_interval: u64,
     #[serde(default = "default_cache_hit_rate")]
     pub target_cache_hit_rate: f64,
+    #[serde(default)]
+    pub cache_warmup: CacheWarmup,
+    #[serde(default)]
+    pub background_pool: BackgroundPoolConfig,
+    #[serde(default = "default_wal_shards")]
+    pub wal_shards: usize,
+    #[serde(default)]
+    pub memtable_huge_pages: bool,
+    /// `standard` or `io_uring`.
+    #[serde(default)]
+    pub io_backend: IoBackend,
+    #[serde(default)]
+    pub hot_keys: HotKeyConfig,
 }
 
 impl Default for PerformanceSection {
@@ -123,6 +334,12 @@ impl Default for PerformanceSection {
             enable_metrics: true,
             metrics_interval: default_metrics_interval(),
             target_cache_hit_rate: default_cache_hit_rate(),
+            cache_warmup: CacheWarmup::Off,
+            background_pool: BackgroundPoolConfig::default(),
+            wal_shards: default_wal_shards(),
+            memtable_huge_pages: false,
+            io_backend: IoBackend::default(),
+            hot_keys: HotKeyConfig::default(),
         }
     }
 }
@@ -137,6 +354,10 @@ pub struct SecuritySection {
     pub auth_ban_duration: u64,
     #[serde(default = "default_auth_failures")]
     pub max_auth_failures: u32,
+    #[serde(default)]
+    pub password_policy: PasswordPolicy,
+    #[serde(default)]
+    pub admin_users: Vec<String>,
 }
 
 impl Default for SecuritySection {
@@ -146,6 +367,8 @@ impl Default for SecuritySection {
             audit_logging: true,
             auth_ban_duration: default_ban_duration(),
             max_auth_failures: default_auth_failures(),
+            password_policy: PasswordPolicy::default(),
+            admin_users: Vec::new(),
         }
     }
 }
@@ -162,6 +385,28 @@ pub struct DatabaseConfigSection {
     pub compaction_threshold: usize,
     #[serde(default)]
     pub enable_compression: bool,
+    #[serde(default)]
+    pub record_metadata: bool,
+    /// What writes do to the read cache of the default database.
+    #[serde(default)]
+    pub cache_policy: CachePolicy,
+    #[serde(default)]
+    pub data_layout: LayoutMode,
+    /// Open with `Velocity::open_with_verification`.
+    #[serde(default)]
+    pub verify_on_open: bool,
+    /// Read rate limit for `COMPACT DATABASE … FULL`; 0 means unlimited.
+    #[serde(default)]
+    pub compaction_io_budget_mb_per_second: u64,
+    /// `off`, `size_tiered`, `leveled` or `time_windowed`.
+    #[serde(default)]
+    pub compaction_strategy: CompactionStrategy,
+    /// Settings of the `time_windowed` strategy.
+    #[serde(default)]
+    pub time_window_compaction: TimeWindowPicker,
+    /// `[[database.key_policies]]` entries.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub key_policies: Vec<PrefixPolicy>,
 }
 
 impl Default for DatabaseConfigSection {
@@ -172,6 +417,23 @@ impl Default for DatabaseConfigSection {
             bloom_false_positive_rate: default_bloom(),
             compaction_threshold: default_compaction(),
             enable_compression: false,
+            record_metadata: false,
+            cache_policy: CachePolicy::default(),
+            data_layout: LayoutMode::default(),
+            verify_on_open: false,
+            compaction_io_budget_mb_per_second: 0,
+            compaction_strategy: CompactionStrategy::default(),
+            time_window_compaction: TimeWindowPicker::default(),
+            key_policies: Vec::new(),
+        }
+    }
+}
+
+impl DatabaseConfigSection {
+    pub fn compaction_picker(&self) -> Option<Arc<dyn CompactionPicker>> {
+        match self.compaction_strategy {
+            CompactionStrategy::TimeWindowed => Some(Arc::new(self.time_window_compaction.clone())),
+            strategy => strategy.picker(),
         }
     }
 }
@@ -196,6 +458,8 @@ impl Default for AddonsSection {
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct ConfigFile {
+    #[serde(default = "default_config_version")]
+    pub config_version: u32,
     pub server: ServerConfigSection,
     #[serde(default)]
     pub logging: LoggingSection,
@@ -204,9 +468,15 @@ pub struct ConfigFile {
     #[serde(default)]
     pub security: SecuritySection,
     pub users: HashMap<String, String>,
+    #[serde(default)]
+    pub auth: AuthConfig,
     pub database: DatabaseConfigSection,
     #[serde(default)]
     pub addons: AddonsSection,
+    #[serde(default)]
+    pub analysis: AnalysisConfig,
+    #[serde(default, skip_serializing_if = "DebugSection::is_empty")]
+    pub debug: DebugSection,
 }
 
 impl Default for ConfigFile {
@@ -219,13 +489,246 @@ impl Default for ConfigFile {
         );
 
         Self {
+            config_version: CONFIG_VERSION,
             server: ServerConfigSection::default(),
             logging: LoggingSection::default(),
             performance: PerformanceSection::default(),
             security: SecuritySection::default(),
             users,
+            auth: AuthConfig::default(),
             database: DatabaseConfigSection::default(),
             addons: AddonsSection::default(),
+            analysis: AnalysisConfig::default(),
+            debug: DebugSection::default(),
         }
     }
 }
+
+impl ConfigFile {
+    /// Reads the config file at `path`. A file written for an older layout
+    /// is upgraded and saved, and the original is kept next to it as
+    /// `<file>.v<version>.bak`.
+    pub fn load(path: &Path) -> Result<ConfigFile, Box<dyn std::error::Error>> {
+        let content = std::fs::read_to_string(path)?;
+        let (table, upgrade) = parse_upgraded(&content)?;
+        let upgraded = toml::to_string_pretty(&table)?;
+        let config: ConfigFile = toml::Value::Table(table).try_into()?;
+        let Some(upgrade) = upgrade else {
+            return Ok(config);
+        };
+
+        let backup = backup_path(path, upgrade.from_version);
+        // An install whose config is not writable keeps running on the
+        // upgraded copy in memory.
+        match atomic_write(&backup, &content).and_then(|_| atomic_write(path, upgraded)) {
+            Ok(()) => eprintln!(
+                "{} Upgraded {:?} from config version {} to {} (original saved as {:?}): {}",
+                "[CONFIG]".yellow(),
+                path,
+                upgrade.from_version,
+                CONFIG_VERSION,
+                backup,
+                upgrade.changes.join("; ")
+            ),
+            Err(e) => eprintln!(
+                "{} {:?} uses config version {}; upgraded in memory, but saving it failed: {}",
+                "[WARN]".yellow(),
+                path,
+                upgrade.from_version,
+                e
+            ),
+        }
+        Ok(config)
+    }
+
+    pub fn server_config(
+        &self,
+        bind_address: &str,
+        data_dir: &Path,
+    ) -> Result<ServerConfig, Box<dyn std::error::Error>> {
+        Ok(ServerConfig {
+            bind_address: bind_address.parse()?,
+            max_connections: self.server.max_connections,
+            connection_timeout: Duration::from_secs(self.server.connection_timeout_seconds),
+            rate_limit_per_second: self.server.rate_limit_per_second,
+            enable_tls: self.server.enable_tls,
+            cert_path: self.server.cert_path.clone(),
+            key_path: self.server.key_path.clone(),
+            client_ca_path: self.server.client_ca_path.clone(),
+            require_client_cert: self.server.require_client_cert,
+            client_cert_users: self.server.client_cert_users.clone(),
+            tenants: self.server.tenants.clone(),
+            identity_path: Some(ServerIdentity::path_in(data_dir)),
+            users: self.users.clone(),
+            password_policy: self.security.password_policy.clone(),
+            admin_users: self.security.admin_users.clone(),
+            auth: self.auth.clone(),
+            access: self.server.access.clone(),
+            audit_log_path: self.security.audit_log_path.clone(),
+            audit_logging: self.security.audit_logging,
+            auth_ban_duration: Duration::from_secs(self.security.auth_ban_duration),
+            max_auth_failures: self.security.max_auth_failures,
+            interactive_latency_target: Duration::from_millis(
+                self.server.interactive_latency_target_ms,
+            ),
+            max_concurrent_batch_commands: self.server.max_concurrent_batch_commands,
+            idempotency_window: Duration::from_secs(self.server.idempotency_window_seconds),
+            idempotency_max_entries: self.server.idempotency_max_entries,
+            max_in_flight_commands: self.server.max_in_flight_commands.max(1),
+            max_concurrent_reads: self.server.max_concurrent_reads.max(1),
+            max_concurrent_writes: self.server.max_concurrent_writes.max(1),
+            max_pipelined_commands: self.server.max_pipelined_commands.max(1),
+            max_frame_bytes: self.server.max_frame_bytes.max(14),
+            slow_command_threshold: Duration::from_millis(self.server.slow_command_threshold_ms),
+            statement_cache_size: self.server.statement_cache_size,
+            statement_cache_bytes: self.server.statement_cache_bytes,
+            result_cache_size: self.server.result_cache_size,
+            result_cache_max_age: Duration::from_secs(self.server.result_cache_max_age_seconds),
+            json_values: self.server.json_values,
+            read_only: self.server.read_only,
+            listeners: self.server.listeners.clone(),
+            unix_socket_path: self.server.unix_socket_path.as_ref().map(PathBuf::from),
+            unix_socket_mode: self.server.unix_socket_mode,
+        })
+    }
+
+    pub fn open_database(
+        &self,
+        data_dir: &Path,
+        config: VelocityConfig,
+    ) -> velocity::VeloResult<Velocity> {
+        if self.database.verify_on_open {
+            Velocity::open_with_verification(data_dir, config)
+        } else {
+            Velocity::open_with_config(data_dir, config)
+        }
+    }
+
+    pub fn velocity_config(&self) -> VelocityConfig {
+        VelocityConfig {
+            max_memtable_size: self.database.max_memtable_size,
+            cache_size: self.database.cache_size,
+            bloom_false_positive_rate: self.database.bloom_false_positive_rate,
+            compaction_threshold: self.database.compaction_threshold,
+            enable_compression: self.database.enable_compression,
+            memory_only_mode: false,
+            batch_wal_writes: true,
+            adaptive_cache: self.performance.adaptive_cache,
+            cache_policy: self.database.cache_policy,
+            hot_keys: self.performance.hot_keys.clone(),
+            enable_metrics: self.performance.enable_metrics,
+            metrics_interval: Duration::from_secs(self.performance.metrics_interval),
+            target_cache_hit_rate: self.performance.target_cache_hit_rate,
+            wal_sync_mode: WalSyncMode::Batch,
+            wal_shards: self.performance.wal_shards,
+            memtable_huge_pages: self.performance.memtable_huge_pages,
+            io_backend: self.performance.io_backend,
+            record_metadata: self.database.record_metadata,
+            data_layout: self.database.data_layout,
+            recovery_progress: None,
+            fault_injection: self.debug.fault_injection.clone(),
+            background_threads: true,
+            background_pool: self.performance.background_pool.clone(),
+            compaction_io_budget: self.database.compaction_io_budget_mb_per_second * 1024 * 1024,
+            compaction_picker: self.database.compaction_picker(),
+            key_policies: self.database.key_policies.clone(),
+            cache_warmup: self.performance.cache_warmup,
+            hash_seed: self.debug.deterministic_seed,
+            wal_recovery: Default::default(),
+            read_only: false,
+        }
+    }
+}
+
+/// The layout of velocity.toml this binary writes. Files without a
+/// `config_version` predate it and count as version 1.
+pub const CONFIG_VERSION: u32 = 2;
+
+fn default_config_version() -> u32 {
+    1
+}
+
+/// One step of the upgrade from an older layout. `MIGRATIONS[i]` turns
+/// version `i + 1` into version `i + 2`.
+struct Migration {
+    description: &'static str,
+    apply: fn(&mut toml::Table),
+}
+
+const MIGRATIONS: &[Migration] = &[Migration {
+    description: "renamed [addons.background_service] to [addons.background-service]",
+    apply: rename_background_service,
+}];
+
+const _: () = assert!(MIGRATIONS.len() as u32 == CONFIG_VERSION - 1);
+
+fn rename_background_service(config: &mut toml::Table) {
+    let Some(addons) = config.get_mut("addons").and_then(toml::Value::as_table_mut) else {
+        return;
+    };
+    if let Some(section) = addons.remove("background_service") {
+        addons.entry("background-service").or_insert(section);
+    }
+}
+
+/// What upgrading an older config file changed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConfigUpgrade {
+    pub from_version: u32,
+    pub changes: Vec<&'static str>,
+}
+
+/// Brings a parsed config file up to `CONFIG_VERSION`, or returns `None` if
+/// it already is.
+pub fn upgrade_config(config: &mut toml::Table) -> Result<Option<ConfigUpgrade>, String> {
+    let version = match config.get("config_version") {
+        None => default_config_version(),
+        Some(value) => value
+            .as_integer()
+            .and_then(|version| u32::try_from(version).ok())
+            .filter(|version| *version >= 1)
+            .ok_or_else(|| format!("config_version must be a positive integer, not {}", value))?,
+    };
+    if version > CONFIG_VERSION {
+        return Err(format!(
+            "config_version {} is newer than this binary supports ({}); \
+             run the newer release or restore the backup taken before the upgrade",
+            version, CONFIG_VERSION
+        ));
+    }
+    if version == CONFIG_VERSION {
+        return Ok(None);
+    }
+
+    let changes = MIGRATIONS[(version - 1) as usize..]
+        .iter()
+        .map(|migration| {
+            (migration.apply)(config);
+            migration.description
+        })
+        .collect();
+    config.insert(
+        "config_version".to_string(),
+        toml::Value::Integer(CONFIG_VERSION.into()),
+    );
+    Ok(Some(ConfigUpgrade {
+        from_version: version,
+        changes,
+    }))
+}
+
+fn parse_upgraded(
+    content: &str,
+) -> Result<(toml::Table, Option<ConfigUpgrade>), Box<dyn std::error::Error>> {
+    let mut table: toml::Table = toml::from_str(content)?;
+    let upgrade = upgrade_config(&mut table)?;
+    Ok((table, upgrade))
+}
+
+fn backup_path(path: &Path, version: u32) -> PathBuf {
+    let name = path
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_else(|| "velocity.toml".to_string());
+    path.with_file_name(format!("{}.v{}.bak", name, version))
+}
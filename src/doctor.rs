@@ -0,0 +1,522 @@
+use crate::config::{upgrade_config, ConfigFile, CONFIG_VERSION};
+use colored::*;
+use serde::Serialize;
+use std::fs::File;
+use std::io::BufReader;
+use std::net::TcpListener;
+use std::path::Path;
+use std::time::{Duration, SystemTime};
+use velocity::addon::DatabaseManager;
+use velocity::studio::{analyze_system, Severity};
+use velocity::{Velocity, VelocityConfig, WalRecovery};
+use x509_parser::prelude::{FromDer, X509Certificate};
+
+const MIN_FREE_BYTES: u64 = 1024 * 1024 * 1024;
+const MIN_FREE_RATIO: f64 = 0.10;
+const CERT_EXPIRY_WARNING_DAYS: i64 = 30;
+const CLOCK_SKEW_TOLERANCE: Duration = Duration::from_secs(300);
+const EARLIEST_SANE_TIMESTAMP: u64 = 1_704_067_200;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CheckStatus {
+    Pass,
+    Warn,
+    Fail,
+}
+
+#[derive(Serialize)]
+pub struct DoctorCheck {
+    pub name: &'static str,
+    pub status: CheckStatus,
+    pub detail: String,
+    pub hint: Option<String>,
+}
+
+impl DoctorCheck {
+    pub fn pass(name: &'static str, detail: impl Into<String>) -> Self {
+        Self {
+            name,
+            status: CheckStatus::Pass,
+            detail: detail.into(),
+            hint: None,
+        }
+    }
+
+    pub fn warn(name: &'static str, detail: impl Into<String>, hint: impl Into<String>) -> Self {
+        Self {
+            name,
+            status: CheckStatus::Warn,
+            detail: detail.into(),
+            hint: Some(hint.into()),
+        }
+    }
+
+    pub fn fail(name: &'static str, detail: impl Into<String>, hint: impl Into<String>) -> Self {
+        Self {
+            name,
+            status: CheckStatus::Fail,
+            detail: detail.into(),
+            hint: Some(hint.into()),
+        }
+    }
+}
+
+pub fn run_doctor(config_path: &Path, data_dir: &Path) -> Vec<DoctorCheck> {
+    let mut checks = Vec::new();
+
+    let config = check_config(config_path, &mut checks);
+    let data_dir_usable = check_data_dir(data_dir, &mut checks);
+    check_clock(data_dir, &mut checks);
+
+    if data_dir_usable {
+        check_storage(config_path, data_dir, config.as_ref(), &mut checks);
+    }
+
+    if let Some(config) = &config {
+        check_port(&config.server.bind_address, &mut checks);
+        for listener in &config.server.listeners {
+            check_port(&listener.bind_address.to_string(), &mut checks);
+        }
+        if config.server.enable_tls {
+            check_tls(config, &mut checks);
+        }
+    }
+
+    checks
+}
+
+pub fn worst_status(checks: &[DoctorCheck]) -> CheckStatus {
+    checks
+        .iter()
+        .map(|c| c.status)
+        .max()
+        .unwrap_or(CheckStatus::Pass)
+}
+
+pub fn print_report(checks: &[DoctorCheck]) -> CheckStatus {
+    println!("\n{} Velocity diagnostics", "[DOCTOR]".cyan().bold());
+    for check in checks {
+        let label = match check.status {
+            CheckStatus::Pass => "[ OK ]".green(),
+            CheckStatus::Warn => "[WARN]".yellow(),
+            CheckStatus::Fail => "[FAIL]".red(),
+        };
+        println!("{} {}: {}", label, check.name.bold(), check.detail);
+        if let Some(hint) = &check.hint {
+            println!("       {} {}", "->".dimmed(), hint);
+        }
+    }
+
+    let count = |status| checks.iter().filter(|c| c.status == status).count();
+    let worst = worst_status(checks);
+    println!(
+        "\n{} passed, {} warnings, {} failures",
+        count(CheckStatus::Pass),
+        count(CheckStatus::Warn),
+        count(CheckStatus::Fail)
+    );
+    worst
+}
+
+pub fn check_config(config_path: &Path, checks: &mut Vec<DoctorCheck>) -> Option<ConfigFile> {
+    const NAME: &str = "Config";
+
+    if !config_path.exists() {
+        checks.push(DoctorCheck::fail(
+            NAME,
+            format!("{} not found", config_path.display()),
+            format!(
+                "Run `velocity db init --output {}` to generate one",
+                config_path.display()
+            ),
+        ));
+        return None;
+    }
+
+    let content = match std::fs::read_to_string(config_path) {
+        Ok(content) => content,
+        Err(e) => {
+            checks.push(DoctorCheck::fail(
+                NAME,
+                format!("cannot read {}: {}", config_path.display(), e),
+                "Check the file permissions of the config file",
+            ));
+            return None;
+        }
+    };
+
+    let invalid = |checks: &mut Vec<DoctorCheck>, message: &str| {
+        checks.push(DoctorCheck::fail(
+            NAME,
+            format!("{} is invalid: {}", config_path.display(), message),
+            "Fix the reported key or compare with the output of `velocity db init`",
+        ));
+    };
+    let mut table = match toml::from_str::<toml::Table>(&content) {
+        Ok(table) => table,
+        Err(e) => {
+            invalid(checks, e.message());
+            return None;
+        }
+    };
+    let upgrade = match upgrade_config(&mut table) {
+        Ok(upgrade) => upgrade,
+        Err(e) => {
+            invalid(checks, &e);
+            return None;
+        }
+    };
+    let config = match toml::Value::Table(table).try_into::<ConfigFile>() {
+        Ok(config) => config,
+        Err(e) => {
+            invalid(checks, e.message());
+            return None;
+        }
+    };
+
+    match upgrade {
+        Some(upgrade) => checks.push(DoctorCheck::warn(
+            NAME,
+            format!(
+                "{} uses config version {} ({} is current)",
+                config_path.display(),
+                upgrade.from_version,
+                CONFIG_VERSION
+            ),
+            format!(
+                "The server upgrades it on the next start and keeps a backup: {}",
+                upgrade.changes.join("; ")
+            ),
+        )),
+        None => checks.push(DoctorCheck::pass(
+            NAME,
+            format!("{} parsed", config_path.display()),
+        )),
+    }
+    Some(config)
+}
+
+fn check_data_dir(data_dir: &Path, checks: &mut Vec<DoctorCheck>) -> bool {
+    const NAME: &str = "Data directory";
+
+    if !data_dir.exists() {
+        checks.push(DoctorCheck::warn(
+            NAME,
+            format!("{} does not exist yet", data_dir.display()),
+            "It is created on first start; pass --data-dir if the server uses another path",
+        ));
+        return false;
+    }
+    if !data_dir.is_dir() {
+        checks.push(DoctorCheck::fail(
+            NAME,
+            format!("{} is not a directory", data_dir.display()),
+            "Point --data-dir at a directory",
+        ));
+        return false;
+    }
+
+    let probe = data_dir.join(".doctor_probe");
+    match std::fs::write(&probe, b"probe") {
+        Ok(()) => {
+            let _ = std::fs::remove_file(&probe);
+            checks.push(DoctorCheck::pass(
+                NAME,
+                format!("{} is writable", data_dir.display()),
+            ));
+        }
+        Err(e) => {
+            checks.push(DoctorCheck::fail(
+                NAME,
+                format!("{} is not writable: {}", data_dir.display(), e),
+                "Give the service user write access to the data directory",
+            ));
+            return false;
+        }
+    }
+
+    const SPACE: &str = "Free space";
+    match (fs2::available_space(data_dir), fs2::total_space(data_dir)) {
+        (Ok(available), Ok(total)) => {
+            let detail = format!(
+                "{:.1} GiB available of {:.1} GiB",
+                available as f64 / 1073741824.0,
+                total as f64 / 1073741824.0
+            );
+            if available < MIN_FREE_BYTES || (available as f64) < total as f64 * MIN_FREE_RATIO {
+                checks.push(DoctorCheck::warn(
+                    SPACE,
+                    detail,
+                    "Free up disk space or move the data directory; flushes and compactions need headroom",
+                ));
+            } else {
+                checks.push(DoctorCheck::pass(SPACE, detail));
+            }
+        }
+        (Err(e), _) | (_, Err(e)) => checks.push(DoctorCheck::warn(
+            SPACE,
+            format!("could not determine free space: {}", e),
+            "Check the volume manually with `df`",
+        )),
+    }
+
+    true
+}
+
+fn check_storage(
+    config_path: &Path,
+    data_dir: &Path,
+    config: Option<&ConfigFile>,
+    checks: &mut Vec<DoctorCheck>,
+) {
+    // A server may have the directory open, so nothing is replayed or
+    // written back.
+    let velocity_config = VelocityConfig {
+        read_only: true,
+        wal_recovery: WalRecovery::Skip,
+        background_threads: false,
+        ..config.map(|c| c.velocity_config()).unwrap_or_default()
+    };
+    let db = match Velocity::open_with_config(data_dir, velocity_config) {
+        Ok(db) => db,
+        Err(e) => {
+            checks.push(DoctorCheck::fail(
+                "Storage",
+                format!("cannot open {}: {}", data_dir.display(), e),
+                "Inspect the server log; restore the data directory from a backup if it is damaged",
+            ));
+            return;
+        }
+    };
+
+    match db.wal_integrity_report() {
+        Ok(report) if report.corrupted_records > 0 => checks.push(DoctorCheck::fail(
+            "WAL",
+            format!(
+                "{} of {} records fail their checksum",
+                report.corrupted_records, report.total_records
+            ),
+            "Corrupted records are skipped during replay; restore from a backup if they matter",
+        )),
+        Ok(report) if report.truncated_records > 0 => checks.push(DoctorCheck::warn(
+            "WAL",
+            format!(
+                "{} records, trailing record truncated",
+                report.total_records
+            ),
+            "Usually a torn write from a crash; the partial record is ignored on replay",
+        )),
+        Ok(report) => checks.push(DoctorCheck::pass(
+            "WAL",
+            format!("{} records verified", report.total_records),
+        )),
+        Err(e) => checks.push(DoctorCheck::fail(
+            "WAL",
+            format!("cannot verify: {}", e),
+            "Check that velocity.wal is readable",
+        )),
+    }
+
+    match db.sstable_integrity_report() {
+        Ok(report) if report.damaged_tables.is_empty() => checks.push(DoctorCheck::pass(
+            "SSTables",
+            format!(
+                "{} tables, {} entries verified",
+                report.tables_checked, report.total_entries
+            ),
+        )),
+        Ok(report) => {
+            let damaged = report
+                .damaged_tables
+                .iter()
+                .map(|(path, problem)| format!("{} ({})", path.display(), problem))
+                .collect::<Vec<_>>()
+                .join(", ");
+            checks.push(DoctorCheck::fail(
+                "SSTables",
+                format!(
+                    "{} of {} tables damaged: {}",
+                    report.damaged_tables.len(),
+                    report.tables_checked,
+                    damaged
+                ),
+                "Restore the listed files from a backup before starting the server",
+            ));
+        }
+        Err(e) => checks.push(DoctorCheck::fail(
+            "SSTables",
+            format!("cannot verify: {}", e),
+            "Check the permissions of the sstable_*.vdb files",
+        )),
+    }
+
+    let manager = DatabaseManager::new(db, config_path.to_path_buf());
+    let report = analyze_system(config_path, &manager);
+    if report.findings.is_empty() {
+        checks.push(DoctorCheck::pass(
+            "Analysis",
+            format!("score {}/100", report.score),
+        ));
+    }
+    for finding in report.findings {
+        checks.push(DoctorCheck {
+            name: "Analysis",
+            status: match finding.severity {
+                Severity::Critical => CheckStatus::Fail,
+                Severity::Warning | Severity::Info => CheckStatus::Warn,
+            },
+            detail: format!("{} [{}]", finding.message, finding.rule),
+            hint: Some(finding.remediation.to_string()),
+        });
+    }
+}
+
+fn check_port(bind_address: &str, checks: &mut Vec<DoctorCheck>) {
+    const NAME: &str = "Port";
+
+    match TcpListener::bind(bind_address) {
+        Ok(_) => checks.push(DoctorCheck::pass(
+            NAME,
+            format!("{} is available", bind_address),
+        )),
+        Err(e) if e.kind() == std::io::ErrorKind::AddrInUse => checks.push(DoctorCheck::warn(
+            NAME,
+            format!("{} is already in use", bind_address),
+            "Expected if the server is running; otherwise stop the other process or change bind_address",
+        )),
+        Err(e) => checks.push(DoctorCheck::fail(
+            NAME,
+            format!("cannot bind {}: {}", bind_address, e),
+            "Check bind_address; ports below 1024 need elevated privileges",
+        )),
+    }
+}
+
+fn check_tls(config: &ConfigFile, checks: &mut Vec<DoctorCheck>) {
+    const NAME: &str = "TLS";
+
+    let (Some(cert_path), Some(key_path)) = (&config.server.cert_path, &config.server.key_path)
+    else {
+        checks.push(DoctorCheck::fail(
+            NAME,
+            "enable_tls is set but cert_path or key_path is missing",
+            "Set both cert_path and key_path under [server]",
+        ));
+        return;
+    };
+    if !Path::new(key_path).exists() {
+        checks.push(DoctorCheck::fail(
+            NAME,
+            format!("private key {} not found", key_path),
+            "Fix key_path or generate a new key pair",
+        ));
+    }
+
+    let certs = File::open(cert_path)
+        .map_err(|e| e.to_string())
+        .and_then(|file| {
+            rustls_pemfile::certs(&mut BufReader::new(file)).map_err(|e| e.to_string())
+        });
+    let certs = match certs {
+        Ok(certs) if !certs.is_empty() => certs,
+        Ok(_) => {
+            checks.push(DoctorCheck::fail(
+                NAME,
+                format!("no certificates found in {}", cert_path),
+                "cert_path must contain a PEM encoded certificate chain",
+            ));
+            return;
+        }
+        Err(e) => {
+            checks.push(DoctorCheck::fail(
+                NAME,
+                format!("cannot read {}: {}", cert_path, e),
+                "Fix cert_path or its file permissions",
+            ));
+            return;
+        }
+    };
+
+    let Ok((_, cert)) = X509Certificate::from_der(&certs[0]) else {
+        checks.push(DoctorCheck::fail(
+            NAME,
+            format!("{} does not contain a valid X.509 certificate", cert_path),
+            "Re-export the certificate in PEM format",
+        ));
+        return;
+    };
+
+    let now = chrono::Utc::now().timestamp();
+    let validity = cert.validity();
+    let days_left = (validity.not_after.timestamp() - now) / 86_400;
+    if validity.not_before.timestamp() > now {
+        checks.push(DoctorCheck::fail(
+            NAME,
+            format!("certificate is not valid until {}", validity.not_before),
+            "Wait for the certificate to become valid or check the system clock",
+        ));
+    } else if validity.not_after.timestamp() <= now {
+        checks.push(DoctorCheck::fail(
+            NAME,
+            format!("certificate expired on {}", validity.not_after),
+            "Renew the certificate; clients will refuse the handshake",
+        ));
+    } else if days_left < CERT_EXPIRY_WARNING_DAYS {
+        checks.push(DoctorCheck::warn(
+            NAME,
+            format!("certificate expires in {} days ({})", days_left, validity.not_after),
+            "Renew the certificate; the server reloads it on the next connection",
+        ));
+    } else {
+        checks.push(DoctorCheck::pass(
+            NAME,
+            format!("certificate valid for {} more days", days_left),
+        ));
+    }
+}
+
+fn check_clock(data_dir: &Path, checks: &mut Vec<DoctorCheck>) {
+    const NAME: &str = "Clock";
+
+    let now = SystemTime::now();
+    let since_epoch = now
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    if since_epoch < EARLIEST_SANE_TIMESTAMP {
+        checks.push(DoctorCheck::fail(
+            NAME,
+            format!("system time {} looks wrong", chrono::Local::now()),
+            "Synchronise the clock (NTP); backup schedules and TLS validation depend on it",
+        ));
+        return;
+    }
+
+    let future_files = std::fs::read_dir(data_dir)
+        .into_iter()
+        .flatten()
+        .flatten()
+        .filter_map(|entry| entry.metadata().ok()?.modified().ok())
+        .filter(|modified| {
+            modified
+                .duration_since(now)
+                .is_ok_and(|ahead| ahead > CLOCK_SKEW_TOLERANCE)
+        })
+        .count();
+    if future_files > 0 {
+        checks.push(DoctorCheck::warn(
+            NAME,
+            format!(
+                "{} files in the data directory are dated in the future",
+                future_files
+            ),
+            "The clock may have jumped backwards; check NTP before relying on backup schedules",
+        ));
+    } else {
+        checks.push(DoctorCheck::pass(
+            NAME,
+            format!("system time {}", chrono::Local::now().format("%Y-%m-%d %H:%M:%S %z")),
+        ));
+    }
+}
@@ -0,0 +1,58 @@
+//! `DRY RUN <command>` reports what DROP DATABASE, PURGE TRASH or DELETE
+//! would remove without changing anything.
+
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+const DRY_RUN_PREFIX: &str = "DRY RUN ";
+
+// Names listed in a report; the key and byte counts cover everything.
+const NAME_LIMIT: usize = 1000;
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct DryRunReport {
+    /// Databases or keys that would be removed, in order, up to 1000.
+    pub names: Vec<String>,
+    pub keys: u64,
+    pub bytes: u64,
+    /// More names would be removed than are listed.
+    pub truncated: bool,
+}
+
+impl DryRunReport {
+    pub(crate) fn add(&mut self, name: &str, keys: u64, bytes: u64) {
+        self.keys += keys;
+        self.bytes += bytes;
+        if self.names.len() < NAME_LIMIT {
+            self.names.push(name.to_string());
+        } else {
+            self.truncated = true;
+        }
+    }
+}
+
+/// The command after a leading `DRY RUN`, if it has one.
+pub fn strip_dry_run(command: &str) -> Option<&str> {
+    let command = command.trim_start();
+    let prefix = command.get(..DRY_RUN_PREFIX.len())?;
+    if !prefix.eq_ignore_ascii_case(DRY_RUN_PREFIX) {
+        return None;
+    }
+    Some(command[DRY_RUN_PREFIX.len()..].trim())
+}
+
+/// Bytes of every file under `path`.
+pub(crate) fn tree_bytes(path: &Path) -> u64 {
+    let Ok(entries) = std::fs::read_dir(path) else {
+        return 0;
+    };
+    entries
+        .flatten()
+        .map(|entry| match entry.metadata() {
+            Ok(meta) if meta.is_dir() => tree_bytes(&entry.path()),
+            Ok(meta) => meta.len(),
+            Err(_) => 0,
+        })
+        .sum()
+}
@@ -0,0 +1,96 @@
+use std::io;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{VeloError, VeloResult};
+
+/// What to do to one kind of storage operation. Probabilities are in
+/// `0.0..=1.0` and checked independently for every operation.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct FaultSpec {
+    #[serde(default)]
+    pub latency_ms: u64,
+    #[serde(default)]
+    pub latency_probability: f64,
+    #[serde(default)]
+    pub error_probability: f64,
+}
+
+/// Degrades storage on purpose so applications can be tested against slow or
+/// failing disks. Never enable this in production.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct FaultInjectionConfig {
+    /// Point and range reads, whether answered from the cache, the memtable
+    /// or an SSTable.
+    #[serde(default)]
+    pub read: FaultSpec,
+    /// Puts, deletes and batches. A failed write is not applied.
+    #[serde(default)]
+    pub wal_append: FaultSpec,
+    /// WAL syncs and the fsync of a flushed SSTable.
+    #[serde(default)]
+    pub fsync: FaultSpec,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum StorageOp {
+    Read,
+    WalAppend,
+    Fsync,
+}
+
+impl StorageOp {
+    fn name(self) -> &'static str {
+        match self {
+            StorageOp::Read => "read",
+            StorageOp::WalAppend => "WAL append",
+            StorageOp::Fsync => "fsync",
+        }
+    }
+}
+
+impl FaultInjectionConfig {
+    pub fn validate(&self) -> VeloResult<()> {
+        for (name, spec) in [
+            ("read", &self.read),
+            ("wal_append", &self.wal_append),
+            ("fsync", &self.fsync),
+        ] {
+            for probability in [spec.latency_probability, spec.error_probability] {
+                if !(0.0..=1.0).contains(&probability) {
+                    return Err(VeloError::InvalidOperation(format!(
+                        "Fault injection probability for {} must be between 0 and 1, got {}",
+                        name, probability
+                    )));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Sleeps and/or fails according to the spec for `op`.
+    pub(crate) fn inject(&self, op: StorageOp) -> VeloResult<()> {
+        let spec = match op {
+            StorageOp::Read => &self.read,
+            StorageOp::WalAppend => &self.wal_append,
+            StorageOp::Fsync => &self.fsync,
+        };
+
+        if spec.latency_ms > 0 && roll(spec.latency_probability) {
+            std::thread::sleep(Duration::from_millis(spec.latency_ms));
+        }
+        if roll(spec.error_probability) {
+            log::debug!("Injecting {} failure", op.name());
+            return Err(VeloError::IoError(io::Error::other(format!(
+                "injected {} failure",
+                op.name()
+            ))));
+        }
+        Ok(())
+    }
+}
+
+fn roll(probability: f64) -> bool {
+    probability > 0.0 && crate::hashing::random_f64() < probability
+}
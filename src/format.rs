@@ -0,0 +1,194 @@
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::addon::DatabaseAddonConfig;
+use crate::layout::{DataLayout, CORRUPT_DIR, META_DIR, SST_DIR, TMP_DIR, WAL_DIR};
+use crate::{fsutil, VeloError, VeloResult, Velocity};
+
+/// The on-disk format of a database, kept in its meta directory.
+pub const FORMAT_FILE: &str = "format.json";
+
+/// The format this binary writes.
+pub const FORMAT_VERSION: u32 = 1;
+/// The oldest format this binary opens. Databases written before the format
+/// file existed count as version 1.
+pub const MIN_FORMAT_VERSION: u32 = 1;
+
+/// SSTables whose values are zstd-compressed.
+pub const FEATURE_ZSTD_VALUES: &str = "zstd_values";
+/// Values that carry an expiry header from a TTL key policy.
+pub const FEATURE_VALUE_EXPIRY: &str = "value_expiry";
+
+pub const SUPPORTED_FEATURES: &[&str] = &[FEATURE_ZSTD_VALUES, FEATURE_VALUE_EXPIRY];
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FormatInfo {
+    pub format_version: u32,
+    /// Encodings that have been written to the database. A binary has to
+    /// support all of them to read it.
+    #[serde(default)]
+    pub features: Vec<String>,
+    /// The release that last opened the database for writing.
+    #[serde(default)]
+    pub written_by: String,
+}
+
+impl FormatInfo {
+    /// Reads the format file of the database at `root` in either layout.
+    /// Returns `None` for a database that has none.
+    pub fn read<P: AsRef<Path>>(root: P) -> VeloResult<Option<FormatInfo>> {
+        let root = root.as_ref();
+        for path in [root.join(META_DIR).join(FORMAT_FILE), root.join(FORMAT_FILE)] {
+            let Ok(content) = std::fs::read_to_string(&path) else {
+                continue;
+            };
+            return serde_json::from_str(&content).map(Some).map_err(|e| {
+                VeloError::CorruptedData(format!("Unreadable format file {:?}: {}", path, e))
+            });
+        }
+        Ok(None)
+    }
+
+    /// Why this binary cannot open the database, with a hint on what to do
+    /// instead.
+    pub fn problem(&self) -> Option<String> {
+        let writer = match self.written_by.as_str() {
+            "" => String::new(),
+            version => format!(" (written by velocity {})", version),
+        };
+        if self.format_version > FORMAT_VERSION {
+            return Some(format!(
+                "Database format version {}{} is newer than this binary supports ({}-{}); \
+                 run the newer release or restore a backup taken before the upgrade",
+                self.format_version, writer, MIN_FORMAT_VERSION, FORMAT_VERSION
+            ));
+        }
+        if self.format_version < MIN_FORMAT_VERSION {
+            return Some(format!(
+                "Database format version {}{} is older than this binary supports ({}-{}); \
+                 open it once with an intermediate release to migrate it",
+                self.format_version, writer, MIN_FORMAT_VERSION, FORMAT_VERSION
+            ));
+        }
+        let unknown: Vec<&str> = self
+            .features
+            .iter()
+            .map(String::as_str)
+            .filter(|feature| !SUPPORTED_FEATURES.contains(feature))
+            .collect();
+        if !unknown.is_empty() {
+            return Some(format!(
+                "Database uses features this binary does not support{}: {}; \
+                 run a release that supports them",
+                writer,
+                unknown.join(", ")
+            ));
+        }
+        None
+    }
+
+    pub fn check(&self) -> VeloResult<()> {
+        match self.problem() {
+            Some(problem) => Err(VeloError::InvalidOperation(problem)),
+            None => Ok(()),
+        }
+    }
+}
+
+/// The data directory, the databases kept inside it such as the system
+/// database and, with the database addon config, the named databases under
+/// its `default_path` and at their configured paths.
+pub fn database_dirs<P: AsRef<Path>>(
+    data_dir: P,
+    addon: Option<&DatabaseAddonConfig>,
+) -> Vec<PathBuf> {
+    let data_dir = data_dir.as_ref();
+    let mut dirs = vec![data_dir.to_path_buf()];
+    dirs.extend(nested_databases(data_dir));
+    if let Some(addon) = addon.filter(|addon| addon.enabled) {
+        dirs.extend(nested_databases(&addon.default_path));
+        let mut named: Vec<PathBuf> = addon
+            .databases
+            .values()
+            .filter(|path| is_database(path))
+            .cloned()
+            .collect();
+        named.sort();
+        dirs.extend(named);
+    }
+    let mut seen = std::collections::HashSet::new();
+    dirs.retain(|dir| seen.insert(dir.canonicalize().unwrap_or_else(|_| dir.clone())));
+    dirs
+}
+
+fn nested_databases(dir: &Path) -> Vec<PathBuf> {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return Vec::new();
+    };
+    let mut nested: Vec<PathBuf> = entries
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|path| path.is_dir())
+        .filter(|path| {
+            !path
+                .file_name()
+                .and_then(|name| name.to_str())
+                .is_some_and(|name| [WAL_DIR, SST_DIR, META_DIR, TMP_DIR, CORRUPT_DIR].contains(&name))
+        })
+        .filter(|path| is_database(path))
+        .collect();
+    nested.sort();
+    nested
+}
+
+fn is_database(path: &Path) -> bool {
+    DataLayout::has_data(path) || matches!(FormatInfo::read(path), Ok(Some(_)))
+}
+
+/// Fails if this binary cannot open one of the databases [`database_dirs`]
+/// lists, before anything in them is touched.
+pub fn check_data_dir<P: AsRef<Path>>(
+    data_dir: P,
+    addon: Option<&DatabaseAddonConfig>,
+) -> VeloResult<()> {
+    for dir in database_dirs(data_dir, addon) {
+        if let Some(problem) = FormatInfo::read(&dir)?.and_then(|format| format.problem()) {
+            return Err(VeloError::InvalidOperation(format!("{}: {}", dir.display(), problem)));
+        }
+    }
+    Ok(())
+}
+
+impl Velocity {
+    // Features are never removed: tables written while a feature was on
+    // keep using it after the setting is turned off.
+    pub(crate) fn record_format(&self, previous: Option<FormatInfo>) -> VeloResult<()> {
+        let mut features = previous
+            .as_ref()
+            .map(|format| format.features.clone())
+            .unwrap_or_default();
+        if self.compression_plan().any() {
+            features.push(FEATURE_ZSTD_VALUES.to_string());
+        }
+        if self.config.key_policies.iter().any(|policy| policy.ttl_secs.is_some()) {
+            features.push(FEATURE_VALUE_EXPIRY.to_string());
+        }
+        features.sort();
+        features.dedup();
+
+        let format = FormatInfo {
+            format_version: FORMAT_VERSION,
+            features,
+            written_by: env!("CARGO_PKG_VERSION").to_string(),
+        };
+        if previous.as_ref() == Some(&format) {
+            return Ok(());
+        }
+        let content = serde_json::to_string_pretty(&format).map_err(|e| {
+            VeloError::InvalidOperation(format!("Failed to encode format: {}", e))
+        })?;
+        fsutil::atomic_write(self.layout.meta_dir().join(FORMAT_FILE), content)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,91 @@
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Replaces `path` with `contents` so that readers, and the file after a
+/// crash, see either the old or the new contents and never a torn mix. The
+/// data goes to `<name>.<pid>.<n>.tmp` next to the target, is fsynced, and
+/// is then renamed over it. An existing file's permissions are carried over.
+pub fn atomic_write<P: AsRef<Path>, C: AsRef<[u8]>>(path: P, contents: C) -> io::Result<()> {
+    write_and_rename(path.as_ref(), contents.as_ref(), None)
+}
+
+/// Like [`atomic_write`], but the file is only readable by its owner on Unix.
+pub fn atomic_write_private<P: AsRef<Path>, C: AsRef<[u8]>>(
+    path: P,
+    contents: C,
+) -> io::Result<()> {
+    write_and_rename(path.as_ref(), contents.as_ref(), Some(0o600))
+}
+
+fn write_and_rename(path: &Path, contents: &[u8], mode: Option<u32>) -> io::Result<()> {
+    let tmp_path = temp_path(path)?;
+
+    let result = (|| {
+        let mut options = OpenOptions::new();
+        options.write(true).create(true).truncate(true);
+        #[cfg(unix)]
+        if let Some(mode) = mode {
+            use std::os::unix::fs::OpenOptionsExt;
+            options.mode(mode);
+        }
+        #[cfg(not(unix))]
+        let _ = mode;
+
+        let mut file = options.open(&tmp_path)?;
+        if mode.is_none() {
+            if let Ok(existing) = fs::metadata(path) {
+                file.set_permissions(existing.permissions())?;
+            }
+        }
+        file.write_all(contents)?;
+        file.sync_all()?;
+        drop(file);
+        fs::rename(&tmp_path, path)
+    })();
+    if result.is_err() {
+        let _ = fs::remove_file(&tmp_path);
+    }
+    result?;
+
+    sync_parent(path);
+    Ok(())
+}
+
+// Each call gets its own name, so concurrent writers of the same target never
+// share, truncate or rename each other's temporary file.
+fn temp_path(path: &Path) -> io::Result<PathBuf> {
+    static NEXT: AtomicU64 = AtomicU64::new(0);
+    let name = path.file_name().ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("{:?} has no file name", path),
+        )
+    })?;
+    let mut tmp_name = name.to_os_string();
+    tmp_name.push(format!(
+        ".{}.{}.tmp",
+        std::process::id(),
+        NEXT.fetch_add(1, Ordering::Relaxed)
+    ));
+    Ok(path.with_file_name(tmp_name))
+}
+
+/// Persists a rename into `path`'s directory. Directories cannot be opened
+/// this way on Windows, where the rename is already durable once it returns.
+pub fn sync_parent(path: &Path) {
+    #[cfg(unix)]
+    if let Some(parent) = path.parent() {
+        let parent = if parent.as_os_str().is_empty() {
+            Path::new(".")
+        } else {
+            parent
+        };
+        if let Ok(dir) = fs::File::open(parent) {
+            let _ = dir.sync_all();
+        }
+    }
+    #[cfg(not(unix))]
+    let _ = path;
+}
@@ -0,0 +1,133 @@
+use std::collections::hash_map::{DefaultHasher, RandomState};
+use std::hash::{BuildHasher, Hasher};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Mutex;
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+static DETERMINISTIC: AtomicBool = AtomicBool::new(false);
+static SEED: AtomicU64 = AtomicU64::new(0);
+static RNG: Mutex<Option<StdRng>> = Mutex::new(None);
+
+const FNV_PRIME: u64 = 0x0000_0100_0000_01b3;
+
+/// Makes hashing and random choices in the engine reproducible for the rest
+/// of the process: bloom filters and the cache index hash with `seed`, and
+/// fault injection draws from a generator seeded with it instead of the OS.
+/// Call it before opening a database; filters built earlier keep the seed
+/// they were built with.
+pub fn set_deterministic_seed(seed: u64) {
+    SEED.store(seed, Ordering::Relaxed);
+    *RNG.lock().unwrap() = Some(StdRng::seed_from_u64(seed));
+    DETERMINISTIC.store(true, Ordering::Release);
+}
+
+pub fn deterministic_seed() -> Option<u64> {
+    DETERMINISTIC
+        .load(Ordering::Acquire)
+        .then(|| SEED.load(Ordering::Relaxed))
+}
+
+// The seed new bloom filters hash with. Without deterministic mode it is 0,
+// so filters are still the same from run to run.
+pub(crate) fn bloom_seed() -> u64 {
+    SEED.load(Ordering::Relaxed)
+}
+
+/// A 64-bit hash of `bytes` under `seed`. Unlike the standard library's
+/// hasher, its output is fixed and will not change between Rust releases.
+pub fn hash64(seed: u64, bytes: &[u8]) -> u64 {
+    let mut hasher = SeededHasher::new(seed);
+    hasher.write(bytes);
+    hasher.finish()
+}
+
+fn mix(mut x: u64) -> u64 {
+    x = (x ^ (x >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    x ^ (x >> 31)
+}
+
+/// FNV-1a over the input, started from and finished with a 64-bit mix so
+/// nearby seeds give unrelated hashes.
+pub struct SeededHasher {
+    state: u64,
+}
+
+impl SeededHasher {
+    pub fn new(seed: u64) -> Self {
+        Self {
+            state: mix(seed.wrapping_add(0x9e37_79b9_7f4a_7c15)),
+        }
+    }
+}
+
+impl Hasher for SeededHasher {
+    fn write(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.state = (self.state ^ byte as u64).wrapping_mul(FNV_PRIME);
+        }
+    }
+
+    fn finish(&self) -> u64 {
+        mix(self.state)
+    }
+}
+
+/// Hasher state for the engine's in-memory tables: randomly keyed as usual,
+/// or keyed by the seed in deterministic mode.
+#[derive(Clone)]
+pub enum HashState {
+    Random(RandomState),
+    Seeded(u64),
+}
+
+impl Default for HashState {
+    fn default() -> Self {
+        match deterministic_seed() {
+            Some(seed) => HashState::Seeded(seed),
+            None => HashState::Random(RandomState::new()),
+        }
+    }
+}
+
+impl BuildHasher for HashState {
+    type Hasher = StateHasher;
+
+    fn build_hasher(&self) -> StateHasher {
+        match self {
+            HashState::Random(state) => StateHasher::Random(state.build_hasher()),
+            HashState::Seeded(seed) => StateHasher::Seeded(SeededHasher::new(*seed)),
+        }
+    }
+}
+
+pub enum StateHasher {
+    Random(DefaultHasher),
+    Seeded(SeededHasher),
+}
+
+impl Hasher for StateHasher {
+    fn write(&mut self, bytes: &[u8]) {
+        match self {
+            StateHasher::Random(hasher) => hasher.write(bytes),
+            StateHasher::Seeded(hasher) => hasher.write(bytes),
+        }
+    }
+
+    fn finish(&self) -> u64 {
+        match self {
+            StateHasher::Random(hasher) => hasher.finish(),
+            StateHasher::Seeded(hasher) => hasher.finish(),
+        }
+    }
+}
+
+// A uniform value in [0, 1), from the seeded generator in deterministic mode.
+pub(crate) fn random_f64() -> f64 {
+    match RNG.lock().unwrap().as_mut() {
+        Some(rng) => rng.gen(),
+        None => rand::random(),
+    }
+}
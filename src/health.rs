@@ -0,0 +1,118 @@
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use axum::{http::StatusCode, routing::get, Router};
+
+use crate::server::{ServerState, StartupStatus};
+use crate::{VeloError, VeloResult};
+
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(1);
+const HEARTBEAT_STALE_AFTER: Duration = Duration::from_secs(10);
+
+/// Liveness and readiness as seen by an orchestrator. Readiness follows the
+/// server state and turns false as soon as shutdown begins; liveness follows
+/// a heartbeat task, so a wedged runtime stops reporting live.
+#[derive(Clone)]
+pub struct HealthProbe {
+    startup: StartupStatus,
+    started: Instant,
+    heartbeat_ms: Arc<AtomicU64>,
+    shutting_down: Arc<AtomicBool>,
+}
+
+impl HealthProbe {
+    pub fn new(startup: StartupStatus) -> Self {
+        Self {
+            startup,
+            started: Instant::now(),
+            heartbeat_ms: Arc::new(AtomicU64::new(0)),
+            shutting_down: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    pub fn start_heartbeat(&self) {
+        let probe = self.clone();
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(HEARTBEAT_INTERVAL);
+            loop {
+                ticker.tick().await;
+                probe
+                    .heartbeat_ms
+                    .store(probe.started.elapsed().as_millis() as u64, Ordering::Release);
+            }
+        });
+    }
+
+    pub fn is_live(&self) -> bool {
+        let last = Duration::from_millis(self.heartbeat_ms.load(Ordering::Acquire));
+        self.started.elapsed().saturating_sub(last) < HEARTBEAT_STALE_AFTER
+    }
+
+    pub fn is_ready(&self) -> bool {
+        !self.shutting_down.load(Ordering::Acquire) && self.startup.state() == ServerState::Ready
+    }
+
+    /// Fails readiness ahead of the listener closing, so load balancers stop
+    /// routing new connections while existing ones are still served.
+    pub fn mark_shutting_down(&self) {
+        self.shutting_down.store(true, Ordering::Release);
+    }
+
+    /// Serves `GET /livez`, `GET /readyz` and `GET /storagez` (200 or 503)
+    /// on `addr`. `/storagez` fails while startup verification has
+    /// quarantined any SSTable and lists them.
+    pub async fn serve(self, addr: SocketAddr) -> VeloResult<()> {
+        let live = self.clone();
+        let ready = self.clone();
+        let storage = self.clone();
+        let app = Router::new()
+            .route(
+                "/livez",
+                get(move || async move { status(live.is_live(), "live") }),
+            )
+            .route(
+                "/readyz",
+                get(move || async move {
+                    let state = ready.startup.state();
+                    if ready.is_ready() {
+                        status(true, "ready")
+                    } else if ready.shutting_down.load(Ordering::Acquire) {
+                        status(false, "shutting down")
+                    } else {
+                        status(false, &format!("{:?}", state).to_lowercase())
+                    }
+                }),
+            )
+            .route(
+                "/storagez",
+                get(move || async move {
+                    let quarantined = storage.startup.quarantined();
+                    if quarantined.is_empty() {
+                        return status(true, "ok");
+                    }
+                    let lines: Vec<String> = quarantined
+                        .iter()
+                        .map(|table| format!("quarantined {}: {}", table.path.display(), table.reason))
+                        .collect();
+                    status(false, &lines.join("\n"))
+                }),
+            );
+
+        let listener = tokio::net::TcpListener::bind(addr).await?;
+        log::info!("Health endpoints listening on {}", addr);
+        axum::serve(listener, app)
+            .await
+            .map_err(|e| VeloError::InvalidOperation(e.to_string()))
+    }
+}
+
+fn status(ok: bool, message: &str) -> (StatusCode, String) {
+    let code = if ok {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+    (code, format!("{}\n", message))
+}
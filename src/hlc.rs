@@ -0,0 +1,105 @@
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+
+const LOGICAL_BITS: u32 = 16;
+const LOGICAL_MASK: u64 = (1 << LOGICAL_BITS) - 1;
+
+/// A hybrid logical clock reading: wall-clock milliseconds in the upper 48
+/// bits and a logical counter in the lower 16. Readings compare as plain
+/// integers, across nodes as well as within one.
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize,
+)]
+pub struct HlcTimestamp(u64);
+
+impl HlcTimestamp {
+    pub const ZERO: HlcTimestamp = HlcTimestamp(0);
+
+    pub fn new(physical_ms: u64, logical: u16) -> Self {
+        Self((physical_ms << LOGICAL_BITS) | logical as u64)
+    }
+
+    pub fn from_u64(raw: u64) -> Self {
+        Self(raw)
+    }
+
+    pub fn as_u64(self) -> u64 {
+        self.0
+    }
+
+    pub fn physical_ms(self) -> u64 {
+        self.0 >> LOGICAL_BITS
+    }
+
+    pub fn logical(self) -> u16 {
+        (self.0 & LOGICAL_MASK) as u16
+    }
+}
+
+impl fmt::Display for HlcTimestamp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{:05}", self.physical_ms(), self.logical())
+    }
+}
+
+pub struct HybridClock {
+    last: AtomicU64,
+}
+
+impl HybridClock {
+    pub const fn new() -> Self {
+        Self {
+            last: AtomicU64::new(0),
+        }
+    }
+
+    /// Returns a timestamp greater than every one previously issued or
+    /// observed by this clock.
+    pub fn now(&self) -> HlcTimestamp {
+        let wall = HlcTimestamp::new(wall_clock_ms(), 0).as_u64();
+        let mut last = self.last.load(Ordering::Relaxed);
+        loop {
+            let next = wall.max(last + 1);
+            match self
+                .last
+                .compare_exchange_weak(last, next, Ordering::AcqRel, Ordering::Relaxed)
+            {
+                Ok(_) => return HlcTimestamp(next),
+                Err(current) => last = current,
+            }
+        }
+    }
+
+    /// Merges a timestamp received from another node (or read back from
+    /// disk) so later local readings order after it.
+    pub fn observe(&self, remote: HlcTimestamp) {
+        self.last.fetch_max(remote.as_u64(), Ordering::AcqRel);
+    }
+
+    pub fn last(&self) -> HlcTimestamp {
+        HlcTimestamp(self.last.load(Ordering::Relaxed))
+    }
+}
+
+impl Default for HybridClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+static CLOCK: HybridClock = HybridClock::new();
+
+/// The process-wide clock shared by every database in this node.
+pub fn clock() -> &'static HybridClock {
+    &CLOCK
+}
+
+fn wall_clock_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
@@ -0,0 +1,314 @@
+//! Sampled tracking of the most read and written keys of a database.
+//!
+//! Every `sample_every`-th access is counted in a count-min sketch, which
+//! estimates how often each key was seen in a fixed amount of memory. A key
+//! whose estimate beats the coldest tracked key replaces it in a short
+//! candidate list. Both start over with every interval, so a report shows
+//! what is hot now rather than since the database was opened. Estimates
+//! can only be too high, never too low.
+
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+
+use crate::hashing::hash64;
+
+const SKETCH_DEPTH: usize = 4;
+const SKETCH_WIDTH: usize = 1024;
+// Candidates kept per tracked key, so keys just below the top can still
+// climb into it.
+const CANDIDATES_PER_KEY: usize = 2;
+
+fn default_interval_seconds() -> u64 {
+    60
+}
+
+fn default_top_k() -> usize {
+    20
+}
+
+fn default_sample_every() -> u32 {
+    8
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HotKeyConfig {
+    #[serde(default)]
+    pub enabled: bool,
+    #[serde(default = "default_interval_seconds")]
+    pub interval_seconds: u64,
+    /// Keys reported for reads and for writes.
+    #[serde(default = "default_top_k")]
+    pub top_k: usize,
+    /// Count one in this many accesses; 1 counts every access.
+    #[serde(default = "default_sample_every")]
+    pub sample_every: u32,
+}
+
+impl Default for HotKeyConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            interval_seconds: default_interval_seconds(),
+            top_k: default_top_k(),
+            sample_every: default_sample_every(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Access {
+    Read,
+    Write,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct HotKey {
+    pub key: String,
+    /// Accesses in the interval, scaled up from the sampled ones.
+    pub estimated_count: u64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct HotKeyInterval {
+    /// Unix milliseconds.
+    pub started_at_ms: u64,
+    pub sampled_reads: u64,
+    pub sampled_writes: u64,
+    pub reads: Vec<HotKey>,
+    pub writes: Vec<HotKey>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct HotKeyReport {
+    pub interval_seconds: u64,
+    pub sample_every: u32,
+    /// The last interval that ended, if any has yet.
+    pub previous: Option<HotKeyInterval>,
+    pub current: HotKeyInterval,
+}
+
+impl HotKeyReport {
+    /// Drops keys under any of `prefixes` from every interval.
+    pub fn hide_prefixes(&mut self, prefixes: &[String]) {
+        let visible = |key: &HotKey| {
+            !prefixes
+                .iter()
+                .any(|prefix| key.key.starts_with(prefix.as_str()))
+        };
+        for interval in self.previous.iter_mut().chain([&mut self.current]) {
+            interval.reads.retain(visible);
+            interval.writes.retain(visible);
+        }
+    }
+}
+
+struct CountMinSketch {
+    counters: Vec<u32>,
+}
+
+impl CountMinSketch {
+    fn new() -> Self {
+        Self {
+            counters: vec![0; SKETCH_DEPTH * SKETCH_WIDTH],
+        }
+    }
+
+    // Conservative update: only the counters at the current minimum grow,
+    // which overestimates less than incrementing every row.
+    fn add(&mut self, key: &str) -> u32 {
+        let slots: [usize; SKETCH_DEPTH] = std::array::from_fn(|row| {
+            row * SKETCH_WIDTH + (hash64(row as u64, key.as_bytes()) % SKETCH_WIDTH as u64) as usize
+        });
+        let estimate = slots
+            .iter()
+            .map(|&slot| self.counters[slot])
+            .min()
+            .unwrap_or(0)
+            .saturating_add(1);
+        for slot in slots {
+            self.counters[slot] = self.counters[slot].max(estimate);
+        }
+        estimate
+    }
+}
+
+struct Tracker {
+    sketch: CountMinSketch,
+    candidates: HashMap<String, u32>,
+    capacity: usize,
+    sampled: u64,
+}
+
+impl Tracker {
+    fn new(top_k: usize) -> Self {
+        Self {
+            sketch: CountMinSketch::new(),
+            candidates: HashMap::new(),
+            capacity: top_k.max(1) * CANDIDATES_PER_KEY,
+            sampled: 0,
+        }
+    }
+
+    fn add(&mut self, key: &str) {
+        self.sampled += 1;
+        let estimate = self.sketch.add(key);
+        if let Some(count) = self.candidates.get_mut(key) {
+            *count = estimate;
+            return;
+        }
+        if self.candidates.len() >= self.capacity {
+            let Some((coldest, count)) = self
+                .candidates
+                .iter()
+                .min_by_key(|(_, count)| **count)
+                .map(|(key, count)| (key.clone(), *count))
+            else {
+                return;
+            };
+            if estimate <= count {
+                return;
+            }
+            self.candidates.remove(&coldest);
+        }
+        self.candidates.insert(key.to_string(), estimate);
+    }
+
+    fn top(&self, top_k: usize, scale: u32) -> Vec<HotKey> {
+        let mut keys: Vec<HotKey> = self
+            .candidates
+            .iter()
+            .map(|(key, count)| HotKey {
+                key: key.clone(),
+                estimated_count: *count as u64 * scale as u64,
+            })
+            .collect();
+        keys.sort_by(|a, b| {
+            b.estimated_count
+                .cmp(&a.estimated_count)
+                .then_with(|| a.key.cmp(&b.key))
+        });
+        keys.truncate(top_k);
+        keys
+    }
+}
+
+struct Interval {
+    started: Instant,
+    started_at_ms: u64,
+    reads: Tracker,
+    writes: Tracker,
+}
+
+impl Interval {
+    fn new(top_k: usize) -> Self {
+        Self {
+            started: Instant::now(),
+            started_at_ms: crate::now_millis(),
+            reads: Tracker::new(top_k),
+            writes: Tracker::new(top_k),
+        }
+    }
+
+    fn summary(&self, config: &HotKeyConfig) -> HotKeyInterval {
+        let scale = config.sample_every.max(1);
+        HotKeyInterval {
+            started_at_ms: self.started_at_ms,
+            sampled_reads: self.reads.sampled,
+            sampled_writes: self.writes.sampled,
+            reads: self.reads.top(config.top_k, scale),
+            writes: self.writes.top(config.top_k, scale),
+        }
+    }
+}
+
+struct State {
+    config: HotKeyConfig,
+    current: Interval,
+    previous: Option<HotKeyInterval>,
+}
+
+impl State {
+    fn new(config: &HotKeyConfig) -> Self {
+        Self {
+            config: config.clone(),
+            current: Interval::new(config.top_k),
+            previous: None,
+        }
+    }
+
+    fn rotate(&mut self) {
+        let length = Duration::from_secs(self.config.interval_seconds.max(1));
+        if self.current.started.elapsed() < length {
+            return;
+        }
+        let finished = std::mem::replace(&mut self.current, Interval::new(self.config.top_k));
+        self.previous = Some(finished.summary(&self.config));
+    }
+}
+
+/// Hot-key statistics of one database. While tracking is off, `record`
+/// only reads a flag.
+pub struct HotKeyTracker {
+    enabled: AtomicBool,
+    sample_every: AtomicU32,
+    accesses: AtomicU64,
+    state: Mutex<State>,
+}
+
+impl HotKeyTracker {
+    pub fn new(config: &HotKeyConfig) -> Self {
+        Self {
+            enabled: AtomicBool::new(config.enabled),
+            sample_every: AtomicU32::new(config.sample_every.max(1)),
+            accesses: AtomicU64::new(0),
+            state: Mutex::new(State::new(config)),
+        }
+    }
+
+    /// Replaces the settings and starts a new interval.
+    pub fn configure(&self, config: &HotKeyConfig) {
+        *self.state.lock().unwrap() = State::new(config);
+        self.sample_every
+            .store(config.sample_every.max(1), Ordering::Relaxed);
+        self.enabled.store(config.enabled, Ordering::Relaxed);
+    }
+
+    pub fn config(&self) -> HotKeyConfig {
+        self.state.lock().unwrap().config.clone()
+    }
+
+    pub fn record(&self, access: Access, key: &str) {
+        if !self.enabled.load(Ordering::Relaxed) {
+            return;
+        }
+        let every = self.sample_every.load(Ordering::Relaxed) as u64;
+        if !self.accesses.fetch_add(1, Ordering::Relaxed).is_multiple_of(every) {
+            return;
+        }
+        let mut state = self.state.lock().unwrap();
+        state.rotate();
+        match access {
+            Access::Read => state.current.reads.add(key),
+            Access::Write => state.current.writes.add(key),
+        }
+    }
+
+    /// `None` while tracking is off.
+    pub fn report(&self) -> Option<HotKeyReport> {
+        if !self.enabled.load(Ordering::Relaxed) {
+            return None;
+        }
+        let mut state = self.state.lock().unwrap();
+        state.rotate();
+        Some(HotKeyReport {
+            interval_seconds: state.config.interval_seconds,
+            sample_every: state.config.sample_every.max(1),
+            previous: state.previous.clone(),
+            current: state.current.summary(&state.config),
+        })
+    }
+}
@@ -0,0 +1,117 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::server::MessageType;
+
+pub const IDEMPOTENCY_HEADER: &str = "Idempotency-Key:";
+const MAX_TOKEN_LEN: usize = 128;
+
+/// Prefixes a command or batch-write payload with an idempotency token.
+pub fn with_idempotency_key(token: &str, payload: &[u8]) -> Vec<u8> {
+    [format!("{} {}\n", IDEMPOTENCY_HEADER, token).as_bytes(), payload].concat()
+}
+
+/// Splits an optional `Idempotency-Key: <token>` first line off a command
+/// payload.
+pub fn split_idempotency_key(payload: &[u8]) -> (Option<String>, &[u8]) {
+    let Some(rest) = payload.strip_prefix(IDEMPOTENCY_HEADER.as_bytes()) else {
+        return (None, payload);
+    };
+    let Some(end) = rest.iter().position(|&b| b == b'\n') else {
+        return (None, payload);
+    };
+    let token = String::from_utf8_lossy(&rest[..end]).trim().to_string();
+    if token.is_empty() || token.len() > MAX_TOKEN_LEN {
+        return (None, payload);
+    }
+    (Some(token), &rest[end + 1..])
+}
+
+pub enum Claim {
+    New,
+    InProgress,
+    Completed(MessageType, Vec<u8>),
+}
+
+enum Entry {
+    Pending(Instant),
+    Done(Instant, MessageType, Vec<u8>),
+}
+
+impl Entry {
+    fn created(&self) -> Instant {
+        match self {
+            Entry::Pending(at) | Entry::Done(at, ..) => *at,
+        }
+    }
+}
+
+pub struct IdempotencyCache {
+    window: Duration,
+    max_entries: usize,
+    entries: Mutex<HashMap<String, Entry>>,
+}
+
+impl IdempotencyCache {
+    pub fn new(window: Duration, max_entries: usize) -> Self {
+        Self {
+            window,
+            max_entries: max_entries.max(1),
+            entries: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        !self.window.is_zero()
+    }
+
+    /// Registers `key` as in flight unless a response for it was recorded
+    /// within the window.
+    pub fn claim(&self, key: &str) -> Claim {
+        let mut entries = self.entries.lock().unwrap();
+        let now = Instant::now();
+        if let Some(entry) = entries.get(key).filter(|e| now - e.created() < self.window) {
+            return match entry {
+                Entry::Pending(_) => Claim::InProgress,
+                Entry::Done(_, msg_type, payload) => Claim::Completed(*msg_type, payload.clone()),
+            };
+        }
+
+        if entries.len() >= self.max_entries {
+            entries.retain(|_, e| now - e.created() < self.window);
+        }
+        if entries.len() >= self.max_entries {
+            if let Some(oldest) = entries
+                .iter()
+                .min_by_key(|(_, e)| e.created())
+                .map(|(k, _)| k.clone())
+            {
+                entries.remove(&oldest);
+            }
+        }
+        entries.insert(key.to_string(), Entry::Pending(now));
+        Claim::New
+    }
+
+    pub fn complete(&self, key: &str, msg_type: MessageType, payload: Vec<u8>) {
+        let mut entries = self.entries.lock().unwrap();
+        let created = entries
+            .get(key)
+            .map(Entry::created)
+            .unwrap_or_else(Instant::now);
+        entries.insert(key.to_string(), Entry::Done(created, msg_type, payload));
+    }
+
+    pub fn release(&self, key: &str) {
+        self.entries.lock().unwrap().remove(key);
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
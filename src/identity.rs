@@ -0,0 +1,223 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use ed25519_dalek::{Signature, Signer, SigningKey, Verifier, VerifyingKey};
+use rand::rngs::OsRng;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::fsutil::atomic_write_private;
+use crate::{VeloError, VeloResult};
+
+pub const IDENTITY_FILE: &str = "server_identity.json";
+
+#[derive(Serialize, Deserialize)]
+struct IdentityFile {
+    secret_key: String,
+    created_at: u64,
+    #[serde(default)]
+    previous: Option<PreviousKeyFile>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct PreviousKeyFile {
+    secret_key: String,
+    expires_at: u64,
+}
+
+struct PreviousKey {
+    signing_key: SigningKey,
+    expires_at: u64,
+}
+
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IdentityProof {
+    pub fingerprint: String,
+    pub public_key: String,
+    pub signature: String,
+}
+
+pub struct ServerIdentity {
+    signing_key: SigningKey,
+    created_at: u64,
+    previous: Option<PreviousKey>,
+}
+
+impl ServerIdentity {
+    pub fn generate() -> Self {
+        Self {
+            signing_key: SigningKey::generate(&mut OsRng),
+            created_at: unix_now(),
+            previous: None,
+        }
+    }
+
+    pub fn path_in(data_dir: &Path) -> PathBuf {
+        data_dir.join(IDENTITY_FILE)
+    }
+
+    pub fn load_or_generate(path: &Path) -> VeloResult<Self> {
+        if path.exists() {
+            return Self::load(path);
+        }
+
+        let identity = Self::generate();
+        identity.save(path)?;
+        log::info!("Generated new server identity at {:?}", path);
+        Ok(identity)
+    }
+
+    pub fn load(path: &Path) -> VeloResult<Self> {
+        let content = fs::read_to_string(path)?;
+        let file: IdentityFile = serde_json::from_str(&content).map_err(|e| {
+            VeloError::CorruptedData(format!("Invalid identity file {:?}: {}", path, e))
+        })?;
+
+        let previous = match file.previous {
+            Some(previous) => Some(PreviousKey {
+                signing_key: decode_signing_key(&previous.secret_key)?,
+                expires_at: previous.expires_at,
+            }),
+            None => None,
+        };
+
+        Ok(Self {
+            signing_key: decode_signing_key(&file.secret_key)?,
+            created_at: file.created_at,
+            previous,
+        })
+    }
+
+    pub fn save(&self, path: &Path) -> VeloResult<()> {
+        let file = IdentityFile {
+            secret_key: to_hex(&self.signing_key.to_bytes()),
+            created_at: self.created_at,
+            previous: self.previous.as_ref().map(|previous| PreviousKeyFile {
+                secret_key: to_hex(&previous.signing_key.to_bytes()),
+                expires_at: previous.expires_at,
+            }),
+        };
+        let content = serde_json::to_string_pretty(&file).map_err(|e| {
+            VeloError::InvalidOperation(format!("Failed to encode identity: {}", e))
+        })?;
+
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        atomic_write_private(path, content)?;
+        Ok(())
+    }
+
+
+    pub fn rotate(&mut self, grace: Duration) {
+        let old_key = std::mem::replace(&mut self.signing_key, SigningKey::generate(&mut OsRng));
+        self.created_at = unix_now();
+        self.previous = if grace.is_zero() {
+            None
+        } else {
+            Some(PreviousKey {
+                signing_key: old_key,
+                expires_at: self.created_at + grace.as_secs(),
+            })
+        };
+    }
+
+    pub fn fingerprint(&self) -> String {
+        fingerprint_of(&self.signing_key.verifying_key())
+    }
+
+    pub fn created_at(&self) -> u64 {
+        self.created_at
+    }
+
+    pub fn previous_fingerprint(&self) -> Option<String> {
+        self.active_previous()
+            .map(|previous| fingerprint_of(&previous.signing_key.verifying_key()))
+    }
+
+    pub fn previous_expires_at(&self) -> Option<u64> {
+        self.active_previous().map(|previous| previous.expires_at)
+    }
+
+
+    pub fn prove(&self, nonce: &[u8]) -> Vec<IdentityProof> {
+        let mut proofs = vec![proof_for(&self.signing_key, nonce)];
+        if let Some(previous) = self.active_previous() {
+            proofs.push(proof_for(&previous.signing_key, nonce));
+        }
+        proofs
+    }
+
+    fn active_previous(&self) -> Option<&PreviousKey> {
+        self.previous
+            .as_ref()
+            .filter(|previous| previous.expires_at > unix_now())
+    }
+}
+
+impl IdentityProof {
+
+    pub fn verify(&self, nonce: &[u8]) -> bool {
+        let Some(key_bytes) = from_hex(&self.public_key).and_then(|b| <[u8; 32]>::try_from(b).ok())
+        else {
+            return false;
+        };
+        let Some(sig_bytes) = from_hex(&self.signature).and_then(|b| <[u8; 64]>::try_from(b).ok())
+        else {
+            return false;
+        };
+        let Ok(verifying_key) = VerifyingKey::from_bytes(&key_bytes) else {
+            return false;
+        };
+
+        fingerprint_of(&verifying_key) == self.fingerprint
+            && verifying_key
+                .verify(nonce, &Signature::from_bytes(&sig_bytes))
+                .is_ok()
+    }
+}
+
+fn proof_for(signing_key: &SigningKey, nonce: &[u8]) -> IdentityProof {
+    let verifying_key = signing_key.verifying_key();
+    IdentityProof {
+        fingerprint: fingerprint_of(&verifying_key),
+        public_key: to_hex(verifying_key.as_bytes()),
+        signature: to_hex(&signing_key.sign(nonce).to_bytes()),
+    }
+}
+
+fn fingerprint_of(verifying_key: &VerifyingKey) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(verifying_key.as_bytes());
+    format!("{:x}", hasher.finalize())
+}
+
+fn decode_signing_key(hex: &str) -> VeloResult<SigningKey> {
+    let bytes = from_hex(hex)
+        .and_then(|b| <[u8; 32]>::try_from(b).ok())
+        .ok_or_else(|| VeloError::CorruptedData("Invalid identity secret key".to_string()))?;
+    Ok(SigningKey::from_bytes(&bytes))
+}
+
+pub(crate) fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+pub(crate) fn from_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
@@ -0,0 +1,310 @@
+//! Offline inspection of a single SSTable file, for debugging tables that
+//! fail to load or that `verify` reported as damaged.
+//!
+//! The index and bloom filter are not stored in the file; they are rebuilt
+//! the way opening the database rebuilds them, so what is reported here is
+//! what the engine would see.
+
+use std::path::{Path, PathBuf};
+
+use serde::Serialize;
+
+use crate::compression::{self, Dictionary};
+use crate::layout::META_DIR;
+use crate::{
+    decode_len, BloomFilter, Manifest, VeloResult, Velocity, INDEX_INTERVAL, MANIFEST_FILE,
+};
+
+// Longer values are cut short in record dumps.
+const VALUE_PREVIEW_BYTES: usize = 256;
+
+/// The SSTable file format, as printed by `velocity ops inspect-sst
+/// --describe-format`.
+pub const SSTABLE_FORMAT: &str = "\
+SSTable files (sstable_NNNNNN.vdb)
+
+A table is a sequence of records sorted by key, with no footer:
+
+  key length    u16, little-endian
+  key           UTF-8 bytes
+  value length  u32, little-endian; bit 31 is set when the value starts
+                with a 24-byte metadata header (created_at, updated_at and
+                version, each a u64)
+  value         bytes; an empty value is a tombstone
+
+Tables written with compression start with a header: the bytes
+06 00 ff 7a 73 74 64 01, the length of the zstd dictionary as a u32 and the
+dictionary, empty when the values were compressed one by one. Read as a
+record those bytes would have a key that is not UTF-8, so a table without
+the header never starts with them. Every value in a compressed table begins
+with an encoding byte: 0 for a value stored as is, 1 for zstd followed by
+the raw length as a u32.
+
+The index (one entry every 16 records) and the bloom filter are rebuilt in
+memory when the table is loaded. The CRC32 of the whole file is kept in the
+manifest in the meta directory and checked when the database is opened.
+";
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SstHeader {
+    pub path: PathBuf,
+    /// From the file name, when it follows the `sstable_NNNNNN.vdb` pattern.
+    pub id: Option<u64>,
+    pub size_bytes: u64,
+    pub checksum: u32,
+    /// The checksum the manifest next to the file recorded when the table
+    /// was written, if it can be found.
+    pub recorded_checksum: Option<u32>,
+    pub compressed: bool,
+    pub dictionary_id: Option<u32>,
+    pub dictionary_bytes: usize,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SstProperties {
+    pub entries: usize,
+    pub tombstones: usize,
+    pub min_key: Option<String>,
+    pub max_key: Option<String>,
+    pub raw_value_bytes: u64,
+    pub stored_value_bytes: u64,
+    pub compression_ratio: f64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SstIndexEntry {
+    pub key: String,
+    pub offset: u64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct BloomSummary {
+    pub bit_count: usize,
+    pub hash_functions: usize,
+    pub bits_set: usize,
+    /// The chance that an absent key passes the filter, from how full it is.
+    pub estimated_false_positive_rate: f64,
+}
+
+/// Which records to dump: keys from `start` (inclusive) up to `end`
+/// (exclusive), at most `limit` of them.
+#[derive(Debug, Clone, Default)]
+pub struct RecordFilter {
+    pub start: Option<String>,
+    pub end: Option<String>,
+    pub limit: Option<usize>,
+}
+
+impl RecordFilter {
+    fn matches(&self, key: &str) -> bool {
+        self.start.as_deref().is_none_or(|start| key >= start)
+            && self.end.as_deref().is_none_or(|end| key < end)
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SstRecord {
+    pub offset: u64,
+    pub key: String,
+    /// Length of the value as stored, before decompression.
+    pub stored_len: usize,
+    pub tombstone: bool,
+    /// Whether the value starts with a record metadata header.
+    pub has_meta: bool,
+    /// The decoded value as text, or as hex when it is not UTF-8.
+    pub value: String,
+    pub value_encoding: &'static str,
+    pub value_len: usize,
+    pub truncated: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SstInspection {
+    pub header: SstHeader,
+    pub properties: SstProperties,
+    pub index: Vec<SstIndexEntry>,
+    pub bloom: BloomSummary,
+    /// The first problem a full verification found, if any. Everything
+    /// reported above covers the records before it.
+    pub problem: Option<String>,
+    /// Only filled in when records were asked for.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub records: Option<Vec<SstRecord>>,
+}
+
+fn sstable_id(path: &Path) -> Option<u64> {
+    path.file_stem()?
+        .to_str()?
+        .strip_prefix("sstable_")?
+        .parse()
+        .ok()
+}
+
+// The manifest sits beside the tables in the flat layout and in the
+// sibling meta directory in the split one.
+fn recorded_checksum(path: &Path, id: u64) -> Option<u32> {
+    let dir = path.parent()?;
+    let candidates = [
+        dir.join(MANIFEST_FILE),
+        dir.parent()?.join(META_DIR).join(MANIFEST_FILE),
+    ];
+    candidates.iter().find_map(|manifest| {
+        let content = std::fs::read_to_string(manifest).ok()?;
+        let manifest: Manifest = serde_json::from_str(&content).ok()?;
+        manifest.checksums.get(&id).copied()
+    })
+}
+
+fn bloom_summary(bloom: &BloomFilter) -> BloomSummary {
+    let bits_set: usize = bloom
+        .bits
+        .iter()
+        .map(|word| word.count_ones() as usize)
+        .sum();
+    let fill = bits_set as f64 / bloom.bit_count.max(1) as f64;
+    BloomSummary {
+        bit_count: bloom.bit_count,
+        hash_functions: bloom.hash_functions,
+        bits_set,
+        estimated_false_positive_rate: fill.powi(bloom.hash_functions as i32),
+    }
+}
+
+fn preview(value: &[u8]) -> (String, &'static str, bool) {
+    let truncated = value.len() > VALUE_PREVIEW_BYTES;
+    let shown = &value[..value.len().min(VALUE_PREVIEW_BYTES)];
+    match std::str::from_utf8(shown) {
+        Ok(text) => (text.to_string(), "utf8", truncated),
+        Err(_) => (crate::identity::to_hex(shown), "hex", truncated),
+    }
+}
+
+// Walks the records of `bytes` up to the first damaged one, which the
+// verification reports.
+fn read_records(
+    bytes: &[u8],
+    dictionary: Option<&Dictionary>,
+    filter: &RecordFilter,
+) -> Vec<SstRecord> {
+    let mut records = Vec::new();
+    let mut pos = dictionary.map_or(0, compression::header_len) as usize;
+    while pos < bytes.len() && filter.limit.is_none_or(|limit| records.len() < limit) {
+        let offset = pos;
+        let Some(k_size) = bytes.get(pos..pos + 2) else {
+            break;
+        };
+        let key_start = pos + 2;
+        let key_end = key_start + u16::from_le_bytes([k_size[0], k_size[1]]) as usize;
+        let Some(key) = bytes.get(key_start..key_end) else {
+            break;
+        };
+        let Some(v_size) = bytes.get(key_end..key_end + 4) else {
+            break;
+        };
+        let value_start = key_end + 4;
+        let (value_len, has_meta) = decode_len(v_size.try_into().unwrap());
+        let value_end = value_start + value_len;
+        let Some(stored) = bytes.get(value_start..value_end) else {
+            break;
+        };
+        pos = value_end;
+
+        let key = String::from_utf8_lossy(key).into_owned();
+        if !filter.matches(&key) {
+            if filter.end.as_deref().is_some_and(|end| key.as_str() >= end) {
+                break;
+            }
+            continue;
+        }
+        let (value, error) = match dictionary {
+            Some(dictionary) => match dictionary.decode(stored.to_vec()) {
+                Ok(value) => (value, None),
+                Err(e) => (stored.to_vec(), Some(e.to_string())),
+            },
+            None => (stored.to_vec(), None),
+        };
+        let (text, value_encoding, truncated) = preview(&value);
+        records.push(SstRecord {
+            offset: offset as u64,
+            key,
+            stored_len: stored.len(),
+            tombstone: stored.is_empty(),
+            has_meta,
+            value: text,
+            value_encoding,
+            value_len: value.len(),
+            truncated,
+            error,
+        });
+    }
+    records
+}
+
+/// Reads the SSTable at `path` without opening its database. Records are
+/// only read when `records` is given.
+pub fn inspect_sstable<P: AsRef<Path>>(
+    path: P,
+    records: Option<&RecordFilter>,
+) -> VeloResult<SstInspection> {
+    let path = path.as_ref().to_path_buf();
+    let id = sstable_id(&path);
+    let mut filter = BloomFilter::new(INDEX_INTERVAL, 0.01);
+    let table = Velocity::load_sstable(id.unwrap_or(0), path.clone(), &[], &mut filter)?;
+    let recorded = id.and_then(|id| recorded_checksum(&path, id));
+    let problem = match table.verify()? {
+        Err(problem) => Some(problem),
+        Ok(_) => recorded.filter(|&c| c != table.checksum).map(|c| {
+            format!(
+                "checksum {:08x} does not match {:08x} recorded when it was written",
+                table.checksum, c
+            )
+        }),
+    };
+
+    let records = match records {
+        Some(record_filter) => {
+            let bytes = std::fs::read(&path)?;
+            Some(read_records(
+                &bytes,
+                table.dictionary.as_deref(),
+                record_filter,
+            ))
+        }
+        None => None,
+    };
+
+    Ok(SstInspection {
+        header: SstHeader {
+            path,
+            id,
+            size_bytes: table.size,
+            checksum: table.checksum,
+            recorded_checksum: recorded,
+            compressed: table.dictionary.is_some(),
+            dictionary_id: table.dictionary.as_ref().and_then(|d| d.id()),
+            dictionary_bytes: table.dictionary.as_ref().map_or(0, |d| d.bytes().len()),
+        },
+        properties: SstProperties {
+            entries: table.entry_count,
+            tombstones: table.tombstones,
+            min_key: table.min_key.clone(),
+            max_key: table.max_key.clone(),
+            raw_value_bytes: table.raw_value_bytes,
+            stored_value_bytes: table.stored_value_bytes,
+            compression_ratio: table.compression_ratio(),
+        },
+        index: table
+            .index
+            .iter()
+            .map(|(key, &offset)| SstIndexEntry {
+                key: key.clone(),
+                offset,
+            })
+            .collect(),
+        bloom: bloom_summary(&table.bloom),
+        problem,
+        records,
+    })
+}
@@ -0,0 +1,487 @@
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufReader, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+/// How WAL appends, WAL fsyncs and SSTable reads reach the disk.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum IoBackend {
+    /// Blocking reads and writes through `std::fs`.
+    #[default]
+    Standard,
+    /// io_uring submissions on Linux; needs the `io-uring` feature.
+    IoUring,
+}
+
+impl IoBackend {
+    pub fn name(self) -> &'static str {
+        match self {
+            IoBackend::Standard => "standard",
+            IoBackend::IoUring => "io_uring",
+        }
+    }
+
+    /// The backend that will actually be used: `IoUring` falls back to
+    /// `Standard` when this build or kernel cannot set up a ring.
+    pub fn resolve(self) -> IoBackend {
+        match self {
+            IoBackend::Standard => IoBackend::Standard,
+            IoBackend::IoUring => match io_uring_support() {
+                Ok(()) => IoBackend::IoUring,
+                Err(reason) => {
+                    log::warn!("io_uring unavailable ({}); using standard I/O", reason);
+                    IoBackend::Standard
+                }
+            },
+        }
+    }
+}
+
+/// Why io_uring cannot be used here, if it cannot.
+pub fn io_uring_support() -> Result<(), String> {
+    #[cfg(all(target_os = "linux", feature = "io-uring"))]
+    {
+        uring::Ring::new()
+            .map(|_| ())
+            .map_err(|e| format!("ring setup failed: {}", e))
+    }
+    #[cfg(not(all(target_os = "linux", feature = "io-uring")))]
+    {
+        Err("built without the io-uring feature or not on Linux".to_string())
+    }
+}
+
+/// Buffered append-only file for the WAL. With io_uring, a sync submits the
+/// pending bytes and the fsync as one linked chain.
+pub(crate) struct WalFile {
+    file: File,
+    buf: Vec<u8>,
+    capacity: usize,
+    offset: u64,
+    #[cfg(all(target_os = "linux", feature = "io-uring"))]
+    ring: Option<uring::Ring>,
+}
+
+impl WalFile {
+    pub(crate) fn open(path: &Path, capacity: usize, backend: IoBackend) -> io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        let offset = file.metadata()?.len();
+        #[cfg(not(all(target_os = "linux", feature = "io-uring")))]
+        let _ = backend;
+        Ok(Self {
+            #[cfg(all(target_os = "linux", feature = "io-uring"))]
+            ring: match backend {
+                IoBackend::IoUring => uring::Ring::new().ok(),
+                IoBackend::Standard => None,
+            },
+            file,
+            buf: Vec::with_capacity(capacity),
+            capacity,
+            offset,
+        })
+    }
+
+    pub(crate) fn get_ref(&self) -> &File {
+        &self.file
+    }
+
+    /// Writes out buffered bytes and fsyncs the file's data.
+    pub(crate) fn sync_data(&mut self) -> io::Result<()> {
+        #[cfg(all(target_os = "linux", feature = "io-uring"))]
+        if let Some(ring) = &mut self.ring {
+            ring.write_all_and_sync(&self.file, &self.buf, self.offset)?;
+            self.offset += self.buf.len() as u64;
+            self.buf.clear();
+            return Ok(());
+        }
+        self.flush()?;
+        self.file.sync_data()
+    }
+}
+
+impl Write for WalFile {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        if self.buf.len() + data.len() > self.capacity {
+            self.flush()?;
+        }
+        self.buf.extend_from_slice(data);
+        Ok(data.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if self.buf.is_empty() {
+            return Ok(());
+        }
+        #[cfg(all(target_os = "linux", feature = "io-uring"))]
+        if let Some(ring) = &mut self.ring {
+            ring.write_all(&self.file, &self.buf, self.offset)?;
+            self.offset += self.buf.len() as u64;
+            self.buf.clear();
+            return Ok(());
+        }
+        self.file.write_all(&self.buf)?;
+        self.offset += self.buf.len() as u64;
+        self.buf.clear();
+        Ok(())
+    }
+}
+
+impl Drop for WalFile {
+    fn drop(&mut self) {
+        let _ = self.flush();
+    }
+}
+
+/// Buffered reader over an SSTable file.
+pub(crate) enum TableReader {
+    Standard(BufReader<File>),
+    #[cfg(all(target_os = "linux", feature = "io-uring"))]
+    Ring(uring::RingReader),
+}
+
+impl TableReader {
+    pub(crate) fn open(path: &Path, backend: IoBackend) -> io::Result<Self> {
+        let file = File::open(path)?;
+        match backend {
+            #[cfg(all(target_os = "linux", feature = "io-uring"))]
+            IoBackend::IoUring => Ok(TableReader::Ring(uring::RingReader::new(file))),
+            _ => Ok(TableReader::Standard(BufReader::with_capacity(
+                64 * 1024,
+                file,
+            ))),
+        }
+    }
+}
+
+impl Read for TableReader {
+    fn read(&mut self, out: &mut [u8]) -> io::Result<usize> {
+        match self {
+            TableReader::Standard(reader) => reader.read(out),
+            #[cfg(all(target_os = "linux", feature = "io-uring"))]
+            TableReader::Ring(reader) => reader.read(out),
+        }
+    }
+}
+
+impl Seek for TableReader {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        match self {
+            TableReader::Standard(reader) => reader.seek(pos),
+            #[cfg(all(target_os = "linux", feature = "io-uring"))]
+            TableReader::Ring(reader) => reader.seek(pos),
+        }
+    }
+}
+
+#[cfg(all(target_os = "linux", feature = "io-uring"))]
+mod uring {
+    use std::cell::RefCell;
+    use std::ffi::c_void;
+    use std::fs::File;
+    use std::io::{self, Read, Seek, SeekFrom};
+    use std::os::fd::{AsRawFd, OwnedFd};
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    use rustix::io::Errno;
+    use rustix::io_uring::{
+        addr_or_splice_off_in_union, io_uring_cqe, io_uring_enter, io_uring_params, io_uring_ptr,
+        io_uring_setup, io_uring_sqe, io_uring_user_data, len_union, off_or_addr2_union,
+        op_flags_union, IoringEnterFlags, IoringFsyncFlags, IoringOp, IoringSqeFlags,
+        IORING_OFF_CQ_RING, IORING_OFF_SQES, IORING_OFF_SQ_RING,
+    };
+    use rustix::mm::{mmap, munmap, MapFlags, ProtFlags};
+
+    const ENTRIES: u32 = 8;
+    const READ_CHUNK: usize = 64 * 1024;
+
+    struct Mapping {
+        ptr: *mut c_void,
+        len: usize,
+    }
+
+    impl Mapping {
+        fn new(fd: &OwnedFd, len: usize, offset: u64) -> io::Result<Self> {
+            // SAFETY: a fresh shared mapping of the ring fd; the kernel
+            // validates `len` and `offset`.
+            let ptr = unsafe {
+                mmap(
+                    std::ptr::null_mut(),
+                    len,
+                    ProtFlags::READ | ProtFlags::WRITE,
+                    MapFlags::SHARED | MapFlags::POPULATE,
+                    fd,
+                    offset,
+                )
+            }?;
+            Ok(Self { ptr, len })
+        }
+
+        // SAFETY: `offset` must come from the kernel's ring offsets.
+        unsafe fn at<T>(&self, offset: u32) -> *mut T {
+            self.ptr.cast::<u8>().add(offset as usize).cast()
+        }
+    }
+
+    impl Drop for Mapping {
+        fn drop(&mut self) {
+            // SAFETY: unmaps exactly what `new` mapped.
+            let _ = unsafe { munmap(self.ptr, self.len) };
+        }
+    }
+
+    /// A small ring used synchronously: every call submits its operations
+    /// and waits for all of them, so the queues are empty between calls.
+    pub(super) struct Ring {
+        fd: OwnedFd,
+        sq: Mapping,
+        cq: Mapping,
+        sqes: Mapping,
+        params: io_uring_params,
+    }
+
+    // SAFETY: the mappings are only touched through `&mut self`.
+    unsafe impl Send for Ring {}
+
+    impl Ring {
+        pub(super) fn new() -> io::Result<Self> {
+            let mut params = io_uring_params::default();
+            // SAFETY: `params` is a valid, zeroed parameter block.
+            let fd = unsafe { io_uring_setup(ENTRIES, &mut params) }?;
+            let sq_len = params.sq_off.array as usize + params.sq_entries as usize * 4;
+            let cq_len = params.cq_off.cqes as usize
+                + params.cq_entries as usize * std::mem::size_of::<io_uring_cqe>();
+            let sqes_len = params.sq_entries as usize * std::mem::size_of::<io_uring_sqe>();
+            Ok(Self {
+                sq: Mapping::new(&fd, sq_len, IORING_OFF_SQ_RING)?,
+                cq: Mapping::new(&fd, cq_len, IORING_OFF_CQ_RING)?,
+                sqes: Mapping::new(&fd, sqes_len, IORING_OFF_SQES)?,
+                fd,
+                params,
+            })
+        }
+
+        // Submits `ops` in order and returns each one's result.
+        fn run(&mut self, ops: &[io_uring_sqe]) -> io::Result<Vec<i32>> {
+            let count = ops.len() as u32;
+            let mut results = vec![0; ops.len()];
+            // SAFETY: the offsets come from `io_uring_setup`, and the kernel
+            // only reads submissions after the tail store below.
+            unsafe {
+                let sq_off = &self.params.sq_off;
+                let mask = *self.sq.at::<u32>(sq_off.ring_mask);
+                let tail = &*self.sq.at::<AtomicU32>(sq_off.tail);
+                let array = self.sq.at::<u32>(sq_off.array);
+                let sqes = self.sqes.at::<io_uring_sqe>(0);
+                let start = tail.load(Ordering::Relaxed);
+                for (i, op) in ops.iter().enumerate() {
+                    let slot = start.wrapping_add(i as u32) & mask;
+                    let mut op = *op;
+                    op.user_data = io_uring_user_data::from_u64(i as u64);
+                    sqes.add(slot as usize).write(op);
+                    array.add(slot as usize).write(slot);
+                }
+                tail.store(start.wrapping_add(count), Ordering::Release);
+            }
+
+            let (mut unsubmitted, mut done) = (count, 0);
+            while done < count {
+                // SAFETY: the ring fd is live for `self`'s lifetime.
+                match unsafe {
+                    io_uring_enter(&self.fd, unsubmitted, 1, IoringEnterFlags::GETEVENTS)
+                } {
+                    Ok(submitted) => unsubmitted -= submitted.min(unsubmitted),
+                    Err(Errno::INTR) => continue,
+                    Err(e) => return Err(e.into()),
+                }
+                // SAFETY: as above; entries between head and tail are
+                // completed by the kernel.
+                unsafe {
+                    let cq_off = &self.params.cq_off;
+                    let mask = *self.cq.at::<u32>(cq_off.ring_mask);
+                    let head = &*self.cq.at::<AtomicU32>(cq_off.head);
+                    let tail = self.cq.at::<AtomicU32>(cq_off.tail);
+                    let cqes = self.cq.at::<io_uring_cqe>(cq_off.cqes);
+                    let mut current = head.load(Ordering::Relaxed);
+                    while current != (*tail).load(Ordering::Acquire) {
+                        let cqe = &*cqes.add((current & mask) as usize);
+                        results[cqe.user_data.u64_() as usize] = cqe.res;
+                        current = current.wrapping_add(1);
+                        done += 1;
+                    }
+                    head.store(current, Ordering::Release);
+                }
+            }
+            Ok(results)
+        }
+
+        fn write_op(file: &File, data: &[u8], offset: u64) -> io_uring_sqe {
+            Self::rw_op(
+                IoringOp::Write,
+                file,
+                data.as_ptr() as *mut c_void,
+                data.len(),
+                offset,
+            )
+        }
+
+        fn rw_op(
+            opcode: IoringOp,
+            file: &File,
+            buf: *mut c_void,
+            len: usize,
+            offset: u64,
+        ) -> io_uring_sqe {
+            io_uring_sqe {
+                opcode,
+                fd: file.as_raw_fd(),
+                off_or_addr2: off_or_addr2_union { off: offset },
+                addr_or_splice_off_in: addr_or_splice_off_in_union {
+                    addr: io_uring_ptr::new(buf),
+                },
+                len: len_union {
+                    len: len.min(u32::MAX as usize) as u32,
+                },
+                ..Default::default()
+            }
+        }
+
+        fn fsync_op(file: &File) -> io_uring_sqe {
+            io_uring_sqe {
+                opcode: IoringOp::Fsync,
+                fd: file.as_raw_fd(),
+                op_flags: op_flags_union {
+                    fsync_flags: IoringFsyncFlags::DATASYNC,
+                },
+                ..Default::default()
+            }
+        }
+
+        pub(super) fn write_all(
+            &mut self,
+            file: &File,
+            mut data: &[u8],
+            mut offset: u64,
+        ) -> io::Result<()> {
+            while !data.is_empty() {
+                let written = check(self.run(&[Self::write_op(file, data, offset)])?[0])?;
+                if written == 0 {
+                    return Err(io::ErrorKind::WriteZero.into());
+                }
+                data = &data[written..];
+                offset += written as u64;
+            }
+            Ok(())
+        }
+
+        // A write linked to an fsync: one submission for the common case.
+        // A short write cancels the fsync; the rest is finished separately.
+        pub(super) fn write_all_and_sync(
+            &mut self,
+            file: &File,
+            data: &[u8],
+            offset: u64,
+        ) -> io::Result<()> {
+            if data.is_empty() {
+                return check(self.run(&[Self::fsync_op(file)])?[0]).map(|_| ());
+            }
+            let mut write = Self::write_op(file, data, offset);
+            write.flags = IoringSqeFlags::IO_LINK;
+            let results = self.run(&[write, Self::fsync_op(file)])?;
+            let written = check(results[0])?;
+            if written == data.len() {
+                return check(results[1]).map(|_| ());
+            }
+            self.write_all(file, &data[written..], offset + written as u64)?;
+            check(self.run(&[Self::fsync_op(file)])?[0]).map(|_| ())
+        }
+
+        fn read_at(&mut self, file: &File, out: &mut [u8], offset: u64) -> io::Result<usize> {
+            let read = Self::rw_op(
+                IoringOp::Read,
+                file,
+                out.as_mut_ptr().cast(),
+                out.len(),
+                offset,
+            );
+            check(self.run(&[read])?[0])
+        }
+    }
+
+    fn check(res: i32) -> io::Result<usize> {
+        if res < 0 {
+            Err(Errno::from_raw_os_error(-res).into())
+        } else {
+            Ok(res as usize)
+        }
+    }
+
+    thread_local! {
+        // Readers share one ring per thread; `None` after a failed setup.
+        static READ_RING: RefCell<Option<Option<Ring>>> = const { RefCell::new(None) };
+    }
+
+    fn read_at(file: &File, out: &mut [u8], offset: u64) -> io::Result<usize> {
+        READ_RING.with(|cell| {
+            let mut cell = cell.borrow_mut();
+            match cell.get_or_insert_with(|| Ring::new().ok()) {
+                Some(ring) => ring.read_at(file, out, offset),
+                None => std::os::unix::fs::FileExt::read_at(file, out, offset),
+            }
+        })
+    }
+
+    pub(crate) struct RingReader {
+        file: File,
+        buf: Vec<u8>,
+        filled: usize,
+        // File offset of `buf[0]`, and the read position within the file.
+        buf_start: u64,
+        pos: u64,
+    }
+
+    impl RingReader {
+        pub(super) fn new(file: File) -> Self {
+            Self {
+                file,
+                buf: vec![0; READ_CHUNK],
+                filled: 0,
+                buf_start: 0,
+                pos: 0,
+            }
+        }
+    }
+
+    impl Read for RingReader {
+        fn read(&mut self, out: &mut [u8]) -> io::Result<usize> {
+            let buf_end = self.buf_start + self.filled as u64;
+            if self.pos < self.buf_start || self.pos >= buf_end {
+                self.filled = read_at(&self.file, &mut self.buf, self.pos)?;
+                self.buf_start = self.pos;
+                if self.filled == 0 {
+                    return Ok(0);
+                }
+            }
+            let start = (self.pos - self.buf_start) as usize;
+            let n = out.len().min(self.filled - start);
+            out[..n].copy_from_slice(&self.buf[start..start + n]);
+            self.pos += n as u64;
+            Ok(n)
+        }
+    }
+
+    impl Seek for RingReader {
+        fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+            let target = match pos {
+                SeekFrom::Start(offset) => Some(offset),
+                SeekFrom::Current(delta) => self.pos.checked_add_signed(delta),
+                SeekFrom::End(delta) => self.file.metadata()?.len().checked_add_signed(delta),
+            };
+            self.pos = target.ok_or_else(|| {
+                io::Error::new(io::ErrorKind::InvalidInput, "seek before start of file")
+            })?;
+            Ok(self.pos)
+        }
+    }
+}
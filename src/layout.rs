@@ -0,0 +1,226 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::capacity::CAPACITY_HISTORY_FILE;
+use crate::format::FORMAT_FILE;
+use crate::{
+    VeloError, VeloResult, LIFETIME_STATS_FILE, MANIFEST_FILE, REPLICAS_FILE, SEQUENCE_FILE,
+};
+
+pub const WAL_DIR: &str = "wal";
+pub const SST_DIR: &str = "sst";
+pub const META_DIR: &str = "meta";
+pub const TMP_DIR: &str = "tmp";
+/// SSTables quarantined by `Velocity::open_with_verification`.
+pub const CORRUPT_DIR: &str = "corrupt";
+/// WAL segments kept for replicas that have not read them yet.
+pub const WAL_ARCHIVE_DIR: &str = "wal-archive";
+
+pub const WAL_FILE: &str = "velocity.wal";
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LayoutMode {
+    /// Every file directly in the data directory, as written by older releases.
+    Flat,
+    /// `wal/`, `sst/`, `meta/` and `tmp/` subdirectories.
+    #[default]
+    Structured,
+}
+
+/// Where each component of a database keeps its files.
+#[derive(Debug, Clone)]
+pub struct DataLayout {
+    root: PathBuf,
+    mode: LayoutMode,
+}
+
+impl DataLayout {
+    pub fn new<P: AsRef<Path>>(root: P, mode: LayoutMode) -> Self {
+        Self {
+            root: root.as_ref().to_path_buf(),
+            mode,
+        }
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    pub fn mode(&self) -> LayoutMode {
+        self.mode
+    }
+
+    pub fn wal_dir(&self) -> PathBuf {
+        self.component(WAL_DIR)
+    }
+
+    pub fn sst_dir(&self) -> PathBuf {
+        self.component(SST_DIR)
+    }
+
+    pub fn meta_dir(&self) -> PathBuf {
+        self.component(META_DIR)
+    }
+
+    pub fn tmp_dir(&self) -> PathBuf {
+        self.component(TMP_DIR)
+    }
+
+    // A subdirectory in both layouts, so quarantined tables are never
+    // loaded again.
+    pub fn corrupt_dir(&self) -> PathBuf {
+        self.root.join(CORRUPT_DIR)
+    }
+
+    // A subdirectory in both layouts, so archived segments are never
+    // replayed.
+    pub fn wal_archive_dir(&self) -> PathBuf {
+        self.root.join(WAL_ARCHIVE_DIR)
+    }
+
+    pub fn wal_path(&self) -> PathBuf {
+        self.wal_dir().join(WAL_FILE)
+    }
+
+    fn component(&self, name: &str) -> PathBuf {
+        match self.mode {
+            LayoutMode::Flat => self.root.clone(),
+            LayoutMode::Structured => self.root.join(name),
+        }
+    }
+
+    pub fn create_dirs(&self) -> VeloResult<()> {
+        for dir in [self.wal_dir(), self.sst_dir(), self.meta_dir(), self.tmp_dir()] {
+            fs::create_dir_all(dir)?;
+        }
+        Ok(())
+    }
+
+    /// Moves files left in the root by the flat layout into their component
+    /// directories and clears out leftover temporary files. Each file is
+    /// moved with a single rename, so an interrupted migration resumes on the
+    /// next open. Returns the number of files moved.
+    pub fn migrate(&self) -> VeloResult<usize> {
+        self.create_dirs()?;
+        if self.mode == LayoutMode::Flat {
+            return Ok(0);
+        }
+
+        let mut moved = 0;
+        for entry in fs::read_dir(&self.root)?.flatten() {
+            let path = entry.path();
+            if !path.is_file() {
+                continue;
+            }
+            let Some(name) = path.file_name().and_then(|n| n.to_str()) else {
+                continue;
+            };
+            let Some(dir) = flat_component(name) else {
+                continue;
+            };
+
+            let target = self.component(dir).join(name);
+            if dir == TMP_DIR {
+                fs::remove_file(&path)?;
+                continue;
+            }
+            if target.exists() {
+                return Err(VeloError::InvalidOperation(format!(
+                    "Cannot migrate {:?}: {:?} already exists",
+                    path, target
+                )));
+            }
+            fs::rename(&path, &target)?;
+            moved += 1;
+        }
+
+        for entry in fs::read_dir(self.tmp_dir())?.flatten() {
+            if entry.path().is_file() {
+                fs::remove_file(entry.path())?;
+            }
+        }
+
+        if moved > 0 {
+            log::info!(
+                "Migrated {} files in {:?} to the structured layout",
+                moved,
+                self.root
+            );
+        }
+        Ok(moved)
+    }
+
+    /// The layout to read the database with when it may not be changed.
+    /// Files the flat layout left in the root are read where they are
+    /// rather than migrated.
+    pub fn read_only_view(&self) -> VeloResult<Self> {
+        if self.mode == LayoutMode::Flat {
+            return Ok(self.clone());
+        }
+        let unmigrated = fs::read_dir(&self.root)?.flatten().any(|entry| {
+            let name = entry.file_name();
+            entry.path().is_file()
+                && name
+                    .to_str()
+                    .and_then(flat_component)
+                    .is_some_and(|dir| dir != TMP_DIR)
+        });
+        if !unmigrated {
+            return Ok(self.clone());
+        }
+        let migrated = [self.wal_dir(), self.sst_dir(), self.meta_dir()]
+            .iter()
+            .filter_map(|dir| fs::read_dir(dir).ok())
+            .any(|mut entries| entries.next().is_some());
+        if migrated {
+            return Err(VeloError::InvalidOperation(format!(
+                "{:?} is partly migrated to the structured layout; open it writable once to finish",
+                self.root
+            )));
+        }
+        Ok(Self::new(&self.root, LayoutMode::Flat))
+    }
+
+    /// Whether `root` holds a WAL or SSTables in either layout.
+    pub fn has_data<P: AsRef<Path>>(root: P) -> bool {
+        let root = root.as_ref();
+        [root.to_path_buf(), root.join(WAL_DIR), root.join(SST_DIR)]
+            .iter()
+            .filter_map(|dir| fs::read_dir(dir).ok())
+            .flat_map(|entries| entries.flatten())
+            .any(|entry| {
+                entry
+                    .path()
+                    .extension()
+                    .is_some_and(|ext| ext == "vdb" || ext == "wal")
+            })
+    }
+}
+
+fn flat_component(name: &str) -> Option<&'static str> {
+    if name == WAL_FILE {
+        Some(WAL_DIR)
+    } else if name.starts_with("sstable_") && name.ends_with(".vdb") {
+        Some(SST_DIR)
+    } else if [
+        LIFETIME_STATS_FILE,
+        SEQUENCE_FILE,
+        MANIFEST_FILE,
+        REPLICAS_FILE,
+        FORMAT_FILE,
+        CAPACITY_HISTORY_FILE,
+    ]
+    .contains(&name)
+    {
+        Some(META_DIR)
+    } else if name.ends_with(".json.tmp")
+        || name.ends_with(".vdb.part")
+        || name.ends_with(".vdb.tmp") {
+        Some(TMP_DIR)
+    } else {
+        None
+    }
+}
@@ -1,25 +1,73 @@
+use faults::{FaultInjectionConfig, StorageOp};
+use hlc::HlcTimestamp;
+use io_backend::IoBackend;
+use layout::{DataLayout, LayoutMode};
+use memtable::{ArenaStats, Memtable};
 use serde::{Deserialize, Serialize};
 use std::collections::hash_map::DefaultHasher;
 use std::collections::{BTreeMap, HashMap, VecDeque};
-use std::fs::{create_dir_all, File, OpenOptions};
+use std::fs::{create_dir_all, File};
 use std::hash::{Hash, Hasher};
 use std::io::{self, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
 
 use std::path::{Path, PathBuf};
-use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, AtomicU8, AtomicUsize, Ordering};
 use std::sync::mpsc;
-use std::sync::{Arc, Mutex, RwLock};
+use std::sync::{Arc, Mutex, OnceLock, RwLock};
 use std::thread;
 use std::time::Duration;
 use std::time::{Instant, SystemTime, UNIX_EPOCH};
+use write::WriteOptions;
 
 
+pub mod access;
 pub mod addon;
+pub mod auth;
+pub mod background;
+pub mod builder;
+pub mod backup_schedule;
+pub mod capacity;
 pub mod client;
+pub mod collation;
+pub mod compaction;
+pub mod compression;
+pub mod dry_run;
+pub mod faults;
+pub mod format;
+pub mod fsutil;
+pub mod hashing;
+pub mod health;
+pub mod hlc;
+pub mod hotkeys;
+pub mod idempotency;
+pub mod io_backend;
+pub mod identity;
+pub mod inspect;
+pub mod layout;
+pub mod memtable;
 pub mod performance;
+pub mod policy;
+pub mod privileges;
+#[cfg(feature = "proptest-support")]
+pub mod proptest_support;
+pub mod read;
+pub mod read_sources;
+pub mod replication;
+pub mod request_id;
+pub mod resources;
+pub mod result_cache;
+pub mod scheduler;
+pub mod serializer;
 pub mod server;
 pub mod sql;
+pub mod statement_cache;
 pub mod studio;
+#[cfg(feature = "testkit")]
+pub mod testkit;
+pub mod usage;
+pub mod users;
+pub mod warmup;
+pub mod write;
 
 
 pub type VeloKey = String;
@@ -32,9 +80,315 @@ pub enum VeloError {
     CorruptedData(String),
     KeyNotFound(String),
     InvalidOperation(String),
+    VersionConflict {
+        key: String,
+        expected: u64,
+        actual: u64,
+    },
+    ServerBusy(String),
 }
 
 
+pub const LIFETIME_STATS_FILE: &str = "lifetime_stats.json";
+pub const SEQUENCE_FILE: &str = "sequence.json";
+pub const MANIFEST_FILE: &str = "manifest.json";
+pub const REPLICAS_FILE: &str = "replicas.json";
+
+const RECORD_META_HEADER_LEN: usize = 24;
+// Set in the value length of WAL and SSTable records whose value starts with
+// a `RecordMeta` header.
+const RECORD_META_FLAG: u32 = 1 << 31;
+/// The longest value that can be stored.
+pub const MAX_VALUE_LEN: usize = RECORD_META_FLAG as usize - 1;
+
+// A table's bloom filter is rebuilt at the next compaction once at least
+// this many absent-key lookups show a false-positive rate this many times
+// above the configured target.
+const BLOOM_FPR_MIN_SAMPLES: u64 = 1000;
+const BLOOM_FPR_REBUILD_FACTOR: f64 = 10.0;
+const COMPACTION_LOG_CAPACITY: usize = 256;
+// Records a WAL shard's writer thread may have queued before writes fail
+// with "Write queue full".
+const MAX_QUEUED_WAL_RECORDS: usize = 1 << 20;
+// Stands in for the key length of a WAL record to start a batch frame: the
+// length of the framed records and a checksum over them, then the records.
+// Recovery replays a frame whole or not at all.
+const WAL_BATCH_MARKER: u32 = u32::MAX;
+const WAL_BATCH_HEADER_LEN: usize = 24;
+
+// Tables where at least this share of entries is reclaimable (tombstones and
+// values shadowed by newer tables) are rewritten on their own, densest first.
+const GARBAGE_RATIO_TRIGGER: f64 = 0.5;
+const MAX_REWRITES_PER_COMPACTION: usize = 4;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RecordMeta {
+    pub created_at: u64,
+    pub updated_at: u64,
+    pub version: u64,
+}
+
+pub const TRASH_PREFIX: &str = "_trash:";
+
+fn now_millis() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
+
+impl RecordMeta {
+    fn next(previous: Option<RecordMeta>) -> Self {
+        let now = hlc::clock().now().physical_ms();
+        Self {
+            created_at: previous.map_or(now, |p| p.created_at),
+            updated_at: now,
+            version: previous.map_or(1, |p| p.version + 1),
+        }
+    }
+
+    fn encode(&self, value: &[u8]) -> RawValue {
+        let mut record = Vec::with_capacity(RECORD_META_HEADER_LEN + value.len());
+        record.extend_from_slice(&self.created_at.to_le_bytes());
+        record.extend_from_slice(&self.updated_at.to_le_bytes());
+        record.extend_from_slice(&self.version.to_le_bytes());
+        record.extend_from_slice(value);
+        RawValue {
+            bytes: record,
+            has_meta: true,
+        }
+    }
+}
+
+/// A value as it is stored. With `has_meta`, `bytes` starts with a
+/// `RecordMeta` header. The flag is kept next to the value, in the memtable,
+/// the cache and a bit of the value length in WAL and SSTable records, so a
+/// value is never taken apart because of what its bytes look like.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub(crate) struct RawValue {
+    pub(crate) bytes: VeloValue,
+    pub(crate) has_meta: bool,
+}
+
+impl RawValue {
+    pub(crate) fn plain(bytes: VeloValue) -> Self {
+        Self {
+            bytes,
+            has_meta: false,
+        }
+    }
+
+    pub(crate) fn tombstone() -> Self {
+        Self::default()
+    }
+
+    pub(crate) fn is_tombstone(&self) -> bool {
+        self.bytes.is_empty()
+    }
+
+    // The value length field of a WAL or SSTable record.
+    fn encoded_len(&self) -> u32 {
+        let flag = if self.has_meta { RECORD_META_FLAG } else { 0 };
+        self.bytes.len() as u32 | flag
+    }
+}
+
+// Splits a value length field into the length and the metadata flag.
+fn decode_len(field: [u8; 4]) -> (usize, bool) {
+    let field = u32::from_le_bytes(field);
+    ((field & !RECORD_META_FLAG) as usize, field & RECORD_META_FLAG != 0)
+}
+
+pub(crate) fn split_record(raw: RawValue) -> (VeloValue, Option<RecordMeta>) {
+    let RawValue {
+        bytes: mut raw,
+        has_meta,
+    } = raw;
+    if !has_meta || raw.len() < RECORD_META_HEADER_LEN {
+        return (raw, None);
+    }
+    let field = |i: usize| u64::from_le_bytes(raw[i * 8..i * 8 + 8].try_into().unwrap());
+    let meta = RecordMeta {
+        created_at: field(0),
+        updated_at: field(1),
+        version: field(2),
+    };
+    raw.drain(..RECORD_META_HEADER_LEN);
+    (raw, Some(meta))
+}
+
+fn check_value_len(value: &RawValue) -> VeloResult<()> {
+    if value.bytes.len() > MAX_VALUE_LEN {
+        return Err(VeloError::InvalidOperation(format!(
+            "Value of {} bytes is larger than the {} byte limit",
+            value.bytes.len(),
+            MAX_VALUE_LEN
+        )));
+    }
+    Ok(())
+}
+
+// A trash entry is the deletion time followed by the deleted value. It is
+// stored as a plain value; the top bit of the time carries the deleted
+// value's metadata flag.
+const TRASH_META_FLAG: u64 = 1 << 63;
+
+fn trash_entry(raw: RawValue) -> RawValue {
+    let flag = if raw.has_meta { TRASH_META_FLAG } else { 0 };
+    let mut entry = Vec::with_capacity(8 + raw.bytes.len());
+    entry.extend_from_slice(&(now_millis() | flag).to_le_bytes());
+    entry.extend_from_slice(&raw.bytes);
+    RawValue::plain(entry)
+}
+
+fn trash_header(entry: &[u8]) -> Option<(u64, bool)> {
+    let header = u64::from_le_bytes(entry.get(..8)?.try_into().unwrap());
+    Some((header & !TRASH_META_FLAG, header & TRASH_META_FLAG != 0))
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct LifetimeStats {
+    #[serde(default)]
+    pub total_compactions: u64,
+    #[serde(default)]
+    pub total_flushes: u64,
+    #[serde(default)]
+    pub bytes_written: u64,
+    #[serde(default)]
+    pub uptime_secs: u64,
+    #[serde(default)]
+    pub starts: u64,
+    #[serde(default)]
+    pub last_backup_at: Option<u64>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum RecoveryPhase {
+    WalReplay,
+    SSTableLoad,
+    Complete,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct RecoveryProgress {
+    pub phase: RecoveryPhase,
+    pub items: u64,
+    pub bytes_done: u64,
+    pub bytes_total: u64,
+    pub elapsed_ms: u64,
+}
+
+impl RecoveryProgress {
+    pub fn eta(&self) -> Option<Duration> {
+        if self.bytes_done == 0 || self.bytes_done >= self.bytes_total {
+            return None;
+        }
+        let ms_per_byte = self.elapsed_ms as f64 / self.bytes_done as f64;
+        Some(Duration::from_millis(
+            ((self.bytes_total - self.bytes_done) as f64 * ms_per_byte) as u64,
+        ))
+    }
+}
+
+pub type RecoveryCallback = Arc<dyn Fn(&RecoveryProgress) + Send + Sync>;
+
+/// Called with the sequence and key of every write once it is visible to
+/// reads, including the index entries the engine keeps for itself.
+pub type WriteListener = Arc<dyn Fn(u64, &str) + Send + Sync>;
+
+pub type MaintenanceListener = Arc<dyn Fn(&compaction::MaintenanceProgress) + Send + Sync>;
+
+/// Receives the sequence, key and value of a WAL record.
+pub type WalReplayCallback = Arc<dyn Fn(u64, &str, &[u8]) + Send + Sync>;
+
+/// How opening a database treats WAL records that are not yet in an
+/// SSTable. Every mode except `Replay` opens the database read-only, because
+/// a flush would mark the records it left out as applied.
+#[derive(Clone, Default)]
+pub enum WalRecovery {
+    #[default]
+    Replay,
+    /// Leave the records out, showing only what the SSTables hold.
+    Skip,
+    /// Apply records with a sequence at or below this one.
+    UpToSequence(u64),
+    /// Pass the records to the callback in sequence order instead of
+    /// applying them.
+    Inspect(WalReplayCallback),
+}
+
+impl WalRecovery {
+    /// Apply records written at or before `time`.
+    pub fn up_to_time(time: SystemTime) -> Self {
+        let ms = time.duration_since(UNIX_EPOCH).unwrap_or_default().as_millis() as u64;
+        WalRecovery::UpToSequence(HlcTimestamp::new(ms, u16::MAX).as_u64())
+    }
+}
+
+struct RecoveryReporter<'a> {
+    callback: Option<&'a RecoveryCallback>,
+    data_dir: &'a Path,
+    started: Instant,
+    last_report: Instant,
+}
+
+impl<'a> RecoveryReporter<'a> {
+    const INTERVAL: Duration = Duration::from_secs(1);
+
+    fn new(callback: Option<&'a RecoveryCallback>, data_dir: &'a Path) -> Self {
+        let now = Instant::now();
+        Self {
+            callback,
+            data_dir,
+            started: now,
+            last_report: now,
+        }
+    }
+
+    fn report(&mut self, phase: RecoveryPhase, items: u64, bytes_done: u64, bytes_total: u64) {
+        let finished = phase == RecoveryPhase::Complete || (bytes_total > 0 && bytes_done >= bytes_total);
+        if !finished && self.last_report.elapsed() < Self::INTERVAL {
+            return;
+        }
+        self.last_report = Instant::now();
+
+        let progress = RecoveryProgress {
+            phase,
+            items,
+            bytes_done,
+            bytes_total,
+            elapsed_ms: self.started.elapsed().as_millis() as u64,
+        };
+
+        match phase {
+            RecoveryPhase::WalReplay | RecoveryPhase::SSTableLoad => log::info!(
+                "Recovering {:?}: {} {} ({}/{} bytes{})",
+                self.data_dir,
+                if phase == RecoveryPhase::WalReplay { "replayed WAL records:" } else { "loaded SSTables:" },
+                items,
+                bytes_done,
+                bytes_total,
+                progress
+                    .eta()
+                    .map(|eta| format!(", ETA {}s", eta.as_secs()))
+                    .unwrap_or_default()
+            ),
+            RecoveryPhase::Complete if progress.elapsed_ms >= Self::INTERVAL.as_millis() as u64 => {
+                log::info!(
+                    "Recovery of {:?} finished in {} ms",
+                    self.data_dir,
+                    progress.elapsed_ms
+                )
+            }
+            RecoveryPhase::Complete => {}
+        }
+
+        if let Some(callback) = self.callback {
+            callback(&progress);
+        }
+    }
+}
+
 #[derive(Debug, Default)]
 pub struct WalIntegrityReport {
     pub total_records: usize,
@@ -43,16 +397,94 @@ pub struct WalIntegrityReport {
     pub corrupted_keys: Vec<String>,
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+/// An SSTable left out by `Velocity::open_with_verification`.
+#[derive(Debug, Clone, Serialize)]
+pub struct QuarantinedTable {
+    pub id: u64,
+    /// Where the file is now, under `corrupt/` unless the database was
+    /// opened read-only.
+    pub path: PathBuf,
+    pub reason: String,
+}
+
+#[derive(Debug, Default)]
+pub struct SSTableIntegrityReport {
+    pub tables_checked: usize,
+    pub total_entries: usize,
+    pub damaged_tables: Vec<(PathBuf, String)>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum BatchOp {
+    Put { key: VeloKey, value: VeloValue },
+    Delete { key: VeloKey },
+}
+
+impl BatchOp {
+    pub fn key(&self) -> &str {
+        match self {
+            BatchOp::Put { key, .. } | BatchOp::Delete { key } => key,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
 pub enum WalSyncMode {
     EveryWrite,
+    #[default]
     Batch,
     Interval(u64),
 }
 
-impl Default for WalSyncMode {
-    fn default() -> Self {
-        WalSyncMode::Batch
+/// What a write does to the read cache.
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CachePolicy {
+    /// Every write stores its value in the cache.
+    #[default]
+    WriteThrough = 0,
+    /// Writes drop the key from the cache; the next read loads it again.
+    WriteAround = 1,
+    /// Only reads add entries. Writes update keys that are already cached.
+    ReadFill = 2,
+}
+
+impl CachePolicy {
+    pub fn name(self) -> &'static str {
+        match self {
+            CachePolicy::WriteThrough => "write_through",
+            CachePolicy::WriteAround => "write_around",
+            CachePolicy::ReadFill => "read_fill",
+        }
+    }
+
+    fn from_u8(value: u8) -> Self {
+        match value {
+            1 => CachePolicy::WriteAround,
+            2 => CachePolicy::ReadFill,
+            _ => CachePolicy::WriteThrough,
+        }
+    }
+}
+
+impl std::str::FromStr for CachePolicy {
+    type Err = VeloError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        [
+            CachePolicy::WriteThrough,
+            CachePolicy::WriteAround,
+            CachePolicy::ReadFill,
+        ]
+        .into_iter()
+        .find(|policy| policy.name().eq_ignore_ascii_case(s.trim().trim_matches('\'')))
+        .ok_or_else(|| {
+            VeloError::InvalidOperation(format!(
+                "Unknown cache policy '{}' (expected write_through, write_around or read_fill)",
+                s
+            ))
+        })
     }
 }
 
@@ -63,6 +495,12 @@ impl std::fmt::Display for VeloError {
             VeloError::CorruptedData(msg) => write!(f, "Corrupted Data: {}", msg),
             VeloError::KeyNotFound(key) => write!(f, "Key Not Found: {}", key),
             VeloError::InvalidOperation(msg) => write!(f, "Invalid Operation: {}", msg),
+            VeloError::VersionConflict { key, expected, actual } => write!(
+                f,
+                "Version Conflict: key '{}' is at version {}, expected {}",
+                key, actual, expected
+            ),
+            VeloError::ServerBusy(msg) => write!(f, "Server Busy: {}", msg),
         }
     }
 }
@@ -88,23 +526,25 @@ pub struct BloomFilter {
     pub bits: Vec<u64>,
     pub bit_count: usize,
     pub hash_functions: usize,
+    pub seed: u64,
 }
 
 impl BloomFilter {
     fn new(expected_items: usize, false_positive_rate: f64) -> Self {
         let bit_count = Self::optimal_bit_count(expected_items, false_positive_rate);
         let hash_functions = Self::optimal_hash_count(bit_count, expected_items);
-        let word_count = (bit_count + 63) / 64;
+        let word_count = bit_count.div_ceil(64);
 
         Self {
             bits: vec![0u64; word_count],
             bit_count,
             hash_functions,
+            seed: hashing::bloom_seed(),
         }
     }
 
     fn optimal_bit_count(n: usize, p: f64) -> usize {
-        ((-1.0 * n as f64 * p.ln()) / (2_f64.ln().powi(2))).ceil() as usize
+        ((-(n as f64) * p.ln()) / (2_f64.ln().powi(2))).ceil() as usize
     }
 
     fn optimal_hash_count(m: usize, n: usize) -> usize {
@@ -134,12 +574,19 @@ impl BloomFilter {
         true
     }
 
+    fn with_bits(bit_count: usize, expected_items: usize) -> Self {
+        let bit_count = bit_count.max(64);
+        Self {
+            bits: vec![0u64; bit_count.div_ceil(64)],
+            bit_count,
+            hash_functions: Self::optimal_hash_count(bit_count, expected_items.max(1)),
+            seed: hashing::bloom_seed(),
+        }
+    }
+
     #[inline]
-    fn hash(&self, key: &str, seed: usize) -> usize {
-        let mut hasher = DefaultHasher::new();
-        seed.hash(&mut hasher);
-        key.hash(&mut hasher);
-        hasher.finish() as usize
+    fn hash(&self, key: &str, i: usize) -> usize {
+        hashing::hash64(self.seed.wrapping_add(i as u64), key.as_bytes()) as usize
     }
 }
 
@@ -148,14 +595,14 @@ impl BloomFilter {
 struct UltraFastCache {
     capacity: usize,
     entries: Vec<Option<CacheEntry>>,
-    key_to_index: HashMap<VeloKey, usize>,
+    key_to_index: HashMap<VeloKey, usize, hashing::HashState>,
     access_order: VecDeque<usize>,
     free_slots: Vec<usize>,
 }
 
 struct CacheEntry {
     key: VeloKey,
-    value: VeloValue,
+    value: RawValue,
     access_count: u32,
     last_access: u64,
 }
@@ -173,14 +620,17 @@ impl UltraFastCache {
         Self {
             capacity,
             entries,
-            key_to_index: HashMap::with_capacity(capacity),
+            key_to_index: HashMap::with_capacity_and_hasher(
+                capacity,
+                hashing::HashState::default(),
+            ),
             access_order: VecDeque::with_capacity(capacity),
             free_slots,
         }
     }
 
     #[inline(always)]
-    fn get(&mut self, key: &str) -> Option<VeloValue> {
+    fn get(&mut self, key: &str) -> Option<RawValue> {
         if let Some(&index) = self.key_to_index.get(key) {
             if let Some(ref mut entry) = self.entries[index] {
                 entry.access_count += 1;
@@ -198,7 +648,7 @@ impl UltraFastCache {
     }
 
     #[inline(always)]
-    fn put(&mut self, key: VeloKey, value: VeloValue) {
+    fn put(&mut self, key: VeloKey, value: RawValue) {
 
         if let Some(&index) = self.key_to_index.get(&key) {
             if let Some(ref mut entry) = self.entries[index] {
@@ -229,6 +679,23 @@ impl UltraFastCache {
         self.access_order.push_back(index);
     }
 
+    // Replaces the value of a cached key; other keys are left out.
+    fn update(&mut self, key: &str, value: RawValue) {
+        if let Some(&index) = self.key_to_index.get(key) {
+            if let Some(ref mut entry) = self.entries[index] {
+                entry.value = value;
+            }
+        }
+    }
+
+    fn remove(&mut self, key: &str) {
+        if let Some(index) = self.key_to_index.remove(key) {
+            self.entries[index] = None;
+            self.access_order.retain(|&x| x != index);
+            self.free_slots.push(index);
+        }
+    }
+
     #[inline(always)]
     fn evict_lfu(&mut self) -> usize {
         let mut min_access = u32::MAX;
@@ -255,9 +722,7 @@ impl UltraFastCache {
 
     #[inline(always)]
     fn get_timestamp() -> u64 {
-
-        static COUNTER: AtomicU64 = AtomicU64::new(0);
-        COUNTER.fetch_add(1, Ordering::Relaxed)
+        hlc::clock().now().as_u64()
     }
 
     fn clear(&mut self) {
@@ -276,6 +741,21 @@ impl UltraFastCache {
     fn len(&self) -> usize {
         self.key_to_index.len()
     }
+
+    // Cached keys, most used first.
+    fn keys_by_use(&self) -> Vec<VeloKey> {
+        let mut entries: Vec<&CacheEntry> = self.entries.iter().flatten().collect();
+        entries.sort_by_key(|entry| std::cmp::Reverse(entry.access_count));
+        entries.into_iter().map(|entry| entry.key.clone()).collect()
+    }
+
+    fn memory_bytes(&self) -> u64 {
+        self.entries
+            .iter()
+            .flatten()
+            .map(|e| (e.key.len() + e.value.bytes.len() + 48) as u64)
+            .sum()
+    }
 }
 
 
@@ -297,13 +777,13 @@ impl AdaptiveBatchManager {
     fn should_flush(&self, current_count: usize) -> bool {
 
         for &threshold in &self.batch_thresholds {
-            if current_count >= threshold && current_count % threshold == 0 {
+            if current_count >= threshold && current_count.is_multiple_of(threshold) {
                 return true;
             }
         }
 
 
-        if current_count >= 128 && current_count % 128 == 0 {
+        if current_count >= 128 && current_count.is_multiple_of(128) {
             return true;
         }
 
@@ -327,63 +807,75 @@ impl AdaptiveBatchManager {
 struct AsyncWriteQueue {
     sender: mpsc::Sender<WriteOperation>,
     batch_manager: Arc<AdaptiveBatchManager>,
+    queued: Arc<AtomicUsize>,
     _handle: thread::JoinHandle<()>,
 }
 
+type WalRecord = (u64, VeloKey, RawValue);
+
+// One write, or every record of a batch, which is logged as one frame.
 #[derive(Debug)]
 struct WriteOperation {
-    key: VeloKey,
-    value: VeloValue,
+    records: Vec<WalRecord>,
 }
 
 impl AsyncWriteQueue {
     fn new(
-        _memtable: Arc<RwLock<BTreeMap<VeloKey, VeloValue>>>,
+        _memtable: Arc<RwLock<Memtable>>,
         _filter: Arc<RwLock<BloomFilter>>,
         wal: Arc<Mutex<WriteAheadLog>>,
+        shard: usize,
         config: VelocityConfig,
     ) -> Self {
         let (sender, receiver) = mpsc::channel::<WriteOperation>();
         let batch_manager = Arc::new(AdaptiveBatchManager::new());
         let batch_manager_clone = batch_manager.clone();
+        let queued = Arc::new(AtomicUsize::new(0));
+        let queued_clone = queued.clone();
 
-        let handle = thread::spawn(move || {
-            let mut batch = Vec::with_capacity(128);
+        let handle = thread::Builder::new()
+            .name(format!("velocity-wal-{}", shard))
+            .spawn(move || {
+                let mut batch = Vec::with_capacity(128);
 
-            loop {
-                batch.clear();
+                loop {
+                    batch.clear();
 
 
-                if let Ok(op) = receiver.recv() {
-                    batch.push(op);
+                    if let Ok(op) = receiver.recv() {
+                        batch.push(op);
 
 
-                    while batch.len() < 128 {
-                        match receiver.try_recv() {
-                            Ok(op) => batch.push(op),
-                            Err(_) => break,
+                        while batch.len() < 128 {
+                            match receiver.try_recv() {
+                                Ok(op) => batch.push(op),
+                                Err(_) => break,
+                            }
                         }
-                    }
 
 
-                    let current_count = batch_manager_clone.get_count() + batch.len();
-                    let should_flush = batch_manager_clone.should_flush(current_count);
+                        let current_count = batch_manager_clone.get_count() + batch.len();
+                        let should_flush = batch_manager_clone.should_flush(current_count);
 
 
-                    Self::process_batch(&batch, &wal, &config, should_flush);
+                        Self::process_batch(&batch, &wal, &config, should_flush);
+                        let records = batch.iter().map(|op| op.records.len()).sum();
+                        queued_clone.fetch_sub(records, Ordering::SeqCst);
 
-                    if should_flush {
-                        batch_manager_clone.reset();
+                        if should_flush {
+                            batch_manager_clone.reset();
+                        }
+                    } else {
+                        break;
                     }
-                } else {
-                    break;
                 }
-            }
-        });
+            })
+            .expect("failed to start the WAL writer thread");
 
         Self {
             sender,
             batch_manager,
+            queued,
             _handle: handle,
         }
     }
@@ -399,7 +891,7 @@ impl AsyncWriteQueue {
         if !config.memory_only_mode {
             if let Ok(mut wal_guard) = wal.lock() {
                 for op in batch {
-                    let _ = wal_guard.log_operation(&op.key, &op.value);
+                    let _ = wal_guard.log_records(&op.records);
                 }
 
                 if force_flush || config.batch_wal_writes {
@@ -409,74 +901,121 @@ impl AsyncWriteQueue {
         }
     }
 
-    fn send(&self, key: VeloKey, value: VeloValue) -> Result<(), mpsc::SendError<WriteOperation>> {
-        self.batch_manager.increment();
-        self.sender.send(WriteOperation { key, value })
+    // Called before a write is applied, so a write that cannot be queued
+    // fails without having changed anything. A batch larger than the limit
+    // is still taken by an empty queue.
+    fn reserve(&self, records: usize) -> VeloResult<()> {
+        let queued = self.queued.fetch_add(records, Ordering::SeqCst) + records;
+        if queued > MAX_QUEUED_WAL_RECORDS && queued > records {
+            self.queued.fetch_sub(records, Ordering::SeqCst);
+            return Err(VeloError::InvalidOperation("Write queue full".to_string()));
+        }
+        Ok(())
     }
 
-    fn pending_count(&self) -> usize {
-        self.batch_manager.get_count()
+    // Sends records reserved with `reserve`.
+    fn send(&self, records: Vec<WalRecord>) -> VeloResult<()> {
+        let count = records.len();
+        self.batch_manager.increment();
+        self.sender.send(WriteOperation { records }).map_err(|_| {
+            self.queued.fetch_sub(count, Ordering::SeqCst);
+            VeloError::InvalidOperation("WAL writer has stopped".to_string())
+        })
     }
 }
 struct WriteAheadLog {
-    file: BufWriter<File>,
+    file: io_backend::WalFile,
     path: PathBuf,
+    backend: IoBackend,
     buffer_size: usize,
     entries_since_sync: usize,
     sync_threshold: usize,
     batch_buffer: Vec<u8>,
     sync_mode: WalSyncMode,
     last_sync: Instant,
+    faults: Option<FaultInjectionConfig>,
 }
 
 impl WriteAheadLog {
-    fn new<P: AsRef<Path>>(path: P, sync_mode: WalSyncMode) -> VeloResult<Self> {
+    fn new<P: AsRef<Path>>(
+        path: P,
+        sync_mode: WalSyncMode,
+        backend: IoBackend,
+        faults: Option<FaultInjectionConfig>,
+    ) -> VeloResult<Self> {
         let wal_path = path.as_ref().with_extension("wal");
-        let file = OpenOptions::new()
-            .create(true)
-            .append(true)
-            .open(&wal_path)?;
+        let file = io_backend::WalFile::open(&wal_path, 256 * 1024, backend)?;
 
         Ok(Self {
-            file: BufWriter::with_capacity(256 * 1024, file),
+            file,
             path: wal_path,
+            backend,
             buffer_size: 0,
             entries_since_sync: 0,
             sync_threshold: 1000,
             batch_buffer: Vec::with_capacity(64 * 1024),
             sync_mode,
             last_sync: Instant::now(),
+            faults,
         })
     }
 
-    fn log_operation(&mut self, key: &str, value: &[u8]) -> VeloResult<()> {
-        let timestamp = SystemTime::now()
-            .duration_since(UNIX_EPOCH)
-            .unwrap()
-            .as_secs();
+    fn sync(&mut self) -> VeloResult<()> {
+        if let Some(faults) = &self.faults {
+            faults.inject(StorageOp::Fsync)?;
+        }
+        self.file.sync_data()?;
+        self.entries_since_sync = 0;
+        self.last_sync = Instant::now();
+        Ok(())
+    }
+
+    // `sequence` is the HLC reading taken when the write reached the
+    // memtable, so records from concurrent writers can be put back in
+    // memtable order on recovery even if they reach the log out of order.
+    fn log_operation(&mut self, sequence: u64, key: &str, value: &RawValue) -> VeloResult<()> {
+        self.batch_buffer.clear();
+        encode_wal_record(&mut self.batch_buffer, sequence, key, value);
+        self.file.write_all(&self.batch_buffer)?;
+
+        self.buffer_size += key.len() + value.bytes.len() + 24;
+        self.entries_since_sync += 1;
+
+        self.entries_since_sync += 1;
+        self.sync_if_due()
+    }
 
+    // A single record is logged as is; more are framed so that recovery
+    // replays all of them or none.
+    fn log_records(&mut self, records: &[WalRecord]) -> VeloResult<()> {
+        let [first, rest @ ..] = records else {
+            return Ok(());
+        };
+        if rest.is_empty() {
+            return self.log_operation(first.0, &first.1, &first.2);
+        }
 
+        let mut body = Vec::new();
+        for (sequence, key, value) in records {
+            encode_wal_record(&mut body, *sequence, key, value);
+        }
         self.batch_buffer.clear();
+        self.batch_buffer.extend_from_slice(&first.0.to_le_bytes());
         self.batch_buffer
-            .extend_from_slice(&timestamp.to_le_bytes());
+            .extend_from_slice(&WAL_BATCH_MARKER.to_le_bytes());
         self.batch_buffer
-            .extend_from_slice(&(key.len() as u32).to_le_bytes());
-        self.batch_buffer.extend_from_slice(key.as_bytes());
+            .extend_from_slice(&(body.len() as u32).to_le_bytes());
         self.batch_buffer
-            .extend_from_slice(&(value.len() as u32).to_le_bytes());
-        self.batch_buffer.extend_from_slice(value);
-
-        let checksum = self.calculate_checksum(key.as_bytes(), value);
-        self.batch_buffer.extend_from_slice(&checksum.to_le_bytes());
-
-
+            .extend_from_slice(&wal_batch_checksum(&body).to_le_bytes());
+        self.batch_buffer.extend_from_slice(&body);
         self.file.write_all(&self.batch_buffer)?;
 
-        self.buffer_size += key.len() + value.len() + 24;
-        self.entries_since_sync += 1;
-
-        self.entries_since_sync += 1;
+        self.buffer_size += self.batch_buffer.len();
+        self.entries_since_sync += records.len();
+        self.sync_if_due()
+    }
 
+    fn sync_if_due(&mut self) -> VeloResult<()> {
 
         let should_sync = match self.sync_mode {
             WalSyncMode::EveryWrite => true,
@@ -496,34 +1035,60 @@ impl WriteAheadLog {
         Ok(())
     }
 
-    #[inline]
-    fn calculate_checksum(&self, key: &[u8], value: &[u8]) -> u64 {
-        let mut hasher = DefaultHasher::new();
-        key.hash(&mut hasher);
-        value.hash(&mut hasher);
-        hasher.finish()
+    fn position(&mut self) -> VeloResult<u64> {
+        self.file.flush()?;
+        Ok(self.file.get_ref().metadata()?.len())
     }
 
-    fn clear(&mut self) -> VeloResult<()> {
-        self.file.flush()?;
+    fn read_from(&mut self, offset: u64, max_len: usize) -> VeloResult<Vec<u8>> {
+        let len = self.position()?;
+        if offset > len {
+            return Err(VeloError::InvalidOperation(format!(
+                "WAL position {} is no longer available (log is {} bytes)",
+                offset, len
+            )));
+        }
+
+        read_wal_chunk(File::open(&self.path)?, offset, max_len)
+    }
 
-        let file = OpenOptions::new()
-            .create(true)
-            .append(true)
-            .open(&self.path)?;
-        self.file = BufWriter::with_capacity(64 * 1024, file);
+    // Starts an empty log once its records are in an SSTable. The old file
+    // is moved to `archive_to` when replicas still need it.
+    fn clear(&mut self, archive_to: Option<&Path>) -> VeloResult<()> {
+        self.file.flush()?;
+        match archive_to {
+            Some(target) => {
+                if let Some(dir) = target.parent() {
+                    create_dir_all(dir)?;
+                }
+                std::fs::rename(&self.path, target)?;
+                fsutil::sync_parent(target);
+            }
+            None => match std::fs::remove_file(&self.path) {
+                Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e.into()),
+                _ => {}
+            },
+        }
+        self.file = io_backend::WalFile::open(&self.path, 64 * 1024, self.backend)?;
         self.buffer_size = 0;
         self.entries_since_sync = 0;
         Ok(())
     }
 
-    fn recover(&self) -> VeloResult<Vec<(VeloKey, VeloValue)>> {
-        if !self.path.exists() {
+    fn recover(
+        path: &Path,
+        on_progress: &mut dyn FnMut(u64, u64, u64),
+    ) -> VeloResult<Vec<(u64, VeloKey, RawValue)>> {
+        if !path.exists() {
             return Ok(Vec::new());
         }
 
-        let mut file = BufReader::new(File::open(&self.path)?);
+        let file = File::open(path)?;
+        let bytes_total = file.metadata()?.len();
+        let mut file = BufReader::new(file);
         let mut operations = Vec::new();
+        let mut records = 0u64;
+        let mut bytes_done = 0u64;
 
         loop {
             let mut ts_buf = [0u8; 8];
@@ -535,6 +1100,21 @@ impl WriteAheadLog {
             if file.read_exact(&mut k_size_buf).is_err() {
                 break;
             }
+            if u32::from_le_bytes(k_size_buf) == WAL_BATCH_MARKER {
+                let Some((body, valid)) = read_wal_batch(&mut file) else {
+                    break;
+                };
+                let (batch, _) = decode_wal_records(&body);
+                records += batch.len() as u64;
+                bytes_done += (WAL_BATCH_HEADER_LEN + body.len()) as u64;
+                if valid {
+                    for (timestamp, key, value) in batch {
+                        hlc::clock().observe(timestamp);
+                        operations.push((timestamp.as_u64(), key, value));
+                    }
+                }
+                continue;
+            }
             let k_size = u32::from_le_bytes(k_size_buf) as usize;
 
             let mut k_buf = vec![0u8; k_size];
@@ -547,34 +1127,47 @@ impl WriteAheadLog {
             if file.read_exact(&mut v_size_buf).is_err() {
                 break;
             }
-            let v_size = u32::from_le_bytes(v_size_buf) as usize;
+            let (v_size, has_meta) = decode_len(v_size_buf);
 
             let mut v_buf = vec![0u8; v_size];
             if file.read_exact(&mut v_buf).is_err() {
                 break;
             }
+            let value = RawValue {
+                bytes: v_buf,
+                has_meta,
+            };
 
             let mut checksum_buf = [0u8; 8];
             if file.read_exact(&mut checksum_buf).is_err() {
                 break;
             }
             let stored_checksum = u64::from_le_bytes(checksum_buf);
-            let calculated_checksum = self.calculate_checksum(&k_buf, &v_buf);
+            let calculated_checksum = wal_checksum(&k_buf, &value);
+
+            records += 1;
+            bytes_done += (k_size + v_size + 24) as u64;
+            if records.is_multiple_of(1024) {
+                on_progress(records, bytes_done, bytes_total);
+            }
 
             if stored_checksum == calculated_checksum {
-                operations.push((key, v_buf));
+                let sequence = u64::from_le_bytes(ts_buf);
+                hlc::clock().observe(HlcTimestamp::from_u64(sequence));
+                operations.push((sequence, key, value));
             }
         }
 
+        on_progress(records, bytes_done, bytes_total);
         Ok(operations)
     }
 
-    pub fn verify_integrity(&self) -> VeloResult<WalIntegrityReport> {
-        if !self.path.exists() {
+    fn verify_integrity(path: &Path) -> VeloResult<WalIntegrityReport> {
+        if !path.exists() {
             return Ok(WalIntegrityReport::default());
         }
 
-        let mut file = BufReader::new(File::open(&self.path)?);
+        let mut file = BufReader::new(File::open(path)?);
         let mut report = WalIntegrityReport::default();
 
         loop {
@@ -588,6 +1181,23 @@ impl WriteAheadLog {
                 report.truncated_records += 1;
                 break;
             }
+            if u32::from_le_bytes(k_size_buf) == WAL_BATCH_MARKER {
+                let Some((body, valid)) = read_wal_batch(&mut file) else {
+                    report.truncated_records += 1;
+                    break;
+                };
+                let (batch, _) = decode_wal_records(&body);
+                report.total_records += batch.len();
+                if !valid {
+                    report.corrupted_records += batch.len().max(1);
+                    for (_, key, _) in batch {
+                        if report.corrupted_keys.len() < 5 {
+                            report.corrupted_keys.push(key);
+                        }
+                    }
+                }
+                continue;
+            }
             let k_size = u32::from_le_bytes(k_size_buf) as usize;
 
             let mut k_buf = vec![0u8; k_size];
@@ -602,13 +1212,17 @@ impl WriteAheadLog {
                 report.truncated_records += 1;
                 break;
             }
-            let v_size = u32::from_le_bytes(v_size_buf) as usize;
+            let (v_size, has_meta) = decode_len(v_size_buf);
 
             let mut v_buf = vec![0u8; v_size];
             if file.read_exact(&mut v_buf).is_err() {
                 report.truncated_records += 1;
                 break;
             }
+            let value = RawValue {
+                bytes: v_buf,
+                has_meta,
+            };
 
             let mut checksum_buf = [0u8; 8];
             if file.read_exact(&mut checksum_buf).is_err() {
@@ -616,7 +1230,7 @@ impl WriteAheadLog {
                 break;
             }
             let stored_checksum = u64::from_le_bytes(checksum_buf);
-            let calculated_checksum = self.calculate_checksum(&k_buf, &v_buf);
+            let calculated_checksum = wal_checksum(&k_buf, &value);
 
             report.total_records += 1;
             if stored_checksum != calculated_checksum {
@@ -631,116 +1245,850 @@ impl WriteAheadLog {
     }
 }
 
-
-pub struct SSTable {
-    pub id: u64,
-    pub path: PathBuf,
-    pub index: BTreeMap<VeloKey, u64>,
-    pub bloom: BloomFilter,
-    pub min_key: Option<VeloKey>,
-    pub max_key: Option<VeloKey>,
-    pub size: u64,
-    pub entry_count: usize,
+/// The WAL split by key hash into shards, each a file with its own lock and
+/// writer thread. Shard 0 is `velocity.wal` and shard n `velocity-<n>.wal`.
+struct WalShards {
+    dir: PathBuf,
+    shards: Vec<Arc<Mutex<WriteAheadLog>>>,
 }
 
-impl SSTable {
-    pub fn all_entries(&self) -> VeloResult<Vec<(VeloKey, VeloValue)>> {
-        let mut entries = Vec::new();
-        let file = File::open(&self.path)?;
-        let mut reader = BufReader::with_capacity(256 * 1024, file);
-
-        loop {
-            let mut k_size_buf = [0u8; 2];
-            if reader.read_exact(&mut k_size_buf).is_err() {
-                break;
-            }
-            let k_size = u16::from_le_bytes(k_size_buf) as usize;
-
-            let mut k_buf = vec![0u8; k_size];
-            reader.read_exact(&mut k_buf)?;
-            let key = String::from_utf8_lossy(&k_buf).into_owned();
-
-            let mut v_size_buf = [0u8; 4];
-            reader.read_exact(&mut v_size_buf)?;
-            let v_size = u32::from_le_bytes(v_size_buf) as usize;
-
-            let mut v_buf = vec![0u8; v_size];
-            reader.read_exact(&mut v_buf)?;
+impl WalShards {
+    fn open(
+        dir: &Path,
+        count: usize,
+        sync_mode: WalSyncMode,
+        backend: IoBackend,
+        faults: Option<&FaultInjectionConfig>,
+    ) -> VeloResult<Self> {
+        let shards = (0..count)
+            .map(|shard| {
+                Ok(Arc::new(Mutex::new(WriteAheadLog::new(
+                    Self::shard_path(dir, shard),
+                    sync_mode,
+                    backend,
+                    faults.cloned(),
+                )?)))
+            })
+            .collect::<VeloResult<Vec<_>>>()?;
+        Ok(Self {
+            dir: dir.to_path_buf(),
+            shards,
+        })
+    }
 
-            if !v_buf.is_empty() {
+    fn shard_path(dir: &Path, shard: usize) -> PathBuf {
+        match shard {
+            0 => dir.join("velocity"),
+            n => dir.join(format!("velocity-{}", n)),
+        }
+    }
 
-                entries.push((key, v_buf));
+    // Deletes the files of shards beyond the current count. Only safe once
+    // everything recovered from them has been flushed.
+    fn remove_stale(&self) -> VeloResult<()> {
+        let live: Vec<PathBuf> = (0..self.shards.len())
+            .map(|shard| Self::shard_path(&self.dir, shard).with_extension("wal"))
+            .collect();
+        for path in self.files() {
+            if !live.contains(&path) {
+                std::fs::remove_file(&path)?;
             }
         }
+        Ok(())
+    }
 
-        Ok(entries)
+    fn shard_of(&self, key: &str) -> usize {
+        match self.shards.len() {
+            0 | 1 => 0,
+            n => (hashing::hash64(0, key.as_bytes()) % n as u64) as usize,
+        }
     }
-}
 
-impl SSTable {
-    fn create<P: AsRef<Path>>(
-        path: P,
-        id: u64,
-        data: &BTreeMap<VeloKey, VeloValue>,
-    ) -> VeloResult<Self> {
-        let sstable_path = path.as_ref().join(format!("sstable_{:06}.vdb", id));
-        let mut file = BufWriter::with_capacity(256 * 1024, File::create(&sstable_path)?);
-        let mut index = BTreeMap::new();
-        let mut bloom = BloomFilter::new(data.len(), 0.001);
-        let mut min_key = None;
-        let mut max_key = None;
-        let entry_count = data.len();
+    fn shard(&self, key: &str) -> &Arc<Mutex<WriteAheadLog>> {
+        &self.shards[self.shard_of(key)]
+    }
 
+    // Replicas follow a single log by byte offset, which a sharded WAL
+    // does not have.
+    fn unsharded(&self) -> VeloResult<&Arc<Mutex<WriteAheadLog>>> {
+        match self.shards.as_slice() {
+            [wal] => Ok(wal),
+            [] => Err(VeloError::InvalidOperation(
+                "A database opened read-only has no live WAL to ship".to_string(),
+            )),
+            _ => Err(VeloError::InvalidOperation(
+                "WAL shipping needs wal_shards = 1".to_string(),
+            )),
+        }
+    }
 
-        let mut counter = 0;
-        for (key, value) in data {
-            let offset = file.get_ref().stream_position()?;
+    /// Locks every shard, in shard order.
+    fn lock_all(&self) -> Vec<std::sync::MutexGuard<'_, WriteAheadLog>> {
+        self.shards.iter().map(|wal| wal.lock().unwrap()).collect()
+    }
 
-            bloom.add(key);
+    // Every shard file in the directory, including those of shards beyond
+    // the current count that an earlier run with more shards left behind.
+    fn files(&self) -> Vec<PathBuf> {
+        let mut files: Vec<PathBuf> = std::fs::read_dir(&self.dir)
+            .map(|entries| {
+                entries
+                    .flatten()
+                    .map(|entry| entry.path())
+                    .filter(|path| {
+                        let Some(name) = path.file_name().and_then(|n| n.to_str()) else {
+                            return false;
+                        };
+                        name == layout::WAL_FILE
+                            || name
+                                .strip_prefix("velocity-")
+                                .and_then(|rest| rest.strip_suffix(".wal"))
+                                .is_some_and(|n| {
+                                    !n.is_empty() && n.bytes().all(|b| b.is_ascii_digit())
+                                })
+                    })
+                    .collect()
+            })
+            .unwrap_or_default();
+        files.sort();
+        files
+    }
 
-            if counter % 16 == 0 {
-                index.insert(key.clone(), offset);
-            }
+    fn recover(
+        &self,
+        on_progress: &mut dyn FnMut(u64, u64, u64),
+    ) -> VeloResult<Vec<(u64, VeloKey, RawValue)>> {
+        let files = self.files();
+        let bytes_total: u64 = files
+            .iter()
+            .filter_map(|path| path.metadata().ok())
+            .map(|meta| meta.len())
+            .sum();
+        let (mut records_before, mut bytes_before) = (0, 0);
+        let mut operations = Vec::new();
+        for path in files {
+            let (mut records_done, mut bytes_done) = (0, 0);
+            operations.extend(WriteAheadLog::recover(&path, &mut |records, bytes, _| {
+                (records_done, bytes_done) = (records, bytes);
+                on_progress(records_before + records, bytes_before + bytes, bytes_total)
+            })?);
+            records_before += records_done;
+            bytes_before += bytes_done;
+        }
+        Ok(operations)
+    }
 
-            if min_key.is_none() {
-                min_key = Some(key.clone());
+    fn verify_integrity(&self) -> VeloResult<WalIntegrityReport> {
+        let mut report = WalIntegrityReport::default();
+        for path in self.files() {
+            let shard = WriteAheadLog::verify_integrity(&path)?;
+            report.total_records += shard.total_records;
+            report.corrupted_records += shard.corrupted_records;
+            report.truncated_records += shard.truncated_records;
+            for key in shard.corrupted_keys {
+                if report.corrupted_keys.len() < 5 {
+                    report.corrupted_keys.push(key);
+                }
             }
-            max_key = Some(key.clone());
+        }
+        Ok(report)
+    }
+}
 
+pub(crate) fn decode_wal_records(bytes: &[u8]) -> (Vec<(HlcTimestamp, VeloKey, RawValue)>, usize) {
+    let mut records = Vec::new();
+    let mut pos = 0usize;
 
-            file.write_all(&(key.len() as u16).to_le_bytes())?;
-            file.write_all(key.as_bytes())?;
-            file.write_all(&(value.len() as u32).to_le_bytes())?;
-            file.write_all(value)?;
+    let read_u32 = |at: usize| -> Option<[u8; 4]> { bytes.get(at..at + 4)?.try_into().ok() };
 
-            counter += 1;
+    while let Some(k_size) = read_u32(pos + 8) {
+        if u32::from_le_bytes(k_size) == WAL_BATCH_MARKER {
+            let Some(len) = read_u32(pos + 12) else { break };
+            let body_start = pos + WAL_BATCH_HEADER_LEN;
+            let Some(body) = bytes.get(body_start..body_start + u32::from_le_bytes(len) as usize)
+            else {
+                break;
+            };
+            let checksum = u64::from_le_bytes(bytes[pos + 16..body_start].try_into().unwrap());
+            if wal_batch_checksum(body) == checksum {
+                records.extend(decode_wal_records(body).0);
+            }
+            pos = body_start + body.len();
+            continue;
+        }
+        let k_size = u32::from_le_bytes(k_size) as usize;
+        let key_start = pos + 12;
+        let Some(v_size) = read_u32(key_start + k_size) else { break };
+        let (v_size, has_meta) = decode_len(v_size);
+        let value_start = key_start + k_size + 4;
+        let checksum_start = value_start + v_size;
+        let Some(checksum_bytes) = bytes.get(checksum_start..checksum_start + 8) else { break };
+
+        let k_buf = &bytes[key_start..key_start + k_size];
+        let value = RawValue {
+            bytes: bytes[value_start..checksum_start].to_vec(),
+            has_meta,
+        };
+        let stored_checksum = u64::from_le_bytes(checksum_bytes.try_into().unwrap());
+        if stored_checksum == wal_checksum(k_buf, &value) {
+            let timestamp = u64::from_le_bytes(bytes[pos..pos + 8].try_into().unwrap());
+            records.push((
+                HlcTimestamp::from_u64(timestamp),
+                String::from_utf8_lossy(k_buf).into_owned(),
+                value,
+            ));
         }
+        pos = checksum_start + 8;
+    }
 
-        file.flush()?;
-        let size = file.get_ref().metadata()?.len();
+    (records, pos)
+}
 
-        Ok(Self {
+fn encode_wal_record(buf: &mut Vec<u8>, sequence: u64, key: &str, value: &RawValue) {
+    buf.extend_from_slice(&sequence.to_le_bytes());
+    buf.extend_from_slice(&(key.len() as u32).to_le_bytes());
+    buf.extend_from_slice(key.as_bytes());
+    buf.extend_from_slice(&value.encoded_len().to_le_bytes());
+    buf.extend_from_slice(&value.bytes);
+    buf.extend_from_slice(&wal_checksum(key.as_bytes(), value).to_le_bytes());
+}
+
+fn wal_batch_checksum(body: &[u8]) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    body.hash(&mut hasher);
+    hasher.finish()
+}
+
+// Reads the rest of a batch frame after its marker. `None` when the frame
+// is cut short; otherwise the framed records and whether they match the
+// frame's checksum.
+fn read_wal_batch(file: &mut impl Read) -> Option<(Vec<u8>, bool)> {
+    let mut header = [0u8; WAL_BATCH_HEADER_LEN - 12];
+    file.read_exact(&mut header).ok()?;
+    let len = u32::from_le_bytes(header[..4].try_into().unwrap()) as usize;
+    let checksum = u64::from_le_bytes(header[4..].try_into().unwrap());
+    let mut body = vec![0u8; len];
+    file.read_exact(&mut body).ok()?;
+    let valid = wal_batch_checksum(&body) == checksum;
+    Some((body, valid))
+}
+
+// Up to `max_len` bytes from `offset`, or more when the record or batch
+// frame there is longer, so a replica can always apply what it is sent.
+fn read_wal_chunk(mut file: File, offset: u64, max_len: usize) -> VeloResult<Vec<u8>> {
+    file.seek(SeekFrom::Start(offset))?;
+    let mut buf = Vec::new();
+    (&mut file).take(max_len as u64).read_to_end(&mut buf)?;
+    if let Some(len) = wal_entry_len(&buf).filter(|len| *len > buf.len()) {
+        (&mut file).take((len - buf.len()) as u64).read_to_end(&mut buf)?;
+    }
+    Ok(buf)
+}
+
+// The length of the record or batch frame at the start of `bytes`, once
+// enough of it is there to tell.
+fn wal_entry_len(bytes: &[u8]) -> Option<usize> {
+    let k_size = u32::from_le_bytes(bytes.get(8..12)?.try_into().unwrap());
+    if k_size == WAL_BATCH_MARKER {
+        let len = u32::from_le_bytes(bytes.get(12..16)?.try_into().unwrap());
+        return Some(WAL_BATCH_HEADER_LEN + len as usize);
+    }
+    let v_start = 12 + k_size as usize;
+    let (v_size, _) = decode_len(bytes.get(v_start..v_start + 4)?.try_into().unwrap());
+    Some(v_start + 4 + v_size + 8)
+}
+
+// The flag is only hashed when set, so records without metadata keep the
+// checksums they were written with.
+fn wal_checksum(key: &[u8], value: &RawValue) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    key.hash(&mut hasher);
+    value.bytes.hash(&mut hasher);
+    if value.has_meta {
+        RECORD_META_FLAG.hash(&mut hasher);
+    }
+    hasher.finish()
+}
+
+
+// SSTables are written under this name and renamed into place once synced;
+// files with it are never loaded.
+fn temp_sstable_path(path: &Path) -> PathBuf {
+    path.with_extension("vdb.tmp")
+}
+
+fn sstable_path(dir: &Path, id: u64) -> PathBuf {
+    dir.join(format!("sstable_{:06}.vdb", id))
+}
+
+fn install_sstable(tmp_path: &Path, path: &Path) -> VeloResult<()> {
+    std::fs::rename(tmp_path, path)?;
+    fsutil::sync_parent(path);
+    Ok(())
+}
+
+/// Writes records in ascending key order to a new SSTable file.
+struct SSTableWriter {
+    file: BufWriter<File>,
+    path: PathBuf,
+    id: u64,
+    index: BTreeMap<VeloKey, u64>,
+    bloom: BloomFilter,
+    min_key: Option<VeloKey>,
+    max_key: Option<VeloKey>,
+    entry_count: usize,
+    tombstones: usize,
+    offset: u64,
+    crc: crc32fast::Hasher,
+    compression: Option<(
+        Arc<compression::Dictionary>,
+        compression::ValueEncoder,
+        policy::CompressionPlan,
+    )>,
+    raw_value_bytes: u64,
+    stored_value_bytes: u64,
+}
+
+impl SSTableWriter {
+    fn new(
+        path: PathBuf,
+        id: u64,
+        expected_entries: usize,
+        false_positive_rate: f64,
+    ) -> VeloResult<Self> {
+        Ok(Self {
+            file: BufWriter::with_capacity(256 * 1024, File::create(&path)?),
+            path,
             id,
-            path: sstable_path,
-            index,
-            bloom,
-            min_key,
-            max_key,
+            index: BTreeMap::new(),
+            bloom: BloomFilter::new(expected_entries.max(1), false_positive_rate),
+            min_key: None,
+            max_key: None,
+            entry_count: 0,
+            tombstones: 0,
+            offset: 0,
+            crc: crc32fast::Hasher::new(),
+            compression: None,
+            raw_value_bytes: 0,
+            stored_value_bytes: 0,
+        })
+    }
+
+    // Compresses values as `plan` says, with `dictionary`, which is stored in
+    // the table's header. Must be called before the first `add`.
+    fn compress_with(
+        mut self,
+        compression: Option<(compression::Dictionary, policy::CompressionPlan)>,
+    ) -> VeloResult<Self> {
+        if let Some((dictionary, plan)) = compression {
+            let encoder = dictionary.encoder()?;
+            let header = compression::encode_header(&dictionary);
+            self.file.write_all(&header)?;
+            self.crc.update(&header);
+            self.offset += header.len() as u64;
+            self.compression = Some((Arc::new(dictionary), encoder, plan));
+        }
+        Ok(self)
+    }
+
+    fn add(&mut self, key: &str, value: &[u8], has_meta: bool) -> VeloResult<()> {
+        self.bloom.add(key);
+        if value.is_empty() {
+            self.tombstones += 1;
+        }
+        if self.entry_count.is_multiple_of(INDEX_INTERVAL) {
+            self.index.insert(key.to_string(), self.offset);
+        }
+        if self.min_key.is_none() {
+            self.min_key = Some(key.to_string());
+        }
+        self.max_key = Some(key.to_string());
+
+        let stored = match &mut self.compression {
+            Some((_, encoder, plan)) => encoder.encode(value, plan.level(key))?,
+            None => value.to_vec(),
+        };
+        self.raw_value_bytes += value.len() as u64;
+        self.stored_value_bytes += stored.len() as u64;
+        self.write_record(key, &stored, has_meta)?;
+        self.entry_count += 1;
+        Ok(())
+    }
+
+    fn write_record(&mut self, key: &str, value: &[u8], has_meta: bool) -> VeloResult<()> {
+        let flag = if has_meta { RECORD_META_FLAG } else { 0 };
+        for part in [
+            &(key.len() as u16).to_le_bytes()[..],
+            key.as_bytes(),
+            &(value.len() as u32 | flag).to_le_bytes(),
+            value,
+        ] {
+            self.file.write_all(part)?;
+            self.crc.update(part);
+        }
+        self.offset += (2 + key.len() + 4 + value.len()) as u64;
+        Ok(())
+    }
+
+    fn finish(mut self) -> VeloResult<SSTable> {
+        self.file.flush()?;
+        self.file.get_ref().sync_all()?;
+        let size = self.file.get_ref().metadata()?.len();
+
+        Ok(SSTable {
+            id: self.id,
+            path: self.path,
+            index: self.index,
+            bloom: self.bloom,
+            min_key: self.min_key,
+            max_key: self.max_key,
             size,
-            entry_count,
+            entry_count: self.entry_count,
+            bloom_negatives: AtomicU64::new(0),
+            bloom_false_positives: AtomicU64::new(0),
+            tombstones: self.tombstones,
+            tombstones_retained: 0,
+            overwritten: AtomicU64::new(0),
+            level: AtomicU32::new(0),
+            checksum: self.crc.finalize(),
+            dictionary: self.compression.map(|(dictionary, _, _)| dictionary),
+            raw_value_bytes: self.raw_value_bytes,
+            stored_value_bytes: self.stored_value_bytes,
         })
     }
+}
+
+// Counts a write of `key` against every table in `older` that may hold it.
+fn mark_overwritten(older: &[Arc<SSTable>], key: &str) {
+    for sstable in older {
+        if sstable.may_contain(key) {
+            sstable.overwritten.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+// The records of one table in a key range; see `SSTable::range_from`.
+struct SSTableRange<'a, R> {
+    sstable: &'a SSTable,
+    file: R,
+    start: String,
+    end: Option<String>,
+    done: bool,
+}
+
+impl<R: Read + Seek> SSTableRange<'_, R> {
+    fn read_next(&mut self) -> VeloResult<Option<(VeloKey, RawValue)>> {
+        loop {
+            let mut k_size_buf = [0u8; 2];
+            match self.file.read_exact(&mut k_size_buf) {
+                Ok(()) => {}
+                Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+                Err(e) => return Err(e.into()),
+            }
+            let mut k_buf = vec![0u8; u16::from_le_bytes(k_size_buf) as usize];
+            self.file.read_exact(&mut k_buf)?;
+            let key = String::from_utf8_lossy(&k_buf).into_owned();
+
+            let mut v_size_buf = [0u8; 4];
+            self.file.read_exact(&mut v_size_buf)?;
+            let (v_size, has_meta) = decode_len(v_size_buf);
+
+            if self.end.as_ref().is_some_and(|end| &key >= end) {
+                return Ok(None);
+            }
+            if key < self.start {
+                self.file.seek(SeekFrom::Current(v_size as i64))?;
+                continue;
+            }
+            let mut v_buf = vec![0u8; v_size];
+            self.file.read_exact(&mut v_buf)?;
+            return Ok(Some((key, self.sstable.decode(v_buf, has_meta)?)));
+        }
+    }
+}
+
+impl<R: Read + Seek> Iterator for SSTableRange<'_, R> {
+    type Item = VeloResult<(VeloKey, RawValue)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        let next = self.read_next().transpose();
+        self.done = !matches!(next, Some(Ok(_)));
+        next
+    }
+}
+
+pub struct SSTable {
+    pub id: u64,
+    pub path: PathBuf,
+    pub index: BTreeMap<VeloKey, u64>,
+    pub bloom: BloomFilter,
+    pub min_key: Option<VeloKey>,
+    pub max_key: Option<VeloKey>,
+    pub size: u64,
+    pub entry_count: usize,
+    /// Lookups for keys the table does not hold, split by whether the bloom
+    /// filter rejected them or let them through to disk.
+    pub bloom_negatives: AtomicU64,
+    pub bloom_false_positives: AtomicU64,
+    pub tombstones: usize,
+    /// Tombstones kept by the last rewrite because older tables may still
+    /// hold the key; they are not counted as reclaimable.
+    pub tombstones_retained: usize,
+    /// Estimated entries shadowed by writes in newer tables.
+    pub overwritten: AtomicU64,
+    /// The table's level, mirrored from the manifest's levels so lookups
+    /// can label reads without locking them.
+    pub level: AtomicU32,
+    /// CRC32 of the file contents, taken when the table was written or
+    /// first loaded.
+    pub checksum: u32,
+    /// The dictionary values were compressed with, for tables written by a
+    /// compaction with compression enabled.
+    pub dictionary: Option<Arc<compression::Dictionary>>,
+    /// Value bytes before and after compression.
+    pub raw_value_bytes: u64,
+    pub stored_value_bytes: u64,
+}
+
+impl SSTable {
+    // The same table with a new filter and fresh lookup counters. Tables are
+    // shared with open snapshots, so they are replaced rather than mutated.
+    fn with_bloom(&self, bloom: BloomFilter) -> SSTable {
+        SSTable {
+            id: self.id,
+            path: self.path.clone(),
+            index: self.index.clone(),
+            bloom,
+            min_key: self.min_key.clone(),
+            max_key: self.max_key.clone(),
+            size: self.size,
+            entry_count: self.entry_count,
+            bloom_negatives: AtomicU64::new(0),
+            bloom_false_positives: AtomicU64::new(0),
+            tombstones: self.tombstones,
+            tombstones_retained: self.tombstones_retained,
+            overwritten: AtomicU64::new(self.overwritten.load(Ordering::Relaxed)),
+            level: AtomicU32::new(self.level.load(Ordering::Relaxed)),
+            checksum: self.checksum,
+            dictionary: self.dictionary.clone(),
+            raw_value_bytes: self.raw_value_bytes,
+            stored_value_bytes: self.stored_value_bytes,
+        }
+    }
+
+    /// The share of entries a rewrite could drop.
+    pub fn garbage_ratio(&self) -> f64 {
+        if self.entry_count == 0 {
+            return 0.0;
+        }
+        let garbage = self.tombstones.saturating_sub(self.tombstones_retained) as u64
+            + self.overwritten.load(Ordering::Relaxed);
+        (garbage as f64 / self.entry_count as f64).min(1.0)
+    }
+
+    // Whether the table may hold `key`, without counting it as a lookup.
+    fn may_contain(&self, key: &str) -> bool {
+        let in_range = match (&self.min_key, &self.max_key) {
+            (Some(min), Some(max)) => key >= min.as_str() && key <= max.as_str(),
+            _ => false,
+        };
+        in_range && self.bloom.might_contain(key)
+    }
+
+    /// The share of lookups for absent keys that the bloom filter failed to
+    /// reject, once enough of them have been seen to judge.
+    pub fn observed_false_positive_rate(&self) -> Option<f64> {
+        let negatives = self.bloom_negatives.load(Ordering::Relaxed);
+        let false_positives = self.bloom_false_positives.load(Ordering::Relaxed);
+        let total = negatives + false_positives;
+        (total >= BLOOM_FPR_MIN_SAMPLES).then(|| false_positives as f64 / total as f64)
+    }
+
+    // Where the first record starts, after the header of a compressed table.
+    pub(crate) fn data_start(&self) -> u64 {
+        self.dictionary.as_deref().map_or(0, compression::header_len)
+    }
+
+    fn decode(&self, stored: VeloValue, has_meta: bool) -> VeloResult<RawValue> {
+        let bytes = match &self.dictionary {
+            Some(dictionary) => dictionary.decode(stored)?,
+            None => stored,
+        };
+        Ok(RawValue { bytes, has_meta })
+    }
+
+    /// Raw value bytes per stored byte; 1.0 for uncompressed tables.
+    pub fn compression_ratio(&self) -> f64 {
+        if self.stored_value_bytes == 0 {
+            return 1.0;
+        }
+        self.raw_value_bytes as f64 / self.stored_value_bytes as f64
+    }
+
+    fn keys(&self) -> VeloResult<Vec<VeloKey>> {
+        let mut keys = Vec::with_capacity(self.entry_count);
+        let mut reader = BufReader::with_capacity(256 * 1024, File::open(&self.path)?);
+        reader.seek(SeekFrom::Start(self.data_start()))?;
+
+        loop {
+            let mut k_size_buf = [0u8; 2];
+            if reader.read_exact(&mut k_size_buf).is_err() {
+                break;
+            }
+            let mut k_buf = vec![0u8; u16::from_le_bytes(k_size_buf) as usize];
+            reader.read_exact(&mut k_buf)?;
+            let key = String::from_utf8_lossy(&k_buf).into_owned();
+
+            let mut v_size_buf = [0u8; 4];
+            reader.read_exact(&mut v_size_buf)?;
+            reader.seek_relative(decode_len(v_size_buf).0 as i64)?;
+            keys.push(key);
+        }
+
+        Ok(keys)
+    }
+
+    pub(crate) fn all_entries(&self) -> VeloResult<Vec<(VeloKey, RawValue)>> {
+        let mut entries = self.records()?;
+        entries.retain(|(_, value)| !value.is_tombstone());
+        Ok(entries)
+    }
+
+    // Every record including tombstones, in key order.
+    fn records(&self) -> VeloResult<Vec<(VeloKey, RawValue)>> {
+        let mut entries = Vec::with_capacity(self.entry_count);
+        let file = File::open(&self.path)?;
+        let mut reader = BufReader::with_capacity(256 * 1024, file);
+        reader.seek(SeekFrom::Start(self.data_start()))?;
+
+        loop {
+            let mut k_size_buf = [0u8; 2];
+            if reader.read_exact(&mut k_size_buf).is_err() {
+                break;
+            }
+            let k_size = u16::from_le_bytes(k_size_buf) as usize;
+
+            let mut k_buf = vec![0u8; k_size];
+            reader.read_exact(&mut k_buf)?;
+            let key = String::from_utf8_lossy(&k_buf).into_owned();
+
+            let mut v_size_buf = [0u8; 4];
+            reader.read_exact(&mut v_size_buf)?;
+            let (v_size, has_meta) = decode_len(v_size_buf);
+
+            let mut v_buf = vec![0u8; v_size];
+            reader.read_exact(&mut v_buf)?;
+            entries.push((key, self.decode(v_buf, has_meta)?));
+        }
+
+        Ok(entries)
+    }
+
+
+    /// Spot-checks up to `samples` index entries without reading the whole
+    /// table: each entry must point at the record it names, the records in
+    /// its run must be in order, complete and known to the bloom filter, and
+    /// a lookup must find the indexed key. The last run is read to the end
+    /// of the file.
+    pub fn verify_sample(&self, samples: usize) -> VeloResult<Result<(), String>> {
+        let len = std::fs::metadata(&self.path)?.len();
+        if len != self.size {
+            return Ok(Err(format!(
+                "file is {} bytes but {} bytes were loaded",
+                len, self.size
+            )));
+        }
+
+        let step = (self.index.len() / samples.max(1)).max(1);
+        let mut picked: Vec<_> = self.index.iter().step_by(step).collect();
+        if let Some(last) = self.index.iter().next_back() {
+            if picked.last() != Some(&last) {
+                picked.push(last);
+            }
+        }
 
+        let mut reader = BufReader::with_capacity(64 * 1024, File::open(&self.path)?);
+        for (run, (indexed_key, &offset)) in picked.iter().enumerate() {
+            let last_run = run + 1 == picked.len();
+            reader.seek(SeekFrom::Start(offset))?;
+            let mut pos = offset;
+            let mut previous: Option<String> = None;
+            for n in 0.. {
+                if !last_run && n == INDEX_INTERVAL {
+                    break;
+                }
+                if pos == self.size {
+                    break;
+                }
+                let mut k_size = [0u8; 2];
+                let mut v_size = [0u8; 4];
+                if reader.read_exact(&mut k_size).is_err() {
+                    return Ok(Err(format!("truncated record header at offset {}", pos)));
+                }
+                let mut key = vec![0u8; u16::from_le_bytes(k_size) as usize];
+                if reader.read_exact(&mut key).is_err() || reader.read_exact(&mut v_size).is_err() {
+                    return Ok(Err(format!("truncated record at offset {}", pos)));
+                }
+                let Ok(key) = String::from_utf8(key) else {
+                    return Ok(Err(format!("key at offset {} is not valid UTF-8", pos)));
+                };
+                let v_size = decode_len(v_size).0 as u64;
+                pos += 2 + key.len() as u64 + 4 + v_size;
+                if pos > self.size {
+                    return Ok(Err(format!("value of {:?} runs past the end of the file", key)));
+                }
+                reader.seek_relative(v_size as i64)?;
+
+                if n == 0 && key != **indexed_key {
+                    return Ok(Err(format!(
+                        "index entry {:?} points at offset {}, which holds {:?}",
+                        indexed_key, offset, key
+                    )));
+                }
+                if previous.as_ref().is_some_and(|previous| *previous >= key) {
+                    return Ok(Err(format!("keys out of order at {:?}", key)));
+                }
+                if !self.bloom.might_contain(&key) {
+                    return Ok(Err(format!("{:?} is missing from the bloom filter", key)));
+                }
+                previous = Some(key);
+            }
+            if self.get(indexed_key, IoBackend::Standard)?.is_none() {
+                return Ok(Err(format!("lookup of indexed key {:?} found nothing", indexed_key)));
+            }
+        }
+        Ok(Ok(()))
+    }
+
+    pub fn verify(&self) -> VeloResult<Result<usize, String>> {
+        let mut bytes = Vec::new();
+        File::open(&self.path)?.read_to_end(&mut bytes)?;
+        if bytes.len() as u64 != self.size {
+            return Ok(Err(format!(
+                "file is {} bytes but {} bytes were loaded",
+                bytes.len(),
+                self.size
+            )));
+        }
+
+        let mut pos = self.data_start() as usize;
+        let mut entries = 0usize;
+        let mut previous: Option<&[u8]> = None;
+        while pos < bytes.len() {
+            let Some(k_size) = bytes.get(pos..pos + 2) else {
+                return Ok(Err(format!("truncated record header at offset {}", pos)));
+            };
+            let k_size = u16::from_le_bytes(k_size.try_into().unwrap()) as usize;
+            let key_start = pos + 2;
+            let Some(key) = bytes.get(key_start..key_start + k_size) else {
+                return Ok(Err(format!("truncated key at offset {}", pos)));
+            };
+            let value_len_start = key_start + k_size;
+            let Some(v_size) = bytes.get(value_len_start..value_len_start + 4) else {
+                return Ok(Err(format!("truncated value length at offset {}", pos)));
+            };
+            let v_size = decode_len(v_size.try_into().unwrap()).0;
+            let next = value_len_start + 4 + v_size;
+            if next > bytes.len() {
+                return Ok(Err(format!("truncated value at offset {}", pos)));
+            }
+            if std::str::from_utf8(key).is_err() {
+                return Ok(Err(format!("key at offset {} is not valid UTF-8", pos)));
+            }
+            if previous.is_some_and(|prev| prev >= key) {
+                return Ok(Err(format!("keys out of order at offset {}", pos)));
+            }
+            previous = Some(key);
+            entries += 1;
+            pos = next;
+        }
+
+        if entries != self.entry_count {
+            return Ok(Err(format!(
+                "found {} entries but {} were indexed",
+                entries, self.entry_count
+            )));
+        }
+        if crc32fast::hash(&bytes) != self.checksum {
+            return Ok(Err("checksum mismatch".to_string()));
+        }
+        Ok(Ok(entries))
+    }
+}
+
+impl SSTable {
+    fn create<P: AsRef<Path>>(
+        path: P,
+        id: u64,
+        data: &Memtable,
+        false_positive_rate: f64,
+        faults: Option<&FaultInjectionConfig>,
+    ) -> VeloResult<Self> {
+        let sstable_path = sstable_path(path.as_ref(), id);
+        let tmp_path = temp_sstable_path(&sstable_path);
+        let mut sstable = Self::create_at(
+            tmp_path.clone(),
+            id,
+            data.len(),
+            data.iter(),
+            false_positive_rate,
+            None,
+        )?;
+        // An injected fsync failure fails the table as a real one would,
+        // before it is installed.
+        if let Err(e) = faults.map_or(Ok(()), |f| f.inject(StorageOp::Fsync)) {
+            let _ = std::fs::remove_file(&tmp_path);
+            return Err(e);
+        }
+        install_sstable(&tmp_path, &sstable_path)?;
+        sstable.path = sstable_path;
+        Ok(sstable)
+    }
+
+    // `data` must be in key order.
+    fn create_at<'a>(
+        sstable_path: PathBuf,
+        id: u64,
+        entries: usize,
+        data: impl IntoIterator<Item = (&'a str, &'a [u8], bool)>,
+        false_positive_rate: f64,
+        compression: Option<(compression::Dictionary, policy::CompressionPlan)>,
+    ) -> VeloResult<Self> {
+        let mut writer = SSTableWriter::new(sstable_path, id, entries, false_positive_rate)?
+            .compress_with(compression)?;
+        for (key, value, has_meta) in data {
+            writer.add(key, value, has_meta)?;
+        }
+        writer.finish()
+    }
+
+    /// Returns the stored value, an empty value for a tombstone, or `None`
+    /// when the table does not hold the key.
     #[inline]
-    fn get(&self, key: &str) -> VeloResult<Option<VeloValue>> {
+    fn get(&self, key: &str, backend: IoBackend) -> VeloResult<Option<RawValue>> {
+        self.get_from(key, || Ok(io_backend::TableReader::open(&self.path, backend)?))
+    }
+
+    // Like `get`, reading through whatever `open` returns once the filter and
+    // key range say the table may hold the key.
+    fn get_from<R: Read + Seek>(
+        &self,
+        key: &str,
+        open: impl FnOnce() -> VeloResult<R>,
+    ) -> VeloResult<Option<RawValue>> {
 
         if !self.bloom.might_contain(key) {
+            self.bloom_negatives.fetch_add(1, Ordering::Relaxed);
             return Ok(None);
         }
 
 
         if let (Some(min), Some(max)) = (&self.min_key, &self.max_key) {
             if key < min.as_str() || key > max.as_str() {
+                self.bloom_false_positives.fetch_add(1, Ordering::Relaxed);
                 return Ok(None);
             }
         }
@@ -748,11 +2096,11 @@ impl SSTable {
 
         let offset = match self.index.range(..=key.to_string()).next_back() {
             Some((_, &off)) => off,
-            None => 0,
+            None => self.data_start(),
         };
 
 
-        let mut file = BufReader::with_capacity(64 * 1024, File::open(&self.path)?);
+        let mut file = open()?;
         file.seek(SeekFrom::Start(offset))?;
 
 
@@ -773,18 +2121,12 @@ impl SSTable {
             if file.read_exact(&mut v_size_buf).is_err() {
                 break;
             }
-            let v_size = u32::from_le_bytes(v_size_buf) as usize;
+            let (v_size, has_meta) = decode_len(v_size_buf);
 
             if found_key == key {
                 let mut v_buf = vec![0u8; v_size];
                 file.read_exact(&mut v_buf)?;
-
-
-                if v_buf.is_empty() {
-                    return Ok(None);
-                }
-
-                return Ok(Some(v_buf));
+                return Ok(Some(self.decode(v_buf, has_meta)?));
             } else if found_key.as_ref() > key {
                 break;
             } else {
@@ -792,21 +2134,148 @@ impl SSTable {
             }
         }
 
+        self.bloom_false_positives.fetch_add(1, Ordering::Relaxed);
         Ok(None)
     }
-}
 
+    // Records with `start <= key < end`, tombstones included, in key order.
+    // They are read as the iterator is advanced, so a caller that stops
+    // early only reads what it used.
+    fn range_from<R: Read + Seek>(
+        &self,
+        mut file: R,
+        start: &str,
+        end: Option<&str>,
+    ) -> VeloResult<SSTableRange<'_, R>> {
+        let past_end = |key: &str| end.is_some_and(|end| key >= end);
+        let overlaps = match (&self.min_key, &self.max_key) {
+            (Some(min), Some(max)) => max.as_str() >= start && !past_end(min),
+            _ => false,
+        };
+        if overlaps {
+            let offset = match self.index.range(..=start.to_string()).next_back() {
+                Some((_, &off)) => off,
+                None => self.data_start(),
+            };
+            file.seek(SeekFrom::Start(offset))?;
+        }
+        Ok(SSTableRange {
+            sstable: self,
+            file,
+            start: start.to_string(),
+            end: end.map(str::to_string),
+            done: !overlaps,
+        })
+    }
+
+    // Re-hashes the whole file and compares it with the checksum taken when
+    // the table was written.
+    fn verify_checksum<R: Read + Seek>(&self, mut file: R) -> VeloResult<()> {
+        file.seek(SeekFrom::Start(0))?;
+        let mut hasher = crc32fast::Hasher::new();
+        let mut buf = vec![0u8; 64 * 1024];
+        loop {
+            let n = file.read(&mut buf)?;
+            if n == 0 {
+                break;
+            }
+            hasher.update(&buf[..n]);
+        }
+        let actual = hasher.finalize();
+        if actual != self.checksum {
+            return Err(VeloError::CorruptedData(format!(
+                "SSTable {:?} checksum {:08x} does not match {:08x}",
+                self.path, actual, self.checksum
+            )));
+        }
+        Ok(())
+    }
+}
 
 pub struct Velocity {
-    pub memtable: Arc<RwLock<BTreeMap<VeloKey, VeloValue>>>,
-    pub sstables: Arc<RwLock<Vec<SSTable>>>,
+    pub memtable: Arc<RwLock<Memtable>>,
+    pub sstables: Arc<RwLock<Vec<Arc<SSTable>>>>,
     cache: Arc<Mutex<UltraFastCache>>,
     filter: Arc<RwLock<BloomFilter>>,
-    wal: Arc<Mutex<WriteAheadLog>>,
-    write_queue: AsyncWriteQueue,
+    wal: WalShards,
+    // One per WAL shard, unless background threads are off.
+    write_queues: Vec<AsyncWriteQueue>,
     config: VelocityConfig,
     data_dir: PathBuf,
+    layout: DataLayout,
     next_sstable_id: Arc<Mutex<u64>>,
+    lifetime: Mutex<LifetimeStats>,
+    lifetime_bytes_written: AtomicU64,
+    started_at: Instant,
+    write_gate: RwLock<()>,
+    cache_hits: AtomicU64,
+    cache_misses: AtomicU64,
+    read_sources: read_sources::ReadSourceMetrics,
+    // Bytes of the entries read by scans and range reads since open.
+    scan_bytes: AtomicU64,
+    record_metadata: AtomicBool,
+    soft_delete: AtomicBool,
+    // A `CachePolicy`, changed at runtime by `set_cache_policy`.
+    cache_policy: AtomicU8,
+    hot_keys: hotkeys::HotKeyTracker,
+    nocase_index: AtomicBool,
+    meta_lock: Mutex<()>,
+    compaction_log: Mutex<VecDeque<CompactionLogEntry>>,
+    compaction_lock: Mutex<()>,
+    compacting: AtomicBool,
+    compaction_picker: RwLock<Option<Arc<dyn compaction::CompactionPicker>>>,
+    // Levels a `CompactionPicker` assigned, by table id; tables without an
+    // entry are at level 0.
+    table_levels: Mutex<BTreeMap<u64, u32>>,
+    // Unix milliseconds of the oldest and newest write in each table, as
+    // far as flushes can tell.
+    table_write_times: Mutex<BTreeMap<u64, (u64, u64)>>,
+    // When the oldest write still in the memtable arrived.
+    memtable_since: Mutex<Option<Instant>>,
+    // Highest sequence applied to the memtable, and highest one known to be
+    // in an SSTable; WAL records at or below the latter are not replayed.
+    last_sequence: AtomicU64,
+    flushed_sequence: AtomicU64,
+    load_problems: Vec<(PathBuf, String)>,
+    quarantined: Vec<QuarantinedTable>,
+    cache_warmup_keys: Mutex<VecDeque<VeloKey>>,
+    write_listeners: RwLock<Vec<(u64, WriteListener)>>,
+    maintenance_listeners: Arc<RwLock<Vec<(u64, MaintenanceListener)>>>,
+    maintenance_events: OnceLock<mpsc::Sender<compaction::MaintenanceProgress>>,
+    next_listener_id: AtomicU64,
+    wal_retention: replication::WalRetention,
+    read_only: bool,
+    background: Option<Arc<background::BackgroundPool>>,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct SequenceState {
+    flushed_sequence: u64,
+}
+
+// The SSTables that make up the database, by id in age order, with the
+// CRC32 each file had when it was written. Ids below `next_id` were handed
+// out before the manifest was written, so such a file that is not listed
+// was replaced by a merge or rewrite.
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Manifest {
+    sstables: Vec<u64>,
+    #[serde(default)]
+    next_id: u64,
+    #[serde(default)]
+    checksums: BTreeMap<u64, u32>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    levels: BTreeMap<u64, u32>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    write_times: BTreeMap<u64, (u64, u64)>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct CompactionLogEntry {
+    pub timestamp_ms: u64,
+    pub sstable_id: Option<u64>,
+    pub action: String,
+    pub reason: String,
 }
 
 #[derive(Clone)]
@@ -819,10 +2288,49 @@ pub struct VelocityConfig {
     pub memory_only_mode: bool,
     pub batch_wal_writes: bool,
     pub adaptive_cache: bool,
+    /// Whether writes store their values in the read cache.
+    pub cache_policy: CachePolicy,
+    /// Sampled tracking of the most read and written keys.
+    pub hot_keys: hotkeys::HotKeyConfig,
     pub enable_metrics: bool,
     pub metrics_interval: Duration,
     pub target_cache_hit_rate: f64,
     pub wal_sync_mode: WalSyncMode,
+    /// WAL files written side by side, each with its own writer; keys are
+    /// assigned to them by hash. Replicas can only follow a single WAL.
+    pub wal_shards: usize,
+    /// Align memtable arena chunks to 2 MiB and ask Linux to back them with
+    /// transparent huge pages.
+    pub memtable_huge_pages: bool,
+    /// Falls back to `Standard` with a warning where io_uring is unavailable.
+    pub io_backend: IoBackend,
+    pub record_metadata: bool,
+    pub data_layout: LayoutMode,
+    pub recovery_progress: Option<RecoveryCallback>,
+    pub fault_injection: Option<FaultInjectionConfig>,
+    /// When false, WAL records are written on the calling thread and no
+    /// helper threads are started.
+    pub background_threads: bool,
+    /// The threads that run cache fills and full compactions.
+    pub background_pool: background::BackgroundPoolConfig,
+    /// Upper bound on how fast a full compaction reads its input, in bytes
+    /// per second. 0 means no limit.
+    pub compaction_io_budget: u64,
+    /// Chooses tables to merge after each flush; size-tiered by default.
+    /// `None` only rewrites tables to reclaim space. See
+    /// `compaction::CompactionStrategy` for the built-in pickers.
+    pub compaction_picker: Option<Arc<dyn compaction::CompactionPicker>>,
+    /// TTL and compression settings by key prefix.
+    pub key_policies: Vec<policy::PrefixPolicy>,
+    /// Whether cached keys are saved on close and loaded again on open.
+    pub cache_warmup: warmup::CacheWarmup,
+    /// Turns on deterministic hashing for the whole process with this seed;
+    /// see `hashing::set_deterministic_seed`.
+    pub hash_seed: Option<u64>,
+    pub wal_recovery: WalRecovery,
+    /// Opens an existing database for reads only; writes and flushes are
+    /// refused.
+    pub read_only: bool,
 }
 
 impl Default for VelocityConfig {
@@ -836,10 +2344,46 @@ impl Default for VelocityConfig {
             memory_only_mode: false,
             batch_wal_writes: true,
             adaptive_cache: true,
+            cache_policy: CachePolicy::WriteThrough,
+            hot_keys: hotkeys::HotKeyConfig::default(),
             enable_metrics: true,
             metrics_interval: Duration::from_secs(60),
             target_cache_hit_rate: 0.85,
             wal_sync_mode: WalSyncMode::Batch,
+            wal_shards: 1,
+            memtable_huge_pages: false,
+            io_backend: IoBackend::Standard,
+            record_metadata: false,
+            data_layout: LayoutMode::default(),
+            recovery_progress: None,
+            fault_injection: None,
+            background_threads: true,
+            background_pool: background::BackgroundPoolConfig::default(),
+            compaction_io_budget: 0,
+            compaction_picker: compaction::CompactionStrategy::default().picker(),
+            key_policies: Vec::new(),
+            cache_warmup: warmup::CacheWarmup::Off,
+            hash_seed: None,
+            wal_recovery: WalRecovery::Replay,
+            read_only: false,
+        }
+    }
+}
+
+impl VelocityConfig {
+    /// A small, deterministic profile for tests against real storage: tiny
+    /// memtable and cache, no background threads, and every write in the
+    /// WAL before `put` returns.
+    pub fn minimal() -> Self {
+        Self {
+            max_memtable_size: 64,
+            cache_size: 64,
+            compaction_threshold: 4,
+            adaptive_cache: false,
+            enable_metrics: false,
+            wal_sync_mode: WalSyncMode::EveryWrite,
+            background_threads: false,
+            ..Self::default()
         }
     }
 }
@@ -850,25 +2394,97 @@ impl Velocity {
     }
 
     pub fn open_with_config<P: AsRef<Path>>(path: P, config: VelocityConfig) -> VeloResult<Self> {
-        let data_dir = path.as_ref().to_path_buf();
-        create_dir_all(&data_dir)?;
+        Self::open_checked(path.as_ref(), config, false)
+    }
+
+    /// Like `open_with_config`, but spot-checks every SSTable before the
+    /// database is used (see `SSTable::verify_sample`). Tables that fail, or
+    /// whose checksum no longer matches the manifest, are moved to `corrupt/`
+    /// and left out; `quarantined_tables` lists them.
+    pub fn open_with_verification<P: AsRef<Path>>(
+        path: P,
+        config: VelocityConfig,
+    ) -> VeloResult<Self> {
+        Self::open_checked(path.as_ref(), config, true)
+    }
+
+    fn open_checked(path: &Path, mut config: VelocityConfig, verify: bool) -> VeloResult<Self> {
+        policy::validate_policies(&config.key_policies)?;
+        if let Some(seed) = config.hash_seed {
+            hashing::set_deterministic_seed(seed);
+        }
+        let data_dir = path.to_path_buf();
+        let record_metadata = config.record_metadata;
+        let cache_policy = config.cache_policy;
+        let hot_keys = hotkeys::HotKeyTracker::new(&config.hot_keys);
+        let compaction_picker = config.compaction_picker.clone();
+        if config.read_only && !data_dir.is_dir() {
+            return Err(VeloError::InvalidOperation(format!(
+                "Cannot open {:?} read-only: no database there",
+                data_dir
+            )));
+        }
+        let read_only = config.read_only || !matches!(config.wal_recovery, WalRecovery::Replay);
+        if !read_only {
+            create_dir_all(&data_dir)?;
+        }
+        let format = format::FormatInfo::read(&data_dir)?;
+        if let Some(format) = &format {
+            format.check()?;
+        }
+        let mut layout = DataLayout::new(&data_dir, config.data_layout);
+        if read_only {
+            layout = layout.read_only_view()?;
+        } else {
+            layout.migrate()?;
+        }
 
-        let wal = Arc::new(Mutex::new(WriteAheadLog::new(
-            data_dir.join("velocity"),
+        if let Some(faults) = &config.fault_injection {
+            faults.validate()?;
+            log::warn!(
+                "Fault injection is enabled for {:?}; storage will misbehave on purpose",
+                data_dir
+            );
+        }
+
+        config.io_backend = config.io_backend.resolve();
+        // A read-only database opens no WAL files; its log is only read,
+        // by recovery.
+        let wal = WalShards::open(
+            &layout.wal_dir(),
+            if read_only { 0 } else { config.wal_shards.max(1) },
             config.wal_sync_mode,
-        )?));
-        let memtable = Arc::new(RwLock::new(BTreeMap::new()));
+            config.io_backend,
+            config.fault_injection.as_ref(),
+        )?;
+        let memtable = Arc::new(RwLock::new(Memtable::new(config.memtable_huge_pages)));
         let filter = Arc::new(RwLock::new(BloomFilter::new(
             config.max_memtable_size * 10,
             config.bloom_false_positive_rate,
         )));
 
-        let write_queue = AsyncWriteQueue::new(
-            memtable.clone(),
-            filter.clone(),
-            wal.clone(),
-            config.clone(),
-        );
+        let write_queues = if config.background_threads {
+            wal.shards
+                .iter()
+                .enumerate()
+                .map(|(shard, shard_wal)| {
+                    AsyncWriteQueue::new(
+                        memtable.clone(),
+                        filter.clone(),
+                        shard_wal.clone(),
+                        shard,
+                        config.clone(),
+                    )
+                })
+                .collect()
+        } else {
+            Vec::new()
+        };
+        let background = if config.background_threads {
+            Some(Arc::new(background::BackgroundPool::new(&config.background_pool)?))
+        } else {
+            None
+        };
 
         let mut engine = Self {
             memtable: memtable.clone(),
@@ -876,47 +2492,339 @@ impl Velocity {
             cache: Arc::new(Mutex::new(UltraFastCache::new(config.cache_size))),
             filter: filter.clone(),
             wal,
-            write_queue,
+            write_queues,
             config,
             data_dir: data_dir.clone(),
+            layout: layout.clone(),
             next_sstable_id: Arc::new(Mutex::new(0)),
+            lifetime: Mutex::new(LifetimeStats::default()),
+            lifetime_bytes_written: AtomicU64::new(0),
+            started_at: Instant::now(),
+            write_gate: RwLock::new(()),
+            cache_hits: AtomicU64::new(0),
+            cache_misses: AtomicU64::new(0),
+            read_sources: read_sources::ReadSourceMetrics::new(),
+            scan_bytes: AtomicU64::new(0),
+            record_metadata: AtomicBool::new(record_metadata),
+            soft_delete: AtomicBool::new(false),
+            cache_policy: AtomicU8::new(cache_policy as u8),
+            hot_keys,
+            nocase_index: AtomicBool::new(false),
+            meta_lock: Mutex::new(()),
+            compaction_log: Mutex::new(VecDeque::new()),
+            compaction_lock: Mutex::new(()),
+            compacting: AtomicBool::new(false),
+            compaction_picker: RwLock::new(compaction_picker),
+            table_levels: Mutex::new(BTreeMap::new()),
+            table_write_times: Mutex::new(BTreeMap::new()),
+            memtable_since: Mutex::new(None),
+            last_sequence: AtomicU64::new(0),
+            flushed_sequence: AtomicU64::new(0),
+            load_problems: Vec::new(),
+            quarantined: Vec::new(),
+            cache_warmup_keys: Mutex::new(VecDeque::new()),
+            write_listeners: RwLock::new(Vec::new()),
+            maintenance_listeners: Arc::new(RwLock::new(Vec::new())),
+            maintenance_events: OnceLock::new(),
+            next_listener_id: AtomicU64::new(0),
+            wal_retention: replication::WalRetention::open(&layout, read_only),
+            read_only,
+            background,
         };
 
-        engine.recover_from_wal()?;
-        engine.load_sstables()?;
+        engine.load_lifetime_stats()?;
+        engine.load_flushed_sequence();
+
+        let callback = engine.config.recovery_progress.clone();
+        let mut reporter = RecoveryReporter::new(callback.as_ref(), &data_dir);
+        engine.recover_from_wal(&mut reporter)?;
+        engine.load_sstables(&mut reporter)?;
+        if verify {
+            engine.quarantine_inconsistent_tables()?;
+        }
+        reporter.report(RecoveryPhase::Complete, 0, 0, 0);
+        if !engine.read_only {
+            engine.load_cache_keys()?;
+            engine.record_format(format)?;
+        }
 
         Ok(engine)
     }
 
-    fn recover_from_wal(&mut self) -> VeloResult<()> {
-        let wal = self.wal.lock().unwrap();
-        let operations = wal.recover()?;
-        drop(wal);
+    fn load_lifetime_stats(&mut self) -> VeloResult<()> {
+        let path = self.layout.meta_dir().join(LIFETIME_STATS_FILE);
+        let mut stats = if path.exists() {
+            match serde_json::from_str::<LifetimeStats>(&std::fs::read_to_string(&path)?) {
+                Ok(stats) => stats,
+                Err(e) => {
+                    log::warn!("Ignoring unreadable lifetime stats {:?}: {}", path, e);
+                    LifetimeStats::default()
+                }
+            }
+        } else {
+            LifetimeStats::default()
+        };
+        stats.starts += 1;
 
-        if operations.is_empty() {
+        self.lifetime_bytes_written = AtomicU64::new(stats.bytes_written);
+        self.lifetime = Mutex::new(stats);
+        if self.read_only {
             return Ok(());
         }
+        self.persist_lifetime_stats()
+    }
+
+    fn load_flushed_sequence(&mut self) {
+        let path = self.layout.meta_dir().join(SEQUENCE_FILE);
+        let Ok(content) = std::fs::read_to_string(&path) else {
+            return;
+        };
+        match serde_json::from_str::<SequenceState>(&content) {
+            Ok(state) => {
+                self.flushed_sequence = AtomicU64::new(state.flushed_sequence);
+                self.last_sequence = AtomicU64::new(state.flushed_sequence);
+                hlc::clock().observe(HlcTimestamp::from_u64(state.flushed_sequence));
+            }
+            Err(e) => log::warn!("Ignoring unreadable sequence state {:?}: {}", path, e),
+        }
+    }
+
+    fn persist_flushed_sequence(&self, sequence: u64) -> VeloResult<()> {
+        let content = serde_json::to_string(&SequenceState {
+            flushed_sequence: sequence,
+        })
+        .map_err(|e| VeloError::InvalidOperation(format!("Failed to encode sequence: {}", e)))?;
+        fsutil::atomic_write(self.layout.meta_dir().join(SEQUENCE_FILE), content)?;
+        self.flushed_sequence.store(sequence, Ordering::Release);
+        Ok(())
+    }
+
+    // Called with the memtable write lock held, so sequences follow the
+    // order in which writes become visible.
+    fn next_sequence(&self) -> u64 {
+        let sequence = hlc::clock().now().as_u64();
+        self.last_sequence.fetch_max(sequence, Ordering::AcqRel);
+        sequence
+    }
+
+    pub fn on_write<F>(&self, listener: F) -> u64
+    where
+        F: Fn(u64, &str) + Send + Sync + 'static,
+    {
+        let id = self.next_listener_id.fetch_add(1, Ordering::Relaxed);
+        self.write_listeners
+            .write()
+            .unwrap()
+            .push((id, Arc::new(listener)));
+        id
+    }
+
+    pub fn remove_write_listener(&self, id: u64) -> bool {
+        let mut listeners = self.write_listeners.write().unwrap();
+        let before = listeners.len();
+        listeners.retain(|(listener_id, _)| *listener_id != id);
+        listeners.len() != before
+    }
+
+    fn notify_write(&self, sequence: u64, key: &str) {
+        for (_, listener) in self.write_listeners.read().unwrap().iter() {
+            listener(sequence, key);
+        }
+    }
 
+    /// Calls `listener` with the progress of every flush and merge; see
+    /// `compaction::MaintenanceProgress`. Listeners are called in order on a
+    /// thread of their own, never while the operation holds a lock, so they
+    /// may use the database.
+    pub fn on_maintenance<F>(&self, listener: F) -> u64
+    where
+        F: Fn(&compaction::MaintenanceProgress) + Send + Sync + 'static,
+    {
+        self.maintenance_events.get_or_init(|| {
+            let (sender, receiver) = mpsc::channel::<compaction::MaintenanceProgress>();
+            let listeners = self.maintenance_listeners.clone();
+            thread::Builder::new()
+                .name("velocity-maintenance".to_string())
+                .spawn(move || {
+                    for progress in receiver {
+                        let listeners: Vec<MaintenanceListener> = listeners
+                            .read()
+                            .unwrap()
+                            .iter()
+                            .map(|(_, listener)| listener.clone())
+                            .collect();
+                        for listener in listeners {
+                            listener(&progress);
+                        }
+                    }
+                })
+                .expect("failed to start the maintenance event thread");
+            sender
+        });
+        let id = self.next_listener_id.fetch_add(1, Ordering::Relaxed);
+        self.maintenance_listeners
+            .write()
+            .unwrap()
+            .push((id, Arc::new(listener)));
+        id
+    }
+
+    pub fn remove_maintenance_listener(&self, id: u64) -> bool {
+        let mut listeners = self.maintenance_listeners.write().unwrap();
+        let before = listeners.len();
+        listeners.retain(|(listener_id, _)| *listener_id != id);
+        listeners.len() != before
+    }
+
+    fn notify_maintenance(&self, progress: compaction::MaintenanceProgress) {
+        if let Some(events) = self.maintenance_events.get() {
+            let _ = events.send(progress);
+        }
+    }
+
+    /// The sequence of the most recent write, and of the most recent write
+    /// that is known to be in an SSTable.
+    pub fn sequences(&self) -> (u64, u64) {
+        (
+            self.last_sequence.load(Ordering::Acquire),
+            self.flushed_sequence.load(Ordering::Acquire),
+        )
+    }
+
+    pub fn lifetime_stats(&self) -> LifetimeStats {
+        let mut stats = self.lifetime.lock().unwrap().clone();
+        stats.bytes_written = self.lifetime_bytes_written.load(Ordering::Relaxed);
+        stats.uptime_secs += self.started_at.elapsed().as_secs();
+        stats
+    }
+
+    pub fn record_backup(&self) -> VeloResult<()> {
+        self.lifetime.lock().unwrap().last_backup_at = Some(
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs(),
+        );
+        self.persist_lifetime_stats()
+    }
+
+    fn persist_lifetime_stats(&self) -> VeloResult<()> {
+        let content = serde_json::to_string_pretty(&self.lifetime_stats()).map_err(|e| {
+            VeloError::InvalidOperation(format!("Failed to encode lifetime stats: {}", e))
+        })?;
+        fsutil::atomic_write(self.layout.meta_dir().join(LIFETIME_STATS_FILE), content)?;
+        Ok(())
+    }
+
+    fn recover_from_wal(&mut self, reporter: &mut RecoveryReporter) -> VeloResult<()> {
+        let operations = self.wal.recover(&mut |records, bytes_done, bytes_total| {
+            reporter.report(RecoveryPhase::WalReplay, records, bytes_done, bytes_total)
+        })?;
+
+        // Records already flushed are skipped, and the rest are applied in
+        // sequence order so the newest write to each key wins regardless of
+        // where it sits in the file. Skipped keys still go into the filter;
+        // `load_sstables` adds the keys of every table after this.
+        let flushed = self.flushed_sequence.load(Ordering::Acquire);
+        let last = match &self.config.wal_recovery {
+            WalRecovery::Replay => u64::MAX,
+            WalRecovery::UpToSequence(last) => *last,
+            WalRecovery::Skip | WalRecovery::Inspect(_) => flushed,
+        };
         let mut memtable = self.memtable.write().unwrap();
         let mut filter = self.filter.write().unwrap();
+        let mut operations: Vec<_> = operations
+            .into_iter()
+            .filter(|(sequence, key, _)| {
+                filter.add(key);
+                *sequence > flushed
+            })
+            .collect();
+        operations.sort_by_key(|(sequence, _, _)| *sequence);
+        if let WalRecovery::Inspect(callback) = &self.config.wal_recovery {
+            for (sequence, key, value) in &operations {
+                callback(*sequence, key, &value.bytes);
+            }
+        }
+        operations.retain(|(sequence, _, _)| *sequence <= last);
+        if operations.is_empty() {
+            return Ok(());
+        }
 
-        for (key, value) in operations {
-            filter.add(&key);
-            memtable.insert(key, value);
+        for (sequence, key, value) in operations {
+            self.last_sequence.fetch_max(sequence, Ordering::AcqRel);
+            memtable.insert(&key, &value.bytes, value.has_meta);
         }
+        *self.memtable_since.lock().unwrap() = Some(Instant::now());
 
         Ok(())
     }
 
     pub fn wal_integrity_report(&self) -> VeloResult<WalIntegrityReport> {
-        let wal = self.wal.lock().unwrap();
-        let report = wal.verify_integrity()?;
+        self.wal.verify_integrity()
+    }
+
+    pub fn sstable_integrity_report(&self) -> VeloResult<SSTableIntegrityReport> {
+        let mut report = SSTableIntegrityReport::default();
+        report.damaged_tables.extend(self.load_problems.iter().cloned());
+        for sstable in self.sstables.read().unwrap().iter() {
+            report.tables_checked += 1;
+            match sstable.verify()? {
+                Ok(entries) => report.total_entries += entries,
+                Err(_) if report.damaged_tables.iter().any(|(path, _)| *path == sstable.path) => {}
+                Err(problem) => report.damaged_tables.push((sstable.path.clone(), problem)),
+            }
+        }
         Ok(report)
     }
 
-    fn load_sstables(&mut self) -> VeloResult<()> {
+    /// Tables moved to `corrupt/` by `open_with_verification`.
+    pub fn quarantined_tables(&self) -> &[QuarantinedTable] {
+        &self.quarantined
+    }
 
-        let entries = match std::fs::read_dir(&self.data_dir) {
+    fn quarantine_inconsistent_tables(&mut self) -> VeloResult<()> {
+        let mut sstables = self.sstables.write().unwrap();
+        let mut kept = Vec::with_capacity(sstables.len());
+        for sstable in sstables.drain(..) {
+            let problem = match self.load_problems.iter().find(|(path, _)| *path == sstable.path) {
+                Some((_, problem)) => Some(problem.clone()),
+                None => sstable
+                    .verify_sample(VERIFY_SAMPLES)
+                    .unwrap_or_else(|e| Err(e.to_string()))
+                    .err(),
+            };
+            let Some(reason) = problem else {
+                kept.push(sstable);
+                continue;
+            };
+
+            log::error!("SSTable {:?} is inconsistent ({}); quarantining it", sstable.path, reason);
+            let mut path = sstable.path.clone();
+            if !self.read_only {
+                let dir = self.layout.corrupt_dir();
+                create_dir_all(&dir)?;
+                let target = dir.join(sstable.path.file_name().unwrap_or_default());
+                std::fs::rename(&sstable.path, &target)?;
+                path = target;
+            }
+            self.load_problems.retain(|(problem_path, _)| *problem_path != sstable.path);
+            self.quarantined.push(QuarantinedTable {
+                id: sstable.id,
+                path,
+                reason,
+            });
+        }
+        *sstables = kept;
+        if !self.quarantined.is_empty() && !self.read_only {
+            self.persist_manifest(&sstables)?;
+        }
+        Ok(())
+    }
+
+    fn load_sstables(&mut self, reporter: &mut RecoveryReporter) -> VeloResult<()> {
+
+        let entries = match std::fs::read_dir(self.layout.sst_dir()) {
             Ok(entries) => entries,
             Err(_) => return Ok(()),
         };
@@ -927,6 +2835,14 @@ impl Velocity {
 
         for entry in entries.flatten() {
             let path = entry.path();
+            let name = entry.file_name().to_string_lossy().into_owned();
+            if name.ends_with(".vdb.tmp") || name.ends_with(".vdb.part") {
+                if !self.read_only {
+                    log::warn!("Removing incomplete SSTable {:?} left by an interrupted write", path);
+                    let _ = std::fs::remove_file(&path);
+                }
+                continue;
+            }
             if let Some(ext) = path.extension() {
                 if ext == "vdb" {
                     if let Some(file_name) = path.file_stem() {
@@ -947,25 +2863,191 @@ impl Velocity {
 
         sstable_files.sort_by_key(|(id, _)| *id);
 
+        // Flushed tables renamed into place just before a crash may not be
+        // listed yet; they are complete, so they are loaded after the listed
+        // ones. Unlisted files with older ids were merged or rewritten away.
+        let manifest = self.read_manifest();
+        if let Some(manifest) = &manifest {
+            sstable_files.retain(|(id, path)| {
+                if *id >= manifest.next_id || manifest.sstables.contains(id) {
+                    return true;
+                }
+                log::warn!(
+                    "SSTable {:?} was replaced before a crash; removing it",
+                    path
+                );
+                if !self.read_only {
+                    let _ = std::fs::remove_file(path);
+                }
+                false
+            });
+            sstable_files.sort_by_key(|(id, _)| {
+                let listed = manifest.sstables.iter().position(|listed| listed == id);
+                (listed.is_none(), listed, *id)
+            });
+            for id in &manifest.sstables {
+                if !sstable_files.iter().any(|(file_id, _)| file_id == id) {
+                    let path = sstable_path(&self.layout.sst_dir(), *id);
+                    log::error!("SSTable {:?} is listed in the manifest but missing", path);
+                    self.load_problems
+                        .push((path, "listed in the manifest but missing".to_string()));
+                }
+            }
+            for (id, path) in &sstable_files {
+                if !manifest.sstables.contains(id) {
+                    log::warn!("SSTable {:?} is not in the manifest; adding it", path);
+                }
+            }
+        }
+
+        let bytes_total: u64 = sstable_files
+            .iter()
+            .filter_map(|(_, path)| std::fs::metadata(path).ok())
+            .map(|metadata| metadata.len())
+            .sum();
+        let mut bytes_done = 0u64;
 
+        // The engine-level filter starts empty on open, so it is fed with
+        // every table's keys; otherwise reads of keys that only live in
+        // tables would be answered as absent.
         let mut sstables = self.sstables.write().unwrap();
+        let mut filter = self.filter.write().unwrap();
         for (id, path) in sstable_files {
-            match Self::load_sstable(id, path) {
-                Ok(sstable) => sstables.push(sstable),
+            match Self::load_sstable(id, path, &sstables, &mut filter) {
+                Ok(mut sstable) => {
+                    let expected = manifest
+                        .as_ref()
+                        .and_then(|manifest| manifest.checksums.get(&id));
+                    if let Some(expected) = expected.filter(|c| **c != sstable.checksum) {
+                        let problem = format!(
+                            "checksum {:08x} does not match {:08x} recorded when it was written",
+                            sstable.checksum, expected
+                        );
+                        log::error!("SSTable {:?}: {}", sstable.path, problem);
+                        self.load_problems.push((sstable.path.clone(), problem));
+                        // Keep the written checksum so verified reads refuse
+                        // the damaged file.
+                        sstable.checksum = *expected;
+                    }
+                    bytes_done += sstable.size;
+                    sstables.push(Arc::new(sstable));
+                }
                 Err(e) => {
                     eprintln!("Warning: Failed to load SSTable {}: {}", id, e);
                     continue;
                 }
             }
+            reporter.report(
+                RecoveryPhase::SSTableLoad,
+                sstables.len() as u64,
+                bytes_done,
+                bytes_total,
+            );
         }
 
 
-        *self.next_sstable_id.lock().unwrap() = max_id + 1;
+        drop(filter);
+        if let Some(manifest) = &manifest {
+            *self.table_levels.get_mut().unwrap() = manifest
+                .levels
+                .iter()
+                .filter(|(id, _)| sstables.iter().any(|t| t.id == **id))
+                .map(|(id, level)| (*id, *level))
+                .collect();
+            for sstable in sstables.iter() {
+                if let Some(level) = manifest.levels.get(&sstable.id) {
+                    sstable.level.store(*level, Ordering::Relaxed);
+                }
+            }
+        }
+        // Tables from before write times were recorded count as written
+        // when their file was.
+        let write_times = self.table_write_times.get_mut().unwrap();
+        for sstable in sstables.iter() {
+            let recorded = manifest
+                .as_ref()
+                .and_then(|manifest| manifest.write_times.get(&sstable.id).copied());
+            let times = recorded.unwrap_or_else(|| {
+                let modified = std::fs::metadata(&sstable.path)
+                    .and_then(|metadata| metadata.modified())
+                    .ok()
+                    .and_then(|modified| modified.duration_since(std::time::UNIX_EPOCH).ok())
+                    .map_or(0, |since| since.as_millis() as u64);
+                (modified, modified)
+            });
+            write_times.insert(sstable.id, times);
+        }
+        let next_id = manifest.as_ref().map_or(0, |manifest| manifest.next_id);
+        *self.next_sstable_id.lock().unwrap() = next_id.max(max_id + 1);
+        if !self.read_only {
+            self.persist_manifest(&sstables)?;
+        }
+        drop(sstables);
 
         Ok(())
     }
 
-    fn load_sstable(id: u64, path: PathBuf) -> VeloResult<SSTable> {
+    fn read_manifest(&self) -> Option<Manifest> {
+        let path = self.layout.meta_dir().join(MANIFEST_FILE);
+        let content = std::fs::read_to_string(&path).ok()?;
+        match serde_json::from_str(&content) {
+            Ok(manifest) => Some(manifest),
+            Err(e) => {
+                log::warn!("Ignoring unreadable manifest {:?}: {}", path, e);
+                None
+            }
+        }
+    }
+
+    // A merged or rewritten table gets its own id and file, so the tables
+    // it replaces stay intact until the manifest no longer lists them.
+    pub(crate) fn new_table_path(&self) -> (u64, PathBuf) {
+        let mut next_id = self.next_sstable_id.lock().unwrap();
+        let id = *next_id;
+        *next_id += 1;
+        (id, sstable_path(&self.layout.sst_dir(), id))
+    }
+
+    // Installs a table from `new_table_path` with the table list locked.
+    // The manifest first records that its id was handed out, so if the
+    // list is not persisted after all, the next open discards the file.
+    pub(crate) fn install_replacement(
+        &self,
+        sstables: &[Arc<SSTable>],
+        tmp_path: &Path,
+        path: &Path,
+    ) -> VeloResult<()> {
+        self.persist_manifest(sstables)?;
+        install_sstable(tmp_path, path)
+    }
+
+    // Called with the table list locked, after files are in place and
+    // before any dropped table is deleted.
+    fn persist_manifest(&self, sstables: &[Arc<SSTable>]) -> VeloResult<()> {
+        let manifest = Manifest {
+            sstables: sstables.iter().map(|sstable| sstable.id).collect(),
+            next_id: *self.next_sstable_id.lock().unwrap(),
+            checksums: sstables
+                .iter()
+                .map(|sstable| (sstable.id, sstable.checksum))
+                .collect(),
+            levels: self.table_levels.lock().unwrap().clone(),
+            write_times: self.table_write_times.lock().unwrap().clone(),
+        };
+        let content = serde_json::to_string(&manifest)
+            .map_err(|e| VeloError::InvalidOperation(format!("Failed to encode manifest: {}", e)))?;
+        fsutil::atomic_write(self.layout.meta_dir().join(MANIFEST_FILE), content)?;
+        Ok(())
+    }
+
+    // Overwrites of keys held by `older` tables are counted while reading,
+    // and every key is added to the engine-level `filter`.
+    fn load_sstable(
+        id: u64,
+        path: PathBuf,
+        older: &[Arc<SSTable>],
+        filter: &mut BloomFilter,
+    ) -> VeloResult<SSTable> {
         use std::io::{BufReader, Read};
 
         let file = File::open(&path)?;
@@ -978,7 +3060,15 @@ impl Velocity {
         let mut min_key: Option<VeloKey> = None;
         let mut max_key: Option<VeloKey> = None;
         let mut entry_count = 0usize;
+        let mut tombstones = 0usize;
         let mut offset = 0u64;
+        let mut crc = crc32fast::Hasher::new();
+        let dictionary = compression::read_header(&mut reader)?.map(Arc::new);
+        if let Some(dictionary) = &dictionary {
+            crc.update(&compression::encode_header(dictionary));
+            offset = compression::header_len(dictionary);
+        }
+        let (mut raw_value_bytes, mut stored_value_bytes) = (0u64, 0u64);
 
 
         loop {
@@ -1005,7 +3095,7 @@ impl Velocity {
             if reader.read_exact(&mut v_size_buf).is_err() {
                 break;
             }
-            let v_size = u32::from_le_bytes(v_size_buf) as usize;
+            let v_size = decode_len(v_size_buf).0;
             offset += 4;
 
 
@@ -1014,12 +3104,25 @@ impl Velocity {
                 break;
             }
             offset += v_size as u64;
+            for part in [&k_size_buf[..], &k_buf, &v_size_buf, &v_buf] {
+                crc.update(part);
+            }
+            stored_value_bytes += v_size as u64;
+            raw_value_bytes += match dictionary {
+                Some(_) => compression::raw_len(&v_buf) as u64,
+                None => v_size as u64,
+            };
 
 
             bloom.add(&key);
+            filter.add(&key);
+            if v_buf.is_empty() {
+                tombstones += 1;
+            }
+            mark_overwritten(older, &key);
 
 
-            if entry_count % 16 == 0 {
+            if entry_count.is_multiple_of(INDEX_INTERVAL) {
                 index.insert(key.clone(), current_offset);
             }
 
@@ -1040,129 +3143,636 @@ impl Velocity {
             max_key,
             size,
             entry_count,
+            bloom_negatives: AtomicU64::new(0),
+            bloom_false_positives: AtomicU64::new(0),
+            tombstones,
+            tombstones_retained: 0,
+            overwritten: AtomicU64::new(0),
+            level: AtomicU32::new(0),
+            checksum: crc.finalize(),
+            dictionary,
+            raw_value_bytes,
+            stored_value_bytes,
         })
     }
 
     #[inline(always)]
     pub fn put(&self, key: VeloKey, value: VeloValue) -> VeloResult<()> {
+        self.put_with_options(key, value, &WriteOptions::default())
+    }
+
+    pub fn put_with_options(
+        &self,
+        key: VeloKey,
+        value: VeloValue,
+        options: &WriteOptions,
+    ) -> VeloResult<()> {
+        if !self.record_metadata.load(Ordering::Relaxed) {
+            return self.put_raw_with(key, RawValue::plain(value), options);
+        }
+
+        let _meta = self.meta_lock.lock().unwrap();
+        if value.is_empty() {
+            return self.put_raw_with(key, RawValue::tombstone(), options);
+        }
+        let previous = self.get_with_meta(&key)?.and_then(|(_, meta)| meta);
+        let record = RecordMeta::next(previous).encode(&value);
+        self.put_raw_with(key, record, options)
+    }
+
+    /// Writes `value` only if the key is currently at `expected_version`
+    /// (0 for a key that does not exist yet) and returns the new version.
+    pub fn put_if_version(
+        &self,
+        key: VeloKey,
+        expected_version: u64,
+        value: VeloValue,
+    ) -> VeloResult<u64> {
+        if !self.record_metadata.load(Ordering::Relaxed) {
+            return Err(VeloError::InvalidOperation(
+                "Versioned writes require record metadata to be enabled".to_string(),
+            ));
+        }
+        if value.is_empty() {
+            return Err(VeloError::InvalidOperation(
+                "Versioned writes cannot store empty values".to_string(),
+            ));
+        }
+
+        let _meta = self.meta_lock.lock().unwrap();
+        let previous = self.get_with_meta(&key)?.and_then(|(_, meta)| meta);
+        let actual = previous.map_or(0, |meta| meta.version);
+        if actual != expected_version {
+            return Err(VeloError::VersionConflict {
+                key,
+                expected: expected_version,
+                actual,
+            });
+        }
+
+        let meta = RecordMeta::next(previous);
+        self.put_raw(key, meta.encode(&value))?;
+        Ok(meta.version)
+    }
+
+    #[inline(always)]
+    pub(crate) fn put_raw(&self, key: VeloKey, value: RawValue) -> VeloResult<()> {
+        self.put_raw_with(key, value, &WriteOptions::default())
+    }
+
+    // The index entry is written before a new key and removed after a
+    // deleted one, so a crash can only leave an extra entry, which lookups
+    // check against the key itself.
+    fn put_raw_with(&self, key: VeloKey, value: RawValue, options: &WriteOptions) -> VeloResult<()> {
+        let value = RawValue {
+            bytes: self.apply_ttl(&key, value.bytes),
+            has_meta: value.has_meta,
+        };
+        match self.nocase_index_key(&key) {
+            Some(index_key) if !value.is_tombstone() => {
+                self.put_entry(index_key, RawValue::plain(vec![1]), options)?;
+                self.put_entry(key, value, options)
+            }
+            Some(index_key) => {
+                self.put_entry(key, value, options)?;
+                self.put_entry(index_key, RawValue::tombstone(), options)
+            }
+            None => self.put_entry(key, value, options),
+        }
+    }
+
+    fn put_entry(&self, key: VeloKey, value: RawValue, options: &WriteOptions) -> VeloResult<()> {
+        if options.low_priority {
+            self.yield_to_compaction();
+        }
+        self.check_writable()?;
+        check_value_len(&value)?;
+        self.inject_fault(StorageOp::WalAppend)?;
+        let _gate = self.write_gate.read().unwrap();
+        self.reserve_wal(&key, 1, options)?;
+        self.lifetime_bytes_written
+            .fetch_add((key.len() + value.bytes.len()) as u64, Ordering::Relaxed);
+        self.hot_keys.record(hotkeys::Access::Write, &key);
+
+        let sequence = {
+            let mut memtable = self.memtable.write().unwrap();
+            let mut filter = self.filter.write().unwrap();
+
+            if memtable.is_empty() {
+                *self.memtable_since.lock().unwrap() = Some(Instant::now());
+            }
+            filter.add(&key);
+            memtable.insert(&key, &value.bytes, value.has_meta);
+            // Updated before the memtable is unlocked, so writers of the
+            // same key update the cache in the order they wrote it.
+            self.cache_write(&mut self.cache.lock().unwrap(), &key, &value);
+            self.next_sequence()
+        };
+
+        self.notify_write(sequence, &key);
+
+
+        self.log_writes(vec![(sequence, key, value)], options)
+    }
+
+    // Synced writes bypass the write queue so they are on disk before the
+    // call returns.
+    fn write_queue(&self, key: &str, options: &WriteOptions) -> Option<&AsyncWriteQueue> {
+        if options.disable_wal || (options.sync && !self.config.memory_only_mode) {
+            return None;
+        }
+        self.write_queues.get(self.wal.shard_of(key))
+    }
+
+    // Takes room in the write queue for `records` writes before they are
+    // applied, so a full queue fails a write without applying it.
+    fn reserve_wal(&self, key: &str, records: usize, options: &WriteOptions) -> VeloResult<()> {
+        match self.write_queue(key, options) {
+            Some(queue) => queue.reserve(records),
+            None => Ok(()),
+        }
+    }
+
+    // All of `writes` go to the shard of the first key, so a batch is one
+    // frame in one file; recovery orders records by sequence across shards.
+    fn log_writes(&self, writes: Vec<WalRecord>, options: &WriteOptions) -> VeloResult<()> {
+        let Some((_, key, _)) = writes.first() else {
+            return Ok(());
+        };
+        if options.disable_wal {
+            return Ok(());
+        }
+        if let Some(queue) = self.write_queue(key, options) {
+            return queue.send(writes);
+        }
+        if self.config.memory_only_mode {
+            return Ok(());
+        }
+        let mut wal = self.wal.shard(key).lock().unwrap();
+        wal.log_records(&writes)?;
+        if options.sync {
+            wal.sync()?;
+        }
+        Ok(())
+    }
+
+    /// Applies every operation or none of them: readers see either the state
+    /// before the batch or after it, never part of it.
+    pub fn write_batch(&self, ops: &[BatchOp]) -> VeloResult<()> {
+        self.write_batch_with_options(ops, &WriteOptions::default())
+    }
+
+    pub fn write_batch_with_options(&self, ops: &[BatchOp], options: &WriteOptions) -> VeloResult<()> {
+        self.check_writable()?;
+        let _meta = self.meta_lock.lock().unwrap();
+        let record_metadata = self.record_metadata.load(Ordering::Relaxed);
+        let soft_delete = self.soft_delete.load(Ordering::Relaxed);
+
+
+        let mut writes: Vec<(VeloKey, RawValue)> = Vec::with_capacity(ops.len());
+        let current = |writes: &[(VeloKey, RawValue)], key: &str| -> VeloResult<Option<RawValue>> {
+            match writes.iter().rev().find(|(k, _)| k == key) {
+                Some((_, raw)) => Ok(Some(raw.clone())),
+                None => self.get_raw(key),
+            }
+            .map(|raw| raw.filter(|raw| !raw.is_tombstone()))
+        };
+        for op in ops {
+            match op {
+                BatchOp::Put { key, value } if record_metadata && !value.is_empty() => {
+                    let previous = current(&writes, key)?.and_then(|raw| split_record(raw).1);
+                    writes.push((key.clone(), RecordMeta::next(previous).encode(value)));
+                }
+                BatchOp::Put { key, value } => {
+                    writes.push((key.clone(), RawValue::plain(value.clone())))
+                }
+                BatchOp::Delete { key } => {
+                    if soft_delete && !key.starts_with(TRASH_PREFIX) {
+                        if let Some(raw) = current(&writes, key)? {
+                            writes.push((format!("{}{}", TRASH_PREFIX, key), trash_entry(raw)));
+                        }
+                    }
+                    writes.push((key.clone(), RawValue::tombstone()));
+                }
+            }
+        }
+
+
+        for (key, value) in writes.iter_mut() {
+            value.bytes = self.apply_ttl(key, std::mem::take(&mut value.bytes));
+            check_value_len(value)?;
+            self.hot_keys.record(hotkeys::Access::Write, key);
+        }
+        if self.nocase_index.load(Ordering::Relaxed) {
+            let index: Vec<(VeloKey, RawValue)> = writes
+                .iter()
+                .filter_map(|(key, value)| {
+                    let marker = if value.is_tombstone() {
+                        RawValue::tombstone()
+                    } else {
+                        RawValue::plain(vec![1])
+                    };
+                    self.nocase_index_key(key).map(|index_key| (index_key, marker))
+                })
+                .collect();
+            writes.extend(index);
+        }
+
+
+        if options.low_priority {
+            self.yield_to_compaction();
+        }
+        self.inject_fault(StorageOp::WalAppend)?;
+        let _gate = self.write_gate.read().unwrap();
+        if let Some((key, _)) = writes.first() {
+            self.reserve_wal(key, writes.len(), options)?;
+        }
+        let bytes: usize = writes.iter().map(|(k, v)| k.len() + v.bytes.len()).sum();
+        self.lifetime_bytes_written
+            .fetch_add(bytes as u64, Ordering::Relaxed);
+        let sequences: Vec<u64> = {
+            let mut memtable = self.memtable.write().unwrap();
+            let mut filter = self.filter.write().unwrap();
+            let mut cache = self.cache.lock().unwrap();
+            if memtable.is_empty() && !writes.is_empty() {
+                *self.memtable_since.lock().unwrap() = Some(Instant::now());
+            }
+            writes
+                .iter()
+                .map(|(key, value)| {
+                    filter.add(key);
+                    memtable.insert(key, &value.bytes, value.has_meta);
+                    self.cache_write(&mut cache, key, value);
+                    self.next_sequence()
+                })
+                .collect()
+        };
+        for (sequence, (key, _)) in sequences.iter().zip(&writes) {
+            self.notify_write(*sequence, key);
+        }
+
+        self.log_writes(
+            sequences
+                .into_iter()
+                .zip(writes)
+                .map(|(sequence, (key, value))| (sequence, key, value))
+                .collect(),
+            options,
+        )
+    }
+
+    pub fn put_batch(&self, operations: Vec<(VeloKey, VeloValue)>) -> VeloResult<()> {
+
+        for (key, value) in operations {
+            self.put(key, value)?;
+        }
+        Ok(())
+    }
+
+    pub fn delete(&self, key: VeloKey) -> VeloResult<()> {
+        self.delete_with_options(key, &WriteOptions::default())
+    }
+
+    pub fn delete_with_options(&self, key: VeloKey, options: &WriteOptions) -> VeloResult<()> {
+        if !self.soft_delete.load(Ordering::Relaxed) || key.starts_with(TRASH_PREFIX) {
+            return self.put_with_options(key, vec![], options);
+        }
+
+
+        let _meta = self.meta_lock.lock().unwrap();
+        self.soft_delete_locked(key, options)
+    }
+
+    fn soft_delete_locked(&self, key: VeloKey, options: &WriteOptions) -> VeloResult<()> {
+        if let Some(raw) = self.get_raw(&key)?.filter(|raw| !raw.is_tombstone()) {
+            self.put_raw_with(format!("{}{}", TRASH_PREFIX, key), trash_entry(raw), options)?;
+        }
+        self.put_raw_with(key, RawValue::tombstone(), options)
+    }
+
+    /// Deletes `key` only if it still holds `expected` and reports whether
+    /// it did. The check and the delete are atomic with respect to batches
+    /// and the other conditional writes.
+    pub fn delete_if_value(&self, key: &str, expected: &[u8]) -> VeloResult<bool> {
+        let _meta = self.meta_lock.lock().unwrap();
+        if self.get(key)?.as_deref() != Some(expected) {
+            return Ok(false);
+        }
+        if self.soft_delete.load(Ordering::Relaxed) && !key.starts_with(TRASH_PREFIX) {
+            self.soft_delete_locked(key.to_string(), &WriteOptions::default())?;
+        } else {
+            self.put_raw_with(key.to_string(), RawValue::tombstone(), &WriteOptions::default())?;
+        }
+        Ok(true)
+    }
 
+    /// Moves a soft-deleted key back out of the trash. Returns `false` when
+    /// the trash holds no entry for `key`.
+    pub fn restore(&self, key: &str) -> VeloResult<bool> {
+        let trash_key = format!("{}{}", TRASH_PREFIX, key);
+        let _meta = self.meta_lock.lock().unwrap();
+        let Some(entry) = self.get_raw(&trash_key)?.filter(|entry| entry.bytes.len() > 8) else {
+            return Ok(false);
+        };
+        if self.get_raw(key)?.is_some_and(|raw| !raw.is_tombstone()) {
+            return Err(VeloError::InvalidOperation(format!(
+                "Key '{}' already exists; delete it before restoring",
+                key
+            )));
+        }
 
-        {
-            let mut memtable = self.memtable.write().unwrap();
-            let mut filter = self.filter.write().unwrap();
+        let has_meta = trash_header(&entry.bytes).is_some_and(|(_, has_meta)| has_meta);
+        let value = RawValue {
+            bytes: entry.bytes[8..].to_vec(),
+            has_meta,
+        };
+        self.put_raw(key.to_string(), value)?;
+        self.put_raw(trash_key, RawValue::tombstone())?;
+        Ok(true)
+    }
 
-            filter.add(&key);
-            memtable.insert(key.clone(), value.clone());
+    /// Permanently removes trashed entries deleted more than `older_than`
+    /// ago, or all of them when `older_than` is `None`.
+    pub fn purge_trash(&self, older_than: Option<Duration>) -> VeloResult<usize> {
+        let _meta = self.meta_lock.lock().unwrap();
+        let mut purged = 0;
+        for (trash_key, _) in self.trashed(older_than) {
+            self.put_raw(trash_key, RawValue::tombstone())?;
+            purged += 1;
         }
+        Ok(purged)
+    }
 
-
-        if let Ok(mut cache) = self.cache.try_lock() {
-            cache.put(key.clone(), value.clone());
+    /// The trashed keys `purge_trash` would remove.
+    pub fn purge_trash_dry_run(&self, older_than: Option<Duration>) -> dry_run::DryRunReport {
+        let mut report = dry_run::DryRunReport::default();
+        for (trash_key, entry) in self.trashed(older_than) {
+            let key = &trash_key[TRASH_PREFIX.len()..];
+            report.add(key, 1, (trash_key.len() + entry.len()) as u64);
         }
+        report
+    }
 
+    fn trashed(&self, older_than: Option<Duration>) -> Vec<(VeloKey, VeloValue)> {
+        let cutoff = older_than.map(|age| now_millis().saturating_sub(age.as_millis() as u64));
+        self.scan_prefix_raw(TRASH_PREFIX)
+            .into_iter()
+            .map(|(key, entry)| (key, entry.bytes))
+            .filter(|(_, entry)| {
+                let deleted_at = trash_header(entry).map_or(0, |(deleted_at, _)| deleted_at);
+                cutoff.is_none_or(|cutoff| deleted_at <= cutoff)
+            })
+            .collect()
+    }
 
-        self.write_queue
-            .send(key, value)
-            .map_err(|_| VeloError::InvalidOperation("Write queue full".to_string()))?;
+    pub fn set_cache_policy(&self, policy: CachePolicy) {
+        self.cache_policy.store(policy as u8, Ordering::Relaxed);
+    }
 
-        Ok(())
+    pub fn cache_policy(&self) -> CachePolicy {
+        CachePolicy::from_u8(self.cache_policy.load(Ordering::Relaxed))
     }
 
-    pub fn put_batch(&self, operations: Vec<(VeloKey, VeloValue)>) -> VeloResult<()> {
+    pub fn hot_keys(&self) -> Option<hotkeys::HotKeyReport> {
+        self.hot_keys.report()
+    }
 
-        for (key, value) in operations {
-            self.put(key, value)?;
+    pub fn hot_key_config(&self) -> hotkeys::HotKeyConfig {
+        self.hot_keys.config()
+    }
+
+    /// Changes the hot-key settings at runtime; statistics start over.
+    pub fn set_hot_key_tracking(&self, config: &hotkeys::HotKeyConfig) {
+        self.hot_keys.configure(config);
+    }
+
+    fn cache_write(&self, cache: &mut UltraFastCache, key: &str, value: &RawValue) {
+        match self.cache_policy() {
+            CachePolicy::WriteThrough => cache.put(key.to_string(), value.clone()),
+            CachePolicy::WriteAround => cache.remove(key),
+            CachePolicy::ReadFill => cache.update(key, value.clone()),
         }
-        Ok(())
     }
 
-    pub fn delete(&self, key: VeloKey) -> VeloResult<()> {
+    pub fn set_soft_delete(&self, enabled: bool) {
+        self.soft_delete.store(enabled, Ordering::Relaxed);
+    }
 
-        self.put(key, vec![])
+    pub fn soft_delete_enabled(&self) -> bool {
+        self.soft_delete.load(Ordering::Relaxed)
     }
 
-    #[inline(always)]
     pub fn get(&self, key: &str) -> VeloResult<Option<VeloValue>> {
+        Ok(self.get_raw(key)?.map(|raw| split_record(raw).0))
+    }
+
+    pub fn get_with_meta(&self, key: &str) -> VeloResult<Option<(VeloValue, Option<RecordMeta>)>> {
+        Ok(self.get_raw(key)?.map(split_record))
+    }
+
+    pub fn set_record_metadata(&self, enabled: bool) {
+        self.record_metadata.store(enabled, Ordering::Relaxed);
+    }
+
+    pub fn record_metadata_enabled(&self) -> bool {
+        self.record_metadata.load(Ordering::Relaxed)
+    }
+
+    #[inline(always)]
+    fn get_raw(&self, key: &str) -> VeloResult<Option<RawValue>> {
+        self.get_raw_with(key, &read::ReadOptions::default())
+    }
+
+    fn get_raw_with(&self, key: &str, options: &read::ReadOptions) -> VeloResult<Option<RawValue>> {
+        let Some(stored) = self.get_stored_with(key, options)? else {
+            return Ok(None);
+        };
+        Ok(self
+            .strip_expiry(key, stored.bytes, now_millis())
+            .map(|bytes| RawValue {
+                bytes,
+                has_meta: stored.has_meta,
+            }))
+    }
+
+    // Like `get_raw_with`, but values under a TTL policy keep their expiry
+    // header and are returned even once expired.
+    fn get_stored_with(
+        &self,
+        key: &str,
+        options: &read::ReadOptions,
+    ) -> VeloResult<Option<RawValue>> {
+        self.hot_keys.record(hotkeys::Access::Read, key);
+        self.inject_fault(StorageOp::Read)?;
+        if let Some(snapshot) = options.snapshot {
+            return snapshot.get_raw(key, options.verify_checksums);
+        }
+        let started = Instant::now();
+        let answered = |source| self.read_sources.record(source, started.elapsed());
 
         {
             let cache_guard = self.cache.try_lock();
             if let Ok(mut cache) = cache_guard {
                 if let Some(value) = cache.get(key) {
-                    return Ok(Some(value));
+                    self.cache_hits.fetch_add(1, Ordering::Relaxed);
+                    answered(read_sources::ReadSource::Cache);
+                    // Write-through caching keeps deletes as empty values.
+                    return Ok((!value.is_tombstone()).then_some(value));
                 }
             }
         }
+        self.cache_misses.fetch_add(1, Ordering::Relaxed);
 
 
-        {
+        let generation = {
             let memtable = self.memtable.read().unwrap();
-            if let Some(value) = memtable.get(key) {
-
-                if value.is_empty() {
+            if let Some((value, has_meta)) = memtable.get(key) {
+                answered(read_sources::ReadSource::Memtable);
+                let value = RawValue {
+                    bytes: value.to_vec(),
+                    has_meta,
+                };
+
+                if value.is_tombstone() {
                     return Ok(None);
                 }
 
 
-                let cache = self.cache.clone();
-                let key_clone = key.to_string();
-                let value_clone = value.clone();
-
-                std::thread::spawn(move || {
-                    if let Ok(mut cache_guard) = cache.lock() {
-                        cache_guard.put(key_clone, value_clone);
-                    }
-                });
+                if !options.fill_cache {
+                    return Ok(Some(value));
+                }
 
-                return Ok(Some(value.clone()));
+                // Filled while the memtable is still read-locked: a newer
+                // write of the key needs the write lock and updates the
+                // cache while holding it, so this value can never replace it.
+                // A busy cache is skipped rather than waited for.
+                if let Ok(mut cache) = self.cache.try_lock() {
+                    cache.put(key.to_string(), value.clone());
+                }
+                return Ok(Some(value));
             }
-        }
+            memtable.arena_stats().generation
+        };
 
 
         {
             let filter = self.filter.read().unwrap();
             if !filter.might_contain(key) {
+                answered(read_sources::ReadSource::Miss);
                 return Ok(None);
             }
         }
 
 
-        {
+        let found = {
             let sstables = self.sstables.read().unwrap();
+            let mut found = None;
             for sstable in sstables.iter().rev() {
-                if let Some(value) = sstable.get(key)? {
-
-                    let mut cache = self.cache.lock().unwrap();
-                    cache.put(key.to_string(), value.clone());
-                    return Ok(Some(value));
+                if let Some(value) = sstable.get(key, self.config.io_backend)? {
+                    if options.verify_checksums {
+                        sstable.verify_checksum(File::open(&sstable.path)?)?;
+                    }
+                    answered(read_sources::ReadSource::SSTable {
+                        level: sstable.level.load(Ordering::Relaxed),
+                    });
+                    found = Some(value);
+                    break;
+                }
+            }
+            found
+        };
+        if let Some(value) = found {
+            if value.is_tombstone() {
+                return Ok(None);
+            }
+            if options.fill_cache {
+                // A write of the key since the memtable was checked would be
+                // in the same memtable generation; without one this value is
+                // still the newest. The SSTables are unlocked first, since a
+                // flush takes the memtable before them.
+                let memtable = self.memtable.read().unwrap();
+                if memtable.arena_stats().generation == generation && memtable.get(key).is_none() {
+                    self.cache.lock().unwrap().put(key.to_string(), value.clone());
                 }
             }
+            return Ok(Some(value));
         }
 
+        answered(read_sources::ReadSource::Miss);
         Ok(None)
     }
 
     pub fn flush(&self) -> VeloResult<()> {
+        self.check_writable()?;
         let mut memtable = self.memtable.write().unwrap();
 
         if memtable.is_empty() {
             return Ok(());
         }
+        let progress = compaction::ProgressReporter::start(
+            self,
+            compaction::MaintenanceOperation::Flush,
+            memtable.arena_stats().live_bytes,
+            0,
+        );
 
         let mut next_id = self.next_sstable_id.lock().unwrap();
-        let sstable = SSTable::create(&self.data_dir, *next_id, &memtable)?;
+        let sstable = SSTable::create(
+            self.layout.sst_dir(),
+            *next_id,
+            &memtable,
+            self.config.bloom_false_positive_rate,
+            self.config.fault_injection.as_ref(),
+        )?;
         *next_id += 1;
         drop(next_id);
 
+        let now = now_millis();
+        let oldest_write = self
+            .memtable_since
+            .lock()
+            .unwrap()
+            .map_or(now, |since| now.saturating_sub(since.elapsed().as_millis() as u64));
+        self.table_write_times
+            .lock()
+            .unwrap()
+            .insert(sstable.id, (oldest_write, now));
+
         let mut sstables = self.sstables.write().unwrap();
-        sstables.push(sstable);
+        for key in memtable.keys() {
+            mark_overwritten(&sstables, key);
+        }
+        sstables.push(Arc::new(sstable));
+        self.persist_manifest(&sstables)?;
+        self.persist_flushed_sequence(self.last_sequence.load(Ordering::Acquire))?;
 
         memtable.clear();
+        *self.memtable_since.lock().unwrap() = None;
 
-        let mut wal = self.wal.lock().unwrap();
-        wal.clear()?;
+        let mut retained = self.wal_retention.lock();
+        let mut wal = self.wal.lock_all();
+        let archive_to = match wal.len() {
+            1 => retained.archive_path(),
+            _ => None,
+        };
+        for shard in wal.iter_mut() {
+            shard.clear(archive_to.as_deref())?;
+        }
+        self.wal.remove_stale()?;
+        if archive_to.is_some() {
+            retained.archived()?;
+        }
+        drop(retained);
 
+        self.lifetime.lock().unwrap().total_flushes += 1;
+        self.persist_lifetime_stats()?;
+        progress.finish(compaction::CompactionState::Completed);
 
-        if sstables.len() >= self.config.compaction_threshold {
+        let dense = sstables
+            .iter()
+            .any(|sstable| sstable.garbage_ratio() >= GARBAGE_RATIO_TRIGGER);
+        let picked = self.next_compaction_pick(&sstables).is_some();
+        if sstables.len() >= self.config.compaction_threshold || dense || picked {
             drop(sstables);
             drop(memtable);
             drop(wal);
@@ -1172,19 +3782,363 @@ impl Velocity {
         Ok(())
     }
 
+    /// How long the oldest write still in the memtable has been waiting for
+    /// a flush; `None` when the memtable is empty.
+    pub fn memtable_age(&self) -> Option<Duration> {
+        self.memtable_since.lock().unwrap().map(|since| since.elapsed())
+    }
+
+    /// Whether the database was opened with `read_only` or a `WalRecovery`
+    /// other than `Replay`, so writes and flushes are refused.
+    pub fn is_read_only(&self) -> bool {
+        self.read_only
+    }
+
+    /// The pool that runs this database's background work, unless
+    /// `background_threads` is off.
+    pub fn background_pool(&self) -> Option<&Arc<background::BackgroundPool>> {
+        self.background.as_ref()
+    }
+
+    /// Runs `job` on the background pool, or on a thread of its own when
+    /// the database has no pool. Fails when the pool's queue is full.
+    pub fn run_in_background<F>(&self, job: F) -> VeloResult<()>
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        match &self.background {
+            Some(background) => background.spawn(job),
+            None => {
+                thread::spawn(job);
+                Ok(())
+            }
+        }
+    }
+
+    fn check_writable(&self) -> VeloResult<()> {
+        if self.read_only {
+            return Err(VeloError::InvalidOperation(
+                "Database was opened read-only or without full WAL recovery".to_string(),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Flushes the memtable when its oldest write is older than `max_age`,
+    /// so data written to a quiet database still reaches SSTables and the
+    /// WAL gets truncated. Returns whether a flush happened.
+    pub fn flush_if_older_than(&self, max_age: Duration) -> VeloResult<bool> {
+        match self.memtable_age() {
+            Some(age) if age >= max_age => self.flush().map(|_| true),
+            _ => Ok(false),
+        }
+    }
+
+    // WAL records are written by a background thread, so write faults are
+    // raised on the calling thread before anything is applied.
+    fn inject_fault(&self, op: StorageOp) -> VeloResult<()> {
+        match &self.config.fault_injection {
+            Some(faults) => faults.inject(op),
+            None => Ok(()),
+        }
+    }
+
+    // Skipped while a full compaction runs; it rewrites everything anyway.
     fn compact(&self) -> VeloResult<()> {
+        let Ok(_compacting) = self.compaction_lock.try_lock() else {
+            return Ok(());
+        };
+        let _flag = self.mark_compacting();
+        self.rebuild_leaky_blooms()?;
+        self.rewrite_dense_tables()?;
+        self.run_picked_compactions()?;
+        self.lifetime.lock().unwrap().total_compactions += 1;
+        self.persist_lifetime_stats()
+    }
+
+    // Tables whose filters let through far more absent keys than the target
+    // rate get a larger filter. Keys are read without holding the table list
+    // lock; the new filters are swapped in afterwards.
+    fn rebuild_leaky_blooms(&self) -> VeloResult<()> {
+        let target = self.config.bloom_false_positive_rate;
+        let mut rebuilt = Vec::new();
+        {
+            let sstables = self.sstables.read().unwrap();
+            for sstable in sstables.iter() {
+                let Some(observed) = sstable.observed_false_positive_rate() else {
+                    continue;
+                };
+                if observed <= target * BLOOM_FPR_REBUILD_FACTOR {
+                    continue;
+                }
+
+                let keys = sstable.keys()?;
+                let bit_count = BloomFilter::optimal_bit_count(keys.len().max(1), target)
+                    .max(sstable.bloom.bit_count * 2);
+                let mut bloom = BloomFilter::with_bits(bit_count, keys.len());
+                for key in &keys {
+                    bloom.add(key);
+                }
+                self.log_compaction(
+                    Some(sstable.id),
+                    "rebuild_bloom",
+                    format!(
+                        "observed false-positive rate {:.4} exceeds target {} by more than {}x; \
+                         bits {} -> {}",
+                        observed, target, BLOOM_FPR_REBUILD_FACTOR, sstable.bloom.bit_count, bit_count
+                    ),
+                );
+                rebuilt.push((sstable.id, bloom));
+            }
+        }
+
+        let mut sstables = self.sstables.write().unwrap();
+        for (id, bloom) in rebuilt {
+            if let Some(sstable) = sstables.iter_mut().find(|s| s.id == id) {
+                *sstable = Arc::new(sstable.with_bloom(bloom));
+            }
+        }
+        Ok(())
+    }
+
+    fn rewrite_dense_tables(&self) -> VeloResult<()> {
+        let mut candidates: Vec<(usize, u64, f64)> = self
+            .sstables
+            .read()
+            .unwrap()
+            .iter()
+            .enumerate()
+            .map(|(pos, sstable)| (pos, sstable.id, sstable.garbage_ratio()))
+            .filter(|(_, _, ratio)| *ratio >= GARBAGE_RATIO_TRIGGER)
+            .collect();
+        candidates.sort_by(|a, b| b.2.total_cmp(&a.2));
+        candidates.truncate(MAX_REWRITES_PER_COMPACTION);
+        // Oldest first, so values dropped there can free tombstones above.
+        candidates.sort_by_key(|(pos, _, _)| *pos);
+
+        for (_, id, ratio) in candidates {
+            self.rewrite_table(id, ratio)?;
+        }
+        Ok(())
+    }
+
+    // Rewrites one table without the values that newer tables shadow and
+    // without tombstones for keys no older table holds. The result takes the
+    // table's position under a new id, and the old file is only deleted
+    // once the manifest lists the new one.
+    fn rewrite_table(&self, id: u64, ratio: f64) -> VeloResult<()> {
+        let rewritten = {
+            let sstables = self.sstables.read().unwrap();
+            let Some(pos) = sstables.iter().position(|sstable| sstable.id == id) else {
+                return Ok(());
+            };
+            let (older, rest) = sstables.split_at(pos);
+            let (table, newer) = rest.split_first().unwrap();
+
+            let mut survivors = BTreeMap::new();
+            let (mut shadowed, mut tombstones_dropped, mut tombstones_retained) = (0, 0, 0);
+            let now = now_millis();
+            for (key, mut value) in table.records()? {
+                // An expired value still hides older versions of the key.
+                if self.is_expired(&key, &value.bytes, now) {
+                    value = RawValue::tombstone();
+                }
+                let mut is_shadowed = false;
+                for newer_table in newer.iter().filter(|t| t.may_contain(&key)) {
+                    if newer_table.get(&key, self.config.io_backend)?.is_some() {
+                        is_shadowed = true;
+                        break;
+                    }
+                }
+                if is_shadowed {
+                    shadowed += 1;
+                } else if value.is_tombstone() && !older.iter().any(|t| t.may_contain(&key)) {
+                    tombstones_dropped += 1;
+                } else {
+                    if value.is_tombstone() {
+                        tombstones_retained += 1;
+                    }
+                    survivors.insert(key, value);
+                }
+            }
+
+            let new_table = if survivors.is_empty() {
+                None
+            } else {
+                let (new_id, new_path) = self.new_table_path();
+                let tmp_path = temp_sstable_path(&new_path);
+                let plan = self.compression_plan();
+                let compression = plan.any().then(|| {
+                    let values = survivors
+                        .iter()
+                        .filter(|(key, _)| plan.level(key).is_some())
+                        .map(|(_, value)| &value.bytes);
+                    let samples = compression::sample(values, compression::SAMPLE_BYTES);
+                    (compression::Dictionary::train(&samples), plan)
+                });
+                let mut new_table = SSTable::create_at(
+                    tmp_path.clone(),
+                    new_id,
+                    survivors.len(),
+                    survivors
+                        .iter()
+                        .map(|(key, value)| (key.as_str(), value.bytes.as_slice(), value.has_meta)),
+                    self.config.bloom_false_positive_rate,
+                    compression,
+                )?;
+                new_table.path = new_path;
+                new_table.tombstones_retained = tombstones_retained;
+                Some((tmp_path, new_table))
+            };
+
+            self.log_compaction(
+                Some(id),
+                "rewrite",
+                format!(
+                    "{:.0}% reclaimable; dropped {} shadowed entries and {} tombstones, {} of {} entries left",
+                    ratio * 100.0,
+                    shadowed,
+                    tombstones_dropped,
+                    survivors.len(),
+                    table.entry_count
+                ),
+            );
+            (table.path.clone(), new_table)
+        };
 
+        let (path, new_table) = rewritten;
+        let mut sstables = self.sstables.write().unwrap();
+        let Some(pos) = sstables.iter().position(|sstable| sstable.id == id) else {
+            return Ok(());
+        };
+        match new_table {
+            Some((tmp_path, new_table)) => {
+                self.install_replacement(&sstables, &tmp_path, &new_table.path)?;
+                let new_id = new_table.id;
+                sstables[pos] = Arc::new(new_table);
+                let mut levels = self.table_levels.lock().unwrap();
+                if let Some(level) = levels.remove(&id) {
+                    sstables[pos].level.store(level, Ordering::Relaxed);
+                    levels.insert(new_id, level);
+                }
+                let mut write_times = self.table_write_times.lock().unwrap();
+                if let Some(times) = write_times.remove(&id) {
+                    write_times.insert(new_id, times);
+                }
+                drop((levels, write_times));
+            }
+            None => {
+                sstables.remove(pos);
+                self.table_levels.lock().unwrap().remove(&id);
+                self.table_write_times.lock().unwrap().remove(&id);
+            }
+        }
+        self.persist_manifest(&sstables)?;
+        std::fs::remove_file(&path)?;
         Ok(())
     }
 
+    fn log_compaction(&self, sstable_id: Option<u64>, action: &str, reason: String) {
+        log::info!(
+            "Compaction: {} {} ({})",
+            action,
+            sstable_id.map(|id| format!("sstable {}", id)).unwrap_or_default(),
+            reason
+        );
+        let mut log = self.compaction_log.lock().unwrap();
+        if log.len() >= COMPACTION_LOG_CAPACITY {
+            log.pop_front();
+        }
+        log.push_back(CompactionLogEntry {
+            timestamp_ms: now_millis(),
+            sstable_id,
+            action: action.to_string(),
+            reason,
+        });
+    }
+
+    /// Recent decisions taken during compaction, oldest first.
+    pub fn compaction_log(&self) -> Vec<CompactionLogEntry> {
+        self.compaction_log.lock().unwrap().iter().cloned().collect()
+    }
+
     pub fn close(&self) -> VeloResult<()> {
+        if self.read_only {
+            self.cache.lock().unwrap().clear();
+            return Ok(());
+        }
         self.flush()?;
+        self.persist_lifetime_stats()?;
+        if let Err(e) = self.record_capacity_sample() {
+            log::warn!("Failed to record capacity sample for {:?}: {}", self.data_dir, e);
+        }
+        self.save_cache_keys()?;
         let mut cache = self.cache.lock().unwrap();
         cache.clear();
         Ok(())
     }
 
+    /// Bytes of the entries read by scans and range reads since the
+    /// database was opened, before filtering and limits.
+    pub fn scan_bytes(&self) -> u64 {
+        self.scan_bytes.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn count_scan<'a>(
+        &self,
+        entries: impl Iterator<Item = (&'a VeloKey, &'a RawValue)>,
+    ) {
+        let bytes: usize = entries.map(|(key, value)| key.len() + value.bytes.len()).sum();
+        self.scan_bytes.fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
     pub fn scan(&self, limit: usize) -> Vec<(VeloKey, VeloValue)> {
+        self.scan_with_meta(limit)
+            .into_iter()
+            .map(|(key, value, _)| (key, value))
+            .collect()
+    }
+
+    pub(crate) fn scan_prefix_raw(&self, prefix: &str) -> Vec<(VeloKey, RawValue)> {
+        let mut entries = BTreeMap::new();
+        if let Ok(sstables) = self.sstables.read() {
+            for sstable in sstables.iter() {
+                if let Ok(all) = sstable.all_entries() {
+                    entries.extend(all.into_iter().filter(|(k, _)| k.starts_with(prefix)));
+                }
+            }
+        }
+        if let Ok(memtable) = self.memtable.read() {
+            for (k, v, has_meta) in memtable.range_from(prefix) {
+                if !k.starts_with(prefix) {
+                    break;
+                }
+                let v = RawValue {
+                    bytes: v.to_vec(),
+                    has_meta,
+                };
+                entries.insert(k.to_string(), v);
+            }
+        }
+        self.count_scan(entries.iter());
+        let now = now_millis();
+        entries
+            .into_iter()
+            .filter(|(k, v)| !v.is_tombstone() && !Self::hidden_from_scan(prefix, k))
+            .filter_map(|(k, v)| {
+                let bytes = self.strip_expiry(&k, v.bytes, now)?;
+                Some((
+                    k,
+                    RawValue {
+                        bytes,
+                        has_meta: v.has_meta,
+                    },
+                ))
+            })
+            .collect()
+    }
+
+    pub fn scan_with_meta(&self, limit: usize) -> Vec<(VeloKey, VeloValue, Option<RecordMeta>)> {
         let mut all_data = HashMap::new();
 
 
@@ -1200,16 +4154,35 @@ impl Velocity {
 
 
         if let Ok(memtable) = self.memtable.read() {
-            for (k, v) in memtable.iter() {
+            for (k, v, has_meta) in memtable.iter() {
                 if v.is_empty() {
                     all_data.remove(k);
                 } else {
-                    all_data.insert(k.clone(), v.clone());
+                    let v = RawValue {
+                        bytes: v.to_vec(),
+                        has_meta,
+                    };
+                    all_data.insert(k.to_string(), v);
                 }
             }
         }
 
-        let mut result: Vec<(String, Vec<u8>)> = all_data.into_iter().collect();
+        self.count_scan(all_data.iter());
+        let now = now_millis();
+        let mut result: Vec<(String, RawValue)> = all_data
+            .into_iter()
+            .filter(|(k, _)| !Self::hidden_from_scan("", k))
+            .filter_map(|(k, v)| {
+                let bytes = self.strip_expiry(&k, v.bytes, now)?;
+                Some((
+                    k,
+                    RawValue {
+                        bytes,
+                        has_meta: v.has_meta,
+                    },
+                ))
+            })
+            .collect();
         result.sort_by(|a, b| a.0.cmp(&b.0));
 
         if result.len() > limit {
@@ -1217,6 +4190,161 @@ impl Velocity {
         }
 
         result
+            .into_iter()
+            .map(|(key, raw)| {
+                let (value, meta) = split_record(raw);
+                (key, value, meta)
+            })
+            .collect()
+    }
+
+    pub fn pause_writes(&self) -> std::sync::RwLockWriteGuard<'_, ()> {
+        self.write_gate.write().unwrap()
+    }
+
+    pub fn data_dir(&self) -> &Path {
+        &self.data_dir
+    }
+
+    pub fn layout(&self) -> &DataLayout {
+        &self.layout
+    }
+
+    pub fn config(&self) -> &VelocityConfig {
+        &self.config
+    }
+
+    pub fn create_checkpoint(&self) -> VeloResult<replication::Checkpoint> {
+        self.checkpoint(None)
+    }
+
+    /// A checkpoint for a new replica named `replica`. WAL segments it has
+    /// not read are kept until it reads past them with `read_wal`.
+    pub fn create_replica_checkpoint(&self, replica: &str) -> VeloResult<replication::Checkpoint> {
+        self.checkpoint(Some(replica))
+    }
+
+    fn checkpoint(&self, replica: Option<&str>) -> VeloResult<replication::Checkpoint> {
+        let wal = self.wal.unsharded()?;
+        self.flush()?;
+
+        let sstables = self.sstables.read().unwrap();
+        let mut retained = self.wal_retention.lock();
+        let wal_segment = match replica {
+            Some(replica) => retained.register(replica)?,
+            None => retained.segment(),
+        };
+        let files = sstables
+            .iter()
+            .filter_map(|sstable| {
+                Some(replication::CheckpointFile {
+                    name: sstable.path.file_name()?.to_str()?.to_string(),
+                    size: sstable.size,
+                })
+            })
+            .collect();
+        let wal_offset = wal.lock().unwrap().position()?;
+
+        Ok(replication::Checkpoint {
+            files,
+            wal_segment,
+            wal_offset,
+            hlc: hlc::clock().now(),
+        })
+    }
+
+    pub fn read_sstable_chunk(&self, name: &str, offset: u64, max_len: usize) -> VeloResult<Vec<u8>> {
+        let path = {
+            let sstables = self.sstables.read().unwrap();
+            sstables
+                .iter()
+                .find(|sstable| sstable.path.file_name().and_then(|n| n.to_str()) == Some(name))
+                .map(|sstable| sstable.path.clone())
+                .ok_or_else(|| {
+                    VeloError::KeyNotFound(format!("SSTable '{}' is not part of this database", name))
+                })?
+        };
+
+        let mut file = File::open(path)?;
+        file.seek(SeekFrom::Start(offset))?;
+        let mut buf = Vec::new();
+        file.take(max_len as u64).read_to_end(&mut buf)?;
+        Ok(buf)
+    }
+
+    /// Up to `max_len` WAL bytes for `replica` from `offset` of `segment`,
+    /// moving on to the next segment once one is read to the end. Reading
+    /// from a segment acknowledges every segment before it.
+    pub fn read_wal(
+        &self,
+        replica: &str,
+        mut segment: u64,
+        mut offset: u64,
+        max_len: usize,
+    ) -> VeloResult<replication::WalChunk> {
+        let wal = self.wal.unsharded()?;
+        let mut retained = self.wal_retention.lock();
+        retained.acknowledge(replica, segment)?;
+        while segment < retained.segment() {
+            let path = retained.segment_path(segment);
+            let len = std::fs::metadata(&path)
+                .map_err(|_| {
+                    VeloError::InvalidOperation(format!(
+                        "WAL segment {} is no longer kept; bootstrap replica '{}' again",
+                        segment, replica
+                    ))
+                })?
+                .len();
+            if offset < len {
+                let bytes = read_wal_chunk(File::open(&path)?, offset, max_len)?;
+                return Ok(replication::WalChunk {
+                    segment,
+                    offset,
+                    bytes,
+                });
+            }
+            if offset > len {
+                break;
+            }
+            segment += 1;
+            offset = 0;
+        }
+        if segment != retained.segment() {
+            return Err(VeloError::InvalidOperation(format!(
+                "WAL position {}:{} does not exist; the live segment is {}",
+                segment,
+                offset,
+                retained.segment()
+            )));
+        }
+        let bytes = wal.lock().unwrap().read_from(offset, max_len)?;
+        Ok(replication::WalChunk {
+            segment,
+            offset,
+            bytes,
+        })
+    }
+
+    /// Replicas that WAL segments are kept for, with the segment each one
+    /// reads next.
+    pub fn replicas(&self) -> BTreeMap<String, u64> {
+        self.wal_retention.lock().replicas()
+    }
+
+    /// Stops keeping WAL segments for `replica`.
+    pub fn forget_replica(&self, replica: &str) -> VeloResult<bool> {
+        self.wal_retention.lock().forget(replica)
+    }
+
+
+    pub fn apply_wal(&self, bytes: &[u8]) -> VeloResult<(usize, usize)> {
+        let (records, consumed) = decode_wal_records(bytes);
+        let applied = records.len();
+        for (timestamp, key, value) in records {
+            hlc::clock().observe(timestamp);
+            self.put_raw(key, value)?;
+        }
+        Ok((applied, consumed))
     }
 
     pub fn stats(&self) -> VelocityStats {
@@ -1228,30 +4356,219 @@ impl Velocity {
         let sstable_size: u64 = sstables.iter().map(|s| s.size).sum();
 
 
-        let memtable_size: u64 = memtable
-            .iter()
-            .map(|(k, v)| (k.len() + v.len() + 32) as u64)
-            .sum();
+        let arena = memtable.arena_stats();
+        let memtable_size = arena.live_bytes + 32 * memtable.len() as u64;
 
         VelocityStats {
             memtable_entries: memtable.len(),
             sstable_count: sstables.len(),
             cache_entries: cache.len(),
+            cache_bytes: cache.memory_bytes(),
+            memtable_bytes: memtable_size,
             total_sstable_size: sstable_size,
             total_records: memtable.len() + sstable_records,
             total_size_bytes: sstable_size + memtable_size,
+            cache_hits: self.cache_hits.load(Ordering::Relaxed),
+            cache_misses: self.cache_misses.load(Ordering::Relaxed),
+            memtable_arena: arena,
+            read_sources: self.read_sources.stats(),
+        }
+    }
+
+    /// Per-table properties, oldest table first.
+    pub fn table_properties(&self) -> Vec<TableProperties> {
+        self.sstables
+            .read()
+            .unwrap()
+            .iter()
+            .map(|sstable| TableProperties {
+                id: sstable.id,
+                entries: sstable.entry_count,
+                size_bytes: sstable.size,
+                tombstones: sstable.tombstones,
+                compressed: sstable.dictionary.is_some(),
+                dictionary_id: sstable.dictionary.as_ref().and_then(|dictionary| dictionary.id()),
+                dictionary_bytes: sstable
+                    .dictionary
+                    .as_ref()
+                    .map_or(0, |dictionary| dictionary.bytes().len()),
+                raw_value_bytes: sstable.raw_value_bytes,
+                stored_value_bytes: sstable.stored_value_bytes,
+                compression_ratio: sstable.compression_ratio(),
+            })
+            .collect()
+    }
+
+    /// The shape of the LSM tree: every SSTable with its key range and how
+    /// many other tables it overlaps, the tables grouped into size tiers, and
+    /// the read amplification and compaction debt that follow from them.
+    pub fn lsm_shape(&self) -> LsmShape {
+        let stats = self.stats();
+        let sstables = self.sstables.read().unwrap();
+        let levels = self.table_levels.lock().unwrap().clone();
+
+        let tables: Vec<TableShape> = sstables
+            .iter()
+            .map(|sstable| TableShape {
+                id: sstable.id,
+                entries: sstable.entry_count,
+                size_bytes: sstable.size,
+                tombstones: sstable.tombstones,
+                min_key: sstable.min_key.clone(),
+                max_key: sstable.max_key.clone(),
+                tier: size_tier(sstable.size),
+                level: levels.get(&sstable.id).copied().unwrap_or(0),
+                overlaps: sstables
+                    .iter()
+                    .filter(|other| other.id != sstable.id && ranges_overlap(sstable, other))
+                    .count(),
+            })
+            .collect();
+
+        let mut tiers: BTreeMap<u32, LsmTier> = BTreeMap::new();
+        for table in &tables {
+            let tier = tiers.entry(table.tier).or_insert_with(|| LsmTier {
+                tier: table.tier,
+                max_bytes: LSM_TIER_BASE_BYTES << (2 * table.tier),
+                tables: 0,
+                size_bytes: 0,
+            });
+            tier.tables += 1;
+            tier.size_bytes += table.size_bytes;
+        }
+
+        // Sweep the key ranges to find the most tables covering one key;
+        // starts sort before ends at the same key so touching ranges count.
+        let mut bounds: Vec<(&VeloKey, i32)> = sstables
+            .iter()
+            .filter_map(|sstable| {
+                Some([
+                    (sstable.min_key.as_ref()?, -1),
+                    (sstable.max_key.as_ref()?, 1),
+                ])
+            })
+            .flatten()
+            .collect();
+        bounds.sort();
+        let mut depth = 0;
+        let mut deepest = 0;
+        for (_, bound) in bounds {
+            depth -= bound;
+            deepest = deepest.max(depth);
+        }
+
+        LsmShape {
+            memtable_entries: stats.memtable_entries,
+            memtable_bytes: stats.memtable_bytes,
+            read_amplification: deepest as usize + 1,
+            compaction_threshold: self.config.compaction_threshold,
+            compaction_debt: tables
+                .len()
+                .saturating_sub(self.config.compaction_threshold),
+            tiers: tiers.into_values().collect(),
+            tables,
         }
     }
 }
 
+fn ranges_overlap(a: &SSTable, b: &SSTable) -> bool {
+    match (&a.min_key, &a.max_key, &b.min_key, &b.max_key) {
+        (Some(a_min), Some(a_max), Some(b_min), Some(b_max)) => a_min <= b_max && b_min <= a_max,
+        _ => false,
+    }
+}
+
+// Every this many records of an SSTable get an index entry.
+const INDEX_INTERVAL: usize = 16;
+// Index entries `open_with_verification` checks per SSTable.
+const VERIFY_SAMPLES: usize = 64;
+
+// Tier n holds tables smaller than 4^n MiB.
+const LSM_TIER_BASE_BYTES: u64 = 1024 * 1024;
+
+pub(crate) fn size_tier(size: u64) -> u32 {
+    let mut tier = 0;
+    while size >= LSM_TIER_BASE_BYTES << (2 * tier) && tier < 16 {
+        tier += 1;
+    }
+    tier
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct LsmShape {
+    pub memtable_entries: usize,
+    pub memtable_bytes: u64,
+    /// Oldest table first.
+    pub tables: Vec<TableShape>,
+    pub tiers: Vec<LsmTier>,
+    /// The most places a point lookup may have to search: the memtable and
+    /// every table whose key range covers the key.
+    pub read_amplification: usize,
+    pub compaction_threshold: usize,
+    /// Tables beyond the compaction threshold.
+    pub compaction_debt: usize,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct TableShape {
+    pub id: u64,
+    pub entries: usize,
+    pub size_bytes: u64,
+    pub tombstones: usize,
+    pub min_key: Option<VeloKey>,
+    pub max_key: Option<VeloKey>,
+    pub tier: u32,
+    /// Set by a `CompactionPicker`; 0 for flushed tables.
+    pub level: u32,
+    /// Other tables whose key range intersects this one.
+    pub overlaps: usize,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct LsmTier {
+    pub tier: u32,
+    /// Tables in the tier are smaller than this.
+    pub max_bytes: u64,
+    pub tables: usize,
+    pub size_bytes: u64,
+}
+
 #[derive(Debug, Serialize)]
 pub struct VelocityStats {
     pub memtable_entries: usize,
     pub sstable_count: usize,
     pub cache_entries: usize,
+    pub cache_bytes: u64,
+    pub memtable_bytes: u64,
     pub total_sstable_size: u64,
     pub total_records: usize,
     pub total_size_bytes: u64,
+    pub cache_hits: u64,
+    pub cache_misses: u64,
+    pub memtable_arena: ArenaStats,
+    pub read_sources: read_sources::ReadSourceStats,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct TableProperties {
+    pub id: u64,
+    pub entries: usize,
+    pub size_bytes: u64,
+    pub tombstones: usize,
+    pub compressed: bool,
+    /// Set when the table's values are compressed with a trained dictionary.
+    pub dictionary_id: Option<u32>,
+    pub dictionary_bytes: usize,
+    pub raw_value_bytes: u64,
+    pub stored_value_bytes: u64,
+    pub compression_ratio: f64,
+}
+
+impl VelocityStats {
+    pub fn cache_hit_rate(&self) -> Option<f64> {
+        let lookups = self.cache_hits + self.cache_misses;
+        (lookups > 0).then(|| self.cache_hits as f64 / lookups as f64)
+    }
 }
 
 impl Drop for Velocity {
@@ -1,16 +1,20 @@
 use velocity::VeloError;
-use clap::{Parser, Subcommand};
+use clap::{Args, Parser, Subcommand, ValueEnum};
 use colored::*;
 use dialoguer::{theme::ColorfulTheme, Confirm, Input, Password, Select};
-use env_logger;
 use std::path::{Path, PathBuf};
-use tokio;
 use velocity::addon::BackupAddonConfig;
 use velocity::addon::DatabaseAddonConfig;
-use velocity::server::{hash_password, ServerConfig, VelocityServer};
+use velocity::capacity::CapacityReport;
+use velocity::fsutil::atomic_write;
+use velocity::identity::ServerIdentity;
+use velocity::layout::DataLayout;
+use velocity::server::{hash_password, BoundListeners, StartupStatus, VelocityServer};
 use velocity::{Velocity, VelocityConfig};
 
 mod config;
+mod doctor;
+mod preflight;
 mod service_runner;
 mod setup;
 use crate::config::ConfigFile;
@@ -36,12 +40,16 @@ enum Commands {
 
     #[command(about = "Administration commands")]
     Admin {
+        #[arg(long, global = true, value_enum, default_value_t = OutputFormat::Human)]
+        output: OutputFormat,
         #[command(subcommand)]
         subcommand: AdminCommands,
     },
 
     #[command(about = "Operations and service commands")]
     Ops {
+        #[arg(long, global = true, value_enum, default_value_t = OutputFormat::Human)]
+        output: OutputFormat,
         #[command(subcommand)]
         subcommand: OpsCommands,
     },
@@ -52,6 +60,16 @@ enum Commands {
         subcommand: SetupCommands,
     },
 
+    #[command(about = "Run diagnostics against the config and data directory")]
+    Doctor {
+        #[arg(short, long, default_value = "velocity.toml")]
+        config: PathBuf,
+        #[arg(short, long, default_value = "./velocitydb")]
+        data_dir: PathBuf,
+        #[arg(long, value_enum, default_value_t = OutputFormat::Human)]
+        output: OutputFormat,
+    },
+
     #[command(hide = true)]
     Server {
         #[arg(short, long, default_value = "velocity.toml")]
@@ -62,6 +80,8 @@ enum Commands {
         bind: Option<String>,
         #[arg(short, long)]
         verbose: bool,
+        #[arg(long)]
+        read_only: bool,
     },
 
     #[command(hide = true)]
@@ -78,6 +98,8 @@ enum Commands {
     Init {
         #[arg(short, long, default_value = "velocity.toml")]
         output: PathBuf,
+        #[command(flatten)]
+        options: InitOptions,
     },
 
     #[command(hide = true)]
@@ -92,6 +114,8 @@ enum Commands {
         config: PathBuf,
         #[arg(short, long, default_value = "./velocitydb")]
         data_dir: PathBuf,
+        #[arg(long)]
+        consistent: bool,
     },
 
     #[command(hide = true)]
@@ -122,9 +146,46 @@ enum Commands {
         config: PathBuf,
         #[arg(short, long, default_value = "./velocitydb")]
         data_dir: PathBuf,
+        /// Follow flush and compaction progress on a running server instead
+        /// of printing a snapshot
+        #[arg(long)]
+        follow: bool,
+        #[arg(short, long, default_value = "127.0.0.1:2005")]
+        address: String,
+        #[arg(short, long)]
+        username: Option<String>,
+        #[arg(short, long)]
+        password: Option<String>,
     },
 }
 
+/// How `admin`, `ops` and `doctor` report their results.
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum OutputFormat {
+    /// Colored text.
+    Human,
+    /// A single JSON document on stdout, for scripts and monitoring.
+    Json,
+}
+
+#[derive(Args, Clone, Debug)]
+struct InitOptions {
+    #[arg(long)]
+    non_interactive: bool,
+    #[arg(long, default_value = "VELOCITY_ADMIN_PASSWORD")]
+    admin_password_env: String,
+    #[arg(long, default_value = "admin")]
+    admin_user: String,
+    #[arg(long)]
+    bind: Option<String>,
+    #[arg(long)]
+    multi_tenant: bool,
+    #[arg(long)]
+    backup_interval_minutes: Option<u64>,
+    #[arg(long)]
+    force: bool,
+}
+
 #[derive(Subcommand)]
 enum AddonCommands {
     List {
@@ -156,10 +217,14 @@ enum DbCommands {
         bind: Option<String>,
         #[arg(short, long)]
         verbose: bool,
+        #[arg(long)]
+        read_only: bool,
     },
     Init {
         #[arg(short, long, default_value = "velocity.toml")]
         output: PathBuf,
+        #[command(flatten)]
+        options: InitOptions,
     },
     Studio {
         #[arg(short, long, default_value = "3000")]
@@ -185,6 +250,12 @@ enum AdminCommands {
         #[command(subcommand)]
         subcommand: AddonCommands,
     },
+    RotateIdentity {
+        #[arg(short, long, default_value = "./velocitydb")]
+        data_dir: PathBuf,
+        #[arg(long, default_value = "24")]
+        grace_hours: u64,
+    },
 }
 
 #[derive(Subcommand)]
@@ -194,12 +265,24 @@ enum OpsCommands {
         config: PathBuf,
         #[arg(short, long, default_value = "./velocitydb")]
         data_dir: PathBuf,
+        #[arg(long)]
+        consistent: bool,
     },
     Monitor {
         #[arg(short, long, default_value = "velocity.toml")]
         config: PathBuf,
         #[arg(short, long, default_value = "./velocitydb")]
         data_dir: PathBuf,
+        /// Follow flush and compaction progress on a running server instead
+        /// of printing a snapshot
+        #[arg(long)]
+        follow: bool,
+        #[arg(short, long, default_value = "127.0.0.1:2005")]
+        address: String,
+        #[arg(short, long)]
+        username: Option<String>,
+        #[arg(short, long)]
+        password: Option<String>,
     },
     Benchmark {
         #[arg(short, long, default_value = "./benchmark_db")]
@@ -211,6 +294,103 @@ enum OpsCommands {
         #[arg(long)]
         cache_size: Option<usize>,
     },
+    #[command(about = "Check that this binary can serve a data directory before switching to it")]
+    Preflight {
+        #[arg(short, long, default_value = "velocity.toml")]
+        config: PathBuf,
+        #[arg(short, long, default_value = "./velocitydb")]
+        data_dir: PathBuf,
+    },
+    #[command(about = "Project disk and cache needs from the recorded growth of each database")]
+    CapacityReport {
+        #[arg(short, long, default_value = "velocity.toml")]
+        config: PathBuf,
+        #[arg(short, long, default_value = "./velocitydb")]
+        data_dir: PathBuf,
+        #[arg(long)]
+        json: bool,
+    },
+    #[command(about = "Dump the header, index, bloom filter and records of one SSTable file")]
+    InspectSst {
+        #[arg(required_unless_present = "describe_format")]
+        file: Option<PathBuf>,
+        /// Also dump records, decoded and cut short at 256 bytes.
+        #[arg(long)]
+        records: bool,
+        /// First key to dump.
+        #[arg(long)]
+        start: Option<String>,
+        /// Dump keys before this one.
+        #[arg(long)]
+        end: Option<String>,
+        #[arg(long)]
+        limit: Option<usize>,
+        /// Print the SSTable file format instead.
+        #[arg(long)]
+        describe_format: bool,
+    },
+    #[command(about = "Drain a running server, flush its databases and wait for it to exit")]
+    Drain {
+        #[arg(short, long, default_value = "127.0.0.1:2005")]
+        address: String,
+        #[arg(short, long)]
+        username: Option<String>,
+        #[arg(short, long)]
+        password: Option<String>,
+        #[arg(long, default_value = "60")]
+        timeout_secs: u64,
+    },
+    #[command(about = "Drop a database on a running server")]
+    DropDatabase {
+        name: String,
+        /// Report what would be removed without removing it
+        #[arg(long)]
+        dry_run: bool,
+        /// Do not ask for confirmation
+        #[arg(short, long)]
+        yes: bool,
+        #[arg(short, long, default_value = "127.0.0.1:2005")]
+        address: String,
+        #[arg(short, long)]
+        username: Option<String>,
+        #[arg(short, long)]
+        password: Option<String>,
+    },
+    #[command(about = "Remove the trashed keys of a database on a running server for good")]
+    PurgeTrash {
+        #[arg(short, long, default_value = "default")]
+        database: String,
+        /// Report what would be removed without removing it
+        #[arg(long)]
+        dry_run: bool,
+        /// Do not ask for confirmation
+        #[arg(short, long)]
+        yes: bool,
+        #[arg(short, long, default_value = "127.0.0.1:2005")]
+        address: String,
+        #[arg(short, long)]
+        username: Option<String>,
+        #[arg(short, long)]
+        password: Option<String>,
+    },
+    #[command(about = "Delete every key under a prefix on a running server")]
+    DeletePrefix {
+        prefix: String,
+        #[arg(short, long, default_value = "default")]
+        database: String,
+        /// Report what would be removed without removing it
+        #[arg(long)]
+        dry_run: bool,
+        /// Do not ask for confirmation
+        #[arg(short, long)]
+        yes: bool,
+        #[arg(short, long, default_value = "127.0.0.1:2005")]
+        address: String,
+        #[arg(short, long)]
+        username: Option<String>,
+        #[arg(short, long)]
+        password: Option<String>,
+    },
     Service {
         #[command(subcommand)]
         subcommand: ServiceCommands,
@@ -232,6 +412,12 @@ enum ServiceCommands {
         pid_file: Option<PathBuf>,
         #[arg(long, default_value_t = true)]
         watch_config: bool,
+        #[arg(long)]
+        user: Option<String>,
+        #[arg(long)]
+        group: Option<String>,
+        #[arg(long)]
+        read_only: bool,
     },
     Install {
         #[arg(short, long, default_value = "./service_templates")]
@@ -268,12 +454,47 @@ enum SetupCommands {
     Paths,
 }
 
+// A running server that `ops` commands connect to.
+struct RemoteTarget {
+    address: String,
+    username: Option<String>,
+    password: Option<String>,
+}
+
+enum Removal {
+    DropDatabase(String),
+    PurgeTrash,
+    DeletePrefix(String),
+}
+
+impl Removal {
+    fn command(&self) -> String {
+        match self {
+            Removal::DropDatabase(name) => format!("DROP DATABASE {}", name),
+            Removal::PurgeTrash => "PURGE TRASH".to_string(),
+            // `!` escapes LIKE wildcards that are part of the prefix.
+            Removal::DeletePrefix(prefix) => {
+                let mut pattern = String::new();
+                for c in prefix.chars() {
+                    match c {
+                        '%' | '_' | '!' => pattern.extend(['!', c]),
+                        '\'' => pattern.push_str("''"),
+                        c => pattern.push(c),
+                    }
+                }
+                format!("DELETE FROM kv WHERE key LIKE '{}%' ESCAPE '!'", pattern)
+            }
+        }
+    }
+}
+
 enum ResolvedCommand {
     Server {
         config: PathBuf,
         data_dir: PathBuf,
         bind: Option<String>,
         verbose: bool,
+        read_only: bool,
     },
     CreateUser {
         username: Option<String>,
@@ -282,13 +503,19 @@ enum ResolvedCommand {
     },
     Init {
         output: PathBuf,
+        options: InitOptions,
     },
     Addon {
         subcommand: AddonCommands,
     },
+    RotateIdentity {
+        data_dir: PathBuf,
+        grace_hours: u64,
+    },
     Backup {
         config: PathBuf,
         data_dir: PathBuf,
+        consistent: bool,
     },
     Benchmark {
         data_dir: PathBuf,
@@ -296,6 +523,33 @@ enum ResolvedCommand {
         mode: String,
         cache_size: Option<usize>,
     },
+    Preflight {
+        config: PathBuf,
+        data_dir: PathBuf,
+    },
+    CapacityReport {
+        config: PathBuf,
+        data_dir: PathBuf,
+        json: bool,
+    },
+    InspectSst {
+        file: Option<PathBuf>,
+        records: Option<velocity::inspect::RecordFilter>,
+        describe_format: bool,
+    },
+    Drain {
+        address: String,
+        username: Option<String>,
+        password: Option<String>,
+        timeout_secs: u64,
+    },
+    Remove {
+        target: RemoteTarget,
+        database: Option<String>,
+        removal: Removal,
+        dry_run: bool,
+        yes: bool,
+    },
     Studio {
         port: u16,
         config: PathBuf,
@@ -304,6 +558,11 @@ enum ResolvedCommand {
     Monitor {
         config: PathBuf,
         data_dir: PathBuf,
+        follow: Option<RemoteTarget>,
+    },
+    Doctor {
+        config: PathBuf,
+        data_dir: PathBuf,
     },
     ServiceRun {
         config: PathBuf,
@@ -312,6 +571,9 @@ enum ResolvedCommand {
         verbose: bool,
         pid_file: Option<PathBuf>,
         watch_config: bool,
+        user: Option<String>,
+        group: Option<String>,
+        read_only: bool,
     },
     ServiceInstall {
         template_dir: PathBuf,
@@ -333,21 +595,29 @@ enum ResolvedCommand {
     SetupPaths,
 }
 
-fn resolve_command(command: Commands) -> ResolvedCommand {
-    match command {
+fn resolve_command(command: Commands) -> (ResolvedCommand, OutputFormat) {
+    let output = match &command {
+        Commands::Admin { output, .. }
+        | Commands::Ops { output, .. }
+        | Commands::Doctor { output, .. } => *output,
+        _ => OutputFormat::Human,
+    };
+    let command = match command {
         Commands::Db { subcommand } => match subcommand {
             DbCommands::Server {
                 config,
                 data_dir,
                 bind,
                 verbose,
+                read_only,
             } => ResolvedCommand::Server {
                 config,
                 data_dir,
                 bind,
                 verbose,
+                read_only,
             },
-            DbCommands::Init { output } => ResolvedCommand::Init { output },
+            DbCommands::Init { output, options } => ResolvedCommand::Init { output, options },
             DbCommands::Studio {
                 port,
                 config,
@@ -358,7 +628,7 @@ fn resolve_command(command: Commands) -> ResolvedCommand {
                 data_dir,
             },
         },
-        Commands::Admin { subcommand } => match subcommand {
+        Commands::Admin { subcommand, .. } => match subcommand {
             AdminCommands::CreateUser {
                 username,
                 password,
@@ -369,12 +639,40 @@ fn resolve_command(command: Commands) -> ResolvedCommand {
                 config,
             },
             AdminCommands::Addon { subcommand } => ResolvedCommand::Addon { subcommand },
+            AdminCommands::RotateIdentity {
+                data_dir,
+                grace_hours,
+            } => ResolvedCommand::RotateIdentity {
+                data_dir,
+                grace_hours,
+            },
         },
-        Commands::Ops { subcommand } => match subcommand {
-            OpsCommands::Backup { config, data_dir } => ResolvedCommand::Backup { config, data_dir },
-            OpsCommands::Monitor { config, data_dir } => {
-                ResolvedCommand::Monitor { config, data_dir }
-            }
+        Commands::Ops { subcommand, .. } => match subcommand {
+            OpsCommands::Backup {
+                config,
+                data_dir,
+                consistent,
+            } => ResolvedCommand::Backup {
+                config,
+                data_dir,
+                consistent,
+            },
+            OpsCommands::Monitor {
+                config,
+                data_dir,
+                follow,
+                address,
+                username,
+                password,
+            } => ResolvedCommand::Monitor {
+                config,
+                data_dir,
+                follow: follow.then_some(RemoteTarget {
+                    address,
+                    username,
+                    password,
+                }),
+            },
             OpsCommands::Benchmark {
                 data_dir,
                 operations,
@@ -386,6 +684,97 @@ fn resolve_command(command: Commands) -> ResolvedCommand {
                 mode,
                 cache_size,
             },
+            OpsCommands::Preflight { config, data_dir } => {
+                ResolvedCommand::Preflight { config, data_dir }
+            }
+            OpsCommands::CapacityReport {
+                config,
+                data_dir,
+                json,
+            } => ResolvedCommand::CapacityReport {
+                config,
+                data_dir,
+                json,
+            },
+            OpsCommands::InspectSst {
+                file,
+                records,
+                start,
+                end,
+                limit,
+                describe_format,
+            } => ResolvedCommand::InspectSst {
+                file,
+                records: (records || start.is_some() || end.is_some() || limit.is_some())
+                    .then_some(velocity::inspect::RecordFilter { start, end, limit }),
+                describe_format,
+            },
+            OpsCommands::Drain {
+                address,
+                username,
+                password,
+                timeout_secs,
+            } => ResolvedCommand::Drain {
+                address,
+                username,
+                password,
+                timeout_secs,
+            },
+            OpsCommands::DropDatabase {
+                name,
+                dry_run,
+                yes,
+                address,
+                username,
+                password,
+            } => ResolvedCommand::Remove {
+                target: RemoteTarget {
+                    address,
+                    username,
+                    password,
+                },
+                database: None,
+                removal: Removal::DropDatabase(name),
+                dry_run,
+                yes,
+            },
+            OpsCommands::PurgeTrash {
+                database,
+                dry_run,
+                yes,
+                address,
+                username,
+                password,
+            } => ResolvedCommand::Remove {
+                target: RemoteTarget {
+                    address,
+                    username,
+                    password,
+                },
+                database: Some(database),
+                removal: Removal::PurgeTrash,
+                dry_run,
+                yes,
+            },
+            OpsCommands::DeletePrefix {
+                prefix,
+                database,
+                dry_run,
+                yes,
+                address,
+                username,
+                password,
+            } => ResolvedCommand::Remove {
+                target: RemoteTarget {
+                    address,
+                    username,
+                    password,
+                },
+                database: Some(database),
+                removal: Removal::DeletePrefix(prefix),
+                dry_run,
+                yes,
+            },
             OpsCommands::Service { subcommand } => match subcommand {
                 ServiceCommands::Run {
                     config,
@@ -394,6 +783,9 @@ fn resolve_command(command: Commands) -> ResolvedCommand {
                     verbose,
                     pid_file,
                     watch_config,
+                    user,
+                    group,
+                    read_only,
                 } => ResolvedCommand::ServiceRun {
                     config,
                     data_dir,
@@ -401,6 +793,9 @@ fn resolve_command(command: Commands) -> ResolvedCommand {
                     verbose,
                     pid_file,
                     watch_config,
+                    user,
+                    group,
+                    read_only,
                 },
                 ServiceCommands::Install {
                     template_dir,
@@ -441,11 +836,13 @@ fn resolve_command(command: Commands) -> ResolvedCommand {
             data_dir,
             bind,
             verbose,
+            read_only,
         } => ResolvedCommand::Server {
             config,
             data_dir,
             bind,
             verbose,
+            read_only,
         },
         Commands::CreateUser {
             username,
@@ -456,9 +853,17 @@ fn resolve_command(command: Commands) -> ResolvedCommand {
             password,
             config,
         },
-        Commands::Init { output } => ResolvedCommand::Init { output },
+        Commands::Init { output, options } => ResolvedCommand::Init { output, options },
         Commands::Addon { subcommand } => ResolvedCommand::Addon { subcommand },
-        Commands::Backup { config, data_dir } => ResolvedCommand::Backup { config, data_dir },
+        Commands::Backup {
+            config,
+            data_dir,
+            consistent,
+        } => ResolvedCommand::Backup {
+            config,
+            data_dir,
+            consistent,
+        },
         Commands::Benchmark {
             data_dir,
             operations,
@@ -479,18 +884,42 @@ fn resolve_command(command: Commands) -> ResolvedCommand {
             config,
             data_dir,
         },
-        Commands::Monitor { config, data_dir } => ResolvedCommand::Monitor { config, data_dir },
-    }
+        Commands::Monitor {
+            config,
+            data_dir,
+            follow,
+            address,
+            username,
+            password,
+        } => ResolvedCommand::Monitor {
+            config,
+            data_dir,
+            follow: follow.then_some(RemoteTarget {
+                address,
+                username,
+                password,
+            }),
+        },
+        Commands::Doctor {
+            config, data_dir, ..
+        } => ResolvedCommand::Doctor { config, data_dir },
+    };
+    (command, output)
 }
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
     let cli = Cli::parse();
-    let command = resolve_command(cli.command);
+    let (command, output) = resolve_command(cli.command);
+    let json = output == OutputFormat::Json;
 
     match command {
-        ResolvedCommand::Init { output } => {
-            handle_init(&output).await?;
+        ResolvedCommand::Init { output, options } => {
+            if options.non_interactive {
+                handle_init_non_interactive(&output, &options)?;
+            } else {
+                handle_init(&output, &options).await?;
+            }
         }
 
         ResolvedCommand::Server {
@@ -498,11 +927,11 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
             data_dir,
             bind,
             verbose,
+            read_only,
         } => {
 
             let file_config = if config.exists() {
-                let content = std::fs::read_to_string(&config)?;
-                toml::from_str::<ConfigFile>(&content)?
+                ConfigFile::load(&config)?
             } else {
                 println!(
                     "{} Config file not found, creating default...",
@@ -510,7 +939,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                 );
                 let default_cfg = ConfigFile::default();
                 let toml_string = toml::to_string_pretty(&default_cfg)?;
-                std::fs::write(&config, toml_string)?;
+                atomic_write(&config, toml_string)?;
                 default_cfg
             };
 
@@ -531,6 +960,9 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                     verbose,
                     pid_file: Some(background_service_cfg.pid_file),
                     watch_config: background_service_cfg.watch_config,
+                    run_as_user: None,
+                    run_as_group: None,
+                    read_only,
                 })
                 .await?;
                 return Ok(());
@@ -552,36 +984,17 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                 config
             );
 
-            let mut server_config = ServerConfig::default();
+            velocity::format::check_data_dir(&data_dir, file_config.addons.database.as_ref())?;
+            let bind_override = bind.clone();
             let bind_addr = bind.unwrap_or(file_config.server.bind_address.clone());
-            server_config.bind_address = bind_addr.parse()?;
-            server_config.max_connections = file_config.server.max_connections;
-            server_config.connection_timeout =
-                std::time::Duration::from_secs(file_config.server.connection_timeout_seconds);
-            server_config.rate_limit_per_second = file_config.server.rate_limit_per_second;
-            server_config.users = file_config.users;
-            server_config.audit_log_path = file_config.security.audit_log_path;
-            server_config.audit_logging = file_config.security.audit_logging;
-            server_config.auth_ban_duration =
-                std::time::Duration::from_secs(file_config.security.auth_ban_duration);
-            server_config.max_auth_failures = file_config.security.max_auth_failures;
-
-
+            let mut server_config = file_config.server_config(&bind_addr, &data_dir)?;
+            server_config.read_only |= read_only;
+            let read_only_server = server_config.read_only;
+            let startup = StartupStatus::new();
             let db_config = VelocityConfig {
-                max_memtable_size: file_config.database.max_memtable_size,
-                cache_size: file_config.database.cache_size,
-                bloom_false_positive_rate: file_config.database.bloom_false_positive_rate,
-                compaction_threshold: file_config.database.compaction_threshold,
-                enable_compression: file_config.database.enable_compression,
-                memory_only_mode: false,
-                batch_wal_writes: true,
-                adaptive_cache: file_config.performance.adaptive_cache,
-                enable_metrics: file_config.performance.enable_metrics,
-                metrics_interval: std::time::Duration::from_secs(
-                    file_config.performance.metrics_interval,
-                ),
-                target_cache_hit_rate: file_config.performance.target_cache_hit_rate,
-                wal_sync_mode: velocity::WalSyncMode::Batch,
+                recovery_progress: Some(startup.recovery_callback()),
+                read_only: server_config.read_only,
+                ..file_config.velocity_config()
             };
 
             println!(
@@ -589,59 +1002,67 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                 "[STORAGE]".blue(),
                 data_dir
             );
-            let db = Velocity::open_with_config(&data_dir, db_config)?;
-
-
-            let db_manager =
-                std::sync::Arc::new(velocity::addon::DatabaseManager::new(db, config.clone()));
+            // Bound first, so clients that connect during recovery are told
+            // the server is still recovering.
+            let listeners = BoundListeners::bind(&server_config).await?;
+            let file_config = std::sync::Arc::new(file_config);
+            let opening = tokio::task::spawn_blocking({
+                let file_config = file_config.clone();
+                let data_dir = data_dir.clone();
+                let config = config.clone();
+                move || -> velocity::VeloResult<_> {
+                    let db = file_config.open_database(&data_dir, db_config)?;
+                    let quarantined = db.quarantined_tables().to_vec();
+                    let manager = velocity::addon::DatabaseManager::new(db, config);
+                    Ok((manager, quarantined))
+                }
+            });
+            let (db_manager, quarantined) = listeners
+                .answer_until(&server_config, &startup, opening)
+                .await??;
+            startup.record_quarantined(&quarantined);
+            let db_manager = std::sync::Arc::new(db_manager);
 
 
-            let server = VelocityServer::new(db_manager.clone(), server_config)?;
+            let server = VelocityServer::new(db_manager.clone(), server_config)?
+                .with_startup_status(startup);
 
 
 
 
             if let Some(backup_cfg) = &file_config.addons.backup {
                 if backup_cfg.enabled {
-                    let manager = db_manager.clone();
-                    let interval = backup_cfg.interval_minutes;
-                    println!(
-                        "{} Automatic backups enabled ({} min interval)",
-                        "[INFO]".green(),
-                        interval
-                    );
+                    match db_manager.next_backup_run() {
+                        Ok(Some(next)) => println!(
+                            "{} Automatic backups enabled (next run {})",
+                            "[INFO]".green(),
+                            next.format("%Y-%m-%d %H:%M")
+                        ),
+                        Ok(None) => println!(
+                            "{} Automatic backups enabled but the schedule never permits a run",
+                            "[WARN]".yellow()
+                        ),
+                        Err(e) => println!("{} Invalid backup schedule: {}", "[WARN]".yellow(), e),
+                    }
 
-                    tokio::spawn(async move {
-                        let mut interval_timer =
-                            tokio::time::interval(std::time::Duration::from_secs(interval * 60));
-                        interval_timer.tick().await;
-
-                        loop {
-                            interval_timer.tick().await;
-
-                            let current_enabled = manager
-                                .list_addons()
-                                .iter()
-                                .find(|(name, _)| name == "backup")
-                                .map(|(_, enabled)| *enabled)
-                                .unwrap_or(false);
-
-                            if current_enabled {
-                                match manager.backup_all_databases() {
-                                    Ok(succ) => log::info!(
-                                        "Automatic backup successful for {} databases",
-                                        succ.len()
-                                    ),
-                                    Err(e) => log::error!("Automatic backup failed: {}", e),
-                                }
-                            }
-                        }
-                    });
+                    tokio::spawn(velocity::backup_schedule::run_backup_schedule(
+                        db_manager.clone(),
+                    ));
                 }
             }
+            // Storage is opened read-only too; these would only fail.
+            if !read_only_server {
+                tokio::spawn(velocity::addon::run_trash_retention(db_manager.clone()));
+                tokio::spawn(velocity::addon::run_memtable_auto_flush(db_manager.clone()));
+            }
+            tokio::spawn(velocity::addon::run_capacity_sampling(db_manager.clone()));
+            tokio::spawn(velocity::addon::run_cache_warmup(db_manager.clone()));
 
 
             let manager_for_watcher = db_manager.clone();
+            let server_for_watcher = server.clone();
+            let config_for_watcher = config.clone();
+            let data_dir_for_watcher = data_dir.clone();
             let (tx, mut rx) = tokio::sync::mpsc::channel(1);
 
             let mut watcher =
@@ -658,12 +1079,19 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
             watcher.watch(&config, notify::RecursiveMode::NonRecursive)?;
 
             tokio::spawn(async move {
-                while let Some(_) = rx.recv().await {
+                while rx.recv().await.is_some() {
                     println!(
                         "{} Configuration change detected, reloading...",
                         "[CONFIG]".blue()
                     );
                     let _ = manager_for_watcher.reload_config();
+                    service_runner::reload_listeners(
+                        &server_for_watcher,
+                        &config_for_watcher,
+                        bind_override.as_deref(),
+                        &data_dir_for_watcher,
+                    )
+                    .await;
                 }
             });
 
@@ -672,7 +1100,13 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                 "[SERVER]".green(),
                 bind_addr.bold()
             );
-            server.start().await?;
+            server.serve(listeners, server.drain_requested()).await?;
+
+            let drain_timeout =
+                std::time::Duration::from_secs(file_config.server.drain_timeout_seconds);
+            server.drain(drain_timeout).await;
+            db_manager.close_all()?;
+            println!("{} Drained and shut down", "[SERVER]".green());
         }
 
         ResolvedCommand::CreateUser {
@@ -696,24 +1130,45 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                     .interact()?
             };
 
+            let policy = if config.exists() {
+                ConfigFile::load(&config)?.security.password_policy
+            } else {
+                velocity::users::PasswordPolicy::default()
+            };
+            policy.validate(&pass)?;
+
             let hash = hash_password(&pass)?;
 
 
             if config.exists() {
-                let content = std::fs::read_to_string(&config)?;
-                let mut toml_config: ConfigFile = toml::from_str(&content)?;
+                let mut toml_config = ConfigFile::load(&config)?;
 
                 toml_config.users.insert(user.clone(), hash.clone());
 
                 let new_content = toml::to_string_pretty(&toml_config)?;
-                std::fs::write(&config, new_content)?;
-
-                println!(
-                    "\n{} User {} created and saved to {:?}",
-                    "[SUCCESS]".green(),
-                    user.bold().cyan(),
-                    config
-                );
+                atomic_write(&config, new_content)?;
+
+                if json {
+                    print_json(&serde_json::json!({
+                        "user": user,
+                        "config": config,
+                        "saved": true,
+                    }))?;
+                } else {
+                    println!(
+                        "\n{} User {} created and saved to {:?}",
+                        "[SUCCESS]".green(),
+                        user.bold().cyan(),
+                        config
+                    );
+                }
+            } else if json {
+                print_json(&serde_json::json!({
+                    "user": user,
+                    "config": config,
+                    "saved": false,
+                    "hash": hash,
+                }))?;
             } else {
                 println!("\n{} Config file {:?} not found!", "[ERROR]".red(), config);
                 println!(
@@ -730,58 +1185,61 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                 if !config.exists() {
                     return Err(format!("Config file {:?} not found!", config).into());
                 }
-                let content = std::fs::read_to_string(&config)?;
-                let toml_config: ConfigFile = toml::from_str(&content)?;
-
-                println!("{} Available Addons:", "[ADDON]".blue());
-                let db_status = if toml_config
-                    .addons
-                    .database
-                    .as_ref()
-                    .map(|a| a.enabled)
-                    .unwrap_or(false)
-                {
-                    "Enabled".green()
-                } else {
-                    "Disabled".red()
-                };
-                let backup_status = if toml_config
-                    .addons
-                    .backup
-                    .as_ref()
-                    .map(|a| a.enabled)
-                    .unwrap_or(false)
-                {
-                    "Enabled".green()
-                } else {
-                    "Disabled".red()
-                };
-                let background_service_status = if toml_config
-                    .addons
-                    .background_service
-                    .as_ref()
-                    .map(|a| a.enabled)
-                    .unwrap_or(true)
-                {
-                    "Enabled".green()
+                let toml_config = ConfigFile::load(&config)?;
+
+                let addons = [
+                    (
+                        "database",
+                        toml_config
+                            .addons
+                            .database
+                            .as_ref()
+                            .map(|a| a.enabled)
+                            .unwrap_or(false),
+                    ),
+                    (
+                        "backup",
+                        toml_config
+                            .addons
+                            .backup
+                            .as_ref()
+                            .map(|a| a.enabled)
+                            .unwrap_or(false),
+                    ),
+                    (
+                        "background-service",
+                        toml_config
+                            .addons
+                            .background_service
+                            .as_ref()
+                            .map(|a| a.enabled)
+                            .unwrap_or(true),
+                    ),
+                ];
+
+                if json {
+                    let addons: Vec<_> = addons
+                        .iter()
+                        .map(|(name, enabled)| serde_json::json!({ "name": name, "enabled": enabled }))
+                        .collect();
+                    print_json(&addons)?;
                 } else {
-                    "Disabled".red()
-                };
-
-                println!("  - {}: {}", "database".bold(), db_status);
-                println!("  - {}: {}", "backup".bold(), backup_status);
-                println!(
-                    "  - {}: {}",
-                    "background-service".bold(),
-                    background_service_status
-                );
+                    println!("{} Available Addons:", "[ADDON]".blue());
+                    for (name, enabled) in addons {
+                        let status = if enabled {
+                            "Enabled".green()
+                        } else {
+                            "Disabled".red()
+                        };
+                        println!("  - {}: {}", name.bold(), status);
+                    }
+                }
             }
             AddonCommands::Enable { name, config } => {
                 if !config.exists() {
                     return Err(format!("Config file {:?} not found!", config).into());
                 }
-                let content = std::fs::read_to_string(&config)?;
-                let mut toml_config: ConfigFile = toml::from_str(&content)?;
+                let mut toml_config = ConfigFile::load(&config)?;
 
                 match name.to_lowercase().as_str() {
                     "database" => {
@@ -803,19 +1261,22 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                 }
 
                 let new_content = toml::to_string_pretty(&toml_config)?;
-                std::fs::write(&config, new_content)?;
-                println!(
-                    "{} Addon {} enabled.",
-                    "[SUCCESS]".green(),
-                    name.bold().cyan()
-                );
+                atomic_write(&config, new_content)?;
+                if json {
+                    print_json(&serde_json::json!({ "name": name, "enabled": true }))?;
+                } else {
+                    println!(
+                        "{} Addon {} enabled.",
+                        "[SUCCESS]".green(),
+                        name.bold().cyan()
+                    );
+                }
             }
             AddonCommands::Disable { name, config } => {
                 if !config.exists() {
                     return Err(format!("Config file {:?} not found!", config).into());
                 }
-                let content = std::fs::read_to_string(&config)?;
-                let mut toml_config: ConfigFile = toml::from_str(&content)?;
+                let mut toml_config = ConfigFile::load(&config)?;
 
                 match name.to_lowercase().as_str() {
                     "database" => {
@@ -839,22 +1300,86 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                 }
 
                 let new_content = toml::to_string_pretty(&toml_config)?;
-                std::fs::write(&config, new_content)?;
+                atomic_write(&config, new_content)?;
+                if json {
+                    print_json(&serde_json::json!({ "name": name, "enabled": false }))?;
+                } else {
+                    println!(
+                        "{} Addon {} disabled.",
+                        "[SUCCESS]".green(),
+                        name.bold().cyan()
+                    );
+                }
+            }
+        },
+
+        ResolvedCommand::RotateIdentity {
+            data_dir,
+            grace_hours,
+        } => {
+            let path = ServerIdentity::path_in(&data_dir);
+            let mut identity = ServerIdentity::load_or_generate(&path)?;
+            let old_fingerprint = identity.fingerprint();
+
+            identity.rotate(std::time::Duration::from_secs(grace_hours * 60 * 60));
+            identity.save(&path)?;
+
+            if json {
+                print_json(&serde_json::json!({
+                    "path": path,
+                    "fingerprint": identity.fingerprint(),
+                    "previous_fingerprint": old_fingerprint,
+                    "grace_hours": grace_hours,
+                }))?;
+            } else {
                 println!(
-                    "{} Addon {} disabled.",
+                    "{} Server identity rotated ({:?})",
                     "[SUCCESS]".green(),
-                    name.bold().cyan()
+                    path
+                );
+                println!(
+                    "  New fingerprint: {}",
+                    identity.fingerprint().bold().cyan()
+                );
+                if grace_hours > 0 {
+                    println!(
+                        "  Old fingerprint: {} (accepted for {} more hours)",
+                        old_fingerprint, grace_hours
+                    );
+                } else {
+                    println!("  Old fingerprint: {} (revoked)", old_fingerprint);
+                }
+                println!(
+                    "A running server picks up the new identity and reloads its TLS certificates on the next connection."
                 );
             }
-        },
+        }
 
-        ResolvedCommand::Backup { config, data_dir } => {
+        ResolvedCommand::Backup {
+            config,
+            data_dir,
+            consistent,
+        } => {
             let db_config = VelocityConfig::default();
             let db = Velocity::open_with_config(&data_dir, db_config)?;
             let manager = velocity::addon::DatabaseManager::new(db, config);
 
-            println!("{} Starting manual backup...", "[BACKUP]".blue());
-            match manager.backup_all_databases() {
+            if !json {
+                println!("{} Starting manual backup...", "[BACKUP]".blue());
+            }
+            let result = if consistent {
+                manager.backup_snapshot()
+            } else {
+                manager.backup_all_databases()
+            };
+            match result {
+                // A failed backup exits non-zero instead of printing a
+                // document scripts would have to inspect.
+                Ok(succ) if json => print_json(&serde_json::json!({
+                    "consistent": consistent,
+                    "databases": succ,
+                }))?,
+                Err(e) if json => return Err(e.into()),
                 Ok(succ) => {
                     println!(
                         "{} Backup completed successfully for {} databases.",
@@ -869,36 +1394,122 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
             }
         }
 
-        ResolvedCommand::Monitor { config, data_dir } => {
-            if !config.exists() {
-                return Err(format!("Config file {:?} not found!", config).into());
+        ResolvedCommand::Preflight { config, data_dir } => {
+            let checks = preflight::run_preflight(&config, &data_dir);
+            if print_checks(&checks, output)? == doctor::CheckStatus::Fail {
+                std::process::exit(1);
             }
+        }
 
-            let content = std::fs::read_to_string(&config)?;
-            let toml_config: ConfigFile = toml::from_str(&content)?;
-
-            let velocity_config = VelocityConfig {
-                max_memtable_size: toml_config.database.max_memtable_size,
-                cache_size: toml_config.database.cache_size,
-                bloom_false_positive_rate: toml_config.database.bloom_false_positive_rate,
-                compaction_threshold: toml_config.database.compaction_threshold,
-                enable_compression: toml_config.database.enable_compression,
-                memory_only_mode: false,
-                batch_wal_writes: true,
-                adaptive_cache: toml_config.performance.adaptive_cache,
-                enable_metrics: toml_config.performance.enable_metrics,
-                metrics_interval: std::time::Duration::from_secs(
-                    toml_config.performance.metrics_interval,
-                ),
-                target_cache_hit_rate: toml_config.performance.target_cache_hit_rate,
-                wal_sync_mode: velocity::WalSyncMode::Batch,
+        ResolvedCommand::CapacityReport {
+            config,
+            data_dir,
+            json,
+        } => {
+            let file_config = if config.exists() {
+                ConfigFile::load(&config)?
+            } else {
+                ConfigFile::default()
             };
+            let report = CapacityReport::build(
+                &data_dir,
+                &file_config.velocity_config(),
+                file_config.addons.database.as_ref(),
+            )?;
+            if json || output == OutputFormat::Json {
+                println!("{}", serde_json::to_string_pretty(&report)?);
+            } else {
+                print_capacity_report(&report);
+            }
+        }
 
-            let db = Velocity::open_with_config(&data_dir, velocity_config)?;
-            let manager =
-                std::sync::Arc::new(velocity::addon::DatabaseManager::new(db, config.clone()));
+        ResolvedCommand::InspectSst {
+            file,
+            records,
+            describe_format,
+        } => {
+            if describe_format {
+                print!("{}", velocity::inspect::SSTABLE_FORMAT);
+                return Ok(());
+            }
+            let file = file.ok_or("an SSTable file is required")?;
+            let inspection = velocity::inspect::inspect_sstable(&file, records.as_ref())?;
+            if json {
+                print_json(&inspection)?;
+            } else {
+                print_sst_inspection(&inspection);
+            }
+            if inspection.problem.is_some() {
+                std::process::exit(1);
+            }
+        }
 
-            let stats = manager.stats();
+        ResolvedCommand::Drain {
+            address,
+            username,
+            password,
+            timeout_secs,
+        } => {
+            let user = match username {
+                Some(u) => u,
+                None => Input::<String>::with_theme(&ColorfulTheme::default())
+                    .with_prompt("Username")
+                    .interact_text()?,
+            };
+            let pass = match password {
+                Some(p) => p,
+                None => Password::with_theme(&ColorfulTheme::default())
+                    .with_prompt("Password")
+                    .interact()?,
+            };
+
+            let mut client = velocity::client::VelocityClient::connect(&address).await?;
+            client.authenticate(&user, &pass).await?;
+            if !json {
+                println!("{} Draining server at {}...", "[OPS]".blue(), address);
+            }
+            client
+                .drain_server(std::time::Duration::from_secs(timeout_secs))
+                .await?;
+            if json {
+                print_json(&serde_json::json!({ "address": address, "drained": true }))?;
+            } else {
+                println!("{} Server drained and shut down", "[OPS]".green());
+            }
+        }
+
+        ResolvedCommand::Remove {
+            target,
+            database,
+            removal,
+            dry_run,
+            yes,
+        } => remove(target, database, removal, dry_run, yes, json).await?,
+
+        ResolvedCommand::Monitor {
+            follow: Some(target),
+            ..
+        } => follow_maintenance(target, json).await?,
+
+        ResolvedCommand::Monitor {
+            config, data_dir, ..
+        } => {
+            if !config.exists() {
+                return Err(format!("Config file {:?} not found!", config).into());
+            }
+
+            let toml_config = ConfigFile::load(&config)?;
+
+            let velocity_config = VelocityConfig {
+                fault_injection: None,
+                ..toml_config.velocity_config()
+            };
+
+            let db = Velocity::open_with_config(&data_dir, velocity_config)?;
+            let manager =
+                std::sync::Arc::new(velocity::addon::DatabaseManager::new(db, config.clone()));
+
+            let stats = manager.stats();
             let wal_report = manager
                 .get_database("default")
                 .ok_or_else(|| VeloError::InvalidOperation("Default database missing".to_string()))?
@@ -918,78 +1529,144 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                 .collect::<Vec<_>>()
                 .join(", ");
 
-            println!("\n{} Monitoring snapshot", "[MONITOR]".cyan().bold());
-            println!("Config file: {}", config.display());
-            println!(
-                "Studio console: `velocity studio --port 3000 --config {} --data-dir {}`",
-                config.display(),
-                data_dir.display()
-            );
-            println!(
-                "Multi-tenant addon: {}",
-                if multi_tenant { "enabled" } else { "disabled" }
-            );
-            println!("Addon states: {}", addon_status);
+            if json {
+                let (next_run, schedule_error) = match manager.next_backup_run() {
+                    _ if !backup_cfg.enabled => (None, None),
+                    Ok(next) => (next.map(|next| next.to_rfc3339()), None),
+                    Err(e) => (None, Some(e.to_string())),
+                };
+                print_json(&serde_json::json!({
+                    "config": config,
+                    "data_dir": data_dir,
+                    "multi_tenant": multi_tenant,
+                    "addons": manager.list_addons().into_iter().collect::<std::collections::BTreeMap<_, _>>(),
+                    "metrics": {
+                        "enabled": toml_config.performance.enable_metrics,
+                        "interval_seconds": toml_config.performance.metrics_interval,
+                        "target_cache_hit_rate": toml_config.performance.target_cache_hit_rate,
+                    },
+                    "stats": stats,
+                    "backup": {
+                        "config": backup_cfg,
+                        "next_run": next_run,
+                        "schedule_error": schedule_error,
+                    },
+                    "wal": {
+                        "path": DataLayout::new(&data_dir, toml_config.database.data_layout).wal_path(),
+                        "total_records": wal_report.total_records,
+                        "corrupted_records": wal_report.corrupted_records,
+                        "truncated_records": wal_report.truncated_records,
+                        "corrupted_keys": wal_report.corrupted_keys,
+                    },
+                }))?;
+            } else {
+                println!("\n{} Monitoring snapshot", "[MONITOR]".cyan().bold());
+                println!("Config file: {}", config.display());
+                println!(
+                    "Studio console: `velocity studio --port 3000 --config {} --data-dir {}`",
+                    config.display(),
+                    data_dir.display()
+                );
+                println!(
+                    "Multi-tenant addon: {}",
+                    if multi_tenant { "enabled" } else { "disabled" }
+                );
+                println!("Addon states: {}", addon_status);
 
-            println!("\n{} Metrics", "[METRICS]".cyan().bold());
-            println!(
-                "Collector: {}",
-                if toml_config.performance.enable_metrics {
-                    "enabled"
-                } else {
-                    "disabled"
+                println!("\n{} Metrics", "[METRICS]".cyan().bold());
+                println!(
+                    "Collector: {}",
+                    if toml_config.performance.enable_metrics {
+                        "enabled"
+                    } else {
+                        "disabled"
+                    }
+                );
+                println!(
+                    "Interval: {} seconds",
+                    toml_config.performance.metrics_interval
+                );
+                println!(
+                    "Target cache hit rate: {:.2}",
+                    toml_config.performance.target_cache_hit_rate
+                );
+                println!("Aggregate stats:");
+                println!("  - memtable entries: {}", stats.memtable_entries);
+                println!("  - sstable count: {}", stats.sstable_count);
+                println!("  - cache entries: {}", stats.cache_entries);
+                println!("  - total SSTable size: {} bytes", stats.total_sstable_size);
+
+                println!("\n{} Backup strategy", "[BACKUP]".cyan().bold());
+                println!("Enabled: {}", backup_cfg.enabled);
+                println!("Path: {:?}", backup_cfg.backup_path);
+                println!("Interval: {} minutes", backup_cfg.interval_minutes);
+                println!("Consistent snapshots: {}", backup_cfg.consistent);
+                if let Some(cron) = &backup_cfg.cron {
+                    println!("Cron: {}", cron);
                 }
-            );
-            println!(
-                "Interval: {} seconds",
-                toml_config.performance.metrics_interval
-            );
-            println!(
-                "Target cache hit rate: {:.2}",
-                toml_config.performance.target_cache_hit_rate
-            );
-            println!("Aggregate stats:");
-            println!("  - memtable entries: {}", stats.memtable_entries);
-            println!("  - sstable count: {}", stats.sstable_count);
-            println!("  - cache entries: {}", stats.cache_entries);
-            println!("  - total SSTable size: {} bytes", stats.total_sstable_size);
-
-            println!("\n{} Backup strategy", "[BACKUP]".cyan().bold());
-            println!("Enabled: {}", backup_cfg.enabled);
-            println!("Path: {:?}", backup_cfg.backup_path);
-            println!("Interval: {} minutes", backup_cfg.interval_minutes);
-            println!(
-                "Scope: {}",
-                if backup_cfg.backup_all {
-                    "all managed databases"
-                } else {
-                    "targeted subset"
+                if !backup_cfg.allowed_windows.is_empty() {
+                    println!("Allowed windows: {}", backup_cfg.allowed_windows.join(", "));
+                }
+                if !backup_cfg.blackout_periods.is_empty() {
+                    println!(
+                        "Blackout periods: {}",
+                        backup_cfg.blackout_periods.join(", ")
+                    );
+                }
+                if backup_cfg.enabled {
+                    match manager.next_backup_run() {
+                        Ok(Some(next)) => println!("Next run: {}", next.format("%Y-%m-%d %H:%M")),
+                        Ok(None) => println!("Next run: never (schedule has no permitted slot)"),
+                        Err(e) => println!("Next run: invalid schedule ({})", e),
+                    }
+                }
+                println!(
+                    "Scope: {}",
+                    if backup_cfg.backup_all {
+                        "all managed databases"
+                    } else {
+                        "targeted subset"
+                    }
+                );
+                if !backup_cfg.target_databases.is_empty() {
+                    println!("Targets: {}", backup_cfg.target_databases.join(", "));
                 }
-            );
-            if !backup_cfg.target_databases.is_empty() {
-                println!("Targets: {}", backup_cfg.target_databases.join(", "));
-            }
 
-            println!("\n{} Upgrade story", "[UPGRADE]".cyan().bold());
-            println!("  1. Stop the running binary.");
-            println!("  2. Pull latest code (`git pull`).");
-            println!("  3. Rebuild via `make release` or `cargo install --path .`.");
-            println!("  4. Restart against the same data directory to let WAL replay finish.");
-            println!("  - Docker users: `docker-compose down && docker-compose up -d --build`.");
-
-            println!("\n{} Corruption detection", "[INTEGRITY]".cyan().bold());
-            println!("WAL file: {}/velocity.wal", data_dir.display());
-            println!("Total WAL entries: {}", wal_report.total_records);
-            println!("Checksum mismatches: {}", wal_report.corrupted_records);
-            println!(
-                "Incomplete/truncated WAL records: {}",
-                wal_report.truncated_records
-            );
-            if !wal_report.corrupted_keys.is_empty() {
+                println!("\n{} Upgrade story", "[UPGRADE]".cyan().bold());
+                println!("  1. Stop the running binary.");
+                println!("  2. Pull latest code (`git pull`).");
+                println!("  3. Rebuild via `make release` or `cargo install --path .`.");
+                println!("  4. Restart against the same data directory to let WAL replay finish.");
                 println!(
-                    "Sample inconsistent keys: {}",
-                    wal_report.corrupted_keys.join(", ")
+                    "  - Docker users: `docker-compose down && docker-compose up -d --build`."
                 );
+
+                println!("\n{} Corruption detection", "[INTEGRITY]".cyan().bold());
+                println!(
+                    "WAL file: {}",
+                    DataLayout::new(&data_dir, toml_config.database.data_layout)
+                        .wal_path()
+                        .display()
+                );
+                println!("Total WAL entries: {}", wal_report.total_records);
+                println!("Checksum mismatches: {}", wal_report.corrupted_records);
+                println!(
+                    "Incomplete/truncated WAL records: {}",
+                    wal_report.truncated_records
+                );
+                if !wal_report.corrupted_keys.is_empty() {
+                    println!(
+                        "Sample inconsistent keys: {}",
+                        wal_report.corrupted_keys.join(", ")
+                    );
+                }
+            }
+        }
+
+        ResolvedCommand::Doctor { config, data_dir } => {
+            let checks = doctor::run_doctor(&config, &data_dir);
+            if print_checks(&checks, output)? == doctor::CheckStatus::Fail {
+                std::process::exit(1);
             }
         }
 
@@ -999,7 +1676,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
             mode,
             cache_size,
         } => {
-            run_benchmark(&data_dir, operations, mode, cache_size).await?;
+            run_benchmark(&data_dir, operations, mode, cache_size, output).await?;
         }
 
         ResolvedCommand::Studio {
@@ -1022,6 +1699,9 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
             verbose,
             pid_file,
             watch_config,
+            user,
+            group,
+            read_only,
         } => {
             run_velocity_service(ServiceSpec {
                 config_path: config,
@@ -1030,6 +1710,9 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                 verbose,
                 pid_file,
                 watch_config,
+                run_as_user: user,
+                run_as_group: group,
+                read_only,
             })
             .await?;
         }
@@ -1039,10 +1722,37 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
             data_dir,
             bind,
         } => {
-            install_service_templates(&template_dir, &config, &data_dir, bind.as_ref())?;
+            let files =
+                install_service_templates(&template_dir, &config, &data_dir, bind.as_ref())?;
+            if json {
+                print_json(&serde_json::json!({ "template_dir": template_dir, "files": files }))?;
+            } else {
+                println!(
+                    "{} Service templates written to {:?}",
+                    "[SUCCESS]".green(),
+                    template_dir
+                );
+            }
         }
         ResolvedCommand::ServiceUninstall { template_dir } => {
-            uninstall_service_templates(&template_dir)?;
+            let removed = uninstall_service_templates(&template_dir)?;
+            if json {
+                print_json(
+                    &serde_json::json!({ "template_dir": template_dir, "removed": removed }),
+                )?;
+            } else if removed {
+                println!(
+                    "{} Removed templates under {:?}",
+                    "[WARN]".yellow(),
+                    template_dir
+                );
+            } else {
+                println!(
+                    "{} No templates found at {:?}",
+                    "[WARN]".yellow(),
+                    template_dir
+                );
+            }
         }
         ResolvedCommand::SetupInstall {
             config,
@@ -1069,35 +1779,351 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     Ok(())
 }
 
+fn print_json<T: serde::Serialize + ?Sized>(value: &T) -> Result<(), Box<dyn std::error::Error>> {
+    println!("{}", serde_json::to_string_pretty(value)?);
+    Ok(())
+}
+
+async fn connect_remote(
+    target: &RemoteTarget,
+) -> Result<velocity::client::VelocityClient, Box<dyn std::error::Error>> {
+    let user = match &target.username {
+        Some(u) => u.clone(),
+        None => Input::<String>::with_theme(&ColorfulTheme::default())
+            .with_prompt("Username")
+            .interact_text()?,
+    };
+    let pass = match &target.password {
+        Some(p) => p.clone(),
+        None => Password::with_theme(&ColorfulTheme::default())
+            .with_prompt("Password")
+            .interact()?,
+    };
+    let mut client = velocity::client::VelocityClient::connect(&target.address).await?;
+    client.authenticate(&user, &pass).await?;
+    Ok(client)
+}
+
+// Reports what `removal` would remove, then removes it unless this is a
+// dry run or the user declines.
+async fn remove(
+    target: RemoteTarget,
+    database: Option<String>,
+    removal: Removal,
+    dry_run: bool,
+    yes: bool,
+    json: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut client = connect_remote(&target).await?;
+    if let Some(database) = &database {
+        client.use_database(database).await?;
+    }
+    let command = removal.command();
+    let report = client.dry_run(&command).await?;
+
+    if dry_run {
+        if json {
+            print_json(
+                &serde_json::json!({ "command": command, "dry_run": true, "report": report }),
+            )?;
+            return Ok(());
+        }
+        println!("{} Dry run of: {}", "[OPS]".blue(), command);
+        const SHOWN: usize = 20;
+        for name in report.names.iter().take(SHOWN) {
+            println!("  {}", name);
+        }
+        if report.names.len() > SHOWN || report.truncated {
+            println!("  ...");
+        }
+        println!(
+            "{} Would remove {} keys, {} bytes; nothing was changed",
+            "[OPS]".green(),
+            report.keys,
+            report.bytes
+        );
+        return Ok(());
+    }
+
+    if !yes {
+        let confirmed = Confirm::with_theme(&ColorfulTheme::default())
+            .with_prompt(format!(
+                "{} removes {} keys ({} bytes). Continue?",
+                command, report.keys, report.bytes
+            ))
+            .default(false)
+            .interact()?;
+        if !confirmed {
+            println!("{} Nothing was changed", "[OPS]".yellow());
+            return Ok(());
+        }
+    }
+
+    let summary = match &removal {
+        Removal::DropDatabase(name) => {
+            client.drop_database(name).await?;
+            format!("Database '{}' dropped", name)
+        }
+        Removal::PurgeTrash => client.purge_trash().await?,
+        Removal::DeletePrefix(_) => {
+            let result = client.query(&command).await?;
+            format!("Deleted {} keys", result.rows_affected)
+        }
+    };
+    if json {
+        print_json(
+            &serde_json::json!({ "command": command, "dry_run": false, "result": summary }),
+        )?;
+    } else {
+        println!("{} {}", "[OPS]".green(), summary);
+    }
+    Ok(())
+}
+
+// Polls the server's maintenance feed once a second until interrupted. With
+// `--json`, each event is printed as one line of JSON.
+async fn follow_maintenance(
+    target: RemoteTarget,
+    json: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut client = connect_remote(&target).await?;
+    if !json {
+        println!(
+            "{} Following flushes and compactions on {} (Ctrl-C to stop)",
+            "[MONITOR]".cyan().bold(),
+            target.address
+        );
+    }
+
+    let mut last_id = 0;
+    loop {
+        let feed = client.maintenance(last_id).await?;
+        let events = feed["events"].as_array().cloned().unwrap_or_default();
+        for event in events {
+            last_id = last_id.max(event["id"].as_u64().unwrap_or(0));
+            if json {
+                println!("{}", serde_json::to_string(&event)?);
+                continue;
+            }
+            let done = event["bytes_done"].as_u64().unwrap_or(0);
+            let total = event["bytes_total"].as_u64().unwrap_or(0);
+            let percent = if total == 0 {
+                100.0
+            } else {
+                done as f64 / total as f64 * 100.0
+            };
+            let eta = match event["eta_ms"].as_u64() {
+                Some(ms) => format!(", ETA {:.1}s", ms as f64 / 1000.0),
+                None => String::new(),
+            };
+            println!(
+                "{} {} {} {} {:.1}% ({}/{} bytes, {} tables left, {:.1}s elapsed{})",
+                event["timestamp"].as_str().unwrap_or_default(),
+                event["database"].as_str().unwrap_or_default(),
+                event["operation"].as_str().unwrap_or_default(),
+                event["state"].as_str().unwrap_or_default(),
+                percent,
+                done,
+                total,
+                event["tables_remaining"].as_u64().unwrap_or(0),
+                event["elapsed_ms"].as_u64().unwrap_or(0) as f64 / 1000.0,
+                eta
+            );
+        }
+        tokio::select! {
+            _ = tokio::signal::ctrl_c() => return Ok(()),
+            _ = tokio::time::sleep(std::time::Duration::from_secs(1)) => {}
+        }
+    }
+}
+
+fn print_checks(
+    checks: &[doctor::DoctorCheck],
+    output: OutputFormat,
+) -> Result<doctor::CheckStatus, Box<dyn std::error::Error>> {
+    match output {
+        OutputFormat::Human => Ok(doctor::print_report(checks)),
+        OutputFormat::Json => {
+            let status = doctor::worst_status(checks);
+            print_json(&serde_json::json!({ "status": status, "checks": checks }))?;
+            Ok(status)
+        }
+    }
+}
+
+fn print_capacity_report(report: &CapacityReport) {
+    let mb = |bytes: f64| format!("{:.1} MB", bytes / (1024.0 * 1024.0));
+    println!("\n{} Capacity of {}", "[CAPACITY]".cyan().bold(), report.data_dir.display());
+    for db in &report.databases {
+        println!("{}", db.path.display().to_string().bold());
+        println!("  Size:            {}", mb(db.disk_bytes as f64));
+        match db.growth_bytes_per_day {
+            Some(growth) => println!(
+                "  Growth:          {}/day over {} days of history",
+                mb(growth),
+                db.history_days
+            ),
+            None => println!("  Growth:          not enough history yet"),
+        }
+        if let Some(written) = db.write_bytes_per_day {
+            println!("  Writes:          {}/day", mb(written));
+        }
+        println!(
+            "  SSTables:        {} ({} waiting for compaction)",
+            db.sstables, db.compaction_debt
+        );
+        match db.cache_hit_rate {
+            Some(rate) => println!(
+                "  Cache hit rate:  {:.1}% ({:?})",
+                rate * 100.0,
+                db.cache_hit_trend
+            ),
+            None => println!("  Cache hit rate:  no samples"),
+        }
+    }
+
+    println!("\n{}", "Projection".bold());
+    println!("  Data:            {}", mb(report.disk_bytes as f64));
+    if let (Some(available), Some(total)) = (report.disk_available_bytes, report.disk_total_bytes) {
+        println!(
+            "  Disk free:       {} of {}",
+            mb(available as f64),
+            mb(total as f64)
+        );
+    }
+    match (report.growth_bytes_per_day, report.days_until_full) {
+        (Some(growth), Some(days)) => println!(
+            "  Disk full in:    {:.0} days at {}/day",
+            days,
+            mb(growth)
+        ),
+        (Some(_), None) => println!("  Disk full in:    never at the current growth"),
+        _ => println!("  Disk full in:    unknown until growth is recorded"),
+    }
+    println!(
+        "  Cache size:      {} entries, recommended {}",
+        report.cache_size, report.recommended_cache_size
+    );
+    println!("  {} {}", "->".dimmed(), report.cache_advice);
+}
+
+fn print_sst_inspection(inspection: &velocity::inspect::SstInspection) {
+    // The index has an entry every 16 records; the rest is in --output json.
+    const INDEX_SHOWN: usize = 20;
+    let header = &inspection.header;
+    let properties = &inspection.properties;
+    let bloom = &inspection.bloom;
+
+    println!("\n{} {}", "[SSTABLE]".cyan().bold(), header.path.display());
+    match header.id {
+        Some(id) => println!("  Id:              {}", id),
+        None => println!("  Id:              unknown (not named sstable_NNNNNN.vdb)"),
+    }
+    println!("  Size:            {} bytes", header.size_bytes);
+    match header.recorded_checksum {
+        Some(recorded) if recorded != header.checksum => println!(
+            "  Checksum:        {:08x} (manifest recorded {:08x})",
+            header.checksum, recorded
+        ),
+        Some(_) => println!("  Checksum:        {:08x} (matches the manifest)", header.checksum),
+        None => println!("  Checksum:        {:08x}", header.checksum),
+    }
+    if header.compressed {
+        match header.dictionary_id {
+            Some(id) => println!(
+                "  Compression:     zstd, dictionary {} ({} bytes)",
+                id, header.dictionary_bytes
+            ),
+            None => println!("  Compression:     zstd, no dictionary"),
+        }
+    } else {
+        println!("  Compression:     none");
+    }
+
+    println!("\n{}", "Properties".bold());
+    println!(
+        "  Entries:         {} ({} tombstones)",
+        properties.entries, properties.tombstones
+    );
+    println!(
+        "  Keys:            {} .. {}",
+        properties.min_key.as_deref().unwrap_or("-"),
+        properties.max_key.as_deref().unwrap_or("-")
+    );
+    println!(
+        "  Value bytes:     {} raw, {} stored ({:.2}x)",
+        properties.raw_value_bytes, properties.stored_value_bytes, properties.compression_ratio
+    );
+
+    println!("\n{}", "Bloom filter".bold());
+    println!(
+        "  Bits:            {} of {} set, {} hash functions",
+        bloom.bits_set, bloom.bit_count, bloom.hash_functions
+    );
+    println!(
+        "  Est. false pos.: {:.4}%",
+        bloom.estimated_false_positive_rate * 100.0
+    );
+
+    println!("\n{} ({} entries)", "Index".bold(), inspection.index.len());
+    for entry in inspection.index.iter().take(INDEX_SHOWN) {
+        println!("  {:>10}  {}", entry.offset, entry.key);
+    }
+    if inspection.index.len() > INDEX_SHOWN {
+        println!(
+            "  {} {} more; use --output json for all",
+            "...".dimmed(),
+            inspection.index.len() - INDEX_SHOWN
+        );
+    }
+
+    if let Some(records) = &inspection.records {
+        println!("\n{} ({} shown)", "Records".bold(), records.len());
+        for record in records {
+            let value = if record.tombstone {
+                "<tombstone>".dimmed().to_string()
+            } else {
+                let prefix = if record.value_encoding == "hex" { "0x" } else { "" };
+                let more = if record.truncated { "..." } else { "" };
+                format!("{}{}{} ({} bytes)", prefix, record.value, more, record.value_len)
+            };
+            println!("  {:>10}  {} = {}", record.offset, record.key, value);
+            if let Some(error) = &record.error {
+                println!("  {:>10}  {}", "", error.red());
+            }
+        }
+    }
+
+    match &inspection.problem {
+        Some(problem) => println!("\n{} {}", "[DAMAGED]".red().bold(), problem),
+        None => println!("\n{} Every record is readable", "[OK]".green().bold()),
+    }
+}
+
 fn install_service_templates(
     dir: &Path,
     config: &Path,
     data_dir: &Path,
     bind: Option<&String>,
-) -> Result<(), Box<dyn std::error::Error>> {
+) -> Result<Vec<PathBuf>, Box<dyn std::error::Error>> {
     std::fs::create_dir_all(dir)?;
     let exe = std::env::current_exe()?;
     let exe_str = exe.display().to_string();
     let unit = generate_systemd_unit(&exe_str, config, data_dir, bind);
     let script = generate_windows_script(&exe_str, config, data_dir, bind);
-    std::fs::write(dir.join("velocity.service"), unit)?;
-    std::fs::write(dir.join("install-velocity.ps1"), script)?;
-    println!(
-        "{} Service templates written to {:?}",
-        "[SUCCESS]".green(),
-        dir
-    );
-    Ok(())
+    let files = vec![dir.join("velocity.service"), dir.join("install-velocity.ps1")];
+    atomic_write(&files[0], unit)?;
+    atomic_write(&files[1], script)?;
+    Ok(files)
 }
 
-fn uninstall_service_templates(dir: &Path) -> Result<(), Box<dyn std::error::Error>> {
-    if dir.exists() {
-        std::fs::remove_dir_all(dir)?;
-        println!("{} Removed templates under {:?}", "[WARN]".yellow(), dir);
-    } else {
-        println!("{} No templates found at {:?}", "[WARN]".yellow(), dir);
+/// Returns whether there were templates to remove.
+fn uninstall_service_templates(dir: &Path) -> Result<bool, Box<dyn std::error::Error>> {
+    if !dir.exists() {
+        return Ok(false);
     }
-    Ok(())
+    std::fs::remove_dir_all(dir)?;
+    Ok(true)
 }
 
 fn generate_systemd_unit(
@@ -1117,7 +2143,8 @@ After=network.target\n\
 \n\
 [Service]\n\
 Type=simple\n\
-ExecStart={} ops service run --config {} --data-dir {}{} --verbose\n\
+ExecStart={} ops service run --config {} --data-dir {}{}\n\
+ExecReload=/bin/kill -HUP $MAINPID\n\
 Restart=on-failure\n\
 \n\
 [Install]\n\
@@ -1140,7 +2167,7 @@ fn generate_windows_script(
         .unwrap_or_default();
 
     format!(
-        "param(\n    [string]$ServiceName = \"VelocityDB\",\n    [string]$DisplayName = \"VelocityDB Service\"\n)\n\n$binPath = \"{} ops service run --config {} --data-dir {}{} --verbose\"\n\nsc.exe create $ServiceName binPath= \"$binPath\" DisplayName= \"$DisplayName\" start= auto\nsc.exe description $ServiceName \"VelocityDB background service\"\n",
+        "param(\n    [string]$ServiceName = \"VelocityDB\",\n    [string]$DisplayName = \"VelocityDB Service\"\n)\n\n$binPath = \"{} ops service run --config {} --data-dir {}{}\"\n\nsc.exe create $ServiceName binPath= \"$binPath\" DisplayName= \"$DisplayName\" start= auto\nsc.exe description $ServiceName \"VelocityDB background service\"\n",
         exe_path,
         config.display(),
         data_dir.display(),
@@ -1148,7 +2175,60 @@ fn generate_windows_script(
     )
 }
 
-async fn handle_init(path: &Path) -> Result<(), Box<dyn std::error::Error>> {
+fn handle_init_non_interactive(
+    path: &Path,
+    options: &InitOptions,
+) -> Result<(), Box<dyn std::error::Error>> {
+    if path.exists() && !options.force {
+        return Err(format!("{:?} already exists; pass --force to overwrite it", path).into());
+    }
+
+    let bind_address = options
+        .bind
+        .clone()
+        .unwrap_or_else(|| "127.0.0.1:2005".to_string());
+    bind_address
+        .parse::<std::net::SocketAddr>()
+        .map_err(|e| format!("Invalid --bind address '{}': {}", bind_address, e))?;
+
+    let admin_pass = std::env::var(&options.admin_password_env).map_err(|_| {
+        format!(
+            "Environment variable {} must hold the admin password",
+            options.admin_password_env
+        )
+    })?;
+
+    let mut config = ConfigFile::default();
+    config.server.bind_address = bind_address;
+    config.security.password_policy.validate(&admin_pass)?;
+
+    if options.multi_tenant {
+        config.addons.database = Some(DatabaseAddonConfig::default());
+    }
+    if let Some(interval) = options.backup_interval_minutes {
+        config.addons.database = Some(config.addons.database.unwrap_or_default());
+        config.addons.backup = Some(BackupAddonConfig {
+            enabled: true,
+            interval_minutes: interval,
+            ..Default::default()
+        });
+    }
+
+    config
+        .users
+        .insert(options.admin_user.clone(), hash_password(&admin_pass)?);
+
+    atomic_write(path, toml::to_string_pretty(&config)?)?;
+    println!(
+        "{} Configuration saved to {:?} (admin user '{}')",
+        "[SUCCESS]".green(),
+        path,
+        options.admin_user
+    );
+    Ok(())
+}
+
+async fn handle_init(path: &Path, options: &InitOptions) -> Result<(), Box<dyn std::error::Error>> {
     println!(
         "{} {} VelocityDB Interactive Setup\n",
         "[INIT]".blue(),
@@ -1169,7 +2249,12 @@ async fn handle_init(path: &Path) -> Result<(), Box<dyn std::error::Error>> {
 
     let bind_address: String = Input::with_theme(&ColorfulTheme::default())
         .with_prompt("Bind address (e.g. 127.0.0.1:2005)")
-        .default("127.0.0.1:2005".into())
+        .default(
+            options
+                .bind
+                .clone()
+                .unwrap_or_else(|| "127.0.0.1:2005".into()),
+        )
         .interact_text()?;
 
     let mode = Select::with_theme(&ColorfulTheme::default())
@@ -1193,8 +2278,10 @@ async fn handle_init(path: &Path) -> Result<(), Box<dyn std::error::Error>> {
         .interact()?;
 
     if enable_backup {
-        let mut backup_cfg = BackupAddonConfig::default();
-        backup_cfg.enabled = true;
+        let mut backup_cfg = BackupAddonConfig {
+            enabled: true,
+            ..Default::default()
+        };
 
         let backup_mode = Select::with_theme(&ColorfulTheme::default())
             .with_prompt("Backup Scope")
@@ -1233,10 +2320,10 @@ async fn handle_init(path: &Path) -> Result<(), Box<dyn std::error::Error>> {
 
     config
         .users
-        .insert("admin".to_string(), hash_password(&admin_pass)?);
+        .insert(options.admin_user.clone(), hash_password(&admin_pass)?);
 
     let toml_string = toml::to_string_pretty(&config)?;
-    std::fs::write(path, toml_string)?;
+    atomic_write(path, toml_string)?;
 
     println!(
         "\n{} Configuration saved to {:?}",
@@ -1251,24 +2338,118 @@ async fn handle_init(path: &Path) -> Result<(), Box<dyn std::error::Error>> {
     Ok(())
 }
 
+// Runs the same fsynced-write and cold-read workload on each I/O backend.
+fn run_io_benchmark(
+    data_dir: &Path,
+    operations: usize,
+    config: VelocityConfig,
+    human: bool,
+) -> Result<serde_json::Value, Box<dyn std::error::Error>> {
+    use velocity::io_backend::{io_uring_support, IoBackend};
+    use velocity::write::WriteOptions;
+
+    let io_uring_error = io_uring_support().err();
+    if let (Some(reason), true) = (&io_uring_error, human) {
+        println!(
+            "{} io_uring unavailable ({}); only the standard backend runs",
+            "[WARN]".yellow(),
+            reason
+        );
+    }
+    // Every synced write waits for an fsync, so they get a tenth of the
+    // operations to keep the run short on slow disks.
+    let synced = (operations / 10).max(1);
+    let sync = WriteOptions {
+        sync: true,
+        ..Default::default()
+    };
+
+    let mut results = Vec::new();
+    for backend in [IoBackend::Standard, IoBackend::IoUring] {
+        if backend == IoBackend::IoUring && io_uring_error.is_some() {
+            continue;
+        }
+        let dir = data_dir.join(backend.name());
+        let config = VelocityConfig {
+            io_backend: backend,
+            ..config.clone()
+        };
+        if human {
+            println!("{} {} backend...", "[RUN]".blue(), backend.name());
+        }
+
+        let db = Velocity::open_with_config(&dir, config.clone())?;
+        let start = std::time::Instant::now();
+        for i in 0..synced {
+            db.put_with_options(format!("key_{:06}", i), vec![0u8; 128], &sync)?;
+        }
+        let write = start.elapsed();
+        for i in synced..operations {
+            db.put(format!("key_{:06}", i), vec![0u8; 128])?;
+        }
+        db.flush()?;
+        db.close()?;
+        drop(db);
+
+        let db = Velocity::open_with_config(&dir, config)?;
+        let start = std::time::Instant::now();
+        for i in 0..operations {
+            db.get(&format!("key_{:06}", i))?;
+        }
+        let read = start.elapsed();
+        db.close()?;
+        results.push((
+            backend,
+            write.as_micros() as f64 / synced as f64,
+            read.as_micros() as f64 / operations as f64,
+        ));
+    }
+
+    if human {
+        println!("\n{}", "I/O Backend Results:".bold().green());
+        println!(
+            "  {:<10} {:>18} {:>18}",
+            "Backend", "Synced write μs/op", "Cold read μs/op"
+        );
+        for (backend, write, read) in &results {
+            println!("  {:<10} {:>18.2} {:>18.2}", backend.name(), write, read);
+        }
+    }
+    Ok(serde_json::json!({
+        "io_uring_error": io_uring_error,
+        "backends": results
+            .iter()
+            .map(|(backend, write, read)| serde_json::json!({
+                "backend": backend.name(),
+                "synced_write_us_per_op": write,
+                "cold_read_us_per_op": read,
+            }))
+            .collect::<Vec<_>>(),
+    }))
+}
+
 async fn run_benchmark(
     data_dir: &PathBuf,
     operations: usize,
     mode: String,
     cache_size: Option<usize>,
+    output: OutputFormat,
 ) -> Result<(), Box<dyn std::error::Error>> {
-    println!(
-        "{} {}",
-        "[BENCH]".yellow(),
-        "VelocityDB Performance Benchmark".bold()
-    );
-    println!("{}", "=================================".dimmed());
-    println!("{} Mode: {}", "[INFO]".blue(), mode.to_uppercase().green());
+    let human = output == OutputFormat::Human;
+    if human {
+        println!(
+            "{} {}",
+            "[BENCH]".yellow(),
+            "VelocityDB Performance Benchmark".bold()
+        );
+        println!("{}", "=================================".dimmed());
+        println!("{} Mode: {}", "[INFO]".blue(), mode.to_uppercase().green());
+    }
 
     let has_cache_limit = cache_size.is_some();
     let cache_limit = cache_size.unwrap_or(100_000);
 
-    let mut config = VelocityConfig {
+    let config = VelocityConfig {
         max_memtable_size: 200_000,
         cache_size: cache_limit,
         bloom_false_positive_rate: 0.001,
@@ -1277,21 +2458,41 @@ async fn run_benchmark(
         memory_only_mode: false,
         batch_wal_writes: true,
         adaptive_cache: false,
+        cache_policy: Default::default(),
+        hot_keys: Default::default(),
         enable_metrics: false,
         metrics_interval: Default::default(),
         target_cache_hit_rate: 0.0,
         wal_sync_mode: velocity::WalSyncMode::Batch,
+        wal_shards: 1,
+        memtable_huge_pages: false,
+        io_backend: Default::default(),
+        record_metadata: false,
+        data_layout: Default::default(),
+        recovery_progress: None,
+        fault_injection: None,
+        background_threads: true,
+        background_pool: Default::default(),
+        compaction_io_budget: 0,
+        compaction_picker: None,
+        key_policies: Vec::new(),
+        cache_warmup: Default::default(),
+        hash_seed: Some(42),
+        wal_recovery: Default::default(),
+        read_only: false,
     };
 
-    println!(
-        "{} Configuration: Cache={}, Sync=Batch",
-        "[INFO]".blue(),
-        if has_cache_limit {
-            format!("{} (Constrained)", cache_limit).yellow()
-        } else {
-            "Unlimited".into()
-        }
-    );
+    if human {
+        println!(
+            "{} Configuration: Cache={}, Sync=Batch",
+            "[INFO]".blue(),
+            if has_cache_limit {
+                format!("{} (Constrained)", cache_limit).yellow()
+            } else {
+                "Unlimited".into()
+            }
+        );
+    }
 
 
     if data_dir.exists() {
@@ -1299,12 +2500,16 @@ async fn run_benchmark(
     }
     std::fs::create_dir_all(data_dir)?;
 
-    if mode == "mixed" {
+    let results = if mode == "io" {
+        run_io_benchmark(data_dir, operations, config, human)?
+    } else if mode == "mixed" {
         let db = Velocity::open_with_config(data_dir, config)?;
-        println!(
-            "{} Running mixed R/W benchmark (70% Read / 30% Write)...",
-            "[MIX]".blue()
-        );
+        if human {
+            println!(
+                "{} Running mixed R/W benchmark (70% Read / 30% Write)...",
+                "[MIX]".blue()
+            );
+        }
 
         let mut rng = <rand::rngs::StdRng as rand::SeedableRng>::seed_from_u64(42);
         use rand::Rng;
@@ -1348,110 +2553,161 @@ async fn run_benchmark(
             .get(write_latencies.len() * 99 / 100)
             .unwrap_or(&std::time::Duration::ZERO);
 
-        println!("\n{}", "Mixed Workload Results:".bold().green());
-        println!(
-            "  Throughput: {} ops/sec",
-            format!("{:.0}", ops_sec).bold().yellow()
-        );
-        println!("  Read Latency (p50): {:?}", p50_r);
-        println!("  Read Latency (p99): {:?}", p99_r);
-        println!("  Write Latency (p50): {:?}", p50_w);
-        println!("  Write Latency (p99): {:?}", p99_w);
+        if human {
+            println!("\n{}", "Mixed Workload Results:".bold().green());
+            println!(
+                "  Throughput: {} ops/sec",
+                format!("{:.0}", ops_sec).bold().yellow()
+            );
+            println!("  Read Latency (p50): {:?}", p50_r);
+            println!("  Read Latency (p99): {:?}", p99_r);
+            println!("  Write Latency (p50): {:?}", p50_w);
+            println!("  Write Latency (p99): {:?}", p99_w);
+        }
 
         db.close()?;
+        serde_json::json!({
+            "ops_per_sec": ops_sec,
+            "read_p50_us": p50_r.as_micros() as u64,
+            "read_p99_us": p99_r.as_micros() as u64,
+            "write_p50_us": p50_w.as_micros() as u64,
+            "write_p99_us": p99_w.as_micros() as u64,
+        })
     } else if mode == "persistent" {
         let db = Velocity::open_with_config(data_dir, config.clone())?;
 
-        println!("{} Writing {} records...", "[WRITE]".blue(), operations);
+        if human {
+            println!("{} Writing {} records...", "[WRITE]".blue(), operations);
+        }
         for i in 0..operations {
             let key = format!("key_{:06}", i);
             let value = vec![0u8; 128];
             db.put(key, value)?;
         }
 
-        println!("{} Flushing to SSTables (Disk)...", "[FLUSH]".yellow());
+        if human {
+            println!("{} Flushing to SSTables (Disk)...", "[FLUSH]".yellow());
+        }
         let flush_start = std::time::Instant::now();
         db.flush()?;
-        println!("  Flush took: {:?}", flush_start.elapsed());
+        let flush = flush_start.elapsed();
+        if human {
+            println!("  Flush took: {:?}", flush);
+        }
 
 
         db.close()?;
         drop(db);
-        println!(
-            "{} Re-opening database (Cold Start)...",
-            "[RELOAD]".yellow()
-        );
+        if human {
+            println!(
+                "{} Re-opening database (Cold Start)...",
+                "[RELOAD]".yellow()
+            );
+        }
 
         let db = Velocity::open_with_config(data_dir, config)?;
 
-        println!(
-            "{} Reading {} records from SSTables...",
-            "[READ]".blue(),
-            operations
-        );
+        if human {
+            println!(
+                "{} Reading {} records from SSTables...",
+                "[READ]".blue(),
+                operations
+            );
+        }
         let start = std::time::Instant::now();
         for i in 0..operations {
             let key = format!("key_{:06}", i);
             db.get(&key)?;
         }
         let duration = start.elapsed();
+        let stats = db.stats();
 
-        println!("\n{}", "Cold Read Results (SSTable):".bold().green());
-        println!("  Duration:   {:?}", duration);
-        println!(
-            "  Throughput: {} ops/sec",
-            format!("{:.0}", operations as f64 / duration.as_secs_f64())
-                .bold()
-                .yellow()
-        );
-        println!(
-            "  Latency:    {:.2} μs/op",
-            duration.as_micros() as f64 / operations as f64
-        );
+        if human {
+            println!("\n{}", "Cold Read Results (SSTable):".bold().green());
+            println!("  Duration:   {:?}", duration);
+            println!(
+                "  Throughput: {} ops/sec",
+                format!("{:.0}", operations as f64 / duration.as_secs_f64())
+                    .bold()
+                    .yellow()
+            );
+            println!(
+                "  Latency:    {:.2} μs/op",
+                duration.as_micros() as f64 / operations as f64
+            );
 
 
-        let stats = db.stats();
-        println!("\n{}", "Storage Stats:".bold().cyan());
-        println!("  SSTables: {} files", stats.sstable_count);
-        println!(
-            "  Total Size: {:.2} MB",
-            stats.total_sstable_size as f64 / 1024.0 / 1024.0
-        );
+            println!("\n{}", "Storage Stats:".bold().cyan());
+            println!("  SSTables: {} files", stats.sstable_count);
+            println!(
+                "  Total Size: {:.2} MB",
+                stats.total_sstable_size as f64 / 1024.0 / 1024.0
+            );
+        }
+        serde_json::json!({
+            "flush_ms": flush.as_millis() as u64,
+            "cold_read_ms": duration.as_millis() as u64,
+            "cold_read_ops_per_sec": operations as f64 / duration.as_secs_f64(),
+            "cold_read_us_per_op": duration.as_micros() as f64 / operations as f64,
+            "sstable_count": stats.sstable_count,
+            "total_sstable_size": stats.total_sstable_size,
+        })
     } else {
 
         let db = Velocity::open_with_config(data_dir, config)?;
 
-        println!("{} Running write benchmark...", "[WRITE]".blue());
+        if human {
+            println!("{} Running write benchmark...", "[WRITE]".blue());
+        }
         let start = std::time::Instant::now();
         for i in 0..operations {
             db.put(format!("k_{}", i), vec![0u8; 64])?;
         }
-        let dur = start.elapsed();
-        println!(
-            "  Throughput: {:.0} ops/sec",
-            operations as f64 / dur.as_secs_f64()
-        );
+        let write = start.elapsed();
+        if human {
+            println!(
+                "  Throughput: {:.0} ops/sec",
+                operations as f64 / write.as_secs_f64()
+            );
 
-        println!("{} Waiting for WAL sync...", "[WAIT]".yellow());
+            println!("{} Waiting for WAL sync...", "[WAIT]".yellow());
+        }
         std::thread::sleep(std::time::Duration::from_millis(500));
 
-        println!("{} Running read benchmark...", "[READ]".blue());
+        if human {
+            println!("{} Running read benchmark...", "[READ]".blue());
+        }
         let start = std::time::Instant::now();
         for i in 0..operations {
             db.get(&format!("k_{}", i))?;
         }
-        let dur = start.elapsed();
-        println!(
-            "  Throughput: {:.0} ops/sec",
-            operations as f64 / dur.as_secs_f64()
-        );
-        println!(
-            "  Latency:    {:.2} μs/op",
-            dur.as_micros() as f64 / operations as f64
-        );
+        let read = start.elapsed();
+        if human {
+            println!(
+                "  Throughput: {:.0} ops/sec",
+                operations as f64 / read.as_secs_f64()
+            );
+            println!(
+                "  Latency:    {:.2} μs/op",
+                read.as_micros() as f64 / operations as f64
+            );
+        }
         db.close()?;
-    }
+        serde_json::json!({
+            "write_ops_per_sec": operations as f64 / write.as_secs_f64(),
+            "read_ops_per_sec": operations as f64 / read.as_secs_f64(),
+            "read_us_per_op": read.as_micros() as f64 / operations as f64,
+        })
+    };
 
+    if !human {
+        print_json(&serde_json::json!({
+            "mode": mode,
+            "operations": operations,
+            "cache_size": cache_size,
+            "results": results,
+        }))?;
+    }
     Ok(())
 }
 
@@ -0,0 +1,362 @@
+use std::alloc::{self, Layout};
+use std::borrow::Borrow;
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
+use std::ops::{AddAssign, Bound};
+use std::ptr::NonNull;
+
+use serde::Serialize;
+
+// Matches the x86-64 and aarch64 transparent huge page size.
+const CHUNK_SIZE: usize = 2 * 1024 * 1024;
+// Records larger than this get a chunk of their own instead of wasting the
+// tail of a shared one.
+const LARGE_RECORD: usize = CHUNK_SIZE / 4;
+
+/// Allocator counters for the memtable arena.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct ArenaStats {
+    /// Memtables flushed and freed since the database was opened.
+    pub generation: u64,
+    pub chunks: usize,
+    /// Memory held by the arena's chunks.
+    pub reserved_bytes: u64,
+    /// Bytes carved out of the chunks so far.
+    pub used_bytes: u64,
+    /// Bytes of the keys and values currently in the memtable.
+    pub live_bytes: u64,
+    /// Slots left behind by overwritten values, kept for later writes.
+    pub free_bytes: u64,
+    pub allocations: u64,
+}
+
+impl AddAssign for ArenaStats {
+    fn add_assign(&mut self, other: Self) {
+        self.generation += other.generation;
+        self.chunks += other.chunks;
+        self.reserved_bytes += other.reserved_bytes;
+        self.used_bytes += other.used_bytes;
+        self.live_bytes += other.live_bytes;
+        self.free_bytes += other.free_bytes;
+        self.allocations += other.allocations;
+    }
+}
+
+struct Chunk {
+    ptr: NonNull<u8>,
+    layout: Layout,
+}
+
+impl Chunk {
+    fn new(size: usize, huge_pages: bool) -> Self {
+        let align = if huge_pages { CHUNK_SIZE } else { 64 };
+        let layout = Layout::from_size_align(size, align).expect("arena chunk layout");
+        // SAFETY: `size` is never zero; callers only allocate for non-empty
+        // records.
+        let ptr = NonNull::new(unsafe { alloc::alloc(layout) })
+            .unwrap_or_else(|| alloc::handle_alloc_error(layout));
+        #[cfg(target_os = "linux")]
+        if huge_pages {
+            // SAFETY: advice on memory this chunk owns; failure is harmless.
+            unsafe {
+                libc::madvise(ptr.as_ptr().cast(), size, libc::MADV_HUGEPAGE);
+            }
+        }
+        Self { ptr, layout }
+    }
+}
+
+impl Drop for Chunk {
+    fn drop(&mut self) {
+        // SAFETY: allocated in `new` with this layout.
+        unsafe { alloc::dealloc(self.ptr.as_ptr(), self.layout) }
+    }
+}
+
+/// Bump allocator for one memtable generation. Chunks are only freed, all
+/// at once, when the memtable is flushed; slots of overwritten values are
+/// handed out again before new space is carved.
+struct Arena {
+    chunks: Vec<Chunk>,
+    // Free space at the end of the last shared chunk.
+    bump: Option<(NonNull<u8>, usize)>,
+    // Released slots by capacity.
+    free: BTreeMap<usize, Vec<NonNull<u8>>>,
+    huge_pages: bool,
+    stats: ArenaStats,
+}
+
+impl Arena {
+    fn new(huge_pages: bool, generation: u64) -> Self {
+        Self {
+            chunks: Vec::new(),
+            bump: None,
+            free: BTreeMap::new(),
+            huge_pages,
+            stats: ArenaStats {
+                generation,
+                ..Default::default()
+            },
+        }
+    }
+
+    // Copies `bytes` into a released slot of at most twice their length, or
+    // into new space. Returns the slice and the capacity of its slot.
+    fn copy(&mut self, bytes: &[u8]) -> (Slice, usize) {
+        let len = bytes.len();
+        if len == 0 {
+            return (
+                Slice {
+                    ptr: NonNull::dangling(),
+                    len,
+                },
+                0,
+            );
+        }
+        let (ptr, capacity) = match self.take_free(len) {
+            Some(slot) => slot,
+            None => (self.carve(len), len),
+        };
+        // SAFETY: `ptr` has at least `len` unused bytes that nothing else
+        // points to.
+        unsafe { std::ptr::copy_nonoverlapping(bytes.as_ptr(), ptr.as_ptr(), len) };
+        self.stats.allocations += 1;
+        (Slice { ptr, len }, capacity)
+    }
+
+    fn take_free(&mut self, len: usize) -> Option<(NonNull<u8>, usize)> {
+        let mut slots = self.free.range_mut(len..=len.saturating_mul(2));
+        let (&capacity, ptrs) = slots.next()?;
+        let ptr = ptrs.pop()?;
+        if ptrs.is_empty() {
+            self.free.remove(&capacity);
+        }
+        self.stats.free_bytes -= capacity as u64;
+        Some((ptr, capacity))
+    }
+
+    fn carve(&mut self, len: usize) -> NonNull<u8> {
+        self.stats.used_bytes += len as u64;
+        if len > LARGE_RECORD {
+            return self.add_chunk(len, false).ptr;
+        }
+        let (ptr, free) = match self.bump {
+            Some((ptr, free)) if free >= len => (ptr, free),
+            _ => (self.add_chunk(CHUNK_SIZE, self.huge_pages).ptr, CHUNK_SIZE),
+        };
+        // SAFETY: `len <= free`, so the new bump pointer stays inside (or one
+        // past) the chunk.
+        self.bump = Some((unsafe { ptr.add(len) }, free - len));
+        ptr
+    }
+
+    // Nothing may point into the slot once it is released.
+    fn release(&mut self, slot: Slice, capacity: usize) {
+        if capacity > 0 {
+            self.stats.free_bytes += capacity as u64;
+            self.free.entry(capacity).or_default().push(slot.ptr);
+        }
+    }
+
+    fn add_chunk(&mut self, size: usize, huge_pages: bool) -> &Chunk {
+        self.stats.chunks += 1;
+        self.stats.reserved_bytes += size as u64;
+        self.chunks.push(Chunk::new(size, huge_pages));
+        self.chunks.last().unwrap()
+    }
+}
+
+// Bytes inside an arena chunk. Valid until the arena is dropped.
+#[derive(Clone, Copy)]
+struct Slice {
+    ptr: NonNull<u8>,
+    len: usize,
+}
+
+impl Slice {
+    fn bytes(&self) -> &[u8] {
+        // SAFETY: the slice was copied into a chunk that outlives every
+        // `Slice` the memtable hands out borrows of.
+        unsafe { std::slice::from_raw_parts(self.ptr.as_ptr(), self.len) }
+    }
+}
+
+// A key in the arena; ordered like the `str` it holds.
+#[derive(Clone, Copy)]
+struct Key(Slice);
+
+impl Key {
+    fn as_str(&self) -> &str {
+        // SAFETY: only ever built from a `&str`.
+        unsafe { std::str::from_utf8_unchecked(self.0.bytes()) }
+    }
+}
+
+impl Borrow<str> for Key {
+    fn borrow(&self) -> &str {
+        self.as_str()
+    }
+}
+
+impl PartialEq for Key {
+    fn eq(&self, other: &Self) -> bool {
+        self.as_str() == other.as_str()
+    }
+}
+
+impl Eq for Key {}
+
+impl PartialOrd for Key {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Key {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.as_str().cmp(other.as_str())
+    }
+}
+
+// A value in the arena, the size of the slot holding it, and whether it
+// starts with a record metadata header.
+#[derive(Clone, Copy)]
+struct Value {
+    slice: Slice,
+    capacity: usize,
+    has_meta: bool,
+}
+
+impl Value {
+    fn get(&self) -> (&[u8], bool) {
+        (self.slice.bytes(), self.has_meta)
+    }
+}
+
+/// Sorted in-memory table of the latest writes. Keys and values are copied
+/// into an arena that is freed wholesale by `clear`, so a busy memtable does
+/// not leave millions of small heap allocations behind when it is flushed.
+/// An overwrite reuses the old value's slot when the new value fits, so a hot
+/// key does not grow the arena.
+pub struct Memtable {
+    entries: BTreeMap<Key, Value>,
+    arena: Arena,
+}
+
+// SAFETY: the raw pointers only refer to chunks owned by `arena`, which are
+// written once and only replaced through `&mut self`.
+unsafe impl Send for Memtable {}
+unsafe impl Sync for Memtable {}
+
+impl Memtable {
+    /// With `huge_pages`, arena chunks are 2 MiB aligned and advised as
+    /// transparent huge pages on Linux.
+    pub fn new(huge_pages: bool) -> Self {
+        Self {
+            entries: BTreeMap::new(),
+            arena: Arena::new(huge_pages, 0),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// The value of `key` and whether it starts with a record metadata
+    /// header.
+    pub fn get(&self, key: &str) -> Option<(&[u8], bool)> {
+        self.entries.get(key).map(Value::get)
+    }
+
+    pub fn insert(&mut self, key: &str, value: &[u8], has_meta: bool) {
+        self.arena.stats.live_bytes += value.len() as u64;
+        if let Some(old) = self.entries.get_mut(key) {
+            self.arena.stats.live_bytes -= old.slice.len as u64;
+            old.has_meta = has_meta;
+            if value.len() <= old.capacity {
+                // SAFETY: the slot has `capacity` bytes and belongs to this
+                // entry alone; `&mut self` means no borrow of it is alive.
+                unsafe {
+                    std::ptr::copy_nonoverlapping(
+                        value.as_ptr(),
+                        old.slice.ptr.as_ptr(),
+                        value.len(),
+                    )
+                };
+                old.slice.len = value.len();
+                return;
+            }
+            let (released, capacity) = (old.slice, old.capacity);
+            let (slice, new_capacity) = self.arena.copy(value);
+            let old = self.entries.get_mut(key).unwrap();
+            old.slice = slice;
+            old.capacity = new_capacity;
+            self.arena.release(released, capacity);
+            return;
+        }
+        let (slice, capacity) = self.arena.copy(value);
+        let (key_slice, _) = self.arena.copy(key.as_bytes());
+        self.arena.stats.live_bytes += key.len() as u64;
+        self.entries.insert(
+            Key(key_slice),
+            Value {
+                slice,
+                capacity,
+                has_meta,
+            },
+        );
+    }
+
+    /// Drops every entry and frees the arena, starting a new generation.
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.arena = Arena::new(self.arena.huge_pages, self.arena.stats.generation + 1);
+    }
+
+    pub fn iter(&self) -> impl DoubleEndedIterator<Item = (&str, &[u8], bool)> + '_ {
+        self.entries.iter().map(|(key, value)| {
+            let (bytes, has_meta) = value.get();
+            (key.as_str(), bytes, has_meta)
+        })
+    }
+
+    pub fn keys(&self) -> impl Iterator<Item = &str> + '_ {
+        self.entries.keys().map(Key::as_str)
+    }
+
+    /// Entries with keys at or after `start`, in key order.
+    pub fn range_from<'a>(
+        &'a self,
+        start: &str,
+    ) -> impl Iterator<Item = (&'a str, &'a [u8], bool)> + 'a {
+        self.entries
+            .range::<str, _>((Bound::Included(start), Bound::Unbounded))
+            .map(|(key, value)| {
+                let (bytes, has_meta) = value.get();
+                (key.as_str(), bytes, has_meta)
+            })
+    }
+
+    pub fn arena_stats(&self) -> ArenaStats {
+        self.arena.stats
+    }
+}
+
+impl Clone for Memtable {
+    // Copies the live entries into a fresh arena, leaving overwritten values
+    // behind.
+    fn clone(&self) -> Self {
+        let mut copy = Self {
+            entries: BTreeMap::new(),
+            arena: Arena::new(self.arena.huge_pages, self.arena.stats.generation),
+        };
+        for (key, value, has_meta) in self.iter() {
+            copy.insert(key, value, has_meta);
+        }
+        copy
+    }
+}
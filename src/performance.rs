@@ -165,6 +165,12 @@ impl LatencyTracker {
     }
 }
 
+impl Default for LatencyTracker {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 #[derive(Debug, Clone, Default)]
 pub struct LatencyStats {
     pub count: u64,
@@ -241,8 +247,10 @@ impl AdaptiveCacheManager {
 }
 
 
+type PendingWrites = Vec<(String, Vec<u8>)>;
+
 pub struct BatchOptimizer {
-    pending_writes: Arc<RwLock<Vec<(String, Vec<u8>)>>>,
+    pending_writes: Arc<RwLock<PendingWrites>>,
     batch_size: usize,
     batch_timeout: Duration,
     last_flush: Arc<RwLock<Instant>>,
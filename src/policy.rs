@@ -0,0 +1,194 @@
+use serde::{Deserialize, Serialize};
+
+use crate::compression::DEFAULT_LEVEL;
+use crate::{now_millis, VeloError, VeloResult, VeloValue, Velocity};
+
+const EXPIRY_MAGIC: [u8; 4] = *b"\0VTL";
+const EXPIRY_HEADER_LEN: usize = EXPIRY_MAGIC.len() + 8;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Compression {
+    None,
+    Zstd,
+}
+
+/// Settings for keys that start with `prefix`; a trailing `*` is optional.
+/// When several prefixes match a key, the longest one applies.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PrefixPolicy {
+    pub prefix: String,
+    /// Values written under the prefix expire this many seconds later.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ttl_secs: Option<u64>,
+    /// Overrides `enable_compression` for the prefix.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub compression: Option<Compression>,
+    /// zstd level from 1 to 22; defaults to 3.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub compression_level: Option<i32>,
+}
+
+impl PrefixPolicy {
+    fn prefix(&self) -> &str {
+        self.prefix.strip_suffix('*').unwrap_or(&self.prefix)
+    }
+}
+
+pub fn validate_policies(policies: &[PrefixPolicy]) -> VeloResult<()> {
+    for (i, policy) in policies.iter().enumerate() {
+        if policies[..i].iter().any(|other| other.prefix() == policy.prefix()) {
+            return Err(VeloError::InvalidOperation(format!(
+                "Key prefix '{}' has more than one policy",
+                policy.prefix()
+            )));
+        }
+        if policy.ttl_secs == Some(0) {
+            return Err(VeloError::InvalidOperation(format!(
+                "ttl_secs for key prefix '{}' must be greater than 0",
+                policy.prefix()
+            )));
+        }
+        if let Some(level) = policy.compression_level {
+            if !(1..=22).contains(&level) {
+                return Err(VeloError::InvalidOperation(format!(
+                    "compression_level for key prefix '{}' must be between 1 and 22",
+                    policy.prefix()
+                )));
+            }
+            if policy.compression == Some(Compression::None) {
+                return Err(VeloError::InvalidOperation(format!(
+                    "Key prefix '{}' sets a compression_level but disables compression",
+                    policy.prefix()
+                )));
+            }
+        }
+    }
+    Ok(())
+}
+
+fn policy_for<'a>(policies: &'a [PrefixPolicy], key: &str) -> Option<&'a PrefixPolicy> {
+    policies
+        .iter()
+        .filter(|policy| key.starts_with(policy.prefix()))
+        .max_by_key(|policy| policy.prefix().len())
+}
+
+/// How compaction compresses each value of a table it writes.
+#[derive(Clone)]
+pub(crate) struct CompressionPlan {
+    policies: Vec<PrefixPolicy>,
+    enabled_by_default: bool,
+}
+
+impl CompressionPlan {
+    // The zstd level for `key`'s value, or `None` to store it as it is.
+    pub(crate) fn level(&self, key: &str) -> Option<i32> {
+        match policy_for(&self.policies, key) {
+            Some(PrefixPolicy {
+                compression: Some(Compression::None),
+                ..
+            }) => None,
+            Some(PrefixPolicy {
+                compression: Some(Compression::Zstd),
+                compression_level,
+                ..
+            }) => Some(compression_level.unwrap_or(DEFAULT_LEVEL)),
+            Some(PrefixPolicy {
+                compression_level: Some(level),
+                ..
+            }) => Some(*level),
+            _ => self.enabled_by_default.then_some(DEFAULT_LEVEL),
+        }
+    }
+
+    // Whether any key may be compressed.
+    pub(crate) fn any(&self) -> bool {
+        self.enabled_by_default
+            || self
+                .policies
+                .iter()
+                .any(|policy| policy.compression == Some(Compression::Zstd))
+    }
+}
+
+// Stored values under a prefix with a TTL start with their expiry time.
+fn with_expiry(value: &[u8], expires_at: u64) -> VeloValue {
+    let mut entry = Vec::with_capacity(EXPIRY_HEADER_LEN + value.len());
+    entry.extend_from_slice(&EXPIRY_MAGIC);
+    entry.extend_from_slice(&expires_at.to_le_bytes());
+    entry.extend_from_slice(value);
+    entry
+}
+
+fn expires_at(stored: &[u8]) -> Option<u64> {
+    if stored.len() < EXPIRY_HEADER_LEN || stored[..4] != EXPIRY_MAGIC {
+        return None;
+    }
+    Some(u64::from_le_bytes(stored[4..EXPIRY_HEADER_LEN].try_into().unwrap()))
+}
+
+fn is_expired(stored: &[u8], now: u64) -> bool {
+    expires_at(stored).is_some_and(|expires_at| expires_at <= now)
+}
+
+fn strip_expiry(mut stored: VeloValue, now: u64) -> Option<VeloValue> {
+    match expires_at(&stored) {
+        Some(expires_at) if expires_at <= now => None,
+        Some(_) => {
+            stored.drain(..EXPIRY_HEADER_LEN);
+            Some(stored)
+        }
+        None => Some(stored),
+    }
+}
+
+impl Velocity {
+    /// The policy that applies to `key`, if any.
+    pub fn prefix_policy(&self, key: &str) -> Option<&PrefixPolicy> {
+        policy_for(&self.config.key_policies, key)
+    }
+
+    pub(crate) fn ttl_secs(&self, key: &str) -> Option<u64> {
+        self.prefix_policy(key).and_then(|policy| policy.ttl_secs)
+    }
+
+    // Whether a TTL policy can apply to some key that starts with `prefix`.
+    pub(crate) fn ttl_under(&self, prefix: &str) -> bool {
+        self.config.key_policies.iter().any(|policy| {
+            policy.ttl_secs.is_some()
+                && (policy.prefix().starts_with(prefix) || prefix.starts_with(policy.prefix()))
+        })
+    }
+
+    // Adds the expiry of `key`'s TTL policy to a value about to be written.
+    pub(crate) fn apply_ttl(&self, key: &str, value: VeloValue) -> VeloValue {
+        match self.ttl_secs(key) {
+            Some(ttl) if !value.is_empty() => {
+                with_expiry(&value, now_millis().saturating_add(ttl.saturating_mul(1000)))
+            }
+            _ => value,
+        }
+    }
+
+    // Only keys under a TTL policy have an expiry header, so a value that
+    // merely starts with the header bytes is returned as it was written.
+    pub(crate) fn is_expired(&self, key: &str, stored: &[u8], now: u64) -> bool {
+        self.ttl_secs(key).is_some() && is_expired(stored, now)
+    }
+
+    // The value without its expiry header, or `None` once it has expired.
+    pub(crate) fn strip_expiry(&self, key: &str, stored: VeloValue, now: u64) -> Option<VeloValue> {
+        match self.ttl_secs(key) {
+            Some(_) => strip_expiry(stored, now),
+            None => Some(stored),
+        }
+    }
+
+    pub(crate) fn compression_plan(&self) -> CompressionPlan {
+        CompressionPlan {
+            policies: self.config.key_policies.clone(),
+            enabled_by_default: self.config.enable_compression,
+        }
+    }
+}
@@ -0,0 +1,96 @@
+use crate::config::ConfigFile;
+use crate::doctor::{check_config, DoctorCheck};
+use std::path::Path;
+use velocity::format::{
+    database_dirs, FormatInfo, FORMAT_VERSION, MIN_FORMAT_VERSION, SUPPORTED_FEATURES,
+};
+use velocity::layout::DataLayout;
+use velocity::policy::validate_policies;
+
+/// Checks that this binary can serve `data_dir` with the config at
+/// `config_path` without opening any database, so it is safe to run before
+/// replacing a running release.
+pub fn run_preflight(config_path: &Path, data_dir: &Path) -> Vec<DoctorCheck> {
+    let mut checks = vec![DoctorCheck::pass(
+        "Binary",
+        format!(
+            "velocity {} reads formats {}-{} with features {}",
+            env!("CARGO_PKG_VERSION"),
+            MIN_FORMAT_VERSION,
+            FORMAT_VERSION,
+            SUPPORTED_FEATURES.join(", ")
+        ),
+    )];
+
+    let config = check_config(config_path, &mut checks);
+    if let Some(config) = &config {
+        check_config_values(config, data_dir, &mut checks);
+    }
+    let addon = config.as_ref().and_then(|config| config.addons.database.as_ref());
+    for dir in database_dirs(data_dir, addon) {
+        check_format(&dir, &mut checks);
+    }
+    checks
+}
+
+fn check_config_values(config: &ConfigFile, data_dir: &Path, checks: &mut Vec<DoctorCheck>) {
+    const NAME: &str = "Config values";
+
+    let result = validate_policies(&config.database.key_policies)
+        .map_err(|e| e.to_string())
+        .and_then(|_| match &config.debug.fault_injection {
+            Some(faults) => faults.validate().map_err(|e| e.to_string()),
+            None => Ok(()),
+        })
+        .and_then(|_| {
+            config
+                .server_config(&config.server.bind_address, data_dir)
+                .map(|_| ())
+                .map_err(|e| e.to_string())
+        });
+    match result {
+        Ok(()) => checks.push(DoctorCheck::pass(NAME, "accepted by this binary")),
+        Err(e) => checks.push(DoctorCheck::fail(
+            NAME,
+            e,
+            "Fix the setting, or check the release notes for settings that changed",
+        )),
+    }
+}
+
+fn check_format(dir: &Path, checks: &mut Vec<DoctorCheck>) {
+    const NAME: &str = "Data format";
+
+    match FormatInfo::read(dir) {
+        Ok(Some(format)) => match format.problem() {
+            Some(problem) => checks.push(DoctorCheck::fail(
+                NAME,
+                format!("{}: {}", dir.display(), problem),
+                "Do not start this binary against the directory; nothing has been changed",
+            )),
+            None => checks.push(DoctorCheck::pass(
+                NAME,
+                format!(
+                    "{}: version {}{}",
+                    dir.display(),
+                    format.format_version,
+                    if format.features.is_empty() {
+                        String::new()
+                    } else {
+                        format!(" with {}", format.features.join(", "))
+                    }
+                ),
+            )),
+        },
+        Ok(None) if DataLayout::has_data(dir) => checks.push(DoctorCheck::pass(
+            NAME,
+            format!("{}: written before format tracking, read as version 1", dir.display()),
+        )),
+        Ok(None) => {}
+        Err(e) => checks.push(DoctorCheck::fail(
+            NAME,
+            format!("{}: {}", dir.display(), e),
+            "Restore meta/format.json from a backup before starting the server",
+        )),
+    }
+}
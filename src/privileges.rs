@@ -0,0 +1,135 @@
+use std::path::{Path, PathBuf};
+
+use crate::{VeloError, VeloResult};
+
+/// Switches the process to `user` (and `group`, or the user's primary group)
+/// after privileged setup such as binding a low port or opening root-owned
+/// files is done. Succeeds without doing anything when the process already
+/// runs as that user. Every directory in `database_dirs` (see
+/// [`crate::format::database_dirs`]) must be writable by the new user,
+/// since SSTables and WAL segments are created there later.
+#[cfg(unix)]
+pub fn drop_privileges(user: &str, group: Option<&str>, database_dirs: &[PathBuf]) -> VeloResult<()> {
+    let (uid, primary_gid) = lookup_user(user)?;
+    let gid = match group {
+        Some(group) => lookup_group(group)?,
+        None => primary_gid,
+    };
+
+    // SAFETY: plain libc calls without pointer arguments.
+    let (current_uid, current_gid) = unsafe { (libc::geteuid(), libc::getegid()) };
+    if current_uid == uid && current_gid == gid {
+        return Ok(());
+    }
+    if current_uid != 0 {
+        return Err(VeloError::InvalidOperation(format!(
+            "Cannot switch to user '{}': the service must be started as root to drop privileges",
+            user
+        )));
+    }
+
+    let c_user = std::ffi::CString::new(user)
+        .map_err(|_| VeloError::InvalidOperation(format!("Invalid user name '{}'", user)))?;
+    // SAFETY: c_user is a valid NUL-terminated string; the order (groups,
+    // gid, uid) is required because setgid fails once root is given up.
+    unsafe {
+        if libc::initgroups(c_user.as_ptr(), gid as _) != 0 {
+            return Err(os_error("initgroups"));
+        }
+        if libc::setgid(gid) != 0 {
+            return Err(os_error("setgid"));
+        }
+        if libc::setuid(uid) != 0 {
+            return Err(os_error("setuid"));
+        }
+        if libc::setuid(0) == 0 {
+            return Err(VeloError::InvalidOperation(
+                "Privilege drop failed: root could be regained".to_string(),
+            ));
+        }
+    }
+
+    for dir in database_dirs {
+        let probe = dir.join(".privilege_probe");
+        std::fs::write(&probe, b"probe").map_err(|e| {
+            VeloError::InvalidOperation(format!(
+                "Database directory {:?} is not writable by user '{}': {} (chown it to that user)",
+                dir, user, e
+            ))
+        })?;
+        let _ = std::fs::remove_file(&probe);
+    }
+
+    log::info!("Dropped privileges to user '{}' (uid {}, gid {})", user, uid, gid);
+    Ok(())
+}
+
+/// Hands `path` to `user` (and `group`, or the user's primary group) before
+/// privileges are dropped, e.g. a Unix socket created while still root.
+#[cfg(unix)]
+pub fn chown_to(path: &Path, user: &str, group: Option<&str>) -> VeloResult<()> {
+    let (uid, primary_gid) = lookup_user(user)?;
+    let gid = match group {
+        Some(group) => lookup_group(group)?,
+        None => primary_gid,
+    };
+    std::os::unix::fs::chown(path, Some(uid), Some(gid)).map_err(|e| {
+        VeloError::InvalidOperation(format!("Cannot chown {:?} to '{}': {}", path, user, e))
+    })
+}
+
+#[cfg(not(unix))]
+pub fn drop_privileges(
+    user: &str,
+    _group: Option<&str>,
+    _database_dirs: &[PathBuf],
+) -> VeloResult<()> {
+    Err(VeloError::InvalidOperation(format!(
+        "Running as user '{}' is only supported on Unix; configure the service account instead",
+        user
+    )))
+}
+
+#[cfg(unix)]
+fn lookup_user(name: &str) -> VeloResult<(libc::uid_t, libc::gid_t)> {
+    let c_name = std::ffi::CString::new(name)
+        .map_err(|_| VeloError::InvalidOperation(format!("Invalid user name '{}'", name)))?;
+    let mut buf = vec![0 as libc::c_char; 16 * 1024];
+    // SAFETY: passwd is plain old data and getpwnam_r only writes into the
+    // buffers we hand it.
+    let mut pwd: libc::passwd = unsafe { std::mem::zeroed() };
+    let mut result: *mut libc::passwd = std::ptr::null_mut();
+    let rc = unsafe {
+        libc::getpwnam_r(c_name.as_ptr(), &mut pwd, buf.as_mut_ptr(), buf.len(), &mut result)
+    };
+    if rc != 0 || result.is_null() {
+        return Err(VeloError::InvalidOperation(format!("Unknown user '{}'", name)));
+    }
+    Ok((pwd.pw_uid, pwd.pw_gid))
+}
+
+#[cfg(unix)]
+fn lookup_group(name: &str) -> VeloResult<libc::gid_t> {
+    let c_name = std::ffi::CString::new(name)
+        .map_err(|_| VeloError::InvalidOperation(format!("Invalid group name '{}'", name)))?;
+    let mut buf = vec![0 as libc::c_char; 16 * 1024];
+    // SAFETY: as in lookup_user.
+    let mut grp: libc::group = unsafe { std::mem::zeroed() };
+    let mut result: *mut libc::group = std::ptr::null_mut();
+    let rc = unsafe {
+        libc::getgrnam_r(c_name.as_ptr(), &mut grp, buf.as_mut_ptr(), buf.len(), &mut result)
+    };
+    if rc != 0 || result.is_null() {
+        return Err(VeloError::InvalidOperation(format!("Unknown group '{}'", name)));
+    }
+    Ok(grp.gr_gid)
+}
+
+#[cfg(unix)]
+fn os_error(call: &str) -> VeloError {
+    VeloError::InvalidOperation(format!(
+        "Privilege drop failed in {}: {}",
+        call,
+        std::io::Error::last_os_error()
+    ))
+}
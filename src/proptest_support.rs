@@ -0,0 +1,467 @@
+//! Property-based round-trip checks of the storage engine against an
+//! in-memory model.
+//!
+//! `OpStrategy` generates keys, values and sequences of operations from a
+//! seed, mixing writes and reads with flushes, compactions and restarts.
+//! `Harness` applies a sequence to a real database and to `Model`, a plain
+//! ordered map, and fails on the first read where the two disagree. After
+//! the last operation the whole database is compared with the model.
+//!
+//! `check` runs many seeded cases and, when one fails, shrinks it by
+//! dropping operations for as long as it keeps failing, so the reported
+//! sequence is short enough to read. The seed in a `Failure` reproduces
+//! the original case. Contributors changing the write, flush or compaction
+//! paths can add operations or run the check under their own config.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+use crate::compaction::CompactionControl;
+use crate::{BatchOp, VeloKey, VeloResult, VeloValue, Velocity, VelocityConfig};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Op {
+    /// An empty value is stored as a tombstone, like a delete.
+    Put(VeloKey, VeloValue),
+    Delete(VeloKey),
+    Batch(Vec<BatchOp>),
+    Get(VeloKey),
+    Range {
+        start: VeloKey,
+        end: Option<VeloKey>,
+        limit: usize,
+    },
+    Flush,
+    /// The compaction a flush would trigger.
+    Compact,
+    CompactFull,
+    /// Closes the database and opens it again.
+    Restart,
+}
+
+/// Relative frequency of each kind of operation.
+#[derive(Debug, Clone)]
+pub struct OpWeights {
+    pub put: u32,
+    pub delete: u32,
+    pub batch: u32,
+    pub get: u32,
+    pub range: u32,
+    pub flush: u32,
+    pub compact: u32,
+    pub compact_full: u32,
+    pub restart: u32,
+}
+
+impl Default for OpWeights {
+    fn default() -> Self {
+        Self {
+            put: 40,
+            delete: 12,
+            batch: 6,
+            get: 20,
+            range: 6,
+            flush: 6,
+            compact: 4,
+            compact_full: 2,
+            restart: 4,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct OpStrategy {
+    /// Distinct keys to draw from. A small space makes overwrites and
+    /// deletes of existing keys common.
+    pub key_space: usize,
+    pub max_value_len: usize,
+    pub max_batch_len: usize,
+    pub weights: OpWeights,
+}
+
+impl Default for OpStrategy {
+    fn default() -> Self {
+        Self {
+            key_space: 64,
+            max_value_len: 48,
+            max_batch_len: 8,
+            weights: OpWeights::default(),
+        }
+    }
+}
+
+impl OpStrategy {
+    pub fn key(&self, rng: &mut StdRng) -> VeloKey {
+        format!("key/{:04}", rng.gen_range(0..self.key_space.max(1)))
+    }
+
+    /// Empty one time in sixteen, so tombstones written by puts are covered.
+    pub fn value(&self, rng: &mut StdRng) -> VeloValue {
+        if rng.gen_ratio(1, 16) {
+            return Vec::new();
+        }
+        let len = rng.gen_range(1..=self.max_value_len.max(1));
+        (0..len).map(|_| rng.gen()).collect()
+    }
+
+    pub fn batch_op(&self, rng: &mut StdRng) -> BatchOp {
+        if rng.gen_ratio(1, 4) {
+            BatchOp::Delete { key: self.key(rng) }
+        } else {
+            BatchOp::Put {
+                key: self.key(rng),
+                value: self.value(rng),
+            }
+        }
+    }
+
+    pub fn op(&self, rng: &mut StdRng) -> Op {
+        let w = &self.weights;
+        let choices = [
+            w.put,
+            w.delete,
+            w.batch,
+            w.get,
+            w.range,
+            w.flush,
+            w.compact,
+            w.compact_full,
+            w.restart,
+        ];
+        let total: u32 = choices.iter().sum();
+        let mut pick = rng.gen_range(0..total.max(1));
+        let kind = choices
+            .iter()
+            .position(|&weight| {
+                let hit = pick < weight;
+                pick = pick.saturating_sub(weight);
+                hit
+            })
+            .unwrap_or(0);
+        match kind {
+            0 => Op::Put(self.key(rng), self.value(rng)),
+            1 => Op::Delete(self.key(rng)),
+            2 => {
+                let len = rng.gen_range(1..=self.max_batch_len.max(1));
+                Op::Batch((0..len).map(|_| self.batch_op(rng)).collect())
+            }
+            3 => Op::Get(self.key(rng)),
+            4 => {
+                let start = self.key(rng);
+                let end = rng
+                    .gen_bool(0.7)
+                    .then(|| self.key(rng))
+                    .filter(|end| *end > start);
+                Op::Range {
+                    start,
+                    end,
+                    limit: rng.gen_range(1..=self.key_space.max(1)),
+                }
+            }
+            5 => Op::Flush,
+            6 => Op::Compact,
+            7 => Op::CompactFull,
+            _ => Op::Restart,
+        }
+    }
+
+    pub fn ops(&self, rng: &mut StdRng, len: usize) -> Vec<Op> {
+        (0..len).map(|_| self.op(rng)).collect()
+    }
+}
+
+/// What the database should hold after a sequence of operations.
+#[derive(Debug, Clone, Default)]
+pub struct Model {
+    entries: BTreeMap<VeloKey, VeloValue>,
+}
+
+impl Model {
+    fn put(&mut self, key: &str, value: &[u8]) {
+        if value.is_empty() {
+            self.entries.remove(key);
+        } else {
+            self.entries.insert(key.to_string(), value.to_vec());
+        }
+    }
+
+    pub fn apply(&mut self, op: &Op) {
+        match op {
+            Op::Put(key, value) => self.put(key, value),
+            Op::Delete(key) => {
+                self.entries.remove(key);
+            }
+            Op::Batch(ops) => {
+                for op in ops {
+                    match op {
+                        BatchOp::Put { key, value } => self.put(key, value),
+                        BatchOp::Delete { key } => {
+                            self.entries.remove(key);
+                        }
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    pub fn get(&self, key: &str) -> Option<&VeloValue> {
+        self.entries.get(key)
+    }
+
+    pub fn range(&self, start: &str, end: Option<&str>, limit: usize) -> Vec<(VeloKey, VeloValue)> {
+        self.entries
+            .range(start.to_string()..)
+            .take_while(|(key, _)| end.is_none_or(|end| key.as_str() < end))
+            .take(limit)
+            .map(|(key, value)| (key.clone(), value.clone()))
+            .collect()
+    }
+
+    pub fn entries(&self) -> &BTreeMap<VeloKey, VeloValue> {
+        &self.entries
+    }
+}
+
+/// A database and its model, kept in step one operation at a time.
+pub struct Harness {
+    dir: PathBuf,
+    config: VelocityConfig,
+    db: Option<Velocity>,
+    model: Model,
+}
+
+impl Harness {
+    /// Opens a database in `dir`, which should be empty.
+    pub fn open<P: AsRef<Path>>(dir: P, config: VelocityConfig) -> VeloResult<Self> {
+        let dir = dir.as_ref().to_path_buf();
+        let db = Velocity::open_with_config(&dir, config.clone())?;
+        Ok(Self {
+            dir,
+            config,
+            db: Some(db),
+            model: Model::default(),
+        })
+    }
+
+    pub fn db(&self) -> &Velocity {
+        self.db
+            .as_ref()
+            .expect("database is open between operations")
+    }
+
+    pub fn model(&self) -> &Model {
+        &self.model
+    }
+
+    /// Applies `op` to both sides. Reads are compared; `Err` describes the
+    /// first mismatch or engine error.
+    pub fn apply(&mut self, op: &Op) -> Result<(), String> {
+        self.model.apply(op);
+        let db = self.db();
+        match op {
+            Op::Put(key, value) => db.put(key.clone(), value.clone()),
+            Op::Delete(key) => db.delete(key.clone()),
+            Op::Batch(ops) => db.write_batch(ops),
+            Op::Get(key) => {
+                let actual = db.get(key).map_err(|e| e.to_string())?;
+                let expected = self.model.get(key);
+                if actual.as_ref() != expected {
+                    return Err(format!(
+                        "get {:?} returned {:?}, expected {:?}",
+                        key, actual, expected
+                    ));
+                }
+                Ok(())
+            }
+            Op::Range { start, end, limit } => {
+                let actual = db
+                    .range(start, end.as_deref(), *limit)
+                    .map_err(|e| e.to_string())?;
+                let expected = self.model.range(start, end.as_deref(), *limit);
+                if actual != expected {
+                    return Err(format!(
+                        "range {:?}..{:?} returned {} entries, expected {}",
+                        start,
+                        end,
+                        actual.len(),
+                        expected.len()
+                    ));
+                }
+                Ok(())
+            }
+            Op::Flush => db.flush(),
+            Op::Compact => db.compact(),
+            Op::CompactFull => db.compact_full(&CompactionControl::new(0)).map(|_| ()),
+            Op::Restart => {
+                if let Some(db) = self.db.take() {
+                    db.close().map_err(|e| e.to_string())?;
+                }
+                let db = Velocity::open_with_config(&self.dir, self.config.clone())
+                    .map_err(|e| format!("reopen failed: {}", e))?;
+                self.db = Some(db);
+                Ok(())
+            }
+        }
+        .map_err(|e| format!("{:?} failed: {}", op, e))
+    }
+
+    /// Compares every key of the database with the model.
+    pub fn check_contents(&self) -> Result<(), String> {
+        let actual = self
+            .db()
+            .range("", None, usize::MAX)
+            .map_err(|e| e.to_string())?;
+        let expected = self.model.range("", None, usize::MAX);
+        if actual == expected {
+            return Ok(());
+        }
+        let first = actual
+            .iter()
+            .zip(&expected)
+            .find(|(a, e)| a != e)
+            .map(|(a, e)| format!(", first difference at {:?} (expected {:?})", a.0, e.0))
+            .unwrap_or_default();
+        Err(format!(
+            "database holds {} keys, model holds {}{}",
+            actual.len(),
+            expected.len(),
+            first
+        ))
+    }
+
+    pub fn close(mut self) -> VeloResult<()> {
+        match self.db.take() {
+            Some(db) => db.close(),
+            None => Ok(()),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct PropertyConfig {
+    pub cases: usize,
+    pub max_ops: usize,
+    /// Seed of the first case; later cases use the following seeds.
+    /// Random when unset.
+    pub seed: Option<u64>,
+    pub strategy: OpStrategy,
+    /// The database under test. The default has a tiny memtable so that
+    /// writes reach SSTables quickly.
+    pub velocity: VelocityConfig,
+    pub shrink: bool,
+}
+
+impl Default for PropertyConfig {
+    fn default() -> Self {
+        Self {
+            cases: 32,
+            max_ops: 200,
+            seed: None,
+            strategy: OpStrategy::default(),
+            velocity: VelocityConfig {
+                max_memtable_size: 16,
+                cache_size: 64,
+                background_threads: false,
+                ..VelocityConfig::default()
+            },
+            shrink: true,
+        }
+    }
+}
+
+/// A failing case, reduced to the shortest sequence found that still fails.
+#[derive(Debug, Clone)]
+pub struct Failure {
+    pub seed: u64,
+    pub ops: Vec<Op>,
+    pub message: String,
+}
+
+impl fmt::Display for Failure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "case with seed {} failed: {}", self.seed, self.message)?;
+        for (i, op) in self.ops.iter().enumerate() {
+            writeln!(f, "  {:>4}: {:?}", i, op)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for Failure {}
+
+/// Runs `ops` against a fresh database in the `case` subdirectory of `dir`,
+/// removing that subdirectory afterwards. Nothing else in `dir` is touched.
+pub fn run_ops(dir: &Path, config: &VelocityConfig, ops: &[Op]) -> Result<(), String> {
+    let dir = &dir.join("case");
+    let _ = std::fs::remove_dir_all(dir);
+    let result = (|| {
+        let mut harness = Harness::open(dir, config.clone()).map_err(|e| e.to_string())?;
+        for op in ops {
+            harness.apply(op)?;
+        }
+        harness.check_contents()?;
+        harness.close().map_err(|e| e.to_string())
+    })();
+    let _ = std::fs::remove_dir_all(dir);
+    result
+}
+
+// Drops chunks of operations, halving the chunk size whenever no chunk can
+// go, until single operations cannot be dropped either.
+fn shrink(
+    dir: &Path,
+    config: &VelocityConfig,
+    mut ops: Vec<Op>,
+    mut message: String,
+) -> (Vec<Op>, String) {
+    let mut chunk = ops.len().div_ceil(2).max(1);
+    loop {
+        let mut start = 0;
+        let mut removed = false;
+        while start < ops.len() {
+            let mut candidate = ops.clone();
+            candidate.drain(start..(start + chunk).min(ops.len()));
+            match run_ops(dir, config, &candidate) {
+                Err(candidate_message) => {
+                    ops = candidate;
+                    message = candidate_message;
+                    removed = true;
+                }
+                Ok(()) => start += chunk,
+            }
+        }
+        if !removed {
+            if chunk == 1 {
+                return (ops, message);
+            }
+            chunk = chunk.div_ceil(2);
+        }
+    }
+}
+
+/// Checks that the database agrees with the model over `config.cases`
+/// random sequences, using `dir` as scratch space.
+pub fn check<P: AsRef<Path>>(dir: P, config: &PropertyConfig) -> Result<(), Failure> {
+    let dir = dir.as_ref();
+    let first_seed = config.seed.unwrap_or_else(rand::random);
+    for case in 0..config.cases as u64 {
+        let seed = first_seed.wrapping_add(case);
+        let mut rng = StdRng::seed_from_u64(seed);
+        let len = rng.gen_range(1..=config.max_ops.max(1));
+        let ops = config.strategy.ops(&mut rng, len);
+        if let Err(message) = run_ops(dir, &config.velocity, &ops) {
+            let (ops, message) = if config.shrink {
+                shrink(dir, &config.velocity, ops, message)
+            } else {
+                (ops, message)
+            };
+            return Err(Failure { seed, ops, message });
+        }
+    }
+    Ok(())
+}
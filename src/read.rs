@@ -0,0 +1,271 @@
+use std::fs::File;
+use std::io::BufReader;
+use std::sync::atomic::Ordering;
+use std::sync::{Arc, Mutex};
+
+use crate::faults::StorageOp;
+use crate::memtable::Memtable;
+use crate::{
+    now_millis, split_record, RawValue, RecordMeta, SSTable, VeloKey, VeloResult, VeloValue,
+    Velocity,
+};
+
+type ValueWithMeta = (VeloValue, Option<RecordMeta>);
+type RangeSource<'a> = Box<dyn Iterator<Item = VeloResult<(VeloKey, RawValue)>> + 'a>;
+
+/// Per-call read settings for `get_with_options` and `range_with_options`.
+pub struct ReadOptions<'a> {
+    /// Whether values read from disk or the memtable are added to the block
+    /// cache. Bulk scans should turn this off so they do not evict hot keys.
+    pub fill_cache: bool,
+    /// Re-hash every SSTable a value is read from and fail with
+    /// `CorruptedData` if it no longer matches its checksum.
+    pub verify_checksums: bool,
+    /// Read the database as it was when the snapshot was taken.
+    pub snapshot: Option<&'a Snapshot>,
+}
+
+impl Default for ReadOptions<'_> {
+    fn default() -> Self {
+        Self {
+            fill_cache: true,
+            verify_checksums: false,
+            snapshot: None,
+        }
+    }
+}
+
+/// A point-in-time view of the database. It holds a copy of the memtable and
+/// open handles to the SSTables that existed when it was taken, so later
+/// writes, flushes and compactions do not change what it returns.
+pub struct Snapshot {
+    sequence: u64,
+    memtable: Memtable,
+    sstables: Vec<(Arc<SSTable>, Mutex<File>)>,
+}
+
+impl Snapshot {
+    /// The sequence of the last write the snapshot includes.
+    pub fn sequence(&self) -> u64 {
+        self.sequence
+    }
+
+    pub(crate) fn get_raw(&self, key: &str, verify: bool) -> VeloResult<Option<RawValue>> {
+        if let Some((value, has_meta)) = self.memtable.get(key) {
+            return Ok((!value.is_empty()).then(|| RawValue {
+                bytes: value.to_vec(),
+                has_meta,
+            }));
+        }
+        for (sstable, file) in self.sstables.iter().rev() {
+            let mut file = file.lock().unwrap();
+            if let Some(value) = sstable.get_from(key, || Ok(BufReader::new(&mut *file)))? {
+                if verify {
+                    sstable.verify_checksum(&mut *file)?;
+                }
+                return Ok((!value.is_tombstone()).then_some(value));
+            }
+        }
+        Ok(None)
+    }
+}
+
+impl Velocity {
+    /// Pins the current memtable and SSTables. Keep snapshots short-lived:
+    /// the memtable is copied, and files replaced by compaction stay on disk
+    /// until every snapshot holding them is dropped.
+    pub fn snapshot(&self) -> VeloResult<Snapshot> {
+        let memtable = self.memtable.read().unwrap();
+        let sstables = self.sstables.read().unwrap();
+        Ok(Snapshot {
+            sequence: self.sequences().0,
+            memtable: memtable.clone(),
+            sstables: Self::pin_sstables(&sstables)?,
+        })
+    }
+
+    pub fn get_with_options(
+        &self,
+        key: &str,
+        options: &ReadOptions,
+    ) -> VeloResult<Option<VeloValue>> {
+        Ok(self.get_raw_with(key, options)?.map(|raw| split_record(raw).0))
+    }
+
+    /// Looks up each of `keys` and returns the values in the same order,
+    /// with `None` for keys that are missing.
+    pub fn multi_get<K: AsRef<str>>(&self, keys: &[K]) -> VeloResult<Vec<Option<VeloValue>>> {
+        keys.iter().map(|key| self.get(key.as_ref())).collect()
+    }
+
+    /// `multi_get` that also returns each record's metadata.
+    pub fn multi_get_with_meta<K: AsRef<str>>(
+        &self,
+        keys: &[K],
+    ) -> VeloResult<Vec<Option<ValueWithMeta>>> {
+        keys.iter().map(|key| self.get_with_meta(key.as_ref())).collect()
+    }
+
+    /// Live entries with `start <= key < end` in key order, at most `limit`
+    /// of them. `None` for `end` reads to the last key.
+    pub fn range(
+        &self,
+        start: &str,
+        end: Option<&str>,
+        limit: usize,
+    ) -> VeloResult<Vec<(VeloKey, VeloValue)>> {
+        self.range_with_options(start, end, limit, &ReadOptions::default())
+    }
+
+    /// `range` with explicit read options. Range results never go into the
+    /// cache, so `fill_cache` has no effect here.
+    pub fn range_with_options(
+        &self,
+        start: &str,
+        end: Option<&str>,
+        limit: usize,
+        options: &ReadOptions,
+    ) -> VeloResult<Vec<(VeloKey, VeloValue)>> {
+        Ok(self
+            .range_raw(start, end, limit, options, &|_| true)?
+            .into_iter()
+            .map(|(key, raw)| (key, split_record(raw).0))
+            .collect())
+    }
+
+    pub fn range_with_meta(
+        &self,
+        start: &str,
+        end: Option<&str>,
+        limit: usize,
+    ) -> VeloResult<Vec<(VeloKey, VeloValue, Option<RecordMeta>)>> {
+        self.range_matching_with_meta(start, end, limit, |_| true)
+    }
+
+    // `range_with_meta` over only the keys `keep` accepts; the limit counts
+    // kept keys, so a filtered page stops reading as soon as it is full.
+    pub(crate) fn range_matching_with_meta(
+        &self,
+        start: &str,
+        end: Option<&str>,
+        limit: usize,
+        keep: impl Fn(&str) -> bool,
+    ) -> VeloResult<Vec<(VeloKey, VeloValue, Option<RecordMeta>)>> {
+        Ok(self
+            .range_raw(start, end, limit, &ReadOptions::default(), &keep)?
+            .into_iter()
+            .map(|(key, raw)| {
+                let (value, meta) = split_record(raw);
+                (key, value, meta)
+            })
+            .collect())
+    }
+
+    // Merges the memtable and the SSTables key by key, newest version first,
+    // and stops once `limit` live keys are found, so reading a page costs
+    // the page rather than the rest of the range.
+    fn range_raw(
+        &self,
+        start: &str,
+        end: Option<&str>,
+        limit: usize,
+        options: &ReadOptions,
+        keep: &dyn Fn(&str) -> bool,
+    ) -> VeloResult<Vec<(VeloKey, RawValue)>> {
+        let now = now_millis();
+        let in_range = |key: &str| end.is_none_or(|end| key < end);
+        let live = |key: &str, value: &RawValue| {
+            !value.is_tombstone()
+                && !Self::hidden_from_scan(start, key)
+                && keep(key)
+                && !self.is_expired(key, &value.bytes, now)
+        };
+        // A key live in the memtable is live whatever the tables hold, so
+        // the page is complete by the memtable's `limit`-th live key and
+        // nothing after it needs copying.
+        let memtable_range = |memtable: &Memtable| {
+            let mut entries = Vec::new();
+            let mut found = 0;
+            for (key, value, has_meta) in memtable.range_from(start) {
+                if found == limit || !in_range(key) {
+                    break;
+                }
+                let value = RawValue {
+                    bytes: value.to_vec(),
+                    has_meta,
+                };
+                found += live(key, &value) as usize;
+                entries.push((key.to_string(), value));
+            }
+            entries
+        };
+
+        self.inject_fault(StorageOp::Read)?;
+        let pinned;
+        let (sstables, memtable) = match options.snapshot {
+            Some(snapshot) => (&snapshot.sstables, memtable_range(&snapshot.memtable)),
+            None => {
+                let memtable = self.memtable.read().unwrap();
+                let sstables = self.sstables.read().unwrap();
+                pinned = Self::pin_sstables(&sstables)?;
+                (&pinned, memtable_range(&memtable))
+            }
+        };
+
+        // Sources newest first: the memtable, then the tables from the most
+        // recently written back to the oldest.
+        let mut files: Vec<_> = sstables
+            .iter()
+            .map(|(_, file)| file.lock().unwrap())
+            .collect();
+        let mut sources: Vec<RangeSource> = vec![Box::new(memtable.into_iter().map(Ok))];
+        for ((sstable, _), file) in sstables.iter().zip(files.iter_mut()).rev() {
+            if options.verify_checksums {
+                sstable.verify_checksum(&mut **file)?;
+            }
+            let reader = BufReader::with_capacity(256 * 1024, &mut **file);
+            sources.push(Box::new(sstable.range_from(reader, start, end)?));
+        }
+        let mut heads = sources
+            .iter_mut()
+            .map(|source| source.next().transpose())
+            .collect::<VeloResult<Vec<_>>>()?;
+
+        let mut entries = Vec::new();
+        let mut scanned = 0;
+        while entries.len() < limit {
+            let Some(key) = heads.iter().flatten().map(|(key, _)| key).min().cloned() else {
+                break;
+            };
+            let mut newest = None;
+            for (head, source) in heads.iter_mut().zip(sources.iter_mut()) {
+                if head.as_ref().is_some_and(|(head_key, _)| *head_key == key) {
+                    let next = source.next().transpose()?;
+                    let (_, value) = std::mem::replace(head, next).unwrap();
+                    scanned += key.len() + value.bytes.len();
+                    newest.get_or_insert(value);
+                }
+            }
+            let value = newest.unwrap();
+            if !live(&key, &value) {
+                continue;
+            }
+            if let Some(bytes) = self.strip_expiry(&key, value.bytes, now) {
+                let value = RawValue {
+                    bytes,
+                    has_meta: value.has_meta,
+                };
+                entries.push((key, value));
+            }
+        }
+        self.scan_bytes.fetch_add(scanned as u64, Ordering::Relaxed);
+        Ok(entries)
+    }
+
+    fn pin_sstables(sstables: &[Arc<SSTable>]) -> VeloResult<Vec<(Arc<SSTable>, Mutex<File>)>> {
+        sstables
+            .iter()
+            .map(|sstable| Ok((sstable.clone(), Mutex::new(File::open(&sstable.path)?))))
+            .collect()
+    }
+}
@@ -0,0 +1,233 @@
+//! Where point reads are answered — the cache, the memtable, an SSTable at
+//! some level, or nowhere — and how long each kind of read takes.
+
+use std::collections::BTreeMap;
+use std::ops::AddAssign;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+use serde::ser::SerializeStruct;
+use serde::{Serialize, Serializer};
+
+// Reads answered by deeper levels are counted with the deepest one.
+const LEVELS: usize = 8;
+
+// Each power of two of nanoseconds is split into this many buckets, so a
+// percentile is within 25% of the true latency.
+const SUB_BUCKETS: u32 = 4;
+// Up to 2^40 ns, about 18 minutes.
+const BUCKETS: usize = 40 * SUB_BUCKETS as usize;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReadSource {
+    Cache,
+    Memtable,
+    SSTable {
+        level: u32,
+    },
+    /// The key was in neither, or the bloom filter ruled it out.
+    Miss,
+}
+
+impl ReadSource {
+    /// Prometheus labels, such as `source="sstable",level="1"`.
+    pub fn labels(&self) -> String {
+        match self {
+            ReadSource::Cache => "source=\"cache\"".to_string(),
+            ReadSource::Memtable => "source=\"memtable\"".to_string(),
+            ReadSource::SSTable { level } => format!("source=\"sstable\",level=\"{}\"", level),
+            ReadSource::Miss => "source=\"miss\"".to_string(),
+        }
+    }
+}
+
+pub(crate) struct ReadSourceMetrics {
+    cache: AtomicHistogram,
+    memtable: AtomicHistogram,
+    levels: [AtomicHistogram; LEVELS],
+    miss: AtomicHistogram,
+}
+
+impl ReadSourceMetrics {
+    pub(crate) fn new() -> Self {
+        Self {
+            cache: AtomicHistogram::new(),
+            memtable: AtomicHistogram::new(),
+            levels: std::array::from_fn(|_| AtomicHistogram::new()),
+            miss: AtomicHistogram::new(),
+        }
+    }
+
+    pub(crate) fn record(&self, source: ReadSource, elapsed: Duration) {
+        let histogram = match source {
+            ReadSource::Cache => &self.cache,
+            ReadSource::Memtable => &self.memtable,
+            ReadSource::SSTable { level } => &self.levels[(level as usize).min(LEVELS - 1)],
+            ReadSource::Miss => &self.miss,
+        };
+        histogram.record(elapsed);
+    }
+
+    pub(crate) fn stats(&self) -> ReadSourceStats {
+        ReadSourceStats {
+            cache: self.cache.snapshot(),
+            memtable: self.memtable.snapshot(),
+            sstable_levels: self
+                .levels
+                .iter()
+                .enumerate()
+                .map(|(level, histogram)| (level as u32, histogram.snapshot()))
+                .filter(|(_, histogram)| histogram.count > 0)
+                .collect(),
+            miss: self.miss.snapshot(),
+        }
+    }
+}
+
+struct AtomicHistogram {
+    total_ns: AtomicU64,
+    buckets: [AtomicU64; BUCKETS],
+}
+
+impl AtomicHistogram {
+    fn new() -> Self {
+        Self {
+            total_ns: AtomicU64::new(0),
+            buckets: std::array::from_fn(|_| AtomicU64::new(0)),
+        }
+    }
+
+    fn record(&self, elapsed: Duration) {
+        let ns = elapsed.as_nanos().min(u64::MAX as u128) as u64;
+        self.total_ns.fetch_add(ns, Ordering::Relaxed);
+        self.buckets[bucket(ns)].fetch_add(1, Ordering::Relaxed);
+    }
+
+    // The count is the sum of the buckets read, so percentiles rank
+    // against the same reads they are looked up in.
+    fn snapshot(&self) -> LatencyHistogram {
+        let buckets: Vec<u64> = self
+            .buckets
+            .iter()
+            .map(|b| b.load(Ordering::Relaxed))
+            .collect();
+        LatencyHistogram {
+            count: buckets.iter().sum(),
+            total_ns: self.total_ns.load(Ordering::Relaxed),
+            buckets,
+        }
+    }
+}
+
+fn bucket(ns: u64) -> usize {
+    if ns < SUB_BUCKETS as u64 {
+        return ns as usize;
+    }
+    let log = 63 - ns.leading_zeros();
+    let sub = (ns >> (log - 2)) & (SUB_BUCKETS as u64 - 1);
+    (((log - 1) * SUB_BUCKETS) as usize + sub as usize).min(BUCKETS - 1)
+}
+
+// The largest latency that falls in bucket `index`.
+fn bucket_limit(index: usize) -> u64 {
+    let index = index as u64;
+    let sub_buckets = SUB_BUCKETS as u64;
+    if index < sub_buckets {
+        return index;
+    }
+    let log = index / sub_buckets + 1;
+    let sub = index % sub_buckets;
+    ((sub_buckets + sub + 1) << (log - 2)) - 1
+}
+
+/// Read latencies of one source; serialized as the read count, the mean and
+/// the 50th, 90th and 99th percentiles in microseconds.
+#[derive(Debug, Clone, Default)]
+pub struct LatencyHistogram {
+    pub count: u64,
+    pub total_ns: u64,
+    buckets: Vec<u64>,
+}
+
+impl LatencyHistogram {
+    pub fn mean(&self) -> Duration {
+        Duration::from_nanos(self.total_ns.checked_div(self.count).unwrap_or(0))
+    }
+
+    /// The latency `q` of the reads were at most, `q` between 0 and 1.
+    pub fn percentile(&self, q: f64) -> Duration {
+        let rank = ((self.count as f64 * q).ceil() as u64).max(1);
+        let mut seen = 0;
+        for (index, count) in self.buckets.iter().enumerate() {
+            seen += count;
+            if seen >= rank {
+                return Duration::from_nanos(bucket_limit(index));
+            }
+        }
+        Duration::ZERO
+    }
+}
+
+impl AddAssign for LatencyHistogram {
+    fn add_assign(&mut self, other: Self) {
+        self.count += other.count;
+        self.total_ns += other.total_ns;
+        if self.buckets.len() < other.buckets.len() {
+            self.buckets.resize(other.buckets.len(), 0);
+        }
+        for (mine, theirs) in self.buckets.iter_mut().zip(other.buckets) {
+            *mine += theirs;
+        }
+    }
+}
+
+impl Serialize for LatencyHistogram {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let micros = |d: Duration| d.as_nanos() as f64 / 1000.0;
+        let mut state = serializer.serialize_struct("LatencyHistogram", 5)?;
+        state.serialize_field("reads", &self.count)?;
+        state.serialize_field("mean_us", &micros(self.mean()))?;
+        state.serialize_field("p50_us", &micros(self.percentile(0.5)))?;
+        state.serialize_field("p90_us", &micros(self.percentile(0.9)))?;
+        state.serialize_field("p99_us", &micros(self.percentile(0.99)))?;
+        state.end()
+    }
+}
+
+/// Point reads by where they were answered. A deleted key counts where its
+/// tombstone was found.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ReadSourceStats {
+    pub cache: LatencyHistogram,
+    pub memtable: LatencyHistogram,
+    /// Only levels that answered a read.
+    pub sstable_levels: BTreeMap<u32, LatencyHistogram>,
+    pub miss: LatencyHistogram,
+}
+
+impl ReadSourceStats {
+    pub fn sources(&self) -> impl Iterator<Item = (ReadSource, &LatencyHistogram)> {
+        [
+            (ReadSource::Cache, &self.cache),
+            (ReadSource::Memtable, &self.memtable),
+        ]
+        .into_iter()
+        .chain(
+            self.sstable_levels
+                .iter()
+                .map(|(level, histogram)| (ReadSource::SSTable { level: *level }, histogram)),
+        )
+        .chain([(ReadSource::Miss, &self.miss)])
+    }
+}
+
+impl AddAssign for ReadSourceStats {
+    fn add_assign(&mut self, other: Self) {
+        self.cache += other.cache;
+        self.memtable += other.memtable;
+        for (level, histogram) in other.sstable_levels {
+            *self.sstable_levels.entry(level).or_default() += histogram;
+        }
+        self.miss += other.miss;
+    }
+}
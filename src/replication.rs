@@ -0,0 +1,350 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, MutexGuard};
+
+use serde::{Deserialize, Serialize};
+
+use crate::client::VelocityClient;
+use crate::fsutil;
+use crate::hlc::{self, HlcTimestamp};
+use crate::layout::DataLayout;
+use crate::{VeloError, VeloResult, Velocity, REPLICAS_FILE};
+
+pub const CHUNK_SIZE: usize = 1024 * 1024;
+
+// Archived WAL segments beyond this many bytes are deleted oldest first,
+// and replicas that still needed them have to be bootstrapped again.
+const MAX_RETAINED_WAL_BYTES: u64 = 1024 * 1024 * 1024;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CheckpointFile {
+    pub name: String,
+    pub size: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Checkpoint {
+    pub files: Vec<CheckpointFile>,
+    #[serde(default)]
+    pub wal_segment: u64,
+    pub wal_offset: u64,
+    #[serde(default)]
+    pub hlc: HlcTimestamp,
+}
+
+impl Checkpoint {
+    pub fn total_bytes(&self) -> u64 {
+        self.files.iter().map(|f| f.size).sum()
+    }
+}
+
+/// Where a replica is in the primary's WAL. Each flush on the primary
+/// starts a new segment, so an offset is only meaningful within its segment.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReplicaCursor {
+    pub replica: String,
+    pub wal_segment: u64,
+    pub wal_offset: u64,
+}
+
+/// WAL bytes starting at `offset` of segment `segment`.
+#[derive(Debug, Clone)]
+pub struct WalChunk {
+    pub segment: u64,
+    pub offset: u64,
+    pub bytes: Vec<u8>,
+}
+
+impl WalChunk {
+    pub fn encode(&self) -> Vec<u8> {
+        let mut payload = Vec::with_capacity(16 + self.bytes.len());
+        payload.extend_from_slice(&self.segment.to_le_bytes());
+        payload.extend_from_slice(&self.offset.to_le_bytes());
+        payload.extend_from_slice(&self.bytes);
+        payload
+    }
+
+    pub fn decode(mut payload: Vec<u8>) -> VeloResult<Self> {
+        if payload.len() < 16 {
+            return Err(VeloError::CorruptedData(
+                "WAL chunk is missing its position".to_string(),
+            ));
+        }
+        let segment = u64::from_le_bytes(payload[..8].try_into().unwrap());
+        let offset = u64::from_le_bytes(payload[8..16].try_into().unwrap());
+        payload.drain(..16);
+        Ok(Self {
+            segment,
+            offset,
+            bytes: payload,
+        })
+    }
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct RetentionState {
+    // The live WAL's segment; archived segments have lower numbers.
+    segment: u64,
+    // Each replica's cursor segment. Everything before it has been applied.
+    replicas: BTreeMap<String, u64>,
+}
+
+/// The WAL segments that registered replicas have not read yet. While any
+/// replica is registered, a flush archives the live WAL instead of deleting
+/// it.
+pub(crate) struct WalRetention {
+    dir: PathBuf,
+    path: PathBuf,
+    read_only: bool,
+    state: Mutex<RetentionState>,
+}
+
+impl WalRetention {
+    pub(crate) fn open(layout: &DataLayout, read_only: bool) -> Self {
+        let path = layout.meta_dir().join(REPLICAS_FILE);
+        let mut state: RetentionState = match fs::read_to_string(&path) {
+            Ok(content) => serde_json::from_str(&content).unwrap_or_else(|e| {
+                log::warn!("Ignoring unreadable replica list {:?}: {}", path, e);
+                RetentionState::default()
+            }),
+            Err(_) => RetentionState::default(),
+        };
+        let dir = layout.wal_archive_dir();
+        // A crash between archiving a segment and recording it leaves the
+        // archive ahead of the list.
+        if let Some(last) = archived_segments(&dir).last() {
+            state.segment = state.segment.max(last + 1);
+        }
+        Self {
+            dir,
+            path,
+            read_only,
+            state: Mutex::new(state),
+        }
+    }
+
+    /// Locked before the WAL, so the live segment cannot change while it is
+    /// being read or archived.
+    pub(crate) fn lock(&self) -> RetainedWal<'_> {
+        RetainedWal {
+            retention: self,
+            state: self.state.lock().unwrap(),
+        }
+    }
+}
+
+pub(crate) struct RetainedWal<'a> {
+    retention: &'a WalRetention,
+    state: MutexGuard<'a, RetentionState>,
+}
+
+impl RetainedWal<'_> {
+    pub(crate) fn segment(&self) -> u64 {
+        self.state.segment
+    }
+
+    pub(crate) fn replicas(&self) -> BTreeMap<String, u64> {
+        self.state.replicas.clone()
+    }
+
+    // Where the live WAL goes when it is replaced, or `None` when no
+    // replica needs it.
+    pub(crate) fn archive_path(&self) -> Option<PathBuf> {
+        (!self.state.replicas.is_empty()).then(|| self.segment_path(self.state.segment))
+    }
+
+    pub(crate) fn segment_path(&self, segment: u64) -> PathBuf {
+        self.retention.dir.join(format!("{:020}.wal", segment))
+    }
+
+    // Called once the live WAL was moved to `archive_path`.
+    pub(crate) fn archived(&mut self) -> VeloResult<()> {
+        self.state.segment += 1;
+        self.prune()
+    }
+
+    pub(crate) fn register(&mut self, replica: &str) -> VeloResult<u64> {
+        let segment = self.state.segment;
+        self.state.replicas.insert(replica.to_string(), segment);
+        self.persist()?;
+        Ok(segment)
+    }
+
+    pub(crate) fn forget(&mut self, replica: &str) -> VeloResult<bool> {
+        let known = self.state.replicas.remove(replica).is_some();
+        self.prune()?;
+        Ok(known)
+    }
+
+    // A replica reading from `segment` has applied every segment before it.
+    pub(crate) fn acknowledge(&mut self, replica: &str, segment: u64) -> VeloResult<()> {
+        let Some(acknowledged) = self.state.replicas.get_mut(replica) else {
+            return Err(VeloError::InvalidOperation(format!(
+                "Replica '{}' is not registered or fell too far behind; bootstrap it again",
+                replica
+            )));
+        };
+        if segment > *acknowledged {
+            *acknowledged = segment;
+            self.prune()?;
+        }
+        Ok(())
+    }
+
+    // Deletes segments every replica has read past, then the oldest ones
+    // while the archive is over its size limit.
+    fn prune(&mut self) -> VeloResult<()> {
+        if self.retention.read_only {
+            return Ok(());
+        }
+        let needed = self
+            .state
+            .replicas
+            .values()
+            .min()
+            .copied()
+            .unwrap_or(self.state.segment);
+        let mut segments = archived_segments(&self.retention.dir);
+        let mut retained: u64 = 0;
+        for segment in segments.iter().filter(|segment| **segment >= needed) {
+            retained += fs::metadata(self.segment_path(*segment)).map_or(0, |meta| meta.len());
+        }
+        for segment in segments.drain(..) {
+            let path = self.segment_path(segment);
+            if segment >= needed {
+                if retained <= MAX_RETAINED_WAL_BYTES {
+                    break;
+                }
+                retained -= fs::metadata(&path).map_or(0, |meta| meta.len());
+                self.state.replicas.retain(|replica, acknowledged| {
+                    let lost = *acknowledged <= segment;
+                    if lost {
+                        log::warn!(
+                            "Replica '{}' fell more than {} bytes of WAL behind; it must be bootstrapped again",
+                            replica,
+                            MAX_RETAINED_WAL_BYTES
+                        );
+                    }
+                    !lost
+                });
+            }
+            fs::remove_file(&path)?;
+        }
+        self.persist()
+    }
+
+    fn persist(&self) -> VeloResult<()> {
+        if self.retention.read_only {
+            return Ok(());
+        }
+        let content = serde_json::to_string(&*self.state).map_err(|e| {
+            VeloError::InvalidOperation(format!("Failed to encode replica list: {}", e))
+        })?;
+        fsutil::atomic_write(&self.retention.path, content)?;
+        Ok(())
+    }
+}
+
+fn archived_segments(dir: &Path) -> Vec<u64> {
+    let mut segments: Vec<u64> = fs::read_dir(dir)
+        .map(|entries| {
+            entries
+                .flatten()
+                .filter_map(|entry| {
+                    entry
+                        .file_name()
+                        .to_str()?
+                        .strip_suffix(".wal")?
+                        .parse()
+                        .ok()
+                })
+                .collect()
+        })
+        .unwrap_or_default();
+    segments.sort_unstable();
+    segments
+}
+
+/// Copies a checkpoint of the primary into the empty `layout` and registers
+/// the replica as `replica`, so the primary keeps the WAL it has not read.
+pub async fn bootstrap_replica(
+    client: &mut VelocityClient,
+    layout: &DataLayout,
+    replica: &str,
+) -> VeloResult<ReplicaCursor> {
+    if DataLayout::has_data(layout.root()) {
+        return Err(VeloError::InvalidOperation(format!(
+            "Replica directory {:?} already contains data",
+            layout.root()
+        )));
+    }
+    layout.create_dirs()?;
+
+    let checkpoint: Checkpoint = serde_json::from_slice(
+        &client
+            .replica_command(&format!("REPLICA CHECKPOINT {}", replica))
+            .await?,
+    )
+    .map_err(|e| VeloError::CorruptedData(format!("Invalid checkpoint: {}", e)))?;
+    hlc::clock().observe(checkpoint.hlc);
+    log::info!(
+        "Bootstrapping replica from checkpoint: {} SSTables, {} bytes",
+        checkpoint.files.len(),
+        checkpoint.total_bytes()
+    );
+
+    for file in &checkpoint.files {
+        let part_path = layout.tmp_dir().join(format!("{}.part", file.name));
+        let mut content = Vec::with_capacity(file.size as usize);
+        while (content.len() as u64) < file.size {
+            let chunk = client
+                .replica_command(&format!("REPLICA FETCH {} {}", file.name, content.len()))
+                .await?;
+            if chunk.is_empty() {
+                return Err(VeloError::CorruptedData(format!(
+                    "SSTable '{}' ended after {} of {} bytes",
+                    file.name,
+                    content.len(),
+                    file.size
+                )));
+            }
+            content.extend_from_slice(&chunk);
+        }
+        fs::write(&part_path, &content)?;
+        fs::rename(&part_path, layout.sst_dir().join(&file.name))?;
+        log::debug!("Shipped {} ({} bytes)", file.name, file.size);
+    }
+
+    Ok(ReplicaCursor {
+        replica: replica.to_string(),
+        wal_segment: checkpoint.wal_segment,
+        wal_offset: checkpoint.wal_offset,
+    })
+}
+
+
+pub async fn tail_wal(
+    client: &mut VelocityClient,
+    cursor: &mut ReplicaCursor,
+    replica: &Velocity,
+) -> VeloResult<usize> {
+    let mut applied = 0;
+    loop {
+        let chunk = WalChunk::decode(
+            client
+                .replica_command(&format!(
+                    "REPLICA WAL {} {} {}",
+                    cursor.replica, cursor.wal_segment, cursor.wal_offset
+                ))
+                .await?,
+        )?;
+        let (records, consumed) = replica.apply_wal(&chunk.bytes)?;
+        applied += records;
+        cursor.wal_segment = chunk.segment;
+        cursor.wal_offset = chunk.offset + consumed as u64;
+        if consumed == 0 {
+            return Ok(applied);
+        }
+    }
+}
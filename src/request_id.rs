@@ -0,0 +1,50 @@
+use std::future::Future;
+
+use rand::Rng;
+
+pub const REQUEST_ID_HEADER: &str = "Request-Id:";
+const MAX_REQUEST_ID_LEN: usize = 128;
+
+tokio::task_local! {
+    static REQUEST_ID: String;
+}
+
+/// Prefixes a command payload with a `Request-Id: <id>` first line. It goes
+/// before an idempotency key when a command carries both.
+pub fn with_request_id(request_id: &str, payload: &[u8]) -> Vec<u8> {
+    let mut framed = format!("{} {}\n", REQUEST_ID_HEADER, request_id).into_bytes();
+    framed.extend_from_slice(payload);
+    framed
+}
+
+/// Splits an optional `Request-Id: <id>` first line off a command payload.
+pub fn split_request_id(payload: &[u8]) -> (Option<String>, &[u8]) {
+    let Some(rest) = payload.strip_prefix(REQUEST_ID_HEADER.as_bytes()) else {
+        return (None, payload);
+    };
+    let Some(end) = rest.iter().position(|&b| b == b'\n') else {
+        return (None, payload);
+    };
+    let request_id = String::from_utf8_lossy(&rest[..end]).trim().to_string();
+    if request_id.is_empty()
+        || request_id.len() > MAX_REQUEST_ID_LEN
+        || request_id.chars().any(char::is_control)
+    {
+        return (None, payload);
+    }
+    (Some(request_id), &rest[end + 1..])
+}
+
+pub fn generate() -> String {
+    format!("{:016x}", rand::thread_rng().gen::<u64>())
+}
+
+/// Runs `future` with `request_id` as the current request ID.
+pub async fn scope<F: Future>(request_id: String, future: F) -> F::Output {
+    REQUEST_ID.scope(request_id, future).await
+}
+
+/// The ID of the command being handled on this task, if any.
+pub fn current() -> Option<String> {
+    REQUEST_ID.try_with(|id| id.clone()).ok()
+}
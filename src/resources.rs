@@ -0,0 +1,144 @@
+use serde::Serialize;
+
+// Linux reports CPU times in clock ticks; USER_HZ is 100 on every mainstream
+// architecture.
+const CLOCK_TICKS_PER_SECOND: f64 = 100.0;
+
+/// A point-in-time view of the process' own resource usage. Fields are `None`
+/// on platforms without `/proc`.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ProcessResources {
+    pub rss_bytes: Option<u64>,
+    pub virtual_bytes: Option<u64>,
+    pub threads: Option<usize>,
+    pub open_fds: Option<usize>,
+    pub cpu_user_seconds: Option<f64>,
+    pub cpu_system_seconds: Option<f64>,
+}
+
+impl ProcessResources {
+    pub fn sample() -> Self {
+        let mut resources = ProcessResources::default();
+
+        if let Ok(status) = std::fs::read_to_string("/proc/self/status") {
+            for line in status.lines() {
+                if let Some(value) = line.strip_prefix("VmRSS:") {
+                    resources.rss_bytes = parse_kb(value);
+                } else if let Some(value) = line.strip_prefix("VmSize:") {
+                    resources.virtual_bytes = parse_kb(value);
+                } else if let Some(value) = line.strip_prefix("Threads:") {
+                    resources.threads = value.trim().parse().ok();
+                }
+            }
+        }
+
+        if let Ok(entries) = std::fs::read_dir("/proc/self/fd") {
+            resources.open_fds = Some(entries.count());
+        }
+
+
+        if let Ok(stat) = std::fs::read_to_string("/proc/self/stat") {
+            if let Some((_, rest)) = stat.rsplit_once(')') {
+                let fields: Vec<&str> = rest.split_whitespace().collect();
+                let ticks = |i: usize| {
+                    fields
+                        .get(i)
+                        .and_then(|v| v.parse::<u64>().ok())
+                        .map(|t| t as f64 / CLOCK_TICKS_PER_SECOND)
+                };
+                resources.cpu_user_seconds = ticks(11);
+                resources.cpu_system_seconds = ticks(12);
+            }
+        }
+
+        resources
+    }
+}
+
+fn parse_kb(value: &str) -> Option<u64> {
+    value
+        .trim()
+        .trim_end_matches("kB")
+        .trim()
+        .parse::<u64>()
+        .ok()
+        .map(|kb| kb * 1024)
+}
+
+/// Renders database and process gauges in the Prometheus text exposition
+/// format.
+pub fn render_prometheus(
+    stats: &crate::VelocityStats,
+    lifetime: &crate::LifetimeStats,
+    process: &ProcessResources,
+) -> String {
+    let cpu_seconds = process
+        .cpu_user_seconds
+        .zip(process.cpu_system_seconds)
+        .map(|(user, system)| user + system);
+
+    let metrics: [(&str, &str, &str, Option<f64>); 18] = [
+        ("velocity_memtable_entries", "gauge", "Entries in memtables.", Some(stats.memtable_entries as f64)),
+        ("velocity_memtable_bytes", "gauge", "Approximate memtable memory.", Some(stats.memtable_bytes as f64)),
+        ("velocity_memtable_arena_bytes", "gauge", "Memory reserved by memtable arenas.", Some(stats.memtable_arena.reserved_bytes as f64)),
+        ("velocity_memtable_arena_allocations", "gauge", "Allocations in the current memtable arenas.", Some(stats.memtable_arena.allocations as f64)),
+        ("velocity_cache_entries", "gauge", "Entries in read caches.", Some(stats.cache_entries as f64)),
+        ("velocity_cache_bytes", "gauge", "Approximate read cache memory.", Some(stats.cache_bytes as f64)),
+        ("velocity_cache_hits_total", "counter", "Read cache hits.", Some(stats.cache_hits as f64)),
+        ("velocity_cache_misses_total", "counter", "Read cache misses.", Some(stats.cache_misses as f64)),
+        ("velocity_sstables", "gauge", "SSTables on disk.", Some(stats.sstable_count as f64)),
+        ("velocity_sstable_bytes", "gauge", "Total SSTable size.", Some(stats.total_sstable_size as f64)),
+        ("velocity_compactions_total", "counter", "Lifetime compactions.", Some(lifetime.total_compactions as f64)),
+        ("velocity_flushes_total", "counter", "Lifetime memtable flushes.", Some(lifetime.total_flushes as f64)),
+        ("velocity_written_bytes_total", "counter", "Lifetime bytes written.", Some(lifetime.bytes_written as f64)),
+        ("process_resident_memory_bytes", "gauge", "Resident set size.", process.rss_bytes.map(|v| v as f64)),
+        ("process_virtual_memory_bytes", "gauge", "Virtual memory size.", process.virtual_bytes.map(|v| v as f64)),
+        ("process_open_fds", "gauge", "Open file descriptors.", process.open_fds.map(|v| v as f64)),
+        ("process_threads", "gauge", "OS threads.", process.threads.map(|v| v as f64)),
+        ("process_cpu_seconds_total", "counter", "User and system CPU time.", cpu_seconds),
+    ];
+
+    let mut out = String::new();
+    for (name, kind, help, value) in metrics {
+        if let Some(value) = value {
+            out.push_str(&format!(
+                "# HELP {name} {help}\n# TYPE {name} {kind}\n{name} {value}\n"
+            ));
+        }
+    }
+
+    out.push_str("# HELP velocity_reads_total Point reads by where they were answered.\n");
+    out.push_str("# TYPE velocity_reads_total counter\n");
+    for (source, histogram) in stats.read_sources.sources() {
+        out.push_str(&format!(
+            "velocity_reads_total{{{}}} {}\n",
+            source.labels(),
+            histogram.count
+        ));
+    }
+    out.push_str(
+        "# HELP velocity_read_latency_seconds Point read latency by where the read was answered.\n",
+    );
+    out.push_str("# TYPE velocity_read_latency_seconds summary\n");
+    for (source, histogram) in stats.read_sources.sources() {
+        let labels = source.labels();
+        for quantile in [0.5, 0.9, 0.99] {
+            out.push_str(&format!(
+                "velocity_read_latency_seconds{{{},quantile=\"{}\"}} {}\n",
+                labels,
+                quantile,
+                histogram.percentile(quantile).as_secs_f64()
+            ));
+        }
+        out.push_str(&format!(
+            "velocity_read_latency_seconds_sum{{{}}} {}\n",
+            labels,
+            histogram.total_ns as f64 / 1e9
+        ));
+        out.push_str(&format!(
+            "velocity_read_latency_seconds_count{{{}}} {}\n",
+            labels, histogram.count
+        ));
+    }
+    out
+}
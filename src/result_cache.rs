@@ -0,0 +1,297 @@
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, Weak};
+use std::time::{Duration, Instant};
+
+use serde::Serialize;
+
+use crate::sql::QueryResult;
+use crate::Velocity;
+
+// Writes remembered for reads still running when they happened. A read
+// that started before the oldest of them is not cached.
+const RECENT_WRITES: usize = 1024;
+
+/// The keys a cached result was read from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CachedKeys {
+    Keys(Vec<String>),
+    Prefix(String),
+    All,
+}
+
+impl CachedKeys {
+    pub fn contains(&self, key: &str) -> bool {
+        match self {
+            CachedKeys::Keys(keys) => keys.iter().any(|k| k == key),
+            CachedKeys::Prefix(prefix) => key.starts_with(prefix.as_str()),
+            CachedKeys::All => true,
+        }
+    }
+}
+
+/// SELECT results keyed by their normalized SQL, for one database. Each
+/// result remembers the snapshot sequence it was read at and is served
+/// until a later write touches one of its keys or it is older than
+/// `max_age`. Tables dropped because their data expired count as writes of
+/// their keys. The least recently used result is evicted once `capacity`
+/// is reached.
+pub struct ResultCache {
+    capacity: usize,
+    max_age: Duration,
+    entries: Mutex<Entries>,
+    listener: Mutex<Option<(Weak<Velocity>, u64)>>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+    invalidations: AtomicU64,
+}
+
+struct Entry {
+    result: QueryResult,
+    keys: CachedKeys,
+    sequence: u64,
+    stored_at: Instant,
+    last_use: u64,
+}
+
+#[derive(Default)]
+struct Entries {
+    by_sql: HashMap<String, Entry>,
+    // SQL text by last use, oldest first.
+    by_use: BTreeMap<u64, String>,
+    // SQL text by the keys and prefixes it was read from, so a write only
+    // looks at the results it can affect.
+    by_key: HashMap<String, HashSet<String>>,
+    by_prefix: HashMap<String, HashSet<String>>,
+    all_keys: HashSet<String>,
+    clock: u64,
+    recent_writes: VecDeque<(u64, String)>,
+    // Highest sequence of a write no longer in `recent_writes`.
+    forgotten_through: u64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ResultCacheStats {
+    pub entries: usize,
+    pub capacity: usize,
+    pub hits: u64,
+    pub misses: u64,
+    pub hit_rate: f64,
+    /// Results dropped because a write touched their keys.
+    pub invalidations: u64,
+}
+
+impl ResultCache {
+    pub fn new(capacity: usize, max_age: Duration) -> Self {
+        Self {
+            capacity,
+            max_age,
+            entries: Mutex::new(Entries::default()),
+            listener: Mutex::new(None),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+            invalidations: AtomicU64::new(0),
+        }
+    }
+
+    /// Watches the writes to `db`, the database the cached results come
+    /// from, until `detach` is called.
+    pub fn attach(self: &Arc<Self>, db: &Arc<Velocity>) {
+        let cache = Arc::downgrade(self);
+        let id = db.on_write(move |sequence, key| {
+            if let Some(cache) = cache.upgrade() {
+                cache.record_write(sequence, key);
+            }
+        });
+        if let Some((db, id)) = self
+            .listener
+            .lock()
+            .unwrap()
+            .replace((Arc::downgrade(db), id))
+        {
+            if let Some(db) = db.upgrade() {
+                db.remove_write_listener(id);
+            }
+        }
+    }
+
+    pub fn detach(&self) {
+        if let Some((db, id)) = self.listener.lock().unwrap().take() {
+            if let Some(db) = db.upgrade() {
+                db.remove_write_listener(id);
+            }
+        }
+    }
+
+    pub fn get(&self, sql: &str) -> Option<QueryResult> {
+        if self.capacity == 0 {
+            return None;
+        }
+        let mut guard = self.entries.lock().unwrap();
+        let entries = &mut *guard;
+        let tick = entries.next_tick();
+        let fresh = match entries.by_sql.get(sql) {
+            Some(entry) => entry.stored_at.elapsed() <= self.max_age,
+            None => false,
+        };
+        if !fresh {
+            entries.remove(sql);
+            self.misses.fetch_add(1, Ordering::Relaxed);
+            return None;
+        }
+        let entry = entries.by_sql.get_mut(sql)?;
+        let previous = std::mem::replace(&mut entry.last_use, tick);
+        entries.by_use.remove(&previous);
+        entries.by_use.insert(tick, sql.to_string());
+        self.hits.fetch_add(1, Ordering::Relaxed);
+        Some(entry.result.clone())
+    }
+
+    /// Caches `result`, read from `keys` at snapshot `sequence`, unless a
+    /// write after that sequence may have changed it.
+    pub fn insert(&self, sql: &str, keys: CachedKeys, sequence: u64, result: &QueryResult) {
+        if self.capacity == 0 {
+            return;
+        }
+        let mut guard = self.entries.lock().unwrap();
+        let entries = &mut *guard;
+        let overtaken = sequence < entries.forgotten_through
+            || entries
+                .recent_writes
+                .iter()
+                .any(|(written, key)| *written > sequence && keys.contains(key));
+        if overtaken {
+            return;
+        }
+        entries.remove(sql);
+        let tick = entries.next_tick();
+        entries.index(sql, &keys);
+        entries.by_sql.insert(
+            sql.to_string(),
+            Entry {
+                result: result.clone(),
+                keys,
+                sequence,
+                stored_at: Instant::now(),
+                last_use: tick,
+            },
+        );
+        entries.by_use.insert(tick, sql.to_string());
+        while entries.by_sql.len() > self.capacity {
+            let Some((_, oldest)) = entries.by_use.first_key_value() else {
+                break;
+            };
+            let oldest = oldest.clone();
+            entries.remove(&oldest);
+        }
+    }
+
+    fn record_write(&self, sequence: u64, key: &str) {
+        let mut guard = self.entries.lock().unwrap();
+        let entries = &mut *guard;
+        let candidates: Vec<String> = entries
+            .readers_of(key)
+            .filter(|sql| {
+                entries
+                    .by_sql
+                    .get(*sql)
+                    .is_some_and(|entry| entry.sequence < sequence)
+            })
+            .cloned()
+            .collect();
+        for sql in candidates {
+            if entries.remove(&sql).is_some() {
+                self.invalidations.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+        entries.recent_writes.push_back((sequence, key.to_string()));
+        if entries.recent_writes.len() > RECENT_WRITES {
+            if let Some((forgotten, _)) = entries.recent_writes.pop_front() {
+                entries.forgotten_through = entries.forgotten_through.max(forgotten);
+            }
+        }
+    }
+
+    pub fn stats(&self) -> ResultCacheStats {
+        let hits = self.hits.load(Ordering::Relaxed);
+        let misses = self.misses.load(Ordering::Relaxed);
+        ResultCacheStats {
+            entries: self.entries.lock().unwrap().by_sql.len(),
+            capacity: self.capacity,
+            hits,
+            misses,
+            hit_rate: if hits + misses == 0 {
+                0.0
+            } else {
+                hits as f64 / (hits + misses) as f64
+            },
+            invalidations: self.invalidations.load(Ordering::Relaxed),
+        }
+    }
+}
+
+impl Entries {
+    fn next_tick(&mut self) -> u64 {
+        self.clock += 1;
+        self.clock
+    }
+
+    fn index(&mut self, sql: &str, keys: &CachedKeys) {
+        match keys {
+            CachedKeys::Keys(keys) => {
+                for key in keys {
+                    self.by_key
+                        .entry(key.clone())
+                        .or_default()
+                        .insert(sql.to_string());
+                }
+            }
+            CachedKeys::Prefix(prefix) => {
+                self.by_prefix
+                    .entry(prefix.clone())
+                    .or_default()
+                    .insert(sql.to_string());
+            }
+            CachedKeys::All => {
+                self.all_keys.insert(sql.to_string());
+            }
+        }
+    }
+
+    fn remove(&mut self, sql: &str) -> Option<Entry> {
+        let entry = self.by_sql.remove(sql)?;
+        self.by_use.remove(&entry.last_use);
+        let unindex = |index: &mut HashMap<String, HashSet<String>>, key: &str| {
+            if let Some(sqls) = index.get_mut(key) {
+                sqls.remove(sql);
+                if sqls.is_empty() {
+                    index.remove(key);
+                }
+            }
+        };
+        match &entry.keys {
+            CachedKeys::Keys(keys) => keys.iter().for_each(|key| unindex(&mut self.by_key, key)),
+            CachedKeys::Prefix(prefix) => unindex(&mut self.by_prefix, prefix),
+            CachedKeys::All => {
+                self.all_keys.remove(sql);
+            }
+        }
+        Some(entry)
+    }
+
+    // The cached statements that read `key`: by the key itself, by any
+    // prefix of it, or by reading everything.
+    fn readers_of<'a>(&'a self, key: &'a str) -> impl Iterator<Item = &'a String> + 'a {
+        let prefixes = key
+            .char_indices()
+            .map(|(i, _)| i)
+            .chain([key.len()])
+            .filter_map(|end| self.by_prefix.get(&key[..end]));
+        self.by_key
+            .get(key)
+            .into_iter()
+            .chain(prefixes)
+            .flatten()
+            .chain(&self.all_keys)
+    }
+}
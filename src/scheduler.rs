@@ -0,0 +1,233 @@
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use serde::Serialize;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+const MAX_BATCH_DEFERRAL: Duration = Duration::from_secs(5);
+const DEFERRAL_STEP: Duration = Duration::from_millis(10);
+const CONGESTION_WINDOW: Duration = Duration::from_secs(1);
+const BATCH_SHED_RATIO: f64 = 0.8;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CommandPriority {
+    Interactive,
+    Batch,
+}
+
+impl CommandPriority {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            CommandPriority::Interactive => "interactive",
+            CommandPriority::Batch => "batch",
+        }
+    }
+}
+
+/// Whether a command only reads or also writes. Each kind has its own pool
+/// of execution slots, so a write burst cannot take every worker from reads.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CommandKind {
+    Read,
+    Write,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SchedulerStats {
+    pub interactive_latency_us: u64,
+    pub latency_target_us: u64,
+    pub congested: bool,
+    pub batch_in_flight: usize,
+    pub batch_deferred_total: u64,
+    pub in_flight: usize,
+    pub max_in_flight: usize,
+    pub shed_interactive_total: u64,
+    pub shed_batch_total: u64,
+    pub reads_in_flight: usize,
+    pub max_concurrent_reads: usize,
+    pub read_waits_total: u64,
+    pub writes_in_flight: usize,
+    pub max_concurrent_writes: usize,
+    pub write_waits_total: u64,
+}
+
+pub struct PriorityScheduler {
+    latency_target: Duration,
+    max_batch: usize,
+    epoch: Instant,
+    interactive_latency_us: AtomicU64,
+    last_interactive_ms: AtomicU64,
+    batch_deferred_total: AtomicU64,
+    batch_slots: Arc<Semaphore>,
+    max_in_flight: usize,
+    in_flight: Arc<AtomicUsize>,
+    shed_interactive_total: AtomicU64,
+    shed_batch_total: AtomicU64,
+    read_slots: Arc<Semaphore>,
+    max_reads: usize,
+    read_waits_total: AtomicU64,
+    write_slots: Arc<Semaphore>,
+    max_writes: usize,
+    write_waits_total: AtomicU64,
+}
+
+/// Counts a command as in flight until dropped.
+pub struct InFlightGuard {
+    in_flight: Arc<AtomicUsize>,
+}
+
+impl Drop for InFlightGuard {
+    fn drop(&mut self) {
+        self.in_flight.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+impl PriorityScheduler {
+    pub fn new(latency_target: Duration, max_concurrent_batch: usize) -> Self {
+        let max_batch = max_concurrent_batch.max(1);
+        Self {
+            latency_target,
+            max_batch,
+            epoch: Instant::now(),
+            interactive_latency_us: AtomicU64::new(0),
+            last_interactive_ms: AtomicU64::new(0),
+            batch_deferred_total: AtomicU64::new(0),
+            batch_slots: Arc::new(Semaphore::new(max_batch)),
+            max_in_flight: usize::MAX,
+            in_flight: Arc::new(AtomicUsize::new(0)),
+            shed_interactive_total: AtomicU64::new(0),
+            shed_batch_total: AtomicU64::new(0),
+            read_slots: Arc::new(Semaphore::new(Semaphore::MAX_PERMITS)),
+            max_reads: Semaphore::MAX_PERMITS,
+            read_waits_total: AtomicU64::new(0),
+            write_slots: Arc::new(Semaphore::new(Semaphore::MAX_PERMITS)),
+            max_writes: Semaphore::MAX_PERMITS,
+            write_waits_total: AtomicU64::new(0),
+        }
+    }
+
+    pub fn with_max_in_flight(mut self, max_in_flight: usize) -> Self {
+        self.max_in_flight = max_in_flight.max(1);
+        self
+    }
+
+    pub fn with_concurrency_limits(mut self, max_reads: usize, max_writes: usize) -> Self {
+        self.max_reads = max_reads.max(1);
+        self.read_slots = Arc::new(Semaphore::new(self.max_reads));
+        self.max_writes = max_writes.max(1);
+        self.write_slots = Arc::new(Semaphore::new(self.max_writes));
+        self
+    }
+
+    /// Admits a command into the in-flight set, or sheds it when the server
+    /// is saturated. Batch commands are shed first, once the in-flight count
+    /// reaches `BATCH_SHED_RATIO` of the limit.
+    pub fn try_enter(&self, priority: CommandPriority) -> Option<InFlightGuard> {
+        let limit = match priority {
+            CommandPriority::Interactive => self.max_in_flight,
+            CommandPriority::Batch => {
+                ((self.max_in_flight as f64 * BATCH_SHED_RATIO) as usize).max(1)
+            }
+        };
+        let admitted = self
+            .in_flight
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |current| {
+                (current < limit).then_some(current + 1)
+            })
+            .is_ok();
+        if !admitted {
+            match priority {
+                CommandPriority::Interactive => &self.shed_interactive_total,
+                CommandPriority::Batch => &self.shed_batch_total,
+            }
+            .fetch_add(1, Ordering::Relaxed);
+            return None;
+        }
+        Some(InFlightGuard {
+            in_flight: self.in_flight.clone(),
+        })
+    }
+
+    pub async fn admit(&self, priority: CommandPriority) -> Option<OwnedSemaphorePermit> {
+        if priority == CommandPriority::Interactive {
+            return None;
+        }
+
+        let started = Instant::now();
+        let mut step = DEFERRAL_STEP;
+        let mut deferred = false;
+        while self.is_congested() && started.elapsed() < MAX_BATCH_DEFERRAL {
+            deferred = true;
+            tokio::time::sleep(step).await;
+            step = (step * 2).min(Duration::from_millis(250));
+        }
+        if deferred {
+            self.batch_deferred_total.fetch_add(1, Ordering::Relaxed);
+        }
+
+        self.batch_slots.clone().acquire_owned().await.ok()
+    }
+
+    /// Waits for an execution slot in the pool of `kind`.
+    pub async fn acquire(&self, kind: CommandKind) -> Option<OwnedSemaphorePermit> {
+        let (slots, waits) = match kind {
+            CommandKind::Read => (&self.read_slots, &self.read_waits_total),
+            CommandKind::Write => (&self.write_slots, &self.write_waits_total),
+        };
+        if let Ok(permit) = slots.clone().try_acquire_owned() {
+            return Some(permit);
+        }
+        waits.fetch_add(1, Ordering::Relaxed);
+        slots.clone().acquire_owned().await.ok()
+    }
+
+    pub fn record(&self, priority: CommandPriority, elapsed: Duration) {
+        if priority != CommandPriority::Interactive {
+            return;
+        }
+
+        let sample = elapsed.as_micros() as u64;
+        self.last_interactive_ms
+            .store(self.epoch.elapsed().as_millis() as u64, Ordering::Relaxed);
+        let _ = self
+            .interactive_latency_us
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |current| {
+                if current == 0 {
+                    Some(sample)
+                } else {
+                    Some(current - current / 8 + sample / 8)
+                }
+            });
+    }
+
+    pub fn is_congested(&self) -> bool {
+        let idle_ms = (self.epoch.elapsed().as_millis() as u64)
+            .saturating_sub(self.last_interactive_ms.load(Ordering::Relaxed));
+        idle_ms <= CONGESTION_WINDOW.as_millis() as u64
+            && self.interactive_latency_us.load(Ordering::Relaxed)
+                > self.latency_target.as_micros() as u64
+    }
+
+    pub fn stats(&self) -> SchedulerStats {
+        SchedulerStats {
+            interactive_latency_us: self.interactive_latency_us.load(Ordering::Relaxed),
+            latency_target_us: self.latency_target.as_micros() as u64,
+            congested: self.is_congested(),
+            batch_in_flight: self.max_batch - self.batch_slots.available_permits(),
+            batch_deferred_total: self.batch_deferred_total.load(Ordering::Relaxed),
+            in_flight: self.in_flight.load(Ordering::Relaxed),
+            max_in_flight: self.max_in_flight,
+            shed_interactive_total: self.shed_interactive_total.load(Ordering::Relaxed),
+            shed_batch_total: self.shed_batch_total.load(Ordering::Relaxed),
+            reads_in_flight: self.max_reads - self.read_slots.available_permits(),
+            max_concurrent_reads: self.max_reads,
+            read_waits_total: self.read_waits_total.load(Ordering::Relaxed),
+            writes_in_flight: self.max_writes - self.write_slots.available_permits(),
+            max_concurrent_writes: self.max_writes,
+            write_waits_total: self.write_waits_total.load(Ordering::Relaxed),
+        }
+    }
+}
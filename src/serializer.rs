@@ -0,0 +1,129 @@
+//! Encodings for the typed values of `VelocityClient::put_typed` and
+//! `get_typed`.
+//!
+//! A client (or pool) holds one serializer and uses it for every typed call,
+//! so an application that sets it in one place reads back what it wrote.
+//! Values are stored as the serializer's bytes; nothing records which
+//! serializer wrote a key, so every client sharing keys has to use the same
+//! one.
+
+use bincode::Options;
+
+use crate::{VeloError, VeloResult};
+
+/// Callback handed to `ValueSerializer::decode`, which deserializes the
+/// caller's type from the erased deserializer.
+pub type DecodeFn<'a> =
+    dyn FnMut(&mut dyn erased_serde::Deserializer<'_>) -> Result<(), erased_serde::Error> + 'a;
+
+/// Turns serde values into the bytes stored under a key and back. The
+/// methods work on erased values so a client can hold any serializer as a
+/// trait object.
+pub trait ValueSerializer: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    fn encode(&self, value: &dyn erased_serde::Serialize) -> VeloResult<Vec<u8>>;
+
+    /// Calls `decode` once with a deserializer over `bytes`.
+    fn decode(&self, bytes: &[u8], decode: &mut DecodeFn<'_>) -> VeloResult<()>;
+}
+
+fn encode_error(serializer: &str, e: impl std::fmt::Display) -> VeloError {
+    VeloError::InvalidOperation(format!("Failed to encode {} value: {}", serializer, e))
+}
+
+fn decode_error(serializer: &str, e: impl std::fmt::Display) -> VeloError {
+    VeloError::CorruptedData(format!("Failed to decode {} value: {}", serializer, e))
+}
+
+/// JSON text; readable from SQL and Studio like any other JSON value.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct JsonSerializer;
+
+impl ValueSerializer for JsonSerializer {
+    fn name(&self) -> &'static str {
+        "json"
+    }
+
+    fn encode(&self, value: &dyn erased_serde::Serialize) -> VeloResult<Vec<u8>> {
+        serde_json::to_vec(value).map_err(|e| encode_error(self.name(), e))
+    }
+
+    fn decode(&self, bytes: &[u8], decode: &mut DecodeFn<'_>) -> VeloResult<()> {
+        let mut deserializer = serde_json::Deserializer::from_slice(bytes);
+        let mut erased = <dyn erased_serde::Deserializer>::erase(&mut deserializer);
+        decode(&mut erased).map_err(|e| decode_error(self.name(), e))?;
+        deserializer.end().map_err(|e| decode_error(self.name(), e))
+    }
+}
+
+/// MessagePack with struct fields written by name, so fields can be added
+/// or reordered without breaking values already stored.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct MessagePackSerializer;
+
+impl ValueSerializer for MessagePackSerializer {
+    fn name(&self) -> &'static str {
+        "messagepack"
+    }
+
+    fn encode(&self, value: &dyn erased_serde::Serialize) -> VeloResult<Vec<u8>> {
+        rmp_serde::to_vec_named(value).map_err(|e| encode_error(self.name(), e))
+    }
+
+    fn decode(&self, bytes: &[u8], decode: &mut DecodeFn<'_>) -> VeloResult<()> {
+        let mut deserializer = rmp_serde::Deserializer::from_read_ref(bytes);
+        let mut erased = <dyn erased_serde::Deserializer>::erase(&mut deserializer);
+        decode(&mut erased).map_err(|e| decode_error(self.name(), e))
+    }
+}
+
+/// Bincode's compact fixed-width encoding. The smallest of the three, but
+/// values only read back into the same type with the same field order.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct BincodeSerializer;
+
+impl BincodeSerializer {
+    fn options() -> impl Options {
+        bincode::DefaultOptions::new().with_fixint_encoding()
+    }
+}
+
+impl ValueSerializer for BincodeSerializer {
+    fn name(&self) -> &'static str {
+        "bincode"
+    }
+
+    fn encode(&self, value: &dyn erased_serde::Serialize) -> VeloResult<Vec<u8>> {
+        Self::options()
+            .serialize(value)
+            .map_err(|e| encode_error(self.name(), e))
+    }
+
+    fn decode(&self, bytes: &[u8], decode: &mut DecodeFn<'_>) -> VeloResult<()> {
+        let mut deserializer = bincode::Deserializer::from_slice(bytes, Self::options());
+        let mut erased = <dyn erased_serde::Deserializer>::erase(&mut deserializer);
+        decode(&mut erased).map_err(|e| decode_error(self.name(), e))
+    }
+}
+
+/// Encodes `value` with `serializer`.
+pub fn to_bytes<T: serde::Serialize + ?Sized>(
+    serializer: &dyn ValueSerializer,
+    value: &T,
+) -> VeloResult<Vec<u8>> {
+    serializer.encode(&value)
+}
+
+/// Decodes a `T` that `serializer` encoded.
+pub fn from_bytes<T: serde::de::DeserializeOwned>(
+    serializer: &dyn ValueSerializer,
+    bytes: &[u8],
+) -> VeloResult<T> {
+    let mut value = None;
+    serializer.decode(bytes, &mut |deserializer| {
+        value = Some(erased_serde::deserialize::<T>(deserializer)?);
+        Ok(())
+    })?;
+    value.ok_or_else(|| decode_error(serializer.name(), "no value decoded"))
+}
@@ -1,27 +1,52 @@
-use std::collections::HashMap;
-use std::net::SocketAddr;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::fs::File;
+use std::io::BufReader;
+use std::net::{IpAddr, SocketAddr};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, AtomicU8, Ordering};
 use std::sync::Arc;
-use std::time::{Duration, Instant};
+use std::time::{Duration, Instant, SystemTime};
 
 use argon2::password_hash::{rand_core::OsRng, SaltString};
-use argon2::{Argon2, PasswordHash, PasswordHasher, PasswordVerifier};
+use argon2::{Argon2, PasswordHasher};
 use bytes::{Buf, BufMut, BytesMut};
 use crc32fast::Hasher as CrcHasher;
-use rustls::{Certificate, PrivateKey, ServerConfig as TlsServerConfig};
-use sha2::{Digest, Sha256};
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
-use tokio::net::{TcpListener, TcpStream};
-use tokio::sync::{RwLock, Semaphore};
+use rustls::server::{AllowAnyAnonymousOrAuthenticatedClient, AllowAnyAuthenticatedClient};
+use rustls::{Certificate, PrivateKey, RootCertStore};
+use serde::{Deserialize, Serialize};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, DuplexStream};
+use tokio::net::TcpListener;
+use tokio::sync::{mpsc, oneshot, Notify, RwLock, Semaphore};
 use tokio::time::timeout;
-use tokio_rustls::{server::TlsStream, TlsAcceptor};
-
-use crate::sql::SqlEngine;
-use crate::{VeloError, VeloResult, Velocity, VelocityConfig};
+use tokio_rustls::TlsAcceptor;
+use x509_parser::prelude::{FromDer, GeneralName, X509Certificate};
+
+use crate::access::{AccessConfig, AccessControl, AccessList, Cidr};
+use crate::auth::{AuthConfig, AuthRegistry};
+use crate::replication::CHUNK_SIZE;
+use crate::identity::{from_hex, ServerIdentity};
+use crate::dry_run::strip_dry_run;
+use crate::idempotency::{split_idempotency_key, Claim, IdempotencyCache};
+use crate::request_id::{self, split_request_id};
+use crate::resources::ProcessResources;
+use crate::result_cache::{ResultCache, ResultCacheStats};
+use crate::scheduler::{CommandKind, CommandPriority, PriorityScheduler};
+use crate::sql::{is_write_statement, QueryResult, SqlEngine};
+use crate::statement_cache::{StatementCache, StatementCacheStats};
+use crate::usage::{self, SessionSummary};
+use crate::users::{load_user_meta, save_user_meta, PasswordPolicy};
+use crate::{
+    BatchOp, CachePolicy, QuarantinedTable, RecoveryCallback, RecoveryPhase, RecoveryProgress, VeloError,
+    VeloResult, Velocity,
+};
 
 
 const MAGIC: u32 = 0x56454C4F;
 const VERSION: u8 = 0x01;
 
+static CONNECTION_IDS: AtomicU64 = AtomicU64::new(1);
+
 
 #[repr(u8)]
 #[derive(Debug, Clone, Copy, PartialEq)]
@@ -37,6 +62,10 @@ pub enum MessageType {
     Command = 0x10,
     Response = 0x11,
     Error = 0x12,
+    BatchCommand = 0x13,
+    BatchWrite = 0x14,
+    ServerBusy = 0x15,
+    Get = 0x16,
 
 
     Ping = 0x20,
@@ -55,6 +84,10 @@ impl From<u8> for MessageType {
             0x10 => MessageType::Command,
             0x11 => MessageType::Response,
             0x12 => MessageType::Error,
+            0x13 => MessageType::BatchCommand,
+            0x14 => MessageType::BatchWrite,
+            0x15 => MessageType::ServerBusy,
+            0x16 => MessageType::Get,
             0x20 => MessageType::Ping,
             0x21 => MessageType::Pong,
             0x22 => MessageType::Stats,
@@ -95,6 +128,14 @@ impl VelocityMessage {
         buffer
     }
 
+    pub fn frame_len(header: &[u8]) -> Option<usize> {
+        if header.len() < 10 {
+            return None;
+        }
+        let payload_len = u32::from_le_bytes([header[6], header[7], header[8], header[9]]) as usize;
+        Some(14 + payload_len)
+    }
+
     pub fn decode(data: &[u8]) -> VeloResult<Self> {
         if data.len() < 14 {
             return Err(VeloError::InvalidOperation("Message too short".to_string()));
@@ -154,6 +195,172 @@ impl VelocityMessage {
 }
 
 
+fn queued_frames(buffer: &[u8], limit: usize) -> usize {
+    let mut pos = 0;
+    let mut count = 0;
+    while count < limit {
+        match VelocityMessage::frame_len(&buffer[pos..]) {
+            Some(len) if pos + len <= buffer.len() => {
+                pos += len;
+                count += 1;
+            }
+            _ => break,
+        }
+    }
+    count
+}
+
+/// Commands other than INSERT, UPDATE and DELETE that change stored data or
+/// the set of databases; a read-only server rejects them.
+fn changes_data(command: &str) -> bool {
+    let upper = command.trim().trim_end_matches(';').to_uppercase();
+    [
+        "CREATE DATABASE",
+        "DROP DATABASE",
+        "ALTER DATABASE",
+        "RESTORE KEY",
+        "PURGE TRASH",
+        "PUT TEMPORARY",
+        "REPLICA CHECKPOINT",
+        "REPLICA FORGET",
+    ]
+    .iter()
+    .any(|prefix| upper.starts_with(prefix))
+        || (upper.starts_with("COMPACT DATABASE") && upper.ends_with(" FULL"))
+}
+
+/// The key and value of `PUT TEMPORARY '<key>' '<value>'`. Quotes inside
+/// either literal are doubled, as in SQL.
+fn parse_put_temporary(command: &str) -> Option<(String, String)> {
+    let command = command.trim().trim_end_matches(';');
+    let rest = command.get("PUT TEMPORARY".len()..)?;
+    let mut literals = Vec::new();
+    let mut chars = rest.trim().chars().peekable();
+    while let Some(c) = chars.next() {
+        if c.is_whitespace() {
+            continue;
+        }
+        if c != '\'' {
+            return None;
+        }
+        let mut literal = String::new();
+        loop {
+            match chars.next()? {
+                '\'' if chars.peek() == Some(&'\'') => {
+                    chars.next();
+                    literal.push('\'');
+                }
+                '\'' => break,
+                c => literal.push(c),
+            }
+        }
+        literals.push(literal);
+    }
+    let [key, value]: [String; 2] = literals.try_into().ok()?;
+    Some((key, value))
+}
+
+/// A stable id for an API key that does not reveal the key: the first 16
+/// hex digits of its SHA-256.
+fn api_key_id(key: &str) -> String {
+    use sha2::{Digest, Sha256};
+    let digest = Sha256::digest(key.as_bytes());
+    crate::identity::to_hex(&digest[..8])
+}
+
+const BATCH_OP_PUT: u8 = 0x01;
+const BATCH_OP_DELETE: u8 = 0x02;
+
+pub fn encode_batch_ops(ops: &[BatchOp]) -> Vec<u8> {
+    let mut buffer = Vec::new();
+    buffer.put_u32_le(ops.len() as u32);
+    for op in ops {
+        let (tag, key, value) = match op {
+            BatchOp::Put { key, value } => (BATCH_OP_PUT, key, Some(value)),
+            BatchOp::Delete { key } => (BATCH_OP_DELETE, key, None),
+        };
+        buffer.put_u8(tag);
+        buffer.put_u32_le(key.len() as u32);
+        buffer.extend_from_slice(key.as_bytes());
+        if let Some(value) = value {
+            buffer.put_u32_le(value.len() as u32);
+            buffer.extend_from_slice(value);
+        }
+    }
+    buffer
+}
+
+pub fn decode_batch_ops(payload: &[u8]) -> VeloResult<Vec<BatchOp>> {
+    let malformed = || VeloError::InvalidOperation("Malformed batch payload".to_string());
+    let mut pos = 0usize;
+    let mut take = |len: usize| -> VeloResult<&[u8]> {
+        let bytes = payload.get(pos..pos + len).ok_or_else(malformed)?;
+        pos += len;
+        Ok(bytes)
+    };
+
+    let count = u32::from_le_bytes(take(4)?.try_into().unwrap()) as usize;
+    let mut ops = Vec::with_capacity(count.min(4096));
+    for _ in 0..count {
+        let tag = take(1)?[0];
+        let key_len = u32::from_le_bytes(take(4)?.try_into().unwrap()) as usize;
+        let key = String::from_utf8(take(key_len)?.to_vec()).map_err(|_| malformed())?;
+        ops.push(match tag {
+            BATCH_OP_PUT => {
+                let value_len = u32::from_le_bytes(take(4)?.try_into().unwrap()) as usize;
+                BatchOp::Put {
+                    key,
+                    value: take(value_len)?.to_vec(),
+                }
+            }
+            BATCH_OP_DELETE => BatchOp::Delete { key },
+            other => {
+                return Err(VeloError::InvalidOperation(format!(
+                    "Unknown batch operation {:#04x}",
+                    other
+                )))
+            }
+        });
+    }
+    if pos != payload.len() {
+        return Err(malformed());
+    }
+    Ok(ops)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BatchOpStatus {
+    Ok,
+    Error,
+    NotApplied,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BatchOpResult {
+    pub status: BatchOpStatus,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BatchWriteResult {
+    pub applied: bool,
+    pub results: Vec<BatchOpResult>,
+}
+
+/// Payload of a `Pong`: the server's clocks when it answered and how long it
+/// took to answer. Servers before this field existed send an empty `Pong`.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct ServerClock {
+    /// Microseconds since the Unix epoch.
+    pub wall_clock_us: i64,
+    /// Microseconds since the server started; unaffected by clock changes.
+    pub monotonic_us: u64,
+    pub processing_us: u64,
+}
+
+
 #[derive(Debug, Clone)]
 pub struct ServerConfig {
     pub bind_address: SocketAddr,
@@ -163,11 +370,76 @@ pub struct ServerConfig {
     pub enable_tls: bool,
     pub cert_path: Option<String>,
     pub key_path: Option<String>,
+    pub client_ca_path: Option<String>,
+    pub require_client_cert: bool,
+    pub client_cert_users: HashMap<String, String>,
+    pub tenants: HashMap<String, String>,
+    pub identity_path: Option<PathBuf>,
     pub users: HashMap<String, String>,
+    /// Principals from other realms (or certificate mappings) that may run
+    /// administration commands. Accounts in `users` always may.
+    pub admin_users: Vec<String>,
+    pub password_policy: PasswordPolicy,
+    pub auth: AuthConfig,
+    pub access: AccessConfig,
     pub audit_log_path: String,
     pub audit_logging: bool,
     pub auth_ban_duration: Duration,
     pub max_auth_failures: u32,
+    pub interactive_latency_target: Duration,
+    pub max_concurrent_batch_commands: usize,
+    pub idempotency_window: Duration,
+    pub idempotency_max_entries: usize,
+    pub max_in_flight_commands: usize,
+    /// Commands that only read and commands that write run in separate
+    /// pools of this many slots each.
+    pub max_concurrent_reads: usize,
+    pub max_concurrent_writes: usize,
+    pub max_pipelined_commands: usize,
+    /// Larger frames close the connection before their payload is read.
+    pub max_frame_bytes: usize,
+    /// Commands that take at least this long are logged with their request
+    /// ID; zero turns the log off.
+    pub slow_command_threshold: Duration,
+    /// Parsed statements kept per database; 0 turns the cache off.
+    pub statement_cache_size: usize,
+    /// Bytes of SQL text the statement cache of each database may hold.
+    pub statement_cache_bytes: usize,
+    /// SELECT results kept per database; 0 (the default) turns the cache
+    /// off.
+    pub result_cache_size: usize,
+    /// How long a cached result is served when no write invalidates it.
+    pub result_cache_max_age: Duration,
+    /// Returns values holding a JSON object or array as structured JSON
+    /// rather than strings.
+    pub json_values: bool,
+    /// Rejects every command that changes data, so a restored copy or
+    /// snapshot can be served safely.
+    pub read_only: bool,
+    pub listeners: Vec<ListenerConfig>,
+    pub unix_socket_path: Option<PathBuf>,
+    pub unix_socket_mode: u32,
+}
+
+impl ServerConfig {
+    /// The primary listener described by the top-level fields, followed by
+    /// the additional ones in `listeners`.
+    pub fn listener_configs(&self) -> Vec<ListenerConfig> {
+        let primary = ListenerConfig {
+            bind_address: self.bind_address,
+            enable_tls: self.enable_tls,
+            cert_path: self.cert_path.clone(),
+            key_path: self.key_path.clone(),
+            client_ca_path: self.client_ca_path.clone(),
+            require_client_cert: self.require_client_cert,
+            allow_password_auth: true,
+            allow_api_keys: true,
+            access: AccessConfig::default(),
+        };
+        std::iter::once(primary)
+            .chain(self.listeners.iter().cloned())
+            .collect()
+    }
 }
 
 impl Default for ServerConfig {
@@ -186,23 +458,97 @@ impl Default for ServerConfig {
             enable_tls: false,
             cert_path: None,
             key_path: None,
+            client_ca_path: None,
+            require_client_cert: false,
+            client_cert_users: HashMap::new(),
+            tenants: HashMap::new(),
+            identity_path: None,
             users,
+            admin_users: Vec::new(),
+            password_policy: PasswordPolicy::default(),
+            auth: AuthConfig::default(),
+            access: AccessConfig::default(),
             audit_log_path: "./velocitydb_audit.log".to_string(),
             audit_logging: true,
             auth_ban_duration: Duration::from_secs(300),
             max_auth_failures: 5,
+            interactive_latency_target: Duration::from_millis(50),
+            max_concurrent_batch_commands: 4,
+            idempotency_window: Duration::from_secs(600),
+            idempotency_max_entries: 100_000,
+            max_in_flight_commands: 1024,
+            max_concurrent_reads: 64,
+            max_concurrent_writes: 16,
+            max_pipelined_commands: 64,
+            max_frame_bytes: 64 * 1024 * 1024,
+            slow_command_threshold: Duration::from_secs(1),
+            statement_cache_size: 256,
+            statement_cache_bytes: 1024 * 1024,
+            result_cache_size: 0,
+            result_cache_max_age: Duration::from_secs(60),
+            json_values: false,
+            read_only: false,
+            listeners: Vec::new(),
+            unix_socket_path: None,
+            unix_socket_mode: 0o660,
         }
     }
 }
 
+fn default_allow_auth() -> bool {
+    true
+}
+
+/// An address the server accepts connections on. Each listener carries its
+/// own TLS settings and may restrict which login methods it accepts, e.g. a
+/// plaintext localhost port for Studio next to a TLS-only public one.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ListenerConfig {
+    pub bind_address: SocketAddr,
+    #[serde(default)]
+    pub enable_tls: bool,
+    #[serde(default)]
+    pub cert_path: Option<String>,
+    #[serde(default)]
+    pub key_path: Option<String>,
+    #[serde(default)]
+    pub client_ca_path: Option<String>,
+    #[serde(default)]
+    pub require_client_cert: bool,
+    #[serde(default = "default_allow_auth")]
+    pub allow_password_auth: bool,
+    #[serde(default = "default_allow_auth")]
+    pub allow_api_keys: bool,
+    /// Allow and deny lists applied on top of `[server.access]` to
+    /// connections through this listener only.
+    #[serde(default)]
+    pub access: AccessConfig,
+}
+
 #[derive(Debug)]
 struct ClientState {
     authenticated: bool,
     username: Option<String>,
+    // Set for sessions that logged in with an API key, whose username is
+    // always "apikey".
+    api_key_id: Option<String>,
     last_activity: Instant,
     command_count: u64,
     rate_limiter: RateLimiter,
     current_db: String,
+    tenant: Option<String>,
+    // The listener the connection came in on, as configured when it was
+    // accepted.
+    listener: Option<ListenerConfig>,
+    connected_at: Instant,
+    started_at: chrono::DateTime<chrono::Utc>,
+    bytes_in: u64,
+    bytes_out: u64,
+    error_count: u64,
+    rate_limited: u64,
+    // Keys written with PUT TEMPORARY, by database and key, with the value
+    // this session wrote.
+    temporary_keys: HashMap<(String, String), Vec<u8>>,
 }
 
 impl ClientState {
@@ -210,10 +556,45 @@ impl ClientState {
         Self {
             authenticated: false,
             username: None,
+            api_key_id: None,
             last_activity: Instant::now(),
             command_count: 0,
             rate_limiter: RateLimiter::new(rate_limit),
             current_db: "default".to_string(),
+            tenant: None,
+            listener: None,
+            connected_at: Instant::now(),
+            started_at: chrono::Utc::now(),
+            bytes_in: 0,
+            bytes_out: 0,
+            error_count: 0,
+            rate_limited: 0,
+            temporary_keys: HashMap::new(),
+        }
+    }
+
+    /// Who the session acts as: the API key for key logins, otherwise the
+    /// username.
+    fn principal(&self) -> Option<String> {
+        match &self.api_key_id {
+            Some(id) => Some(format!("apikey:{}", id)),
+            None => self.username.clone(),
+        }
+    }
+
+    fn summary(&self, peer: Peer) -> SessionSummary {
+        SessionSummary {
+            user: self.principal(),
+            tenant: self.tenant.clone(),
+            database: self.current_db.clone(),
+            addr: peer.to_string(),
+            started_at: self.started_at.to_rfc3339(),
+            duration_ms: self.connected_at.elapsed().as_millis() as u64,
+            commands: self.command_count,
+            bytes_in: self.bytes_in,
+            bytes_out: self.bytes_out,
+            errors: self.error_count,
+            rate_limited: self.rate_limited,
         }
     }
 }
@@ -253,256 +634,1382 @@ impl RateLimiter {
     }
 }
 
-use crate::addon::DatabaseManager;
+use crate::addon::{DatabaseManager, ManagerEvent, SYSTEM_DATABASE};
+
+
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize)]
+pub enum ServerState {
+    Starting = 0,
+    Recovering = 1,
+    Ready = 2,
+    Draining = 3,
+}
+
+#[derive(Clone)]
+pub struct StartupStatus {
+    state: Arc<AtomicU8>,
+    progress: Arc<std::sync::Mutex<Option<RecoveryProgress>>>,
+    quarantined: Arc<std::sync::Mutex<Vec<QuarantinedTable>>>,
+}
+
+impl StartupStatus {
+    pub fn new() -> Self {
+        Self {
+            state: Arc::new(AtomicU8::new(ServerState::Starting as u8)),
+            progress: Arc::new(std::sync::Mutex::new(None)),
+            quarantined: Arc::new(std::sync::Mutex::new(Vec::new())),
+        }
+    }
+
+    pub fn state(&self) -> ServerState {
+        match self.state.load(Ordering::Acquire) {
+            0 => ServerState::Starting,
+            1 => ServerState::Recovering,
+            2 => ServerState::Ready,
+            _ => ServerState::Draining,
+        }
+    }
+
+    pub fn set_state(&self, state: ServerState) {
+        self.state.store(state as u8, Ordering::Release);
+    }
+
+    pub fn progress(&self) -> Option<RecoveryProgress> {
+        self.progress.lock().unwrap().clone()
+    }
+
+    /// Tables the startup verification set aside, for the health endpoint.
+    pub fn quarantined(&self) -> Vec<QuarantinedTable> {
+        self.quarantined.lock().unwrap().clone()
+    }
+
+    pub fn record_quarantined(&self, tables: &[QuarantinedTable]) {
+        self.quarantined.lock().unwrap().extend_from_slice(tables);
+    }
+
+
+    pub fn recovery_callback(&self) -> RecoveryCallback {
+        let status = self.clone();
+        Arc::new(move |progress: &RecoveryProgress| {
+            if progress.phase != RecoveryPhase::Complete && status.state() == ServerState::Starting {
+                status.set_state(ServerState::Recovering);
+            }
+            *status.progress.lock().unwrap() = Some(progress.clone());
+        })
+    }
+}
+
+impl Default for StartupStatus {
+    fn default() -> Self {
+        Self::new()
+    }
+}
 
 
 pub struct VelocityServer {
     db_manager: Arc<DatabaseManager>,
 
-    config: ServerConfig,
-    server_fingerprint: String,
+    config: Arc<ServerConfig>,
+    identity: Arc<std::sync::RwLock<LoadedIdentity>>,
+    listeners: Arc<std::sync::RwLock<Vec<ListenerConfig>>>,
+    tls_acceptors: Arc<std::sync::RwLock<Vec<Option<TlsAcceptor>>>>,
+    users: Arc<std::sync::RwLock<HashMap<String, String>>>,
+    auth: Arc<AuthRegistry>,
+    access: Arc<AccessControl>,
     connection_semaphore: Arc<Semaphore>,
-    clients: Arc<RwLock<HashMap<SocketAddr, ClientState>>>,
+    clients: Arc<RwLock<HashMap<u64, ClientState>>>,
+    scheduler: Arc<PriorityScheduler>,
+    idempotency: Arc<IdempotencyCache>,
+    startup: StartupStatus,
+    drain_request: Arc<Notify>,
+    // Set while `serve` runs, which applies listener changes.
+    listener_updates: Arc<std::sync::Mutex<Option<mpsc::UnboundedSender<ListenerUpdate>>>>,
+    listener_update_lock: Arc<tokio::sync::Mutex<()>>,
+    statement_caches: Arc<std::sync::Mutex<HashMap<String, Arc<StatementCache>>>>,
+    result_caches: Arc<std::sync::Mutex<HashMap<String, Arc<ResultCache>>>>,
+    started: Instant,
 }
 
 impl VelocityServer {
     pub fn new(db_manager: Arc<DatabaseManager>, config: ServerConfig) -> VeloResult<Self> {
-
-        let server_key = b"velocity_server_key_placeholder";
-        let mut hasher = Sha256::new();
-        hasher.update(server_key);
-        let server_fingerprint = format!("{:x}", hasher.finalize());
+        let identity = LoadedIdentity::load(config.identity_path.as_ref())?;
+        let listeners = config.listener_configs();
+        let tls_acceptors = load_tls_acceptors(&listeners)?;
+        load_listener_access(&listeners)?;
+        let users = Arc::new(std::sync::RwLock::new(config.users.clone()));
+        let auth = AuthRegistry::from_config(&config.auth, users.clone())?;
+        let access = AccessControl::new(
+            &config.access,
+            config.max_auth_failures,
+            config.auth_ban_duration,
+        )?;
+
+        let statement_caches: Arc<std::sync::Mutex<HashMap<String, Arc<StatementCache>>>> =
+            Arc::new(std::sync::Mutex::new(HashMap::new()));
+        db_manager.on_event({
+            let statement_caches = statement_caches.clone();
+            move |event: &ManagerEvent| {
+                let mut caches = statement_caches.lock().unwrap();
+                match event {
+                    ManagerEvent::DatabaseDropped { name } => {
+                        caches.remove(name);
+                    }
+                    ManagerEvent::DatabaseCreated { name, .. }
+                    | ManagerEvent::DatabaseAltered { name, .. } => {
+                        if let Some(cache) = caches.get(name) {
+                            cache.clear();
+                        }
+                    }
+                    ManagerEvent::ConfigReloaded => caches.values().for_each(|cache| cache.clear()),
+                    _ => {}
+                }
+            }
+        });
+        // Result caches watch one database's writes, so they are dropped
+        // rather than emptied; the next query attaches a new one.
+        let result_caches: Arc<std::sync::Mutex<HashMap<String, Arc<ResultCache>>>> =
+            Arc::new(std::sync::Mutex::new(HashMap::new()));
+        db_manager.on_event({
+            let result_caches = result_caches.clone();
+            move |event: &ManagerEvent| {
+                let mut caches = result_caches.lock().unwrap();
+                let dropped: Vec<Arc<ResultCache>> = match event {
+                    ManagerEvent::DatabaseDropped { name }
+                    | ManagerEvent::DatabaseCreated { name, .. }
+                    | ManagerEvent::DatabaseAltered { name, .. } => {
+                        caches.remove(name).into_iter().collect()
+                    }
+                    ManagerEvent::ConfigReloaded => caches.drain().map(|(_, cache)| cache).collect(),
+                    _ => Vec::new(),
+                };
+                dropped.iter().for_each(|cache| cache.detach());
+            }
+        });
 
         Ok(Self {
             db_manager,
-            config: config.clone(),
-            server_fingerprint,
+            config: Arc::new(config.clone()),
+            identity: Arc::new(std::sync::RwLock::new(identity)),
+            listeners: Arc::new(std::sync::RwLock::new(listeners)),
+            tls_acceptors: Arc::new(std::sync::RwLock::new(tls_acceptors)),
+            users,
+            auth: Arc::new(auth),
+            access: Arc::new(access),
             connection_semaphore: Arc::new(Semaphore::new(config.max_connections)),
             clients: Arc::new(RwLock::new(HashMap::new())),
+            scheduler: Arc::new(
+                PriorityScheduler::new(
+                    config.interactive_latency_target,
+                    config.max_concurrent_batch_commands,
+                )
+                .with_max_in_flight(config.max_in_flight_commands)
+                .with_concurrency_limits(
+                    config.max_concurrent_reads,
+                    config.max_concurrent_writes,
+                ),
+            ),
+            idempotency: Arc::new(IdempotencyCache::new(
+                config.idempotency_window,
+                config.idempotency_max_entries,
+            )),
+            startup: {
+                let startup = StartupStatus::new();
+                startup.set_state(ServerState::Ready);
+                startup
+            },
+            drain_request: Arc::new(Notify::new()),
+            listener_updates: Arc::new(std::sync::Mutex::new(None)),
+            listener_update_lock: Arc::new(tokio::sync::Mutex::new(())),
+            statement_caches,
+            result_caches,
+            started: Instant::now(),
         })
     }
 
+
+    pub fn with_startup_status(mut self, startup: StartupStatus) -> Self {
+        self.startup = startup;
+        self
+    }
+
+    pub fn state(&self) -> ServerState {
+        self.startup.state()
+    }
+
     pub async fn start(&self) -> VeloResult<()> {
-        let listener = TcpListener::bind(&self.config.bind_address).await?;
-        log::info!(
-            "VelocityDB server listening on {}",
-            self.config.bind_address
-        );
-        log::info!("Server fingerprint: {}", self.server_fingerprint);
+        self.start_until(std::future::pending()).await
+    }
 
-        loop {
-            match listener.accept().await {
-                Ok((mut stream, addr)) => {
-                    log::info!("New connection from {}", addr);
+    /// Serves on the configured listeners until `shutdown` resolves. Open
+    /// connections are left alone; call [`VelocityServer::drain`] afterwards
+    /// to wait for their commands to finish.
+    pub async fn start_until<F>(&self, shutdown: F) -> VeloResult<()>
+    where
+        F: std::future::Future<Output = ()>,
+    {
+        let listeners = self.bind().await?;
+        self.serve(listeners, shutdown).await
+    }
 
+    /// Binds every configured listener without serving yet, so the caller
+    /// can drop privileges in between.
+    pub async fn bind(&self) -> VeloResult<BoundListeners> {
+        let listeners = self.listeners();
+        BoundListeners::bind_with(&listeners, &self.config).await
+    }
 
-                    if let Ok(_permit) = self.connection_semaphore.clone().try_acquire_owned() {
-                        let server = self.clone();
-                        tokio::spawn(async move {
-                            if let Err(e) = server.handle_connection(stream, addr).await {
-                                log::error!("Connection error for {}: {:?}", addr, e);
-                            }
 
-                        });
-                    } else {
-                        log::warn!("Connection limit reached, rejecting {}", addr);
-                        let _ = stream.shutdown().await;
-                    }
-                }
-                Err(e) => {
-                    log::error!("Failed to accept connection: {:?}", e);
-                }
+    /// Stops admitting commands and waits up to `timeout` for the ones in
+    /// flight to complete. Returns false if some were still running.
+    pub async fn drain(&self, timeout: Duration) -> bool {
+        self.startup.set_state(ServerState::Draining);
+        let deadline = Instant::now() + timeout;
+        loop {
+            let in_flight = self.scheduler.stats().in_flight;
+            if in_flight == 0 {
+                return true;
             }
+            if Instant::now() >= deadline {
+                log::warn!("Drain timed out with {} commands still running", in_flight);
+                return false;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
         }
     }
 
-    async fn handle_connection(&self, stream: TcpStream, addr: SocketAddr) -> VeloResult<()> {
+    /// Asks whoever is waiting on [`VelocityServer::drain_requested`] to
+    /// close the listeners and drain. Sent by the `DRAIN SERVER` command.
+    pub fn request_drain(&self) {
+        self.drain_request.notify_one();
+    }
 
-        {
-            let mut clients = self.clients.write().await;
-            clients.insert(addr, ClientState::new(self.config.rate_limit_per_second));
+    /// Resolves once a drain has been requested; pass it as the shutdown
+    /// future of [`VelocityServer::serve`].
+    pub async fn drain_requested(&self) {
+        self.drain_request.notified().await
+    }
+
+    fn statement_cache(&self, db_name: &str) -> Arc<StatementCache> {
+        self.statement_caches
+            .lock()
+            .unwrap()
+            .entry(db_name.to_string())
+            .or_insert_with(|| {
+                Arc::new(StatementCache::new(
+                    self.config.statement_cache_size,
+                    self.config.statement_cache_bytes,
+                ))
+            })
+            .clone()
+    }
+
+    fn result_cache(&self, db_name: &str, db: &Arc<Velocity>) -> Option<Arc<ResultCache>> {
+        if self.config.result_cache_size == 0 {
+            return None;
         }
+        let mut caches = self.result_caches.lock().unwrap();
+        let cache = caches.entry(db_name.to_string()).or_insert_with(|| {
+            let cache = Arc::new(ResultCache::new(
+                self.config.result_cache_size,
+                self.config.result_cache_max_age,
+            ));
+            cache.attach(db);
+            cache
+        });
+        Some(cache.clone())
+    }
+
+    /// Hit statistics of the SELECT result cache of each database.
+    pub fn result_cache_stats(&self) -> BTreeMap<String, ResultCacheStats> {
+        self.result_caches
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(name, cache)| (name.clone(), cache.stats()))
+            .collect()
+    }
 
-        let result = if self.config.enable_tls {
+    /// Hit statistics of the parsed-statement cache of each database.
+    pub fn statement_cache_stats(&self) -> BTreeMap<String, StatementCacheStats> {
+        self.statement_caches
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(name, cache)| (name.clone(), cache.stats()))
+            .collect()
+    }
 
-            self.handle_plain_connection(stream, addr).await
-        } else {
-            self.handle_plain_connection(stream, addr).await
-        };
+    fn handle_usage(&self, sql: &str) -> VeloResult<VelocityMessage> {
+        let day = sql
+            .split_whitespace()
+            .nth(2)
+            .map(|day| day.trim_matches('\''));
+        if let Some(day) = day {
+            chrono::NaiveDate::parse_from_str(day, "%Y-%m-%d").map_err(|_| {
+                VeloError::InvalidOperation(format!("Invalid day '{}', expected YYYY-MM-DD", day))
+            })?;
+        }
+        let usage = usage::load_usage(&self.db_manager.system_database(), day)?;
+        let payload = serde_json::to_vec(&usage).map_err(|e| {
+            VeloError::InvalidOperation(format!("Failed to encode usage: {}", e))
+        })?;
+        Ok(VelocityMessage::new(MessageType::Response, payload))
+    }
 
+    // SHOW MAINTENANCE [AFTER <id>]
+    fn handle_maintenance(&self, sql_upper: &str) -> VeloResult<VelocityMessage> {
+        let words: Vec<&str> = sql_upper.trim_end_matches(';').split_whitespace().collect();
+        let after = match words[2..] {
+            [] => 0,
+            ["AFTER", id] => id.parse().map_err(|_| {
+                VeloError::InvalidOperation(format!("Invalid event id '{}'", id))
+            })?,
+            _ => {
+                return Err(VeloError::InvalidOperation(
+                    "Usage: SHOW MAINTENANCE [AFTER <id>]".to_string(),
+                ))
+            }
+        };
+        let payload = serde_json::to_vec(&serde_json::json!({
+            "running": self.db_manager.running_maintenance(),
+            "events": self.db_manager.maintenance_events(after),
+        }))
+        .map_err(|e| {
+            VeloError::InvalidOperation(format!("Failed to encode maintenance events: {}", e))
+        })?;
+        Ok(VelocityMessage::new(MessageType::Response, payload))
+    }
 
-        {
-            let mut clients = self.clients.write().await;
-            clients.remove(&addr);
+    // SHOW PLAN CACHE only reports the session's current database.
+    fn handle_plan_cache(
+        &self,
+        sql: &str,
+        sql_upper: &str,
+        current_db: &str,
+    ) -> VeloResult<VelocityMessage> {
+        let caches = self.statement_caches.lock().unwrap();
+        if sql_upper == "SHOW PLAN CACHE" {
+            let report: BTreeMap<&String, serde_json::Value> = caches
+                .get_key_value(current_db)
+                .map(|(name, cache)| {
+                    (
+                        name,
+                        serde_json::json!({
+                            "stats": cache.stats(),
+                            "entries": cache.entries(),
+                        }),
+                    )
+                })
+                .into_iter()
+                .collect();
+            let payload = serde_json::to_vec(&report).map_err(|e| {
+                VeloError::InvalidOperation(format!("Failed to encode plan cache: {}", e))
+            })?;
+            return Ok(VelocityMessage::new(MessageType::Response, payload));
         }
 
+        let message = match sql.split_whitespace().nth(3) {
+            Some(db_name) => {
+                if let Some(cache) = caches.get(db_name) {
+                    cache.clear();
+                }
+                format!("Plan cache of '{}' cleared", db_name)
+            }
+            None => {
+                caches.values().for_each(|cache| cache.clear());
+                "Plan cache cleared".to_string()
+            }
+        };
+        Ok(VelocityMessage::new(MessageType::Response, message.into_bytes()))
+    }
+
+    pub async fn start_ephemeral(&self) -> VeloResult<ServerHandle> {
+        let bind_address = SocketAddr::new(self.config.bind_address.ip(), 0);
+        let listener = TcpListener::bind(bind_address).await?;
+        let local_addr = listener.local_addr()?;
+        log::info!("Ephemeral VelocityDB server listening on {}", local_addr);
+
+        let (shutdown_tx, shutdown_rx) = oneshot::channel::<()>();
+        let server = self.clone();
+        let task = tokio::spawn(async move {
+            let shutdown = async {
+                let _ = shutdown_rx.await;
+            };
+            let listeners = BoundListeners::from(vec![listener]);
+            if let Err(e) = server.serve_listeners(listeners, shutdown, None).await {
+                log::error!("Ephemeral server error: {:?}", e);
+            }
+        });
+
+        Ok(ServerHandle {
+            local_addr,
+            shutdown: Some(shutdown_tx),
+            task: Some(task),
+        })
+    }
+
+    /// Accepts on all `listeners` concurrently until `shutdown` resolves.
+    /// TCP listeners are matched to the configured ones by position, as
+    /// returned by [`VelocityServer::bind`]. While it runs,
+    /// [`VelocityServer::update_listeners`] can move them.
+    pub async fn serve<F>(&self, listeners: BoundListeners, shutdown: F) -> VeloResult<()>
+    where
+        F: std::future::Future<Output = ()>,
+    {
+        log::info!("Server fingerprint: {}", self.fingerprint());
+        if self.config.read_only {
+            log::info!("Read-only mode: write commands are rejected");
+        }
+        let (tx, rx) = mpsc::unbounded_channel();
+        *self.listener_updates.lock().unwrap() = Some(tx);
+        let result = self.serve_listeners(listeners, shutdown, Some(rx)).await;
+        *self.listener_updates.lock().unwrap() = None;
         result
     }
 
-    async fn handle_plain_connection(
+    async fn serve_listeners<F>(
         &self,
-        mut stream: TcpStream,
-        addr: SocketAddr,
-    ) -> VeloResult<()> {
-        let mut buffer = BytesMut::with_capacity(8192);
+        listeners: BoundListeners,
+        shutdown: F,
+        mut updates: Option<mpsc::UnboundedReceiver<ListenerUpdate>>,
+    ) -> VeloResult<()>
+    where
+        F: std::future::Future<Output = ()>,
+    {
+        self.startup.set_state(ServerState::Ready);
+        let mut tcp = listeners.tcp;
+
+        #[cfg(unix)]
+        let unix_socket = listeners.unix.is_some();
+        #[cfg(unix)]
+        let unix_loop = async {
+            match listeners.unix {
+                Some(listener) => self.accept_unix_loop(listener).await,
+                None => std::future::pending().await,
+            }
+        };
+        #[cfg(not(unix))]
+        let unix_loop = std::future::pending::<()>();
+        tokio::pin!(unix_loop);
+        tokio::pin!(shutdown);
 
+        // The accept loops are restarted whenever the listeners change;
+        // connections already accepted carry on regardless.
         loop {
-
-            match timeout(self.config.connection_timeout, stream.readable()).await {
-                Ok(Ok(())) => {
-
-                    match stream.try_read_buf(&mut buffer) {
-                        Ok(0) => break,
-                        Ok(_) => {
-
-                            while buffer.len() >= 14 {
-                                match VelocityMessage::decode(&buffer) {
-                                    Ok(message) => {
-                                        let message_len = 14 + message.payload.len();
-                                        buffer.advance(message_len);
-
-                                        match self.handle_message(message, addr).await {
-                                            Ok(Some(response)) => {
-                                                let response_data = response.encode();
-                                                if let Err(e) =
-                                                    stream.write_all(&response_data).await
-                                                {
-                                                    log::error!(
-                                                        "Failed to send response to {}: {:?}",
-                                                        addr,
-                                                        e
-                                                    );
-                                                    return Err(VeloError::IoError(e));
-                                                }
-                                            }
-                                            Ok(None) => {
-
-                                            }
-                                            Err(e) => {
-                                                log::error!(
-                                                    "Error handling message from {}: {:?}",
-                                                    addr,
-                                                    e
-                                                );
-
-                                                let error_msg = format!("{:?}", e);
-                                                let error_response = VelocityMessage::new(
-                                                    MessageType::Error,
-                                                    error_msg.into_bytes(),
-                                                );
-                                                let _ = stream
-                                                    .write_all(&error_response.encode())
-                                                    .await;
-                                            }
-                                        }
-                                    }
-                                    Err(e) => {
-                                        log::error!(
-                                            "Failed to decode message from {}: {:?}",
-                                            addr,
-                                            e
-                                        );
-                                        log::error!(
-                                            "Buffer length: {}, hex: {}",
-                                            buffer.len(),
-                                            buffer
-                                                .iter()
-                                                .take(32)
-                                                .map(|b| format!("{:02x}", b))
-                                                .collect::<String>()
-                                        );
-                                        break;
-                                    }
-                                }
-                            }
-                        }
-                        Err(ref e) if e.kind() == std::io::ErrorKind::WouldBlock => {
-                            continue;
-                        }
-                        Err(e) => return Err(VeloError::IoError(e)),
-                    }
+            let accept_loops = futures::future::join_all(
+                tcp.iter()
+                    .enumerate()
+                    .map(|(index, listener)| self.accept_loop(index, listener)),
+            );
+            let next_update = async {
+                match &mut updates {
+                    Some(updates) => updates.recv().await,
+                    None => std::future::pending().await,
                 }
-                Ok(Err(e)) => return Err(VeloError::IoError(e)),
-                Err(_) => {
-                    log::warn!("Connection timeout for {}", addr);
+            };
+            tokio::select! {
+                _ = futures::future::join(accept_loops, &mut unix_loop) => break,
+                update = next_update => match update {
+                    Some(update) => tcp = self.apply_listener_update(tcp, update).await,
+                    None => updates = None,
+                },
+                _ = &mut shutdown => {
+                    log::info!("Listener shutting down");
                     break;
                 }
             }
         }
 
+        #[cfg(unix)]
+        if unix_socket {
+            if let Some(path) = &self.config.unix_socket_path {
+                let _ = std::fs::remove_file(path);
+            }
+        }
         Ok(())
     }
 
-    async fn handle_message(
-        &self,
-        message: VelocityMessage,
-        addr: SocketAddr,
-    ) -> VeloResult<Option<VelocityMessage>> {
+    #[cfg(unix)]
+    async fn accept_unix_loop(&self, listener: tokio::net::UnixListener) {
+        loop {
+            match listener.accept().await {
+                Ok((stream, _)) => {
+                    let peer = Peer::local();
+                    log::debug!("New Unix socket connection {}", peer);
 
-        {
-            let mut clients = self.clients.write().await;
-            if let Some(client) = clients.get_mut(&addr) {
-                if !client.rate_limiter.try_acquire() {
-                    return Ok(Some(VelocityMessage::new(
-                        MessageType::Error,
-                        b"Rate limit exceeded".to_vec(),
-                    )));
+                    if let Ok(_permit) = self.connection_semaphore.clone().try_acquire_owned() {
+                        let server = self.clone();
+                        tokio::spawn(async move {
+                            if let Err(e) = server
+                                .handle_connection(stream, peer, SessionInfo::default())
+                                .await
+                            {
+                                log::error!("Connection error for {}: {:?}", peer, e);
+                            }
+                        });
+                    } else {
+                        log::warn!("Connection limit reached, rejecting Unix socket connection");
+                    }
+                }
+                Err(e) => {
+                    log::error!("Failed to accept Unix socket connection: {:?}", e);
                 }
-                client.last_activity = Instant::now();
             }
         }
+    }
 
-        match message.msg_type {
-            MessageType::Hello => Ok(Some(VelocityMessage::new(
-                MessageType::ServerInfo,
-                format!("VelocityDB v1.0\nFingerprint: {}", self.server_fingerprint).into_bytes(),
-            ))),
-
-            MessageType::AuthRequest => self.handle_auth(message.payload, addr).await,
-
-            MessageType::Command => {
+    /// Moves the server to `listeners`, for example after the bind address
+    /// or TLS settings in the config file changed. New addresses are bound
+    /// before anything else changes, so a failure leaves the current
+    /// listeners as they are. An address that is in use, for example by a
+    /// wildcard listener being replaced, is bound once the listeners being
+    /// removed are closed; if it still fails they are bound again. Listeners that are no longer configured stop
+    /// accepting; connections made through them stay open until the client
+    /// disconnects.
+    pub async fn update_listeners(
+        &self,
+        listeners: Vec<ListenerConfig>,
+    ) -> VeloResult<ListenerChanges> {
+        let _updating = self.listener_update_lock.lock().await;
+        let current = self.listeners.read().unwrap().clone();
+        let changes = ListenerChanges::between(&current, &listeners);
+        if changes.is_empty() {
+            return Ok(changes);
+        }
+        let tls_acceptors = load_tls_acceptors(&listeners)?;
+        load_listener_access(&listeners)?;
+
+        let sender = self.listener_updates.lock().unwrap().clone();
+        let Some(sender) = sender else {
+            *self.listeners.write().unwrap() = listeners;
+            *self.tls_acceptors.write().unwrap() = tls_acceptors;
+            return Ok(changes);
+        };
+        let mut bound = HashMap::new();
+        let mut deferred = Vec::new();
+        for address in &changes.opened {
+            match TcpListener::bind(address).await {
+                Ok(listener) => {
+                    bound.insert(*address, listener);
+                }
+                Err(e) if e.kind() == std::io::ErrorKind::AddrInUse && !changes.closed.is_empty() => {
+                    deferred.push(*address);
+                }
+                Err(e) => {
+                    return Err(VeloError::InvalidOperation(format!(
+                        "Cannot bind {}: {}",
+                        address, e
+                    )))
+                }
+            }
+        }
+        let (done, applied) = oneshot::channel();
+        let update = ListenerUpdate {
+            listeners,
+            tls_acceptors,
+            bound,
+            deferred,
+            done,
+        };
+        if let Err(mpsc::error::SendError(update)) = sender.send(update) {
+            *self.listeners.write().unwrap() = update.listeners;
+            *self.tls_acceptors.write().unwrap() = update.tls_acceptors;
+            return Ok(changes);
+        }
+        applied.await.unwrap_or(Ok(()))?;
+        Ok(changes)
+    }
 
-                let (authenticated, current_db) = {
-                    let clients = self.clients.read().await;
-                    if let Some(c) = clients.get(&addr) {
-                        (c.authenticated, c.current_db.clone())
-                    } else {
-                        (false, "default".to_string())
+    // Sockets whose address is still configured are kept, so their accept
+    // queue is not lost.
+    async fn apply_listener_update(
+        &self,
+        tcp: Vec<TcpListener>,
+        update: ListenerUpdate,
+    ) -> Vec<TcpListener> {
+        let ListenerUpdate {
+            listeners,
+            tls_acceptors,
+            mut bound,
+            deferred,
+            done,
+        } = update;
+        let current = self.listeners.read().unwrap().clone();
+        let mut previous: HashMap<SocketAddr, TcpListener> = current
+            .iter()
+            .map(|listener| listener.bind_address)
+            .zip(tcp)
+            .collect();
+
+        if !deferred.is_empty() {
+            let closing: Vec<SocketAddr> = previous
+                .keys()
+                .filter(|address| !listeners.iter().any(|l| l.bind_address == **address))
+                .copied()
+                .collect();
+            for address in &closing {
+                previous.remove(address);
+            }
+            if let Err(e) = bind_addresses(&deferred, &mut bound).await {
+                if let Err(rebind) = bind_addresses(&closing, &mut previous).await {
+                    log::error!("Could not listen on the previous addresses again: {}", rebind);
+                }
+                let acceptors = self.tls_acceptors.read().unwrap().clone();
+                let mut sockets = Vec::with_capacity(current.len());
+                let mut configs = Vec::with_capacity(current.len());
+                let mut kept_acceptors = Vec::with_capacity(current.len());
+                for (listener, acceptor) in current.into_iter().zip(acceptors) {
+                    if let Some(socket) = previous.remove(&listener.bind_address) {
+                        sockets.push(socket);
+                        configs.push(listener);
+                        kept_acceptors.push(acceptor);
                     }
-                };
-
-                if !authenticated {
-                    return Ok(Some(VelocityMessage::new(
-                        MessageType::Error,
-                        b"Not authenticated".to_vec(),
-                    )));
                 }
-
-                self.handle_command(message.payload, addr, &current_db)
-                    .await
+                *self.listeners.write().unwrap() = configs;
+                *self.tls_acceptors.write().unwrap() = kept_acceptors;
+                let _ = done.send(Err(e));
+                return sockets;
             }
+        }
 
-            MessageType::Ping => Ok(Some(VelocityMessage::new(MessageType::Pong, Vec::new()))),
-
-            MessageType::Stats => self.handle_stats().await,
+        let mut sockets = Vec::with_capacity(listeners.len());
+        let mut configs = Vec::with_capacity(listeners.len());
+        let mut acceptors = Vec::with_capacity(listeners.len());
+        for (listener, acceptor) in listeners.into_iter().zip(tls_acceptors) {
+            let address = listener.bind_address;
+            let Some(socket) = previous.remove(&address).or_else(|| bound.remove(&address)) else {
+                log::error!("Listener {} was not bound and is left out", address);
+                continue;
+            };
+            match current.iter().find(|old| old.bind_address == address) {
+                None => log::info!(
+                    "Now listening on {}{}",
+                    address,
+                    if listener.enable_tls { " (TLS)" } else { "" }
+                ),
+                Some(old) if *old != listener => log::info!(
+                    "Listener {} reconfigured; new connections use the new settings",
+                    address
+                ),
+                Some(_) => {}
+            }
+            sockets.push(socket);
+            configs.push(listener);
+            acceptors.push(acceptor);
+        }
 
-            _ => Ok(Some(VelocityMessage::new(
-                MessageType::Error,
-                b"Unsupported message type".to_vec(),
-            ))),
+        let stopped: Vec<SocketAddr> = current
+            .iter()
+            .map(|listener| listener.bind_address)
+            .filter(|address| !configs.iter().any(|l| l.bind_address == *address))
+            .collect();
+        drop(previous);
+        if !stopped.is_empty() {
+            let clients = self.clients.read().await;
+            for address in &stopped {
+                let open = clients
+                    .values()
+                    .filter(|client| {
+                        client
+                            .listener
+                            .as_ref()
+                            .is_some_and(|listener| listener.bind_address == *address)
+                    })
+                    .count();
+                log::info!(
+                    "Stopped listening on {}; {} connections made through it stay open until they close",
+                    address,
+                    open
+                );
+            }
         }
+        *self.listeners.write().unwrap() = configs;
+        *self.tls_acceptors.write().unwrap() = acceptors;
+        let _ = done.send(Ok(()));
+        sockets
     }
 
-    async fn handle_auth(
+    async fn accept_loop(&self, index: usize, listener: &TcpListener) {
+        loop {
+            match listener.accept().await {
+                Ok((mut stream, addr)) => {
+                    let listener_access = self.listener_config(index).map_or(Ok(()), |listener| {
+                        AccessList::new(&listener.access)
+                            .map_err(|e| e.to_string())
+                            .and_then(|list| list.check(addr.ip()))
+                    });
+                    if let Err(reason) = self.access.check(addr.ip()).and(listener_access) {
+                        log::warn!("Rejected connection from {}: {}", addr, reason);
+                        let _ = stream.shutdown().await;
+                        continue;
+                    }
+
+                    log::info!("New connection from {}", addr);
+                    let peer = Peer::new(addr);
+
+                    if let Ok(_permit) = self.connection_semaphore.clone().try_acquire_owned() {
+                        let server = self.clone();
+                        tokio::spawn(async move {
+                            // The identity file is checked on the blocking
+                            // pool so a slow disk never stalls accepting.
+                            let refresh = server.clone();
+                            if let Ok(Err(e)) =
+                                tokio::task::spawn_blocking(move || refresh.refresh_identity())
+                                    .await
+                            {
+                                log::error!("Failed to reload server identity: {:?}", e);
+                            }
+                            let tls_acceptor = server
+                                .tls_acceptors
+                                .read()
+                                .unwrap()
+                                .get(index)
+                                .cloned()
+                                .flatten();
+                            let result = match tls_acceptor {
+                                Some(acceptor) => match acceptor.accept(stream).await {
+                                    Ok(tls_stream) => {
+                                        let tls_session = tls_stream.get_ref().1;
+                                        let session = SessionInfo {
+                                            cert_user: tls_session
+                                                .peer_certificates()
+                                                .and_then(|certs| certs.first())
+                                                .and_then(|cert| {
+                                                    server.map_client_certificate(&cert.0)
+                                                }),
+                                            server_name: tls_session
+                                                .server_name()
+                                                .map(|name| name.to_string()),
+                                            listener: server.listener_config(index),
+                                        };
+                                        server.handle_connection(tls_stream, peer, session).await
+                                    }
+                                    Err(e) => Err(VeloError::IoError(e)),
+                                },
+                                None => {
+                                    let session = SessionInfo {
+                                        listener: server.listener_config(index),
+                                        ..SessionInfo::default()
+                                    };
+                                    server.handle_connection(stream, peer, session).await
+                                }
+                            };
+                            if let Err(e) = result {
+                                log::error!("Connection error for {}: {:?}", addr, e);
+                            }
+
+                        });
+                    } else {
+                        log::warn!("Connection limit reached, rejecting {}", addr);
+                        let _ = stream.shutdown().await;
+                    }
+                }
+                Err(e) => {
+                    log::error!("Failed to accept connection: {:?}", e);
+                }
+            }
+        }
+    }
+
+    fn listener_config(&self, index: usize) -> Option<ListenerConfig> {
+        self.listeners.read().unwrap().get(index).cloned()
+    }
+
+    /// The listeners the server is configured with now.
+    pub fn listeners(&self) -> Vec<ListenerConfig> {
+        self.listeners.read().unwrap().clone()
+    }
+
+    pub fn fingerprint(&self) -> String {
+        self.identity.read().unwrap().identity.fingerprint()
+    }
+
+
+    pub fn reload_identity(&self) -> VeloResult<()> {
+        let identity = LoadedIdentity::load(self.config.identity_path.as_ref())?;
+        let tls_acceptors = load_tls_acceptors(&self.listeners.read().unwrap())?;
+
+        log::info!("Server identity reloaded, fingerprint: {}", identity.identity.fingerprint());
+        *self.identity.write().unwrap() = identity;
+        *self.tls_acceptors.write().unwrap() = tls_acceptors;
+        Ok(())
+    }
+
+    fn refresh_identity(&self) -> VeloResult<()> {
+        let Some(path) = &self.config.identity_path else {
+            return Ok(());
+        };
+        let modified = std::fs::metadata(path).and_then(|m| m.modified()).ok();
+        if modified == self.identity.read().unwrap().modified {
+            return Ok(());
+        }
+        self.reload_identity()
+    }
+
+    pub fn connect_in_memory(&self) -> DuplexStream {
+        let (client_side, server_side) = tokio::io::duplex(64 * 1024);
+        let peer = Peer::local();
+
+        let server = self.clone();
+        tokio::spawn(async move {
+            if let Err(e) = server
+                .handle_connection(server_side, peer, SessionInfo::default())
+                .await
+            {
+                log::error!("In-memory connection error for {}: {:?}", peer, e);
+            }
+        });
+
+        client_side
+    }
+
+    fn map_client_certificate(&self, der: &[u8]) -> Option<String> {
+        let (_, cert) = X509Certificate::from_der(der).ok()?;
+
+        let mut names: Vec<String> = cert
+            .subject()
+            .iter_common_name()
+            .filter_map(|cn| cn.as_str().ok().map(|s| s.to_string()))
+            .collect();
+        if let Ok(Some(san)) = cert.subject_alternative_name() {
+            for name in &san.value.general_names {
+                match name {
+                    GeneralName::DNSName(n) | GeneralName::RFC822Name(n) | GeneralName::URI(n) => {
+                        names.push(n.to_string())
+                    }
+                    _ => {}
+                }
+            }
+        }
+
+        let user = names
+            .iter()
+            .find_map(|name| self.config.client_cert_users.get(name).cloned());
+        match &user {
+            Some(user) => log::info!("Client certificate {:?} mapped to user {}", names, user),
+            None => log::info!("Client certificate {:?} has no user mapping", names),
+        }
+        user
+    }
+
+    // Only names listed in `[server.tenants]` route anywhere; a database
+    // that merely shares a tenant's name is not reachable this way.
+    fn resolve_tenant(&self, tenant: &str) -> Option<String> {
+        self.config.tenants.get(&tenant.trim().to_lowercase()).cloned()
+    }
+
+    async fn handle_connection<S>(
+        &self,
+        stream: S,
+        peer: Peer,
+        session: SessionInfo,
+    ) -> VeloResult<()>
+    where
+        S: AsyncRead + AsyncWrite + Unpin,
+    {
+        let cert_user = match session.cert_user {
+            Some(user) => {
+                let disabled = load_user_meta(&self.db_manager.system_database(), &user)?
+                    .map(|m| m.disabled)
+                    .unwrap_or(false);
+                if disabled {
+                    log::warn!("Certificate login for disabled user {} from {}", user, peer);
+                    None
+                } else {
+                    Some(user)
+                }
+            }
+            None => None,
+        };
+
+        {
+            let mut state = ClientState::new(self.config.rate_limit_per_second);
+            state.listener = session.listener;
+            if let Some(user) = cert_user {
+                log::info!("User {} authenticated via client certificate from {}", user, peer);
+                state.authenticated = true;
+                state.username = Some(user);
+            }
+            if let Some(server_name) = &session.server_name {
+                match self.resolve_tenant(server_name) {
+                    Some(db_name) => {
+                        log::info!("Routing {} (SNI {}) to database '{}'", peer, server_name, db_name);
+                        state.current_db = db_name;
+                        state.tenant = Some(server_name.clone());
+                    }
+                    None => log::debug!("No tenant for SNI {} from {}", server_name, peer),
+                }
+            }
+            let mut clients = self.clients.write().await;
+            clients.insert(peer.id, state);
+        }
+
+        let result = self.handle_stream(stream, peer).await;
+
+
+        let state = self.clients.write().await.remove(&peer.id);
+        if let Some(state) = state {
+            self.drop_temporary_keys(&state.temporary_keys, peer);
+            self.record_session(&state.summary(peer));
+        }
+
+        result
+    }
+
+    // A temporary key that another session has since overwritten is left
+    // alone, so a lock taken over after a timeout is not released by its
+    // previous holder.
+    fn drop_temporary_keys(&self, keys: &HashMap<(String, String), Vec<u8>>, peer: Peer) {
+        let mut dropped = 0usize;
+        for ((db_name, key), value) in keys {
+            let Some(db) = self.db_manager.get_database(db_name) else {
+                continue;
+            };
+            let result = match self.db_manager.is_read_only(db_name) {
+                true => Err(VeloError::InvalidOperation(format!(
+                    "Database '{}' is read-only",
+                    db_name
+                ))),
+                false => db.delete_if_value(key, value),
+            };
+            match result {
+                Ok(true) => dropped += 1,
+                Ok(false) => {}
+                Err(e) => log::warn!(
+                    "Failed to delete temporary key '{}' in '{}' of {}: {}",
+                    key,
+                    db_name,
+                    peer,
+                    e
+                ),
+            }
+        }
+        if dropped > 0 {
+            log::debug!("Deleted {} temporary keys of {}", dropped, peer);
+        }
+    }
+
+    fn record_session(&self, session: &SessionSummary) {
+        self.audit("session_closed", serde_json::json!({ "session": session }));
+        let system_db = self.db_manager.system_database();
+        if let Err(e) = usage::record_session(&system_db, &usage::today(), session) {
+            log::warn!("Failed to record usage of session {}: {}", session.addr, e);
+        }
+    }
+
+    /// Appends an `event` entry to the audit log, when audit logging is on.
+    fn audit(&self, event: &str, mut entry: serde_json::Value) {
+        use std::io::Write;
+
+        if !self.config.audit_logging {
+            return;
+        }
+        entry["timestamp"] = serde_json::json!(chrono::Local::now().to_rfc3339());
+        entry["event"] = serde_json::json!(event);
+        let written = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.config.audit_log_path)
+            .and_then(|mut file| writeln!(file, "{}", entry));
+        if let Err(e) = written {
+            log::warn!("Failed to write audit log {}: {}", self.config.audit_log_path, e);
+        }
+    }
+
+    async fn handle_stream<S>(&self, mut stream: S, peer: Peer) -> VeloResult<()>
+    where
+        S: AsyncRead + AsyncWrite + Unpin,
+    {
+        let mut buffer = BytesMut::with_capacity(8192);
+
+        loop {
+            match timeout(self.config.connection_timeout, stream.read_buf(&mut buffer)).await {
+                Ok(Ok(0)) => break,
+                Ok(Ok(_)) => {
+                    while let Some(frame_len) = VelocityMessage::frame_len(&buffer) {
+                        if frame_len > self.config.max_frame_bytes {
+                            let message = format!(
+                                "Frame of {} bytes is larger than the {} byte limit",
+                                frame_len, self.config.max_frame_bytes
+                            );
+                            log::warn!("{} from {}", message, peer);
+                            let reply = VelocityMessage::new(MessageType::Error, message.clone().into_bytes());
+                            let _ = stream.write_all(&reply.encode()).await;
+                            return Err(VeloError::InvalidOperation(message));
+                        }
+                        if buffer.len() < frame_len {
+                            break;
+                        }
+
+                        let message = match VelocityMessage::decode(&buffer[..frame_len]) {
+                            Ok(message) => message,
+                            Err(e) => {
+                                log::error!("Failed to decode message from {}: {:?}", peer, e);
+                                log::error!(
+                                    "Buffer length: {}, hex: {}",
+                                    buffer.len(),
+                                    buffer
+                                        .iter()
+                                        .take(32)
+                                        .map(|b| format!("{:02x}", b))
+                                        .collect::<String>()
+                                );
+                                return Err(e);
+                            }
+                        };
+                        buffer.advance(frame_len);
+
+                        let is_command = matches!(
+                            message.msg_type,
+                            MessageType::Command
+                                | MessageType::BatchCommand
+                                | MessageType::BatchWrite
+                                | MessageType::Get
+                        );
+                        let response = if is_command
+                            && queued_frames(&buffer, self.config.max_pipelined_commands)
+                                >= self.config.max_pipelined_commands
+                        {
+                            VelocityMessage::new(
+                                MessageType::ServerBusy,
+                                b"Too many pipelined commands on this connection".to_vec(),
+                            )
+                        } else {
+                            match self.handle_message(message, peer).await {
+                            Ok(Some(response)) => response,
+                            Ok(None) => continue,
+                            Err(e) => {
+                                log::error!("Error handling message from {}: {:?}", peer, e);
+                                VelocityMessage::new(
+                                    MessageType::Error,
+                                    format!("{:?}", e).into_bytes(),
+                                )
+                            }
+                            }
+                        };
+
+                        let encoded = response.encode();
+                        if let Some(client) = self.clients.write().await.get_mut(&peer.id) {
+                            client.bytes_in += frame_len as u64;
+                            client.bytes_out += encoded.len() as u64;
+                            if response.msg_type == MessageType::Error {
+                                client.error_count += 1;
+                            }
+                        }
+                        if let Err(e) = stream.write_all(&encoded).await {
+                            log::error!("Failed to send response to {}: {:?}", peer, e);
+                            return Err(VeloError::IoError(e));
+                        }
+                        stream.flush().await?;
+                    }
+                }
+                Ok(Err(e)) => return Err(VeloError::IoError(e)),
+                Err(_) => {
+                    log::warn!("Connection timeout for {}", peer);
+                    break;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn handle_message(
+        &self,
+        message: VelocityMessage,
+        peer: Peer,
+    ) -> VeloResult<Option<VelocityMessage>> {
+        let received = Instant::now();
+
+        {
+            let mut clients = self.clients.write().await;
+            if let Some(client) = clients.get_mut(&peer.id) {
+                if !client.rate_limiter.try_acquire() {
+                    client.rate_limited += 1;
+                    return Ok(Some(VelocityMessage::new(
+                        MessageType::Error,
+                        b"Rate limit exceeded".to_vec(),
+                    )));
+                }
+                client.last_activity = Instant::now();
+            }
+        }
+
+        match message.msg_type {
+            MessageType::Hello => {
+                let (nonce, tenant) = parse_hello(&message.payload);
+
+                let mut clients = self.clients.write().await;
+                let Some(client) = clients.get_mut(&peer.id) else {
+                    return Ok(None);
+                };
+                if let Some(tenant) = tenant.filter(|_| client.tenant.is_none()) {
+                    match self.resolve_tenant(&tenant) {
+                        Some(db_name) => {
+                            log::info!("Routing {} (tenant {}) to database '{}'", peer, tenant, db_name);
+                            client.current_db = db_name;
+                            client.tenant = Some(tenant);
+                        }
+                        None => {
+                            return Ok(Some(VelocityMessage::new(
+                                MessageType::Error,
+                                format!("Unknown tenant '{}'", tenant).into_bytes(),
+                            )))
+                        }
+                    }
+                }
+
+                let cert_user = client
+                    .username
+                    .clone()
+                    .filter(|_| client.authenticated);
+                let routed_db = client.tenant.as_ref().map(|_| client.current_db.clone());
+                Ok(Some(self.handle_hello(nonce, cert_user, routed_db)))
+            }
+
+            MessageType::AuthRequest
+            | MessageType::Command
+            | MessageType::BatchCommand
+            | MessageType::BatchWrite
+            | MessageType::Get
+                if self.state() != ServerState::Ready =>
+            {
+                Ok(Some(VelocityMessage::new(
+                    MessageType::Error,
+                    format!("Server is not ready ({:?})", self.state()).into_bytes(),
+                )))
+            }
+
+            MessageType::AuthRequest => self.handle_auth(message.payload, peer).await,
+
+            MessageType::Command
+            | MessageType::BatchCommand
+            | MessageType::BatchWrite
+            | MessageType::Get => self.handle_request(message, peer).await,
+
+            MessageType::Ping => {
+                let clock = ServerClock {
+                    wall_clock_us: chrono::Utc::now().timestamp_micros(),
+                    monotonic_us: self.started.elapsed().as_micros() as u64,
+                    processing_us: received.elapsed().as_micros() as u64,
+                };
+                Ok(Some(VelocityMessage::new(
+                    MessageType::Pong,
+                    serde_json::to_vec(&clock).unwrap(),
+                )))
+            }
+
+            MessageType::Stats => self.handle_stats().await,
+
+            _ => Ok(Some(VelocityMessage::new(
+                MessageType::Error,
+                b"Unsupported message type".to_vec(),
+            ))),
+        }
+    }
+
+    async fn handle_request(
+        &self,
+        message: VelocityMessage,
+        peer: Peer,
+    ) -> VeloResult<Option<VelocityMessage>> {
+        let (request_id, payload) = match split_request_id(&message.payload) {
+            (Some(request_id), payload) => (request_id, payload.to_vec()),
+            (None, _) => (request_id::generate(), message.payload),
+        };
+        let message = VelocityMessage::new(message.msg_type, payload);
+        let description = match message.msg_type {
+            MessageType::BatchWrite => format!("batch write of {} bytes", message.payload.len()),
+            MessageType::Get => format!("get of {}", String::from_utf8_lossy(&message.payload)),
+            _ => {
+                let sql = String::from_utf8_lossy(split_idempotency_key(&message.payload).1);
+                sql.chars().take(200).collect()
+            }
+        };
+
+        let started = Instant::now();
+        let result = request_id::scope(request_id.clone(), self.execute_request(message, peer)).await;
+        let elapsed = started.elapsed();
+        if !self.config.slow_command_threshold.is_zero()
+            && elapsed >= self.config.slow_command_threshold
+        {
+            log::warn!(
+                "Slow command (request {}) from {} took {}ms: {}",
+                request_id,
+                peer,
+                elapsed.as_millis(),
+                description
+            );
+        }
+
+        match result {
+            Ok(Some(mut response))
+                if matches!(response.msg_type, MessageType::Error | MessageType::ServerBusy) =>
+            {
+                response
+                    .payload
+                    .extend_from_slice(format!(" (request {})", request_id).as_bytes());
+                Ok(Some(response))
+            }
+            Err(e) => {
+                log::error!("Error handling request {} from {}: {:?}", request_id, peer, e);
+                Ok(Some(VelocityMessage::new(
+                    MessageType::Error,
+                    format!("{:?} (request {})", e, request_id).into_bytes(),
+                )))
+            }
+            result => result,
+        }
+    }
+
+    async fn execute_request(
+        &self,
+        message: VelocityMessage,
+        peer: Peer,
+    ) -> VeloResult<Option<VelocityMessage>> {
+        let priority = if message.msg_type == MessageType::Command {
+            CommandPriority::Interactive
+        } else {
+            CommandPriority::Batch
+        };
+
+        let (authenticated, current_db) = {
+            let clients = self.clients.read().await;
+            if let Some(c) = clients.get(&peer.id) {
+                (c.authenticated, c.current_db.clone())
+            } else {
+                (false, "default".to_string())
+            }
+        };
+
+        if !authenticated {
+            return Ok(Some(VelocityMessage::new(
+                MessageType::Error,
+                b"Not authenticated".to_vec(),
+            )));
+        }
+
+        let command = match message.msg_type {
+            MessageType::Get => Default::default(),
+            _ => String::from_utf8_lossy(split_idempotency_key(&message.payload).1),
+        };
+        let kind = if message.msg_type == MessageType::BatchWrite || is_write_statement(&command) {
+            CommandKind::Write
+        } else {
+            CommandKind::Read
+        };
+        if self.config.read_only && (kind == CommandKind::Write || changes_data(&command)) {
+            return Ok(Some(VelocityMessage::new(
+                MessageType::Error,
+                b"Server is read-only; write commands are rejected".to_vec(),
+            )));
+        }
+
+        let Some(_in_flight) = self.scheduler.try_enter(priority) else {
+            return Ok(Some(VelocityMessage::new(
+                MessageType::ServerBusy,
+                format!(
+                    "Too many commands in flight; {} command shed",
+                    priority.as_str()
+                )
+                .into_bytes(),
+            )));
+        };
+        let _slot = self.scheduler.admit(priority).await;
+        let started = Instant::now();
+        let _execution_slot = self.scheduler.acquire(kind).await;
+        // Engine calls block on disk I/O and storage locks, so the command
+        // runs on the blocking pool instead of stalling a runtime worker that
+        // other connections share.
+        let server = self.clone();
+        let runtime = tokio::runtime::Handle::current();
+        let request_id = request_id::current().unwrap_or_else(request_id::generate);
+        let result = tokio::task::spawn_blocking(move || {
+            runtime.block_on(request_id::scope(
+                request_id,
+                server.dispatch_command(message, peer, current_db),
+            ))
+        })
+        .await
+        .map_err(|e| VeloError::InvalidOperation(format!("Command task failed: {}", e)))?;
+        self.scheduler.record(priority, started.elapsed());
+        result
+    }
+
+    async fn dispatch_command(
+        &self,
+        message: VelocityMessage,
+        peer: Peer,
+        current_db: String,
+    ) -> VeloResult<Option<VelocityMessage>> {
+        match message.msg_type {
+            MessageType::Get => self.handle_get(&message.payload, &current_db),
+            _ => self.handle_idempotent(message, peer, &current_db).await,
+        }
+    }
+
+    fn handle_hello(
+        &self,
+        nonce: Option<Vec<u8>>,
+        cert_user: Option<String>,
+        routed_db: Option<String>,
+    ) -> VelocityMessage {
+        let loaded = self.identity.read().unwrap();
+        let identity = &loaded.identity;
+        let mut info = format!(
+            "VelocityDB v1.0\nFingerprint: {}\nState: {:?}",
+            identity.fingerprint(),
+            self.state()
+        );
+        if let Some(previous) = identity.previous_fingerprint() {
+            info.push_str(&format!("\nPreviousFingerprint: {}", previous));
+        }
+
+        if let Some(nonce) = nonce.filter(|n| !n.is_empty()) {
+            let mut proofs = identity.prove(&nonce).into_iter();
+            if let Some(current) = proofs.next() {
+                info.push_str(&format!(
+                    "\nPublicKey: {}\nSignature: {}",
+                    current.public_key, current.signature
+                ));
+            }
+            if let Some(previous) = proofs.next() {
+                info.push_str(&format!(
+                    "\nPreviousPublicKey: {}\nPreviousSignature: {}",
+                    previous.public_key, previous.signature
+                ));
+            }
+        }
+
+        if let Some(user) = cert_user {
+            info.push_str(&format!("\nAuthenticated: {}", user));
+        }
+        if let Some(db_name) = routed_db {
+            info.push_str(&format!("\nDatabase: {}", db_name));
+        }
+
+        VelocityMessage::new(MessageType::ServerInfo, info.into_bytes())
+    }
+
+    async fn handle_auth(
         &self,
         payload: Vec<u8>,
-        addr: SocketAddr,
+        peer: Peer,
     ) -> VeloResult<Option<VelocityMessage>> {
 
+        let local = peer.ip().is_unspecified();
+        if !local && self.access.check(peer.ip()).is_err() {
+            return Ok(Some(VelocityMessage::new(
+                MessageType::AuthResponse,
+                b"Authentication failed".to_vec(),
+            )));
+        }
+
         let auth_data = String::from_utf8_lossy(&payload);
         let parts: Vec<&str> = auth_data.split('\0').collect();
 
-        if parts.len() != 2 {
+        if parts.len() != 2 && parts.len() != 3 {
             return Ok(Some(VelocityMessage::new(
                 MessageType::AuthResponse,
                 b"Invalid auth format".to_vec(),
@@ -511,76 +2018,547 @@ impl VelocityServer {
 
         let username = parts[0];
         let password = parts[1];
+        let new_password = parts.get(2).copied();
 
+        let is_api_key =
+            username == "apikey" && password.starts_with("vdb_") && new_password.is_none();
+        let listener = match self.clients.read().await.get(&peer.id) {
+            Some(client) => client.listener.clone(),
+            None => None,
+        };
+        if let Some(listener) = listener {
+            if (is_api_key && !listener.allow_api_keys)
+                || (!is_api_key && !listener.allow_password_auth)
+            {
+                log::warn!(
+                    "Rejected {} login from {} on listener {}",
+                    if is_api_key { "API key" } else { "password" },
+                    peer,
+                    listener.bind_address
+                );
+                return Ok(Some(VelocityMessage::new(
+                    MessageType::AuthResponse,
+                    b"Authentication method not allowed on this listener".to_vec(),
+                )));
+            }
+        }
 
-        if username == "apikey" && password.starts_with("vdb_") {
-            if let Some(default_db) = self.db_manager.get_database("default") {
-                let auth_key = format!("auth:keys:{}", password);
-                if let Ok(Some(db_name_bytes)) = default_db.get(&auth_key) {
-                    let db_name = String::from_utf8_lossy(&db_name_bytes).to_string();
+        if is_api_key {
+            let system_db = self.db_manager.system_database();
+            let auth_key = format!("auth:keys:{}", password);
+            if let Ok(Some(db_name_bytes)) = system_db.get(&auth_key) {
+                let db_name = String::from_utf8_lossy(&db_name_bytes).to_string();
 
-                    {
-                        let mut clients = self.clients.write().await;
-                        if let Some(client) = clients.get_mut(&addr) {
-                            client.authenticated = true;
-                            client.username = Some(username.to_string());
-                            client.current_db = db_name.clone();
-                        }
+                {
+                    let mut clients = self.clients.write().await;
+                    if let Some(client) = clients.get_mut(&peer.id) {
+                        client.authenticated = true;
+                        client.username = Some(username.to_string());
+                        client.api_key_id = Some(api_key_id(password));
+                        client.current_db = db_name.clone();
                     }
-                    log::info!(
-                        "Dynamic API Key validated. Scoped to database '{}' from {}",
-                        db_name,
-                        addr
-                    );
+                }
+                self.access.record_auth_success(peer.ip());
+                log::info!(
+                    "Dynamic API Key validated. Scoped to database '{}' from {}",
+                    db_name,
+                    peer
+                );
+                return Ok(Some(VelocityMessage::new(
+                    MessageType::AuthResponse,
+                    b"OK".to_vec(),
+                )));
+            }
+        }
+
+
+        let authenticated = match self.auth.authenticate(username, password).await {
+            Ok(user) => user,
+            Err(e) => {
+                log::error!("Auth provider error for {} from {}: {}", username, peer, e);
+                None
+            }
+        };
+
+        if let Some(user) = authenticated {
+            let principal = user.principal.as_str();
+            let system_db = self.db_manager.system_database();
+            let mut meta = load_user_meta(&system_db, principal)?.unwrap_or_default();
+
+            if meta.disabled {
+                log::warn!("Login attempt for disabled user {} from {}", principal, peer);
+                return Ok(Some(VelocityMessage::new(
+                    MessageType::AuthResponse,
+                    b"Account disabled".to_vec(),
+                )));
+            }
+
+            if let Some(new_password) = new_password {
+                if !user.can_change_password {
+                    return Ok(Some(VelocityMessage::new(
+                        MessageType::AuthResponse,
+                        format!("Password changes are not supported for realm '{}'", user.realm)
+                            .into_bytes(),
+                    )));
+                }
+                if let Err(e) = self.change_password(&user.username, new_password) {
                     return Ok(Some(VelocityMessage::new(
                         MessageType::AuthResponse,
-                        b"OK".to_vec(),
+                        e.to_string().into_bytes(),
                     )));
                 }
+                meta.record_password_change();
+                log::info!("User {} changed password from {}", principal, peer);
+            } else if user.can_change_password
+                && meta.needs_password_change(&self.config.password_policy)
+            {
+                return Ok(Some(VelocityMessage::new(
+                    MessageType::AuthResponse,
+                    b"Password change required".to_vec(),
+                )));
+            }
+
+            meta.record_login();
+            save_user_meta(&system_db, principal, &meta)?;
+
+            {
+                let mut clients = self.clients.write().await;
+                if let Some(client) = clients.get_mut(&peer.id) {
+                    client.authenticated = true;
+                    client.username = Some(principal.to_string());
+                }
             }
+
+            self.access.record_auth_success(peer.ip());
+            log::info!(
+                "User {} authenticated via {} realm from {}",
+                principal,
+                user.realm,
+                peer
+            );
+            return Ok(Some(VelocityMessage::new(
+                MessageType::AuthResponse,
+                b"OK".to_vec(),
+            )));
+        }
+
+        log::warn!(
+            "Failed authentication attempt for {} from {}",
+            username,
+            peer
+        );
+        if !local && self.access.record_auth_failure(peer.ip()) {
+            log::warn!(
+                "Blocking {} for {:?} after {} failed logins",
+                peer.ip(),
+                self.config.auth_ban_duration,
+                self.config.max_auth_failures
+            );
         }
+        Ok(Some(VelocityMessage::new(
+            MessageType::AuthResponse,
+            b"Authentication failed".to_vec(),
+        )))
+    }
 
+    fn change_password(&self, username: &str, new_password: &str) -> VeloResult<()> {
+        self.config.password_policy.validate(new_password)?;
+        let hash = hash_password(new_password)?;
+        self.db_manager.set_user_password_hash(username, &hash)?;
+        self.users
+            .write()
+            .unwrap()
+            .insert(username.to_string(), hash);
+        Ok(())
+    }
 
-        if let Some(stored_hash) = self.config.users.get(username) {
-            let argon2 = Argon2::default();
-            if let Ok(parsed_hash) = PasswordHash::new(stored_hash) {
-                if argon2
-                    .verify_password(password.as_bytes(), &parsed_hash)
-                    .is_ok()
-                {
+    fn handle_user_admin(&self, sql: &str, sql_upper: &str) -> VeloResult<VelocityMessage> {
+        let db = self.db_manager.system_database();
+
+        if sql_upper == "SHOW USERS" {
+            let mut usernames: Vec<String> = self.users.read().unwrap().keys().cloned().collect();
+            usernames.sort();
+
+            let mut users = Vec::new();
+            for username in usernames {
+                let meta = load_user_meta(&db, &username)?;
+                let password_expired = meta
+                    .as_ref()
+                    .map(|m| m.needs_password_change(&self.config.password_policy))
+                    .unwrap_or(false);
+                users.push(serde_json::json!({
+                    "username": username,
+                    "meta": meta,
+                    "password_change_required": password_expired
+                }));
+            }
+            let response = serde_json::to_vec(&users).unwrap();
+            return Ok(VelocityMessage::new(MessageType::Response, response));
+        }
 
-                    {
-                        let mut clients = self.clients.write().await;
-                        if let Some(client) = clients.get_mut(&addr) {
-                            client.authenticated = true;
-                            client.username = Some(username.to_string());
-                        }
+        let parts: Vec<&str> = sql.split_whitespace().collect();
+        if parts.len() < 4 {
+            return Err(VeloError::InvalidOperation(
+                "Usage: ALTER USER <name> ENABLE | DISABLE | REQUIRE PASSWORD CHANGE".to_string(),
+            ));
+        }
+        let username = parts[2];
+        if !self.users.read().unwrap().contains_key(username) && !username.contains('@') {
+            return Err(VeloError::InvalidOperation(format!(
+                "User '{}' not found",
+                username
+            )));
+        }
+
+        let action = parts[3..].join(" ").to_uppercase();
+        let action = action.trim_end_matches(';');
+        let mut meta = load_user_meta(&db, username)?.unwrap_or_default();
+        let msg = match action {
+            "DISABLE" => {
+                meta.disabled = true;
+                format!("User '{}' disabled", username)
+            }
+            "ENABLE" => {
+                meta.disabled = false;
+                format!("User '{}' enabled", username)
+            }
+            "REQUIRE PASSWORD CHANGE" => {
+                meta.must_change_password = true;
+                format!("User '{}' must change password at next login", username)
+            }
+            _ => {
+                return Err(VeloError::InvalidOperation(format!(
+                    "Unknown ALTER USER action: {}",
+                    action
+                )))
+            }
+        };
+        save_user_meta(&db, username, &meta)?;
+
+        Ok(VelocityMessage::new(MessageType::Response, msg.into_bytes()))
+    }
+
+    fn handle_replication(&self, sql: &str, current_db: &str) -> VeloResult<VelocityMessage> {
+        let db = self.db_manager.get_database(current_db).ok_or_else(|| {
+            VeloError::KeyNotFound(format!("Database '{}' not found", current_db))
+        })?;
+        let parts: Vec<&str> = sql.trim().trim_end_matches(';').split_whitespace().collect();
+        let offset = |index: usize| {
+            parts
+                .get(index)
+                .and_then(|s| s.parse::<u64>().ok())
+                .ok_or_else(|| VeloError::InvalidOperation("Expected a byte offset".to_string()))
+        };
+        let replica = || {
+            parts
+                .get(2)
+                .copied()
+                .ok_or_else(|| VeloError::InvalidOperation("Expected a replica name".to_string()))
+        };
+
+        let payload = match parts.get(1).map(|s| s.to_uppercase()).as_deref() {
+            Some("CHECKPOINT") => {
+                let replica = replica()?;
+                let checkpoint = db.create_replica_checkpoint(replica)?;
+                log::info!(
+                    "Created checkpoint of '{}' for replica '{}': {} SSTables, WAL at {}:{}",
+                    current_db,
+                    replica,
+                    checkpoint.files.len(),
+                    checkpoint.wal_segment,
+                    checkpoint.wal_offset
+                );
+                serde_json::to_vec(&checkpoint).unwrap()
+            }
+            Some("FETCH") => {
+                let name = parts.get(2).ok_or_else(|| {
+                    VeloError::InvalidOperation("Usage: REPLICA FETCH <sstable> <offset>".to_string())
+                })?;
+                db.read_sstable_chunk(name, offset(3)?, CHUNK_SIZE)?
+            }
+            Some("WAL") => db
+                .read_wal(replica()?, offset(3)?, offset(4)?, CHUNK_SIZE)?
+                .encode(),
+            Some("FORGET") => {
+                let replica = replica()?;
+                match db.forget_replica(replica)? {
+                    true => format!("Replica '{}' forgotten", replica),
+                    false => format!("Replica '{}' was not registered", replica),
+                }
+                .into_bytes()
+            }
+            _ => {
+                return Err(VeloError::InvalidOperation(
+                    "Usage: REPLICA CHECKPOINT <replica> | REPLICA FETCH <sstable> <offset> | REPLICA WAL <replica> <segment> <offset> | REPLICA FORGET <replica>"
+                        .to_string(),
+                ))
+            }
+        };
+
+        Ok(VelocityMessage::new(MessageType::Response, payload))
+    }
+
+    fn handle_access_admin(&self, sql: &str, sql_upper: &str) -> VeloResult<VelocityMessage> {
+        if sql_upper == "SHOW BLOCKS" {
+            let response = serde_json::to_vec(&self.access.blocks()).unwrap();
+            return Ok(VelocityMessage::new(MessageType::Response, response));
+        }
+
+        let parts: Vec<&str> = sql
+            .trim()
+            .trim_end_matches(';')
+            .split_whitespace()
+            .collect();
+        if parts.len() < 3 {
+            return Err(VeloError::InvalidOperation(
+                "Usage: BLOCK IP <cidr> [FOR <seconds>] | UNBLOCK IP <cidr>".to_string(),
+            ));
+        }
+        let cidr: Cidr = parts[2].parse()?;
+
+        let msg = if sql_upper.starts_with("UNBLOCK") {
+            if self.access.unblock(cidr) {
+                format!("Unblocked {}", cidr)
+            } else {
+                format!("No temporary block for {}", cidr)
+            }
+        } else {
+            let duration = match parts.get(3) {
+                Some(kw) if kw.eq_ignore_ascii_case("FOR") => {
+                    let secs = parts.get(4).and_then(|s| s.parse::<u64>().ok()).ok_or_else(|| {
+                        VeloError::InvalidOperation("BLOCK IP ... FOR expects seconds".to_string())
+                    })?;
+                    Duration::from_secs(secs)
+                }
+                _ => self.config.auth_ban_duration,
+            };
+            self.access.block(cidr, duration, "blocked by administrator");
+            format!("Blocked {} for {} seconds", cidr, duration.as_secs())
+        };
+
+        Ok(VelocityMessage::new(MessageType::Response, msg.into_bytes()))
+    }
+
+    async fn handle_batch_write(
+        &self,
+        payload: &[u8],
+        peer: Peer,
+        current_db: &str,
+    ) -> VeloResult<Option<VelocityMessage>> {
+        let error = |e: VeloError| {
+            Ok(Some(VelocityMessage::new(
+                MessageType::Error,
+                format!("Batch Error: {}", e).into_bytes(),
+            )))
+        };
+        let ops = match decode_batch_ops(payload) {
+            Ok(ops) => ops,
+            Err(e) => return error(e),
+        };
+        let Some(db) = self.db_manager.get_database(current_db) else {
+            return error(VeloError::KeyNotFound(format!(
+                "Database '{}' not found",
+                current_db
+            )));
+        };
+        if let Err(e) = self.db_manager.can_accept_write(current_db) {
+            return error(e);
+        }
+
+
+        let privileged = self.is_privileged(peer).await;
+        let policy = self.db_manager.key_policy(current_db);
+        let failures: Vec<Option<String>> = ops
+            .iter()
+            .map(|op| {
+                let check = match op {
+                    BatchOp::Put { value, .. } if value.is_empty() => Err(VeloError::InvalidOperation(
+                        "Empty values are not allowed; use a delete operation".to_string(),
+                    )),
+                    _ if op.key().is_empty() => {
+                        Err(VeloError::InvalidOperation("Empty key".to_string()))
                     }
+                    _ => policy.check(op.key(), privileged),
+                };
+                check.err().map(|e| e.to_string())
+            })
+            .collect();
+
+        let applied = failures.iter().all(Option::is_none);
+        let results = if applied {
+            if let Err(e) = db.write_batch(&ops) {
+                return error(e);
+            }
+            failures
+                .into_iter()
+                .map(|_| BatchOpResult {
+                    status: BatchOpStatus::Ok,
+                    error: None,
+                })
+                .collect()
+        } else {
+            failures
+                .into_iter()
+                .map(|failure| BatchOpResult {
+                    status: if failure.is_some() {
+                        BatchOpStatus::Error
+                    } else {
+                        BatchOpStatus::NotApplied
+                    },
+                    error: failure,
+                })
+                .collect()
+        };
+
+        let response = serde_json::to_vec(&BatchWriteResult { applied, results }).unwrap();
+        Ok(Some(VelocityMessage::new(MessageType::Response, response)))
+    }
+
+    // The response is 0x01 followed by the stored bytes, or 0x00 when the key
+    // is missing or deleted.
+    fn handle_get(&self, payload: &[u8], current_db: &str) -> VeloResult<Option<VelocityMessage>> {
+        let key = std::str::from_utf8(payload)
+            .map_err(|_| VeloError::InvalidOperation("Key is not valid UTF-8".to_string()))?;
+        let Some(db) = self.db_manager.get_database(current_db) else {
+            return Ok(Some(VelocityMessage::new(
+                MessageType::Error,
+                b"Current database not found".to_vec(),
+            )));
+        };
+        let response = match db.get(key)? {
+            Some(value) => [&[0x01], value.as_slice()].concat(),
+            None => vec![0x00],
+        };
+        Ok(Some(VelocityMessage::new(MessageType::Response, response)))
+    }
+
+    // Runs a command or batch write, deduplicated by its idempotency key
+    // when it carries one and writes.
+    async fn handle_idempotent(
+        &self,
+        message: VelocityMessage,
+        peer: Peer,
+        current_db: &str,
+    ) -> VeloResult<Option<VelocityMessage>> {
+        let is_batch = message.msg_type == MessageType::BatchWrite;
+        let (token, body) = split_idempotency_key(&message.payload);
+        let body = body.to_vec();
+        let Some(token) = token.filter(|_| {
+            self.idempotency.is_enabled()
+                && (is_batch || is_write_statement(&String::from_utf8_lossy(&body)))
+        }) else {
+            return self.run_idempotent_body(is_batch, body, peer, current_db).await;
+        };
+
+        let principal = {
+            let clients = self.clients.read().await;
+            clients
+                .get(&peer.id)
+                .and_then(ClientState::principal)
+                .unwrap_or_else(|| peer.to_string())
+        };
+        let key = format!("{}\0{}\0{}", principal, current_db, token);
+        match self.idempotency.claim(&key) {
+            Claim::Completed(msg_type, payload) => {
+                log::debug!("Replaying response for idempotency key '{}' from {}", token, peer);
+                return Ok(Some(VelocityMessage::new(msg_type, payload)));
+            }
+            Claim::InProgress => {
+                return Ok(Some(VelocityMessage::new(
+                    MessageType::Error,
+                    format!(
+                        "A request with idempotency key '{}' is still in progress",
+                        token
+                    )
+                    .into_bytes(),
+                )));
+            }
+            Claim::New => {}
+        }
+
+        let result = self.run_idempotent_body(is_batch, body, peer, current_db).await;
+        match &result {
+            Ok(Some(response)) if response.msg_type == MessageType::Response => {
+                self.idempotency
+                    .complete(&key, response.msg_type, response.payload.clone());
+            }
+            _ => self.idempotency.release(&key),
+        }
+        result
+    }
+
+    async fn run_idempotent_body(
+        &self,
+        is_batch: bool,
+        body: Vec<u8>,
+        peer: Peer,
+        current_db: &str,
+    ) -> VeloResult<Option<VelocityMessage>> {
+        if is_batch {
+            self.handle_batch_write(&body, peer, current_db).await
+        } else {
+            self.handle_command(body, peer, current_db).await
+        }
+    }
 
-                    log::info!("User {} authenticated from {}", username, addr);
-                    return Ok(Some(VelocityMessage::new(
-                        MessageType::AuthResponse,
-                        b"OK".to_vec(),
-                    )));
-                }
-            }
+    /// Writes a key that is deleted again when this connection closes.
+    async fn put_temporary(
+        &self,
+        sql: &str,
+        peer: Peer,
+        current_db: &str,
+    ) -> VeloResult<QueryResult> {
+        let started = Instant::now();
+        let (key, value) = parse_put_temporary(sql).ok_or_else(|| {
+            VeloError::InvalidOperation("Usage: PUT TEMPORARY '<key>' '<value>'".to_string())
+        })?;
+        let db = self
+            .db_manager
+            .get_database(current_db)
+            .ok_or_else(|| VeloError::KeyNotFound("Current database not found".to_string()))?;
+        self.db_manager.can_accept_write(current_db)?;
+        let privileged = self.is_privileged(peer).await;
+        self.db_manager.key_policy(current_db).check(&key, privileged)?;
+
+        // Written as a batch so it serializes with the conditional delete
+        // that releases it.
+        let value = value.into_bytes();
+        db.write_batch(&[BatchOp::Put {
+            key: key.clone(),
+            value: value.clone(),
+        }])?;
+        if let Some(client) = self.clients.write().await.get_mut(&peer.id) {
+            client.temporary_keys.insert((current_db.to_string(), key), value);
         }
+        Ok(QueryResult {
+            success: true,
+            rows_affected: 1,
+            data: Vec::new(),
+            columns: Vec::new(),
+            execution_time_ms: started.elapsed().as_millis() as u64,
+            next_cursor: None,
+        })
+    }
 
-        log::warn!(
-            "Failed authentication attempt for {} from {}",
-            username,
-            addr
-        );
-        Ok(Some(VelocityMessage::new(
-            MessageType::AuthResponse,
-            b"Authentication failed".to_vec(),
-        )))
+    // Administrators are the local accounts and the principals listed in
+    // `[security] admin_users`; API-key sessions never are.
+    async fn is_privileged(&self, peer: Peer) -> bool {
+        let username = {
+            let clients = self.clients.read().await;
+            match clients.get(&peer.id) {
+                Some(c) if c.authenticated && c.api_key_id.is_none() => c.username.clone(),
+                _ => None,
+            }
+        };
+        username.is_some_and(|u| {
+            self.users.read().unwrap().contains_key(&u) || self.config.admin_users.contains(&u)
+        })
     }
 
     async fn handle_command(
         &self,
         payload: Vec<u8>,
-        addr: SocketAddr,
+        peer: Peer,
         current_db: &str,
     ) -> VeloResult<Option<VelocityMessage>> {
         let sql = String::from_utf8_lossy(&payload);
@@ -588,15 +2566,86 @@ impl VelocityServer {
 
         {
             let mut clients = self.clients.write().await;
-            if let Some(client) = clients.get_mut(&addr) {
+            if let Some(client) = clients.get_mut(&peer.id) {
                 client.command_count += 1;
             }
         }
 
 
         let sql_upper = sql.trim().to_uppercase();
-        if sql_upper.starts_with("CREATE DATABASE") {
-            let parts: Vec<&str> = sql.trim().split_whitespace().collect();
+        let is_user_admin = sql_upper == "SHOW USERS" || sql_upper.starts_with("ALTER USER");
+        let is_access_admin = sql_upper == "SHOW BLOCKS"
+            || sql_upper.starts_with("BLOCK IP")
+            || sql_upper.starts_with("UNBLOCK IP");
+        let is_replication = sql_upper.starts_with("REPLICA ");
+        let is_drain = sql_upper == "DRAIN SERVER";
+        let is_plan_cache =
+            sql_upper == "SHOW PLAN CACHE" || sql_upper.starts_with("CLEAR PLAN CACHE");
+        let is_usage = sql_upper == "SHOW USAGE" || sql_upper.starts_with("SHOW USAGE ");
+        let is_maintenance =
+            sql_upper == "SHOW MAINTENANCE" || sql_upper.starts_with("SHOW MAINTENANCE ");
+        if is_user_admin
+            || is_access_admin
+            || is_replication
+            || is_drain
+            || is_plan_cache
+            || is_usage
+            || is_maintenance
+        {
+            if !self.is_privileged(peer).await {
+                return Ok(Some(VelocityMessage::new(
+                    MessageType::Error,
+                    b"Administration commands require an administrator account".to_vec(),
+                )));
+            }
+            let username = {
+                let clients = self.clients.read().await;
+                clients.get(&peer.id).and_then(|c| c.username.clone())
+            };
+
+            let result = if is_user_admin {
+                self.handle_user_admin(&sql, &sql_upper)
+            } else if is_drain {
+                log::info!("Drain requested by {}", peer);
+                self.request_drain();
+                Ok(VelocityMessage::new(MessageType::Response, b"Draining".to_vec()))
+            } else if is_plan_cache {
+                self.handle_plan_cache(&sql, &sql_upper, current_db)
+            } else if is_usage {
+                self.handle_usage(&sql)
+            } else if is_maintenance {
+                self.handle_maintenance(&sql_upper)
+            } else if is_replication {
+                self.handle_replication(&sql, current_db)
+            } else {
+                self.handle_access_admin(&sql, &sql_upper)
+            };
+            self.audit(
+                "admin_command",
+                serde_json::json!({
+                    "request_id": request_id::current(),
+                    "user": username,
+                    "peer": peer.to_string(),
+                    "command": sql.trim(),
+                    "ok": result.is_ok(),
+                }),
+            );
+            return match result {
+                Ok(response) => Ok(Some(response)),
+                Err(e) => Ok(Some(VelocityMessage::new(
+                    MessageType::Error,
+                    e.to_string().into_bytes(),
+                ))),
+            };
+        } else if let Some(command) = strip_dry_run(&sql) {
+            return Ok(Some(
+                match self.handle_dry_run(command, peer, current_db).await {
+                    Ok(response) => response,
+                    Err(e) => VelocityMessage::new(MessageType::Error, e.to_string().into_bytes()),
+                },
+            ));
+        } else if sql_upper.starts_with("CREATE DATABASE") {
+            let parts: Vec<&str> = sql.split_whitespace().collect();
             if parts.len() >= 3 {
                 let db_name = parts[2];
                 match self.db_manager.create_database(db_name, None) {
@@ -617,7 +2666,7 @@ impl VelocityServer {
                 }
             }
         } else if sql_upper.starts_with("DROP DATABASE") {
-            let parts: Vec<&str> = sql.trim().split_whitespace().collect();
+            let parts: Vec<&str> = sql.split_whitespace().collect();
             if parts.len() >= 3 {
                 let db_name = parts[2];
                 match self.db_manager.drop_database(db_name) {
@@ -637,8 +2686,227 @@ impl VelocityServer {
                     }
                 }
             }
+        } else if sql_upper.starts_with("ALTER DATABASE") {
+            let parts: Vec<&str> = sql.split_whitespace().collect();
+            let setting = parts.get(3..).map(|rest| rest.join("")).unwrap_or_default();
+            let setting = setting.to_lowercase();
+            let parsed = setting
+                .trim_end_matches(';')
+                .strip_prefix("set")
+                .and_then(|s| s.split_once('='))
+                .and_then(|(option, value)| match (option, value) {
+                    ("cache_policy", _) => Some((option.to_string(), false)),
+                    (_, "true" | "on") => Some((option.to_string(), true)),
+                    (_, "false" | "off") => Some((option.to_string(), false)),
+                    _ => None,
+                });
+            let (Some(db_name), Some((option, enabled))) = (parts.get(2), parsed) else {
+                return Ok(Some(VelocityMessage::new(
+                    MessageType::Error,
+                    b"Usage: ALTER DATABASE <name> SET read_only | record_metadata | soft_delete | nocase_keys = true|false \
+                      or SET cache_policy = write_through | write_around | read_fill"
+                        .to_vec(),
+                )));
+            };
+
+            let result = match option.as_str() {
+                "cache_policy" => setting
+                    .split_once('=')
+                    .map(|(_, value)| value.trim_end_matches(';'))
+                    .unwrap_or_default()
+                    .parse::<CachePolicy>()
+                    .and_then(|policy| {
+                        self.db_manager.set_cache_policy(db_name, policy)?;
+                        Ok(format!(
+                            "Cache policy of database '{}' is now {}",
+                            db_name,
+                            policy.name()
+                        ))
+                    }),
+                "read_only" => self.db_manager.set_read_only(db_name, enabled).map(|_| {
+                    format!(
+                        "Database '{}' is now {}",
+                        db_name,
+                        if enabled { "read-only" } else { "writable" }
+                    )
+                }),
+                "record_metadata" => self
+                    .db_manager
+                    .set_record_metadata(db_name, enabled)
+                    .map(|_| {
+                        format!(
+                            "Record metadata {} for database '{}'",
+                            if enabled { "enabled" } else { "disabled" },
+                            db_name
+                        )
+                    }),
+                "soft_delete" => self.db_manager.set_soft_delete(db_name, enabled).map(|_| {
+                    format!(
+                        "Soft delete {} for database '{}'",
+                        if enabled { "enabled" } else { "disabled" },
+                        db_name
+                    )
+                }),
+                "nocase_keys" => self.db_manager.set_nocase_keys(db_name, enabled).map(|_| {
+                    format!(
+                        "Case-insensitive keys {} for database '{}'",
+                        if enabled { "enabled" } else { "disabled" },
+                        db_name
+                    )
+                }),
+                other => Err(VeloError::InvalidOperation(format!(
+                    "Unknown database option '{}'",
+                    other
+                ))),
+            };
+
+            return match result {
+                Ok(msg) => Ok(Some(VelocityMessage::new(
+                    MessageType::Response,
+                    msg.into_bytes(),
+                ))),
+                Err(e) => Ok(Some(VelocityMessage::new(
+                    MessageType::Error,
+                    format!("Failed to alter database: {}", e).into_bytes(),
+                ))),
+            };
+        } else if sql_upper.starts_with("COMPACT DATABASE") {
+            let parts: Vec<&str> = sql.trim().trim_end_matches(';').split_whitespace().collect();
+            let action = parts.get(3).map(|a| a.to_uppercase());
+            let (Some(db_name), Some(action)) = (parts.get(2), action) else {
+                return Ok(Some(VelocityMessage::new(
+                    MessageType::Error,
+                    b"Usage: COMPACT DATABASE <name> FULL | STATUS | CANCEL".to_vec(),
+                )));
+            };
+
+            let result = match action.as_str() {
+                "FULL" => self
+                    .db_manager
+                    .start_full_compaction(db_name)
+                    .map(|_| format!("Full compaction of '{}' started", db_name)),
+                "STATUS" => match self.db_manager.compaction_progress(db_name) {
+                    Some(progress) => serde_json::to_string(&progress)
+                        .map_err(|e| VeloError::InvalidOperation(e.to_string())),
+                    None => Err(VeloError::InvalidOperation(format!(
+                        "No full compaction of '{}' has been started",
+                        db_name
+                    ))),
+                },
+                "CANCEL" => self
+                    .db_manager
+                    .cancel_compaction(db_name)
+                    .map(|_| format!("Cancelling full compaction of '{}'", db_name)),
+                other => Err(VeloError::InvalidOperation(format!(
+                    "Unknown compaction action '{}'",
+                    other
+                ))),
+            };
+
+            return match result {
+                Ok(msg) => Ok(Some(VelocityMessage::new(
+                    MessageType::Response,
+                    msg.into_bytes(),
+                ))),
+                Err(e) => Ok(Some(VelocityMessage::new(
+                    MessageType::Error,
+                    e.to_string().into_bytes(),
+                ))),
+            };
+        } else if sql_upper.starts_with("RESTORE KEY") || sql_upper.starts_with("PURGE TRASH") {
+            let Some(db) = self.db_manager.get_database(current_db) else {
+                return Ok(Some(VelocityMessage::new(
+                    MessageType::Error,
+                    b"Current database not found".to_vec(),
+                )));
+            };
+            let result = self.db_manager.can_accept_write(current_db).and_then(|_| {
+                if sql_upper.starts_with("PURGE TRASH") {
+                    db.purge_trash(None)
+                        .map(|count| format!("Purged {} trashed keys", count))
+                } else {
+                    let key = sql
+                        .trim()
+                        .get("RESTORE KEY".len()..)
+                        .unwrap_or_default()
+                        .trim()
+                        .trim_end_matches(';')
+                        .trim_matches(|c| c == '\'' || c == '"');
+                    match db.restore(key)? {
+                        true => Ok(format!("Restored key '{}'", key)),
+                        false => Err(VeloError::KeyNotFound(format!(
+                            "No trashed entry for key '{}'",
+                            key
+                        ))),
+                    }
+                }
+            });
+            return match result {
+                Ok(msg) => Ok(Some(VelocityMessage::new(
+                    MessageType::Response,
+                    msg.into_bytes(),
+                ))),
+                Err(e) => Ok(Some(VelocityMessage::new(
+                    MessageType::Error,
+                    e.to_string().into_bytes(),
+                ))),
+            };
+        } else if sql_upper.starts_with("PUT TEMPORARY") {
+            return Ok(Some(match self.put_temporary(&sql, peer, current_db).await {
+                Ok(result) => VelocityMessage::new(
+                    MessageType::Response,
+                    serde_json::to_vec(&result).unwrap(),
+                ),
+                Err(e) => VelocityMessage::new(MessageType::Error, e.to_string().into_bytes()),
+            }));
+        } else if let Some(level) = sql_upper.strip_prefix("SET TRANSACTION ISOLATION LEVEL") {
+            let level = level
+                .trim()
+                .trim_end_matches(';')
+                .replace(' ', "_")
+                .to_lowercase();
+            return Ok(Some(match level.as_str() {
+                "read_committed" => VelocityMessage::new(
+                    MessageType::Response,
+                    b"Isolation level set to read_committed".to_vec(),
+                ),
+                "snapshot" => VelocityMessage::new(
+                    MessageType::Error,
+                    b"Isolation level 'snapshot' is not supported by the server; every statement reads the latest committed data (read_committed). Embedded callers can read a point-in-time view with Velocity::snapshot()".to_vec(),
+                ),
+                "repeatable_read" | "serializable" => VelocityMessage::new(
+                    MessageType::Error,
+                    format!(
+                        "Isolation level '{}' requires multi-statement transactions, which are not supported yet; every statement runs as read_committed",
+                        level
+                    )
+                    .into_bytes(),
+                ),
+                _ => VelocityMessage::new(
+                    MessageType::Error,
+                    b"Usage: SET TRANSACTION ISOLATION LEVEL read_committed".to_vec(),
+                ),
+            }));
+        } else if sql_upper == "SHOW TRANSACTION ISOLATION LEVEL" {
+            return Ok(Some(VelocityMessage::new(
+                MessageType::Response,
+                b"read_committed".to_vec(),
+            )));
+        } else if sql_upper == "SHOW METRICS" {
+            let response = serde_json::to_vec(&serde_json::json!({
+                "lifetime": self.db_manager.lifetime_stats(),
+                "current": self.db_manager.stats(),
+                "active_connections": self.clients.read().await.len(),
+                "statement_cache": self.statement_cache_stats(),
+                "result_cache": self.result_cache_stats()
+            }))
+            .unwrap();
+            return Ok(Some(VelocityMessage::new(MessageType::Response, response)));
         } else if sql_upper == "SHOW DATABASES" {
-            let list = self.db_manager.list_databases();
+            let mut list = self.db_manager.list_databases();
+            if self.is_privileged(peer).await {
+                list.push(SYSTEM_DATABASE.to_string());
+            }
             let response = serde_json::to_vec(&list).unwrap();
             return Ok(Some(VelocityMessage::new(MessageType::Response, response)));
         } else if sql_upper == "SHOW DATABASE DEFAULT MAX DISK SIZE" {
@@ -648,7 +2916,7 @@ impl VelocityServer {
             .unwrap();
             return Ok(Some(VelocityMessage::new(MessageType::Response, response)));
         } else if sql_upper.starts_with("SET DATABASE DEFAULT MAX DISK SIZE") {
-            let parts: Vec<&str> = sql.trim().split_whitespace().collect();
+            let parts: Vec<&str> = sql.split_whitespace().collect();
             if parts.len() >= 7 {
                 let raw_value = parts[6].trim_end_matches(';');
                 let normalized = raw_value.to_uppercase();
@@ -683,7 +2951,7 @@ impl VelocityServer {
                 )));
             }
         } else if sql_upper.starts_with("DATABASE STATS") {
-            let parts: Vec<&str> = sql.trim().split_whitespace().collect();
+            let parts: Vec<&str> = sql.split_whitespace().collect();
             let db_name = if parts.len() >= 3 {
                 parts[2]
             } else {
@@ -699,7 +2967,15 @@ impl VelocityServer {
                     "cache_entries": s.cache_entries,
                     "total_sstable_size": s.total_sstable_size,
                     "record_count": s.total_records,
-                    "size_bytes": s.total_size_bytes
+                    "size_bytes": s.total_size_bytes,
+                    "read_only": self.db_manager.is_read_only(db_name),
+                    "record_metadata": db.record_metadata_enabled(),
+                    "soft_delete": db.soft_delete_enabled(),
+                    "nocase_keys": db.nocase_index_enabled(),
+                    "cache_policy": db.cache_policy().name(),
+                    "scan_bytes": db.scan_bytes(),
+                    "scan_budget": self.db_manager.scan_budget(db_name),
+                    "tables": db.table_properties()
                 });
                 let response = serde_json::to_vec(&stats).unwrap();
                 return Ok(Some(VelocityMessage::new(MessageType::Response, response)));
@@ -709,14 +2985,44 @@ impl VelocityServer {
                     format!("Database '{}' not found", db_name).into_bytes(),
                 )));
             }
+        } else if sql_upper.starts_with("SHOW HOT KEYS") {
+            let parts: Vec<&str> = sql.split_whitespace().collect();
+            let db_name = parts.get(3).copied().unwrap_or(current_db);
+            let privileged = self.is_privileged(peer).await;
+            let db = self
+                .db_manager
+                .get_database(db_name)
+                .filter(|_| db_name != SYSTEM_DATABASE || privileged);
+            let Some(db) = db else {
+                return Ok(Some(VelocityMessage::new(
+                    MessageType::Error,
+                    format!("Database '{}' not found", db_name).into_bytes(),
+                )));
+            };
+            let Some(mut report) = db.hot_keys() else {
+                return Ok(Some(VelocityMessage::new(
+                    MessageType::Error,
+                    "Hot key tracking is off; enable [performance.hot_keys] in the config"
+                        .as_bytes()
+                        .to_vec(),
+                )));
+            };
+            // Keys under reserved prefixes are internal and only shown to
+            // administrators.
+            if !privileged {
+                report.hide_prefixes(&self.db_manager.key_policy(db_name).reserved_prefixes);
+            }
+            let response = serde_json::to_vec(&report).unwrap();
+            return Ok(Some(VelocityMessage::new(MessageType::Response, response)));
         } else if sql_upper.starts_with("USE") {
-            let parts: Vec<&str> = sql.trim().split_whitespace().collect();
+            let parts: Vec<&str> = sql.split_whitespace().collect();
             if parts.len() >= 2 {
                 let db_name = parts[1];
-                if self.db_manager.get_database(db_name).is_some() {
+                let visible = db_name != SYSTEM_DATABASE || self.is_privileged(peer).await;
+                if visible && self.db_manager.get_database(db_name).is_some() {
 
                     let mut clients = self.clients.write().await;
-                    if let Some(client) = clients.get_mut(&addr) {
+                    if let Some(client) = clients.get_mut(&peer.id) {
                         client.current_db = db_name.to_string();
                     }
                     let msg = format!("Switched to database '{}'", db_name);
@@ -735,15 +3041,11 @@ impl VelocityServer {
 
 
         if let Some(db) = self.db_manager.get_database(current_db) {
-            if Self::is_write_sql(&sql) {
-                if let Err(e) = self.db_manager.can_accept_write(current_db) {
-                    return Ok(Some(VelocityMessage::new(
-                        MessageType::Error,
-                        format!("SQL Error: {}", e).into_bytes(),
-                    )));
-                }
+            let result_cache = self.result_cache(current_db, &db);
+            let mut engine = self.sql_engine(db, peer, current_db).await;
+            if let Some(cache) = result_cache {
+                engine = engine.with_result_cache(cache);
             }
-            let engine = SqlEngine::new(db);
             match engine.execute(&sql).await {
                 Ok(result) => {
                     let response = serde_json::to_vec(&result)
@@ -766,31 +3068,124 @@ impl VelocityServer {
         }
     }
 
-    fn is_write_sql(sql: &str) -> bool {
-        let upper = sql.trim_start().to_uppercase();
-        upper.starts_with("INSERT") || upper.starts_with("UPDATE") || upper.starts_with("DELETE")
+    async fn sql_engine(&self, db: Arc<Velocity>, peer: Peer, current_db: &str) -> SqlEngine {
+        let privileged = self.is_privileged(peer).await;
+        self.db_manager
+            .sql_engine(db, current_db, privileged)
+            .with_statement_cache(self.statement_cache(current_db))
+            .with_json_values(self.config.json_values)
+    }
+
+    // DRY RUN DROP DATABASE <name> | PURGE TRASH | DELETE ...
+    async fn handle_dry_run(
+        &self,
+        command: &str,
+        peer: Peer,
+        current_db: &str,
+    ) -> VeloResult<VelocityMessage> {
+        let upper = command.trim().trim_end_matches(';').to_uppercase();
+        let report = if upper.starts_with("DROP DATABASE") {
+            let parts: Vec<&str> = command.trim_end_matches(';').split_whitespace().collect();
+            let Some(db_name) = parts.get(2) else {
+                return Err(VeloError::InvalidOperation(
+                    "Usage: DRY RUN DROP DATABASE <name>".to_string(),
+                ));
+            };
+            self.db_manager.drop_database_dry_run(db_name)?
+        } else {
+            let db = self.db_manager.get_database(current_db).ok_or_else(|| {
+                VeloError::InvalidOperation("Current database not found".to_string())
+            })?;
+            // Matched the way the command itself is.
+            if upper.starts_with("PURGE TRASH") {
+                db.purge_trash_dry_run(None)
+            } else {
+                self.sql_engine(db, peer, current_db)
+                    .await
+                    .dry_run(command)
+                    .await?
+            }
+        };
+        let payload = serde_json::to_vec(&report).map_err(|e| {
+            VeloError::InvalidOperation(format!("Failed to encode dry run report: {}", e))
+        })?;
+        Ok(VelocityMessage::new(MessageType::Response, payload))
     }
 
     async fn handle_stats(&self) -> VeloResult<Option<VelocityMessage>> {
+        let response = serde_json::to_vec(&self.stats_snapshot().await).unwrap();
+        Ok(Some(VelocityMessage::new(MessageType::Response, response)))
+    }
+
+    pub async fn stats_snapshot(&self) -> serde_json::Value {
         let db_stats = self.db_manager.stats();
+        let background_pool = self
+            .db_manager
+            .get_database("default")
+            .and_then(|db| db.background_pool().map(|pool| pool.stats()));
         let client_count = self.clients.read().await.len();
 
-        let stats = serde_json::json!({
+        serde_json::json!({
             "database": {
                 "memtable_entries": db_stats.memtable_entries,
                 "sstable_count": db_stats.sstable_count,
                 "cache_entries": db_stats.cache_entries,
-                "total_sstable_size": db_stats.total_sstable_size
+                "total_sstable_size": db_stats.total_sstable_size,
+                "background_pool": background_pool
+            },
+            "resources": {
+                "process": ProcessResources::sample(),
+                "cache_bytes": db_stats.cache_bytes,
+                "memtable_bytes": db_stats.memtable_bytes,
+                "memtable_arena": db_stats.memtable_arena
             },
             "server": {
                 "active_connections": client_count,
                 "max_connections": self.config.max_connections,
-                "server_fingerprint": self.server_fingerprint
-            }
-        });
+                "server_fingerprint": self.fingerprint(),
+                "previous_fingerprint": self.identity.read().unwrap().identity.previous_fingerprint(),
+                "tls": self.listeners.read().unwrap().first().is_some_and(|l| l.enable_tls),
+                "listeners": self.listeners.read().unwrap().iter().map(|l| serde_json::json!({
+                    "bind_address": l.bind_address,
+                    "tls": l.enable_tls,
+                    "password_auth": l.allow_password_auth,
+                    "api_keys": l.allow_api_keys
+                })).collect::<Vec<_>>(),
+                "state": self.state(),
+                "recovery": self.startup.progress()
+            },
+            "scheduler": self.scheduler.stats(),
+            "lifetime": self.db_manager.lifetime_stats()
+        })
+    }
+}
 
-        let response = serde_json::to_vec(&stats).unwrap();
-        Ok(Some(VelocityMessage::new(MessageType::Response, response)))
+pub struct ServerHandle {
+    local_addr: SocketAddr,
+    shutdown: Option<oneshot::Sender<()>>,
+    task: Option<tokio::task::JoinHandle<()>>,
+}
+
+impl ServerHandle {
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    pub async fn shutdown(mut self) {
+        if let Some(tx) = self.shutdown.take() {
+            let _ = tx.send(());
+        }
+        if let Some(task) = self.task.take() {
+            let _ = task.await;
+        }
+    }
+}
+
+impl Drop for ServerHandle {
+    fn drop(&mut self) {
+        if let Some(tx) = self.shutdown.take() {
+            let _ = tx.send(());
+        }
     }
 }
 
@@ -799,13 +3194,424 @@ impl Clone for VelocityServer {
         Self {
             db_manager: self.db_manager.clone(),
             config: self.config.clone(),
-            server_fingerprint: self.server_fingerprint.clone(),
+            identity: self.identity.clone(),
+            listeners: self.listeners.clone(),
+            tls_acceptors: self.tls_acceptors.clone(),
+            users: self.users.clone(),
+            auth: self.auth.clone(),
+            access: self.access.clone(),
             connection_semaphore: self.connection_semaphore.clone(),
             clients: self.clients.clone(),
+            scheduler: self.scheduler.clone(),
+            idempotency: self.idempotency.clone(),
+            startup: self.startup.clone(),
+            drain_request: self.drain_request.clone(),
+            listener_updates: self.listener_updates.clone(),
+            listener_update_lock: self.listener_update_lock.clone(),
+            statement_caches: self.statement_caches.clone(),
+            result_caches: self.result_caches.clone(),
+            started: self.started,
+        }
+    }
+}
+
+/// What [`VelocityServer::update_listeners`] changed, by address.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct ListenerChanges {
+    pub opened: Vec<SocketAddr>,
+    pub closed: Vec<SocketAddr>,
+    /// Addresses kept with different TLS, login or access settings.
+    pub reconfigured: Vec<SocketAddr>,
+}
+
+impl ListenerChanges {
+    fn between(current: &[ListenerConfig], next: &[ListenerConfig]) -> Self {
+        let find = |listeners: &[ListenerConfig], address: SocketAddr| {
+            listeners
+                .iter()
+                .find(|listener| listener.bind_address == address)
+                .cloned()
+        };
+        let mut changes = ListenerChanges::default();
+        for listener in next {
+            let address = listener.bind_address;
+            match find(current, address) {
+                None if !changes.opened.contains(&address) => changes.opened.push(address),
+                Some(old) if old != *listener && !changes.reconfigured.contains(&address) => {
+                    changes.reconfigured.push(address)
+                }
+                _ => {}
+            }
+        }
+        for listener in current {
+            if find(next, listener.bind_address).is_none() {
+                changes.closed.push(listener.bind_address);
+            }
+        }
+        // The same listeners in another order still need their sockets
+        // matched up again.
+        if changes.is_empty() && current != next {
+            changes.reconfigured = next.iter().map(|listener| listener.bind_address).collect();
+        }
+        changes
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.opened.is_empty() && self.closed.is_empty() && self.reconfigured.is_empty()
+    }
+}
+
+struct ListenerUpdate {
+    listeners: Vec<ListenerConfig>,
+    tls_acceptors: Vec<Option<TlsAcceptor>>,
+    // Sockets for the addresses that are new.
+    bound: HashMap<SocketAddr, TcpListener>,
+    // New addresses that were in use; bound after the closed ones go.
+    deferred: Vec<SocketAddr>,
+    done: oneshot::Sender<VeloResult<()>>,
+}
+
+async fn bind_addresses(
+    addresses: &[SocketAddr],
+    sockets: &mut HashMap<SocketAddr, TcpListener>,
+) -> VeloResult<()> {
+    for address in addresses {
+        let listener = TcpListener::bind(address).await.map_err(|e| {
+            VeloError::InvalidOperation(format!("Cannot bind {}: {}", address, e))
+        })?;
+        sockets.insert(*address, listener);
+    }
+    Ok(())
+}
+
+/// Sockets bound by [`VelocityServer::bind`] and handed to
+/// [`VelocityServer::serve`].
+pub struct BoundListeners {
+    tcp: Vec<TcpListener>,
+    #[cfg(unix)]
+    unix: Option<tokio::net::UnixListener>,
+}
+
+impl BoundListeners {
+    /// Binds the sockets `config` describes without a server, so a service
+    /// can take privileged ports before it gives up root and opens its
+    /// databases.
+    pub async fn bind(config: &ServerConfig) -> VeloResult<Self> {
+        Self::bind_with(&config.listener_configs(), config).await
+    }
+
+    async fn bind_with(listeners: &[ListenerConfig], config: &ServerConfig) -> VeloResult<Self> {
+        let mut tcp = Vec::with_capacity(listeners.len());
+        for listener in listeners {
+            tcp.push(TcpListener::bind(&listener.bind_address).await.map_err(|e| {
+                VeloError::InvalidOperation(format!(
+                    "Cannot bind {}: {}",
+                    listener.bind_address, e
+                ))
+            })?);
+            log::info!(
+                "VelocityDB server listening on {}{}",
+                listener.bind_address,
+                if listener.enable_tls { " (TLS)" } else { "" }
+            );
+        }
+
+        #[cfg(not(unix))]
+        if config.unix_socket_path.is_some() {
+            return Err(VeloError::InvalidOperation(
+                "unix_socket_path is only supported on Unix".to_string(),
+            ));
+        }
+
+        Ok(Self {
+            tcp,
+            #[cfg(unix)]
+            unix: match &config.unix_socket_path {
+                Some(path) => Some(bind_unix_socket(path, config.unix_socket_mode)?),
+                None => None,
+            },
+        })
+    }
+}
+
+impl BoundListeners {
+    /// Answers every connection on the plain listeners with a not-ready
+    /// error until `until` completes, so clients that connect while the
+    /// databases are still recovering see the startup state instead of
+    /// waiting. Connections to TLS listeners wait in the accept queue.
+    pub async fn answer_until<F>(
+        &self,
+        config: &ServerConfig,
+        startup: &StartupStatus,
+        until: F,
+    ) -> F::Output
+    where
+        F: std::future::Future,
+    {
+        let plain = self
+            .tcp
+            .iter()
+            .zip(config.listener_configs())
+            .filter(|(_, listener)| !listener.enable_tls)
+            .map(|(socket, _)| socket);
+        let tcp_loops = futures::future::join_all(plain.map(|socket| async move {
+            loop {
+                if let Ok((stream, _)) = socket.accept().await {
+                    tokio::spawn(answer_not_ready(stream, startup.clone()));
+                }
+            }
+        }));
+        #[cfg(unix)]
+        let unix_loop = async {
+            match &self.unix {
+                Some(socket) => loop {
+                    if let Ok((stream, _)) = socket.accept().await {
+                        tokio::spawn(answer_not_ready(stream, startup.clone()));
+                    }
+                },
+                None => std::future::pending().await,
+            }
+        };
+        #[cfg(not(unix))]
+        let unix_loop = std::future::pending::<()>();
+
+        // Without plain listeners the loops end at once; keep waiting.
+        let accepting = async {
+            futures::future::join(tcp_loops, unix_loop).await;
+            std::future::pending::<()>().await
+        };
+        tokio::select! {
+            output = until => output,
+            _ = accepting => unreachable!("accepting never completes"),
+        }
+    }
+}
+
+// Waits for the client's first frame, so the reply is read as its answer.
+async fn answer_not_ready<S>(mut stream: S, startup: StartupStatus)
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let mut buffer = BytesMut::with_capacity(1024);
+    let _ = timeout(Duration::from_secs(5), stream.read_buf(&mut buffer)).await;
+    let reply = VelocityMessage::new(
+        MessageType::Error,
+        format!("Server is not ready ({:?})", startup.state()).into_bytes(),
+    );
+    let _ = stream.write_all(&reply.encode()).await;
+    let _ = stream.shutdown().await;
+}
+
+impl From<Vec<TcpListener>> for BoundListeners {
+    fn from(tcp: Vec<TcpListener>) -> Self {
+        Self {
+            tcp,
+            #[cfg(unix)]
+            unix: None,
         }
     }
 }
 
+// Access to the socket is governed by its file mode. A leftover socket file
+// from a crashed server is replaced, but one a live server still answers on
+// is not.
+#[cfg(unix)]
+fn bind_unix_socket(path: &std::path::Path, mode: u32) -> VeloResult<tokio::net::UnixListener> {
+    use std::os::unix::fs::PermissionsExt;
+
+    if path.exists() {
+        if std::os::unix::net::UnixStream::connect(path).is_ok() {
+            return Err(VeloError::InvalidOperation(format!(
+                "Unix socket {:?} is already in use",
+                path
+            )));
+        }
+        std::fs::remove_file(path)?;
+    }
+    let listener = tokio::net::UnixListener::bind(path).map_err(|e| {
+        VeloError::InvalidOperation(format!("Cannot bind Unix socket {:?}: {}", path, e))
+    })?;
+    std::fs::set_permissions(path, std::fs::Permissions::from_mode(mode))?;
+    log::info!("VelocityDB server listening on unix:{} (mode {:o})", path.display(), mode);
+    Ok(listener)
+}
+
+/// A connected client. Session state is keyed by `id`, which is never
+/// reused; `addr` is only used for access rules and logging. In-memory and
+/// Unix socket peers have no IP address and get an unspecified one, which
+/// IP based access rules and bans skip.
+#[derive(Debug, Clone, Copy)]
+struct Peer {
+    id: u64,
+    addr: SocketAddr,
+}
+
+impl Peer {
+    fn new(addr: SocketAddr) -> Self {
+        Self {
+            id: CONNECTION_IDS.fetch_add(1, Ordering::Relaxed),
+            addr,
+        }
+    }
+
+    fn local() -> Self {
+        Self::new(SocketAddr::from(([0, 0, 0, 0], 0)))
+    }
+
+    fn ip(&self) -> IpAddr {
+        self.addr.ip()
+    }
+}
+
+impl fmt::Display for Peer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.addr.ip().is_unspecified() {
+            write!(f, "local#{}", self.id)
+        } else {
+            write!(f, "{}", self.addr)
+        }
+    }
+}
+
+#[derive(Default)]
+struct SessionInfo {
+    cert_user: Option<String>,
+    server_name: Option<String>,
+    listener: Option<ListenerConfig>,
+}
+
+
+fn parse_hello(payload: &[u8]) -> (Option<Vec<u8>>, Option<String>) {
+    let text = String::from_utf8_lossy(payload);
+    if !text.contains(": ") {
+        return (from_hex(text.trim()), None);
+    }
+
+    let mut nonce = None;
+    let mut tenant = None;
+    for (key, value) in text.lines().filter_map(|line| line.split_once(": ")) {
+        match key {
+            "Nonce" => nonce = from_hex(value.trim()),
+            "Tenant" => tenant = Some(value.trim().to_string()),
+            _ => {}
+        }
+    }
+    (nonce, tenant)
+}
+
+struct LoadedIdentity {
+    identity: ServerIdentity,
+    modified: Option<SystemTime>,
+}
+
+impl LoadedIdentity {
+    fn load(path: Option<&PathBuf>) -> VeloResult<Self> {
+        match path {
+            Some(path) => {
+                let identity = ServerIdentity::load_or_generate(path)?;
+                let modified = std::fs::metadata(path).and_then(|m| m.modified()).ok();
+                Ok(Self { identity, modified })
+            }
+            None => Ok(Self {
+                identity: ServerIdentity::generate(),
+                modified: None,
+            }),
+        }
+    }
+}
+
+// Rejects listener access lists that do not parse, so a bad entry fails the
+// start or reload instead of every connection.
+fn load_listener_access(listeners: &[ListenerConfig]) -> VeloResult<()> {
+    for listener in listeners {
+        AccessList::new(&listener.access)?;
+    }
+    Ok(())
+}
+
+fn load_tls_acceptors(listeners: &[ListenerConfig]) -> VeloResult<Vec<Option<TlsAcceptor>>> {
+    listeners.iter().map(load_tls_acceptor).collect()
+}
+
+fn load_tls_acceptor(config: &ListenerConfig) -> VeloResult<Option<TlsAcceptor>> {
+    if !config.enable_tls {
+        return Ok(None);
+    }
+
+    let (Some(cert_path), Some(key_path)) = (&config.cert_path, &config.key_path) else {
+        return Err(VeloError::InvalidOperation(format!(
+            "TLS is enabled on {} but cert_path/key_path are not configured",
+            config.bind_address
+        )));
+    };
+
+    let certs = load_certs(cert_path)?;
+    let key = load_private_key(key_path)?;
+
+    let builder = rustls::ServerConfig::builder().with_safe_defaults();
+    let builder = match &config.client_ca_path {
+        Some(ca_path) => {
+            let roots = load_root_store(ca_path)?;
+            if config.require_client_cert {
+                builder.with_client_cert_verifier(AllowAnyAuthenticatedClient::new(roots).boxed())
+            } else {
+                builder.with_client_cert_verifier(
+                    AllowAnyAnonymousOrAuthenticatedClient::new(roots).boxed(),
+                )
+            }
+        }
+        None if config.require_client_cert => {
+            return Err(VeloError::InvalidOperation(
+                "require_client_cert needs client_ca_path".to_string(),
+            ))
+        }
+        None => builder.with_no_client_auth(),
+    };
+    let tls_config = builder
+        .with_single_cert(certs, key)
+        .map_err(|e| VeloError::InvalidOperation(format!("Invalid TLS configuration: {}", e)))?;
+
+    Ok(Some(TlsAcceptor::from(Arc::new(tls_config))))
+}
+
+pub(crate) fn load_certs(path: &str) -> VeloResult<Vec<Certificate>> {
+    let certs = rustls_pemfile::certs(&mut BufReader::new(File::open(path)?))?
+        .into_iter()
+        .map(Certificate)
+        .collect::<Vec<_>>();
+    if certs.is_empty() {
+        return Err(VeloError::InvalidOperation(format!(
+            "No certificates found in {}",
+            path
+        )));
+    }
+    Ok(certs)
+}
+
+pub(crate) fn load_private_key(path: &str) -> VeloResult<PrivateKey> {
+    let mut keys = rustls_pemfile::pkcs8_private_keys(&mut BufReader::new(File::open(path)?))?;
+    if keys.is_empty() {
+        keys = rustls_pemfile::rsa_private_keys(&mut BufReader::new(File::open(path)?))?;
+    }
+    if keys.is_empty() {
+        keys = rustls_pemfile::ec_private_keys(&mut BufReader::new(File::open(path)?))?;
+    }
+    keys.into_iter()
+        .next()
+        .map(PrivateKey)
+        .ok_or_else(|| VeloError::InvalidOperation(format!("No private key found in {}", path)))
+}
+
+pub(crate) fn load_root_store(path: &str) -> VeloResult<RootCertStore> {
+    let mut roots = RootCertStore::empty();
+    for cert in load_certs(path)? {
+        roots.add(&cert).map_err(|e| {
+            VeloError::InvalidOperation(format!("Invalid CA certificate in {}: {}", path, e))
+        })?;
+    }
+    Ok(roots)
+}
+
 
 pub fn hash_password(password: &str) -> VeloResult<String> {
     let salt = SaltString::generate(&mut OsRng);
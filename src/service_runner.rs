@@ -4,9 +4,10 @@ use std::fs;
 use std::path::{Path, PathBuf};
 use std::sync::Arc;
 use tokio::sync::mpsc;
-use tokio::time::Duration;
-use velocity::server::{ServerConfig, VelocityServer};
-use velocity::{Velocity, VelocityConfig};
+use velocity::fsutil::atomic_write;
+use velocity::health::HealthProbe;
+use velocity::server::{BoundListeners, StartupStatus, VelocityServer};
+use velocity::VelocityConfig;
 use colored::*;
 
 pub struct ServiceSpec {
@@ -16,6 +17,9 @@ pub struct ServiceSpec {
     pub verbose: bool,
     pub pid_file: Option<PathBuf>,
     pub watch_config: bool,
+    pub run_as_user: Option<String>,
+    pub run_as_group: Option<String>,
+    pub read_only: bool,
 }
 
 struct PidGuard {
@@ -42,6 +46,9 @@ pub async fn run_velocity_service(spec: ServiceSpec) -> Result<(), Box<dyn std::
         verbose,
         pid_file,
         watch_config,
+        run_as_user,
+        run_as_group,
+        read_only,
     } = spec;
 
     if !data_dir.exists() {
@@ -49,8 +56,7 @@ pub async fn run_velocity_service(spec: ServiceSpec) -> Result<(), Box<dyn std::
     }
 
     let file_config = if config_path.exists() {
-        let content = fs::read_to_string(&config_path)?;
-        toml::from_str::<ConfigFile>(&content)?
+        ConfigFile::load(&config_path)?
     } else {
         println!(
             "{} Config file not found, creating default...",
@@ -58,7 +64,7 @@ pub async fn run_velocity_service(spec: ServiceSpec) -> Result<(), Box<dyn std::
         );
         let default_cfg = ConfigFile::default();
         let toml_string = toml::to_string_pretty(&default_cfg)?;
-        fs::write(&config_path, toml_string)?;
+        atomic_write(&config_path, toml_string)?;
         default_cfg
     };
 
@@ -67,8 +73,7 @@ pub async fn run_velocity_service(spec: ServiceSpec) -> Result<(), Box<dyn std::
     } else {
         &file_config.logging.level
     };
-
-    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or(log_level)).init();
+    init_logging(log_level);
 
     println!(
         "{} Loading configuration from {:?}",
@@ -76,33 +81,48 @@ pub async fn run_velocity_service(spec: ServiceSpec) -> Result<(), Box<dyn std::
         config_path
     );
 
-    let mut server_config = ServerConfig::default();
-    let bind_addr = bind.unwrap_or_else(|| file_config.server.bind_address.clone());
-    server_config.bind_address = bind_addr.parse()?;
-    server_config.max_connections = file_config.server.max_connections;
-    server_config.connection_timeout =
-        Duration::from_secs(file_config.server.connection_timeout_seconds);
-    server_config.rate_limit_per_second = file_config.server.rate_limit_per_second;
-    server_config.users = file_config.users.clone();
-    server_config.audit_log_path = file_config.security.audit_log_path.clone();
-    server_config.audit_logging = file_config.security.audit_logging;
-    server_config.auth_ban_duration =
-        Duration::from_secs(file_config.security.auth_ban_duration);
-    server_config.max_auth_failures = file_config.security.max_auth_failures;
+    velocity::format::check_data_dir(&data_dir, file_config.addons.database.as_ref())?;
+    let bind_addr = bind
+        .clone()
+        .unwrap_or_else(|| file_config.server.bind_address.clone());
+    let mut server_config = file_config.server_config(&bind_addr, &data_dir)?;
+    server_config.read_only |= read_only;
+    let read_only_server = server_config.read_only;
+    let startup = StartupStatus::new();
+    let health = HealthProbe::new(startup.clone());
+    if let Some(addr) = &file_config.server.health_bind_address {
+        let addr: std::net::SocketAddr = addr
+            .parse()
+            .map_err(|e| format!("Invalid health_bind_address '{}': {}", addr, e))?;
+        health.start_heartbeat();
+        let health = health.clone();
+        tokio::spawn(async move {
+            if let Err(e) = health.serve(addr).await {
+                log::error!("Health endpoint failed: {:?}", e);
+            }
+        });
+    }
+
+    // Listeners are bound while still root, so privileged ports work; the
+    // databases are opened only once privileges are dropped, so every file
+    // they create belongs to the service user.
+    let listeners = BoundListeners::bind(&server_config).await?;
+    let run_as_group = run_as_group.or_else(|| file_config.server.run_as_group.clone());
+    if let Some(user) = run_as_user.or_else(|| file_config.server.run_as_user.clone()) {
+        #[cfg(unix)]
+        if let Some(path) = &file_config.server.unix_socket_path {
+            velocity::privileges::chown_to(Path::new(path), &user, run_as_group.as_deref())?;
+        }
+        let dirs = velocity::format::database_dirs(&data_dir, file_config.addons.database.as_ref());
+        velocity::privileges::drop_privileges(&user, run_as_group.as_deref(), &dirs)?;
+    } else if run_as_group.is_some() {
+        return Err("run_as_group requires run_as_user".into());
+    }
 
     let db_config = VelocityConfig {
-        max_memtable_size: file_config.database.max_memtable_size,
-        cache_size: file_config.database.cache_size,
-        bloom_false_positive_rate: file_config.database.bloom_false_positive_rate,
-        compaction_threshold: file_config.database.compaction_threshold,
-        enable_compression: file_config.database.enable_compression,
-        memory_only_mode: false,
-        batch_wal_writes: true,
-        adaptive_cache: file_config.performance.adaptive_cache,
-        enable_metrics: file_config.performance.enable_metrics,
-        metrics_interval: Duration::from_secs(file_config.performance.metrics_interval),
-        target_cache_hit_rate: file_config.performance.target_cache_hit_rate,
-        wal_sync_mode: velocity::WalSyncMode::Batch,
+        recovery_progress: Some(startup.recovery_callback()),
+        read_only: server_config.read_only,
+        ..file_config.velocity_config()
     };
 
     println!(
@@ -110,10 +130,27 @@ pub async fn run_velocity_service(spec: ServiceSpec) -> Result<(), Box<dyn std::
         "[STORAGE]".blue(),
         data_dir
     );
-    let db = Velocity::open_with_config(&data_dir, db_config)?;
-    let db_manager = Arc::new(velocity::addon::DatabaseManager::new(db, config_path.clone()));
+    // Recovery runs on the blocking pool while the listeners tell clients
+    // that connect meanwhile that the server is still recovering.
+    let file_config = Arc::new(file_config);
+    let opening = tokio::task::spawn_blocking({
+        let file_config = file_config.clone();
+        let data_dir = data_dir.clone();
+        let config_path = config_path.clone();
+        move || -> velocity::VeloResult<_> {
+            let db = file_config.open_database(&data_dir, db_config)?;
+            let quarantined = db.quarantined_tables().to_vec();
+            let manager = velocity::addon::DatabaseManager::new(db, config_path);
+            Ok((manager, quarantined))
+        }
+    });
+    let (db_manager, quarantined) = listeners
+        .answer_until(&server_config, &startup, opening)
+        .await??;
+    startup.record_quarantined(&quarantined);
+    let db_manager = Arc::new(db_manager);
 
-    let server = VelocityServer::new(db_manager.clone(), server_config)?;
+    let server = VelocityServer::new(db_manager.clone(), server_config)?.with_startup_status(startup);
 
     let _pid_guard = pid_file.as_ref().map(|path| {
         let pid = std::process::id().to_string();
@@ -122,16 +159,29 @@ pub async fn run_velocity_service(spec: ServiceSpec) -> Result<(), Box<dyn std::
     });
 
     let _config_watcher = if watch_config {
-        Some(setup_config_watcher(&config_path, &db_manager)?)
+        Some(setup_config_watcher(
+            &config_path,
+            &db_manager,
+            &server,
+            bind.clone(),
+            &data_dir,
+        )?)
     } else {
         None
     };
 
     if let Some(backup_cfg) = &file_config.addons.backup {
         if backup_cfg.enabled {
-            spawn_backup_loop(db_manager.clone(), backup_cfg.interval_minutes);
+            spawn_backup_loop(db_manager.clone());
         }
     }
+    // Storage is opened read-only too; these would only fail.
+    if !read_only_server {
+        tokio::spawn(velocity::addon::run_trash_retention(db_manager.clone()));
+        tokio::spawn(velocity::addon::run_memtable_auto_flush(db_manager.clone()));
+    }
+    tokio::spawn(velocity::addon::run_capacity_sampling(db_manager.clone()));
+    tokio::spawn(velocity::addon::run_cache_warmup(db_manager.clone()));
 
     println!(
         "{} Velocity service starting on {}...",
@@ -139,58 +189,206 @@ pub async fn run_velocity_service(spec: ServiceSpec) -> Result<(), Box<dyn std::
         bind_addr.bold()
     );
 
-    let mut server_future = server.start();
-    tokio::pin!(server_future);
+    #[cfg(unix)]
+    spawn_signal_handlers(
+        server.clone(),
+        db_manager.clone(),
+        config_path.clone(),
+        bind.clone(),
+        data_dir.clone(),
+        verbose,
+    );
 
-    tokio::select! {
-        res = &mut server_future => res?,
-        _ = tokio::signal::ctrl_c() => {
-            log::info!("Shutdown signal received");
-        }
-    }
+    let shutdown_delay = std::time::Duration::from_secs(file_config.server.shutdown_delay_seconds);
+    server
+        .serve(listeners, async {
+            tokio::select! {
+                _ = shutdown_signal() => {}
+                _ = server.drain_requested() => {}
+            }
+            health.mark_shutting_down();
+            if !shutdown_delay.is_zero() {
+                log::info!(
+                    "Shutdown requested, not ready; closing the listener in {}s",
+                    shutdown_delay.as_secs()
+                );
+                tokio::time::sleep(shutdown_delay).await;
+            }
+        })
+        .await?;
+
+    let drain_timeout = std::time::Duration::from_secs(file_config.server.drain_timeout_seconds);
+    log::info!(
+        "Listener closed, draining for up to {}s",
+        drain_timeout.as_secs()
+    );
+    server.drain(drain_timeout).await;
+    db_manager.close_all()?;
+    log::info!("Shutdown complete");
 
     Ok(())
 }
 
-fn spawn_backup_loop(manager: Arc<velocity::addon::DatabaseManager>, interval: u64) {
-    println!(
-        "{} Automatic backups enabled ({} min interval)",
-        "[INFO]".green(),
-        interval
-    );
+// With a plain level and no RUST_LOG, the logger accepts everything and the
+// level is enforced through `log::set_max_level`, so SIGHUP can change it.
+fn init_logging(level: &str) {
+    if std::env::var_os("RUST_LOG").is_none() && level.parse::<log::LevelFilter>().is_ok() {
+        env_logger::Builder::from_env(env_logger::Env::default())
+            .filter_level(log::LevelFilter::Trace)
+            .init();
+        apply_log_level(level);
+    } else {
+        env_logger::Builder::from_env(env_logger::Env::default().default_filter_or(level)).init();
+    }
+}
 
-    tokio::spawn(async move {
-        let mut interval_timer = tokio::time::interval(Duration::from_secs(interval * 60));
-        interval_timer.tick().await;
+fn apply_log_level(level: &str) {
+    if std::env::var_os("RUST_LOG").is_some() {
+        return;
+    }
+    match level.parse::<log::LevelFilter>() {
+        Ok(filter) => log::set_max_level(filter),
+        Err(_) => log::warn!("Log level '{}' cannot be changed at runtime", level),
+    }
+}
 
+async fn shutdown_signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+        if let Ok(mut terminate) = signal(SignalKind::terminate()) {
+            tokio::select! {
+                _ = tokio::signal::ctrl_c() => {}
+                _ = terminate.recv() => {}
+            }
+            return;
+        }
+    }
+    let _ = tokio::signal::ctrl_c().await;
+}
+
+#[cfg(unix)]
+fn spawn_signal_handlers(
+    server: VelocityServer,
+    manager: Arc<velocity::addon::DatabaseManager>,
+    config_path: PathBuf,
+    bind: Option<String>,
+    data_dir: PathBuf,
+    verbose: bool,
+) {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let (Ok(mut hangup), Ok(mut user1)) = (
+        signal(SignalKind::hangup()),
+        signal(SignalKind::user_defined1()),
+    ) else {
+        log::warn!("Could not install SIGHUP/SIGUSR1 handlers");
+        return;
+    };
+
+    tokio::spawn(async move {
         loop {
-            interval_timer.tick().await;
-
-            let current_enabled = manager
-                .list_addons()
-                .iter()
-                .find(|(name, _)| name == "backup")
-                .map(|(_, enabled)| *enabled)
-                .unwrap_or(false);
-
-            if current_enabled {
-                match manager.backup_all_databases() {
-                    Ok(succ) => log::info!(
-                        "Automatic backup successful for {} databases",
-                        succ.len()
-                    ),
-                    Err(e) => log::error!("Automatic backup failed: {}", e),
+            tokio::select! {
+                Some(()) = hangup.recv() => {
+                    log::info!("SIGHUP received, reloading configuration");
+                    reload_on_hangup(&config_path, &manager, verbose);
+                    reload_listeners(&server, &config_path, bind.as_deref(), &data_dir).await;
+                }
+                Some(()) = user1.recv() => {
+                    log::info!("Stats: {}", server.stats_snapshot().await);
                 }
+                else => break,
             }
         }
     });
 }
 
+#[cfg(unix)]
+fn reload_on_hangup(
+    config_path: &Path,
+    manager: &velocity::addon::DatabaseManager,
+    verbose: bool,
+) {
+    let file_config = match ConfigFile::load(config_path) {
+        Ok(config) => config,
+        Err(e) => {
+            log::error!("Keeping current configuration, {:?} is invalid: {}", config_path, e);
+            return;
+        }
+    };
+    if !verbose {
+        apply_log_level(&file_config.logging.level);
+        log::info!("Log level is now {}", log::max_level());
+    }
+    if let Err(e) = manager.reload_config() {
+        log::error!("Failed to reload addon configuration: {:?}", e);
+    }
+}
+
+/// Moves the server's listeners to the bind address, extra listeners and TLS
+/// settings now in the config file. A `--bind` override keeps the primary
+/// listener where it is.
+pub async fn reload_listeners(
+    server: &VelocityServer,
+    config_path: &Path,
+    bind: Option<&str>,
+    data_dir: &Path,
+) {
+    let listeners = ConfigFile::load(config_path)
+        .and_then(|file_config| {
+            let bind_addr = bind.unwrap_or(&file_config.server.bind_address);
+            Ok(file_config
+                .server_config(bind_addr, data_dir)?
+                .listener_configs())
+        })
+        .map_err(|e| e.to_string());
+    let listeners = match listeners {
+        Ok(listeners) => listeners,
+        Err(e) => {
+            log::error!("Keeping the current listeners: {}", e);
+            return;
+        }
+    };
+    match server.update_listeners(listeners).await {
+        Ok(changes) if changes.is_empty() => {}
+        Ok(changes) => log::info!(
+            "Listeners updated: {} opened, {} closed, {} reconfigured",
+            changes.opened.len(),
+            changes.closed.len(),
+            changes.reconfigured.len()
+        ),
+        Err(e) => log::error!("Keeping the current listeners: {}", e),
+    }
+}
+
+fn spawn_backup_loop(manager: Arc<velocity::addon::DatabaseManager>) {
+    match manager.next_backup_run() {
+        Ok(Some(next)) => println!(
+            "{} Automatic backups enabled (next run {})",
+            "[INFO]".green(),
+            next.format("%Y-%m-%d %H:%M")
+        ),
+        Ok(None) => println!(
+            "{} Automatic backups enabled but the schedule never permits a run",
+            "[WARN]".yellow()
+        ),
+        Err(e) => println!("{} Invalid backup schedule: {}", "[WARN]".yellow(), e),
+    }
+
+    tokio::spawn(velocity::backup_schedule::run_backup_schedule(manager));
+}
+
 fn setup_config_watcher(
     config: &Path,
     manager: &Arc<velocity::addon::DatabaseManager>,
+    server: &VelocityServer,
+    bind: Option<String>,
+    data_dir: &Path,
 ) -> notify::Result<RecommendedWatcher> {
     let manager_for_watcher = manager.clone();
+    let server = server.clone();
+    let config_path = config.to_path_buf();
+    let data_dir = data_dir.to_path_buf();
     let (tx, mut rx) = mpsc::channel(1);
 
     let mut watcher = RecommendedWatcher::new(
@@ -207,12 +405,13 @@ fn setup_config_watcher(
     watcher.watch(config, RecursiveMode::NonRecursive)?;
 
     tokio::spawn(async move {
-        while let Some(_) = rx.recv().await {
+        while rx.recv().await.is_some() {
             println!(
                 "{} Configuration change detected, reloading...",
                 "[CONFIG]".blue()
             );
             let _ = manager_for_watcher.reload_config();
+            reload_listeners(&server, &config_path, bind.as_deref(), &data_dir).await;
         }
     });
 
@@ -1,6 +1,7 @@
 use colored::*;
 use std::fs;
 use std::path::{Path, PathBuf};
+use velocity::fsutil::atomic_write;
 
 pub struct SetupInstallSpec {
     pub config: PathBuf,
@@ -101,7 +102,7 @@ pub fn run_setup_install(spec: SetupInstallSpec) -> Result<(), Box<dyn std::erro
         }
 
         if cfg!(target_os = "windows") {
-            fs::write(
+            atomic_write(
                 &service_file,
                 render_windows_service_installer(
                     &installed_exe,
@@ -121,7 +122,7 @@ pub fn run_setup_install(spec: SetupInstallSpec) -> Result<(), Box<dyn std::erro
                 service_file.display()
             );
         } else {
-            fs::write(
+            atomic_write(
                 &service_file,
                 render_systemd_unit(
                     &installed_exe,
@@ -174,7 +175,8 @@ After=network.target\n\
 \n\
 [Service]\n\
 Type=simple\n\
-ExecStart={} ops service run --config {} --data-dir {}{} --verbose\n\
+ExecStart={} ops service run --config {} --data-dir {}{}\n\
+ExecReload=/bin/kill -HUP $MAINPID\n\
 Restart=on-failure\n\
 \n\
 [Install]\n\
@@ -197,7 +199,7 @@ fn render_windows_service_installer(
         .unwrap_or_default();
     format!(
         "param(\n    [string]$ServiceName = \"VelocityDB\",\n    [string]$DisplayName = \"VelocityDB Service\"\n)\n\n\
-$binPath = '\"{}\" ops service run --config \"{}\" --data-dir \"{}\"{} --verbose'\n\n\
+$binPath = '\"{}\" ops service run --config \"{}\" --data-dir \"{}\"{}'\n\n\
 sc.exe create $ServiceName binPath= \"$binPath\" DisplayName= \"$DisplayName\" start= auto\n\
 sc.exe description $ServiceName \"VelocityDB background service\"\n\
 sc.exe start $ServiceName\n",
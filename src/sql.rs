@@ -1,30 +1,39 @@
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
 use serde::{Deserialize, Serialize};
 use sqlparser::ast::{
-    BinaryOperator, Expr, Function, FunctionArg, FunctionArgExpr, Query, SelectItem, SetExpr,
-    Statement, TableFactor, TableWithJoins, Value, Values,
+    BinaryOperator, Expr, JsonOperator, Query, SelectItem, SetExpr, Statement, TableFactor,
+    Value,
 };
 use sqlparser::dialect::GenericDialect;
 use sqlparser::parser::Parser;
 use std::sync::Arc;
 
-use crate::{VeloError, VeloKey, VeloResult, VeloValue, Velocity};
+use crate::dry_run::DryRunReport;
+use crate::result_cache::{CachedKeys, ResultCache};
+use crate::statement_cache::StatementCache;
+use crate::{BatchOp, RecordMeta, VeloError, VeloResult, VeloValue, Velocity, TRASH_PREFIX};
 
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct QueryResult {
     pub success: bool,
     pub rows_affected: usize,
     pub data: Vec<Row>,
     pub columns: Vec<String>,
     pub execution_time_ms: u64,
+    /// Set when a SELECT stopped at its LIMIT with more rows left; pass it
+    /// back as `AFTER '<cursor>'` to read the next page.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub next_cursor: Option<String>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Row {
     pub values: Vec<SqlValue>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum SqlValue {
     String(String),
     Integer(i64),
@@ -32,6 +41,8 @@ pub enum SqlValue {
     Boolean(bool),
     Null,
     Binary(Vec<u8>),
+    /// The text of a JSON object or array, exactly as stored.
+    Json(String),
 }
 
 impl From<&VeloValue> for SqlValue {
@@ -42,6 +53,11 @@ impl From<&VeloValue> for SqlValue {
             if let Ok(i) = s.parse::<i64>() {
                 return SqlValue::Integer(i);
             }
+            // Integers beyond i64 stay text rather than lose digits as floats.
+            let digits = s.strip_prefix('-').unwrap_or(&s);
+            if !digits.is_empty() && digits.bytes().all(|b| b.is_ascii_digit()) {
+                return SqlValue::String(s);
+            }
             if let Ok(f) = s.parse::<f64>() {
                 return SqlValue::Float(f);
             }
@@ -56,6 +72,38 @@ impl From<&VeloValue> for SqlValue {
 }
 
 impl SqlValue {
+    /// Like `From<&VeloValue>`, but a value holding a JSON object or array
+    /// becomes `SqlValue::Json`.
+    pub fn detect(value: &VeloValue) -> Self {
+        let looks_like_json = value
+            .iter()
+            .find(|b| !b.is_ascii_whitespace())
+            .is_some_and(|b| matches!(b, b'{' | b'['));
+        if !looks_like_json {
+            return SqlValue::from(value);
+        }
+        match serde_json::from_slice::<serde::de::IgnoredAny>(value) {
+            Ok(_) => SqlValue::Json(String::from_utf8_lossy(value).into_owned()),
+            Err(_) => SqlValue::from(value),
+        }
+    }
+
+    // Objects and arrays stay JSON unless `as_text` asks for their text.
+    fn from_json(json: &serde_json::Value, as_text: bool) -> Self {
+        match json {
+            serde_json::Value::Null => SqlValue::Null,
+            serde_json::Value::Bool(b) => SqlValue::Boolean(*b),
+            serde_json::Value::Number(n) => match n.as_i64() {
+                Some(i) => SqlValue::Integer(i),
+                None if n.is_u64() => SqlValue::String(n.to_string()),
+                None => SqlValue::Float(n.as_f64().unwrap_or(f64::NAN)),
+            },
+            serde_json::Value::String(s) => SqlValue::String(s.clone()),
+            other if as_text => SqlValue::String(other.to_string()),
+            other => SqlValue::Json(other.to_string()),
+        }
+    }
+
     pub fn to_bytes(&self) -> Vec<u8> {
         match self {
             SqlValue::String(s) => s.as_bytes().to_vec(),
@@ -64,14 +112,186 @@ impl SqlValue {
             SqlValue::Boolean(b) => b.to_string().as_bytes().to_vec(),
             SqlValue::Null => Vec::new(),
             SqlValue::Binary(b) => b.clone(),
+            SqlValue::Json(json) => json.as_bytes().to_vec(),
+        }
+    }
+}
+
+const KV_COLUMNS: [&str; 5] = ["key", "value", "created_at", "updated_at", "version"];
+
+// INSERTs are written in engine batches of this many rows, so a huge VALUES
+// list or INSERT ... SELECT does not build one enormous batch.
+const INSERT_BATCH_ROWS: usize = 1000;
+
+// Prefix DELETEs read and tombstone this many keys at a time.
+const DELETE_BATCH_ROWS: usize = 1000;
+
+// SELECTs that scan return at most this many rows.
+const SELECT_ROW_LIMIT: usize = 1000;
+
+// A selected column: one of KV_COLUMNS, or a path into a JSON value such as
+// `value->'address'->>'city'`. `as_text` is set when the last step is `->>`.
+enum Column {
+    Kv(String),
+    JsonPath { path: Vec<JsonStep>, as_text: bool },
+}
+
+enum JsonStep {
+    Key(String),
+    Index(usize),
+}
+
+fn project_row(
+    columns: &[Column],
+    json_values: bool,
+    key: &str,
+    value: &VeloValue,
+    meta: Option<RecordMeta>,
+) -> Row {
+    let timestamp = |millis: u64| {
+        chrono::DateTime::from_timestamp_millis(millis as i64)
+            .map(|t| SqlValue::String(t.to_rfc3339()))
+            .unwrap_or(SqlValue::Null)
+    };
+    let json = columns
+        .iter()
+        .any(|column| matches!(column, Column::JsonPath { .. }))
+        .then(|| serde_json::from_slice::<serde_json::Value>(value).ok())
+        .flatten();
+    let values = columns
+        .iter()
+        .map(|column| match column {
+            Column::JsonPath { path, as_text } => json
+                .as_ref()
+                .and_then(|root| {
+                    path.iter().try_fold(root, |json, step| match step {
+                        JsonStep::Key(k) => json.get(k),
+                        JsonStep::Index(i) => json.get(i),
+                    })
+                })
+                .map(|json| SqlValue::from_json(json, *as_text))
+                .unwrap_or(SqlValue::Null),
+            Column::Kv(name) => match (name.as_str(), meta) {
+                ("key", _) => SqlValue::String(key.to_string()),
+                ("value", _) if json_values => SqlValue::detect(value),
+                ("value", _) => SqlValue::from(value),
+                ("created_at", Some(meta)) => timestamp(meta.created_at),
+                ("updated_at", Some(meta)) => timestamp(meta.updated_at),
+                ("version", Some(meta)) => SqlValue::Integer(meta.version as i64),
+                _ => SqlValue::Null,
+            },
+        })
+        .collect();
+    Row { values }
+}
+
+// Reads `value->'a'->>'b'` into its path. `->>` may only be the last step.
+fn json_path(expr: &Expr) -> Option<(Vec<JsonStep>, bool)> {
+    // The parser nests chained operators either way, so flatten them first.
+    fn flatten<'a>(expr: &'a Expr, operands: &mut Vec<&'a Expr>, ops: &mut Vec<&'a JsonOperator>) {
+        match expr {
+            Expr::JsonAccess {
+                left,
+                operator,
+                right,
+            } => {
+                flatten(left, operands, ops);
+                ops.push(operator);
+                flatten(right, operands, ops);
+            }
+            operand => operands.push(operand),
         }
     }
+    let (mut operands, mut ops) = (Vec::new(), Vec::new());
+    flatten(expr, &mut operands, &mut ops);
+
+    let (Expr::Identifier(root), steps) = (operands[0], &operands[1..]) else {
+        return None;
+    };
+    if !root.value.eq_ignore_ascii_case("value") {
+        return None;
+    }
+    let path = steps
+        .iter()
+        .map(|step| match step {
+            Expr::Value(Value::SingleQuotedString(k)) => Some(JsonStep::Key(k.clone())),
+            Expr::Value(Value::Number(n, _)) => n.parse().ok().map(JsonStep::Index),
+            _ => None,
+        })
+        .collect::<Option<Vec<_>>>()?;
+    let (last, inner) = ops.split_last()?;
+    if inner.iter().any(|op| **op != JsonOperator::Arrow) {
+        return None;
+    }
+    match last {
+        JsonOperator::Arrow => Some((path, false)),
+        JsonOperator::LongArrow => Some((path, true)),
+        _ => None,
+    }
+}
+
+// Splits a trailing `AFTER '<cursor>'` off a statement, since the SQL
+// parser does not know the clause. Cursors never contain quotes.
+fn split_after_clause(sql: &str) -> (&str, Option<&str>) {
+    let trimmed = sql.trim_end().trim_end_matches(';').trim_end();
+    let Some(quoted) = trimmed.strip_suffix('\'') else {
+        return (sql, None);
+    };
+    let Some(open) = quoted.rfind('\'') else {
+        return (sql, None);
+    };
+    let head = quoted[..open].trim_end();
+    let Some(keyword) = head.len().checked_sub(5).and_then(|at| head.get(at..)) else {
+        return (sql, None);
+    };
+    let before = &head[..head.len() - 5];
+    if !keyword.eq_ignore_ascii_case("AFTER") || !before.ends_with(char::is_whitespace) {
+        return (sql, None);
+    }
+    (before, Some(&quoted[open + 1..]))
+}
+
+fn decode_cursor(cursor: &str) -> VeloResult<String> {
+    URL_SAFE_NO_PAD
+        .decode(cursor)
+        .ok()
+        .and_then(|bytes| String::from_utf8(bytes).ok())
+        .ok_or_else(|| VeloError::InvalidOperation(format!("Invalid cursor '{}'", cursor)))
 }
 
+/// Whether `sql` parses to an INSERT, UPDATE or DELETE. Comments and case
+/// do not matter; text that does not parse is not a write.
+pub fn is_write_statement(sql: &str) -> bool {
+    Parser::parse_sql(&GenericDialect {}, split_after_clause(sql).0)
+        .is_ok_and(|statements| statements.iter().any(writes))
+}
+
+fn writes(statement: &Statement) -> bool {
+    matches!(
+        statement,
+        Statement::Insert { .. } | Statement::Update { .. } | Statement::Delete { .. }
+    )
+}
+
+
+pub type KeyValidator = Arc<dyn Fn(&str) -> VeloResult<()> + Send + Sync>;
+
+/// Asked before every scan. Returns how long to wait before asking again,
+/// `None` to let the scan run, or an error to refuse it.
+pub type ScanAdmission = Arc<dyn Fn() -> VeloResult<Option<std::time::Duration>> + Send + Sync>;
+
+/// Asked before every INSERT, UPDATE or DELETE. An error refuses it.
+pub type WriteAdmission = Arc<dyn Fn() -> VeloResult<()> + Send + Sync>;
 
 pub struct SqlEngine {
     pub db: Arc<Velocity>,
     dialect: GenericDialect,
+    key_validator: Option<KeyValidator>,
+    statement_cache: Option<Arc<StatementCache>>,
+    result_cache: Option<Arc<ResultCache>>,
+    scan_admission: Option<ScanAdmission>,
+    write_admission: Option<WriteAdmission>,
+    json_values: bool,
 }
 
 impl SqlEngine {
@@ -79,6 +299,79 @@ impl SqlEngine {
         Self {
             db,
             dialect: GenericDialect {},
+            key_validator: None,
+            statement_cache: None,
+            result_cache: None,
+            scan_admission: None,
+            write_admission: None,
+            json_values: false,
+        }
+    }
+
+    /// Runs `validator` on every key an INSERT, UPDATE or DELETE touches
+    /// before anything is written.
+    pub fn with_key_validator(mut self, validator: KeyValidator) -> Self {
+        self.key_validator = Some(validator);
+        self
+    }
+
+    /// Looks statements up in `cache` before parsing them. A trailing
+    /// `AFTER` cursor is not part of the cached text.
+    pub fn with_statement_cache(mut self, cache: Arc<StatementCache>) -> Self {
+        self.statement_cache = Some(cache);
+        self
+    }
+
+    /// Answers repeated SELECTs from `cache`, which must be attached to
+    /// this engine's database.
+    pub fn with_result_cache(mut self, cache: Arc<ResultCache>) -> Self {
+        self.result_cache = Some(cache);
+        self
+    }
+
+    /// Checks with `admission` before a SELECT, INSERT ... SELECT or DELETE
+    /// reads a key range instead of looking keys up.
+    pub fn with_scan_admission(mut self, admission: ScanAdmission) -> Self {
+        self.scan_admission = Some(admission);
+        self
+    }
+
+    /// Checks with `admission` before an INSERT, UPDATE or DELETE runs, such
+    /// as a database's read-only flag and disk quota.
+    pub fn with_write_admission(mut self, admission: WriteAdmission) -> Self {
+        self.write_admission = Some(admission);
+        self
+    }
+
+    /// With `enabled`, a `value` holding a JSON object or array is returned
+    /// as `SqlValue::Json` instead of a string. Off by default.
+    pub fn with_json_values(mut self, enabled: bool) -> Self {
+        self.json_values = enabled;
+        self
+    }
+
+    async fn admit_scan(&self, key_filter: &KeyFilter) -> VeloResult<()> {
+        let Some(admission) = &self.scan_admission else {
+            return Ok(());
+        };
+        let lookup = match key_filter {
+            KeyFilter::Exact(_) | KeyFilter::In(_) => true,
+            KeyFilter::NoCase(_) => self.db.nocase_index_enabled(),
+            _ => false,
+        };
+        if lookup {
+            return Ok(());
+        }
+        while let Some(wait) = admission()? {
+            tokio::time::sleep(wait.max(std::time::Duration::from_millis(10))).await;
+        }
+        Ok(())
+    }
+
+    fn validate_key(&self, key: &str) -> VeloResult<()> {
+        match &self.key_validator {
+            Some(validator) => validator(key),
+            None => Ok(()),
         }
     }
 
@@ -86,8 +379,15 @@ impl SqlEngine {
         let start_time = std::time::Instant::now();
 
 
-        let statements = Parser::parse_sql(&self.dialect, sql)
-            .map_err(|e| VeloError::InvalidOperation(format!("SQL Parse Error: {}", e)))?;
+        let (sql, after) = split_after_clause(sql);
+        let parse = |sql: &str| {
+            Parser::parse_sql(&self.dialect, sql)
+                .map_err(|e| VeloError::InvalidOperation(format!("SQL Parse Error: {}", e)))
+        };
+        let statements = match &self.statement_cache {
+            Some(cache) => cache.get_or_parse(sql, parse)?,
+            None => Arc::new(parse(sql)?),
+        };
 
         if statements.is_empty() {
             return Err(VeloError::InvalidOperation(
@@ -96,14 +396,26 @@ impl SqlEngine {
         }
 
         let statement = &statements[0];
+        if after.is_some() && !matches!(statement, Statement::Query(_)) {
+            return Err(VeloError::InvalidOperation(
+                "AFTER is only supported on SELECT".to_string(),
+            ));
+        }
+        if let Some(admission) = self.write_admission.as_ref().filter(|_| writes(statement)) {
+            admission()?;
+        }
         let result = match statement {
-            Statement::Query(query) => self.execute_query(query).await?,
+            Statement::Query(query) => match &self.result_cache {
+                Some(cache) => {
+                    self.execute_query_cached(cache, statement, query, after)
+                        .await?
+                }
+                None => self.execute_query(query, after).await?,
+            },
+            Statement::Explain { statement, .. } => self.execute_explain(statement)?,
             Statement::Insert {
-                table_name,
-                columns,
-                source,
-                ..
-            } => self.execute_insert(table_name, columns, source).await?,
+                table_name, source, ..
+            } => self.execute_insert(table_name, source).await?,
             Statement::Update {
                 table,
                 assignments,
@@ -127,20 +439,89 @@ impl SqlEngine {
             data: result.data,
             columns: result.columns,
             execution_time_ms: execution_time,
+            next_cursor: result.next_cursor,
         })
     }
 
-    async fn execute_query(&self, query: &Query) -> VeloResult<QueryResult> {
+    async fn execute_query(&self, query: &Query, after: Option<&str>) -> VeloResult<QueryResult> {
+        let limit = match &query.limit {
+            Some(Expr::Value(Value::Number(n, _))) => Some(n.parse::<usize>().map_err(|_| {
+                VeloError::InvalidOperation(format!("Invalid LIMIT: {}", n))
+            })?),
+            Some(other) => {
+                return Err(VeloError::InvalidOperation(format!(
+                    "Unsupported LIMIT: {}",
+                    other
+                )))
+            }
+            None => None,
+        };
+        let after = after.map(decode_cursor).transpose()?;
 
         match query.body.as_ref() {
-            sqlparser::ast::SetExpr::Select(select) => self.execute_select(select).await,
+            sqlparser::ast::SetExpr::Select(select) => {
+                self.execute_select(select, limit, after.as_deref()).await
+            }
             _ => Err(VeloError::InvalidOperation(
                 "Complex queries not supported yet".to_string(),
             )),
         }
     }
 
-    async fn execute_select(&self, select: &sqlparser::ast::Select) -> VeloResult<QueryResult> {
+    // The key is the statement as the parser prints it, so spacing and
+    // keyword case do not matter.
+    async fn execute_query_cached(
+        &self,
+        cache: &ResultCache,
+        statement: &Statement,
+        query: &Query,
+        after: Option<&str>,
+    ) -> VeloResult<QueryResult> {
+        let key = match after {
+            Some(cursor) => format!("{} AFTER '{}'", statement, cursor),
+            None => statement.to_string(),
+        };
+        if let Some(result) = cache.get(&key) {
+            return Ok(result);
+        }
+        let keys = self.query_keys(query);
+        // Values under a TTL policy expire without a write that would
+        // invalidate the result, so such results are not cached.
+        let expires = match &keys {
+            CachedKeys::Keys(keys) => keys.iter().any(|key| self.db.ttl_secs(key).is_some()),
+            CachedKeys::Prefix(prefix) => self.db.ttl_under(prefix),
+            CachedKeys::All => self.db.ttl_under(""),
+        };
+        let (sequence, _) = self.db.sequences();
+        let result = self.execute_query(query, after).await?;
+        if !expires {
+            cache.insert(&key, keys, sequence, &result);
+        }
+        Ok(result)
+    }
+
+    fn query_keys(&self, query: &Query) -> CachedKeys {
+        let SetExpr::Select(select) = query.body.as_ref() else {
+            return CachedKeys::All;
+        };
+        let Some(selection) = &select.selection else {
+            return CachedKeys::All;
+        };
+        match self.extract_key_filter(selection) {
+            Ok(KeyFilter::Exact(key)) => CachedKeys::Keys(vec![key]),
+            Ok(KeyFilter::In(keys)) => CachedKeys::Keys(keys),
+            Ok(KeyFilter::Prefix(prefix)) => CachedKeys::Prefix(prefix),
+            Ok(KeyFilter::Pattern(pattern)) => CachedKeys::Prefix(pattern.literal_prefix()),
+            _ => CachedKeys::All,
+        }
+    }
+
+    async fn execute_select(
+        &self,
+        select: &sqlparser::ast::Select,
+        limit: Option<usize>,
+        after: Option<&str>,
+    ) -> VeloResult<QueryResult> {
 
         let table_name = self.extract_table_name(&select.from)?;
         if table_name != "kv" {
@@ -157,41 +538,213 @@ impl SqlEngine {
         };
 
 
+        if after.is_some() && matches!(key_filter, KeyFilter::Exact(_) | KeyFilter::In(_)) {
+            return Err(VeloError::InvalidOperation(
+                "AFTER requires a key scan, not a key lookup".to_string(),
+            ));
+        }
+
+        let (names, columns) = self.projection_columns(&select.projection)?;
+        // One row past the page tells whether there is another page.
+        let page = limit.unwrap_or(SELECT_ROW_LIMIT).min(SELECT_ROW_LIMIT);
+        self.admit_scan(&key_filter).await?;
+        let mut rows = self.matching_rows(&key_filter, after, page + 1)?;
+        let next_cursor = if rows.len() > page {
+            rows.truncate(page);
+            rows.last().map(|(key, _, _)| URL_SAFE_NO_PAD.encode(key))
+        } else {
+            None
+        };
+        let data: Vec<Row> = rows
+            .into_iter()
+            .map(|(key, value, meta)| project_row(&columns, self.json_values, &key, &value, meta))
+            .collect();
+        Ok(QueryResult {
+            success: true,
+            rows_affected: data.len(),
+            data,
+            columns: names,
+            execution_time_ms: 0,
+            next_cursor,
+        })
+    }
+
+    // Key lookups and prefixes are answered from the matching key range;
+    // patterns without a literal prefix filter a full scan. Scans start past
+    // `after` when it is set.
+    fn matching_rows(
+        &self,
+        key_filter: &KeyFilter,
+        after: Option<&str>,
+        limit: usize,
+    ) -> VeloResult<Vec<(String, VeloValue, Option<RecordMeta>)>> {
+        let start_from = |start: &str| match after {
+            Some(after) if after >= start => format!("{}\0", after),
+            _ => start.to_string(),
+        };
+        let prefix_rows = |prefix: &str, limit: usize, keep: &dyn Fn(&str) -> bool| {
+            let end = format!("{}\u{10FFFF}", prefix);
+            self.db
+                .range_matching_with_meta(&start_from(prefix), Some(&end), limit, keep)
+        };
         match key_filter {
-            KeyFilter::Exact(key) => {
-                if let Some(value) = self.db.get(&key)? {
-                    Ok(QueryResult {
-                        success: true,
-                        rows_affected: 1,
-                        data: vec![Row {
-                            values: vec![SqlValue::String(key), SqlValue::from(&value)],
-                        }],
-                        columns: vec!["key".to_string(), "value".to_string()],
-                        execution_time_ms: 0,
-                    })
-                } else {
-                    Ok(QueryResult {
-                        success: true,
-                        rows_affected: 0,
-                        data: vec![],
-                        columns: vec!["key".to_string(), "value".to_string()],
-                        execution_time_ms: 0,
-                    })
+            KeyFilter::Exact(key) => Ok(self
+                .db
+                .get_with_meta(key)?
+                .map(|(value, meta)| (key.clone(), value, meta))
+                .into_iter()
+                .collect()),
+            KeyFilter::In(keys) => Ok(keys
+                .iter()
+                .zip(self.db.multi_get_with_meta(keys)?)
+                .filter_map(|(key, found)| found.map(|(value, meta)| (key.clone(), value, meta)))
+                .take(limit)
+                .collect()),
+            KeyFilter::NoCase(key) if self.db.nocase_index_enabled() => {
+                let mut rows = Vec::new();
+                let keys = self.db.keys_nocase(key)?.into_iter();
+                for original in keys.filter(|k| after.is_none_or(|after| k.as_str() > after)) {
+                    if rows.len() == limit {
+                        break;
+                    }
+                    if let Some((value, meta)) = self.db.get_with_meta(&original)? {
+                        rows.push((original, value, meta));
+                    }
                 }
+                Ok(rows)
+            }
+            KeyFilter::NoCase(key) => {
+                let folded = key.to_lowercase();
+                self.db
+                    .range_matching_with_meta(&start_from(""), None, limit, |key| {
+                        key.to_lowercase() == folded
+                    })
+            }
+            KeyFilter::Prefix(prefix) => prefix_rows(prefix, limit, &|_| true),
+            KeyFilter::Pattern(pattern) => prefix_rows(&pattern.literal_prefix(), limit, &|key| {
+                pattern.matches(key)
+            }),
+            KeyFilter::Range(_, _) | KeyFilter::All => {
+                self.db.range_with_meta(&start_from(""), None, limit)
+            }
+        }
+    }
+
+    fn execute_explain(&self, statement: &Statement) -> VeloResult<QueryResult> {
+        let Statement::Query(query) = statement else {
+            return Err(VeloError::InvalidOperation(
+                "EXPLAIN supports SELECT statements only".to_string(),
+            ));
+        };
+        let SetExpr::Select(select) = query.body.as_ref() else {
+            return Err(VeloError::InvalidOperation(
+                "Complex queries not supported yet".to_string(),
+            ));
+        };
+        let key_filter = match &select.selection {
+            Some(where_clause) => self.extract_key_filter(where_clause)?,
+            None => KeyFilter::All,
+        };
+
+        let mut plan = match &key_filter {
+            KeyFilter::Exact(key) => vec![format!("KEY LOOKUP key = '{}'", key)],
+            KeyFilter::In(keys) => vec![format!("MULTI KEY LOOKUP key IN ({} keys)", keys.len())],
+            KeyFilter::NoCase(key) if self.db.nocase_index_enabled() => {
+                vec![format!("NOCASE INDEX LOOKUP key = '{}' COLLATE NOCASE", key)]
             }
-            KeyFilter::Prefix(prefix) => self.execute_prefix_scan(&prefix).await,
-            KeyFilter::Range(start, end) => self.execute_range_scan(&start, &end).await,
-            KeyFilter::All => {
+            KeyFilter::NoCase(key) => vec![
+                "FULL SCAN".to_string(),
+                format!("FILTER key = '{}' COLLATE NOCASE", key),
+                "WARNING: full scan required; enable nocase_keys on the database to use an index"
+                    .to_string(),
+            ],
+            KeyFilter::Prefix(prefix) => vec![format!("PREFIX SCAN key LIKE '{}%'", prefix)],
+            KeyFilter::Pattern(pattern) if !pattern.literal_prefix().is_empty() => vec![
+                format!("PREFIX SCAN key LIKE '{}%'", pattern.literal_prefix()),
+                format!("FILTER {}", pattern),
+            ],
+            KeyFilter::Pattern(pattern) => vec![
+                "FULL SCAN".to_string(),
+                format!("FILTER {}", pattern),
+                "WARNING: full scan required; the pattern has no literal prefix".to_string(),
+            ],
+            KeyFilter::Range(_, _) | KeyFilter::All => vec!["FULL SCAN".to_string()],
+        };
+        if plan[0].contains("SCAN") {
+            plan.push(format!("LIMIT {}", SELECT_ROW_LIMIT));
+        }
+
+        let data: Vec<Row> = plan
+            .into_iter()
+            .map(|line| Row {
+                values: vec![SqlValue::String(line)],
+            })
+            .collect();
+        Ok(QueryResult {
+            success: true,
+            rows_affected: data.len(),
+            data,
+            columns: vec!["plan".to_string()],
+            execution_time_ms: 0,
+            next_cursor: None,
+        })
+    }
 
-                self.execute_full_scan().await
+    fn projection_columns(
+        &self,
+        projection: &[SelectItem],
+    ) -> VeloResult<(Vec<String>, Vec<Column>)> {
+        let mut names = Vec::new();
+        let mut columns = Vec::new();
+        for item in projection {
+            let (expr, alias) = match item {
+                SelectItem::Wildcard(_) => {
+                    for name in ["key", "value"] {
+                        names.push(name.to_string());
+                        columns.push(Column::Kv(name.to_string()));
+                    }
+                    continue;
+                }
+                SelectItem::UnnamedExpr(expr) => (expr, None),
+                SelectItem::ExprWithAlias { expr, alias } => (expr, Some(alias.value.clone())),
+                other => {
+                    return Err(VeloError::InvalidOperation(format!(
+                        "Unsupported column '{}'",
+                        other
+                    )))
+                }
+            };
+            match expr {
+                Expr::Identifier(id) if KV_COLUMNS.contains(&id.value.to_lowercase().as_str()) => {
+                    let name = id.value.to_lowercase();
+                    names.push(alias.unwrap_or_else(|| name.clone()));
+                    columns.push(Column::Kv(name));
+                }
+                Expr::JsonAccess { .. } => {
+                    let (path, as_text) = json_path(expr).ok_or_else(|| {
+                        VeloError::InvalidOperation(format!(
+                            "Unsupported JSON path '{}' (expected value->'field' or value->>'field')",
+                            expr
+                        ))
+                    })?;
+                    names.push(alias.unwrap_or_else(|| expr.to_string()));
+                    columns.push(Column::JsonPath { path, as_text });
+                }
+                other => {
+                    return Err(VeloError::InvalidOperation(format!(
+                        "Unsupported column '{}' (expected one of: {})",
+                        other,
+                        KV_COLUMNS.join(", ")
+                    )));
+                }
             }
         }
+        Ok((names, columns))
     }
 
     async fn execute_insert(
         &self,
         table_name: &sqlparser::ast::ObjectName,
-        columns: &[sqlparser::ast::Ident],
         source: &Query,
     ) -> VeloResult<QueryResult> {
         let table = table_name.to_string();
@@ -202,9 +755,9 @@ impl SqlEngine {
         }
 
 
-        match source.body.as_ref() {
+        let rows = match source.body.as_ref() {
             SetExpr::Values(values) => {
-                let mut rows_inserted = 0;
+                let mut rows = Vec::with_capacity(values.rows.len());
 
                 for row in &values.rows {
                     if row.len() != 2 {
@@ -214,23 +767,138 @@ impl SqlEngine {
                     }
 
                     let key = self.extract_string_value(&row[0])?;
-                    let value = self.extract_value_bytes(&row[1])?;
+                    self.validate_key(&key)?;
+                    rows.push((key, self.extract_value_bytes(&row[1])?));
+                }
+                rows
+            }
+            SetExpr::Select(select) => self.insert_rows_from_select(select).await?,
+            _ => {
+                return Err(VeloError::InvalidOperation(
+                    "Unsupported INSERT format".to_string(),
+                ))
+            }
+        };
+
+        let rows_inserted = rows.len();
+        let mut rows = rows.into_iter().peekable();
+        while rows.peek().is_some() {
+            let batch: Vec<BatchOp> = rows
+                .by_ref()
+                .take(INSERT_BATCH_ROWS)
+                .map(|(key, value)| BatchOp::Put { key, value })
+                .collect();
+            self.db.write_batch(&batch)?;
+        }
+
+        Ok(QueryResult {
+            success: true,
+            rows_affected: rows_inserted,
+            data: vec![],
+            columns: vec![],
+            execution_time_ms: 0,
+            next_cursor: None,
+        })
+    }
 
-                    self.db.put(key, value)?;
-                    rows_inserted += 1;
+    // Reads every matching row before anything is written, so a SELECT over
+    // the keys being inserted sees none of its own output.
+    async fn insert_rows_from_select(
+        &self,
+        select: &sqlparser::ast::Select,
+    ) -> VeloResult<Vec<(String, VeloValue)>> {
+        if self.extract_table_name(&select.from)? != "kv" {
+            return Err(VeloError::InvalidOperation(
+                "Only 'kv' table is supported".to_string(),
+            ));
+        }
+        let mut columns = Vec::with_capacity(2);
+        for item in &select.projection {
+            match item {
+                SelectItem::UnnamedExpr(expr) | SelectItem::ExprWithAlias { expr, .. } => {
+                    columns.push(expr)
                 }
+                other => {
+                    return Err(VeloError::InvalidOperation(format!(
+                        "Unsupported INSERT ... SELECT column '{}'",
+                        other
+                    )))
+                }
+            }
+        }
+        let [key_expr, value_expr] = columns.as_slice() else {
+            return Err(VeloError::InvalidOperation(
+                "INSERT ... SELECT must select exactly 2 columns (key, value)".to_string(),
+            ));
+        };
 
-                Ok(QueryResult {
-                    success: true,
-                    rows_affected: rows_inserted,
-                    data: vec![],
-                    columns: vec![],
-                    execution_time_ms: 0,
-                })
+        let key_filter = match &select.selection {
+            Some(where_clause) => match self.extract_key_filter(where_clause)? {
+                KeyFilter::All => {
+                    return Err(VeloError::InvalidOperation(
+                        "INSERT ... SELECT only supports WHERE key = '...' or key LIKE '...'"
+                            .to_string(),
+                    ))
+                }
+                filter => filter,
+            },
+            None => KeyFilter::All,
+        };
+        self.admit_scan(&key_filter).await?;
+        let source = self.matching_rows(&key_filter, None, usize::MAX)?;
+
+        let mut rows = Vec::with_capacity(source.len());
+        for (key, value, _) in source {
+            let new_key = String::from_utf8(self.eval_insert_column(key_expr, &key, &value)?)
+                .map_err(|_| VeloError::InvalidOperation("Keys must be valid UTF-8".to_string()))?;
+            self.validate_key(&new_key)?;
+            rows.push((new_key, self.eval_insert_column(value_expr, &key, &value)?));
+        }
+        Ok(rows)
+    }
+
+    // Columns of an INSERT ... SELECT may be `key`, `value`, literals, `||`
+    // concatenation and REPLACE(expr, 'from', 'to').
+    fn eval_insert_column(&self, expr: &Expr, key: &str, value: &VeloValue) -> VeloResult<Vec<u8>> {
+        match expr {
+            Expr::Identifier(id) if id.value.eq_ignore_ascii_case("key") => {
+                Ok(key.as_bytes().to_vec())
             }
-            _ => Err(VeloError::InvalidOperation(
-                "Unsupported INSERT format".to_string(),
-            )),
+            Expr::Identifier(id) if id.value.eq_ignore_ascii_case("value") => Ok(value.clone()),
+            Expr::Value(_) => self.extract_value_bytes(expr),
+            Expr::Nested(inner) => self.eval_insert_column(inner, key, value),
+            Expr::BinaryOp {
+                left,
+                op: BinaryOperator::StringConcat,
+                right,
+            } => {
+                let mut bytes = self.eval_insert_column(left, key, value)?;
+                bytes.extend(self.eval_insert_column(right, key, value)?);
+                Ok(bytes)
+            }
+            Expr::Function(func)
+                if func.name.to_string().eq_ignore_ascii_case("replace") && func.args.len() == 3 =>
+            {
+                let mut args = Vec::with_capacity(3);
+                for arg in &func.args {
+                    let sqlparser::ast::FunctionArg::Unnamed(
+                        sqlparser::ast::FunctionArgExpr::Expr(arg),
+                    ) = arg
+                    else {
+                        return Err(VeloError::InvalidOperation(format!(
+                            "Unsupported REPLACE argument '{}'",
+                            arg
+                        )));
+                    };
+                    let bytes = self.eval_insert_column(arg, key, value)?;
+                    args.push(String::from_utf8_lossy(&bytes).into_owned());
+                }
+                Ok(args[0].replace(&args[1], &args[2]).into_bytes())
+            }
+            other => Err(VeloError::InvalidOperation(format!(
+                "Unsupported INSERT ... SELECT expression '{}'",
+                other
+            ))),
         }
     }
 
@@ -257,9 +925,10 @@ impl SqlEngine {
         }
 
 
-        let key = if let Some(where_clause) = selection {
-            match self.extract_key_filter(where_clause)? {
-                KeyFilter::Exact(k) => k,
+        let (key, expected_version) = if let Some(where_clause) = selection {
+            let (key_clause, expected_version) = self.split_version_check(where_clause)?;
+            match self.extract_key_filter(key_clause)? {
+                KeyFilter::Exact(k) => (k, expected_version),
                 _ => {
                     return Err(VeloError::InvalidOperation(
                         "UPDATE requires exact key match".to_string(),
@@ -273,17 +942,29 @@ impl SqlEngine {
         };
 
 
+        self.validate_key(&key)?;
         if self.db.get(&key)?.is_none() {
+            // A missing key has no version to match, so a versioned UPDATE
+            // fails the same way a stale one does.
+            if let Some(expected) = expected_version {
+                return Err(VeloError::VersionConflict {
+                    key,
+                    expected,
+                    actual: 0,
+                });
+            }
             return Ok(QueryResult {
                 success: true,
                 rows_affected: 0,
                 data: vec![],
                 columns: vec![],
                 execution_time_ms: 0,
+                next_cursor: None,
             });
         }
 
 
+        let mut new_value = None;
         for assignment in assignments {
             if assignment.id.len() != 1 || assignment.id[0].value != "value" {
                 return Err(VeloError::InvalidOperation(
@@ -291,8 +972,16 @@ impl SqlEngine {
                 ));
             }
 
-            let new_value = self.extract_value_bytes(&assignment.value)?;
-            self.db.put(key.clone(), new_value)?;
+            new_value = Some(self.extract_value_bytes(&assignment.value)?);
+        }
+
+        if let Some(new_value) = new_value {
+            match expected_version {
+                Some(expected) => {
+                    self.db.put_if_version(key, expected, new_value)?;
+                }
+                None => self.db.put(key, new_value)?,
+            }
         }
 
         Ok(QueryResult {
@@ -301,14 +990,127 @@ impl SqlEngine {
             data: vec![],
             columns: vec![],
             execution_time_ms: 0,
+            next_cursor: None,
         })
     }
 
+    fn split_version_check<'a>(&self, expr: &'a Expr) -> VeloResult<(&'a Expr, Option<u64>)> {
+        if let Expr::BinaryOp {
+            left,
+            op: BinaryOperator::And,
+            right,
+        } = expr
+        {
+            for (condition, rest) in [(right, left), (left, right)] {
+                if let Some(version) = self.extract_version_condition(condition)? {
+                    return Ok((rest.as_ref(), Some(version)));
+                }
+            }
+        }
+        Ok((expr, None))
+    }
+
+    fn extract_version_condition(&self, expr: &Expr) -> VeloResult<Option<u64>> {
+        if let Expr::BinaryOp {
+            left,
+            op: BinaryOperator::Eq,
+            right,
+        } = expr
+        {
+            if let (Expr::Identifier(id), Expr::Value(val)) = (left.as_ref(), right.as_ref()) {
+                if id.value.eq_ignore_ascii_case("version") {
+                    let version = match val {
+                        Value::Number(n, _) => n.parse::<u64>().ok(),
+                        _ => None,
+                    };
+                    return version.map(Some).ok_or_else(|| {
+                        VeloError::InvalidOperation(format!("Invalid version: {}", val))
+                    });
+                }
+            }
+        }
+        Ok(None)
+    }
+
     async fn execute_delete(
         &self,
         from: &[sqlparser::ast::TableWithJoins],
         selection: &Option<Expr>,
     ) -> VeloResult<QueryResult> {
+        let key = match self.delete_target(from, selection).await? {
+            DeleteTarget::Key(key) => key,
+            DeleteTarget::Keys(keys) => return self.delete_keys(keys),
+            DeleteTarget::Matching(prefix, pattern) => {
+                return self.delete_matching(&prefix, pattern.as_ref())
+            }
+        };
+
+
+        self.validate_key(&key)?;
+        let existed = self.db.get(&key)?.is_some();
+        if existed {
+            self.db.delete(key)?;
+        }
+
+        Ok(QueryResult {
+            success: true,
+            rows_affected: if existed { 1 } else { 0 },
+            data: vec![],
+            columns: vec![],
+            execution_time_ms: 0,
+            next_cursor: None,
+        })
+    }
+
+    /// The keys a DELETE would remove, with their sizes, found the same way
+    /// the DELETE finds them but without writing anything.
+    pub async fn dry_run(&self, sql: &str) -> VeloResult<DryRunReport> {
+        let statements = Parser::parse_sql(&self.dialect, sql)
+            .map_err(|e| VeloError::InvalidOperation(format!("SQL Parse Error: {}", e)))?;
+        let Some(Statement::Delete {
+            from, selection, ..
+        }) = statements.first()
+        else {
+            return Err(VeloError::InvalidOperation(
+                "DRY RUN supports DELETE, DROP DATABASE and PURGE TRASH".to_string(),
+            ));
+        };
+
+        let mut report = DryRunReport::default();
+        let keys = match self.delete_target(from, selection).await? {
+            DeleteTarget::Key(key) => vec![key],
+            DeleteTarget::Keys(keys) => keys,
+            DeleteTarget::Matching(prefix, pattern) => {
+                // Validated batch by batch like `delete_matching`, so a key the
+                // DELETE would stop at fails the dry run too.
+                self.for_each_matching(&prefix, pattern.as_ref(), |rows| {
+                    for (key, _) in &rows {
+                        self.validate_key(key)?;
+                    }
+                    for (key, value) in rows {
+                        report.add(&key, 1, (key.len() + value.len()) as u64);
+                    }
+                    Ok(())
+                })?;
+                return Ok(report);
+            }
+        };
+        for key in &keys {
+            self.validate_key(key)?;
+        }
+        for (key, value) in keys.iter().zip(self.db.multi_get(&keys)?) {
+            if let Some(value) = value {
+                report.add(key, 1, (key.len() + value.len()) as u64);
+            }
+        }
+        Ok(report)
+    }
+
+    async fn delete_target(
+        &self,
+        from: &[sqlparser::ast::TableWithJoins],
+        selection: &Option<Expr>,
+    ) -> VeloResult<DeleteTarget> {
 
         if from.is_empty() {
             return Err(VeloError::InvalidOperation(
@@ -332,39 +1134,131 @@ impl SqlEngine {
         }
 
 
-        let key = if let Some(where_clause) = selection {
-            match self.extract_key_filter(where_clause)? {
-                KeyFilter::Exact(k) => k,
-                _ => {
-                    return Err(VeloError::InvalidOperation(
-                        "DELETE requires exact key match".to_string(),
-                    ))
-                }
-            }
-        } else {
+        let Some(where_clause) = selection else {
             return Err(VeloError::InvalidOperation(
                 "DELETE requires WHERE clause".to_string(),
             ));
         };
+        let key_filter = self.extract_key_filter(where_clause)?;
+        if matches!(key_filter, KeyFilter::Prefix(_) | KeyFilter::Pattern(_)) {
+            self.admit_scan(&key_filter).await?;
+        }
+        match key_filter {
+            KeyFilter::Exact(k) => Ok(DeleteTarget::Key(k)),
+            KeyFilter::In(keys) => Ok(DeleteTarget::Keys(keys)),
+            // `key >= '...'` also comes back as a prefix; only LIKE and
+            // GLOB delete by prefix.
+            KeyFilter::Prefix(prefix) if !matches!(where_clause, Expr::BinaryOp { .. }) => {
+                Ok(DeleteTarget::Matching(prefix, None))
+            }
+            KeyFilter::Pattern(pattern) => Ok(DeleteTarget::Matching(
+                pattern.literal_prefix(),
+                Some(pattern),
+            )),
+            _ => Err(VeloError::InvalidOperation(
+                "DELETE requires an exact key, IN list or LIKE 'prefix%' match".to_string(),
+            )),
+        }
+    }
 
 
-        let existed = self.db.get(&key)?.is_some();
-        if existed {
+    // Tombstones for the live keys under `prefix` that match `pattern`,
+    // written a batch at a time so clients need not list the keys first.
+    fn delete_matching(
+        &self,
+        prefix: &str,
+        pattern: Option<&KeyPattern>,
+    ) -> VeloResult<QueryResult> {
+        let started = std::time::Instant::now();
+        let mut deleted = 0;
+        self.for_each_matching(prefix, pattern, |rows| {
+            let keys: Vec<String> = rows.into_iter().map(|(key, _)| key).collect();
+            for key in &keys {
+                self.validate_key(key)?;
+            }
+            deleted += keys.len();
+            let ops: Vec<BatchOp> = keys
+                .into_iter()
+                .map(|key| BatchOp::Delete { key })
+                .collect();
+            self.db.write_batch(&ops)
+        })?;
 
+        Ok(QueryResult {
+            success: true,
+            rows_affected: deleted,
+            data: vec![],
+            columns: vec![],
+            execution_time_ms: started.elapsed().as_millis() as u64,
+            next_cursor: None,
+        })
+    }
+
+    // Hands `f` the live entries under `prefix` that match `pattern`, a
+    // non-empty batch at a time. Trashed entries are only included for a
+    // prefix inside the trash.
+    fn for_each_matching(
+        &self,
+        prefix: &str,
+        pattern: Option<&KeyPattern>,
+        mut f: impl FnMut(Vec<(String, VeloValue)>) -> VeloResult<()>,
+    ) -> VeloResult<()> {
+        if prefix.is_empty() {
+            return Err(VeloError::InvalidOperation(
+                "DELETE with LIKE needs a literal prefix, such as 'tmp:%'".to_string(),
+            ));
+        }
+        let include_trash = prefix.starts_with(TRASH_PREFIX);
+        let end = format!("{}\u{10FFFF}", prefix);
+        let mut start = prefix.to_string();
+        loop {
+            let rows = self.db.range(&start, Some(&end), DELETE_BATCH_ROWS)?;
+            let Some((last, _)) = rows.last() else {
+                break;
+            };
+            start = format!("{}\0", last);
+            let done = rows.len() < DELETE_BATCH_ROWS;
+            let rows: Vec<(String, VeloValue)> = rows
+                .into_iter()
+                .filter(|(key, _)| include_trash || !key.starts_with(TRASH_PREFIX))
+                .filter(|(key, _)| pattern.is_none_or(|pattern| pattern.matches(key)))
+                .collect();
+            if !rows.is_empty() {
+                f(rows)?;
+            }
+            if done {
+                break;
+            }
+        }
+        Ok(())
+    }
 
-            self.db.put(key, vec![])?;
+    // Tombstones for the listed keys that exist, written as one batch.
+    fn delete_keys(&self, keys: Vec<String>) -> VeloResult<QueryResult> {
+        for key in &keys {
+            self.validate_key(key)?;
+        }
+        let found = self.db.multi_get(&keys)?;
+        let ops: Vec<BatchOp> = keys
+            .into_iter()
+            .zip(found)
+            .filter(|(_, value)| value.is_some())
+            .map(|(key, _)| BatchOp::Delete { key })
+            .collect();
+        if !ops.is_empty() {
+            self.db.write_batch(&ops)?;
         }
 
         Ok(QueryResult {
             success: true,
-            rows_affected: if existed { 1 } else { 0 },
+            rows_affected: ops.len(),
             data: vec![],
             columns: vec![],
             execution_time_ms: 0,
+            next_cursor: None,
         })
     }
 
-
     fn extract_table_name(&self, from: &[sqlparser::ast::TableWithJoins]) -> VeloResult<String> {
         if from.is_empty() {
             return Err(VeloError::InvalidOperation(
@@ -393,6 +1287,28 @@ impl SqlEngine {
                                 return Ok(KeyFilter::Exact(key));
                             }
                         }
+                        // `key = 'x' COLLATE NOCASE` or `key COLLATE NOCASE = 'x'`
+                        let (left, right, collation) = match (left.as_ref(), right.as_ref()) {
+                            (left, Expr::Collate { expr, collation }) => {
+                                (left, expr.as_ref(), collation)
+                            }
+                            (Expr::Collate { expr, collation }, right) => {
+                                (expr.as_ref(), right, collation)
+                            }
+                            _ => return Ok(KeyFilter::All),
+                        };
+                        if let (Expr::Identifier(id), Expr::Value(val)) = (left, right) {
+                            if id.value == "key" {
+                                if !collation.to_string().eq_ignore_ascii_case("nocase") {
+                                    return Err(VeloError::InvalidOperation(format!(
+                                        "Unsupported collation '{}' (expected NOCASE)",
+                                        collation
+                                    )));
+                                }
+                                let key = self.extract_string_from_value(val)?;
+                                return Ok(KeyFilter::NoCase(key));
+                            }
+                        }
                     }
                     BinaryOperator::GtEq => {
                         if let (Expr::Identifier(id), Expr::Value(val)) =
@@ -412,38 +1328,76 @@ impl SqlEngine {
                 }
             }
 
-            Expr::Function(func) => {
-                if func.name.to_string().to_lowercase() == "like" && func.args.len() == 2 {
-
-                    if let (
-                        sqlparser::ast::FunctionArg::Unnamed(
-                            sqlparser::ast::FunctionArgExpr::Expr(Expr::Identifier(id)),
-                        ),
-                        sqlparser::ast::FunctionArg::Unnamed(
-                            sqlparser::ast::FunctionArgExpr::Expr(Expr::Value(val)),
-                        ),
-                    ) = (&func.args[0], &func.args[1])
-                    {
-                        if id.value == "key" {
-                            let pattern = self.extract_string_from_value(val)?;
-                            if pattern.ends_with('%') {
-                                let prefix = pattern.trim_end_matches('%');
-                                return Ok(KeyFilter::Prefix(prefix.to_string()));
-                            }
-                        }
+            Expr::InList {
+                expr: target,
+                list,
+                negated,
+            } if matches!(target.as_ref(), Expr::Identifier(id) if id.value == "key") => {
+                if *negated {
+                    return Err(VeloError::InvalidOperation(
+                        "NOT IN is not supported on key".to_string(),
+                    ));
+                }
+                let mut keys: Vec<String> = Vec::with_capacity(list.len());
+                for item in list {
+                    let key = self.extract_string_value(item)?;
+                    if !keys.contains(&key) {
+                        keys.push(key);
+                    }
+                }
+                return Ok(KeyFilter::In(keys));
+            }
+
+            Expr::Function(func)
+                if matches!(func.name.to_string().to_lowercase().as_str(), "like" | "glob")
+                    && func.args.len() == 2 =>
+            {
+                if let (
+                    sqlparser::ast::FunctionArg::Unnamed(
+                        sqlparser::ast::FunctionArgExpr::Expr(Expr::Identifier(id)),
+                    ),
+                    sqlparser::ast::FunctionArg::Unnamed(
+                        sqlparser::ast::FunctionArgExpr::Expr(Expr::Value(val)),
+                    ),
+                ) = (&func.args[0], &func.args[1])
+                {
+                    if id.value == "key" {
+                        let pattern = self.extract_string_from_value(val)?;
+                        let pattern = if func.name.to_string().eq_ignore_ascii_case("glob") {
+                            KeyPattern::glob(&pattern)?
+                        } else {
+                            KeyPattern::like(&pattern, None, false, false)?
+                        };
+                        return Ok(pattern.into_filter());
                     }
                 }
             }
 
-            Expr::Like { expr, pattern, .. } => {
-                if let (Expr::Identifier(id), Expr::Value(val)) = (expr.as_ref(), pattern.as_ref())
+            Expr::Like {
+                negated,
+                expr: target,
+                pattern,
+                escape_char,
+            }
+            | Expr::ILike {
+                negated,
+                expr: target,
+                pattern,
+                escape_char,
+            } => {
+                if let (Expr::Identifier(id), Expr::Value(val)) =
+                    (target.as_ref(), pattern.as_ref())
                 {
                     if id.value == "key" {
                         let pattern_str = self.extract_string_from_value(val)?;
-                        if pattern_str.ends_with('%') {
-                            let prefix = pattern_str.trim_end_matches('%');
-                            return Ok(KeyFilter::Prefix(prefix.to_string()));
-                        }
+                        let case_insensitive = matches!(expr, Expr::ILike { .. });
+                        let pattern = KeyPattern::like(
+                            &pattern_str,
+                            *escape_char,
+                            case_insensitive,
+                            *negated,
+                        )?;
+                        return Ok(pattern.into_filter());
                     }
                 }
             }
@@ -483,76 +1437,215 @@ impl SqlEngine {
             )),
         }
     }
+}
 
-    async fn execute_prefix_scan(&self, prefix: &str) -> VeloResult<QueryResult> {
-
-        let mut results = Vec::new();
-
-
-        if let Ok(memtable) = self.db.memtable.read() {
-
+#[derive(Debug)]
+#[allow(dead_code)]
+enum KeyFilter {
+    Exact(String),
+    // Distinct keys in the order they were listed.
+    In(Vec<String>),
+    NoCase(String),
+    Prefix(String),
+    Pattern(KeyPattern),
+    Range(String, String),
+    All,
+}
 
-            let end_prefix = format!("{}\u{10FFFF}", prefix);
+#[derive(Debug, Clone, PartialEq)]
+enum PatternToken {
+    Char(char),
+    AnyChar,
+    AnySequence,
+    // GLOB `[...]`: inclusive ranges, optionally negated with `^`.
+    Class { negated: bool, ranges: Vec<(char, char)> },
+}
 
-            for (key, value) in memtable.range(prefix.to_string()..end_prefix) {
-                if key.starts_with(prefix) {
-                    results.push(Row {
-                        values: vec![SqlValue::String(key.clone()), SqlValue::from(value)],
-                    });
+enum DeleteTarget {
+    Key(String),
+    Keys(Vec<String>),
+    /// Keys under a prefix, and matching a pattern when there is one.
+    Matching(String, Option<KeyPattern>),
+}
 
+/// A LIKE, ILIKE or GLOB pattern on keys.
+#[derive(Debug)]
+struct KeyPattern {
+    source: String,
+    operator: &'static str,
+    tokens: Vec<PatternToken>,
+    case_insensitive: bool,
+    negated: bool,
+}
 
-                    if results.len() >= 1000 {
-                        break;
+impl KeyPattern {
+    // `%` matches any run of characters and `_` exactly one; `escape` makes
+    // the next character literal.
+    fn like(
+        pattern: &str,
+        escape: Option<char>,
+        case_insensitive: bool,
+        negated: bool,
+    ) -> VeloResult<Self> {
+        let mut tokens = Vec::new();
+        let mut chars = pattern.chars();
+        while let Some(c) = chars.next() {
+            tokens.push(match c {
+                c if Some(c) == escape => match chars.next() {
+                    Some(next) => PatternToken::Char(next),
+                    None => {
+                        return Err(VeloError::InvalidOperation(format!(
+                            "LIKE pattern '{}' ends with the escape character",
+                            pattern
+                        )))
                     }
-                }
-            }
+                },
+                '%' => PatternToken::AnySequence,
+                '_' => PatternToken::AnyChar,
+                c if case_insensitive => PatternToken::Char(c.to_lowercase().next().unwrap_or(c)),
+                c => PatternToken::Char(c),
+            });
         }
-
-        Ok(QueryResult {
-            success: true,
-            rows_affected: results.len(),
-            data: results,
-            columns: vec!["key".to_string(), "value".to_string()],
-            execution_time_ms: 0,
+        let operator = match (negated, case_insensitive) {
+            (false, false) => "LIKE",
+            (false, true) => "ILIKE",
+            (true, false) => "NOT LIKE",
+            (true, true) => "NOT ILIKE",
+        };
+        Ok(Self {
+            source: pattern.to_string(),
+            operator,
+            tokens,
+            case_insensitive,
+            negated,
         })
     }
 
-    async fn execute_range_scan(&self, _start: &str, _end: &str) -> VeloResult<QueryResult> {
-
-        Ok(QueryResult {
-            success: true,
-            rows_affected: 0,
-            data: vec![],
-            columns: vec!["key".to_string(), "value".to_string()],
-            execution_time_ms: 0,
+    // `*` matches any run of characters, `?` exactly one and `[a-z]` or
+    // `[^abc]` one character from (or not from) a set. Case-sensitive.
+    fn glob(pattern: &str) -> VeloResult<Self> {
+        let mut tokens = Vec::new();
+        let mut chars = pattern.chars().peekable();
+        while let Some(c) = chars.next() {
+            tokens.push(match c {
+                '*' => PatternToken::AnySequence,
+                '?' => PatternToken::AnyChar,
+                '[' => {
+                    let negated = chars.next_if_eq(&'^').is_some();
+                    let mut ranges = Vec::new();
+                    loop {
+                        let start = match chars.next() {
+                            Some(']') if !ranges.is_empty() => break,
+                            Some(start) => start,
+                            None => {
+                                return Err(VeloError::InvalidOperation(format!(
+                                    "GLOB pattern '{}' has an unclosed '['",
+                                    pattern
+                                )))
+                            }
+                        };
+                        let end = match chars.peek() {
+                            Some('-') => {
+                                chars.next();
+                                match chars.next() {
+                                    Some(']') => {
+                                        ranges.push((start, start));
+                                        ranges.push(('-', '-'));
+                                        break;
+                                    }
+                                    Some(end) => end,
+                                    None => start,
+                                }
+                            }
+                            _ => start,
+                        };
+                        ranges.push((start, end));
+                    }
+                    PatternToken::Class { negated, ranges }
+                }
+                c => PatternToken::Char(c),
+            });
+        }
+        Ok(Self {
+            source: pattern.to_string(),
+            operator: "GLOB",
+            tokens,
+            case_insensitive: false,
+            negated: false,
         })
     }
 
-    async fn execute_full_scan(&self) -> VeloResult<QueryResult> {
-        let scans = self.db.scan(1000);
-        let mut results = Vec::with_capacity(scans.len());
+    // Pure `prefix%` patterns become plain prefix scans. A negated pattern
+    // never does: `NOT LIKE '%'` matches no key, not every key.
+    fn into_filter(self) -> KeyFilter {
+        if self.negated {
+            return KeyFilter::Pattern(self);
+        }
+        let prefix = self.literal_prefix();
+        let rest = &self.tokens[prefix.chars().count()..];
+        if !rest.is_empty() && rest.iter().all(|t| *t == PatternToken::AnySequence) {
+            KeyFilter::Prefix(prefix)
+        } else {
+            KeyFilter::Pattern(self)
+        }
+    }
 
-        for (key, value) in scans {
-            results.push(Row {
-                values: vec![SqlValue::String(key), SqlValue::from(&value)],
-            });
+    /// The literal characters every matching key starts with; empty when the
+    /// pattern cannot narrow the scan.
+    fn literal_prefix(&self) -> String {
+        if self.negated || self.case_insensitive {
+            return String::new();
         }
+        self.tokens
+            .iter()
+            .map_while(|token| match token {
+                PatternToken::Char(c) => Some(*c),
+                _ => None,
+            })
+            .collect()
+    }
 
-        Ok(QueryResult {
-            success: true,
-            rows_affected: results.len(),
-            data: results,
-            columns: vec!["key".to_string(), "value".to_string()],
-            execution_time_ms: 0,
-        })
+    fn matches(&self, key: &str) -> bool {
+        let key: Vec<char> = if self.case_insensitive {
+            key.to_lowercase().chars().collect()
+        } else {
+            key.chars().collect()
+        };
+        let tokens = &self.tokens;
+        let (mut k, mut t) = (0, 0);
+        // Where to resume after the last `%`: the token after it and the key
+        // position it currently absorbs up to.
+        let mut backtrack: Option<(usize, usize)> = None;
+        while k < key.len() {
+            let single = match tokens.get(t) {
+                Some(PatternToken::Char(c)) => *c == key[k],
+                Some(PatternToken::AnyChar) => true,
+                Some(PatternToken::Class { negated, ranges }) => {
+                    ranges.iter().any(|(lo, hi)| (*lo..=*hi).contains(&key[k])) != *negated
+                }
+                _ => false,
+            };
+            if single {
+                k += 1;
+                t += 1;
+            } else if tokens.get(t) == Some(&PatternToken::AnySequence) {
+                t += 1;
+                backtrack = Some((t, k));
+            } else if let Some((resume_t, resume_k)) = backtrack {
+                t = resume_t;
+                k = resume_k + 1;
+                backtrack = Some((resume_t, k));
+            } else {
+                return self.negated;
+            }
+        }
+        let matched = tokens[t..].iter().all(|t| *t == PatternToken::AnySequence);
+        matched != self.negated
     }
 }
 
-#[derive(Debug)]
-#[allow(dead_code)]
-enum KeyFilter {
-    Exact(String),
-    Prefix(String),
-    Range(String, String),
-    All,
+impl std::fmt::Display for KeyPattern {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "key {} '{}'", self.operator, self.source)
+    }
 }
@@ -0,0 +1,219 @@
+use std::collections::{BTreeMap, HashMap};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+use serde::Serialize;
+use sqlparser::ast::Statement;
+
+use crate::VeloResult;
+
+/// Parsed statements keyed by their SQL text, so repeated statements skip
+/// the parser. The least recently used entry is evicted once `capacity`
+/// entries or `max_bytes` of SQL text are held; a capacity of 0 turns the
+/// cache off.
+pub struct StatementCache {
+    capacity: usize,
+    max_bytes: usize,
+    entries: Mutex<Entries>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+    invalidations: AtomicU64,
+}
+
+struct Entry {
+    statements: Arc<Vec<Statement>>,
+    last_use: u64,
+    hits: u64,
+}
+
+#[derive(Default)]
+struct Entries {
+    by_sql: HashMap<String, Entry>,
+    // SQL text by last use, oldest first.
+    by_use: BTreeMap<u64, String>,
+    // Total length of the SQL text in `by_sql`.
+    bytes: usize,
+    clock: u64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct StatementCacheStats {
+    pub entries: usize,
+    pub capacity: usize,
+    pub bytes: usize,
+    pub max_bytes: usize,
+    pub hits: u64,
+    pub misses: u64,
+    pub hit_rate: f64,
+    pub invalidations: u64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct StatementCacheEntry {
+    pub sql: String,
+    pub hits: u64,
+}
+
+impl StatementCache {
+    pub fn new(capacity: usize, max_bytes: usize) -> Self {
+        Self {
+            capacity,
+            max_bytes,
+            entries: Mutex::new(Entries::default()),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+            invalidations: AtomicU64::new(0),
+        }
+    }
+
+    /// The cached statements for `sql`, or the result of `parse` for it,
+    /// which is cached if it succeeds.
+    pub fn get_or_parse<F>(&self, sql: &str, parse: F) -> VeloResult<Arc<Vec<Statement>>>
+    where
+        F: FnOnce(&str) -> VeloResult<Vec<Statement>>,
+    {
+        if self.capacity == 0 {
+            return parse(sql).map(Arc::new);
+        }
+        {
+            let mut guard = self.entries.lock().unwrap();
+            let entries = &mut *guard;
+            let tick = entries.next_tick();
+            if let Some(entry) = entries.by_sql.get_mut(sql) {
+                let statements = entry.statements.clone();
+                let previous = std::mem::replace(&mut entry.last_use, tick);
+                entry.hits += 1;
+                entries.by_use.remove(&previous);
+                entries.by_use.insert(tick, sql.to_string());
+                self.hits.fetch_add(1, Ordering::Relaxed);
+                return Ok(statements);
+            }
+        }
+
+        self.misses.fetch_add(1, Ordering::Relaxed);
+        let statements = Arc::new(parse(sql)?);
+        if sql.len() > self.max_bytes {
+            return Ok(statements);
+        }
+        let mut guard = self.entries.lock().unwrap();
+        let entries = &mut *guard;
+        let tick = entries.next_tick();
+        let entry = Entry {
+            statements: statements.clone(),
+            last_use: tick,
+            hits: 0,
+        };
+        match entries.by_sql.insert(sql.to_string(), entry) {
+            Some(previous) => {
+                entries.by_use.remove(&previous.last_use);
+            }
+            None => entries.bytes += sql.len(),
+        }
+        entries.by_use.insert(tick, sql.to_string());
+        while entries.by_sql.len() > self.capacity || entries.bytes > self.max_bytes {
+            let Some((_, oldest)) = entries.by_use.pop_first() else {
+                break;
+            };
+            entries.by_sql.remove(&oldest);
+            entries.bytes -= oldest.len();
+        }
+        Ok(statements)
+    }
+
+    pub fn stats(&self) -> StatementCacheStats {
+        let hits = self.hits.load(Ordering::Relaxed);
+        let misses = self.misses.load(Ordering::Relaxed);
+        let entries = self.entries.lock().unwrap();
+        StatementCacheStats {
+            entries: entries.by_sql.len(),
+            capacity: self.capacity,
+            bytes: entries.bytes,
+            max_bytes: self.max_bytes,
+            hits,
+            misses,
+            hit_rate: if hits + misses == 0 {
+                0.0
+            } else {
+                hits as f64 / (hits + misses) as f64
+            },
+            invalidations: self.invalidations.load(Ordering::Relaxed),
+        }
+    }
+
+    /// The cached statements, most recently used first, with their literals
+    /// replaced by `?`. Statements that differ only in literals are listed
+    /// once with their hits added up.
+    pub fn entries(&self) -> Vec<StatementCacheEntry> {
+        let entries = self.entries.lock().unwrap();
+        let mut listed: Vec<StatementCacheEntry> = Vec::new();
+        let mut positions: HashMap<String, usize> = HashMap::new();
+        for sql in entries.by_use.values().rev() {
+            let hits = entries.by_sql[sql].hits;
+            let sql = redact_literals(sql);
+            match positions.get(&sql) {
+                Some(&position) => listed[position].hits += hits,
+                None => {
+                    positions.insert(sql.clone(), listed.len());
+                    listed.push(StatementCacheEntry { sql, hits });
+                }
+            }
+        }
+        listed
+    }
+
+    /// Drops every cached statement. Hit counts are kept.
+    pub fn clear(&self) {
+        let mut entries = self.entries.lock().unwrap();
+        entries.by_sql.clear();
+        entries.by_use.clear();
+        entries.bytes = 0;
+        self.invalidations.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+impl Entries {
+    fn next_tick(&mut self) -> u64 {
+        self.clock += 1;
+        self.clock
+    }
+}
+
+// Replaces string and number literals with `?`. Quoted identifiers and
+// digits inside names are kept.
+fn redact_literals(sql: &str) -> String {
+    let mut redacted = String::with_capacity(sql.len());
+    let mut chars = sql.chars().peekable();
+    let mut in_word = false;
+    while let Some(c) = chars.next() {
+        match c {
+            '\'' => {
+                while let Some(c) = chars.next() {
+                    if c == '\'' && chars.next_if_eq(&'\'').is_none() {
+                        break;
+                    }
+                }
+                redacted.push('?');
+                in_word = false;
+            }
+            '"' => {
+                redacted.push(c);
+                for c in chars.by_ref() {
+                    redacted.push(c);
+                    if c == '"' {
+                        break;
+                    }
+                }
+                in_word = false;
+            }
+            c if c.is_ascii_digit() && !in_word => {
+                while chars.next_if(|c| c.is_ascii_alphanumeric() || *c == '.').is_some() {}
+                redacted.push('?');
+            }
+            c => {
+                in_word = c.is_alphanumeric() || c == '_';
+                redacted.push(c);
+            }
+        }
+    }
+    redacted
+}
@@ -1,18 +1,38 @@
-use crate::addon::DatabaseManager;
-use crate::{VeloError, VeloResult};
+use crate::addon::{DatabaseManager, KeyPolicy, ManagerEvent, SYSTEM_DATABASE};
+use crate::auth::{AuthConfig, AuthRegistry};
+use crate::resources::{render_prometheus, ProcessResources};
+use crate::sql::QueryResult;
+use crate::{VeloError, VeloResult, VelocityConfig, VelocityStats, WalSyncMode};
 use axum::{
-    response::Html,
-    routing::{get, post},
+    body::Body,
+    extract::{DefaultBodyLimit, Multipart, Path as AxumPath, Query},
+    http::{header, HeaderMap, StatusCode},
+    response::{Html, IntoResponse, Response},
+    routing::{delete, get, post},
     Json, Router,
 };
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
 use colored::Colorize;
+use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{HashMap, VecDeque};
 use std::net::SocketAddr;
 use std::path::{Path, PathBuf};
-use std::sync::Arc;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant};
 
+const EVENT_FEED_CAPACITY: usize = 100;
+const MAX_IMPORT_BYTES: usize = 64 * 1024 * 1024;
+pub const STUDIO_KEY_PREFIX: &str = "_studio:";
+const MAX_ROWS_PER_PAGE: usize = 1000;
+const MAX_HISTORY_ENTRIES: usize = 200;
+const STUDIO_AUTH_TTL: Duration = Duration::from_secs(300);
 
+static STUDIO_STORE_LOCK: Mutex<()> = Mutex::new(());
+
+
+#[derive(Default)]
 pub struct StudioEngine {
     templates: HashMap<String, String>,
 }
@@ -44,10 +64,63 @@ impl StudioEngine {
     }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Info,
+    Warning,
+    Critical,
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct AnalysisConfig {
+    #[serde(default)]
+    pub suppressed_rules: Vec<String>,
+}
+
+pub struct AnalysisContext<'a> {
+    pub config_path: &'a Path,
+    pub config: Option<toml::Value>,
+    pub manager: &'a DatabaseManager,
+    pub engine: VelocityConfig,
+    pub stats: VelocityStats,
+}
+
+impl AnalysisContext<'_> {
+    fn config_value(&self, section: &str, key: &str) -> Option<&toml::Value> {
+        self.config.as_ref()?.get(section)?.get(key)
+    }
+
+    fn backup_enabled(&self) -> bool {
+        self.manager
+            .list_addons()
+            .iter()
+            .any(|(name, enabled)| name == "backup" && *enabled)
+    }
+}
+
+pub struct AnalysisRule {
+    pub id: &'static str,
+    pub severity: Severity,
+    pub weight: u8,
+    pub check: fn(&AnalysisContext) -> Option<String>,
+    pub remediation: &'static str,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Finding {
+    pub rule: &'static str,
+    pub severity: Severity,
+    pub message: String,
+    pub remediation: &'static str,
+}
+
 #[derive(Serialize)]
 pub struct AnalysisReport {
     pub issues: Vec<String>,
     pub score: u8,
+    pub findings: Vec<Finding>,
+    pub suppressed: Vec<&'static str>,
 }
 
 #[derive(Deserialize)]
@@ -61,45 +134,196 @@ struct DatabaseLimitUpdateRequest {
     default_max_disk_size_bytes: Option<u64>,
 }
 
+const MAX_HEALTHY_SSTABLES: usize = 50;
+const MIN_CACHE_LOOKUPS: u64 = 1000;
+const MAX_BLOOM_FPR: f64 = 0.05;
+const MAX_WAL_SYNC_INTERVAL_MS: u64 = 1000;
+const MIN_FREE_DISK_BYTES: u64 = 1024 * 1024 * 1024;
+const MIN_FREE_DISK_RATIO: f64 = 0.10;
+
+pub fn default_rules() -> Vec<AnalysisRule> {
+    vec![
+        AnalysisRule {
+            id: "config.missing",
+            severity: Severity::Critical,
+            weight: 50,
+            check: |ctx| {
+                (!ctx.config_path.exists())
+                    .then(|| "Configuration file (velocity.toml) is missing!".to_string())
+            },
+            remediation: "Generate one with `velocity db init` and restart the server",
+        },
+        AnalysisRule {
+            id: "config.bind_address",
+            severity: Severity::Warning,
+            weight: 10,
+            check: |ctx| {
+                (ctx.config.is_some() && ctx.config_value("server", "bind_address").is_none())
+                    .then(|| "Security: No bind_address defined, using default.".to_string())
+            },
+            remediation: "Set [server] bind_address to the interface clients should reach",
+        },
+        AnalysisRule {
+            id: "backup.disabled",
+            severity: Severity::Warning,
+            weight: 20,
+            check: |ctx| {
+                (!ctx.backup_enabled())
+                    .then(|| "Risk: Automatic backup system is disabled.".to_string())
+            },
+            remediation: "Enable [addons.backup] or schedule external backups of the data directory",
+        },
+        AnalysisRule {
+            id: "sstable.count",
+            severity: Severity::Warning,
+            weight: 15,
+            check: |ctx| {
+                (ctx.stats.sstable_count > MAX_HEALTHY_SSTABLES).then(|| {
+                    format!(
+                        "Performance: High SSTable count ({}), compaction might be lagging.",
+                        ctx.stats.sstable_count
+                    )
+                })
+            },
+            remediation: "Lower compaction_threshold or check the logs for failed compactions",
+        },
+        AnalysisRule {
+            id: "storage.external_dir",
+            severity: Severity::Warning,
+            weight: 10,
+            check: |ctx| {
+                (!ctx.manager.get_db_config().default_path.exists()).then(|| {
+                    "Path Error: External database directory does not exist.".to_string()
+                })
+            },
+            remediation: "Create [addons.database] default_path or point it at an existing directory",
+        },
+        AnalysisRule {
+            id: "cache.hit_rate",
+            severity: Severity::Warning,
+            weight: 10,
+            check: |ctx| {
+                let target = ctx
+                    .config_value("performance", "target_cache_hit_rate")
+                    .and_then(toml::Value::as_float)
+                    .unwrap_or(ctx.engine.target_cache_hit_rate);
+                let hit_rate = ctx.stats.cache_hit_rate()?;
+                let lookups = ctx.stats.cache_hits + ctx.stats.cache_misses;
+                let cache_full = ctx.stats.cache_entries >= ctx.engine.cache_size * 9 / 10;
+                (lookups >= MIN_CACHE_LOOKUPS && hit_rate < target && cache_full).then(|| {
+                    format!(
+                        "Performance: Cache hit rate {:.1}% is below the {:.1}% target with a full cache ({} entries).",
+                        hit_rate * 100.0,
+                        target * 100.0,
+                        ctx.engine.cache_size
+                    )
+                })
+            },
+            remediation: "Increase [database] cache_size or enable [performance] adaptive_cache",
+        },
+        AnalysisRule {
+            id: "bloom.fpr",
+            severity: Severity::Warning,
+            weight: 5,
+            check: |ctx| {
+                let fpr = ctx.engine.bloom_false_positive_rate;
+                (fpr > MAX_BLOOM_FPR).then(|| {
+                    format!(
+                        "Performance: Bloom filter false positive rate {} causes extra SSTable reads.",
+                        fpr
+                    )
+                })
+            },
+            remediation: "Set [database] bloom_false_positive_rate to 0.01 or lower",
+        },
+        AnalysisRule {
+            id: "wal.sync_mode",
+            severity: Severity::Warning,
+            weight: 10,
+            check: |ctx| match ctx.engine.wal_sync_mode {
+                WalSyncMode::Interval(ms) if ms > MAX_WAL_SYNC_INTERVAL_MS => Some(format!(
+                    "Durability: WAL is synced every {} ms; a crash can lose that much acknowledged data.",
+                    ms
+                )),
+                WalSyncMode::EveryWrite => None,
+                _ if !ctx.backup_enabled() => Some(
+                    "Durability: WAL is not synced on every write and no backups are configured."
+                        .to_string(),
+                ),
+                _ => None,
+            },
+            remediation: "Enable backups, or open embedded databases with WalSyncMode::EveryWrite",
+        },
+        AnalysisRule {
+            id: "disk.headroom",
+            severity: Severity::Critical,
+            weight: 20,
+            check: |ctx| {
+                let db = ctx.manager.get_database("default")?;
+                let available = fs2::available_space(db.data_dir()).ok()?;
+                let total = fs2::total_space(db.data_dir()).ok()?;
+                (available < MIN_FREE_DISK_BYTES
+                    || (available as f64) < total as f64 * MIN_FREE_DISK_RATIO)
+                    .then(|| {
+                        format!(
+                            "Capacity: Only {:.1} GiB of {:.1} GiB free on the data volume.",
+                            available as f64 / 1073741824.0,
+                            total as f64 / 1073741824.0
+                        )
+                    })
+            },
+            remediation: "Free disk space or move the data directory; flushes and compactions need headroom",
+        },
+    ]
+}
+
 pub fn analyze_system(config_path: &Path, db_manager: &DatabaseManager) -> AnalysisReport {
-    let mut issues = Vec::new();
-    let mut score = 100u8;
+    let config = std::fs::read_to_string(config_path)
+        .ok()
+        .and_then(|content| toml::from_str::<toml::Value>(&content).ok());
+    let analysis_config: AnalysisConfig = config
+        .as_ref()
+        .and_then(|c| c.get("analysis"))
+        .and_then(|a| a.clone().try_into().ok())
+        .unwrap_or_default();
+    let engine = db_manager
+        .get_database("default")
+        .map(|db| db.config().clone())
+        .unwrap_or_default();
 
+    let ctx = AnalysisContext {
+        config_path,
+        config,
+        manager: db_manager,
+        engine,
+        stats: db_manager.stats(),
+    };
 
-    if !config_path.exists() {
-        issues.push("Configuration file (velocity.toml) is missing!".to_string());
-        score = score.saturating_sub(50);
-    } else if let Ok(content) = std::fs::read_to_string(config_path) {
-        if !content.contains("bind_address") {
-            issues.push("Security: No bind_address defined, using default.".to_string());
-            score = score.saturating_sub(10);
+    let mut score = 100u8;
+    let mut findings = Vec::new();
+    let mut suppressed = Vec::new();
+    for rule in default_rules() {
+        if analysis_config.suppressed_rules.iter().any(|id| id == rule.id) {
+            suppressed.push(rule.id);
+            continue;
         }
-        if !content.contains("backup")
-            || (content.contains("[addons.backup]") && content.contains("enabled = false"))
-        {
-            issues.push("Risk: Automatic backup system is disabled.".to_string());
-            score = score.saturating_sub(20);
+        if let Some(message) = (rule.check)(&ctx) {
+            score = score.saturating_sub(rule.weight);
+            findings.push(Finding {
+                rule: rule.id,
+                severity: rule.severity,
+                message,
+                remediation: rule.remediation,
+            });
         }
     }
 
-
-    let stats = db_manager.stats();
-    if stats.sstable_count > 50 {
-        issues.push(format!(
-            "Performance: High SSTable count ({}), compaction might be lagging.",
-            stats.sstable_count
-        ));
-        score = score.saturating_sub(15);
-    }
-
-
-    let db_config = db_manager.get_db_config();
-    if !db_config.default_path.exists() {
-        issues.push("Path Error: External database directory does not exist.".to_string());
-        score = score.saturating_sub(10);
+    AnalysisReport {
+        issues: findings.iter().map(|f| f.message.clone()).collect(),
+        score,
+        findings,
+        suppressed,
     }
-
-    AnalysisReport { issues, score }
 }
 
 impl DatabaseManager {
@@ -113,10 +337,29 @@ pub async fn start_studio(
     db_manager: Arc<DatabaseManager>,
     config_path: PathBuf,
 ) -> VeloResult<()> {
+    let auth = Arc::new(StudioAuth::from_config_file(&config_path)?);
     let mut engine = StudioEngine::new();
     engine.register("index", get_studio_html());
     let engine = Arc::new(engine);
 
+    let events: Arc<Mutex<VecDeque<serde_json::Value>>> = Arc::new(Mutex::new(VecDeque::new()));
+    db_manager.on_event({
+        let events = events.clone();
+        move |event: &ManagerEvent| {
+            // Progress has its own panel, fed by /api/maintenance.
+            if matches!(event, ManagerEvent::Maintenance(_)) {
+                return;
+            }
+            let mut entry = serde_json::to_value(event).unwrap();
+            entry["timestamp"] = serde_json::json!(chrono::Local::now().to_rfc3339());
+            let mut events = events.lock().unwrap();
+            if events.len() == EVENT_FEED_CAPACITY {
+                events.pop_front();
+            }
+            events.push_back(entry);
+        }
+    });
+
     let app = Router::new()
         .route(
             "/",
@@ -147,6 +390,72 @@ pub async fn start_studio(
                 move || async move { Json(manager.stats()) }
             }),
         )
+        .route(
+            "/api/metrics",
+            get({
+                let manager = db_manager.clone();
+                move || async move { Json(manager.lifetime_stats()) }
+            }),
+        )
+        .route(
+            "/metrics",
+            get({
+                let manager = db_manager.clone();
+                move || async move {
+                    let body = render_prometheus(
+                        &manager.stats(),
+                        &manager.lifetime_stats(),
+                        &ProcessResources::sample(),
+                    );
+                    (
+                        [(
+                            axum::http::header::CONTENT_TYPE,
+                            "text/plain; version=0.0.4",
+                        )],
+                        body,
+                    )
+                }
+            }),
+        )
+        .route(
+            "/api/backup/schedule",
+            get({
+                let manager = db_manager.clone();
+                move || async move {
+                    match manager.backup_schedule_info() {
+                        Ok(info) => Json(serde_json::to_value(info).unwrap()),
+                        Err(e) => Json(serde_json::json!({ "error": e.to_string() })),
+                    }
+                }
+            }),
+        )
+        .route(
+            "/api/events",
+            get({
+                let events = events.clone();
+                move || async move {
+                    let events: Vec<_> = events.lock().unwrap().iter().rev().cloned().collect();
+                    Json(events)
+                }
+            }),
+        )
+        .route(
+            "/api/maintenance",
+            get({
+                let manager = db_manager.clone();
+                let auth = auth.clone();
+                move |headers: HeaderMap, Query(query): Query<MaintenanceQuery>| async move {
+                    if let Err(response) = auth.principal(&headers).await {
+                        return response;
+                    }
+                    Json(serde_json::json!({
+                        "running": manager.running_maintenance(),
+                        "events": manager.maintenance_events(query.after.unwrap_or(0)),
+                    }))
+                    .into_response()
+                }
+            }),
+        )
         .route(
             "/api/addons",
             get({
@@ -221,6 +530,291 @@ pub async fn start_studio(
                 }
             }),
         )
+        .route(
+            "/api/db/:name/lsm",
+            get({
+                let manager = db_manager.clone();
+                move |AxumPath(name): AxumPath<String>| async move {
+                    match manager.get_database(&name) {
+                        Some(db) => Json(serde_json::to_value(db.lsm_shape()).unwrap()).into_response(),
+                        None => (
+                            StatusCode::NOT_FOUND,
+                            Json(serde_json::json!({ "status": "error", "message": format!("Database '{}' not found", name) })),
+                        )
+                            .into_response(),
+                    }
+                }
+            }),
+        )
+        .route(
+            "/api/db/:name/hot-keys",
+            get({
+                let manager = db_manager.clone();
+                move |AxumPath(name): AxumPath<String>| async move {
+                    // As SHOW HOT KEYS for a non-administrator: no reserved
+                    // keys and no `_system`.
+                    let db = manager.get_database(&name).filter(|_| name != SYSTEM_DATABASE);
+                    match db {
+                        Some(db) => match db.hot_keys() {
+                            Some(mut report) => {
+                                report.hide_prefixes(&manager.key_policy(&name).reserved_prefixes);
+                                Json(serde_json::to_value(report).unwrap()).into_response()
+                            }
+                            None => Json(serde_json::json!({ "status": "error", "message": "Hot key tracking is off" })).into_response(),
+                        },
+                        None => (
+                            StatusCode::NOT_FOUND,
+                            Json(serde_json::json!({ "status": "error", "message": format!("Database '{}' not found", name) })),
+                        )
+                            .into_response(),
+                    }
+                }
+            }),
+        )
+        .route(
+            "/api/db/:name/export",
+            get({
+                let manager = db_manager.clone();
+                let auth = auth.clone();
+                move |headers: HeaderMap,
+                      AxumPath(name): AxumPath<String>,
+                      Query(query): Query<ExportQuery>| async move {
+                    if let Err(response) = auth.principal(&headers).await {
+                        return response;
+                    }
+                    export_database(&manager, &name, query)
+                }
+            }),
+        )
+        .route(
+            "/api/db/:name/import",
+            post({
+                let manager = db_manager.clone();
+                let auth = auth.clone();
+                move |headers: HeaderMap,
+                      AxumPath(name): AxumPath<String>,
+                      Query(query): Query<ImportQuery>,
+                      multipart: Multipart| async move {
+                    if let Err(response) = auth.principal(&headers).await {
+                        return response;
+                    }
+                    import_database(&manager, &name, query, multipart).await.into_response()
+                }
+            })
+            .layer(DefaultBodyLimit::max(MAX_IMPORT_BYTES)),
+        )
+        .route(
+            "/api/preferences",
+            get({
+                let manager = db_manager.clone();
+                let auth = auth.clone();
+                move |headers: HeaderMap| async move {
+                    let user = match auth.principal(&headers).await {
+                        Ok(user) => user,
+                        Err(response) => return response,
+                    };
+                    match load_preferences(&manager, &user) {
+                        Ok(preferences) => Json(serde_json::to_value(preferences).unwrap()).into_response(),
+                        Err(e) => Json(serde_json::json!({ "status": "error", "message": e.to_string() })).into_response(),
+                    }
+                }
+            })
+            .post({
+                let manager = db_manager.clone();
+                let auth = auth.clone();
+                move |headers: HeaderMap, Json(update): Json<PreferencesUpdate>| async move {
+                    let user = match auth.principal(&headers).await {
+                        Ok(user) => user,
+                        Err(response) => return response,
+                    };
+                    if !is_local_request(&headers) {
+                        return Json(serde_json::json!({ "status": "error", "message": "Access Denied" })).into_response();
+                    }
+                    match update_preferences(&manager, &user, update) {
+                        Ok(preferences) => {
+                            Json(serde_json::json!({ "status": "ok", "user": user, "preferences": preferences })).into_response()
+                        }
+                        Err(message) => Json(serde_json::json!({ "status": "error", "message": message })).into_response(),
+                    }
+                }
+            }),
+        )
+        .route(
+            "/api/query",
+            post({
+                let manager = db_manager.clone();
+                let auth = auth.clone();
+                move |headers: HeaderMap, Json(request): Json<QueryRequest>| async move {
+                    execute_console_request(&manager, &auth, &headers, request.sql, request.database).await
+                }
+            }),
+        )
+        .route(
+            "/api/history",
+            get({
+                let manager = db_manager.clone();
+                let auth = auth.clone();
+                move |headers: HeaderMap| async move {
+                    let user = match auth.principal(&headers).await {
+                        Ok(user) => user,
+                        Err(response) => return response,
+                    };
+                    studio_json(query_history(&manager, &user).map_err(|e| e.to_string())).into_response()
+                }
+            })
+            .delete({
+                let manager = db_manager.clone();
+                let auth = auth.clone();
+                move |headers: HeaderMap| async move {
+                    let user = match auth.principal(&headers).await {
+                        Ok(user) => user,
+                        Err(response) => return response,
+                    };
+                    if !is_local_request(&headers) {
+                        return studio_json::<()>(Err("Access Denied".to_string())).into_response();
+                    }
+                    studio_json(
+                        delete_history_entry(&manager, &user, None)
+                            .map(|deleted| serde_json::json!({ "status": "ok", "deleted": deleted }))
+                            .map_err(|e| e.to_string()),
+                    )
+                    .into_response()
+                }
+            }),
+        )
+        .route(
+            "/api/history/export",
+            get({
+                let manager = db_manager.clone();
+                let auth = auth.clone();
+                move |headers: HeaderMap| async move {
+                    let user = match auth.principal(&headers).await {
+                        Ok(user) => user,
+                        Err(response) => return response,
+                    };
+                    match query_history(&manager, &user) {
+                        Ok(history) => (
+                            [
+                                (header::CONTENT_TYPE, "application/json".to_string()),
+                                (
+                                    header::CONTENT_DISPOSITION,
+                                    format!("attachment; filename=\"{}-query-history.json\"", user),
+                                ),
+                            ],
+                            serde_json::to_string_pretty(&history).unwrap(),
+                        )
+                            .into_response(),
+                        Err(e) => studio_json::<()>(Err(e.to_string())).into_response(),
+                    }
+                }
+            }),
+        )
+        .route(
+            "/api/history/:id",
+            delete({
+                let manager = db_manager.clone();
+                let auth = auth.clone();
+                move |headers: HeaderMap, AxumPath(id): AxumPath<u64>| async move {
+                    let user = match auth.principal(&headers).await {
+                        Ok(user) => user,
+                        Err(response) => return response,
+                    };
+                    if !is_local_request(&headers) {
+                        return studio_json::<()>(Err("Access Denied".to_string())).into_response();
+                    }
+                    studio_json(match delete_history_entry(&manager, &user, Some(id)) {
+                        Ok(0) => Err(format!("History entry {} not found", id)),
+                        Ok(_) => Ok(serde_json::json!({ "status": "ok" })),
+                        Err(e) => Err(e.to_string()),
+                    })
+                    .into_response()
+                }
+            }),
+        )
+        .route(
+            "/api/history/:id/rerun",
+            post({
+                let manager = db_manager.clone();
+                let auth = auth.clone();
+                move |headers: HeaderMap, AxumPath(id): AxumPath<u64>| async move {
+                    let user = match auth.principal(&headers).await {
+                        Ok(user) => user,
+                        Err(response) => return response,
+                    };
+                    let entry = query_history(&manager, &user)
+                        .map_err(|e| e.to_string())
+                        .and_then(|history| {
+                            history
+                                .into_iter()
+                                .find(|entry| entry.id == id)
+                                .ok_or_else(|| format!("History entry {} not found", id))
+                        });
+                    match entry {
+                        Ok(entry) => {
+                            execute_console_request(&manager, &auth, &headers, entry.sql, Some(entry.database))
+                                .await
+                        }
+                        Err(message) => studio_json::<()>(Err(message)).into_response(),
+                    }
+                }
+            }),
+        )
+        .route(
+            "/api/saved-queries",
+            get({
+                let manager = db_manager.clone();
+                let auth = auth.clone();
+                move |headers: HeaderMap| async move {
+                    if let Err(response) = auth.principal(&headers).await {
+                        return response;
+                    }
+                    studio_json(saved_queries(&manager).map_err(|e| e.to_string())).into_response()
+                }
+            })
+            .post({
+                let manager = db_manager.clone();
+                let auth = auth.clone();
+                move |headers: HeaderMap, Json(request): Json<SaveQueryRequest>| async move {
+                    let user = match auth.principal(&headers).await {
+                        Ok(user) => user,
+                        Err(response) => return response,
+                    };
+                    if !is_local_request(&headers) {
+                        return studio_json::<()>(Err("Access Denied".to_string())).into_response();
+                    }
+                    let query = SavedQuery {
+                        name: request.name.trim().to_string(),
+                        sql: request.sql,
+                        database: request.database.unwrap_or_else(|| "default".to_string()),
+                        saved_by: user,
+                        saved_at: chrono::Local::now().to_rfc3339(),
+                    };
+                    let result = save_query(&manager, query).map_err(|e| e.to_string());
+                    studio_json(result.map(|_| serde_json::json!({ "status": "ok" }))).into_response()
+                }
+            }),
+        )
+        .route(
+            "/api/saved-queries/:name",
+            delete({
+                let manager = db_manager.clone();
+                let auth = auth.clone();
+                move |headers: HeaderMap, AxumPath(name): AxumPath<String>| async move {
+                    if let Err(response) = auth.principal(&headers).await {
+                        return response;
+                    }
+                    if !is_local_request(&headers) {
+                        return studio_json::<()>(Err("Access Denied".to_string())).into_response();
+                    }
+                    studio_json(match delete_saved_query(&manager, &name) {
+                        Ok(true) => Ok(serde_json::json!({ "status": "ok" })),
+                        Ok(false) => Err(format!("Saved query '{}' not found", name)),
+                        Err(e) => Err(e.to_string()),
+                    })
+                    .into_response()
+                }
+            }),
+        )
         .layer(tower_http::cors::CorsLayer::permissive());
 
     println!(
@@ -240,6 +834,726 @@ pub async fn start_studio(
     Ok(())
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum TransferFormat {
+    Jsonl,
+    Csv,
+}
+
+impl TransferFormat {
+    fn parse(format: Option<&str>, file_name: Option<&str>) -> Result<Self, String> {
+        match format.map(str::to_ascii_lowercase).as_deref() {
+            Some("jsonl") | Some("json") => Ok(Self::Jsonl),
+            Some("csv") => Ok(Self::Csv),
+            Some(other) => Err(format!("Unsupported format '{}' (use jsonl or csv)", other)),
+            None if file_name.is_some_and(|n| n.to_ascii_lowercase().ends_with(".csv")) => {
+                Ok(Self::Csv)
+            }
+            None => Ok(Self::Jsonl),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct MaintenanceQuery {
+    after: Option<u64>,
+}
+
+#[derive(Deserialize)]
+struct ExportQuery {
+    format: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct ImportQuery {
+    format: Option<String>,
+    #[serde(default)]
+    dry_run: bool,
+}
+
+type ImportRecord = (String, Vec<u8>);
+
+#[derive(Debug, Serialize)]
+struct ImportError {
+    line: usize,
+    message: String,
+}
+
+fn encode_value(value: &[u8]) -> (String, &'static str) {
+    match std::str::from_utf8(value) {
+        Ok(text) => (text.to_string(), "utf8"),
+        Err(_) => (BASE64.encode(value), "base64"),
+    }
+}
+
+fn decode_value(value: &str, encoding: &str) -> Result<Vec<u8>, String> {
+    match encoding {
+        "" | "utf8" => Ok(value.as_bytes().to_vec()),
+        "base64" => BASE64
+            .decode(value)
+            .map_err(|e| format!("invalid base64 value: {}", e)),
+        other => Err(format!("unknown encoding '{}'", other)),
+    }
+}
+
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+fn export_line(format: TransferFormat, key: &str, value: &[u8]) -> String {
+    let (value, encoding) = encode_value(value);
+    match format {
+        TransferFormat::Jsonl => format!(
+            "{}\n",
+            serde_json::json!({ "key": key, "value": value, "encoding": encoding })
+        ),
+        TransferFormat::Csv => format!(
+            "{},{},{}\n",
+            csv_field(key),
+            csv_field(&value),
+            encoding
+        ),
+    }
+}
+
+fn parse_csv(text: &str) -> Vec<(usize, Result<Vec<String>, String>)> {
+    let mut records = Vec::new();
+    let mut fields = Vec::new();
+    let mut field = String::new();
+    let mut in_quotes = false;
+    let mut line = 1;
+    let mut record_line = 1;
+    let mut chars = text.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if in_quotes && chars.peek() == Some(&'"') => {
+                chars.next();
+                field.push('"');
+            }
+            '"' if in_quotes => in_quotes = false,
+            '"' if field.is_empty() => in_quotes = true,
+            ',' if !in_quotes => fields.push(std::mem::take(&mut field)),
+            '\r' if !in_quotes && chars.peek() == Some(&'\n') => {}
+            '\n' if !in_quotes => {
+                fields.push(std::mem::take(&mut field));
+                records.push((record_line, Ok(std::mem::take(&mut fields))));
+                line += 1;
+                record_line = line;
+            }
+            c => {
+                if c == '\n' {
+                    line += 1;
+                }
+                field.push(c);
+            }
+        }
+    }
+
+    if in_quotes {
+        records.push((record_line, Err("unterminated quoted field".to_string())));
+    } else if !field.is_empty() || !fields.is_empty() {
+        fields.push(field);
+        records.push((record_line, Ok(fields)));
+    }
+    records
+}
+
+fn validate_record(key: &str, value: Vec<u8>) -> Result<ImportRecord, String> {
+    if key.is_empty() {
+        return Err("key is empty".to_string());
+    }
+    if key.starts_with(STUDIO_KEY_PREFIX) {
+        return Err(format!("keys starting with '{}' are reserved", STUDIO_KEY_PREFIX));
+    }
+    if key.len() > u16::MAX as usize {
+        return Err(format!("key is longer than {} bytes", u16::MAX));
+    }
+    if value.is_empty() {
+        return Err("value is empty".to_string());
+    }
+    Ok((key.to_string(), value))
+}
+
+// Keys are checked against the database's key policy as a logged-in user
+// account, like writes over the protocol.
+fn parse_import(
+    data: &[u8],
+    format: TransferFormat,
+    policy: &KeyPolicy,
+) -> (Vec<ImportRecord>, Vec<ImportError>) {
+    let mut records = Vec::new();
+    let mut errors = Vec::new();
+
+    let text = match std::str::from_utf8(data) {
+        Ok(text) => text,
+        Err(e) => {
+            errors.push(ImportError {
+                line: 0,
+                message: format!("file is not valid UTF-8: {}", e),
+            });
+            return (records, errors);
+        }
+    };
+
+    let parsed: Vec<(usize, Result<ImportRecord, String>)> = match format {
+        TransferFormat::Jsonl => text
+            .lines()
+            .enumerate()
+            .filter(|(_, line)| !line.trim().is_empty())
+            .map(|(index, line)| {
+                let record = serde_json::from_str::<serde_json::Value>(line)
+                    .map_err(|e| format!("invalid JSON: {}", e))
+                    .and_then(|value| {
+                        let key = value["key"].as_str().ok_or("missing string field 'key'")?;
+                        let raw = value["value"]
+                            .as_str()
+                            .ok_or("missing string field 'value'")?;
+                        let encoding = value["encoding"].as_str().unwrap_or("utf8");
+                        validate_record(key, decode_value(raw, encoding)?)
+                    });
+                (index + 1, record)
+            })
+            .collect(),
+        TransferFormat::Csv => parse_csv(text)
+            .into_iter()
+            .filter(|(line, fields)| {
+                let is_header = *line == 1
+                    && fields
+                        .as_ref()
+                        .is_ok_and(|f| f.first().map(String::as_str) == Some("key"));
+                !is_header && fields.as_ref().map_or(true, |f| f != &[String::new()])
+            })
+            .map(|(line, fields)| {
+                let record = fields.and_then(|fields| match fields.as_slice() {
+                    [key, value] => validate_record(key, value.as_bytes().to_vec()),
+                    [key, value, encoding] => validate_record(key, decode_value(value, encoding)?),
+                    _ => Err(format!(
+                        "expected key,value[,encoding] but found {} fields",
+                        fields.len()
+                    )),
+                });
+                (line, record)
+            })
+            .collect(),
+    };
+
+    for (line, record) in parsed {
+        let record = record.and_then(|record| {
+            policy
+                .check(&record.0, true)
+                .map(|_| record)
+                .map_err(|e| e.to_string())
+        });
+        match record {
+            Ok(record) => records.push(record),
+            Err(message) => errors.push(ImportError { line, message }),
+        }
+    }
+    (records, errors)
+}
+
+fn export_database(manager: &DatabaseManager, name: &str, query: ExportQuery) -> Response {
+    if name == SYSTEM_DATABASE {
+        return (
+            StatusCode::FORBIDDEN,
+            Json(serde_json::json!({ "status": "error", "message": "The system database cannot be exported" })),
+        )
+            .into_response();
+    }
+    let Some(db) = manager.get_database(name) else {
+        return (
+            StatusCode::NOT_FOUND,
+            Json(serde_json::json!({ "status": "error", "message": format!("Database '{}' not found", name) })),
+        )
+            .into_response();
+    };
+    let format = match TransferFormat::parse(query.format.as_deref(), None) {
+        Ok(format) => format,
+        Err(message) => {
+            return (
+                StatusCode::BAD_REQUEST,
+                Json(serde_json::json!({ "status": "error", "message": message })),
+            )
+                .into_response()
+        }
+    };
+
+    let (content_type, extension) = match format {
+        TransferFormat::Jsonl => ("application/x-ndjson", "jsonl"),
+        TransferFormat::Csv => ("text/csv; charset=utf-8", "csv"),
+    };
+    let header = (format == TransferFormat::Csv).then(|| "key,value,encoding\n".to_string());
+    let entries = match db.range("", None, usize::MAX) {
+        Ok(entries) => entries,
+        Err(e) => {
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(serde_json::json!({ "status": "error", "message": e.to_string() })),
+            )
+                .into_response()
+        }
+    };
+    let lines = entries
+        .into_iter()
+        .filter(|(key, _)| !key.starts_with(STUDIO_KEY_PREFIX))
+        .map(move |(key, value)| export_line(format, &key, &value));
+    let body = futures::stream::iter(header.into_iter().chain(lines).map(Ok::<_, std::io::Error>));
+
+    (
+        [
+            (header::CONTENT_TYPE, content_type.to_string()),
+            (
+                header::CONTENT_DISPOSITION,
+                format!("attachment; filename=\"{}.{}\"", name, extension),
+            ),
+        ],
+        Body::from_stream(body),
+    )
+        .into_response()
+}
+
+async fn import_database(
+    manager: &DatabaseManager,
+    name: &str,
+    query: ImportQuery,
+    mut multipart: Multipart,
+) -> (StatusCode, Json<serde_json::Value>) {
+    let error = |status: StatusCode, message: String| {
+        (status, Json(serde_json::json!({ "status": "error", "message": message })))
+    };
+
+    if name == SYSTEM_DATABASE {
+        return error(
+            StatusCode::FORBIDDEN,
+            "The system database cannot be imported into".to_string(),
+        );
+    }
+    let Some(db) = manager.get_database(name) else {
+        return error(StatusCode::NOT_FOUND, format!("Database '{}' not found", name));
+    };
+
+    let (file_name, data) = loop {
+        match multipart.next_field().await {
+            Ok(Some(field)) if field.name() == Some("file") => {
+                let file_name = field.file_name().map(str::to_string);
+                match field.bytes().await {
+                    Ok(data) => break (file_name, data),
+                    Err(e) => return error(StatusCode::BAD_REQUEST, e.to_string()),
+                }
+            }
+            Ok(Some(_)) => continue,
+            Ok(None) => {
+                return error(
+                    StatusCode::BAD_REQUEST,
+                    "Missing multipart field 'file'".to_string(),
+                )
+            }
+            Err(e) => return error(StatusCode::BAD_REQUEST, e.to_string()),
+        }
+    };
+
+    let format = match TransferFormat::parse(query.format.as_deref(), file_name.as_deref()) {
+        Ok(format) => format,
+        Err(message) => return error(StatusCode::BAD_REQUEST, message),
+    };
+    let (records, errors) = parse_import(&data, format, &manager.key_policy(name));
+
+    if query.dry_run || !errors.is_empty() {
+        let status = if errors.is_empty() {
+            StatusCode::OK
+        } else {
+            StatusCode::UNPROCESSABLE_ENTITY
+        };
+        return (
+            status,
+            Json(serde_json::json!({
+                "status": if errors.is_empty() { "ok" } else { "error" },
+                "dry_run": query.dry_run,
+                "records": records.len(),
+                "errors": errors,
+            })),
+        );
+    }
+
+    if let Err(e) = manager.can_accept_write(name) {
+        return error(StatusCode::CONFLICT, e.to_string());
+    }
+    let imported = records.len();
+    if let Err(e) = db.put_batch(records) {
+        return error(StatusCode::INTERNAL_SERVER_ERROR, e.to_string());
+    }
+    log::info!("Studio imported {} records into '{}'", imported, name);
+
+    (
+        StatusCode::OK,
+        Json(serde_json::json!({ "status": "ok", "dry_run": false, "records": imported })),
+    )
+}
+
+// Studio users sign in with HTTP basic auth against the same users and
+// realms as the server. Verified credentials are remembered for a few
+// minutes so that polling does not hash a password on every request.
+struct StudioAuth {
+    registry: AuthRegistry,
+    verified: Mutex<HashMap<Vec<u8>, (String, Instant)>>,
+}
+
+impl StudioAuth {
+    fn from_config_file(config_path: &Path) -> VeloResult<Self> {
+        let config = std::fs::read_to_string(config_path)
+            .ok()
+            .and_then(|content| toml::from_str::<toml::Value>(&content).ok());
+        let section = |name: &str| config.as_ref().and_then(|c| c.get(name)).cloned();
+        let users: HashMap<String, String> = section("users")
+            .and_then(|users| users.try_into().ok())
+            .unwrap_or_default();
+        let auth: AuthConfig = section("auth")
+            .and_then(|auth| auth.try_into().ok())
+            .unwrap_or_default();
+        Ok(Self {
+            registry: AuthRegistry::from_config(&auth, Arc::new(RwLock::new(users)))?,
+            verified: Mutex::new(HashMap::new()),
+        })
+    }
+
+    /// The principal that signed the request, or the response to send back.
+    async fn principal(&self, headers: &HeaderMap) -> Result<String, Response> {
+        let credentials = headers
+            .get(header::AUTHORIZATION)
+            .and_then(|h| h.to_str().ok())
+            .and_then(|h| h.strip_prefix("Basic "))
+            .and_then(|encoded| BASE64.decode(encoded.trim()).ok())
+            .and_then(|decoded| String::from_utf8(decoded).ok());
+        let Some(credentials) = credentials else {
+            return Err(unauthorized());
+        };
+        let fingerprint = ring::digest::digest(&ring::digest::SHA256, credentials.as_bytes())
+            .as_ref()
+            .to_vec();
+        if let Some((principal, at)) = self.verified.lock().unwrap().get(&fingerprint) {
+            if at.elapsed() < STUDIO_AUTH_TTL {
+                return Ok(principal.clone());
+            }
+        }
+
+        let (login, secret) = credentials.split_once(':').unwrap_or((&credentials, ""));
+        match self.registry.authenticate(login, secret).await {
+            Ok(Some(user)) => {
+                let mut verified = self.verified.lock().unwrap();
+                verified.retain(|_, (_, at)| at.elapsed() < STUDIO_AUTH_TTL);
+                verified.insert(fingerprint, (user.principal.clone(), Instant::now()));
+                Ok(user.principal)
+            }
+            Ok(None) => Err(unauthorized()),
+            Err(e) => Err((
+                StatusCode::SERVICE_UNAVAILABLE,
+                Json(serde_json::json!({ "status": "error", "message": e.to_string() })),
+            )
+                .into_response()),
+        }
+    }
+}
+
+fn unauthorized() -> Response {
+    (
+        StatusCode::UNAUTHORIZED,
+        [(header::WWW_AUTHENTICATE, "Basic realm=\"Velocity Studio\"")],
+        Json(serde_json::json!({ "status": "error", "message": "Authentication required" })),
+    )
+        .into_response()
+}
+
+fn is_local_request(headers: &HeaderMap) -> bool {
+    let host = headers.get("host").and_then(|h| h.to_str().ok()).unwrap_or("");
+    host.starts_with("localhost") || host.starts_with("127.0.0.1")
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Theme {
+    #[default]
+    Dark,
+    Light,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct StudioPreferences {
+    #[serde(default)]
+    pub theme: Theme,
+    #[serde(default = "default_preferences_database")]
+    pub default_database: String,
+    #[serde(default = "default_rows_per_page")]
+    pub rows_per_page: usize,
+}
+
+fn default_preferences_database() -> String {
+    "default".to_string()
+}
+
+fn default_rows_per_page() -> usize {
+    50
+}
+
+impl Default for StudioPreferences {
+    fn default() -> Self {
+        Self {
+            theme: Theme::default(),
+            default_database: default_preferences_database(),
+            rows_per_page: default_rows_per_page(),
+        }
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct PreferencesUpdate {
+    theme: Option<Theme>,
+    default_database: Option<String>,
+    rows_per_page: Option<usize>,
+}
+
+fn preferences_key(user: &str) -> String {
+    format!("{}prefs:{}", STUDIO_KEY_PREFIX, user)
+}
+
+fn load_studio_value<T: DeserializeOwned + Default>(
+    manager: &DatabaseManager,
+    key: &str,
+) -> VeloResult<T> {
+    let db = manager.system_database();
+    match db.get(key)? {
+        Some(raw) => serde_json::from_slice(&raw)
+            .map_err(|e| VeloError::InvalidOperation(format!("Corrupt Studio data in '{}': {}", key, e))),
+        None => Ok(T::default()),
+    }
+}
+
+fn store_studio_value<T: Serialize>(manager: &DatabaseManager, key: String, value: &T) -> VeloResult<()> {
+    let db = manager.system_database();
+    db.put(key, serde_json::to_vec(value).unwrap())
+}
+
+pub fn load_preferences(manager: &DatabaseManager, user: &str) -> VeloResult<StudioPreferences> {
+    load_studio_value(manager, &preferences_key(user))
+}
+
+pub fn save_preferences(
+    manager: &DatabaseManager,
+    user: &str,
+    preferences: &StudioPreferences,
+) -> VeloResult<()> {
+    if !(1..=MAX_ROWS_PER_PAGE).contains(&preferences.rows_per_page) {
+        return Err(VeloError::InvalidOperation(format!(
+            "rows_per_page must be between 1 and {}",
+            MAX_ROWS_PER_PAGE
+        )));
+    }
+    if manager.get_database(&preferences.default_database).is_none() {
+        return Err(VeloError::KeyNotFound(format!(
+            "Database '{}' not found",
+            preferences.default_database
+        )));
+    }
+    store_studio_value(manager, preferences_key(user), preferences)
+}
+
+fn update_preferences(
+    manager: &DatabaseManager,
+    user: &str,
+    update: PreferencesUpdate,
+) -> Result<StudioPreferences, String> {
+    let mut preferences = load_preferences(manager, user).map_err(|e| e.to_string())?;
+    if let Some(theme) = update.theme {
+        preferences.theme = theme;
+    }
+    if let Some(database) = update.default_database {
+        preferences.default_database = database;
+    }
+    if let Some(rows) = update.rows_per_page {
+        preferences.rows_per_page = rows;
+    }
+    save_preferences(manager, user, &preferences).map_err(|e| e.to_string())?;
+    Ok(preferences)
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct HistoryEntry {
+    pub id: u64,
+    pub sql: String,
+    pub database: String,
+    pub executed_at: String,
+    pub success: bool,
+    pub rows_affected: usize,
+    pub rows_returned: usize,
+    pub execution_time_ms: u64,
+    #[serde(default)]
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct SavedQuery {
+    pub name: String,
+    pub sql: String,
+    pub database: String,
+    pub saved_by: String,
+    pub saved_at: String,
+}
+
+#[derive(Deserialize)]
+struct QueryRequest {
+    sql: String,
+    database: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct SaveQueryRequest {
+    name: String,
+    sql: String,
+    database: Option<String>,
+}
+
+fn history_key(user: &str) -> String {
+    format!("{}history:{}", STUDIO_KEY_PREFIX, user)
+}
+
+fn saved_queries_key() -> String {
+    format!("{}saved_queries", STUDIO_KEY_PREFIX)
+}
+
+pub fn query_history(manager: &DatabaseManager, user: &str) -> VeloResult<Vec<HistoryEntry>> {
+    let mut history: Vec<HistoryEntry> = load_studio_value(manager, &history_key(user))?;
+    history.reverse();
+    Ok(history)
+}
+
+pub fn delete_history_entry(manager: &DatabaseManager, user: &str, id: Option<u64>) -> VeloResult<usize> {
+    let _guard = STUDIO_STORE_LOCK.lock().unwrap();
+    let mut history: Vec<HistoryEntry> = load_studio_value(manager, &history_key(user))?;
+    let before = history.len();
+    history.retain(|entry| id.is_some_and(|id| entry.id != id));
+    store_studio_value(manager, history_key(user), &history)?;
+    Ok(before - history.len())
+}
+
+fn record_history(manager: &DatabaseManager, user: &str, mut entry: HistoryEntry) -> VeloResult<HistoryEntry> {
+    let _guard = STUDIO_STORE_LOCK.lock().unwrap();
+    let mut history: Vec<HistoryEntry> = load_studio_value(manager, &history_key(user))?;
+    entry.id = history.last().map_or(1, |last| last.id + 1);
+    history.push(entry.clone());
+    if history.len() > MAX_HISTORY_ENTRIES {
+        history.drain(..history.len() - MAX_HISTORY_ENTRIES);
+    }
+    store_studio_value(manager, history_key(user), &history)?;
+    Ok(entry)
+}
+
+pub async fn run_console_query(
+    manager: &Arc<DatabaseManager>,
+    user: &str,
+    sql: &str,
+    database: &str,
+) -> VeloResult<(HistoryEntry, Option<QueryResult>)> {
+    let started = std::time::Instant::now();
+    let engine = manager
+        .get_database(database)
+        .ok_or_else(|| VeloError::KeyNotFound(format!("Database '{}' not found", database)))
+        .map(|db| manager.sql_engine(db, database, true));
+    let result = match engine {
+        Ok(engine) => engine.execute(sql).await,
+        Err(e) => Err(e),
+    };
+
+    let entry = HistoryEntry {
+        id: 0,
+        sql: sql.to_string(),
+        database: database.to_string(),
+        executed_at: chrono::Local::now().to_rfc3339(),
+        success: result.is_ok(),
+        rows_affected: result.as_ref().map_or(0, |r| r.rows_affected),
+        rows_returned: result.as_ref().map_or(0, |r| r.data.len()),
+        execution_time_ms: started.elapsed().as_millis() as u64,
+        error: result.as_ref().err().map(|e| e.to_string()),
+    };
+    let entry = record_history(manager, user, entry)?;
+    Ok((entry, result.ok()))
+}
+
+pub fn saved_queries(manager: &DatabaseManager) -> VeloResult<Vec<SavedQuery>> {
+    let mut queries: Vec<SavedQuery> = load_studio_value(manager, &saved_queries_key())?;
+    queries.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(queries)
+}
+
+pub fn save_query(manager: &DatabaseManager, query: SavedQuery) -> VeloResult<()> {
+    if query.name.trim().is_empty() || query.sql.trim().is_empty() {
+        return Err(VeloError::InvalidOperation(
+            "Saved queries need a name and SQL text".to_string(),
+        ));
+    }
+    let _guard = STUDIO_STORE_LOCK.lock().unwrap();
+    let mut queries: Vec<SavedQuery> = load_studio_value(manager, &saved_queries_key())?;
+    queries.retain(|q| q.name != query.name);
+    queries.push(query);
+    store_studio_value(manager, saved_queries_key(), &queries)
+}
+
+pub fn delete_saved_query(manager: &DatabaseManager, name: &str) -> VeloResult<bool> {
+    let _guard = STUDIO_STORE_LOCK.lock().unwrap();
+    let mut queries: Vec<SavedQuery> = load_studio_value(manager, &saved_queries_key())?;
+    let before = queries.len();
+    queries.retain(|q| q.name != name);
+    store_studio_value(manager, saved_queries_key(), &queries)?;
+    Ok(queries.len() != before)
+}
+
+async fn execute_console_request(
+    manager: &Arc<DatabaseManager>,
+    auth: &StudioAuth,
+    headers: &HeaderMap,
+    sql: String,
+    database: Option<String>,
+) -> Response {
+    let error = |message: String| {
+        Json(serde_json::json!({ "status": "error", "message": message })).into_response()
+    };
+    let user = match auth.principal(headers).await {
+        Ok(user) => user,
+        Err(response) => return response,
+    };
+    if !is_local_request(headers) {
+        return error("Access Denied".to_string());
+    }
+    let database = match database {
+        Some(database) => database,
+        None => match load_preferences(manager, &user) {
+            Ok(preferences) => preferences.default_database,
+            Err(e) => return error(e.to_string()),
+        },
+    };
+
+    match run_console_query(manager, &user, &sql, &database).await {
+        Ok((entry, result)) => Json(serde_json::json!({
+            "status": if entry.success { "ok" } else { "error" },
+            "message": entry.error,
+            "entry": entry,
+            "result": result,
+        }))
+        .into_response(),
+        Err(e) => error(e.to_string()),
+    }
+}
+
+fn studio_json<T: Serialize>(result: Result<T, String>) -> Json<serde_json::Value> {
+    match result {
+        Ok(value) => Json(serde_json::to_value(value).unwrap()),
+        Err(message) => Json(serde_json::json!({ "status": "error", "message": message })),
+    }
+}
+
 fn get_studio_html() -> &'static str {
     include_str!("studio_index.html")
 }
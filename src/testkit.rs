@@ -0,0 +1,419 @@
+//! Protocol conformance checks that can be pointed at any Velocity endpoint.
+//!
+//! `run_conformance` opens fresh TCP connections and walks a fixed matrix of
+//! interactions: the handshake, authentication edge cases, frames split
+//! across writes or coalesced into one, large payloads and the error
+//! responses for malformed or unauthorised requests. Server and proxy
+//! implementations can run it in their own test suites; client authors can
+//! use the same frames as a reference for what a conforming peer accepts.
+//!
+//! Only keys under `testkit:<random>:` are written, and they are deleted
+//! again. The wrong-password case records one failed login for the
+//! harness's address.
+
+use std::fmt;
+use std::time::Duration;
+
+use bytes::{Buf, BytesMut};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::time::timeout;
+
+use crate::server::{encode_batch_ops, BatchWriteResult, MessageType, VelocityMessage};
+use crate::sql::QueryResult;
+use crate::BatchOp;
+
+#[derive(Debug, Clone)]
+pub struct ConformanceConfig {
+    pub address: String,
+    pub username: String,
+    pub password: String,
+    /// Size of the value written by the large payload case.
+    pub large_payload_bytes: usize,
+    /// How long to wait for each response.
+    pub timeout: Duration,
+}
+
+impl ConformanceConfig {
+    pub fn new(address: &str, username: &str, password: &str) -> Self {
+        Self {
+            address: address.to_string(),
+            username: username.to_string(),
+            password: password.to_string(),
+            large_payload_bytes: 1024 * 1024,
+            timeout: Duration::from_secs(10),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct CaseResult {
+    pub name: &'static str,
+    pub passed: bool,
+    pub detail: String,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct ConformanceReport {
+    pub cases: Vec<CaseResult>,
+}
+
+impl ConformanceReport {
+    pub fn passed(&self) -> bool {
+        self.cases.iter().all(|case| case.passed)
+    }
+
+    pub fn failures(&self) -> impl Iterator<Item = &CaseResult> {
+        self.cases.iter().filter(|case| !case.passed)
+    }
+
+    fn record(&mut self, name: &'static str, outcome: Result<String, String>) {
+        let (passed, detail) = match outcome {
+            Ok(detail) => (true, detail),
+            Err(detail) => (false, detail),
+        };
+        self.cases.push(CaseResult {
+            name,
+            passed,
+            detail,
+        });
+    }
+}
+
+impl fmt::Display for ConformanceReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for case in &self.cases {
+            let status = if case.passed { "PASS" } else { "FAIL" };
+            writeln!(f, "{} {:<24} {}", status, case.name, case.detail)?;
+        }
+        let failed = self.failures().count();
+        write!(f, "{} passed, {} failed", self.cases.len() - failed, failed)
+    }
+}
+
+type CaseOutcome = Result<String, String>;
+
+/// Runs every case in order against `config.address`.
+pub async fn run_conformance(config: &ConformanceConfig) -> ConformanceReport {
+    let prefix = format!("testkit:{:08x}:", rand::random::<u32>());
+    let mut report = ConformanceReport::default();
+    report.record("handshake", handshake(config).await);
+    report.record("handshake_without_nonce", handshake_without_nonce(config).await);
+    report.record("ping_before_auth", ping_before_auth(config).await);
+    report.record("command_before_auth", command_before_auth(config).await);
+    report.record("auth_malformed", auth_malformed(config).await);
+    report.record("auth_wrong_password", auth_wrong_password(config).await);
+    report.record("auth_ok", auth_ok(config).await);
+    report.record("split_frame", split_frame(config, &prefix).await);
+    report.record("coalesced_frames", coalesced_frames(config).await);
+    report.record("large_payload", large_payload(config, &prefix).await);
+    report.record("batch_write", batch_write(config, &prefix).await);
+    report.record("invalid_sql", invalid_sql(config).await);
+    report.record("unknown_message_type", unknown_message_type(config).await);
+    report.record("bad_checksum", bad_checksum(config).await);
+    report.record("bad_magic", bad_magic(config).await);
+    report
+}
+
+struct RawConnection {
+    stream: TcpStream,
+    buffer: BytesMut,
+    timeout: Duration,
+}
+
+impl RawConnection {
+    async fn connect(config: &ConformanceConfig) -> Result<Self, String> {
+        let stream = timeout(config.timeout, TcpStream::connect(&config.address))
+            .await
+            .map_err(|_| format!("timed out connecting to {}", config.address))?
+            .map_err(|e| format!("cannot connect to {}: {}", config.address, e))?;
+        stream.set_nodelay(true).ok();
+        Ok(Self {
+            stream,
+            buffer: BytesMut::with_capacity(8192),
+            timeout: config.timeout,
+        })
+    }
+
+    async fn send_bytes(&mut self, bytes: &[u8]) -> Result<(), String> {
+        self.stream
+            .write_all(bytes)
+            .await
+            .map_err(|e| format!("write failed: {}", e))?;
+        self.stream
+            .flush()
+            .await
+            .map_err(|e| format!("flush failed: {}", e))
+    }
+
+    async fn send(&mut self, msg_type: MessageType, payload: &[u8]) -> Result<(), String> {
+        self.send_bytes(&VelocityMessage::new(msg_type, payload.to_vec()).encode())
+            .await
+    }
+
+    // `Ok(None)` when the peer closed the connection.
+    async fn receive(&mut self) -> Result<Option<VelocityMessage>, String> {
+        loop {
+            if let Some(len) = VelocityMessage::frame_len(&self.buffer) {
+                if self.buffer.len() >= len {
+                    let message = VelocityMessage::decode(&self.buffer[..len])
+                        .map_err(|e| format!("undecodable response: {}", e))?;
+                    self.buffer.advance(len);
+                    return Ok(Some(message));
+                }
+            }
+            let read = timeout(self.timeout, self.stream.read_buf(&mut self.buffer))
+                .await
+                .map_err(|_| "timed out waiting for a response".to_string())?;
+            match read {
+                Ok(0) => return Ok(None),
+                Ok(_) => {}
+                Err(e) if e.kind() == std::io::ErrorKind::ConnectionReset => return Ok(None),
+                Err(e) => return Err(format!("read failed: {}", e)),
+            }
+        }
+    }
+
+    async fn expect(&mut self, msg_type: MessageType) -> Result<VelocityMessage, String> {
+        match self.receive().await? {
+            Some(message) if message.msg_type == msg_type => Ok(message),
+            Some(message) => Err(format!(
+                "expected {:?}, got {:?}: {}",
+                msg_type,
+                message.msg_type,
+                String::from_utf8_lossy(&message.payload)
+            )),
+            None => Err(format!("connection closed while waiting for {:?}", msg_type)),
+        }
+    }
+
+    async fn handshake(&mut self) -> Result<String, String> {
+        let nonce: [u8; 32] = rand::random();
+        let hello = format!("Nonce: {}", crate::identity::to_hex(&nonce));
+        self.send(MessageType::Hello, hello.as_bytes()).await?;
+        let info = self.expect(MessageType::ServerInfo).await?;
+        Ok(String::from_utf8_lossy(&info.payload).into_owned())
+    }
+
+    async fn authenticate(&mut self, config: &ConformanceConfig) -> Result<(), String> {
+        self.handshake().await?;
+        let credentials = format!("{}\0{}", config.username, config.password);
+        self.send(MessageType::AuthRequest, credentials.as_bytes()).await?;
+        let response = self.expect(MessageType::AuthResponse).await?;
+        match response.payload.as_slice() {
+            b"OK" => Ok(()),
+            other => Err(format!("login failed: {}", String::from_utf8_lossy(other))),
+        }
+    }
+
+    async fn query(&mut self, sql: &str) -> Result<QueryResult, String> {
+        self.send(MessageType::Command, sql.as_bytes()).await?;
+        let response = self.expect(MessageType::Response).await?;
+        serde_json::from_slice(&response.payload)
+            .map_err(|e| format!("response to '{}' is not a query result: {}", sql, e))
+    }
+}
+
+// A correctly checksummed frame with an arbitrary type byte.
+fn frame_with_type(type_byte: u8, payload: &[u8]) -> Vec<u8> {
+    let mut frame = VelocityMessage::new(MessageType::Ping, payload.to_vec()).encode();
+    frame[5] = type_byte;
+    reseal(&mut frame);
+    frame
+}
+
+fn reseal(frame: &mut [u8]) {
+    let body = frame.len() - 4;
+    let crc = crc32fast::hash(&frame[..body]);
+    frame[body..].copy_from_slice(&crc.to_le_bytes());
+}
+
+fn ensure(condition: bool, failure: impl FnOnce() -> String) -> Result<(), String> {
+    if condition {
+        Ok(())
+    } else {
+        Err(failure())
+    }
+}
+
+async fn handshake(config: &ConformanceConfig) -> CaseOutcome {
+    let mut conn = RawConnection::connect(config).await?;
+    let info = conn.handshake().await?;
+    let banner = info.lines().next().unwrap_or_default().to_string();
+    ensure(banner.starts_with("VelocityDB"), || {
+        format!("unexpected banner '{}'", banner)
+    })?;
+    ensure(info.lines().any(|line| line.starts_with("Fingerprint: ")), || {
+        "server info has no Fingerprint line".to_string()
+    })?;
+    Ok(banner)
+}
+
+async fn handshake_without_nonce(config: &ConformanceConfig) -> CaseOutcome {
+    let mut conn = RawConnection::connect(config).await?;
+    conn.send(MessageType::Hello, b"").await?;
+    conn.expect(MessageType::ServerInfo).await?;
+    Ok("server info returned".to_string())
+}
+
+async fn ping_before_auth(config: &ConformanceConfig) -> CaseOutcome {
+    let mut conn = RawConnection::connect(config).await?;
+    conn.send(MessageType::Ping, b"").await?;
+    conn.expect(MessageType::Pong).await?;
+    Ok("pong".to_string())
+}
+
+async fn command_before_auth(config: &ConformanceConfig) -> CaseOutcome {
+    let mut conn = RawConnection::connect(config).await?;
+    conn.handshake().await?;
+    conn.send(MessageType::Command, b"SELECT * FROM kv").await?;
+    let error = conn.expect(MessageType::Error).await?;
+    Ok(String::from_utf8_lossy(&error.payload).into_owned())
+}
+
+async fn auth_malformed(config: &ConformanceConfig) -> CaseOutcome {
+    let mut conn = RawConnection::connect(config).await?;
+    conn.handshake().await?;
+    conn.send(MessageType::AuthRequest, b"no-separator").await?;
+    let response = conn.expect(MessageType::AuthResponse).await?;
+    ensure(response.payload != b"OK", || {
+        "malformed credentials were accepted".to_string()
+    })?;
+    Ok(String::from_utf8_lossy(&response.payload).into_owned())
+}
+
+async fn auth_wrong_password(config: &ConformanceConfig) -> CaseOutcome {
+    let mut conn = RawConnection::connect(config).await?;
+    conn.handshake().await?;
+    let credentials = format!("{}\0{}-wrong", config.username, config.password);
+    conn.send(MessageType::AuthRequest, credentials.as_bytes()).await?;
+    let response = conn.expect(MessageType::AuthResponse).await?;
+    ensure(response.payload != b"OK", || {
+        "a wrong password was accepted".to_string()
+    })?;
+    Ok(String::from_utf8_lossy(&response.payload).into_owned())
+}
+
+async fn auth_ok(config: &ConformanceConfig) -> CaseOutcome {
+    let mut conn = RawConnection::connect(config).await?;
+    conn.authenticate(config).await?;
+    conn.query("SELECT key FROM kv WHERE key = 'testkit:probe'").await?;
+    Ok("logged in and queried".to_string())
+}
+
+async fn split_frame(config: &ConformanceConfig, prefix: &str) -> CaseOutcome {
+    let mut conn = RawConnection::connect(config).await?;
+    conn.authenticate(config).await?;
+    let sql = format!("SELECT key FROM kv WHERE key = '{}split'", prefix);
+    let frame = VelocityMessage::new(MessageType::Command, sql.into_bytes()).encode();
+    for byte in &frame {
+        conn.send_bytes(std::slice::from_ref(byte)).await?;
+    }
+    conn.expect(MessageType::Response).await?;
+    Ok(format!("{} one-byte writes", frame.len()))
+}
+
+async fn coalesced_frames(config: &ConformanceConfig) -> CaseOutcome {
+    let mut conn = RawConnection::connect(config).await?;
+    let mut frames = VelocityMessage::new(MessageType::Ping, Vec::new()).encode();
+    frames.extend(VelocityMessage::new(MessageType::Ping, Vec::new()).encode());
+    conn.send_bytes(&frames).await?;
+    conn.expect(MessageType::Pong).await?;
+    conn.expect(MessageType::Pong).await?;
+    Ok("two pongs for two frames in one write".to_string())
+}
+
+async fn large_payload(config: &ConformanceConfig, prefix: &str) -> CaseOutcome {
+    let mut conn = RawConnection::connect(config).await?;
+    conn.authenticate(config).await?;
+    let key = format!("{}large", prefix);
+    let value = "x".repeat(config.large_payload_bytes);
+    conn.query(&format!("INSERT INTO kv VALUES ('{}', '{}')", key, value))
+        .await?;
+    let result = conn
+        .query(&format!("SELECT value FROM kv WHERE key = '{}'", key))
+        .await;
+    conn.query(&format!("DELETE FROM kv WHERE key = '{}'", key))
+        .await?;
+
+    let returned = result?
+        .data
+        .first()
+        .and_then(|row| row.values.first())
+        .map_or(0, |value| value.to_bytes().len());
+    ensure(returned == value.len(), || {
+        format!("wrote {} bytes but read back {}", value.len(), returned)
+    })?;
+    Ok(format!("{} byte value round-tripped", returned))
+}
+
+async fn batch_write(config: &ConformanceConfig, prefix: &str) -> CaseOutcome {
+    let mut conn = RawConnection::connect(config).await?;
+    conn.authenticate(config).await?;
+    let key = format!("{}batch", prefix);
+    let ops = [
+        BatchOp::Put {
+            key: key.clone(),
+            value: b"1".to_vec(),
+        },
+        BatchOp::Delete { key },
+    ];
+    conn.send(MessageType::BatchWrite, &encode_batch_ops(&ops)).await?;
+    let response = conn.expect(MessageType::Response).await?;
+    let result: BatchWriteResult = serde_json::from_slice(&response.payload)
+        .map_err(|e| format!("batch response is not a batch result: {}", e))?;
+    ensure(result.applied && result.results.len() == ops.len(), || {
+        format!("batch not applied: {:?}", result)
+    })?;
+    Ok("put and delete applied".to_string())
+}
+
+async fn invalid_sql(config: &ConformanceConfig) -> CaseOutcome {
+    let mut conn = RawConnection::connect(config).await?;
+    conn.authenticate(config).await?;
+    conn.send(MessageType::Command, b"SELEKT nothing").await?;
+    let error = conn.expect(MessageType::Error).await?;
+    Ok(String::from_utf8_lossy(&error.payload).into_owned())
+}
+
+async fn unknown_message_type(config: &ConformanceConfig) -> CaseOutcome {
+    let mut conn = RawConnection::connect(config).await?;
+    conn.send_bytes(&frame_with_type(0x7f, b"")).await?;
+    let error = conn.expect(MessageType::Error).await?;
+    Ok(String::from_utf8_lossy(&error.payload).into_owned())
+}
+
+async fn bad_checksum(config: &ConformanceConfig) -> CaseOutcome {
+    let mut frame = VelocityMessage::new(MessageType::Ping, Vec::new()).encode();
+    let last = frame.len() - 1;
+    frame[last] ^= 0xff;
+    rejects_frame(config, &frame).await
+}
+
+async fn bad_magic(config: &ConformanceConfig) -> CaseOutcome {
+    let mut frame = VelocityMessage::new(MessageType::Ping, Vec::new()).encode();
+    frame[0] ^= 0xff;
+    reseal(&mut frame);
+    rejects_frame(config, &frame).await
+}
+
+// A corrupt frame must be answered with an error or a closed connection,
+// never processed, and must not affect other connections.
+async fn rejects_frame(config: &ConformanceConfig, frame: &[u8]) -> CaseOutcome {
+    let mut conn = RawConnection::connect(config).await?;
+    conn.send_bytes(frame).await?;
+    let outcome = match conn.receive().await? {
+        None => "connection closed".to_string(),
+        Some(message) if message.msg_type == MessageType::Error => {
+            format!("error: {}", String::from_utf8_lossy(&message.payload))
+        }
+        Some(message) => {
+            return Err(format!("corrupt frame answered with {:?}", message.msg_type))
+        }
+    };
+
+    let mut other = RawConnection::connect(config).await?;
+    other.send(MessageType::Ping, b"").await?;
+    other.expect(MessageType::Pong).await?;
+    Ok(outcome)
+}
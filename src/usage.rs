@@ -0,0 +1,102 @@
+use std::sync::Mutex;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{split_record, VeloError, VeloResult, Velocity};
+
+const USAGE_PREFIX: &str = "auth:usage:";
+
+// Sessions of the same user can end at the same time; their updates of the
+// day's totals must not overwrite each other.
+static RECORD_LOCK: Mutex<()> = Mutex::new(());
+
+/// What one connection did, written to the audit log when it closes.
+#[derive(Debug, Clone, Serialize)]
+pub struct SessionSummary {
+    pub user: Option<String>,
+    pub tenant: Option<String>,
+    pub database: String,
+    pub addr: String,
+    pub started_at: String,
+    pub duration_ms: u64,
+    pub commands: u64,
+    pub bytes_in: u64,
+    pub bytes_out: u64,
+    pub errors: u64,
+    pub rate_limited: u64,
+}
+
+/// The sessions of one user that ended on one day (UTC).
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct DailyUsage {
+    pub day: String,
+    pub user: String,
+    pub sessions: u64,
+    pub commands: u64,
+    pub bytes_in: u64,
+    pub bytes_out: u64,
+    pub errors: u64,
+    pub rate_limited: u64,
+    pub duration_ms: u64,
+}
+
+impl DailyUsage {
+    fn add(&mut self, session: &SessionSummary) {
+        self.sessions += 1;
+        self.commands += session.commands;
+        self.bytes_in += session.bytes_in;
+        self.bytes_out += session.bytes_out;
+        self.errors += session.errors;
+        self.rate_limited += session.rate_limited;
+        self.duration_ms += session.duration_ms;
+    }
+}
+
+pub fn today() -> String {
+    chrono::Utc::now().format("%Y-%m-%d").to_string()
+}
+
+/// Adds `session` to its user's totals for `day`. Sessions that never
+/// authenticated are not counted.
+pub fn record_session(db: &Velocity, day: &str, session: &SessionSummary) -> VeloResult<()> {
+    let Some(user) = &session.user else {
+        return Ok(());
+    };
+    let key = format!("{}{}:{}", USAGE_PREFIX, day, user);
+
+    let _guard = RECORD_LOCK.lock().unwrap();
+    let mut usage = match db.get(&key)? {
+        Some(bytes) => serde_json::from_slice(&bytes).map_err(|e| {
+            VeloError::CorruptedData(format!("Invalid usage record '{}': {}", key, e))
+        })?,
+        None => DailyUsage {
+            day: day.to_string(),
+            user: user.clone(),
+            ..Default::default()
+        },
+    };
+    usage.add(session);
+    let bytes = serde_json::to_vec(&usage).map_err(|e| {
+        VeloError::InvalidOperation(format!("Failed to encode usage record: {}", e))
+    })?;
+    db.put(key, bytes)
+}
+
+/// Daily usage ordered by day and user, limited to `day` when given.
+pub fn load_usage(db: &Velocity, day: Option<&str>) -> VeloResult<Vec<DailyUsage>> {
+    let prefix = match day {
+        Some(day) => format!("{}{}:", USAGE_PREFIX, day),
+        None => USAGE_PREFIX.to_string(),
+    };
+    let mut usage = db
+        .scan_prefix_raw(&prefix)
+        .into_iter()
+        .map(|(key, raw)| {
+            serde_json::from_slice::<DailyUsage>(&split_record(raw).0).map_err(|e| {
+                VeloError::CorruptedData(format!("Invalid usage record '{}': {}", key, e))
+            })
+        })
+        .collect::<VeloResult<Vec<_>>>()?;
+    usage.sort_by(|a, b| (&a.day, &a.user).cmp(&(&b.day, &b.user)));
+    Ok(usage)
+}
@@ -0,0 +1,147 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{VeloError, VeloResult, Velocity};
+
+const USER_META_PREFIX: &str = "auth:users:";
+
+fn default_min_length() -> usize {
+    8
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PasswordPolicy {
+    #[serde(default = "default_min_length")]
+    pub min_length: usize,
+    #[serde(default)]
+    pub require_uppercase: bool,
+    #[serde(default)]
+    pub require_lowercase: bool,
+    #[serde(default)]
+    pub require_digit: bool,
+    #[serde(default)]
+    pub require_symbol: bool,
+    #[serde(default)]
+    pub max_age_days: Option<u64>,
+}
+
+impl Default for PasswordPolicy {
+    fn default() -> Self {
+        Self {
+            min_length: default_min_length(),
+            require_uppercase: false,
+            require_lowercase: false,
+            require_digit: false,
+            require_symbol: false,
+            max_age_days: None,
+        }
+    }
+}
+
+impl PasswordPolicy {
+    pub fn validate(&self, password: &str) -> VeloResult<()> {
+        let mut problems = Vec::new();
+
+        if password.chars().count() < self.min_length {
+            problems.push(format!("at least {} characters", self.min_length));
+        }
+        if self.require_uppercase && !password.chars().any(|c| c.is_uppercase()) {
+            problems.push("an uppercase letter".to_string());
+        }
+        if self.require_lowercase && !password.chars().any(|c| c.is_lowercase()) {
+            problems.push("a lowercase letter".to_string());
+        }
+        if self.require_digit && !password.chars().any(|c| c.is_ascii_digit()) {
+            problems.push("a digit".to_string());
+        }
+        if self.require_symbol && !password.chars().any(|c| !c.is_alphanumeric()) {
+            problems.push("a symbol".to_string());
+        }
+
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(VeloError::InvalidOperation(format!(
+                "Password does not meet policy: requires {}",
+                problems.join(", ")
+            )))
+        }
+    }
+
+    pub fn is_expired(&self, meta: &UserMeta) -> bool {
+        match self.max_age_days {
+            Some(days) => unix_now().saturating_sub(meta.password_changed_at) > days * 24 * 60 * 60,
+            None => false,
+        }
+    }
+}
+
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UserMeta {
+    pub created_at: u64,
+    #[serde(default)]
+    pub last_login: Option<u64>,
+    #[serde(default)]
+    pub disabled: bool,
+    pub password_changed_at: u64,
+    #[serde(default)]
+    pub must_change_password: bool,
+}
+
+impl UserMeta {
+    pub fn new() -> Self {
+        let now = unix_now();
+        Self {
+            created_at: now,
+            last_login: None,
+            disabled: false,
+            password_changed_at: now,
+            must_change_password: false,
+        }
+    }
+
+    pub fn needs_password_change(&self, policy: &PasswordPolicy) -> bool {
+        self.must_change_password || policy.is_expired(self)
+    }
+
+    pub fn record_login(&mut self) {
+        self.last_login = Some(unix_now());
+    }
+
+    pub fn record_password_change(&mut self) {
+        self.password_changed_at = unix_now();
+        self.must_change_password = false;
+    }
+}
+
+impl Default for UserMeta {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+
+pub fn load_user_meta(db: &Velocity, username: &str) -> VeloResult<Option<UserMeta>> {
+    match db.get(&format!("{}{}", USER_META_PREFIX, username))? {
+        Some(bytes) => serde_json::from_slice(&bytes).map(Some).map_err(|e| {
+            VeloError::CorruptedData(format!("Invalid metadata for user '{}': {}", username, e))
+        }),
+        None => Ok(None),
+    }
+}
+
+pub fn save_user_meta(db: &Velocity, username: &str, meta: &UserMeta) -> VeloResult<()> {
+    let bytes = serde_json::to_vec(meta).map_err(|e| {
+        VeloError::InvalidOperation(format!("Failed to encode user metadata: {}", e))
+    })?;
+    db.put(format!("{}{}", USER_META_PREFIX, username), bytes)
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
@@ -0,0 +1,86 @@
+use std::collections::VecDeque;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{fsutil, VeloKey, VeloResult, Velocity};
+
+/// Keys that were cached at the last clean shutdown, hottest first.
+pub const CACHE_KEYS_FILE: &str = "cache_keys.json";
+
+/// What to do with the cached keys saved at the last clean shutdown.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CacheWarmup {
+    /// Nothing is saved and the cache starts empty.
+    #[default]
+    Off,
+    /// The keys are queued at startup and loaded by `warm_cache`, which the
+    /// server calls in the background.
+    Lazy,
+    /// The keys are loaded before `open_with_config` returns.
+    Eager,
+}
+
+impl Velocity {
+    /// Loads up to `max_keys` of the keys queued from the last shutdown into
+    /// the cache and returns how many were read.
+    pub fn warm_cache(&self, max_keys: usize) -> VeloResult<usize> {
+        let mut loaded = 0;
+        while loaded < max_keys {
+            let Some(key) = self.cache_warmup_keys.lock().unwrap().pop_front() else {
+                break;
+            };
+            self.get_raw(&key)?;
+            loaded += 1;
+        }
+        Ok(loaded)
+    }
+
+    /// Keys still waiting to be loaded by `warm_cache`.
+    pub fn pending_cache_warmup(&self) -> usize {
+        self.cache_warmup_keys.lock().unwrap().len()
+    }
+
+    // Runs on close, before the cache is cleared. A close that finds the
+    // cache already empty leaves the saved list alone.
+    pub(crate) fn save_cache_keys(&self) -> VeloResult<()> {
+        if self.config.cache_warmup == CacheWarmup::Off {
+            return Ok(());
+        }
+        let keys = self.cache.lock().unwrap().keys_by_use();
+        if keys.is_empty() {
+            return Ok(());
+        }
+        let content = serde_json::to_string(&keys).map_err(|e| {
+            crate::VeloError::InvalidOperation(format!("Failed to encode cache keys: {}", e))
+        })?;
+        fsutil::atomic_write(self.layout.meta_dir().join(CACHE_KEYS_FILE), content)?;
+        Ok(())
+    }
+
+    // The list is removed once read, so after a crash the cache starts cold
+    // rather than from a list saved before earlier writes.
+    pub(crate) fn load_cache_keys(&self) -> VeloResult<()> {
+        let path = self.layout.meta_dir().join(CACHE_KEYS_FILE);
+        let Ok(content) = std::fs::read_to_string(&path) else {
+            return Ok(());
+        };
+        let _ = std::fs::remove_file(&path);
+        if self.config.cache_warmup == CacheWarmup::Off {
+            return Ok(());
+        }
+        let keys: VecDeque<VeloKey> = match serde_json::from_str(&content) {
+            Ok(keys) => keys,
+            Err(e) => {
+                log::warn!("Ignoring unreadable cache key list {:?}: {}", path, e);
+                return Ok(());
+            }
+        };
+        log::info!("Warming the cache with {} keys from the last shutdown", keys.len());
+        *self.cache_warmup_keys.lock().unwrap() = keys;
+        if self.config.cache_warmup == CacheWarmup::Eager {
+            self.warm_cache(usize::MAX)?;
+        }
+        Ok(())
+    }
+}
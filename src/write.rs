@@ -0,0 +1,50 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+
+use crate::Velocity;
+
+// How long a low-priority write waits for a running compaction before it
+// goes ahead anyway.
+const LOW_PRIORITY_MAX_WAIT: Duration = Duration::from_millis(20);
+
+/// Per-call write settings for `put_with_options`, `delete_with_options` and
+/// `write_batch_with_options`.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct WriteOptions {
+    /// Skip the write-ahead log. The write is visible immediately but only
+    /// becomes durable when the memtable is flushed: a crash before then
+    /// loses it, and replicas that follow the WAL never see it. Only use
+    /// this for loads that can be rerun from their source.
+    pub disable_wal: bool,
+    /// Fsync the WAL before returning, regardless of the configured sync
+    /// mode.
+    pub sync: bool,
+    /// Hold the write back briefly while a compaction is running, so bulk
+    /// loads do not compete with it for I/O.
+    pub low_priority: bool,
+}
+
+// Clears the compacting flag when a compaction ends, however it ends.
+pub(crate) struct CompactingGuard<'a>(&'a AtomicBool);
+
+impl Drop for CompactingGuard<'_> {
+    fn drop(&mut self) {
+        self.0.store(false, Ordering::Release);
+    }
+}
+
+impl Velocity {
+    // Called by a compaction once it holds `compaction_lock`. Writers only
+    // look at the flag, so waiting never contends for the lock itself.
+    pub(crate) fn mark_compacting(&self) -> CompactingGuard<'_> {
+        self.compacting.store(true, Ordering::Release);
+        CompactingGuard(&self.compacting)
+    }
+
+    pub(crate) fn yield_to_compaction(&self) {
+        let started = Instant::now();
+        while self.compacting.load(Ordering::Acquire) && started.elapsed() < LOW_PRIORITY_MAX_WAIT {
+            std::thread::sleep(Duration::from_millis(1));
+        }
+    }
+}
@@ -0,0 +1,87 @@
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
+use velocity::auth::{AuthProvider, LdapAuthProvider, LdapConfig};
+
+// A self-signed CA; no connection in these tests gets as far as checking it.
+const TEST_CA: &str = "
+-----BEGIN CERTIFICATE-----
+MIIBjjCCATOgAwIBAgIUG97e1ZITQMwbslSVdZLPD0d949gwCgYIKoZIzj0EAwIw
+GzEZMBcGA1UEAwwQdmVsb2NpdHktdGVzdC1jYTAgFw0yNjEwMTUxNjQ1MjhaGA8y
+MTI2MDkyMTE2NDUyOFowGzEZMBcGA1UEAwwQdmVsb2NpdHktdGVzdC1jYTBZMBMG
+ByqGSM49AgEGCCqGSM49AwEHA0IABNhiPBykMlA0ar/9AEAM+pyjl99t5d0WOPFD
+hdC/Yo3wEO/DJSiGEHpdu0Ne02/SsJhJAuZVVN8s7Wq8YO+KXwyjUzBRMB0GA1Ud
+DgQWBBQXDPDl10SYN+nSDFggI0KKDLwXkTAfBgNVHSMEGDAWgBQXDPDl10SYN+nS
+DFggI0KKDLwXkTAPBgNVHRMBAf8EBTADAQH/MAoGCCqGSM49BAMCA0kAMEYCIQCZ
+HqknP4aK86LK2ZkTG/qFnRjYkQ92/0JbMr4sTsd/nQIhANgsQlAVsoXMyH4bMzGp
+QhKDWRs7l8zWdgtXYZs/FwBE
+-----END CERTIFICATE-----
+";
+
+fn ldap(url: &str, ca_cert_path: Option<String>) -> LdapConfig {
+    LdapConfig {
+        url: url.to_string(),
+        bind_dn_template: "uid={username},dc=example".to_string(),
+        ca_cert_path,
+        timeout_seconds: 5,
+        start_tls: false,
+        allow_insecure: false,
+    }
+}
+
+#[test]
+fn plain_ldap_is_refused_unless_insecure_is_allowed() {
+    assert!(LdapAuthProvider::new(ldap("ldap://ldap.example", None)).is_err());
+
+    let insecure = LdapConfig {
+        allow_insecure: true,
+        ..ldap("ldap://ldap.example", None)
+    };
+    assert!(LdapAuthProvider::new(insecure).is_ok());
+
+    let start_tls = LdapConfig {
+        start_tls: true,
+        ..ldap("ldaps://ldap.example", None)
+    };
+    assert!(LdapAuthProvider::new(start_tls).is_err());
+}
+
+// When the server will not upgrade the connection, the bind is never sent.
+#[tokio::test]
+async fn refused_start_tls_never_sends_the_password() {
+    let dir = tempfile::tempdir().unwrap();
+    let ca = dir.path().join("ca.pem");
+    std::fs::write(&ca, TEST_CA.trim_start()).unwrap();
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("ldap://{}", listener.local_addr().unwrap());
+    let server = tokio::spawn(async move {
+        let (mut stream, _) = listener.accept().await.unwrap();
+        let mut request = vec![0u8; 256];
+        let n = stream.read(&mut request).await.unwrap();
+        request.truncate(n);
+        // ExtendedResponse, message 1, result code 2 (protocolError).
+        let response = [
+            0x30, 0x0c, 0x02, 0x01, 0x01, 0x78, 0x07, 0x0a, 0x01, 0x02, 0x04, 0x00, 0x04, 0x00,
+        ];
+        stream.write_all(&response).await.unwrap();
+        let mut rest = Vec::new();
+        stream.read_to_end(&mut rest).await.unwrap();
+        request.extend(rest);
+        request
+    });
+
+    let config = LdapConfig {
+        start_tls: true,
+        ..ldap(&url, Some(ca.to_string_lossy().into_owned()))
+    };
+    let provider = LdapAuthProvider::new(config).unwrap();
+    assert!(provider.authenticate("alice", "hunter2-secret").await.is_err());
+    drop(provider);
+
+    let received = server.await.unwrap();
+    let password = b"hunter2-secret";
+    assert!(!received.windows(password.len()).any(|window| window == password));
+    assert!(received
+        .windows(b"1.3.6.1.4.1.1466.20037".len())
+        .any(|window| window == b"1.3.6.1.4.1.1466.20037"));
+}
@@ -0,0 +1,40 @@
+use std::sync::{Arc, Barrier};
+use std::thread;
+
+use velocity::read::ReadOptions;
+use velocity::Velocity;
+
+// Writers of one key race to the cache; whichever wrote last must be what
+// a cached read returns.
+#[test]
+fn concurrent_writes_of_a_key_leave_the_newest_value_cached() {
+    let dir = tempfile::tempdir().unwrap();
+    let db = Arc::new(Velocity::open(dir.path()).unwrap());
+    let writers = 8;
+
+    for round in 0..1000 {
+        let barrier = Arc::new(Barrier::new(writers));
+        let handles: Vec<_> = (0..writers)
+            .map(|writer| {
+                let db = db.clone();
+                let barrier = barrier.clone();
+                thread::spawn(move || {
+                    barrier.wait();
+                    db.put("k".to_string(), format!("{}-{}", round, writer).into_bytes())
+                        .unwrap();
+                })
+            })
+            .collect();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+
+        let snapshot = db.snapshot().unwrap();
+        let options = ReadOptions {
+            snapshot: Some(&snapshot),
+            ..ReadOptions::default()
+        };
+        let stored = db.get_with_options("k", &options).unwrap();
+        assert_eq!(db.get("k").unwrap(), stored, "round {}", round);
+    }
+}
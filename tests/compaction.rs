@@ -0,0 +1,182 @@
+use std::path::Path;
+
+use velocity::compaction::CompactionStrategy;
+use velocity::{Velocity, VelocityConfig};
+
+// Each flush writes 20 new keys and overwrites 5 from the one before, so no
+// table is dense enough to be rewritten away.
+fn write_flushes(db: &Velocity, flushes: usize) {
+    for round in 0..flushes {
+        for i in round * 20..round * 20 + 25 {
+            db.put(format!("key{:05}", i), format!("v{}", round).into_bytes())
+                .unwrap();
+        }
+        db.flush().unwrap();
+    }
+}
+
+fn sstable_files(dir: &Path) -> Vec<String> {
+    let mut files: Vec<String> = std::fs::read_dir(dir.join("sst"))
+        .unwrap()
+        .flatten()
+        .map(|entry| entry.file_name().to_string_lossy().into_owned())
+        .filter(|name| name.ends_with(".vdb"))
+        .collect();
+    files.sort();
+    files
+}
+
+#[test]
+fn default_strategy_bounds_the_table_count() {
+    let dir = tempfile::tempdir().unwrap();
+    let db = Velocity::open_with_config(dir.path(), VelocityConfig::minimal()).unwrap();
+    write_flushes(&db, 40);
+    db.delete("key00003".to_string()).unwrap();
+    db.flush().unwrap();
+
+    let tables = db.lsm_shape().tables.len();
+    assert!(tables <= 8, "{} tables after 41 flushes", tables);
+    assert!(db
+        .compaction_log()
+        .iter()
+        .any(|entry| entry.action == "merge"));
+    assert_eq!(db.get("key00003").unwrap(), None);
+    assert_eq!(db.get("key00004").unwrap(), Some(b"v0".to_vec()));
+    assert_eq!(db.get("key00020").unwrap(), Some(b"v1".to_vec()));
+    assert_eq!(db.get("key00799").unwrap(), Some(b"v39".to_vec()));
+}
+
+#[test]
+fn off_strategy_merges_nothing() {
+    let dir = tempfile::tempdir().unwrap();
+    let config = VelocityConfig {
+        compaction_picker: CompactionStrategy::Off.picker(),
+        ..VelocityConfig::minimal()
+    };
+    let db = Velocity::open_with_config(dir.path(), config).unwrap();
+    write_flushes(&db, 10);
+
+    assert_eq!(db.lsm_shape().tables.len(), 10);
+    assert!(db
+        .compaction_log()
+        .iter()
+        .all(|entry| entry.action != "merge"));
+}
+
+// A crash after a merge's manifest is written but before its inputs are
+// deleted leaves the inputs on disk. They must not come back on open.
+#[test]
+fn merged_inputs_left_on_disk_are_discarded() {
+    let dir = tempfile::tempdir().unwrap();
+    let saved = tempfile::tempdir().unwrap();
+    let config = VelocityConfig {
+        compaction_picker: CompactionStrategy::Off.picker(),
+        ..VelocityConfig::minimal()
+    };
+    let db = Velocity::open_with_config(dir.path(), config.clone()).unwrap();
+    write_flushes(&db, 3);
+    db.delete("key00000".to_string()).unwrap();
+    db.flush().unwrap();
+    let inputs = sstable_files(dir.path());
+    for name in &inputs {
+        std::fs::copy(dir.path().join("sst").join(name), saved.path().join(name)).unwrap();
+    }
+
+    let control = velocity::compaction::CompactionControl::new(0);
+    db.compact_full(&control).unwrap();
+    db.put("key00001".to_string(), b"after".to_vec()).unwrap();
+    db.flush().unwrap();
+    drop(db);
+    for name in &inputs {
+        std::fs::copy(saved.path().join(name), dir.path().join("sst").join(name)).unwrap();
+    }
+
+    let db = Velocity::open_with_config(dir.path(), config).unwrap();
+    assert!(db.quarantined_tables().is_empty());
+    assert_eq!(db.lsm_shape().tables.len(), 2);
+    assert!(sstable_files(dir.path())
+        .iter()
+        .all(|name| !inputs.contains(name)));
+    assert_eq!(db.get("key00000").unwrap(), None);
+    assert_eq!(db.get("key00001").unwrap(), Some(b"after".to_vec()));
+    assert_eq!(db.get("key00030").unwrap(), Some(b"v1".to_vec()));
+}
+
+// The same for a table rewritten to drop values that newer tables shadow.
+#[test]
+fn rewritten_table_left_on_disk_is_discarded() {
+    let dir = tempfile::tempdir().unwrap();
+    let saved = tempfile::tempdir().unwrap();
+    let config = VelocityConfig {
+        compaction_picker: CompactionStrategy::Off.picker(),
+        ..VelocityConfig::minimal()
+    };
+    let db = Velocity::open_with_config(dir.path(), config.clone()).unwrap();
+    for i in 0..20 {
+        db.put(format!("key{:05}", i), b"old".to_vec()).unwrap();
+    }
+    db.flush().unwrap();
+    let original = sstable_files(dir.path());
+    for name in &original {
+        std::fs::copy(dir.path().join("sst").join(name), saved.path().join(name)).unwrap();
+    }
+
+    for i in 0..18 {
+        db.put(format!("key{:05}", i), b"new".to_vec()).unwrap();
+    }
+    db.flush().unwrap();
+    assert!(db
+        .compaction_log()
+        .iter()
+        .any(|entry| entry.action == "rewrite"));
+    drop(db);
+    for name in &original {
+        std::fs::copy(saved.path().join(name), dir.path().join("sst").join(name)).unwrap();
+    }
+
+    let db = Velocity::open_with_config(dir.path(), config).unwrap();
+    assert!(db.quarantined_tables().is_empty());
+    assert!(sstable_files(dir.path())
+        .iter()
+        .all(|name| !original.contains(name)));
+    assert_eq!(db.get("key00000").unwrap(), Some(b"new".to_vec()));
+    assert_eq!(db.get("key00019").unwrap(), Some(b"old".to_vec()));
+}
+
+// A key that sorts first must not be mistaken for a compressed table's
+// dictionary, in a flushed table or a compacted one.
+#[test]
+fn first_key_is_never_read_as_a_dictionary() {
+    let dir = tempfile::tempdir().unwrap();
+    let config = VelocityConfig {
+        compaction_picker: CompactionStrategy::Off.picker(),
+        enable_compression: true,
+        ..VelocityConfig::minimal()
+    };
+    let db = Velocity::open_with_config(dir.path(), config.clone()).unwrap();
+    db.put("\0zstd-dictionary".to_string(), b"first".to_vec())
+        .unwrap();
+    db.put("\0".to_string(), b"nul".to_vec()).unwrap();
+    write_flushes(&db, 3);
+    drop(db);
+
+    let db = Velocity::open_with_config(dir.path(), config.clone()).unwrap();
+    assert_eq!(
+        db.get("\0zstd-dictionary").unwrap(),
+        Some(b"first".to_vec())
+    );
+    let control = velocity::compaction::CompactionControl::new(0);
+    db.compact_full(&control).unwrap();
+    assert!(db.table_properties().iter().all(|table| table.compressed));
+    drop(db);
+
+    let db = Velocity::open_with_config(dir.path(), config).unwrap();
+    assert!(db.quarantined_tables().is_empty());
+    assert_eq!(db.get("\0").unwrap(), Some(b"nul".to_vec()));
+    assert_eq!(
+        db.get("\0zstd-dictionary").unwrap(),
+        Some(b"first".to_vec())
+    );
+    assert_eq!(db.get("key00030").unwrap(), Some(b"v1".to_vec()));
+    assert_eq!(db.range("", None, usize::MAX).unwrap().len(), 67);
+}
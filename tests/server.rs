@@ -0,0 +1,57 @@
+use std::sync::Arc;
+
+use velocity::addon::DatabaseManager;
+use velocity::client::VelocityClient;
+use velocity::server::{hash_password, ServerConfig, VelocityServer};
+use velocity::Velocity;
+
+async fn connect(server: &VelocityServer) -> VelocityClient {
+    let mut client = VelocityClient::connect_in_memory(server).await.unwrap();
+    client.authenticate("tester", "secret-password").await.unwrap();
+    client
+}
+
+fn server(dir: &std::path::Path) -> VelocityServer {
+    let db = Velocity::open(dir.join("data")).unwrap();
+    let manager = Arc::new(DatabaseManager::new(db, dir.join("velocity.toml")));
+    let mut config = ServerConfig::default();
+    config
+        .users
+        .insert("tester".to_string(), hash_password("secret-password").unwrap());
+    VelocityServer::new(manager, config).unwrap()
+}
+
+// Under read_committed each statement sees every write that returned before
+// it started, including ones made on other connections after earlier reads.
+#[tokio::test]
+async fn read_committed_sees_writes_committed_between_statements() {
+    let dir = tempfile::tempdir().unwrap();
+    let server = server(dir.path());
+    let mut reader = connect(&server).await;
+    let mut writer = connect(&server).await;
+
+    reader.set_isolation_level("read_committed").await.unwrap();
+    assert_eq!(reader.isolation_level().await.unwrap(), "read_committed");
+    assert_eq!(reader.select("k").await.unwrap(), None);
+
+    writer.insert("k", "v1").await.unwrap();
+    assert_eq!(reader.select("k").await.unwrap().as_deref(), Some("v1"));
+
+    writer.update("k", "v2").await.unwrap();
+    assert_eq!(reader.select("k").await.unwrap().as_deref(), Some("v2"));
+
+    writer.delete("k").await.unwrap();
+    assert_eq!(reader.select("k").await.unwrap(), None);
+}
+
+#[tokio::test]
+async fn snapshot_isolation_is_refused() {
+    let dir = tempfile::tempdir().unwrap();
+    let server = server(dir.path());
+    let mut client = connect(&server).await;
+
+    for level in ["snapshot", "repeatable read", "serializable"] {
+        assert!(client.set_isolation_level(level).await.is_err(), "{}", level);
+    }
+    assert_eq!(client.isolation_level().await.unwrap(), "read_committed");
+}
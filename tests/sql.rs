@@ -0,0 +1,108 @@
+use std::sync::Arc;
+
+use velocity::addon::DatabaseManager;
+use velocity::sql::is_write_statement;
+use velocity::{VeloError, Velocity, VelocityConfig};
+
+fn manager(dir: &std::path::Path) -> Arc<DatabaseManager> {
+    let db = Velocity::open(dir.join("data")).unwrap();
+    Arc::new(DatabaseManager::new(db, dir.join("velocity.toml")))
+}
+
+#[test]
+fn writes_are_recognised_behind_comments_and_case() {
+    assert!(is_write_statement("/* c */ INSERT INTO kv (key, value) VALUES ('a', 'b')"));
+    assert!(is_write_statement("-- note\ndelete from kv where key = 'a'"));
+    assert!(!is_write_statement("/* INSERT */ SELECT key FROM kv"));
+}
+
+// A comment in front of a write used to slip past the read-only check.
+#[tokio::test]
+async fn comment_prefixed_write_to_a_read_only_database_is_refused() {
+    let dir = tempfile::tempdir().unwrap();
+    let manager = manager(dir.path());
+    manager.set_read_only("default", true).unwrap();
+    let db = manager.get_database("default").unwrap();
+
+    let engine = manager.sql_engine(db.clone(), "default", true);
+    let result = engine
+        .execute("/* c */ INSERT INTO kv (key, value) VALUES ('a', 'b')")
+        .await;
+    assert!(result.is_err());
+    assert_eq!(db.get("a").unwrap(), None);
+}
+
+// Each page of a cursor scan reads about one page from every table, not
+// everything left after the cursor.
+#[tokio::test]
+async fn cursor_pages_read_a_bounded_amount() {
+    let dir = tempfile::tempdir().unwrap();
+    let manager = manager(dir.path());
+    let db = manager.get_database("default").unwrap();
+    for table in 0..4 {
+        for i in 0..2000 {
+            db.put(format!("user:{:05}", i), format!("v{}", table).into_bytes())
+                .unwrap();
+        }
+        db.flush().unwrap();
+    }
+    for i in (0..2000).step_by(10) {
+        db.delete(format!("user:{:05}", i)).unwrap();
+    }
+
+    let before = db.scan_bytes();
+    assert_eq!(db.range("user:", None, usize::MAX).unwrap().len(), 1800);
+    let full_scan = db.scan_bytes() - before;
+
+    let engine = manager.sql_engine(db.clone(), "default", true);
+    let query = "SELECT * FROM kv WHERE key LIKE 'user:%' LIMIT 100";
+    let mut sql = query.to_string();
+    let mut rows = 0;
+    let mut page_bytes = Vec::new();
+    loop {
+        let before = db.scan_bytes();
+        let result = engine.execute(&sql).await.unwrap();
+        page_bytes.push(db.scan_bytes() - before);
+        rows += result.data.len();
+        match result.next_cursor {
+            Some(cursor) => sql = format!("{} AFTER '{}'", query, cursor),
+            None => break,
+        }
+    }
+
+    assert_eq!(rows, 1800);
+    assert!(
+        page_bytes.iter().all(|&bytes| bytes <= full_scan / 10),
+        "{:?} against {} for the whole range",
+        page_bytes,
+        full_scan
+    );
+}
+
+#[tokio::test]
+async fn versioned_update_of_a_missing_key_conflicts() {
+    let dir = tempfile::tempdir().unwrap();
+    let config = VelocityConfig {
+        record_metadata: true,
+        ..VelocityConfig::default()
+    };
+    let db = Velocity::open_with_config(dir.path().join("data"), config).unwrap();
+    let manager = Arc::new(DatabaseManager::new(db, dir.path().join("velocity.toml")));
+    let db = manager.get_database("default").unwrap();
+
+    let engine = manager.sql_engine(db.clone(), "default", true);
+    for version in [0, 1] {
+        let sql = format!(
+            "UPDATE kv SET value = 'v' WHERE key = 'missing' AND version = {}",
+            version
+        );
+        match engine.execute(&sql).await {
+            Err(VeloError::VersionConflict { actual: 0, .. }) => {}
+            other => panic!(
+                "expected a version conflict, got {:?}",
+                other.map(|r| r.rows_affected)
+            ),
+        }
+    }
+    assert_eq!(db.get("missing").unwrap(), None);
+}
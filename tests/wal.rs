@@ -0,0 +1,107 @@
+use std::fs::OpenOptions;
+
+use velocity::{BatchOp, Velocity, VelocityConfig};
+
+// A crash while a batch is being logged leaves part of its frame at the end
+// of the WAL. None of the batch may come back on open.
+#[test]
+fn torn_batch_is_not_replayed() {
+    let dir = tempfile::tempdir().unwrap();
+    let db = Velocity::open_with_config(dir.path(), VelocityConfig::minimal()).unwrap();
+    db.put("before".to_string(), b"1".to_vec()).unwrap();
+    let ops: Vec<BatchOp> = (0..10)
+        .map(|i| BatchOp::Put {
+            key: format!("batch{}", i),
+            value: b"v".to_vec(),
+        })
+        .collect();
+    db.write_batch(&ops).unwrap();
+    // Dropping the database would flush the memtable and empty the WAL.
+    std::mem::forget(db);
+
+    let wal = dir.path().join("wal").join("velocity.wal");
+    let len = std::fs::metadata(&wal).unwrap().len();
+    let file = OpenOptions::new().write(true).open(&wal).unwrap();
+    file.set_len(len - 20).unwrap();
+    drop(file);
+
+    let db = Velocity::open_with_config(dir.path(), VelocityConfig::minimal()).unwrap();
+    assert_eq!(db.get("before").unwrap(), Some(b"1".to_vec()));
+    for i in 0..10 {
+        assert_eq!(db.get(&format!("batch{}", i)).unwrap(), None);
+    }
+    assert_eq!(db.wal_integrity_report().unwrap().truncated_records, 1);
+}
+
+#[test]
+fn logged_batch_is_replayed() {
+    let dir = tempfile::tempdir().unwrap();
+    let db = Velocity::open_with_config(dir.path(), VelocityConfig::minimal()).unwrap();
+    db.put("gone".to_string(), b"1".to_vec()).unwrap();
+    db.write_batch(&[
+        BatchOp::Put {
+            key: "a".to_string(),
+            value: b"1".to_vec(),
+        },
+        BatchOp::Delete {
+            key: "gone".to_string(),
+        },
+    ])
+    .unwrap();
+    std::mem::forget(db);
+
+    let db = Velocity::open_with_config(dir.path(), VelocityConfig::minimal()).unwrap();
+    assert_eq!(db.get("a").unwrap(), Some(b"1".to_vec()));
+    assert_eq!(db.get("gone").unwrap(), None);
+}
+
+// With the WAL gone, as in a database restored from its tables, flushed keys
+// can only be found through the tables loaded on open; the engine-level
+// filter must not report them absent.
+#[test]
+fn flushed_keys_are_found_after_a_restart_without_the_wal() {
+    let dir = tempfile::tempdir().unwrap();
+    let config = VelocityConfig::minimal();
+    let db = Velocity::open_with_config(dir.path(), config.clone()).unwrap();
+    for i in 0..500 {
+        db.put(format!("key_{:08}", i), b"v".to_vec()).unwrap();
+    }
+    db.flush().unwrap();
+    db.close().unwrap();
+    std::fs::remove_dir_all(dir.path().join("wal")).unwrap();
+
+    let db = Velocity::open_with_config(dir.path(), config).unwrap();
+    for i in 0..500 {
+        assert_eq!(
+            db.get(&format!("key_{:08}", i)).unwrap(),
+            Some(b"v".to_vec()),
+            "key_{:08}",
+            i
+        );
+    }
+    assert_eq!(db.get("key_99999999").unwrap(), None);
+}
+
+// Shards beyond a lowered `wal_shards` are still replayed on open, and their
+// files go away once what they held has been flushed.
+#[test]
+fn stale_shards_are_replayed_then_removed() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut config = VelocityConfig::minimal();
+    config.wal_shards = 4;
+    let db = Velocity::open_with_config(dir.path(), config.clone()).unwrap();
+    for i in 0..100 {
+        db.put(format!("key{}", i), b"v".to_vec()).unwrap();
+    }
+    std::mem::forget(db);
+    let stale = dir.path().join("wal").join("velocity-3.wal");
+    assert!(stale.exists());
+
+    config.wal_shards = 1;
+    let db = Velocity::open_with_config(dir.path(), config).unwrap();
+    for i in 0..100 {
+        assert_eq!(db.get(&format!("key{}", i)).unwrap(), Some(b"v".to_vec()));
+    }
+    db.flush().unwrap();
+    assert!(!stale.exists());
+}